@@ -61,6 +61,11 @@ check_testcase () {
             QUICHE_SERVER_OPT="$QUICHE_SERVER_OPT_COMMON"
             ;;
 
+        keyupdate )
+            echo "supported"
+            QUICHE_CLIENT_OPT="$QUICHE_CLIENT_OPT --key-update"
+            ;;
+
         *)
             echo "unsupported"
             exit 127
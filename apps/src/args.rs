@@ -280,6 +280,7 @@ Options:
   --max-active-cids NUM    The maximum number of active Connection IDs we can support [default: 2].
   --enable-active-migration   Enable active connection migration.
   --perform-migration      Perform connection migration on another source port.
+  --key-update             Update the 1-RTT keys once the handshake is confirmed.
   -H --header HEADER ...   Add a request header.
   -n --requests REQUESTS   Send the given number of identical requests [default: 1].
   --send-priority-update   Send HTTP/3 priority updates if the query string params 'u' or 'i' are present in URLs
@@ -308,6 +309,7 @@ pub struct ClientArgs {
     pub session_file: Option<String>,
     pub source_port: u16,
     pub perform_migration: bool,
+    pub key_update: bool,
     pub send_priority_update: bool,
 }
 
@@ -384,6 +386,8 @@ impl Args for ClientArgs {
 
         let perform_migration = args.get_bool("--perform-migration");
 
+        let key_update = args.get_bool("--key-update");
+
         let send_priority_update = args.get_bool("--send-priority-update");
 
         ClientArgs {
@@ -401,6 +405,7 @@ impl Args for ClientArgs {
             session_file,
             source_port,
             perform_migration,
+            key_update,
             send_priority_update,
         }
     }
@@ -423,6 +428,7 @@ impl Default for ClientArgs {
             session_file: None,
             source_port: 0,
             perform_migration: false,
+            key_update: false,
             send_priority_update: false,
         }
     }
@@ -0,0 +1,103 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use quiche_apps::pcap;
+
+const USAGE: &str = "Usage:
+  quiche-decrypt [options] PCAP
+  quiche-decrypt -h | --help
+
+Decrypts the QUIC packets in a pcap file and prints their frames.
+
+Options:
+  --keylog FILE  Read TLS secrets from the given keylog file, instead of the one in the SSLKEYLOGFILE environment variable.
+  -h --help      Show this screen.
+";
+
+fn main() {
+    let args = docopt::Docopt::new(USAGE)
+        .and_then(|dopt| dopt.parse())
+        .unwrap_or_else(|e| e.exit());
+
+    let pcap_file = args.get_str("PCAP");
+
+    let keylog_file = match args.get_str("--keylog") {
+        "" => std::env::var("SSLKEYLOGFILE").ok(),
+
+        v => Some(v.to_string()),
+    };
+
+    let mut decryptor = quiche::Decryptor::new();
+
+    if let Some(keylog_file) = keylog_file {
+        let keylog = std::fs::read_to_string(&keylog_file).unwrap_or_else(|e| {
+            eprintln!("failed to read {keylog_file}: {e}");
+            std::process::exit(1);
+        });
+
+        decryptor.add_keylog(&keylog);
+    }
+
+    let reader = std::fs::read(pcap_file)
+        .map_err(|e| e.to_string())
+        .and_then(pcap::Reader::new)
+        .unwrap_or_else(|e| {
+            eprintln!("failed to read {pcap_file}: {e}");
+            std::process::exit(1);
+        });
+
+    let mut start = None;
+
+    for dgram in reader {
+        let start = *start.get_or_insert(dgram.time);
+        let time = dgram.time.saturating_sub(start);
+
+        println!(
+            "{:.6} {} -> {} len={}",
+            time.as_secs_f64(),
+            dgram.from,
+            dgram.to,
+            dgram.payload.len()
+        );
+
+        for pkt in decryptor.decrypt(&dgram.payload) {
+            match pkt {
+                Ok(pkt) => {
+                    let sender =
+                        if pkt.from_client { "client" } else { "server" };
+
+                    println!("  {sender} {:?} pn={}", pkt.hdr, pkt.pkt_num);
+
+                    for frame in &pkt.frames {
+                        println!("    {frame}");
+                    }
+                },
+
+                Err(e) => println!("  undecryptable packet: {e:?}"),
+            }
+        }
+    }
+}
@@ -254,6 +254,7 @@ pub fn connect(
     let mut scid_sent = false;
     let mut new_path_probed = false;
     let mut migrated = false;
+    let mut key_updated = false;
 
     loop {
         if !conn.is_in_early_data() || app_proto_selected {
@@ -497,6 +498,13 @@ pub fn connect(
             new_path_probed = true;
         }
 
+        // Update keys as soon as the handshake is confirmed.
+        if args.key_update && !key_updated && conn.initiate_key_update().is_ok() {
+            info!("initiated key update");
+
+            key_updated = true;
+        }
+
         // Generate outgoing QUIC packets and send them on the UDP socket, until
         // quiche reports that there are no more packets to be sent.
         let mut sockets = vec![&socket];
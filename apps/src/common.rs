@@ -1341,6 +1341,16 @@ impl HttpConn for Http3Conn {
                     );
                 },
 
+                Ok((_, quiche::h3::Event::PushPromise { push_id, .. })) => {
+                    info!(
+                        "{} got PUSH_PROMISE with push ID {}",
+                        conn.trace_id(),
+                        push_id
+                    );
+                },
+
+                Ok((_, quiche::h3::Event::PushCancelled { .. })) => (),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -1538,6 +1548,10 @@ impl HttpConn for Http3Conn {
                         .send_goaway(conn, self.largest_processed_request)?;
                 },
 
+                Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                Ok((_, quiche::h3::Event::PushCancelled { .. })) => (),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
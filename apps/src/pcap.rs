@@ -0,0 +1,258 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Minimal reader for UDP datagrams stored in pcap files.
+//!
+//! Only the classic pcap format is supported, not pcapng. Packets are expected
+//! to be unfragmented IPv4 or IPv6 packets, over Ethernet, Linux cooked
+//! capture, BSD loopback or raw IP links.
+
+use std::convert::TryInto;
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// A UDP datagram read from a capture.
+pub struct Datagram {
+    /// Capture timestamp, relative to the UNIX epoch.
+    pub time: std::time::Duration,
+
+    pub from: SocketAddr,
+
+    pub to: SocketAddr,
+
+    pub payload: Vec<u8>,
+}
+
+/// Iterates over the UDP datagrams in a pcap file.
+pub struct Reader {
+    buf: Vec<u8>,
+
+    off: usize,
+
+    big_endian: bool,
+
+    nanos: bool,
+
+    link_type: u32,
+}
+
+impl Reader {
+    /// Creates a reader from the content of a pcap file.
+    pub fn new(buf: Vec<u8>) -> Result<Reader, String> {
+        let magic = buf
+            .get(..4)
+            .map(|m| u32::from_le_bytes([m[0], m[1], m[2], m[3]]))
+            .ok_or("file too short")?;
+
+        let (big_endian, nanos) = match magic {
+            0xa1b2c3d4 => (false, false),
+            0xd4c3b2a1 => (true, false),
+            0xa1b23c4d => (false, true),
+            0x4d3cb2a1 => (true, true),
+            0x0a0d0d0a => return Err("pcapng files are not supported".into()),
+            _ => return Err("not a pcap file".into()),
+        };
+
+        let mut reader = Reader {
+            buf,
+            off: 0,
+            big_endian,
+            nanos,
+            link_type: 0,
+        };
+
+        reader.link_type = reader.u32_at(20).ok_or("file too short")?;
+        reader.off = 24;
+
+        match reader.link_type {
+            LINKTYPE_NULL | LINKTYPE_ETHERNET | LINKTYPE_RAW |
+            LINKTYPE_LINUX_SLL | LINKTYPE_IPV4 | LINKTYPE_IPV6 => (),
+
+            v => return Err(format!("unsupported link type {v}")),
+        }
+
+        Ok(reader)
+    }
+
+    fn u32_at(&self, off: usize) -> Option<u32> {
+        let b = self.buf.get(off..off + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    /// Returns the link layer payload of the next record, and its timestamp.
+    fn next_record(&mut self) -> Option<(std::time::Duration, &[u8])> {
+        let secs = self.u32_at(self.off)?;
+        let frac = self.u32_at(self.off + 4)?;
+        let caplen = self.u32_at(self.off + 8)? as usize;
+
+        // Microseconds that don't fit in nanoseconds are malformed.
+        let frac_nanos = if self.nanos {
+            frac
+        } else {
+            frac.checked_mul(1000)?
+        };
+
+        let start = self.off + 16;
+        let data = self.buf.get(start..start + caplen)?;
+
+        self.off = start + caplen;
+
+        let time = std::time::Duration::new(secs.into(), frac_nanos);
+
+        Some((time, data))
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Datagram;
+
+    fn next(&mut self) -> Option<Datagram> {
+        loop {
+            let link_type = self.link_type;
+            let (time, data) = self.next_record()?;
+
+            if let Some((from, to, payload)) = parse_link(link_type, data) {
+                return Some(Datagram {
+                    time,
+                    from,
+                    to,
+                    payload: payload.to_vec(),
+                });
+            }
+        }
+    }
+}
+
+fn parse_link(
+    link_type: u32, data: &[u8],
+) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    match link_type {
+        LINKTYPE_NULL => parse_ip(data.get(4..)?),
+
+        LINKTYPE_ETHERNET => {
+            let mut off = 12;
+            let mut ethertype = u16_be(data, off)?;
+
+            while ethertype == ETHERTYPE_VLAN {
+                off += 4;
+                ethertype = u16_be(data, off)?;
+            }
+
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => parse_ip(data.get(off + 2..)?),
+
+                _ => None,
+            }
+        },
+
+        LINKTYPE_LINUX_SLL => parse_ip(data.get(16..)?),
+
+        _ => parse_ip(data),
+    }
+}
+
+fn parse_ip(data: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, udp) = match data.first()? >> 4 {
+        4 => {
+            let ihl = usize::from(data[0] & 0x0f) * 4;
+            let total_len = usize::from(u16_be(data, 2)?);
+
+            // Skip fragments.
+            if u16_be(data, 6)? & 0x3fff != 0 || data.get(9)? != &IPPROTO_UDP {
+                return None;
+            }
+
+            let src: [u8; 4] = data.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = data.get(16..20)?.try_into().ok()?;
+
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                data.get(ihl..total_len.min(data.len()))?,
+            )
+        },
+
+        6 => {
+            // Extension headers are not supported.
+            if data.get(6)? != &IPPROTO_UDP {
+                return None;
+            }
+
+            let payload_len = usize::from(u16_be(data, 4)?);
+
+            let src: [u8; 16] = data.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = data.get(24..40)?.try_into().ok()?;
+
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                data.get(40..(40 + payload_len).min(data.len()))?,
+            )
+        },
+
+        _ => return None,
+    };
+
+    let src_port = u16_be(udp, 0)?;
+    let dst_port = u16_be(udp, 2)?;
+    let udp_len = usize::from(u16_be(udp, 4)?);
+
+    let payload = udp.get(8..udp_len.min(udp.len()))?;
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload,
+    ))
+}
+
+fn u16_be(data: &[u8], off: usize) -> Option<u16> {
+    let b = data.get(off..off + 2)?;
+
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
@@ -0,0 +1,48 @@
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use std::io::Write;
+
+// Fuzzer for frame parsing, which exports each input as a differential test
+// vector. The first byte selects the packet type and the rest is used as the
+// packet payload.
+//
+// When the QUICHE_DIFF_EXPORT environment variable is set, vectors are
+// appended to the file it points to, so that the corpus can be replayed
+// against other implementations and the outcomes compared.
+fuzz_target!(|data: &[u8]| {
+    let (ty, payload) = match data.split_first() {
+        Some(v) => v,
+
+        None => return,
+    };
+
+    let pkt_type = match ty % 4 {
+        0 => quiche::Type::Initial,
+        1 => quiche::Type::Handshake,
+        2 => quiche::Type::ZeroRTT,
+        _ => quiche::Type::Short,
+    };
+
+    if payload.is_empty() {
+        return;
+    }
+
+    let v = quiche::differential::Vector::new("fuzz", pkt_type, payload);
+
+    // The exported vector must parse back to the same outcome.
+    let line = v.to_string();
+    assert_eq!(quiche::differential::parse_vectors(&line), Ok(vec![v]));
+
+    if let Ok(path) = std::env::var("QUICHE_DIFF_EXPORT") {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+
+        writeln!(file, "{}", line).unwrap();
+    }
+});
@@ -24,15 +24,58 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-/// Zero-copy abstraction for parsing and constructing network packets.
-use std::mem;
-use std::ptr;
+//! Zero-copy abstraction for parsing and constructing network packets.
+//!
+//! [`Octets`] and [`OctetsMut`] wrap a user-provided byte slice and keep track
+//! of an offset within it, which is advanced as data is read or written. Both
+//! support fixed-size integers in network byte-order, QUIC variable-length
+//! integers, and length-prefixed byte strings, without copying the underlying
+//! buffer.
+//!
+//! ## Examples:
+//!
+//! ```
+//! let mut buf = [0; 16];
+//!
+//! let mut b = octets::OctetsMut::with_slice(&mut buf);
+//! b.put_varint(0x1234)?;
+//! b.put_u8(0xff)?;
+//! let written = b.off();
+//!
+//! let mut b = octets::Octets::with_slice(&buf[..written]);
+//! assert_eq!(b.get_varint()?, 0x1234);
+//! assert_eq!(b.get_u8()?, 0xff);
+//! assert_eq!(b.cap(), 0);
+//! # Ok::<(), octets::BufferTooShortError>(())
+//! ```
+//!
+//! ## Feature flags
+//!
+//! * `std` (default): Implement [`std::error::Error`] for
+//!   [`BufferTooShortError`].
+//!
+//! With default features disabled the crate is `no_std` and only depends on
+//! the `core` and `alloc` libraries, so it can be used on embedded targets.
+//! The rest of quiche, including its packet and frame parsers, still requires
+//! `std`.
+//!
+//! [`std::error::Error`]: https://doc.rust-lang.org/std/error/trait.Error.html
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![warn(missing_docs)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use core::mem;
+use core::ptr;
 
 /// A specialized [`Result`] type for [`OctetsMut`] operations.
 ///
 /// [`Result`]: https://doc.rust-lang.org/std/result/enum.Result.html
 /// [`OctetsMut`]: struct.OctetsMut.html
-pub type Result<T> = std::result::Result<T, BufferTooShortError>;
+pub type Result<T> = core::result::Result<T, BufferTooShortError>;
 
 /// An error indicating that the provided [`OctetsMut`] is not big enough.
 ///
@@ -40,12 +83,13 @@ pub type Result<T> = std::result::Result<T, BufferTooShortError>;
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BufferTooShortError;
 
-impl std::fmt::Display for BufferTooShortError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for BufferTooShortError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(f, "BufferTooShortError")
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for BufferTooShortError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         None
@@ -435,9 +479,20 @@ impl<'a> OctetsMut<'a> {
 
     /// Writes an unsigned variable-length integer of the specified length, in
     /// network byte-order at the current offset and advances the buffer.
+    ///
+    /// This is useful to reserve space for a value that is only known after
+    /// more data has been written (e.g. a length field), in which case a
+    /// longer than necessary encoding can be used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is not 1, 2, 4 or 8, or if `v` can't be encoded in
+    /// `len` bytes.
     pub fn put_varint_with_len(
         &mut self, v: u64, len: usize,
     ) -> Result<&mut [u8]> {
+        assert!(varint_len(v) <= len, "value is too large for varint length");
+
         if self.cap() < len {
             return Err(BufferTooShortError);
         }
@@ -666,6 +721,11 @@ impl<'a> AsMut<[u8]> for OctetsMut<'a> {
 
 /// Returns how many bytes it would take to encode `v` as a variable-length
 /// integer.
+///
+/// # Panics
+///
+/// Panics if `v` is larger than 2^62-1, the largest value that can be encoded
+/// as a variable-length integer.
 pub const fn varint_len(v: u64) -> usize {
     if v <= 63 {
         1
@@ -1026,6 +1086,73 @@ mod tests {
         assert!(b.put_varint(u64::MAX).is_err());
     }
 
+    #[test]
+    fn varint_boundaries() {
+        let values = [
+            (0, 1),
+            (63, 1),
+            (64, 2),
+            (16383, 2),
+            (16384, 4),
+            (1_073_741_823, 4),
+            (1_073_741_824, 8),
+            (4_611_686_018_427_387_903, 8),
+        ];
+
+        for &(v, len) in values.iter() {
+            assert_eq!(varint_len(v), len);
+
+            let mut d = [0; 8];
+
+            let written = {
+                let mut b = OctetsMut::with_slice(&mut d);
+                assert_eq!(b.put_varint(v).unwrap().len(), len);
+                b.off()
+            };
+            assert_eq!(written, len);
+            assert_eq!(varint_parse_len(d[0]), len);
+
+            let mut b = Octets::with_slice(&d);
+            assert_eq!(b.get_varint(), Ok(v));
+            assert_eq!(b.off(), len);
+        }
+    }
+
+    #[test]
+    fn put_varint_with_len() {
+        let mut d = [0; 15];
+
+        {
+            let mut b = OctetsMut::with_slice(&mut d);
+            assert!(b.put_varint_with_len(37, 1).is_ok());
+            assert!(b.put_varint_with_len(37, 2).is_ok());
+            assert!(b.put_varint_with_len(37, 4).is_ok());
+            assert!(b.put_varint_with_len(37, 8).is_ok());
+            assert_eq!(b.cap(), 0);
+        }
+
+        let mut b = Octets::with_slice(&d);
+        for len in [1, 2, 4, 8].iter() {
+            let off = b.off();
+            assert_eq!(b.get_varint(), Ok(37));
+            assert_eq!(b.off() - off, *len);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn put_varint_with_len_too_short() {
+        let mut d = [0; 8];
+        let mut b = OctetsMut::with_slice(&mut d);
+        let _ = b.put_varint_with_len(16384, 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn varint_len_too_large() {
+        varint_len(4_611_686_018_427_387_904);
+    }
+
     #[test]
     fn put_u() {
         let mut d = [0; 18];
@@ -0,0 +1,101 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use quiche::crypto;
+
+const DCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+fn aead(c: &mut Criterion) {
+    let (open, seal) = crypto::derive_initial_key_material(
+        &DCID,
+        quiche::PROTOCOL_VERSION,
+        false,
+    )
+    .unwrap();
+
+    // The client's keys are used to open what the client seals.
+    let (peer_open, _) = crypto::derive_initial_key_material(
+        &DCID,
+        quiche::PROTOCOL_VERSION,
+        true,
+    )
+    .unwrap();
+
+    let ad = [0xab; 20];
+
+    let mut group = c.benchmark_group("aead");
+
+    for size in [64, 512, 1350] {
+        let tag_len = seal.alg().tag_len();
+
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("seal", size),
+            &size,
+            |b, &size| {
+                let mut buf = vec![0; size + tag_len];
+
+                b.iter(|| {
+                    seal.seal_with_u64_counter(1, &ad, &mut buf, size, None)
+                        .unwrap()
+                });
+            },
+        );
+
+        let mut sealed = vec![0; size + tag_len];
+        seal.seal_with_u64_counter(1, &ad, &mut sealed, size, None)
+            .unwrap();
+
+        group.bench_with_input(BenchmarkId::new("open", size), &size, |b, _| {
+            let mut buf = sealed.clone();
+
+            b.iter(|| {
+                buf.copy_from_slice(&sealed);
+
+                peer_open.open_with_u64_counter(1, &ad, &mut buf).unwrap()
+            });
+        });
+    }
+
+    group.finish();
+
+    // Header protection is applied to every packet as well.
+    c.bench_function("header_protection_mask", |b| {
+        let sample = [0x5a; 16];
+
+        b.iter(|| open.new_mask(&sample).unwrap());
+    });
+}
+
+criterion_group!(benches, aead);
+criterion_main!(benches);
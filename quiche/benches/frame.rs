@@ -0,0 +1,132 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use quiche::frame::Frame;
+use quiche::Type;
+
+fn stream_frame(len: usize) -> Vec<u8> {
+    let mut out = vec![0; len + 32];
+    let mut b = octets::OctetsMut::with_slice(&mut out);
+
+    // STREAM frame with the OFF, LEN and FIN bits set.
+    b.put_varint(0x0f).unwrap();
+    b.put_varint(4).unwrap();
+    b.put_varint(1 << 20).unwrap();
+    b.put_varint(len as u64).unwrap();
+    b.put_bytes(&vec![0xaa; len]).unwrap();
+
+    let written = b.off();
+    out.truncate(written);
+    out
+}
+
+fn ack_frame(ranges: u64) -> Vec<u8> {
+    let mut out = vec![0; 1350];
+    let mut b = octets::OctetsMut::with_slice(&mut out);
+
+    b.put_varint(0x02).unwrap();
+    b.put_varint(ranges * 10).unwrap(); // largest acknowledged
+    b.put_varint(100).unwrap(); // ACK delay
+    b.put_varint(ranges - 1).unwrap(); // range count
+    b.put_varint(3).unwrap(); // first range
+
+    for _ in 1..ranges {
+        b.put_varint(2).unwrap(); // gap
+        b.put_varint(3).unwrap(); // range length
+    }
+
+    let written = b.off();
+    out.truncate(written);
+    out
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_parse");
+
+    let stream = stream_frame(1300);
+
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("stream", |b| {
+        b.iter(|| {
+            let mut o = octets::Octets::with_slice(&stream);
+            Frame::from_bytes(&mut o, Type::Short).unwrap()
+        });
+    });
+
+    let ack = ack_frame(32);
+
+    group.throughput(Throughput::Bytes(ack.len() as u64));
+    group.bench_function("ack", |b| {
+        b.iter(|| {
+            let mut o = octets::Octets::with_slice(&ack);
+            Frame::from_bytes(&mut o, Type::Short).unwrap()
+        });
+    });
+
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_encode");
+
+    let mut out = vec![0; 1500];
+
+    let stream = stream_frame(1300);
+    let frame =
+        Frame::from_bytes(&mut octets::Octets::with_slice(&stream), Type::Short)
+            .unwrap();
+
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("stream", |b| {
+        b.iter(|| {
+            let mut o = octets::OctetsMut::with_slice(&mut out);
+            frame.to_bytes(&mut o).unwrap()
+        });
+    });
+
+    let ack = ack_frame(32);
+    let frame =
+        Frame::from_bytes(&mut octets::Octets::with_slice(&ack), Type::Short)
+            .unwrap();
+
+    group.throughput(Throughput::Bytes(ack.len() as u64));
+    group.bench_function("ack", |b| {
+        b.iter(|| {
+            let mut o = octets::OctetsMut::with_slice(&mut out);
+            frame.to_bytes(&mut o).unwrap()
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, encode);
+criterion_main!(benches);
@@ -0,0 +1,211 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use quiche::crypto;
+use quiche::packet;
+use quiche::Header;
+
+const DCID: [u8; 16] = [0xba; 16];
+
+const PN: u64 = 0x1234;
+
+const PN_LEN: usize = 2;
+
+const PAYLOAD_LEN: usize = 1300;
+
+/// Writes a protected 1-RTT packet into `out`, returning its length.
+fn encode_short(out: &mut [u8], seal: &crypto::Seal) -> usize {
+    encode_short_with_pn_len(out, seal, PN_LEN)
+}
+
+/// Writes a protected 1-RTT packet with a packet number of the given length
+/// into `out`, returning its length.
+fn encode_short_with_pn_len(
+    out: &mut [u8], seal: &crypto::Seal, pn_len: usize,
+) -> usize {
+    let mut b = octets::OctetsMut::with_slice(out);
+
+    b.put_u8(0x40 | (pn_len - 1) as u8).unwrap();
+    b.put_bytes(&DCID).unwrap();
+    packet::encode_pkt_num(PN, pn_len, &mut b).unwrap();
+
+    let payload_offset = b.off();
+
+    b.put_bytes(&[0xaa; PAYLOAD_LEN]).unwrap();
+
+    packet::encrypt_pkt(
+        &mut b,
+        PN,
+        pn_len,
+        PAYLOAD_LEN,
+        payload_offset,
+        None,
+        seal,
+    )
+    .unwrap()
+}
+
+fn short_packet(c: &mut Criterion) {
+    let (_, seal) = crypto::derive_initial_key_material(
+        &DCID,
+        quiche::PROTOCOL_VERSION,
+        false,
+    )
+    .unwrap();
+
+    let (open, _) = crypto::derive_initial_key_material(
+        &DCID,
+        quiche::PROTOCOL_VERSION,
+        true,
+    )
+    .unwrap();
+
+    let mut out = [0; 1500];
+    let len = encode_short(&mut out, &seal);
+    let pkt = out[..len].to_vec();
+
+    let mut group = c.benchmark_group("packet");
+    group.throughput(Throughput::Bytes(len as u64));
+
+    group.bench_function("encode_short", |b| {
+        b.iter(|| encode_short(&mut out, &seal));
+    });
+
+    group.bench_function("parse_header_short", |b| {
+        let mut buf = pkt.clone();
+
+        b.iter(|| Header::from_slice(&mut buf, DCID.len()).unwrap().ty);
+    });
+
+    group.bench_function("decode_short", |b| {
+        let mut hdr_buf = pkt.clone();
+        let mut hdr = Header::from_slice(&mut hdr_buf, DCID.len()).unwrap();
+
+        let mut buf = pkt.clone();
+
+        b.iter(|| {
+            buf.copy_from_slice(&pkt);
+
+            let mut b = octets::OctetsMut::with_slice(&mut buf);
+            b.skip(1 + DCID.len()).unwrap();
+
+            packet::decrypt_hdr(&mut b, &mut hdr, &open).unwrap();
+
+            let payload_len = b.cap() + PN_LEN;
+
+            packet::decrypt_pkt(&mut b, PN, PN_LEN, payload_len, &open)
+                .unwrap()
+                .len()
+        });
+    });
+
+    group.finish();
+}
+
+/// Measures the failure paths of packet decryption, which shouldn't depend on
+/// the protected packet number length, nor on where authentication failed.
+fn short_packet_timing(c: &mut Criterion) {
+    let (_, seal) = crypto::derive_initial_key_material(
+        &DCID,
+        quiche::PROTOCOL_VERSION,
+        false,
+    )
+    .unwrap();
+
+    let (open, _) = crypto::derive_initial_key_material(
+        &DCID,
+        quiche::PROTOCOL_VERSION,
+        true,
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("packet_timing");
+
+    for pn_len in 1..=packet::MAX_PKT_NUM_LEN {
+        let mut out = [0; 1500];
+        let len = encode_short_with_pn_len(&mut out, &seal, pn_len);
+        let pkt = out[..len].to_vec();
+
+        group.bench_function(format!("decrypt_hdr_pn_len_{pn_len}"), |b| {
+            let mut hdr_buf = pkt.clone();
+            let mut hdr = Header::from_slice(&mut hdr_buf, DCID.len()).unwrap();
+
+            let mut buf = pkt.clone();
+
+            b.iter(|| {
+                buf.copy_from_slice(&pkt);
+
+                let mut b = octets::OctetsMut::with_slice(&mut buf);
+                b.skip(1 + DCID.len()).unwrap();
+
+                packet::decrypt_hdr(&mut b, &mut hdr, &open).unwrap();
+
+                b.off()
+            });
+        });
+    }
+
+    let mut out = [0; 1500];
+    let len = encode_short(&mut out, &seal);
+
+    // Corrupt the packet either right after the packet number, or in the
+    // authentication tag.
+    for (name, off) in [("payload", 1 + DCID.len() + PN_LEN), ("tag", len - 1)] {
+        let mut pkt = out[..len].to_vec();
+        pkt[off] ^= 0xff;
+
+        group.bench_function(format!("decode_short_invalid_{name}"), |b| {
+            let mut hdr_buf = pkt.clone();
+            let mut hdr = Header::from_slice(&mut hdr_buf, DCID.len()).unwrap();
+
+            let mut buf = pkt.clone();
+
+            b.iter(|| {
+                buf.copy_from_slice(&pkt);
+
+                let mut b = octets::OctetsMut::with_slice(&mut buf);
+                b.skip(1 + DCID.len()).unwrap();
+
+                packet::decrypt_hdr(&mut b, &mut hdr, &open).unwrap();
+
+                let payload_len = b.cap() + PN_LEN;
+
+                packet::decrypt_pkt(&mut b, PN, PN_LEN, payload_len, &open)
+                    .is_err()
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, short_packet, short_packet_timing);
+criterion_main!(benches);
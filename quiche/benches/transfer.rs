@@ -0,0 +1,109 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use quiche::testing::Pipe;
+
+const TRANSFER_LEN: usize = 1 << 30;
+
+const CHUNK_LEN: usize = 64 * 1024;
+
+fn pipe() -> Pipe {
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+    config
+        .load_cert_chain_from_pem_file("examples/cert.crt")
+        .unwrap();
+    config
+        .load_priv_key_from_pem_file("examples/cert.key")
+        .unwrap();
+    config
+        .set_application_protos(&[b"proto1", b"proto2"])
+        .unwrap();
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(1_000_000);
+    config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_streams_bidi(10);
+    config.verify_peer(false);
+
+    let mut pipe = Pipe::with_config(&mut config).unwrap();
+    pipe.handshake().unwrap();
+
+    pipe
+}
+
+/// Sends `TRANSFER_LEN` bytes on a single stream from the client to the
+/// server, entirely in memory.
+fn transfer(pipe: &mut Pipe, send_buf: &[u8], recv_buf: &mut [u8]) {
+    let mut sent = 0;
+    let mut recv = 0;
+
+    while recv < TRANSFER_LEN {
+        if sent < TRANSFER_LEN {
+            let len = std::cmp::min(send_buf.len(), TRANSFER_LEN - sent);
+            let fin = sent + len == TRANSFER_LEN;
+
+            match pipe.client.stream_send(0, &send_buf[..len], fin) {
+                Ok(v) => sent += v,
+
+                Err(quiche::Error::Done) => (),
+
+                Err(e) => panic!("stream_send failed: {:?}", e),
+            }
+        }
+
+        pipe.advance().unwrap();
+
+        while let Ok((len, _)) = pipe.server.stream_recv(0, recv_buf) {
+            recv += len;
+        }
+    }
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let send_buf = vec![0xaa; CHUNK_LEN];
+    let mut recv_buf = vec![0; CHUNK_LEN];
+
+    let mut group = c.benchmark_group("transfer");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(TRANSFER_LEN as u64));
+
+    group.bench_function("1GB", |b| {
+        b.iter_batched(
+            pipe,
+            |mut pipe| transfer(&mut pipe, &send_buf, &mut recv_buf),
+            criterion::BatchSize::PerIteration,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, end_to_end);
+criterion_main!(benches);
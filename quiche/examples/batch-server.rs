@@ -0,0 +1,139 @@
+// Copyright (C) 2018-2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A server echoing stream data back to clients, which reads datagrams in
+//! batches and lets `quiche::net::Listener` dispatch them to connections.
+
+#[macro_use]
+extern crate log;
+
+use quiche::net;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+// The number of datagrams read with a single system call.
+const BATCH_SIZE: usize = 32;
+
+fn main() {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    if args.len() != 0 {
+        println!("Usage: {cmd}");
+        println!("\nSee tools/apps/ for more complete implementations.");
+        return;
+    }
+
+    // Setup the event loop.
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    // Create the UDP listening socket, and register it with the event loop.
+    let mut socket =
+        mio::net::UdpSocket::bind("127.0.0.1:4433".parse().unwrap()).unwrap();
+    poll.registry()
+        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .unwrap();
+
+    let caps = net::configure(&socket).unwrap();
+    debug!("socket capabilities: {:?}", caps);
+
+    let local_addr = socket.local_addr().unwrap();
+
+    // Create the configuration for the QUIC connections.
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    config
+        .load_cert_chain_from_pem_file("examples/cert.crt")
+        .unwrap();
+    config
+        .load_priv_key_from_pem_file("examples/cert.key")
+        .unwrap();
+
+    config
+        .set_application_protos(&[b"hq-interop", b"http/0.9"])
+        .unwrap();
+
+    config.set_max_idle_timeout(5000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(1_000_000);
+    config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_streams_bidi(100);
+    config.set_disable_active_migration(true);
+
+    let mut listener = net::Listener::new(config);
+
+    let mut datagrams = vec![net::Datagram::new(65535); BATCH_SIZE];
+
+    loop {
+        poll.poll(&mut events, listener.timeout()).unwrap();
+
+        // If the event loop reported no events, it means that the timeout
+        // has expired, so handle it without attempting to read packets.
+        if events.is_empty() {
+            listener.on_timeout();
+        }
+
+        // Read batches of datagrams until there are no more to read.
+        while !events.is_empty() {
+            let n = match net::recv_batch(&socket, &mut datagrams, local_addr) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        break;
+                    }
+
+                    panic!("recvmmsg() failed: {:?}", e);
+                },
+            };
+
+            let processed = listener.process_datagrams(&mut datagrams[..n]);
+            debug!("processed {} datagrams out of {}", processed, n);
+        }
+
+        // Echo stream data back to clients.
+        for conn in listener.connections() {
+            for s in conn.readable() {
+                while let Ok((read, fin)) = conn.stream_recv(s, &mut buf) {
+                    if let Err(e) = conn.stream_send(s, &buf[..read], fin) {
+                        error!("{} stream send failed {:?}", conn.trace_id(), e);
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = listener.send(&socket, &mut out) {
+            panic!("sendmsg() failed: {:?}", e);
+        }
+    }
+}
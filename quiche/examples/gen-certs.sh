@@ -5,6 +5,7 @@ openssl req -new -x509 -batch -nodes -days 10000 -keyout rootca.key -out rootca.
 openssl req -new -batch -nodes -sha256 -keyout cert.key -out cert.csr -subj '/C=GB/CN=quic.tech'
 openssl x509 -req -days 10000 -in cert.csr -CA rootca.crt -CAkey rootca.key -CAcreateserial -out cert.crt
 openssl verify -CAfile rootca.crt cert.crt
+openssl pkey -in cert.key -outform DER -out cert.key.der
 cp cert.crt cert-big.crt
 cat cert.crt >> cert-big.crt
 cat cert.crt >> cert-big.crt
@@ -0,0 +1,295 @@
+// Copyright (C) 2018-2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Client side of the HTTP/0.9 protocol used in interop testing.
+//!
+//! Each URL is requested with a single `GET /path\r\n` line on its own
+//! client-initiated bidirectional stream (0, 4, 8 and so on), which is then
+//! finished. The server replies with the file's contents on the same stream,
+//! and each response is printed once it has been received in full.
+//!
+//! All the URLs are fetched over the same connection, so they must have the
+//! same host and port.
+
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+
+use std::io::Write;
+
+use ring::rand::*;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+struct Request {
+    url: url::Url,
+
+    // The response received so far.
+    response: Vec<u8>,
+
+    // Whether the response was received in full.
+    finished: bool,
+}
+
+fn main() {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    if args.len() == 0 {
+        println!("Usage: {cmd} URL...");
+        println!("\nAll URLs must have the same host and port.");
+        return;
+    }
+
+    let urls: Vec<url::Url> =
+        args.map(|v| url::Url::parse(&v).unwrap()).collect();
+
+    let authority =
+        |url: &url::Url| (url.host_str().map(String::from), url.port());
+
+    if urls.iter().any(|url| authority(url) != authority(&urls[0])) {
+        println!("All URLs must have the same host and port.");
+        return;
+    }
+
+    // Setup the event loop.
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    // Resolve server address.
+    let peer_addr = urls[0].socket_addrs(|| None).unwrap()[0];
+
+    // Bind to INADDR_ANY or IN6ADDR_ANY depending on the IP family of the
+    // server address. This is needed on macOS and BSD variants that don't
+    // support binding to IN6ADDR_ANY for both v4 and v6.
+    let bind_addr = match peer_addr {
+        std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+        std::net::SocketAddr::V6(_) => "[::]:0",
+    };
+
+    // Create the UDP socket backing the QUIC connection, and register it with
+    // the event loop.
+    let mut socket =
+        mio::net::UdpSocket::bind(bind_addr.parse().unwrap()).unwrap();
+    poll.registry()
+        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .unwrap();
+
+    // Create the configuration for the QUIC connection.
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    // *CAUTION*: this should not be set to `false` in production!!!
+    config.verify_peer(false);
+
+    config
+        .set_application_protos(&[b"hq-interop", b"http/0.9"])
+        .unwrap();
+
+    config.set_max_idle_timeout(5000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(1_000_000);
+    config.set_initial_max_streams_bidi(0);
+    config.set_initial_max_streams_uni(0);
+    config.set_disable_active_migration(true);
+
+    // Generate a random source connection ID for the connection.
+    let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+    SystemRandom::new().fill(&mut scid[..]).unwrap();
+
+    let scid = quiche::ConnectionId::from_ref(&scid);
+
+    // Get local address.
+    let local_addr = socket.local_addr().unwrap();
+
+    // Create a QUIC connection and initiate handshake.
+    let mut conn = quiche::connect(
+        urls[0].domain(),
+        &scid,
+        local_addr,
+        peer_addr,
+        &mut config,
+    )
+    .unwrap();
+
+    info!("connecting to {:} from {:}", peer_addr, local_addr);
+
+    // Requests are sent once the handshake completes, each on the next
+    // client-initiated bidirectional stream.
+    let mut pending: Vec<(u64, url::Url)> = urls
+        .into_iter()
+        .enumerate()
+        .map(|(i, url)| (i as u64 * 4, url))
+        .rev()
+        .collect();
+
+    let mut requests: HashMap<u64, Request> = HashMap::new();
+
+    let req_start = std::time::Instant::now();
+
+    loop {
+        poll.poll(&mut events, conn.timeout()).unwrap();
+
+        // Read incoming UDP packets from the socket and feed them to quiche,
+        // until there are no more packets to read.
+        'read: loop {
+            if events.is_empty() {
+                debug!("timed out");
+
+                conn.on_timeout();
+                break 'read;
+            }
+
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        debug!("recv() would block");
+                        break 'read;
+                    }
+
+                    panic!("recv() failed: {:?}", e);
+                },
+            };
+
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+            };
+
+            // Process potentially coalesced packets.
+            if let Err(e) = conn.recv(&mut buf[..len], recv_info) {
+                error!("recv failed: {:?}", e);
+                continue 'read;
+            }
+        }
+
+        if conn.is_closed() {
+            info!("connection closed, {:?}", conn.stats());
+            break;
+        }
+
+        // Send as many requests as the server's stream limit allows.
+        if conn.is_established() {
+            while let Some((stream_id, url)) = pending.pop() {
+                if conn.peer_streams_left_bidi() == 0 {
+                    pending.push((stream_id, url));
+                    break;
+                }
+
+                info!("sending request for {} on stream {}", url, stream_id);
+
+                let req = format!("GET {}\r\n", url.path());
+
+                if let Err(e) = conn.stream_send(stream_id, req.as_bytes(), true)
+                {
+                    error!("failed to send request {:?}", e);
+                    pending.push((stream_id, url));
+                    break;
+                }
+
+                requests.insert(stream_id, Request {
+                    url,
+                    response: Vec::new(),
+                    finished: false,
+                });
+            }
+        }
+
+        // Process all readable streams.
+        for s in conn.readable() {
+            let req = match requests.get_mut(&s) {
+                Some(v) => v,
+
+                None => continue,
+            };
+
+            while let Ok((read, fin)) = conn.stream_recv(s, &mut buf) {
+                debug!("stream {} has {} bytes (fin? {})", s, read, fin);
+
+                req.response.extend_from_slice(&buf[..read]);
+
+                if fin {
+                    info!(
+                        "response for {} received in {:?}",
+                        req.url,
+                        req_start.elapsed()
+                    );
+
+                    std::io::stdout().write_all(&req.response).unwrap();
+
+                    req.finished = true;
+                }
+            }
+        }
+
+        // Close the connection once all the responses were received.
+        if pending.is_empty() &&
+            requests.values().all(|r| r.finished) &&
+            conn.close(true, 0x00, b"kthxbye").is_ok()
+        {
+            info!("all responses received, closing...");
+        }
+
+        // Generate outgoing QUIC packets and send them on the UDP socket, until
+        // quiche reports that there are no more packets to be sent.
+        loop {
+            let (write, send_info) = match conn.send(&mut out) {
+                Ok(v) => v,
+
+                Err(quiche::Error::Done) => break,
+
+                Err(e) => {
+                    error!("send failed: {:?}", e);
+
+                    conn.close(false, 0x1, b"fail").ok();
+                    break;
+                },
+            };
+
+            if let Err(e) = socket.send_to(&out[..write], send_info.to) {
+                if e.kind() == std::io::ErrorKind::WouldBlock {
+                    debug!("send() would block");
+                    break;
+                }
+
+                panic!("send() failed: {:?}", e);
+            }
+        }
+
+        if conn.is_closed() {
+            info!("connection closed, {:?}", conn.stats());
+            break;
+        }
+    }
+}
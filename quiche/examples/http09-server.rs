@@ -0,0 +1,449 @@
+// Copyright (C) 2018-2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Server side of the HTTP/0.9 protocol used in interop testing.
+//!
+//! Clients send a single `GET /path\r\n` line on each client-initiated
+//! bidirectional stream and finish the stream. The server replies on the same
+//! stream with the contents of the file at that path, relative to its root
+//! directory, and finishes the stream. There are no headers or status codes,
+//! so a missing file is reported with a short plain text body.
+
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+
+use ring::rand::*;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+// Requests are a single short line, so anything longer is rejected.
+const MAX_REQUEST_LEN: usize = 8192;
+
+// The response sent when the requested file can't be read.
+const NOT_FOUND: &[u8] = b"Not Found!\r\n";
+
+struct Stream {
+    // The part of the request received so far.
+    request: Vec<u8>,
+
+    // The response, once the request is complete.
+    response: Option<Vec<u8>>,
+
+    // The number of response bytes written to the stream so far.
+    written: usize,
+}
+
+struct Client {
+    conn: quiche::Connection,
+
+    streams: HashMap<u64, Stream>,
+}
+
+type ClientMap = HashMap<quiche::ConnectionId<'static>, Client>;
+
+fn main() {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    if args.len() > 2 {
+        println!("Usage: {cmd} [ADDR [ROOT]]");
+        println!("\nListens on 127.0.0.1:4433 unless ADDR is given, and serves");
+        println!("files from ROOT, examples/root by default.");
+        return;
+    }
+
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:4433".to_string());
+    let root = args.next().unwrap_or_else(|| "examples/root".to_string());
+
+    // Setup the event loop.
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    // Create the UDP listening socket, and register it with the event loop.
+    let mut socket = mio::net::UdpSocket::bind(addr.parse().unwrap()).unwrap();
+    poll.registry()
+        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .unwrap();
+
+    // Create the configuration for the QUIC connections.
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    config
+        .load_cert_chain_from_pem_file("examples/cert.crt")
+        .unwrap();
+    config
+        .load_priv_key_from_pem_file("examples/cert.key")
+        .unwrap();
+
+    config
+        .set_application_protos(&[b"hq-interop", b"http/0.9"])
+        .unwrap();
+
+    config.set_max_idle_timeout(5000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(1_000_000);
+    config.set_initial_max_stream_data_bidi_remote(MAX_REQUEST_LEN as u64);
+    config.set_initial_max_streams_bidi(100);
+    config.set_initial_max_streams_uni(0);
+    config.set_disable_active_migration(true);
+
+    let rng = SystemRandom::new();
+    let conn_id_seed =
+        ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
+
+    let mut clients = ClientMap::new();
+
+    let local_addr = socket.local_addr().unwrap();
+
+    info!("listening on {}, serving {}", local_addr, root);
+
+    loop {
+        // Find the shorter timeout from all the active connections.
+        let timeout = clients.values().filter_map(|c| c.conn.timeout()).min();
+
+        poll.poll(&mut events, timeout).unwrap();
+
+        // Read incoming UDP packets from the socket and feed them to quiche,
+        // until there are no more packets to read.
+        'read: loop {
+            if events.is_empty() {
+                debug!("timed out");
+
+                clients.values_mut().for_each(|c| c.conn.on_timeout());
+
+                break 'read;
+            }
+
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        debug!("recv() would block");
+                        break 'read;
+                    }
+
+                    panic!("recv() failed: {:?}", e);
+                },
+            };
+
+            let pkt_buf = &mut buf[..len];
+
+            // Parse the QUIC packet's header.
+            let hdr = match quiche::Header::from_slice(
+                pkt_buf,
+                quiche::MAX_CONN_ID_LEN,
+            ) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    error!("Parsing packet header failed: {:?}", e);
+                    continue 'read;
+                },
+            };
+
+            let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
+            let conn_id = &conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN];
+            let conn_id: quiche::ConnectionId<'static> = conn_id.to_vec().into();
+
+            // Lookup a connection based on the packet's connection ID. If there
+            // is no connection matching, create a new one.
+            let client = if !clients.contains_key(&hdr.dcid) &&
+                !clients.contains_key(&conn_id)
+            {
+                if hdr.ty != quiche::Type::Initial {
+                    error!("Packet is not Initial");
+                    continue 'read;
+                }
+
+                if !quiche::version_is_supported(hdr.version) {
+                    warn!("Doing version negotiation");
+
+                    let len =
+                        quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)
+                            .unwrap();
+
+                    if let Err(e) = socket.send_to(&out[..len], from) {
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                            debug!("send() would block");
+                            break;
+                        }
+
+                        panic!("send() failed: {:?}", e);
+                    }
+                    continue 'read;
+                }
+
+                debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, conn_id);
+
+                let conn =
+                    quiche::accept(&conn_id, None, local_addr, from, &mut config)
+                        .unwrap();
+
+                let client = Client {
+                    conn,
+                    streams: HashMap::new(),
+                };
+
+                clients.insert(conn_id.clone(), client);
+
+                clients.get_mut(&conn_id).unwrap()
+            } else {
+                match clients.get_mut(&hdr.dcid) {
+                    Some(v) => v,
+
+                    None => clients.get_mut(&conn_id).unwrap(),
+                }
+            };
+
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+            };
+
+            // Process potentially coalesced packets.
+            if let Err(e) = client.conn.recv(pkt_buf, recv_info) {
+                error!("{} recv failed: {:?}", client.conn.trace_id(), e);
+                continue 'read;
+            }
+
+            if client.conn.is_established() {
+                for stream_id in client.conn.readable() {
+                    handle_readable(client, stream_id, &mut buf, &root);
+                }
+
+                for stream_id in client.conn.writable() {
+                    handle_writable(client, stream_id);
+                }
+            }
+        }
+
+        // Generate outgoing QUIC packets for all active connections and send
+        // them on the UDP socket, until quiche reports that there are no more
+        // packets to be sent.
+        for client in clients.values_mut() {
+            loop {
+                let (write, send_info) = match client.conn.send(&mut out) {
+                    Ok(v) => v,
+
+                    Err(quiche::Error::Done) => break,
+
+                    Err(e) => {
+                        error!("{} send failed: {:?}", client.conn.trace_id(), e);
+
+                        client.conn.close(false, 0x1, b"fail").ok();
+                        break;
+                    },
+                };
+
+                if let Err(e) = socket.send_to(&out[..write], send_info.to) {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        debug!("send() would block");
+                        break;
+                    }
+
+                    panic!("send() failed: {:?}", e);
+                }
+            }
+        }
+
+        // Garbage collect closed connections.
+        clients.retain(|_, ref mut c| {
+            if c.conn.is_closed() {
+                info!(
+                    "{} connection collected {:?}",
+                    c.conn.trace_id(),
+                    c.conn.stats()
+                );
+            }
+
+            !c.conn.is_closed()
+        });
+    }
+}
+
+/// Accumulates the request received on the given stream, and prepares the
+/// response once the request line is complete.
+fn handle_readable(
+    client: &mut Client, stream_id: u64, buf: &mut [u8], root: &str,
+) {
+    let conn = &mut client.conn;
+
+    let stream = client.streams.entry(stream_id).or_insert_with(|| Stream {
+        request: Vec::new(),
+        response: None,
+        written: 0,
+    });
+
+    loop {
+        let (read, fin) = match conn.stream_recv(stream_id, buf) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => break,
+
+            Err(e) => {
+                error!("{} stream recv failed {:?}", conn.trace_id(), e);
+                return;
+            },
+        };
+
+        // Anything after the request line is ignored.
+        if stream.response.is_some() {
+            continue;
+        }
+
+        stream.request.extend_from_slice(&buf[..read]);
+
+        let complete = fin || stream.request.windows(2).any(|w| w == b"\r\n");
+
+        if !complete {
+            if stream.request.len() >= MAX_REQUEST_LEN {
+                error!(
+                    "{} request too long on stream {}",
+                    conn.trace_id(),
+                    stream_id
+                );
+
+                conn.stream_shutdown(stream_id, quiche::Shutdown::Read, 0x1)
+                    .ok();
+                conn.stream_send(stream_id, b"", true).ok();
+
+                client.streams.remove(&stream_id);
+                return;
+            }
+
+            continue;
+        }
+
+        let response = match parse_request(&stream.request) {
+            Some(path) => {
+                let path = resolve_path(root, path);
+
+                info!(
+                    "{} got GET request for {:?} on stream {}",
+                    conn.trace_id(),
+                    path,
+                    stream_id
+                );
+
+                std::fs::read(&path).unwrap_or_else(|_| NOT_FOUND.to_vec())
+            },
+
+            None => {
+                error!(
+                    "{} invalid request on stream {}",
+                    conn.trace_id(),
+                    stream_id
+                );
+
+                NOT_FOUND.to_vec()
+            },
+        };
+
+        info!(
+            "{} sending response of size {} on stream {}",
+            conn.trace_id(),
+            response.len(),
+            stream_id
+        );
+
+        stream.response = Some(response);
+    }
+
+    handle_writable(client, stream_id);
+}
+
+/// Writes as much of the pending response as flow control allows.
+fn handle_writable(client: &mut Client, stream_id: u64) {
+    let conn = &mut client.conn;
+
+    let stream = match client.streams.get_mut(&stream_id) {
+        Some(v) => v,
+
+        None => return,
+    };
+
+    let response = match &stream.response {
+        Some(v) => v,
+
+        None => return,
+    };
+
+    let written =
+        match conn.stream_send(stream_id, &response[stream.written..], true) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => 0,
+
+            Err(e) => {
+                client.streams.remove(&stream_id);
+
+                error!("{} stream send failed {:?}", conn.trace_id(), e);
+                return;
+            },
+        };
+
+    stream.written += written;
+
+    if stream.written == response.len() {
+        client.streams.remove(&stream_id);
+    }
+}
+
+/// Returns the path requested by a `GET /path\r\n` line, if valid.
+fn parse_request(request: &[u8]) -> Option<&str> {
+    let line = request.split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?.trim_end_matches('\r');
+
+    let path = line.strip_prefix("GET ")?.trim();
+
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    Some(path)
+}
+
+/// Maps a request path to a file under `root`, ignoring any component that
+/// would escape it.
+fn resolve_path(root: &str, path: &str) -> std::path::PathBuf {
+    let mut file = std::path::PathBuf::from(root);
+
+    for c in std::path::Path::new(path).components() {
+        if let std::path::Component::Normal(v) = c {
+            file.push(v);
+        }
+    }
+
+    file
+}
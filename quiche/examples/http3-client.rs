@@ -284,6 +284,12 @@ fn main() {
                         info!("GOAWAY id={}", goaway_id);
                     },
 
+                    Ok((_, quiche::h3::Event::PushPromise { .. })) =>
+                        unreachable!(),
+
+                    Ok((_, quiche::h3::Event::PushCancelled { .. })) =>
+                        unreachable!(),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },
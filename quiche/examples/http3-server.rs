@@ -374,6 +374,10 @@ fn main() {
 
                         Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
 
+                        Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                        Ok((_, quiche::h3::Event::PushCancelled { .. })) => (),
+
                         Err(quiche::h3::Error::Done) => {
                             break;
                         },
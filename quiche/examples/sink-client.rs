@@ -0,0 +1,299 @@
+// Copyright (C) 2018-2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Client side of the "sink" throughput test protocol.
+//!
+//! Sends the requested amount of data to a `sink-server` and prints the
+//! server's periodic reports, followed by the client's own view of the path
+//! (packet loss, RTT and congestion window) as the sender.
+
+#[macro_use]
+extern crate log;
+
+use ring::rand::*;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+const DATA_STREAM_ID: u64 = 0;
+
+const CONTROL_STREAM_ID: u64 = 3;
+
+// The amount of data sent when no size is given, 1 GB.
+const DEFAULT_SIZE: u64 = 1_000_000_000;
+
+fn main() {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    // The data sent is irrelevant, so always send the same chunk of zeros.
+    let chunk = [0; 65535];
+
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    if args.len() != 1 && args.len() != 2 {
+        println!("Usage: {cmd} URL [SIZE]");
+        println!("\nSIZE is a number of bytes, with an optional K, M or G");
+        println!("suffix. The default is 1G.");
+        return;
+    }
+
+    let url = url::Url::parse(&args.next().unwrap()).unwrap();
+
+    let size = match args.next() {
+        Some(v) => match parse_size(&v) {
+            Some(v) => v,
+
+            None => {
+                println!("Invalid size {v}");
+                return;
+            },
+        },
+
+        None => DEFAULT_SIZE,
+    };
+
+    // Setup the event loop.
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    // Resolve server address.
+    let peer_addr = url.socket_addrs(|| None).unwrap()[0];
+
+    // Bind to INADDR_ANY or IN6ADDR_ANY depending on the IP family of the
+    // server address. This is needed on macOS and BSD variants that don't
+    // support binding to IN6ADDR_ANY for both v4 and v6.
+    let bind_addr = match peer_addr {
+        std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+        std::net::SocketAddr::V6(_) => "[::]:0",
+    };
+
+    // Create the UDP socket backing the QUIC connection, and register it with
+    // the event loop.
+    let mut socket =
+        mio::net::UdpSocket::bind(bind_addr.parse().unwrap()).unwrap();
+    poll.registry()
+        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .unwrap();
+
+    // Create the configuration for the QUIC connection.
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    // *CAUTION*: this should not be set to `false` in production!!!
+    config.verify_peer(false);
+
+    config.set_application_protos(&[b"sink"]).unwrap();
+
+    config.set_max_idle_timeout(5000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(1_000_000);
+    config.set_initial_max_stream_data_uni(1_000_000);
+    config.set_initial_max_streams_bidi(0);
+    config.set_initial_max_streams_uni(1);
+    config.set_disable_active_migration(true);
+
+    // Generate a random source connection ID for the connection.
+    let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+    SystemRandom::new().fill(&mut scid[..]).unwrap();
+
+    let scid = quiche::ConnectionId::from_ref(&scid);
+
+    // Get local address.
+    let local_addr = socket.local_addr().unwrap();
+
+    // Create a QUIC connection and initiate handshake.
+    let mut conn =
+        quiche::connect(url.domain(), &scid, local_addr, peer_addr, &mut config)
+            .unwrap();
+
+    info!("connecting to {:} from {:}", peer_addr, local_addr);
+
+    let (write, send_info) = conn.send(&mut out).expect("initial send failed");
+
+    while let Err(e) = socket.send_to(&out[..write], send_info.to) {
+        if e.kind() == std::io::ErrorKind::WouldBlock {
+            debug!("send() would block");
+            continue;
+        }
+
+        panic!("send() failed: {:?}", e);
+    }
+
+    // The number of bytes written to the data stream so far.
+    let mut sent = 0;
+
+    // Report data received from the server that doesn't form a full line yet.
+    let mut report = Vec::new();
+
+    loop {
+        poll.poll(&mut events, conn.timeout()).unwrap();
+
+        // Read incoming UDP packets from the socket and feed them to quiche,
+        // until there are no more packets to read.
+        'read: loop {
+            if events.is_empty() {
+                debug!("timed out");
+
+                conn.on_timeout();
+                break 'read;
+            }
+
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        debug!("recv() would block");
+                        break 'read;
+                    }
+
+                    panic!("recv() failed: {:?}", e);
+                },
+            };
+
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+            };
+
+            // Process potentially coalesced packets.
+            if let Err(e) = conn.recv(&mut buf[..len], recv_info) {
+                error!("recv failed: {:?}", e);
+                continue 'read;
+            }
+        }
+
+        if conn.is_closed() {
+            info!("connection closed, {:?}", conn.stats());
+            break;
+        }
+
+        // Print the reports received on the control stream, along with the
+        // sender's view of the path at the time.
+        while let Ok((read, fin)) = conn.stream_recv(CONTROL_STREAM_ID, &mut buf)
+        {
+            report.extend_from_slice(&buf[..read]);
+
+            while let Some(pos) = report.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = report.drain(..=pos).collect();
+
+                print_report(&conn, &line[..pos]);
+            }
+
+            // The server received all the data, so we are done.
+            if fin {
+                info!("transfer of {} bytes complete, closing...", sent);
+
+                conn.close(true, 0x00, b"kthxbye").unwrap();
+            }
+        }
+
+        // Write as much data as flow and congestion control allow.
+        while conn.is_established() && sent < size {
+            let left = std::cmp::min(size - sent, chunk.len() as u64) as usize;
+
+            let fin = left as u64 == size - sent;
+
+            match conn.stream_send(DATA_STREAM_ID, &chunk[..left], fin) {
+                Ok(v) => sent += v as u64,
+
+                Err(quiche::Error::Done) => break,
+
+                Err(e) => {
+                    error!("stream send failed {:?}", e);
+
+                    conn.close(false, 0x1, b"fail").ok();
+                    break;
+                },
+            }
+        }
+
+        // Generate outgoing QUIC packets and send them on the UDP socket, until
+        // quiche reports that there are no more packets to be sent.
+        loop {
+            let (write, send_info) = match conn.send(&mut out) {
+                Ok(v) => v,
+
+                Err(quiche::Error::Done) => break,
+
+                Err(e) => {
+                    error!("send failed: {:?}", e);
+
+                    conn.close(false, 0x1, b"fail").ok();
+                    break;
+                },
+            };
+
+            if let Err(e) = socket.send_to(&out[..write], send_info.to) {
+                if e.kind() == std::io::ErrorKind::WouldBlock {
+                    debug!("send() would block");
+                    break;
+                }
+
+                panic!("send() failed: {:?}", e);
+            }
+        }
+
+        if conn.is_closed() {
+            info!("connection closed, {:?}", conn.stats());
+            break;
+        }
+    }
+}
+
+/// Prints a report line from the server, followed by local sender stats.
+fn print_report(conn: &quiche::Connection, line: &[u8]) {
+    let stats = conn.stats();
+
+    let path = conn.path_stats().find(|p| p.active);
+
+    let (rtt, cwnd) =
+        path.map_or((std::time::Duration::ZERO, 0), |p| (p.rtt, p.cwnd));
+
+    println!(
+        "{} sent_pkts={} lost_pkts={} lost_bytes={} local_rtt={:.3}ms cwnd={}",
+        String::from_utf8_lossy(line),
+        stats.sent,
+        stats.lost,
+        stats.lost_bytes,
+        rtt.as_secs_f64() * 1000.0,
+        cwnd,
+    );
+}
+
+/// Parses a byte count with an optional K, M or G (decimal) suffix.
+fn parse_size(v: &str) -> Option<u64> {
+    let (num, mult) = match v.as_bytes().last()? {
+        b'K' | b'k' => (&v[..v.len() - 1], 1_000),
+        b'M' | b'm' => (&v[..v.len() - 1], 1_000_000),
+        b'G' | b'g' => (&v[..v.len() - 1], 1_000_000_000),
+        _ => (v, 1),
+    };
+
+    num.parse::<u64>().ok()?.checked_mul(mult)
+}
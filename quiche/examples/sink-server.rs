@@ -0,0 +1,433 @@
+// Copyright (C) 2018-2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Server side of the "sink" throughput test protocol.
+//!
+//! Clients send an arbitrary amount of data on the first client-initiated
+//! bidirectional stream, which the server reads and discards. While the
+//! transfer is in progress, the server periodically reports the goodput and
+//! its own view of the path on a server-initiated unidirectional control
+//! stream, one line of text per report. Once the data stream is finished, a
+//! final report is sent and the control stream is closed.
+
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use ring::rand::*;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+const DATA_STREAM_ID: u64 = 0;
+
+const CONTROL_STREAM_ID: u64 = 3;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Client {
+    conn: quiche::Connection,
+
+    // The time the first byte was received on the data stream.
+    start: Option<Instant>,
+
+    // The time the next periodic report is due.
+    next_report: Option<Instant>,
+
+    // The number of bytes received on the data stream.
+    received: u64,
+
+    // Whether the final report was sent.
+    finished: bool,
+
+    // Report data that didn't fit in the control stream yet.
+    report_buf: Vec<u8>,
+
+    // Whether the control stream is finished once the report data is sent.
+    report_fin: bool,
+}
+
+type ClientMap = HashMap<quiche::ConnectionId<'static>, Client>;
+
+fn main() {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    if args.len() > 1 {
+        println!("Usage: {cmd} [ADDR]");
+        println!("\nListens on 127.0.0.1:4433 unless ADDR is given.");
+        return;
+    }
+
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:4433".to_string());
+
+    // Setup the event loop.
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    // Create the UDP listening socket, and register it with the event loop.
+    let mut socket = mio::net::UdpSocket::bind(addr.parse().unwrap()).unwrap();
+    poll.registry()
+        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .unwrap();
+
+    // Create the configuration for the QUIC connections.
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    config
+        .load_cert_chain_from_pem_file("examples/cert.crt")
+        .unwrap();
+    config
+        .load_priv_key_from_pem_file("examples/cert.key")
+        .unwrap();
+
+    config.set_application_protos(&[b"sink"]).unwrap();
+
+    config.set_max_idle_timeout(5000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(25_000_000);
+    config.set_initial_max_stream_data_bidi_remote(25_000_000);
+    config.set_initial_max_streams_bidi(1);
+    config.set_initial_max_streams_uni(0);
+    config.set_disable_active_migration(true);
+
+    let rng = SystemRandom::new();
+    let conn_id_seed =
+        ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
+
+    let mut clients = ClientMap::new();
+
+    let local_addr = socket.local_addr().unwrap();
+
+    info!("listening on {}", local_addr);
+
+    loop {
+        // Wake up for whichever comes first, a connection timeout or a
+        // pending report.
+        let now = Instant::now();
+
+        let timeout = clients
+            .values()
+            .flat_map(|c| {
+                let report =
+                    c.next_report.map(|t| t.saturating_duration_since(now));
+
+                c.conn.timeout().into_iter().chain(report)
+            })
+            .min();
+
+        poll.poll(&mut events, timeout).unwrap();
+
+        // Read incoming UDP packets from the socket and feed them to quiche,
+        // until there are no more packets to read.
+        'read: loop {
+            if events.is_empty() {
+                debug!("timed out");
+
+                clients.values_mut().for_each(|c| c.conn.on_timeout());
+
+                break 'read;
+            }
+
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        debug!("recv() would block");
+                        break 'read;
+                    }
+
+                    panic!("recv() failed: {:?}", e);
+                },
+            };
+
+            let pkt_buf = &mut buf[..len];
+
+            // Parse the QUIC packet's header.
+            let hdr = match quiche::Header::from_slice(
+                pkt_buf,
+                quiche::MAX_CONN_ID_LEN,
+            ) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    error!("Parsing packet header failed: {:?}", e);
+                    continue 'read;
+                },
+            };
+
+            let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
+            let conn_id = &conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN];
+            let conn_id: quiche::ConnectionId<'static> = conn_id.to_vec().into();
+
+            // Lookup a connection based on the packet's connection ID. If there
+            // is no connection matching, create a new one.
+            let client = if !clients.contains_key(&hdr.dcid) &&
+                !clients.contains_key(&conn_id)
+            {
+                if hdr.ty != quiche::Type::Initial {
+                    error!("Packet is not Initial");
+                    continue 'read;
+                }
+
+                if !quiche::version_is_supported(hdr.version) {
+                    warn!("Doing version negotiation");
+
+                    let len =
+                        quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)
+                            .unwrap();
+
+                    if let Err(e) = socket.send_to(&out[..len], from) {
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                            debug!("send() would block");
+                            break;
+                        }
+
+                        panic!("send() failed: {:?}", e);
+                    }
+                    continue 'read;
+                }
+
+                debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, conn_id);
+
+                let conn =
+                    quiche::accept(&conn_id, None, local_addr, from, &mut config)
+                        .unwrap();
+
+                let client = Client {
+                    conn,
+                    start: None,
+                    next_report: None,
+                    received: 0,
+                    finished: false,
+                    report_buf: Vec::new(),
+                    report_fin: false,
+                };
+
+                clients.insert(conn_id.clone(), client);
+
+                clients.get_mut(&conn_id).unwrap()
+            } else {
+                match clients.get_mut(&hdr.dcid) {
+                    Some(v) => v,
+
+                    None => clients.get_mut(&conn_id).unwrap(),
+                }
+            };
+
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+            };
+
+            // Process potentially coalesced packets.
+            if let Err(e) = client.conn.recv(pkt_buf, recv_info) {
+                error!("{} recv failed: {:?}", client.conn.trace_id(), e);
+                continue 'read;
+            }
+
+            if client.conn.is_established() {
+                handle_data(client, &mut buf);
+
+                // Resume any report that didn't fit in the control stream.
+                for stream_id in client.conn.writable() {
+                    if stream_id == CONTROL_STREAM_ID {
+                        flush_report(client);
+                    }
+                }
+            }
+        }
+
+        // Send any reports that are due.
+        let now = Instant::now();
+
+        for client in clients.values_mut() {
+            if client.next_report.map_or(false, |t| t <= now) {
+                send_report(client, false);
+
+                client.next_report = Some(now + REPORT_INTERVAL);
+            }
+        }
+
+        // Generate outgoing QUIC packets for all active connections and send
+        // them on the UDP socket, until quiche reports that there are no more
+        // packets to be sent.
+        for client in clients.values_mut() {
+            loop {
+                let (write, send_info) = match client.conn.send(&mut out) {
+                    Ok(v) => v,
+
+                    Err(quiche::Error::Done) => break,
+
+                    Err(e) => {
+                        error!("{} send failed: {:?}", client.conn.trace_id(), e);
+
+                        client.conn.close(false, 0x1, b"fail").ok();
+                        break;
+                    },
+                };
+
+                if let Err(e) = socket.send_to(&out[..write], send_info.to) {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        debug!("send() would block");
+                        break;
+                    }
+
+                    panic!("send() failed: {:?}", e);
+                }
+            }
+        }
+
+        // Garbage collect closed connections.
+        clients.retain(|_, ref mut c| {
+            if c.conn.is_closed() {
+                info!(
+                    "{} connection collected {:?}",
+                    c.conn.trace_id(),
+                    c.conn.stats()
+                );
+            }
+
+            !c.conn.is_closed()
+        });
+    }
+}
+
+/// Reads and discards everything readable on the data stream.
+fn handle_data(client: &mut Client, buf: &mut [u8]) {
+    if client.finished {
+        return;
+    }
+
+    loop {
+        let (read, fin) = match client.conn.stream_recv(DATA_STREAM_ID, buf) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => return,
+
+            Err(e) => {
+                error!("{} stream recv failed {:?}", client.conn.trace_id(), e);
+                return;
+            },
+        };
+
+        if client.start.is_none() {
+            let now = Instant::now();
+
+            client.start = Some(now);
+            client.next_report = Some(now + REPORT_INTERVAL);
+        }
+
+        client.received += read as u64;
+
+        if fin {
+            info!(
+                "{} received {} bytes, sending final report",
+                client.conn.trace_id(),
+                client.received
+            );
+
+            send_report(client, true);
+
+            client.next_report = None;
+            client.finished = true;
+
+            return;
+        }
+    }
+}
+
+/// Writes a report line to the control stream.
+///
+/// Whatever doesn't fit in the stream right away is buffered, and sent once
+/// the stream becomes writable again.
+fn send_report(client: &mut Client, fin: bool) {
+    let elapsed = client.start.map_or(Duration::ZERO, |t| t.elapsed());
+
+    let goodput = if elapsed.is_zero() {
+        0.0
+    } else {
+        client.received as f64 * 8.0 / elapsed.as_secs_f64() / 1e6
+    };
+
+    let stats = client.conn.stats();
+
+    let rtt = client
+        .conn
+        .path_stats()
+        .find(|p| p.active)
+        .map_or(Duration::ZERO, |p| p.rtt);
+
+    let report = format!(
+        "elapsed={:.3}s received={} goodput={:.2}Mbit/s recv_pkts={} rtt={:.3}ms\n",
+        elapsed.as_secs_f64(),
+        client.received,
+        goodput,
+        stats.recv,
+        rtt.as_secs_f64() * 1000.0,
+    );
+
+    client.report_buf.extend_from_slice(report.as_bytes());
+    client.report_fin = fin;
+
+    flush_report(client);
+}
+
+/// Writes as much of the buffered report data as possible to the control
+/// stream, finishing the stream with the last of it if needed.
+fn flush_report(client: &mut Client) {
+    if client.report_buf.is_empty() {
+        return;
+    }
+
+    let written = match client.conn.stream_send(
+        CONTROL_STREAM_ID,
+        &client.report_buf,
+        client.report_fin,
+    ) {
+        Ok(v) => v,
+
+        Err(quiche::Error::Done) => 0,
+
+        Err(e) => {
+            error!("{} report send failed {:?}", client.conn.trace_id(), e);
+
+            client.report_buf.clear();
+            return;
+        },
+    };
+
+    client.report_buf.drain(..written);
+}
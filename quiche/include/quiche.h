@@ -127,6 +127,9 @@ enum quiche_error {
 
     // The peer sent more data in CRYPTO frames than we can buffer.
     QUICHE_ERR_CRYPTO_BUFFER_EXCEEDED = -20,
+
+    // The connection is closing, draining or closed.
+    QUICHE_ERR_CONNECTION_CLOSED = -21,
 };
 
 // Returns a human readable string with the quiche version number.
@@ -136,6 +139,10 @@ const char *quiche_version(void);
 int quiche_enable_debug_logging(void (*cb)(const char *line, void *argp),
                                 void *argp);
 
+// Configures whether logs include the contents of packets and frames. Only
+// available in debug builds.
+void quiche_set_log_payloads(bool v);
+
 // Stores configuration shared between multiple connections.
 typedef struct quiche_config quiche_config;
 
@@ -150,6 +157,18 @@ int quiche_config_load_cert_chain_from_pem_file(quiche_config *config,
 int quiche_config_load_priv_key_from_pem_file(quiche_config *config,
                                               const char *path);
 
+// Configures the given PEM-encoded certificate chain from memory.
+int quiche_config_load_cert_chain_from_pem(quiche_config *config,
+                                           const uint8_t *pem, size_t pem_len);
+
+// Configures the given PEM-encoded private key from memory.
+int quiche_config_load_priv_key_from_pem(quiche_config *config,
+                                         const uint8_t *pem, size_t pem_len);
+
+// Configures the given DER-encoded private key from memory.
+int quiche_config_load_priv_key_from_der(quiche_config *config,
+                                         const uint8_t *der, size_t der_len);
+
 // Specifies a file where trusted CA certificates are stored for the purposes of certificate verification.
 int quiche_config_load_verify_locations_from_file(quiche_config *config,
                                                   const char *path);
@@ -167,6 +186,15 @@ void quiche_config_grease(quiche_config *config, bool v);
 // Configures whether to do path MTU discovery.
 void quiche_config_discover_pmtu(quiche_config *config, bool v);
 
+enum quiche_spin_bit_mode {
+    QUICHE_SPIN_BIT_DISABLED = 0,
+    QUICHE_SPIN_BIT_ENABLED = 1,
+    QUICHE_SPIN_BIT_RANDOMIZED = 2,
+};
+
+// Sets whether network paths take part in the latency spin bit.
+void quiche_config_set_spin_bit_mode(quiche_config *config, enum quiche_spin_bit_mode mode);
+
 // Enables logging of secrets.
 void quiche_config_log_keys(quiche_config *config);
 
@@ -185,6 +213,9 @@ void quiche_config_set_max_amplification_factor(quiche_config *config, size_t v)
 // no timeout.
 void quiche_config_set_max_idle_timeout(quiche_config *config, uint64_t v);
 
+// Sets the maximum time allowed to complete the handshake, in milliseconds.
+void quiche_config_set_handshake_timeout(quiche_config *config, uint64_t v);
+
 // Sets the `max_udp_payload_size transport` parameter.
 void quiche_config_set_max_recv_udp_payload_size(quiche_config *config, size_t v);
 
@@ -224,6 +255,16 @@ int quiche_config_set_cc_algorithm_name(quiche_config *config, const char *algo)
 // Sets the initial cwnd for the connection in terms of packet count.
 void quiche_config_set_initial_congestion_window_packets(quiche_config *config, size_t packets);
 
+// Sets minimum congestion window size in terms of packet count.
+int quiche_config_set_minimum_congestion_window_packets(quiche_config *config, size_t packets);
+
+// Sets the initial packet reordering threshold used for loss detection.
+int quiche_config_set_packet_reordering_threshold(quiche_config *config, uint64_t v);
+
+// Sets the time reordering threshold used for loss detection, as a multiplier
+// of the round-trip time.
+int quiche_config_set_time_reordering_threshold(quiche_config *config, double v);
+
 enum quiche_cc_algorithm {
     QUICHE_CC_RENO = 0,
     QUICHE_CC_CUBIC = 1,
@@ -248,21 +289,57 @@ void quiche_config_enable_dgram(quiche_config *config, bool enabled,
                                 size_t recv_queue_len,
                                 size_t send_queue_len);
 
+// Configures whether to enable the ACK frequency extension.
+void quiche_config_enable_ack_frequency(quiche_config *config, bool v);
+
+// Configures whether to advertise support for greasing the QUIC bit.
+void quiche_config_enable_grease_quic_bit(quiche_config *config, bool v);
+
+// Adds a transport parameter that is not natively supported to those sent to
+// the peer.
+int quiche_config_set_custom_transport_param(quiche_config *config, uint64_t id,
+                                             const uint8_t *value, size_t value_len);
+
 // Sets the maximum connection window.
 void quiche_config_set_max_connection_window(quiche_config *config, uint64_t v);
 
 // Sets the maximum stream window.
 void quiche_config_set_max_stream_window(quiche_config *config, uint64_t v);
 
+// Sets the fraction of the stream window consumed before MAX_STREAM_DATA is sent.
+int quiche_config_set_stream_window_update_threshold(quiche_config *config, double v);
+
+// Sets the maximum amount of CRYPTO data accepted in each packet number space.
+int quiche_config_set_max_crypto_buffer_size(quiche_config *config, uint64_t v);
+
 // Sets the limit of active connection IDs.
 void quiche_config_set_active_connection_id_limit(quiche_config *config, uint64_t v);
 
 // Sets the initial stateless reset token. |v| must contain 16 bytes, otherwise the behaviour is undefined.
 void quiche_config_set_stateless_reset_token(quiche_config *config, const uint8_t *v);
 
+// Sets the key stateless reset tokens are derived from. Returns -1 unless |key_len| is 32.
+int quiche_config_set_stateless_reset_key(quiche_config *config, const uint8_t *key, size_t key_len);
+
 // Sets whether the QUIC connection should avoid reusing DCIDs over different paths.
 void quiche_config_set_disable_dcid_reuse(quiche_config *config, bool v);
 
+// Sets a label to prepend to the trace ID of new connections.
+void quiche_config_set_trace_label(quiche_config *config, const char *label);
+
+// Configures whether to use multiple validated paths concurrently.
+void quiche_config_set_multipath(quiche_config *config, bool v);
+
+// Configures whether data written to streams can be sent without flushing.
+void quiche_config_set_autoflush(quiche_config *config, bool v);
+
+// Sets the maximum amount of stream data queued before the connection is ready.
+void quiche_config_set_max_pre_handshake_data(quiche_config *config, size_t v);
+
+// Sets the maximum time to wait for more data before sending a mostly-empty
+// packet.
+void quiche_config_set_send_coalescing_delay(quiche_config *config, uint64_t v);
+
 // Configures the session ticket key material.
 int quiche_config_set_ticket_key(quiche_config *config, const uint8_t *key, size_t key_len);
 
@@ -294,6 +371,17 @@ quiche_conn *quiche_connect(const char *server_name,
                             const struct sockaddr *peer, socklen_t peer_len,
                             quiche_config *config);
 
+// Creates a new client-side connection, using the given destination
+// connection ID for the first Initial packet.
+quiche_conn *quiche_connect_with_dcid(const char *server_name,
+                                      const uint8_t *scid, size_t scid_len,
+                                      const uint8_t *dcid, size_t dcid_len,
+                                      const struct sockaddr *local,
+                                      socklen_t local_len,
+                                      const struct sockaddr *peer,
+                                      socklen_t peer_len,
+                                      quiche_config *config);
+
 // Writes a version negotiation packet.
 ssize_t quiche_negotiate_version(const uint8_t *scid, size_t scid_len,
                                  const uint8_t *dcid, size_t dcid_len,
@@ -309,6 +397,9 @@ ssize_t quiche_retry(const uint8_t *scid, size_t scid_len,
 // Returns true if the given protocol version is supported.
 bool quiche_version_is_supported(uint32_t version);
 
+// Creates a new connection using the given SSL object. Prefer
+// quiche_accept_with_tls() and quiche_connect_with_tls(), which don't require
+// passing in the side of the connection.
 quiche_conn *quiche_conn_new_with_tls(const uint8_t *scid, size_t scid_len,
                                       const uint8_t *odcid, size_t odcid_len,
                                       const struct sockaddr *local, socklen_t local_len,
@@ -316,6 +407,21 @@ quiche_conn *quiche_conn_new_with_tls(const uint8_t *scid, size_t scid_len,
                                       const quiche_config *config, void *ssl,
                                       bool is_server);
 
+// Creates a new server-side connection using the given SSL object.
+quiche_conn *quiche_accept_with_tls(const uint8_t *scid, size_t scid_len,
+                                    const uint8_t *odcid, size_t odcid_len,
+                                    const struct sockaddr *local, socklen_t local_len,
+                                    const struct sockaddr *peer, socklen_t peer_len,
+                                    const quiche_config *config, void *ssl);
+
+// Creates a new client-side connection using the given SSL object. If
+// server_name is not NULL, it is set as the SNI of the SSL object.
+quiche_conn *quiche_connect_with_tls(const char *server_name,
+                                     const uint8_t *scid, size_t scid_len,
+                                     const struct sockaddr *local, socklen_t local_len,
+                                     const struct sockaddr *peer, socklen_t peer_len,
+                                     const quiche_config *config, void *ssl);
+
 // Enables keylog to the specified file path. Returns true on success.
 bool quiche_conn_set_keylog_path(quiche_conn *conn, const char *path);
 
@@ -367,6 +473,9 @@ ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len,
 // Returns the size of the send quantum, in bytes.
 size_t quiche_conn_send_quantum(const quiche_conn *conn);
 
+// Returns the amount of new stream data that can currently be sent.
+size_t quiche_conn_send_capacity(const quiche_conn *conn);
+
 // Writes a single QUIC packet to be sent to the peer from the specified
 // local address "from" to the destination address "to".
 ssize_t quiche_conn_send_on_path(quiche_conn *conn, uint8_t *out, size_t out_len,
@@ -387,6 +496,16 @@ ssize_t quiche_conn_stream_recv(quiche_conn *conn, uint64_t stream_id,
                                 uint8_t *out, size_t buf_len, bool *fin,
                                 uint64_t *out_error_code);
 
+// Opens a new locally-initiated bidirectional stream and returns its ID.
+//
+// On error a value lower than 0 is returned.
+int64_t quiche_conn_stream_open_bidi(quiche_conn *conn);
+
+// Opens a new locally-initiated unidirectional stream and returns its ID.
+//
+// On error a value lower than 0 is returned.
+int64_t quiche_conn_stream_open_uni(quiche_conn *conn);
+
 // Writes data to a stream.
 // out_error_code is only set when STREAM_STOPPED or STREAM_RESET are returned.
 // Set to the reported error code associated with STOP_SENDING or STREAM_RESET. 
@@ -408,9 +527,42 @@ int quiche_conn_stream_priority(quiche_conn *conn, uint64_t stream_id,
 int quiche_conn_stream_shutdown(quiche_conn *conn, uint64_t stream_id,
                                 enum quiche_shutdown direction, uint64_t err);
 
+// Allows data held back on a stream to be sent.
+void quiche_conn_stream_flush(quiche_conn *conn, uint64_t stream_id);
+
+// Allows data held back on all streams to be sent.
+void quiche_conn_flush(quiche_conn *conn);
+
+// Configures whether packets are sent without waiting for more data.
+void quiche_conn_set_send_immediate(quiche_conn *conn, bool v);
+
+// Sets the max value for pacing rate of the connection, in bytes per second.
+void quiche_conn_set_max_pacing_rate(quiche_conn *conn, uint64_t v);
+
+// Adds a stream to a stream group.
+int quiche_conn_stream_group(quiche_conn *conn, uint64_t stream_id,
+                             uint64_t group_id);
+
+// Limits the amount of data that can be written on a stream group.
+int quiche_conn_group_set_max_data(quiche_conn *conn, uint64_t group_id,
+                                   uint64_t max);
+
+// Shuts down all streams in a stream group and removes the group.
+int quiche_conn_group_close(quiche_conn *conn, uint64_t group_id, uint64_t err);
+
 // Returns the stream's send capacity in bytes.
 ssize_t quiche_conn_stream_capacity(const quiche_conn *conn, uint64_t stream_id);
 
+// Drops data written to the stream at or after "from_offset" that hasn't been
+// sent yet, and returns the number of bytes dropped.
+ssize_t quiche_conn_stream_cancel_pending(quiche_conn *conn, uint64_t stream_id,
+                                          uint64_t from_offset);
+
+// Writes the offset up to which the stream's data has been acknowledged by
+// the peer to "out" and returns 0.
+int quiche_conn_stream_acked_offset(const quiche_conn *conn, uint64_t stream_id,
+                                    uint64_t *out);
+
 // Returns true if the stream has data that can be read.
 bool quiche_conn_stream_readable(const quiche_conn *conn, uint64_t stream_id);
 
@@ -441,6 +593,9 @@ quiche_stream_iter *quiche_conn_writable(const quiche_conn *conn);
 // Returns the maximum possible size of egress UDP payloads.
 size_t quiche_conn_max_send_udp_payload_size(const quiche_conn *conn);
 
+// Returns the maximum size of UDP payloads the peer is allowed to send.
+size_t quiche_conn_max_recv_udp_payload_size(const quiche_conn *conn);
+
 // Returns the amount of time until the next timeout event, in nanoseconds.
 uint64_t quiche_conn_timeout_as_nanos(const quiche_conn *conn);
 
@@ -488,6 +643,9 @@ void quiche_conn_session(const quiche_conn *conn, const uint8_t **out, size_t *o
 // Returns true if the connection handshake is complete.
 bool quiche_conn_is_established(const quiche_conn *conn);
 
+// Returns true if the connection handshake is confirmed.
+bool quiche_conn_is_handshake_confirmed(const quiche_conn *conn);
+
 // Returns true if the connection is resumed.
 bool quiche_conn_is_resumed(const quiche_conn *conn);
 
@@ -495,6 +653,12 @@ bool quiche_conn_is_resumed(const quiche_conn *conn);
 // enough to send or receive early data.
 bool quiche_conn_is_in_early_data(const quiche_conn *conn);
 
+// Returns true if the connection is ready for application data.
+bool quiche_conn_is_app_data_ready(const quiche_conn *conn);
+
+// Returns the amount of queued stream data not yet written to the streams.
+size_t quiche_conn_pre_handshake_data_len(const quiche_conn *conn);
+
 // Returns whether there is stream or DATAGRAM data available to read.
 bool quiche_conn_is_readable(const quiche_conn *conn);
 
@@ -630,6 +794,12 @@ typedef struct {
 // not yet processed the peer's transport parameters.
 bool quiche_conn_peer_transport_params(const quiche_conn *conn, quiche_transport_params *out);
 
+// Returns the value of the peer's transport parameter with the given ID, if it
+// is not natively supported. Returns false if the peer didn't send it, or if
+// we have not yet processed the peer's transport parameters.
+bool quiche_conn_peer_custom_transport_param(const quiche_conn *conn, uint64_t id,
+                                             const uint8_t **out, size_t *out_len);
+
 typedef struct {
     // The local address used by this path.
     struct sockaddr_storage local_addr;
@@ -731,11 +901,17 @@ bool quiche_conn_is_dgram_recv_queue_full(const quiche_conn *conn);
 // Schedule an ack-eliciting packet on the active path.
 ssize_t quiche_conn_send_ack_eliciting(quiche_conn *conn);
 
+// Schedules an ack-eliciting packet used to sample the path's RTT.
+ssize_t quiche_conn_probe_rtt(quiche_conn *conn);
+
 // Schedule an ack-eliciting packet on the specified path.
 ssize_t quiche_conn_send_ack_eliciting_on_path(quiche_conn *conn,
                            const struct sockaddr *local, socklen_t local_len,
                            const struct sockaddr *peer, socklen_t peer_len);
 
+// Initiates an update of the 1-RTT packet protection keys.
+int quiche_conn_initiate_key_update(quiche_conn *conn);
+
 // Returns true if there are retired source connection ids and fill the parameters
 bool quiche_conn_retired_scid_next(const quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
@@ -990,6 +1166,9 @@ enum quiche_h3_error {
 
     // See QUICHE_ERR_KEY_UPDATE.
     QUICHE_H3_TRANSPORT_ERR_KEY_UPDATE = QUICHE_ERR_KEY_UPDATE - 1000,
+
+    // See QUICHE_ERR_CONNECTION_CLOSED.
+    QUICHE_H3_TRANSPORT_ERR_CONNECTION_CLOSED = QUICHE_ERR_CONNECTION_CLOSED - 1000,
 };
 
 // Stores configuration shared between multiple connections.
@@ -1010,6 +1189,10 @@ void quiche_h3_config_set_qpack_blocked_streams(quiche_h3_config *config, uint64
 // Sets the `SETTINGS_ENABLE_CONNECT_PROTOCOL` setting.
 void quiche_h3_config_enable_extended_connect(quiche_h3_config *config, bool enabled);
 
+// Configures whether priorities signaled by the peer are applied automatically.
+void quiche_h3_config_enable_extensible_priorities(quiche_h3_config *config,
+                                                   bool enabled);
+
 // Frees the HTTP/3 config object.
 void quiche_h3_config_free(quiche_h3_config *config);
 
@@ -1027,6 +1210,8 @@ enum quiche_h3_event_type {
     QUICHE_H3_EVENT_GOAWAY,
     QUICHE_H3_EVENT_RESET,
     QUICHE_H3_EVENT_PRIORITY_UPDATE,
+    QUICHE_H3_EVENT_PUSH_PROMISE,
+    QUICHE_H3_EVENT_PUSH_CANCELLED,
 };
 
 typedef struct quiche_h3_event quiche_h3_event;
@@ -1063,6 +1248,9 @@ int quiche_h3_for_each_setting(quiche_h3_conn *conn,
 // Check whether data will follow the headers on the stream.
 bool quiche_h3_event_headers_has_body(quiche_h3_event *ev);
 
+// Returns the push ID of a PUSH_PROMISE or push cancelled event.
+uint64_t quiche_h3_event_push_promise_push_id(quiche_h3_event *ev);
+
 // Check whether or not extended connection is enabled by the peer
 bool quiche_h3_extended_connect_enabled_by_peer(quiche_h3_conn *conn);
 
@@ -1108,6 +1296,30 @@ ssize_t quiche_h3_send_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
 ssize_t quiche_h3_recv_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, uint8_t *out, size_t out_len);
 
+// Sends a PUSH_PROMISE frame on the specified request stream.
+int64_t quiche_h3_send_push_promise(quiche_h3_conn *conn,
+                                    quiche_conn *quic_conn, uint64_t stream_id,
+                                    const quiche_h3_header *headers,
+                                    size_t headers_len);
+
+// Sends the response of a promised server push on a new push stream.
+int64_t quiche_h3_send_push_response(quiche_h3_conn *conn,
+                                     quiche_conn *quic_conn, uint64_t push_id,
+                                     const quiche_h3_header *headers,
+                                     size_t headers_len, bool fin);
+
+// Returns the push ID associated with the given push stream, if any.
+bool quiche_h3_push_id(quiche_h3_conn *conn, uint64_t stream_id,
+                       uint64_t *out);
+
+// Sends a MAX_PUSH_ID frame to allow the server to push responses.
+int quiche_h3_send_max_push_id(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                               uint64_t push_id);
+
+// Cancels a server push.
+int quiche_h3_cancel_push(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                          uint64_t push_id);
+
 // Sends a GOAWAY frame to initiate graceful connection closure.
 int quiche_h3_send_goaway(quiche_h3_conn *conn, quiche_conn *quic_conn,
                           uint64_t id);
@@ -0,0 +1,285 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::frame;
+
+/// The `min_ack_delay` advertised when the extension is enabled, in
+/// microseconds.
+pub const MIN_ACK_DELAY: u64 = 1000;
+
+/// The reordering threshold requested from the peer, which makes it
+/// acknowledge out-of-order packets immediately.
+const REORDERING_THRESHOLD: u64 = 1;
+
+/// ACK frequency parameters requested by the peer.
+struct Requested {
+    seq_num: u64,
+
+    ack_eliciting_threshold: u64,
+
+    max_ack_delay: Duration,
+
+    reordering_threshold: u64,
+}
+
+/// State of the ACK frequency extension.
+///
+/// The receive side tracks when ACKs need to be sent according to the
+/// parameters requested by the peer, while the send side tracks the
+/// parameters requested from the peer.
+#[derive(Default)]
+pub struct AckFrequency {
+    /// The parameters most recently requested by the peer, if any.
+    requested: Option<Requested>,
+
+    /// The number of ack-eliciting packets received since the last ACK.
+    unacked: u64,
+
+    /// The time by which an ACK needs to be sent.
+    ack_timer: Option<Instant>,
+
+    /// The sequence number of the next ACK_FREQUENCY frame to send.
+    next_seq_num: u64,
+
+    /// The ack-eliciting threshold most recently requested from the peer.
+    threshold_sent: Option<u64>,
+}
+
+impl AckFrequency {
+    /// Processes an ACK_FREQUENCY frame received from the peer.
+    ///
+    /// Frames that are older than the most recently processed one are
+    /// ignored.
+    pub fn on_frame_received(
+        &mut self, seq_num: u64, ack_eliciting_threshold: u64,
+        request_max_ack_delay: u64, reordering_threshold: u64,
+    ) {
+        if let Some(r) = &self.requested {
+            if seq_num <= r.seq_num {
+                return;
+            }
+        }
+
+        self.requested = Some(Requested {
+            seq_num,
+            ack_eliciting_threshold,
+            max_ack_delay: Duration::from_micros(request_max_ack_delay),
+            reordering_threshold,
+        });
+    }
+
+    /// Records the receipt of an ack-eliciting packet.
+    ///
+    /// Returns true if an ACK needs to be sent right away, which is always
+    /// the case unless the peer requested otherwise. The `largest_pn`
+    /// argument is the largest packet number received before `pn`.
+    pub fn on_ack_eliciting_packet(
+        &mut self, pn: u64, largest_pn: u64, now: Instant,
+    ) -> bool {
+        let r = match &self.requested {
+            Some(v) => v,
+
+            None => return true,
+        };
+
+        self.unacked += 1;
+
+        let reordered = r.reordering_threshold > 0 &&
+            (pn < largest_pn || pn > largest_pn + r.reordering_threshold);
+
+        if self.unacked > r.ack_eliciting_threshold || reordered {
+            return true;
+        }
+
+        if self.ack_timer.is_none() {
+            self.ack_timer = Some(now + r.max_ack_delay);
+        }
+
+        false
+    }
+
+    /// Resets the ACK state after an ACK was sent.
+    pub fn on_ack_sent(&mut self) {
+        self.unacked = 0;
+        self.ack_timer = None;
+    }
+
+    /// Returns the time by which an ACK needs to be sent, if any.
+    pub fn ack_timer(&self) -> Option<Instant> {
+        self.ack_timer
+    }
+
+    /// Returns true if the ACK timer expired, meaning that an ACK needs to be
+    /// sent.
+    pub fn on_timeout(&mut self, now: Instant) -> bool {
+        match self.ack_timer {
+            Some(timer) if timer <= now => {
+                self.ack_timer = None;
+
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Returns true if the peer needs to be sent new ACK frequency
+    /// parameters.
+    pub fn should_update(&self, ack_eliciting_threshold: u64) -> bool {
+        self.threshold_sent != Some(ack_eliciting_threshold)
+    }
+
+    /// Returns the ACK_FREQUENCY frame carrying the given parameters.
+    pub fn frame(
+        &self, ack_eliciting_threshold: u64, max_ack_delay: Duration,
+    ) -> frame::Frame {
+        frame::Frame::AckFrequency {
+            seq_num: self.next_seq_num,
+            ack_eliciting_threshold,
+            request_max_ack_delay: max_ack_delay.as_micros() as u64,
+            reordering_threshold: REORDERING_THRESHOLD,
+        }
+    }
+
+    /// Records that an ACK_FREQUENCY frame was sent.
+    pub fn on_frame_sent(&mut self, ack_eliciting_threshold: u64) {
+        self.next_seq_num += 1;
+        self.threshold_sent = Some(ack_eliciting_threshold);
+    }
+
+    /// Schedules the retransmission of a lost ACK_FREQUENCY frame, unless
+    /// newer parameters have been sent since.
+    pub fn on_frame_lost(&mut self, seq_num: u64) {
+        if seq_num + 1 == self.next_seq_num {
+            self.threshold_sent = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ack_immediately_by_default() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        assert!(af.on_ack_eliciting_packet(0, 0, now));
+        assert!(af.on_ack_eliciting_packet(1, 0, now));
+        assert_eq!(af.ack_timer(), None);
+    }
+
+    #[test]
+    fn ack_eliciting_threshold() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_frame_received(0, 2, 25_000, 1);
+
+        assert!(!af.on_ack_eliciting_packet(1, 0, now));
+        assert_eq!(af.ack_timer(), Some(now + Duration::from_millis(25)));
+
+        assert!(!af.on_ack_eliciting_packet(2, 1, now));
+        assert!(af.on_ack_eliciting_packet(3, 2, now));
+
+        af.on_ack_sent();
+        assert_eq!(af.ack_timer(), None);
+
+        // Out-of-order packets are acknowledged immediately.
+        assert!(!af.on_ack_eliciting_packet(4, 3, now));
+        assert!(af.on_ack_eliciting_packet(6, 4, now));
+
+        af.on_ack_sent();
+        assert!(af.on_ack_eliciting_packet(5, 6, now));
+    }
+
+    #[test]
+    fn ack_timer() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_frame_received(0, 10, 25_000, 0);
+
+        assert!(!af.on_ack_eliciting_packet(1, 0, now));
+
+        assert!(!af.on_timeout(now + Duration::from_millis(24)));
+        assert!(af.on_timeout(now + Duration::from_millis(25)));
+        assert_eq!(af.ack_timer(), None);
+    }
+
+    #[test]
+    fn stale_frame() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_frame_received(1, 10, 25_000, 0);
+
+        // Older parameters are ignored.
+        af.on_frame_received(0, 0, 25_000, 0);
+        assert!(!af.on_ack_eliciting_packet(1, 0, now));
+
+        af.on_frame_received(2, 0, 25_000, 0);
+        assert!(af.on_ack_eliciting_packet(2, 1, now));
+    }
+
+    #[test]
+    fn send_frame() {
+        let mut af = AckFrequency::default();
+
+        assert!(af.should_update(4));
+
+        let frame = af.frame(4, Duration::from_millis(25));
+        assert_eq!(frame, frame::Frame::AckFrequency {
+            seq_num: 0,
+            ack_eliciting_threshold: 4,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: REORDERING_THRESHOLD,
+        });
+
+        af.on_frame_sent(4);
+        assert!(!af.should_update(4));
+        assert!(af.should_update(8));
+
+        af.on_frame_sent(8);
+
+        // Only the most recent frame is retransmitted.
+        af.on_frame_lost(0);
+        assert!(!af.should_update(8));
+
+        af.on_frame_lost(1);
+        assert!(af.should_update(8));
+
+        let frame = af.frame(8, Duration::from_millis(25));
+        assert!(matches!(frame, frame::Frame::AckFrequency {
+            seq_num: 2,
+            ..
+        }));
+    }
+}
@@ -0,0 +1,743 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Compact binary tracing of connections.
+//!
+//! Serializing qlog events to JSON is too expensive to be done for every
+//! packet of busy connections. Binary traces instead record each event as a
+//! fixed-size record, which can be converted to qlog offline with
+//! [`to_qlog()`].
+//!
+//! A trace starts with a 16-byte header, followed by 32-byte records. All
+//! integers are encoded in network byte order:
+//!
+//! ```text
+//! header: magic "QBTR" (4) | version (1) | vantage point (1) |
+//!         reserved (2) | reference time (8)
+//! record: time (8) | kind (1) | packet type (1) | reserved (2) |
+//!         a (4) | b (8) | c (8)
+//! ```
+//!
+//! The vantage point is 0 for clients and 1 for servers, and the reference
+//! time is the number of milliseconds since the UNIX epoch at which the trace
+//! started. The time of a record is the number of microseconds elapsed since
+//! then, and the meaning of its other fields depends on its kind:
+//!
+//! * `0x01`, a packet was sent: `a` is the size of the packet and `b` its
+//!   packet number.
+//!
+//! * `0x02`, a packet was received: `a` is the size of the packet and `b` its
+//!   packet number.
+//!
+//! * `0x03`, the recovery metrics changed: `a` is the smoothed RTT in
+//!   microseconds, `b` the congestion window and `c` the number of bytes in
+//!   flight.
+//!
+//! Packet types are encoded as 0 (Initial), 1 (Retry), 2 (Handshake), 3
+//! (0-RTT), 4 (Version Negotiation) and 5 (1-RTT). Unused fields are zero.
+//!
+//! [`to_qlog()`]: fn.to_qlog.html
+
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use crate::Error;
+use crate::Result;
+
+use crate::packet;
+
+const MAGIC: &[u8; 4] = b"QBTR";
+
+const VERSION: u8 = 1;
+
+/// The length of the header of a trace.
+pub const HEADER_LEN: usize = 16;
+
+/// The length of each record of a trace.
+pub const RECORD_LEN: usize = 32;
+
+const PACKET_SENT: u8 = 0x01;
+
+const PACKET_RECEIVED: u8 = 0x02;
+
+const METRICS_UPDATED: u8 = 0x03;
+
+/// The header of a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// Whether the trace was recorded by a server.
+    pub is_server: bool,
+
+    /// The number of milliseconds since the UNIX epoch at which the trace
+    /// started.
+    pub reference_time: u64,
+}
+
+impl Header {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut out = [0; HEADER_LEN];
+
+        out[..4].copy_from_slice(MAGIC);
+        out[4] = VERSION;
+        out[5] = self.is_server as u8;
+        out[8..].copy_from_slice(&self.reference_time.to_be_bytes());
+
+        out
+    }
+
+    fn from_bytes(buf: &[u8]) -> Result<Header> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        if b.get_bytes(4)?.buf() != MAGIC || b.get_u8()? != VERSION {
+            return Err(Error::InvalidState);
+        }
+
+        let is_server = match b.get_u8()? {
+            0 => false,
+
+            1 => true,
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        b.skip(2)?;
+
+        Ok(Header {
+            is_server,
+            reference_time: b.get_u64()?,
+        })
+    }
+}
+
+/// An event recorded in a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Record {
+    /// A packet was sent.
+    PacketSent {
+        /// The type of the packet.
+        ty: packet::Type,
+
+        /// The packet number.
+        pkt_num: u64,
+
+        /// The size of the packet, in bytes.
+        size: usize,
+    },
+
+    /// A packet was received and processed.
+    PacketReceived {
+        /// The type of the packet.
+        ty: packet::Type,
+
+        /// The packet number.
+        pkt_num: u64,
+
+        /// The size of the packet, in bytes.
+        size: usize,
+    },
+
+    /// The recovery state of the path changed.
+    MetricsUpdated {
+        /// The smoothed round-trip time of the path.
+        rtt: Duration,
+
+        /// The congestion window of the path, in bytes.
+        cwnd: usize,
+
+        /// The number of bytes in flight on the path.
+        bytes_in_flight: usize,
+    },
+}
+
+impl Record {
+    fn to_bytes(self, time: Duration) -> [u8; RECORD_LEN] {
+        let (kind, ty, a, b, c) = match self {
+            Record::PacketSent { ty, pkt_num, size } =>
+                (PACKET_SENT, type_to_u8(ty), size as u128, pkt_num, 0),
+
+            Record::PacketReceived { ty, pkt_num, size } =>
+                (PACKET_RECEIVED, type_to_u8(ty), size as u128, pkt_num, 0),
+
+            Record::MetricsUpdated {
+                rtt,
+                cwnd,
+                bytes_in_flight,
+            } => (
+                METRICS_UPDATED,
+                0,
+                rtt.as_micros(),
+                cwnd as u64,
+                bytes_in_flight as u64,
+            ),
+        };
+
+        // Saturate values that don't fit in the record.
+        let a = a.min(u32::MAX.into()) as u32;
+
+        let mut out = [0; RECORD_LEN];
+
+        out[..8].copy_from_slice(&(time.as_micros() as u64).to_be_bytes());
+        out[8] = kind;
+        out[9] = ty;
+        out[12..16].copy_from_slice(&a.to_be_bytes());
+        out[16..24].copy_from_slice(&b.to_be_bytes());
+        out[24..].copy_from_slice(&c.to_be_bytes());
+
+        out
+    }
+
+    fn from_bytes(buf: &[u8]) -> Result<(Duration, Record)> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        let time = Duration::from_micros(b.get_u64()?);
+        let kind = b.get_u8()?;
+        let ty = b.get_u8()?;
+
+        b.skip(2)?;
+
+        let a = b.get_u32()?;
+        let x = b.get_u64()?;
+        let y = b.get_u64()?;
+
+        let record = match kind {
+            PACKET_SENT => Record::PacketSent {
+                ty: type_from_u8(ty)?,
+                pkt_num: x,
+                size: a as usize,
+            },
+
+            PACKET_RECEIVED => Record::PacketReceived {
+                ty: type_from_u8(ty)?,
+                pkt_num: x,
+                size: a as usize,
+            },
+
+            METRICS_UPDATED => Record::MetricsUpdated {
+                rtt: Duration::from_micros(a.into()),
+                cwnd: x as usize,
+                bytes_in_flight: y as usize,
+            },
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        Ok((time, record))
+    }
+}
+
+fn type_to_u8(ty: packet::Type) -> u8 {
+    match ty {
+        packet::Type::Initial => 0,
+
+        packet::Type::Retry => 1,
+
+        packet::Type::Handshake => 2,
+
+        packet::Type::ZeroRTT => 3,
+
+        packet::Type::VersionNegotiation => 4,
+
+        packet::Type::Short => 5,
+    }
+}
+
+fn type_from_u8(ty: u8) -> Result<packet::Type> {
+    match ty {
+        0 => Ok(packet::Type::Initial),
+
+        1 => Ok(packet::Type::Retry),
+
+        2 => Ok(packet::Type::Handshake),
+
+        3 => Ok(packet::Type::ZeroRTT),
+
+        4 => Ok(packet::Type::VersionNegotiation),
+
+        5 => Ok(packet::Type::Short),
+
+        _ => Err(Error::InvalidState),
+    }
+}
+
+/// Reads the records of a trace.
+///
+/// The reader is an iterator over the records of the trace, along with the
+/// time elapsed between the start of the trace and each of them.
+pub struct Reader<'a> {
+    header: Header,
+
+    records: std::slice::ChunksExact<'a, u8>,
+}
+
+impl<'a> Reader<'a> {
+    /// Creates a reader for the given trace.
+    ///
+    /// [`InvalidState`] is returned if the trace doesn't start with a valid
+    /// header, and [`BufferTooShort`] if it ends with a truncated record.
+    ///
+    /// [`InvalidState`]: ../enum.Error.html#variant.InvalidState
+    /// [`BufferTooShort`]: ../enum.Error.html#variant.BufferTooShort
+    pub fn new(buf: &'a [u8]) -> Result<Reader<'a>> {
+        let header = Header::from_bytes(buf)?;
+
+        let records = &buf[HEADER_LEN..];
+
+        if records.len() % RECORD_LEN != 0 {
+            return Err(Error::BufferTooShort);
+        }
+
+        Ok(Reader {
+            header,
+            records: records.chunks_exact(RECORD_LEN),
+        })
+    }
+
+    /// Returns the header of the trace.
+    pub fn header(&self) -> Header {
+        self.header
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<(Duration, Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.records.next().map(Record::from_bytes)
+    }
+}
+
+/// Writes the records of a connection's trace.
+pub(crate) struct Writer {
+    out: Box<dyn std::io::Write + Send + Sync>,
+
+    start: Instant,
+
+    /// The last recovery metrics written, to avoid repeating them.
+    metrics: Option<(Duration, usize, usize)>,
+}
+
+impl Writer {
+    /// Creates a writer, and writes the header of the trace to `out`.
+    pub(crate) fn new(
+        mut out: Box<dyn std::io::Write + Send + Sync>, is_server: bool,
+        now: Instant,
+    ) -> Writer {
+        let reference_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        let header = Header {
+            is_server,
+            reference_time,
+        };
+
+        out.write_all(&header.to_bytes()).ok();
+
+        Writer {
+            out,
+            start: now,
+            metrics: None,
+        }
+    }
+
+    /// Writes a record for an event that happened at the given time.
+    pub(crate) fn record(&mut self, now: Instant, record: Record) {
+        let time = now.saturating_duration_since(self.start);
+
+        self.out.write_all(&record.to_bytes(time)).ok();
+    }
+
+    /// Writes a [`Record::MetricsUpdated`] record, if any of the metrics
+    /// changed since the last one.
+    pub(crate) fn metrics(
+        &mut self, now: Instant, rtt: Duration, cwnd: usize,
+        bytes_in_flight: usize,
+    ) {
+        let metrics = (rtt, cwnd, bytes_in_flight);
+
+        if self.metrics == Some(metrics) {
+            return;
+        }
+
+        self.metrics = Some(metrics);
+
+        self.record(now, Record::MetricsUpdated {
+            rtt,
+            cwnd,
+            bytes_in_flight,
+        });
+    }
+}
+
+/// Converts a binary trace to qlog, serialized as JSON-SEQ.
+///
+/// Records that can't be parsed cause an error to be returned, after the
+/// events that precede them have been written.
+#[cfg(feature = "qlog")]
+#[cfg_attr(docsrs, doc(cfg(feature = "qlog")))]
+pub fn to_qlog(
+    buf: &[u8], writer: Box<dyn std::io::Write + Send + Sync>,
+) -> Result<()> {
+    use qlog::events::quic;
+    use qlog::events::EventData;
+    use qlog::events::RawInfo;
+
+    let reader = Reader::new(buf)?;
+
+    let ty = if reader.header().is_server {
+        qlog::VantagePointType::Server
+    } else {
+        qlog::VantagePointType::Client
+    };
+
+    let trace = qlog::TraceSeq::new(
+        qlog::VantagePoint {
+            name: None,
+            ty,
+            flow: None,
+        },
+        None,
+        None,
+        Some(qlog::Configuration {
+            time_offset: Some(0.0),
+            original_uris: None,
+        }),
+        Some(qlog::CommonFields {
+            reference_time: Some(reader.header().reference_time as f64),
+            ..Default::default()
+        }),
+    );
+
+    // Event times are relative to the start of the streamer, so any instant
+    // can be used as the start of the trace.
+    let start = Instant::now();
+
+    let mut streamer = qlog::streamer::QlogStreamer::new(
+        qlog::QLOG_VERSION.to_string(),
+        None,
+        None,
+        None,
+        start,
+        trace,
+        qlog::events::EventImportance::Extra,
+        writer,
+    );
+
+    streamer.start_log().map_err(|_| Error::InvalidState)?;
+
+    for record in reader {
+        let (time, record) = record?;
+
+        let raw = |size| {
+            Some(RawInfo {
+                length: Some(size as u64),
+                payload_length: None,
+                data: None,
+            })
+        };
+
+        let ev_data = match record {
+            Record::PacketSent { ty, pkt_num, size } =>
+                EventData::PacketSent(quic::PacketSent {
+                    header: quic::PacketHeader::with_type(
+                        ty.to_qlog(),
+                        Some(pkt_num),
+                        None,
+                        None,
+                        None,
+                    ),
+                    is_coalesced: None,
+                    retry_token: None,
+                    stateless_reset_token: None,
+                    supported_versions: None,
+                    raw: raw(size),
+                    datagram_id: None,
+                    trigger: None,
+                    send_at_time: None,
+                    frames: None,
+                }),
+
+            Record::PacketReceived { ty, pkt_num, size } =>
+                EventData::PacketReceived(quic::PacketReceived {
+                    header: quic::PacketHeader::with_type(
+                        ty.to_qlog(),
+                        Some(pkt_num),
+                        None,
+                        None,
+                        None,
+                    ),
+                    is_coalesced: None,
+                    retry_token: None,
+                    stateless_reset_token: None,
+                    supported_versions: None,
+                    raw: raw(size),
+                    datagram_id: None,
+                    trigger: None,
+                    frames: None,
+                }),
+
+            Record::MetricsUpdated {
+                rtt,
+                cwnd,
+                bytes_in_flight,
+            } => EventData::MetricsUpdated(quic::MetricsUpdated {
+                min_rtt: None,
+                smoothed_rtt: Some(rtt.as_secs_f32() * 1000.0),
+                latest_rtt: None,
+                rtt_variance: None,
+                pto_count: None,
+                congestion_window: Some(cwnd as u64),
+                bytes_in_flight: Some(bytes_in_flight as u64),
+                ssthresh: None,
+                packets_in_flight: None,
+                pacing_rate: None,
+            }),
+        };
+
+        streamer
+            .add_event_data_with_instant(ev_data, start + time)
+            .map_err(|_| Error::InvalidState)?;
+    }
+
+    streamer.finish_log().map_err(|_| Error::InvalidState)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Trace(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Trace {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Trace {
+        fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let trace = Trace::default();
+
+        let start = Instant::now();
+
+        let mut w = Writer::new(Box::new(trace.clone()), true, start);
+
+        let sent = Record::PacketSent {
+            ty: packet::Type::Handshake,
+            pkt_num: 3,
+            size: 1200,
+        };
+
+        let received = Record::PacketReceived {
+            ty: packet::Type::Short,
+            pkt_num: 0x1234_5678_9abc,
+            size: 45,
+        };
+
+        let metrics = Record::MetricsUpdated {
+            rtt: Duration::from_micros(12_345),
+            cwnd: 13_500,
+            bytes_in_flight: 1200,
+        };
+
+        w.record(start + Duration::from_millis(1), sent);
+        w.metrics(
+            start + Duration::from_millis(1),
+            Duration::from_micros(12_345),
+            13_500,
+            1200,
+        );
+
+        // Metrics are only written when they change.
+        w.metrics(
+            start + Duration::from_millis(2),
+            Duration::from_micros(12_345),
+            13_500,
+            1200,
+        );
+
+        w.record(start + Duration::from_millis(3), received);
+
+        let buf = trace.bytes();
+        assert_eq!(buf.len(), HEADER_LEN + 3 * RECORD_LEN);
+
+        let reader = Reader::new(&buf).unwrap();
+        assert!(reader.header().is_server);
+
+        assert_eq!(
+            reader.collect::<Result<Vec<_>>>(),
+            Ok(vec![
+                (Duration::from_millis(1), sent),
+                (Duration::from_millis(1), metrics),
+                (Duration::from_millis(3), received),
+            ])
+        );
+    }
+
+    #[test]
+    fn read_invalid() {
+        let header = Header {
+            is_server: false,
+            reference_time: 1_700_000_000_000,
+        }
+        .to_bytes();
+
+        let record = Record::PacketSent {
+            ty: packet::Type::Initial,
+            pkt_num: 0,
+            size: 1200,
+        }
+        .to_bytes(Duration::ZERO);
+
+        let mut buf = header.to_vec();
+        buf.extend_from_slice(&record);
+
+        assert_eq!(Reader::new(&buf).unwrap().count(), 1);
+
+        // Truncated header.
+        assert!(matches!(
+            Reader::new(&buf[..HEADER_LEN - 1]),
+            Err(Error::BufferTooShort)
+        ));
+
+        // Truncated record.
+        assert!(matches!(
+            Reader::new(&buf[..buf.len() - 1]),
+            Err(Error::BufferTooShort)
+        ));
+
+        // Unknown magic.
+        let mut invalid = buf.clone();
+        invalid[0] = b'X';
+        assert!(matches!(Reader::new(&invalid), Err(Error::InvalidState)));
+
+        // Unknown version.
+        let mut invalid = buf.clone();
+        invalid[4] = VERSION + 1;
+        assert!(matches!(Reader::new(&invalid), Err(Error::InvalidState)));
+
+        // Unknown record kind.
+        let mut invalid = buf.clone();
+        invalid[HEADER_LEN + 8] = 0xff;
+        assert_eq!(
+            Reader::new(&invalid).unwrap().next(),
+            Some(Err(Error::InvalidState))
+        );
+
+        // Unknown packet type.
+        let mut invalid = buf;
+        invalid[HEADER_LEN + 9] = 6;
+        assert_eq!(
+            Reader::new(&invalid).unwrap().next(),
+            Some(Err(Error::InvalidState))
+        );
+    }
+
+    #[test]
+    fn connection() {
+        let trace = Trace::default();
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        pipe.client.set_btrace(Box::new(trace.clone()));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let buf = trace.bytes();
+
+        let reader = Reader::new(&buf).unwrap();
+        assert!(!reader.header().is_server);
+
+        let records = reader
+            .map(|r| r.map(|(_, record)| record))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        // The client starts by sending an Initial packet.
+        assert!(matches!(records[0], Record::PacketSent {
+            ty: packet::Type::Initial,
+            pkt_num: 0,
+            ..
+        }));
+
+        assert!(records.iter().any(|r| matches!(r, Record::PacketReceived {
+            ty: packet::Type::Handshake,
+            ..
+        })));
+
+        assert!(records.iter().any(|r| matches!(r, Record::PacketSent {
+            ty: packet::Type::Short,
+            ..
+        })));
+
+        assert!(records
+            .iter()
+            .any(|r| matches!(r, Record::MetricsUpdated { .. })));
+    }
+
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn convert_to_qlog() {
+        let trace = Trace::default();
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        pipe.server.set_btrace(Box::new(trace.clone()));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let qlog = Trace::default();
+
+        assert_eq!(to_qlog(&trace.bytes(), Box::new(qlog.clone())), Ok(()));
+
+        let qlog = String::from_utf8(qlog.bytes()).unwrap();
+
+        // One JSON-SEQ record for the log itself, and one per event.
+        let events = Reader::new(&trace.bytes()).unwrap().count();
+        assert_eq!(qlog.matches('\u{1e}').count(), events + 1);
+
+        assert!(qlog.contains("\"vantage_point\":{\"type\":\"server\"}"));
+        assert!(qlog.contains("transport:packet_received"));
+        assert!(qlog.contains("transport:packet_sent"));
+        assert!(qlog.contains("recovery:metrics_updated"));
+    }
+}
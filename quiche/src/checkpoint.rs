@@ -0,0 +1,505 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Handing connections over to another instance.
+//!
+//! To continue an established connection in another process, e.g. while
+//! draining a server for maintenance, [`export_state()`] writes its state,
+//! including the 1-RTT keys, in a versioned binary format, and [`import()`]
+//! recreates the connection from it.
+//!
+//! [`export_state()`]: ../struct.Connection.html#method.export_state
+//! [`import()`]: ../fn.import.html
+
+use std::net::SocketAddr;
+
+use crate::crypto;
+use crate::packet;
+use crate::stream;
+
+use crate::Config;
+use crate::Connection;
+use crate::ConnectionId;
+use crate::Error;
+use crate::Result;
+use crate::TransportParams;
+
+/// The version of the binary format written by [`export()`].
+const STATE_FORMAT_VERSION: u8 = 1;
+
+/// The state of a connection written by [`export()`].
+struct ExportedState {
+    version: u32,
+
+    is_server: bool,
+
+    scid: ConnectionId<'static>,
+
+    dcid: ConnectionId<'static>,
+
+    alpn: Vec<u8>,
+
+    aead: crypto::Algorithm,
+
+    key_phase: bool,
+
+    open_secret: Vec<u8>,
+
+    open_hp_key: Vec<u8>,
+
+    seal_secret: Vec<u8>,
+
+    seal_hp_key: Vec<u8>,
+
+    next_pkt_num: u64,
+
+    largest_rx_pkt_num: u64,
+
+    rx_data: u64,
+
+    max_rx_data: u64,
+
+    tx_data: u64,
+
+    max_tx_data: u64,
+
+    limits: stream::StreamLimits,
+
+    peer_params: Vec<u8>,
+}
+
+impl ExportedState {
+    fn to_bytes(&self, out: &mut [u8]) -> Result<usize> {
+        let mut b = octets::OctetsMut::with_slice(out);
+
+        b.put_u8(STATE_FORMAT_VERSION)?;
+        b.put_u32(self.version)?;
+        b.put_u8(self.is_server as u8)?;
+
+        packet::put_cid_with_len(&mut b, &self.scid)?;
+        packet::put_cid_with_len(&mut b, &self.dcid)?;
+
+        b.put_u8(self.alpn.len() as u8)?;
+        b.put_bytes(&self.alpn)?;
+
+        b.put_u8(aead_to_u8(self.aead))?;
+        b.put_u8(self.key_phase as u8)?;
+
+        for key in [
+            &self.open_secret,
+            &self.open_hp_key,
+            &self.seal_secret,
+            &self.seal_hp_key,
+        ] {
+            b.put_u8(key.len() as u8)?;
+            b.put_bytes(key)?;
+        }
+
+        let limits = &self.limits;
+
+        for v in [
+            self.next_pkt_num,
+            self.largest_rx_pkt_num,
+            self.rx_data,
+            self.max_rx_data,
+            self.tx_data,
+            self.max_tx_data,
+            limits.peer_max_streams_bidi,
+            limits.peer_max_streams_uni,
+            limits.peer_opened_streams_bidi,
+            limits.peer_opened_streams_uni,
+            limits.local_max_streams_bidi,
+            limits.local_max_streams_bidi_next,
+            limits.local_max_streams_uni,
+            limits.local_max_streams_uni_next,
+            limits.local_opened_streams_bidi,
+            limits.local_opened_streams_uni,
+        ] {
+            b.put_varint(v)?;
+        }
+
+        b.put_varint(self.peer_params.len() as u64)?;
+        b.put_bytes(&self.peer_params)?;
+
+        Ok(b.off())
+    }
+
+    fn from_bytes(buf: &[u8]) -> Result<ExportedState> {
+        let mut buf = buf.to_vec();
+        let mut b = octets::OctetsMut::with_slice(&mut buf);
+
+        if b.get_u8()? != STATE_FORMAT_VERSION {
+            return Err(Error::InvalidState);
+        }
+
+        let version = b.get_u32()?;
+        let is_server = b.get_u8()? != 0;
+
+        let scid = packet::get_cid_with_len(&mut b, version)?;
+        let dcid = packet::get_cid_with_len(&mut b, version)?;
+
+        let alpn = b.get_bytes_with_u8_length()?.to_vec();
+
+        let aead = aead_from_u8(b.get_u8()?)?;
+        let key_phase = b.get_u8()? != 0;
+
+        let open_secret = b.get_bytes_with_u8_length()?.to_vec();
+        let open_hp_key = b.get_bytes_with_u8_length()?.to_vec();
+        let seal_secret = b.get_bytes_with_u8_length()?.to_vec();
+        let seal_hp_key = b.get_bytes_with_u8_length()?.to_vec();
+
+        let next_pkt_num = b.get_varint()?;
+        let largest_rx_pkt_num = b.get_varint()?;
+        let rx_data = b.get_varint()?;
+        let max_rx_data = b.get_varint()?;
+        let tx_data = b.get_varint()?;
+        let max_tx_data = b.get_varint()?;
+
+        let limits = stream::StreamLimits {
+            peer_max_streams_bidi: b.get_varint()?,
+            peer_max_streams_uni: b.get_varint()?,
+            peer_opened_streams_bidi: b.get_varint()?,
+            peer_opened_streams_uni: b.get_varint()?,
+            local_max_streams_bidi: b.get_varint()?,
+            local_max_streams_bidi_next: b.get_varint()?,
+            local_max_streams_uni: b.get_varint()?,
+            local_max_streams_uni_next: b.get_varint()?,
+            local_opened_streams_bidi: b.get_varint()?,
+            local_opened_streams_uni: b.get_varint()?,
+        };
+
+        let peer_params = b.get_bytes_with_varint_length()?.to_vec();
+
+        Ok(ExportedState {
+            version,
+            is_server,
+            scid,
+            dcid,
+            alpn,
+            aead,
+            key_phase,
+            open_secret,
+            open_hp_key,
+            seal_secret,
+            seal_hp_key,
+            next_pkt_num,
+            largest_rx_pkt_num,
+            rx_data,
+            max_rx_data,
+            tx_data,
+            max_tx_data,
+            limits,
+            peer_params,
+        })
+    }
+}
+
+fn aead_to_u8(aead: crypto::Algorithm) -> u8 {
+    match aead {
+        crypto::Algorithm::AES128_GCM => 0,
+        crypto::Algorithm::AES256_GCM => 1,
+        crypto::Algorithm::ChaCha20_Poly1305 => 2,
+    }
+}
+
+fn aead_from_u8(v: u8) -> Result<crypto::Algorithm> {
+    match v {
+        0 => Ok(crypto::Algorithm::AES128_GCM),
+        1 => Ok(crypto::Algorithm::AES256_GCM),
+        2 => Ok(crypto::Algorithm::ChaCha20_Poly1305),
+        _ => Err(Error::InvalidState),
+    }
+}
+
+/// Writes the state needed to continue the connection on another instance.
+///
+/// Stream data and packets in flight are not part of the state, so this
+/// requires the connection to be idle: the handshake must be confirmed, and
+/// all streams must be complete and all sent packets acknowledged.
+pub(crate) fn export(conn: &Connection, out: &mut [u8]) -> Result<usize> {
+    if !conn.is_handshake_confirmed() || conn.is_closed() || conn.is_draining() {
+        return Err(Error::InvalidState);
+    }
+
+    if conn.streams.iter().next().is_some() ||
+        conn.paths.get_active()?.recovery.bytes_in_flight() > 0
+    {
+        return Err(Error::InvalidState);
+    }
+
+    let space = &conn.pkt_num_spaces[packet::Epoch::Application];
+
+    let (open, seal) = match (&space.crypto_open, &space.crypto_seal) {
+        (Some(open), Some(seal)) => (open, seal),
+
+        _ => return Err(Error::InvalidState),
+    };
+
+    let mut raw_params = vec![0; conn.peer_transport_params.max_encoded_len()];
+    let raw_params = TransportParams::encode(
+        &conn.peer_transport_params,
+        !conn.is_server,
+        &mut raw_params,
+    )?;
+
+    let state = ExportedState {
+        version: conn.version,
+        is_server: conn.is_server,
+        scid: conn.source_id().into_owned(),
+        dcid: conn.destination_id().into_owned(),
+        alpn: conn.alpn.clone(),
+        aead: seal.alg(),
+        key_phase: conn.key_phase,
+        open_secret: open.secret().to_vec(),
+        open_hp_key: open.hp_key().to_vec(),
+        seal_secret: seal.secret().to_vec(),
+        seal_hp_key: seal.hp_key().to_vec(),
+        next_pkt_num: space.next_pkt_num,
+        largest_rx_pkt_num: space.largest_rx_pkt_num,
+        rx_data: conn.rx_data,
+        max_rx_data: conn.flow_control.max_data(),
+        tx_data: conn.tx_data,
+        max_tx_data: conn.max_tx_data,
+        limits: conn.streams.limits(),
+        peer_params: raw_params.to_vec(),
+    };
+
+    state.to_bytes(out)
+}
+
+/// Recreates a connection from the state written by [`export()`].
+pub(crate) fn import(
+    state: &[u8], local: SocketAddr, peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    let s = ExportedState::from_bytes(state).map_err(|_| Error::InvalidState)?;
+
+    if !crate::version_is_supported(s.version) {
+        return Err(Error::UnknownVersion);
+    }
+
+    let mut conn =
+        Connection::new(&s.scid, None, local, peer, config, s.is_server)?;
+
+    let now = conn.clock.now();
+
+    conn.version = s.version;
+    conn.alpn = s.alpn;
+
+    let peer_params = TransportParams::decode(&s.peer_params, s.is_server)?;
+    conn.process_peer_transport_params(peer_params)?;
+    conn.parsed_peer_transport_params = true;
+
+    let reset_token = conn.peer_transport_params.stateless_reset_token;
+    let active_path_id = conn.paths.get_active_path_id()?;
+    conn.set_initial_dcid(s.dcid, reset_token, active_path_id)?;
+    conn.got_peer_conn_id = true;
+
+    let active_path = conn.paths.get_active_mut()?;
+    active_path.verified_peer_address = true;
+    active_path.peer_verified_local_address = true;
+
+    // The handshake was completed by the exporting instance, so the keys of
+    // the handshake epochs are never needed.
+    for epoch in [packet::Epoch::Initial, packet::Epoch::Handshake] {
+        conn.pkt_num_spaces[epoch].crypto_open = None;
+        conn.pkt_num_spaces[epoch].crypto_seal = None;
+        conn.pkt_num_spaces[epoch].clear();
+    }
+
+    conn.derived_initial_secrets = true;
+    conn.peer_verified_initial_address = true;
+    conn.handshake_completed = true;
+    conn.handshake_confirmed = true;
+    conn.handshake_done_sent = true;
+    conn.handshake_done_acked = true;
+
+    let handshake_status = conn.handshake_status();
+    for (_, p) in conn.paths.iter_mut() {
+        for epoch in [packet::Epoch::Initial, packet::Epoch::Handshake] {
+            p.recovery
+                .on_pkt_num_space_discarded(epoch, handshake_status, now);
+        }
+    }
+
+    let space = &mut conn.pkt_num_spaces[packet::Epoch::Application];
+
+    space.crypto_open = Some(crypto::Open::from_secret_and_hp_key(
+        s.aead,
+        s.open_secret,
+        s.open_hp_key,
+    )?);
+    space.crypto_seal = Some(crypto::Seal::from_secret_and_hp_key(
+        s.aead,
+        s.seal_secret,
+        s.seal_hp_key,
+    )?);
+
+    space.next_pkt_num = s.next_pkt_num;
+    space.largest_rx_pkt_num = s.largest_rx_pkt_num;
+
+    // The packets received by the previous instance can't be told apart, so
+    // treat all of them as duplicates.
+    space.recv_pkt_num.insert_up_to(s.largest_rx_pkt_num);
+
+    conn.key_phase = s.key_phase;
+
+    conn.rx_data = s.rx_data;
+    conn.flow_control.add_consumed(s.rx_data);
+    conn.flow_control.set_max_data(s.max_rx_data);
+
+    conn.tx_data = s.tx_data;
+    conn.max_tx_data = s.max_tx_data;
+    conn.update_tx_cap();
+
+    conn.streams.set_limits(&s.limits, s.is_server);
+
+    if let Some(idle_timeout) = conn.idle_timeout() {
+        conn.idle_timer = Some(now + idle_timeout);
+    }
+
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    fn config() -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_idle_timeout(180_000);
+        config.verify_peer(false);
+        config
+    }
+
+    #[test]
+    fn export_requires_handshake() {
+        let mut state = [0; 1024];
+
+        let pipe = testing::Pipe::new().unwrap();
+
+        assert_eq!(
+            pipe.client.export_state(&mut state),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    fn export_import() {
+        let mut buf = [0; 65535];
+        let mut state = [0; 1024];
+
+        let mut config = config();
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Streams need to be complete before the state can be exported.
+        assert_eq!(
+            pipe.server.export_state(&mut state),
+            Err(Error::InvalidState)
+        );
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.server.stream_send(0, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_recv(0, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Keep a copy of a packet received before the handoff.
+        assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(
+            testing::process_flight(&mut pipe.server, flight.clone()),
+            Ok(())
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let len = pipe.server.export_state(&mut state).unwrap();
+
+        // Truncated state is rejected.
+        assert_eq!(
+            crate::import(
+                &state[..len - 1],
+                testing::Pipe::server_addr(),
+                testing::Pipe::client_addr(),
+                &mut config,
+            )
+            .err(),
+            Some(Error::InvalidState)
+        );
+
+        pipe.server = crate::import(
+            &state[..len],
+            testing::Pipe::server_addr(),
+            testing::Pipe::client_addr(),
+            &mut config,
+        )
+        .unwrap();
+
+        assert!(pipe.server.is_established());
+        assert!(pipe.server.is_handshake_confirmed());
+        assert_eq!(pipe.server.application_proto(), b"proto1");
+        assert_eq!(pipe.server.source_id(), pipe.client.destination_id());
+        assert_eq!(pipe.server.destination_id(), pipe.client.source_id());
+
+        // Packets received before the handoff are ignored as duplicates.
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+        assert_eq!(pipe.server.stats().recv, 0);
+
+        // Streams used before the handoff can't be created again.
+        assert_eq!(pipe.server.stream_send(0, b"again", true), Err(Error::Done));
+
+        // The connection continues on the new instance.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.client.stats().lost, 0);
+    }
+}
@@ -28,6 +28,7 @@ use crate::Error;
 use crate::Result;
 
 use crate::frame;
+use crate::rand;
 
 use crate::packet::ConnectionId;
 
@@ -41,6 +42,94 @@ use smallvec::SmallVec;
 /// `active_conn_id_limit` (see RFC 9000, section 5.1.2).
 const RETIRED_CONN_ID_LIMIT_MULTIPLIER: usize = 3;
 
+/// Mints source connection IDs on behalf of the application.
+///
+/// Applications that need to encode routing information into their connection
+/// IDs (e.g. for load balancers following the QUIC-LB draft) can implement
+/// this trait and configure it with [`set_connection_id_generator()`].
+///
+/// [`set_connection_id_generator()`]: struct.Config.html#method.set_connection_id_generator
+pub trait ConnectionIdGenerator: Send + Sync {
+    /// Returns a new source connection ID.
+    ///
+    /// The `seq` parameter is the sequence number that will be associated to
+    /// the returned connection ID, with `0` being used for the initial one.
+    ///
+    /// The connection ID must be between 1 and [`MAX_CONN_ID_LEN`] bytes long,
+    /// otherwise it is rejected with [`InvalidState`].
+    ///
+    /// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    fn generate(&self, seq: u64) -> ConnectionId<'static>;
+}
+
+/// Mints a connection ID with `generator`, checking that its length is valid.
+pub(crate) fn generate_cid(
+    generator: &dyn ConnectionIdGenerator, seq: u64,
+) -> Result<ConnectionId<'static>> {
+    let cid = generator.generate(seq);
+
+    if cid.is_empty() || cid.len() > crate::MAX_CONN_ID_LEN {
+        return Err(Error::InvalidState);
+    }
+
+    Ok(cid)
+}
+
+/// The default [`ConnectionIdGenerator`], which mints random connection IDs
+/// of a fixed length.
+///
+/// [`ConnectionIdGenerator`]: trait.ConnectionIdGenerator.html
+pub struct RandomConnectionIdGenerator {
+    len: usize,
+
+    rng: std::sync::Arc<dyn rand::Rng>,
+}
+
+impl RandomConnectionIdGenerator {
+    /// Creates a generator minting random connection IDs of `len` bytes.
+    ///
+    /// The length is capped to [`MAX_CONN_ID_LEN`].
+    ///
+    /// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+    pub fn new(len: usize) -> Self {
+        RandomConnectionIdGenerator {
+            len: std::cmp::min(len, crate::MAX_CONN_ID_LEN),
+            rng: std::sync::Arc::new(rand::SystemRng),
+        }
+    }
+
+    /// Creates a generator minting connection IDs of `len` bytes drawn from
+    /// `rng`.
+    ///
+    /// The length is capped to [`MAX_CONN_ID_LEN`].
+    ///
+    /// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+    #[cfg(feature = "deterministic-rng")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "deterministic-rng")))]
+    pub fn with_rng(len: usize, rng: std::sync::Arc<dyn rand::Rng>) -> Self {
+        RandomConnectionIdGenerator {
+            len: std::cmp::min(len, crate::MAX_CONN_ID_LEN),
+            rng,
+        }
+    }
+}
+
+impl Default for RandomConnectionIdGenerator {
+    fn default() -> Self {
+        Self::new(crate::MAX_CONN_ID_LEN)
+    }
+}
+
+impl ConnectionIdGenerator for RandomConnectionIdGenerator {
+    fn generate(&self, _seq: u64) -> ConnectionId<'static> {
+        let mut cid = vec![0; self.len];
+        self.rng.fill_bytes(&mut cid);
+
+        ConnectionId::from_vec(cid)
+    }
+}
+
 #[derive(Default)]
 struct BoundedConnectionIdSeqSet {
     /// The inner set.
@@ -405,6 +494,12 @@ impl ConnectionIdentifiers {
         Ok(seq)
     }
 
+    /// Returns the sequence number the next source Connection ID will get.
+    #[inline]
+    pub fn next_scid_seq(&self) -> u64 {
+        self.next_scid_seq
+    }
+
     /// Sets the initial destination identifier.
     pub fn set_initial_dcid(
         &mut self, cid: ConnectionId<'static>, reset_token: Option<u128>,
@@ -1076,4 +1171,17 @@ mod tests {
         assert_eq!(ids.pop_retired_scid(), Some(scid2));
         assert_eq!(ids.pop_retired_scid(), None);
     }
+
+    #[test]
+    fn random_generator() {
+        let generator = RandomConnectionIdGenerator::new(8);
+        assert_eq!(generator.generate(0).len(), 8);
+        assert_ne!(generator.generate(1), generator.generate(2));
+
+        let generator = RandomConnectionIdGenerator::new(64);
+        assert_eq!(generator.generate(0).len(), crate::MAX_CONN_ID_LEN);
+
+        let generator = RandomConnectionIdGenerator::default();
+        assert_eq!(generator.generate(0).len(), crate::MAX_CONN_ID_LEN);
+    }
 }
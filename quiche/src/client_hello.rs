@@ -0,0 +1,338 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::Error;
+use crate::Result;
+
+/// TLS handshake message type of a ClientHello.
+const CLIENT_HELLO: u8 = 0x01;
+
+/// TLS extension carrying the server name.
+const EXT_SERVER_NAME: u16 = 0x0000;
+
+/// TLS extension carrying the offered application protocols.
+const EXT_ALPN: u16 = 0x0010;
+
+/// TLS extension carrying the identities of the offered pre-shared keys.
+const EXT_PRE_SHARED_KEY: u16 = 0x0029;
+
+/// TLS extension indicating that the client is sending early data.
+const EXT_EARLY_DATA: u16 = 0x002a;
+
+/// The server name type of a DNS hostname.
+const HOST_NAME: u8 = 0x00;
+
+/// The parts of a client's TLS ClientHello message that are relevant when
+/// deciding whether to accept a connection.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientHello {
+    server_name: Option<String>,
+
+    application_protos: Vec<Vec<u8>>,
+
+    psk_identities: Vec<Vec<u8>>,
+
+    early_data: bool,
+}
+
+impl ClientHello {
+    /// Returns the server name requested by the client, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Returns the application protocols offered by the client, in order of
+    /// preference.
+    pub fn application_protos(&self) -> &[Vec<u8>] {
+        &self.application_protos
+    }
+
+    /// Returns the identities of the pre-shared keys offered by the client,
+    /// i.e. the session tickets it is attempting to resume, in order.
+    pub fn psk_identities(&self) -> &[Vec<u8>] {
+        &self.psk_identities
+    }
+
+    /// Returns whether the client is attempting to send early data.
+    pub fn early_data(&self) -> bool {
+        self.early_data
+    }
+
+    /// Parses a ClientHello handshake message.
+    ///
+    /// [`BufferTooShort`] is returned if the message is incomplete.
+    ///
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    pub(crate) fn from_bytes(buf: &[u8]) -> Result<ClientHello> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        if b.get_u8()? != CLIENT_HELLO {
+            return Err(Error::TlsFail);
+        }
+
+        let len = b.get_u24()? as usize;
+
+        let mut b = b.get_bytes(len)?;
+
+        // From here on the message is complete, so running out of data means
+        // that it is badly formatted.
+        Self::parse_body(&mut b).map_err(|_| Error::TlsFail)
+    }
+
+    fn parse_body(b: &mut octets::Octets) -> Result<ClientHello> {
+        let mut hello = ClientHello::default();
+
+        // Skip legacy_version and random.
+        b.skip(2 + 32)?;
+
+        // Skip legacy_session_id, cipher_suites and
+        // legacy_compression_methods.
+        b.get_bytes_with_u8_length()?;
+        b.get_bytes_with_u16_length()?;
+        b.get_bytes_with_u8_length()?;
+
+        let mut exts = b.get_bytes_with_u16_length()?;
+
+        while exts.cap() > 0 {
+            let ty = exts.get_u16()?;
+            let mut data = exts.get_bytes_with_u16_length()?;
+
+            match ty {
+                EXT_SERVER_NAME => {
+                    let mut names = data.get_bytes_with_u16_length()?;
+
+                    while names.cap() > 0 {
+                        let name_type = names.get_u8()?;
+                        let name = names.get_bytes_with_u16_length()?;
+
+                        if name_type == HOST_NAME {
+                            let name = std::str::from_utf8(name.buf())
+                                .map_err(|_| Error::TlsFail)?;
+
+                            hello.server_name = Some(name.to_string());
+                        }
+                    }
+                },
+
+                EXT_ALPN => {
+                    let mut protos = data.get_bytes_with_u16_length()?;
+
+                    while protos.cap() > 0 {
+                        let proto = protos.get_bytes_with_u8_length()?;
+
+                        hello.application_protos.push(proto.to_vec());
+                    }
+                },
+
+                EXT_PRE_SHARED_KEY => {
+                    let mut identities = data.get_bytes_with_u16_length()?;
+
+                    while identities.cap() > 0 {
+                        let identity = identities.get_bytes_with_u16_length()?;
+
+                        // Skip obfuscated_ticket_age.
+                        identities.skip(4)?;
+
+                        hello.psk_identities.push(identity.to_vec());
+                    }
+                },
+
+                EXT_EARLY_DATA => hello.early_data = true,
+
+                _ => (),
+            }
+        }
+
+        Ok(hello)
+    }
+}
+
+/// The decision taken by a [`ClientHelloHandler`].
+///
+/// [`ClientHelloHandler`]: trait.ClientHelloHandler.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientHelloAction {
+    /// Continue with the handshake.
+    Accept,
+
+    /// Continue with the handshake, but reject any early data, e.g. because
+    /// the ticket being resumed is known to have been used already, or the
+    /// application can't guarantee that replaying the client's requests is
+    /// safe. The client then retransmits its early data after the handshake.
+    RejectEarlyData,
+
+    /// Close the connection with the given transport error code, such as
+    /// `CONNECTION_REFUSED` (0x2), without continuing the handshake.
+    Reject(u64),
+}
+
+/// Decides whether to accept connections based on the client's ClientHello.
+///
+/// Servers can implement this trait and configure it with
+/// [`set_client_hello_handler()`] to refuse connections (e.g. because of rate
+/// limits, or because the requested server name isn't served) before any
+/// expensive cryptographic operation is performed.
+///
+/// The handler is invoked once per connection, as soon as the ClientHello
+/// message has been received in full.
+///
+/// [`set_client_hello_handler()`]:
+/// struct.Config.html#method.set_client_hello_handler
+pub trait ClientHelloHandler: Send + Sync {
+    /// Returns whether the handshake with the client should continue.
+    fn on_client_hello(&self, hello: &ClientHello) -> ClientHelloAction;
+}
+
+/// Records the session tickets used to send early data, so that early data
+/// replayed by an attacker is rejected.
+///
+/// Servers can implement this trait and configure it with
+/// [`set_anti_replay()`]. The store is consulted once per connection that
+/// attempts to send early data, with the identity of the ticket being resumed.
+/// Deployments where several servers share the same ticket key (see
+/// [`set_ticket_key()`]) should back the store with state shared by all of
+/// them, as a replayed ClientHello can be directed at any of the servers.
+///
+/// Tickets are only usable for as long as their lifetime (see
+/// [`set_ticket_lifetime()`]), so entries can be discarded after that.
+///
+/// [`set_anti_replay()`]: struct.Config.html#method.set_anti_replay
+/// [`set_ticket_key()`]: struct.Config.html#method.set_ticket_key
+/// [`set_ticket_lifetime()`]: struct.Config.html#method.set_ticket_lifetime
+pub trait AntiReplay: Send + Sync {
+    /// Records the use of the given ticket for early data.
+    ///
+    /// Returns `false` if the ticket had already been used, in which case
+    /// early data is rejected while the handshake continues.
+    fn insert(&self, ticket: &[u8]) -> bool;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_hello(exts: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0xba; 32]);
+        body.extend_from_slice(&[0x00]);
+        body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]);
+        body.extend_from_slice(&[0x01, 0x00]);
+
+        let mut ext_bytes = Vec::new();
+        for (ty, data) in exts {
+            ext_bytes.extend_from_slice(&ty.to_be_bytes());
+            ext_bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            ext_bytes.extend_from_slice(data);
+        }
+
+        body.extend_from_slice(&(ext_bytes.len() as u16).to_be_bytes());
+        body.extend_from_slice(&ext_bytes);
+
+        let mut msg = vec![CLIENT_HELLO];
+        msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        msg.extend_from_slice(&body);
+
+        msg
+    }
+
+    #[test]
+    fn parse() {
+        let sni = b"\x00\x0c\x00\x00\x09quic.tech";
+        let alpn = b"\x00\x0c\x06proto1\x02h3\x01x";
+
+        let msg = client_hello(&[
+            (0x002b, b"\x02\x03\x04"),
+            (EXT_SERVER_NAME, sni),
+            (EXT_ALPN, alpn),
+        ]);
+
+        let hello = ClientHello::from_bytes(&msg).unwrap();
+        assert_eq!(hello.server_name(), Some("quic.tech"));
+        assert_eq!(hello.application_protos(), &[
+            b"proto1".to_vec(),
+            b"h3".to_vec(),
+            b"x".to_vec()
+        ]);
+    }
+
+    #[test]
+    fn parse_no_extensions() {
+        let msg = client_hello(&[]);
+
+        let hello = ClientHello::from_bytes(&msg).unwrap();
+        assert_eq!(hello.server_name(), None);
+        assert!(hello.application_protos().is_empty());
+        assert!(hello.psk_identities().is_empty());
+        assert!(!hello.early_data());
+    }
+
+    #[test]
+    fn parse_resumption() {
+        let psk = [
+            // Identities.
+            &b"\x00\x14"[..],
+            b"\x00\x05ticka\x00\x00\x00\x01",
+            b"\x00\x03tkb\x00\x00\x00\x02",
+            // Binders.
+            b"\x00\x02\x01\x00",
+        ]
+        .concat();
+
+        let msg =
+            client_hello(&[(EXT_EARLY_DATA, b""), (EXT_PRE_SHARED_KEY, &psk)]);
+
+        let hello = ClientHello::from_bytes(&msg).unwrap();
+        assert!(hello.early_data());
+        assert_eq!(hello.psk_identities(), &[
+            b"ticka".to_vec(),
+            b"tkb".to_vec()
+        ]);
+    }
+
+    #[test]
+    fn parse_incomplete() {
+        let msg = client_hello(&[(EXT_ALPN, b"\x00\x03\x02h3")]);
+
+        for len in 0..msg.len() {
+            assert_eq!(
+                ClientHello::from_bytes(&msg[..len]),
+                Err(Error::BufferTooShort)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // Not a ClientHello.
+        let mut msg = client_hello(&[]);
+        msg[0] = 0x02;
+        assert_eq!(ClientHello::from_bytes(&msg), Err(Error::TlsFail));
+
+        // ALPN list longer than the extension.
+        let msg = client_hello(&[(EXT_ALPN, b"\x00\x09\x02h3")]);
+        assert_eq!(ClientHello::from_bytes(&msg), Err(Error::TlsFail));
+    }
+}
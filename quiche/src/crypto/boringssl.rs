@@ -151,16 +151,18 @@ fn make_aead_ctx(alg: Algorithm, key: &[u8]) -> Result<EVP_AEAD_CTX> {
 
 pub(crate) struct PacketKey {
     ctx: EVP_AEAD_CTX,
-    nonce: Vec<u8>,
+    nonce: Secret,
 }
 
 impl PacketKey {
     pub fn new(
         alg: Algorithm, key: Vec<u8>, iv: Vec<u8>, _enc: u32,
     ) -> Result<Self> {
+        let key = Secret::from(key);
+
         Ok(Self {
             ctx: make_aead_ctx(alg, &key)?,
-            nonce: iv,
+            nonce: iv.into(),
         })
     }
 
@@ -178,6 +180,23 @@ impl PacketKey {
     }
 }
 
+impl Drop for PacketKey {
+    fn drop(&mut self) {
+        unsafe { EVP_AEAD_CTX_cleanup(&mut self.ctx) };
+
+        // The context is not heap allocated by BoringSSL, so the expanded key
+        // needs to be wiped explicitly.
+        let ctx = unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut self.ctx as *mut EVP_AEAD_CTX as *mut u8,
+                std::mem::size_of::<EVP_AEAD_CTX>(),
+            )
+        };
+
+        zeroize(ctx);
+    }
+}
+
 extern {
     fn EVP_aead_aes_128_gcm() -> *const EVP_AEAD;
 
@@ -191,6 +210,8 @@ extern {
         key_len: usize, tag_len: usize, engine: *mut c_void,
     ) -> c_int;
 
+    fn EVP_AEAD_CTX_cleanup(ctx: *mut EVP_AEAD_CTX);
+
     fn EVP_AEAD_CTX_open(
         ctx: *const EVP_AEAD_CTX, out: *mut u8, out_len: *mut usize,
         max_out_len: usize, nonce: *const u8, nonce_len: usize, inp: *const u8,
@@ -27,6 +27,7 @@
 use ring::aead;
 use ring::hkdf;
 
+use libc::c_int;
 use libc::c_void;
 
 use crate::Error;
@@ -55,14 +56,18 @@ impl Level {
     }
 }
 
+/// An AEAD algorithm, along with the hash function used for key derivation.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Algorithm {
+    /// AES-128-GCM with SHA-256.
     #[allow(non_camel_case_types)]
     AES128_GCM,
 
+    /// AES-256-GCM with SHA-384.
     #[allow(non_camel_case_types)]
     AES256_GCM,
 
+    /// ChaCha20-Poly1305 with SHA-256.
     #[allow(non_camel_case_types)]
     ChaCha20_Poly1305,
 }
@@ -86,6 +91,7 @@ impl Algorithm {
         }
     }
 
+    /// Returns the length of the algorithm's keys, in bytes.
     pub fn key_len(self) -> usize {
         match self {
             Algorithm::AES128_GCM => 16,
@@ -94,6 +100,7 @@ impl Algorithm {
         }
     }
 
+    /// Returns the length of the algorithm's authentication tags, in bytes.
     pub fn tag_len(self) -> usize {
         if cfg!(feature = "fuzzing") {
             return 0;
@@ -106,6 +113,7 @@ impl Algorithm {
         }
     }
 
+    /// Returns the length of the algorithm's nonces, in bytes.
     pub fn nonce_len(self) -> usize {
         match self {
             Algorithm::AES128_GCM => 12,
@@ -121,10 +129,223 @@ pub struct EVP_AEAD {
     _unused: c_void,
 }
 
+/// Key material that is wiped from memory when dropped.
+#[derive(Clone, Default)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    pub fn new(len: usize) -> Secret {
+        Secret(vec![0; len])
+    }
+}
+
+impl From<Vec<u8>> for Secret {
+    fn from(v: Vec<u8>) -> Secret {
+        Secret(v)
+    }
+}
+
+impl std::ops::Deref for Secret {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Secret {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Also wipe any spare capacity, in case the buffer was truncated.
+        unsafe {
+            OPENSSL_cleanse(self.0.as_mut_ptr() as *mut c_void, self.0.capacity())
+        }
+    }
+}
+
+/// Wipes the given buffer in a way that can't be optimized away.
+pub fn zeroize(buf: &mut [u8]) {
+    unsafe { OPENSSL_cleanse(buf.as_mut_ptr() as *mut c_void, buf.len()) }
+}
+
+/// Compares two byte slices in constant time.
+///
+/// The time taken depends only on the length of the slices, not on their
+/// contents, so this should be used whenever a secret value, such as an
+/// authentication tag or a stateless reset token, is compared with one
+/// provided by the peer.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    unsafe {
+        CRYPTO_memcmp(
+            a.as_ptr() as *const c_void,
+            b.as_ptr() as *const c_void,
+            a.len(),
+        ) == 0
+    }
+}
+
+/// Derives key material from `secret` using HKDF-Expand-Label, as defined in
+/// [RFC 8446] and used by QUIC.
+///
+/// The `label` is given without the "tls13 " prefix, e.g. `b"quic key"`, and
+/// the context is always empty. The length of the output is the length of
+/// `out`.
+///
+/// [RFC 8446]: https://www.rfc-editor.org/rfc/rfc8446.html#section-7.1
+pub fn expand_label(
+    aead: Algorithm, secret: &[u8], label: &[u8], out: &mut [u8],
+) -> Result<()> {
+    if label.len() > 255 - 6 || out.len() > u16::MAX as usize {
+        return Err(Error::CryptoFail);
+    }
+
+    let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
+    hkdf_expand_label(&secret, label, out)
+}
+
+/// A key for encrypting and authenticating address validation tokens, e.g.
+/// those sent in Retry and NEW_TOKEN frames.
+///
+/// Each token is sealed with a random 96-bit nonce, which is prepended to it.
+/// As random nonces are only unlikely to repeat up to a point, a single key
+/// should not be used to seal more than 2^32 tokens, so keys should be rotated
+/// regularly. Additional data that is not part of the token, such as the
+/// client's address, can be authenticated along with it, in which case the
+/// same data needs to be provided to open the token.
+///
+/// ## Examples:
+///
+/// ```
+/// # let key = [0; 32];
+/// # let addr = b"127.0.0.1:1234";
+/// let key = quiche::TokenKey::new(quiche::Algorithm::AES256_GCM, &key)?;
+///
+/// let mut token = [0; 64];
+/// let len = key.seal(b"original dcid", addr, &mut token)?;
+///
+/// let mut odcid = [0; 64];
+/// let len = key.open(&token[..len], addr, &mut odcid)?;
+/// assert_eq!(&odcid[..len], b"original dcid");
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub struct TokenKey {
+    key: aead::LessSafeKey,
+}
+
+impl TokenKey {
+    /// Creates a token key for the given algorithm.
+    ///
+    /// The length of `key` must match the algorithm's key length.
+    pub fn new(alg: Algorithm, key: &[u8]) -> Result<TokenKey> {
+        let alg = match alg {
+            Algorithm::AES128_GCM => &aead::AES_128_GCM,
+            Algorithm::AES256_GCM => &aead::AES_256_GCM,
+            Algorithm::ChaCha20_Poly1305 => &aead::CHACHA20_POLY1305,
+        };
+
+        let key =
+            aead::UnboundKey::new(alg, key).map_err(|_| Error::CryptoFail)?;
+
+        Ok(TokenKey {
+            key: aead::LessSafeKey::new(key),
+        })
+    }
+
+    /// Returns the number of bytes sealed tokens take in addition to their
+    /// plaintext.
+    pub fn overhead(&self) -> usize {
+        aead::NONCE_LEN + self.key.algorithm().tag_len()
+    }
+
+    /// Seals `data`, authenticating `aad` along with it, and writes the
+    /// resulting token to `out`.
+    ///
+    /// On success the length of the token is returned, which is always the
+    /// length of `data` plus [`overhead()`].
+    ///
+    /// [`overhead()`]: struct.TokenKey.html#method.overhead
+    pub fn seal(&self, data: &[u8], aad: &[u8], out: &mut [u8]) -> Result<usize> {
+        let len = data.len() + self.overhead();
+
+        if out.len() < len {
+            return Err(Error::BufferTooShort);
+        }
+
+        let (nonce, rest) = out.split_at_mut(aead::NONCE_LEN);
+        crate::rand::rand_bytes(nonce);
+
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| Error::CryptoFail)?;
+
+        let (ciphertext, tag) = rest.split_at_mut(data.len());
+        ciphertext.copy_from_slice(data);
+
+        let t = self
+            .key
+            .seal_in_place_separate_tag(nonce, aead::Aad::from(aad), ciphertext)
+            .map_err(|_| Error::CryptoFail)?;
+
+        tag[..t.as_ref().len()].copy_from_slice(t.as_ref());
+
+        Ok(len)
+    }
+
+    /// Opens a token sealed with [`seal()`], checking that it was sealed with
+    /// the same key and additional data, and writes the original data to
+    /// `out`.
+    ///
+    /// On success the length of the original data is returned.
+    ///
+    /// [`seal()`]: struct.TokenKey.html#method.seal
+    pub fn open(
+        &self, token: &[u8], aad: &[u8], out: &mut [u8],
+    ) -> Result<usize> {
+        if token.len() < self.overhead() {
+            return Err(Error::CryptoFail);
+        }
+
+        let (nonce, sealed) = token.split_at(aead::NONCE_LEN);
+
+        let len = sealed.len() - self.key.algorithm().tag_len();
+
+        if out.len() < sealed.len() {
+            return Err(Error::BufferTooShort);
+        }
+
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| Error::CryptoFail)?;
+
+        let buf = &mut out[..sealed.len()];
+        buf.copy_from_slice(sealed);
+
+        if self
+            .key
+            .open_in_place(nonce, aead::Aad::from(aad), buf)
+            .is_err()
+        {
+            // Don't leave unauthenticated plaintext around.
+            zeroize(buf);
+
+            return Err(Error::CryptoFail);
+        }
+
+        Ok(len)
+    }
+}
+
 pub struct Open {
     alg: Algorithm,
 
-    secret: Vec<u8>,
+    secret: Secret,
 
     header: HeaderProtectionKey,
 
@@ -147,11 +368,13 @@ impl Open {
 
             packet: PacketKey::new(alg, key, iv, Self::DECRYPT)?,
 
-            secret,
+            secret: secret.into(),
         })
     }
 
     pub fn from_secret(aead: Algorithm, secret: Vec<u8>) -> Result<Open> {
+        let secret = Secret::from(secret);
+
         Ok(Open {
             alg: aead,
 
@@ -163,6 +386,25 @@ impl Open {
         })
     }
 
+    /// Creates a key from a traffic secret and a separate header protection
+    /// key, as needed after a key update.
+    #[cfg(feature = "checkpoint")]
+    pub fn from_secret_and_hp_key(
+        aead: Algorithm, secret: Vec<u8>, hp_key: Vec<u8>,
+    ) -> Result<Open> {
+        let secret = Secret::from(secret);
+
+        Ok(Open {
+            alg: aead,
+
+            header: HeaderProtectionKey::new(aead, hp_key)?,
+
+            packet: PacketKey::from_secret(aead, &secret, Self::DECRYPT)?,
+
+            secret,
+        })
+    }
+
     pub fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
         if cfg!(feature = "fuzzing") {
             return Ok(<[u8; 5]>::default());
@@ -181,6 +423,18 @@ impl Open {
         self.alg
     }
 
+    /// Returns the traffic secret the packet key was derived from.
+    #[cfg(any(test, feature = "checkpoint"))]
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Returns the header protection key.
+    #[cfg(feature = "checkpoint")]
+    pub fn hp_key(&self) -> &[u8] {
+        &self.header.hp_key
+    }
+
     pub fn derive_next_packet_key(&self) -> Result<Open> {
         let next_secret = derive_next_secret(self.alg, &self.secret)?;
 
@@ -194,7 +448,7 @@ impl Open {
 
             header: HeaderProtectionKey::new(
                 self.alg,
-                self.header.hp_key.clone(),
+                self.header.hp_key.to_vec(),
             )?,
 
             packet: next_packet_key,
@@ -205,7 +459,7 @@ impl Open {
 pub struct Seal {
     alg: Algorithm,
 
-    secret: Vec<u8>,
+    secret: Secret,
 
     header: HeaderProtectionKey,
 
@@ -228,11 +482,13 @@ impl Seal {
 
             packet: PacketKey::new(alg, key, iv, Self::ENCRYPT)?,
 
-            secret,
+            secret: secret.into(),
         })
     }
 
     pub fn from_secret(aead: Algorithm, secret: Vec<u8>) -> Result<Seal> {
+        let secret = Secret::from(secret);
+
         Ok(Seal {
             alg: aead,
 
@@ -244,6 +500,25 @@ impl Seal {
         })
     }
 
+    /// Creates a key from a traffic secret and a separate header protection
+    /// key, as needed after a key update.
+    #[cfg(feature = "checkpoint")]
+    pub fn from_secret_and_hp_key(
+        aead: Algorithm, secret: Vec<u8>, hp_key: Vec<u8>,
+    ) -> Result<Seal> {
+        let secret = Secret::from(secret);
+
+        Ok(Seal {
+            alg: aead,
+
+            header: HeaderProtectionKey::new(aead, hp_key)?,
+
+            packet: PacketKey::from_secret(aead, &secret, Self::ENCRYPT)?,
+
+            secret,
+        })
+    }
+
     pub fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
         if cfg!(feature = "fuzzing") {
             return Ok(<[u8; 5]>::default());
@@ -262,6 +537,18 @@ impl Seal {
         self.alg
     }
 
+    /// Returns the traffic secret the packet key was derived from.
+    #[cfg(any(test, feature = "checkpoint"))]
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Returns the header protection key.
+    #[cfg(feature = "checkpoint")]
+    pub fn hp_key(&self) -> &[u8] {
+        &self.header.hp_key
+    }
+
     pub fn derive_next_packet_key(&self) -> Result<Seal> {
         let next_secret = derive_next_secret(self.alg, &self.secret)?;
 
@@ -275,7 +562,7 @@ impl Seal {
 
             header: HeaderProtectionKey::new(
                 self.alg,
-                self.header.hp_key.clone(),
+                self.header.hp_key.to_vec(),
             )?,
 
             packet: next_packet_key,
@@ -286,11 +573,13 @@ impl Seal {
 pub struct HeaderProtectionKey {
     hpk: aead::quic::HeaderProtectionKey,
 
-    hp_key: Vec<u8>,
+    hp_key: Secret,
 }
 
 impl HeaderProtectionKey {
     pub fn new(alg: Algorithm, hp_key: Vec<u8>) -> Result<Self> {
+        let hp_key = Secret::from(hp_key);
+
         aead::quic::HeaderProtectionKey::new(alg.get_ring_hp(), &hp_key)
             .map(|hpk| Self { hpk, hp_key })
             .map_err(|_| Error::CryptoFail)
@@ -310,8 +599,8 @@ impl HeaderProtectionKey {
 pub fn derive_initial_key_material(
     cid: &[u8], version: u32, is_server: bool,
 ) -> Result<(Open, Seal)> {
-    let mut client_secret = [0; 32];
-    let mut server_secret = [0; 32];
+    let mut client_secret = Secret::new(32);
+    let mut server_secret = Secret::new(32);
 
     let aead = Algorithm::AES128_GCM;
 
@@ -405,10 +694,10 @@ fn derive_server_initial_secret(prk: &hkdf::Prk, out: &mut [u8]) -> Result<()> {
     hkdf_expand_label(prk, LABEL, out)
 }
 
-fn derive_next_secret(aead: Algorithm, secret: &[u8]) -> Result<Vec<u8>> {
+fn derive_next_secret(aead: Algorithm, secret: &[u8]) -> Result<Secret> {
     const LABEL: &[u8] = b"quic ku";
 
-    let mut next_secret = vec![0; secret.len()];
+    let mut next_secret = Secret::new(secret.len());
 
     let secret_prk = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
     hkdf_expand_label(&secret_prk, LABEL, &mut next_secret)?;
@@ -416,6 +705,29 @@ fn derive_next_secret(aead: Algorithm, secret: &[u8]) -> Result<Vec<u8>> {
     Ok(next_secret)
 }
 
+/// Derives the header protection key from a packet protection secret.
+///
+/// The secret can be e.g. one of the traffic secrets logged in the [keylog]
+/// format. The key is written to the start of `out`, which must be at least
+/// as long as the algorithm's key length.
+///
+/// ## Examples:
+///
+/// ```
+/// # let secret = [0; 32];
+/// let aead = quiche::Algorithm::AES128_GCM;
+///
+/// let mut key = [0; 16];
+/// let mut iv = [0; 12];
+/// let mut hp = [0; 16];
+///
+/// quiche::derive_pkt_key(aead, &secret, &mut key)?;
+/// quiche::derive_pkt_iv(aead, &secret, &mut iv)?;
+/// quiche::derive_hdr_key(aead, &secret, &mut hp)?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
+///
+/// [keylog]: https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format
 pub fn derive_hdr_key(
     aead: Algorithm, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
@@ -431,6 +743,12 @@ pub fn derive_hdr_key(
     hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
 }
 
+/// Derives the packet protection key from a packet protection secret.
+///
+/// The key is written to the start of `out`, which must be at least as long
+/// as the algorithm's key length. See [`derive_hdr_key()`] for an example.
+///
+/// [`derive_hdr_key()`]: fn.derive_hdr_key.html
 pub fn derive_pkt_key(
     aead: Algorithm, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
@@ -446,6 +764,12 @@ pub fn derive_pkt_key(
     hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
 }
 
+/// Derives the packet protection IV from a packet protection secret.
+///
+/// The IV is written to the start of `out`, which must be at least as long
+/// as the algorithm's nonce length. See [`derive_hdr_key()`] for an example.
+///
+/// [`derive_hdr_key()`]: fn.derive_hdr_key.html
 pub fn derive_pkt_iv(
     aead: Algorithm, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
@@ -492,6 +816,12 @@ fn make_nonce(iv: &[u8], counter: u64) -> [u8; aead::NONCE_LEN] {
     nonce
 }
 
+extern {
+    fn OPENSSL_cleanse(ptr: *mut c_void, len: usize);
+
+    fn CRYPTO_memcmp(a: *const c_void, b: *const c_void, len: usize) -> c_int;
+}
+
 // The ring HKDF expand() API does not accept an arbitrary output length, so we
 // need to hide the `usize` length as part of a type that implements the trait
 // `ring::hkdf::KeyType` in order to trick ring into accepting it.
@@ -507,6 +837,109 @@ impl hkdf::KeyType for ArbitraryOutputLen {
 mod tests {
     use super::*;
 
+    #[test]
+    fn zeroize_secret() {
+        let mut buf = [0xaa; 32];
+        zeroize(&mut buf);
+        assert_eq!(buf, [0; 32]);
+
+        let mut secret = Secret::from(vec![0xbb; 16]);
+        assert_eq!(&secret[..], &[0xbb; 16]);
+
+        zeroize(&mut secret);
+        assert_eq!(&secret[..], &[0; 16]);
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!constant_time_eq(&[1, 2, 3], &[1, 2, 4]));
+        assert!(!constant_time_eq(&[1, 2, 3], &[1, 2]));
+    }
+
+    #[test]
+    fn expand_label_matches_packet_key_derivation() {
+        let secret = [0x42; 32];
+
+        for &aead in &[
+            Algorithm::AES128_GCM,
+            Algorithm::AES256_GCM,
+            Algorithm::ChaCha20_Poly1305,
+        ] {
+            let mut expected = [0; 32];
+            let mut out = [0; 32];
+
+            let len = aead.key_len();
+            derive_pkt_key(aead, &secret, &mut expected).unwrap();
+            expand_label(aead, &secret, b"quic key", &mut out[..len]).unwrap();
+            assert_eq!(&out[..len], &expected[..len]);
+
+            let len = aead.nonce_len();
+            derive_pkt_iv(aead, &secret, &mut expected).unwrap();
+            expand_label(aead, &secret, b"quic iv", &mut out[..len]).unwrap();
+            assert_eq!(&out[..len], &expected[..len]);
+        }
+
+        let mut out = [0; 16];
+        assert_eq!(
+            expand_label(Algorithm::AES128_GCM, &secret, &[0; 250], &mut out),
+            Err(Error::CryptoFail)
+        );
+    }
+
+    #[test]
+    fn token_seal_open() {
+        let key = TokenKey::new(Algorithm::AES128_GCM, &[0x11; 16]).unwrap();
+        assert_eq!(key.overhead(), 28);
+
+        let mut token = [0; 64];
+        let len = key.seal(b"hello", b"aad", &mut token).unwrap();
+        assert_eq!(len, 5 + 28);
+
+        let token = &token[..len];
+
+        let mut out = [0; 64];
+        assert_eq!(key.open(token, b"aad", &mut out), Ok(5));
+        assert_eq!(&out[..5], b"hello");
+
+        // Wrong additional data.
+        assert_eq!(key.open(token, b"bad", &mut out), Err(Error::CryptoFail));
+
+        // Tampered token.
+        let mut bad = token.to_vec();
+        bad[15] ^= 1;
+        assert_eq!(key.open(&bad, b"aad", &mut out), Err(Error::CryptoFail));
+
+        // Truncated token.
+        assert_eq!(
+            key.open(&token[..27], b"aad", &mut out),
+            Err(Error::CryptoFail)
+        );
+
+        // Wrong key.
+        let other = TokenKey::new(Algorithm::AES128_GCM, &[0x22; 16]).unwrap();
+        assert_eq!(other.open(token, b"aad", &mut out), Err(Error::CryptoFail));
+
+        // Nonces are random, so sealing twice yields different tokens.
+        let mut again = [0; 64];
+        assert_eq!(key.seal(b"hello", b"aad", &mut again), Ok(len));
+        assert_ne!(&again[..len], token);
+
+        // Output buffers that are too small.
+        assert_eq!(
+            key.seal(b"hello", b"aad", &mut again[..32]),
+            Err(Error::BufferTooShort)
+        );
+        assert_eq!(
+            key.open(token, b"aad", &mut out[..4]),
+            Err(Error::BufferTooShort)
+        );
+
+        // Keys of the wrong length are rejected.
+        assert!(TokenKey::new(Algorithm::AES256_GCM, &[0; 16]).is_err());
+    }
+
     #[test]
     fn derive_initial_secrets_v1() {
         let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
@@ -586,6 +1019,31 @@ mod tests {
         assert_eq!(&hdr_key, &expected_server_hdr_key);
     }
 
+    #[test]
+    fn derive_short_output() {
+        let secret = [0; 48];
+        let aead = Algorithm::AES256_GCM;
+
+        let mut out = [0; 31];
+        assert_eq!(
+            derive_pkt_key(aead, &secret, &mut out),
+            Err(Error::CryptoFail)
+        );
+        assert_eq!(
+            derive_hdr_key(aead, &secret, &mut out),
+            Err(Error::CryptoFail)
+        );
+        assert_eq!(
+            derive_pkt_iv(aead, &secret, &mut out[..11]),
+            Err(Error::CryptoFail)
+        );
+
+        // Only the start of longer buffers is written.
+        let mut out = [0xff; 40];
+        assert_eq!(derive_pkt_key(aead, &secret, &mut out), Ok(()));
+        assert_eq!(&out[32..], &[0xff; 8]);
+    }
+
     #[test]
     fn derive_chacha20_secrets() {
         let secret = [
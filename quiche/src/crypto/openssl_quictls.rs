@@ -294,11 +294,11 @@ fn make_evp_cipher_ctx_basic(
 
 pub(crate) struct PacketKey {
     ctx: *mut EVP_CIPHER_CTX,
-    nonce: Vec<u8>,
+    nonce: Secret,
     // Note: We'd need the key for later use as it is needed by the openssl API.
     // TODO: check if we can avoid this and get the key when needed and not
     // have it stored here.
-    key: Vec<u8>,
+    key: Secret,
 }
 
 impl PacketKey {
@@ -307,8 +307,8 @@ impl PacketKey {
     ) -> Result<Self> {
         Ok(Self {
             ctx: make_evp_cipher_ctx_basic(algo, enc)?,
-            nonce: iv,
-            key,
+            nonce: iv.into(),
+            key: key.into(),
         })
     }
 
@@ -326,6 +326,13 @@ impl PacketKey {
     }
 }
 
+impl Drop for PacketKey {
+    fn drop(&mut self) {
+        // This also wipes the cipher state.
+        unsafe { EVP_CIPHER_CTX_free(self.ctx) }
+    }
+}
+
 unsafe impl std::marker::Send for PacketKey {}
 unsafe impl std::marker::Sync for PacketKey {}
 
@@ -0,0 +1,732 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::HashMap;
+
+use crate::Error;
+use crate::Result;
+
+use crate::crypto;
+use crate::frame;
+use crate::packet;
+
+use crate::packet::ConnectionId;
+use crate::packet::Header;
+use crate::packet::Type;
+
+/// Index of the client in per-endpoint arrays.
+const CLIENT: usize = 0;
+
+/// Index of the server in per-endpoint arrays.
+const SERVER: usize = 1;
+
+/// A packet decrypted by a [`Decryptor`].
+///
+/// [`Decryptor`]: struct.Decryptor.html
+#[derive(Debug)]
+pub struct DecryptedPacket {
+    /// The packet's header.
+    pub hdr: Header<'static>,
+
+    /// The packet's full packet number.
+    pub pkt_num: u64,
+
+    /// Whether the packet was sent by the client.
+    pub from_client: bool,
+
+    /// The frames carried by the packet, formatted for display.
+    ///
+    /// This is empty for packets that don't carry frames, such as Retry and
+    /// Version Negotiation packets.
+    pub frames: Vec<String>,
+}
+
+/// Decrypts QUIC packets observed on the wire, e.g. in a packet capture.
+///
+/// The TLS secrets of each connection need to be provided in the [keylog]
+/// format, as written by [`set_keylog()`]. Initial packets can be decrypted
+/// without them.
+///
+/// Connections are tracked by their connection IDs, so datagrams need to be
+/// passed to [`decrypt()`] in the order they were observed, starting with the
+/// client's first Initial packet. The direction of each packet is inferred
+/// from its destination connection ID.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let keylog = "";
+/// # let mut datagrams: Vec<Vec<u8>> = Vec::new();
+/// let mut decryptor = quiche::Decryptor::new();
+/// decryptor.add_keylog(keylog);
+///
+/// for dgram in &datagrams {
+///     for pkt in decryptor.decrypt(dgram) {
+///         match pkt {
+///             Ok(pkt) => println!("{:?} {:?}", pkt.hdr, pkt.frames),
+///
+///             Err(e) => println!("undecryptable packet: {:?}", e),
+///         }
+///     }
+/// }
+/// ```
+///
+/// [keylog]: https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format
+/// [`set_keylog()`]: struct.Connection.html#method.set_keylog
+/// [`decrypt()`]: struct.Decryptor.html#method.decrypt
+#[derive(Default)]
+pub struct Decryptor {
+    /// TLS secrets, indexed by client random.
+    secrets: HashMap<Vec<u8>, Secrets>,
+
+    /// Connections seen so far.
+    conns: Vec<CapturedConnection>,
+}
+
+impl Decryptor {
+    /// Creates a decryptor without any secrets.
+    pub fn new() -> Decryptor {
+        Default::default()
+    }
+
+    /// Adds the secrets contained in `keylog`.
+    ///
+    /// Lines that don't hold TLS 1.3 traffic secrets are ignored.
+    pub fn add_keylog(&mut self, keylog: &str) {
+        for line in keylog.lines() {
+            let mut fields = line.split_whitespace();
+
+            let (label, random, secret) =
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(l), Some(r), Some(s)) => (l, r, s),
+
+                    _ => continue,
+                };
+
+            let (random, secret) = match (hex_decode(random), hex_decode(secret))
+            {
+                (Some(r), Some(s)) => (r, s),
+
+                _ => continue,
+            };
+
+            let secrets = self.secrets.entry(random).or_default();
+
+            match label {
+                "CLIENT_EARLY_TRAFFIC_SECRET" => secrets.early = Some(secret),
+
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET" =>
+                    secrets.handshake[CLIENT] = Some(secret),
+
+                "SERVER_HANDSHAKE_TRAFFIC_SECRET" =>
+                    secrets.handshake[SERVER] = Some(secret),
+
+                "CLIENT_TRAFFIC_SECRET_0" =>
+                    secrets.traffic[CLIENT] = Some(secret),
+
+                "SERVER_TRAFFIC_SECRET_0" =>
+                    secrets.traffic[SERVER] = Some(secret),
+
+                _ => (),
+            }
+        }
+    }
+
+    /// Decrypts the packets coalesced in the UDP payload `buf`.
+    ///
+    /// A result is returned for each packet, in order. Decryption stops at the
+    /// first packet that can't be decrypted, as the boundary of the following
+    /// packet is unknown.
+    pub fn decrypt(&mut self, buf: &[u8]) -> Vec<Result<DecryptedPacket>> {
+        let mut buf = buf.to_vec();
+        let mut off = 0;
+
+        let mut pkts = Vec::new();
+
+        while off < buf.len() {
+            // Datagrams carrying Initial packets may be padded with zeros
+            // after the last packet.
+            if buf[off..].iter().all(|&b| b == 0) {
+                break;
+            }
+
+            match self.decrypt_single(&mut buf[off..]) {
+                Ok((pkt, len)) => {
+                    pkts.push(Ok(pkt));
+                    off += len;
+                },
+
+                Err(e) => {
+                    pkts.push(Err(e));
+                    break;
+                },
+            }
+        }
+
+        pkts
+    }
+
+    fn decrypt_single(
+        &mut self, buf: &mut [u8],
+    ) -> Result<(DecryptedPacket, usize)> {
+        let buf_len = buf.len();
+
+        let first = *buf.first().ok_or(Error::BufferTooShort)?;
+
+        let (idx, sender, mut hdr, pn_off) = if Header::is_long(first) {
+            let mut b = octets::OctetsMut::with_slice(buf);
+            let hdr: Header<'static> = Header::from_bytes(&mut b, 0)?;
+            let pn_off = b.off();
+
+            let (idx, sender) = match self.find_long(&hdr) {
+                Some(v) => v,
+
+                // A client Initial packet for an unknown destination starts a
+                // new connection.
+                None if hdr.ty == Type::Initial => {
+                    self.conns.push(CapturedConnection::new(&hdr)?);
+
+                    (self.conns.len() - 1, CLIENT)
+                },
+
+                None => return Err(Error::InvalidPacket),
+            };
+
+            (idx, sender, hdr, pn_off)
+        } else {
+            let (idx, sender, dcid_len) =
+                self.find_short(&buf[1..]).ok_or(Error::InvalidPacket)?;
+
+            let mut b = octets::OctetsMut::with_slice(buf);
+            let hdr: Header<'static> = Header::from_bytes(&mut b, dcid_len)?;
+            let pn_off = b.off();
+
+            (idx, sender, hdr, pn_off)
+        };
+
+        let secrets = &self.secrets;
+        let conn = &mut self.conns[idx];
+
+        if !hdr.scid.is_empty() && !conn.cids[sender].contains(&hdr.scid) {
+            conn.cids[sender].push(hdr.scid.clone());
+        }
+
+        match hdr.ty {
+            Type::VersionNegotiation =>
+                return Ok((DecryptedPacket::new(hdr, sender, vec![]), buf_len)),
+
+            Type::Retry => {
+                // The client starts using the new connection ID to derive
+                // Initial keys.
+                conn.initial = initial_keys(&hdr.scid, conn.version)?;
+
+                return Ok((DecryptedPacket::new(hdr, sender, vec![]), buf_len));
+            },
+
+            _ => (),
+        }
+
+        // Long header packets have an explicit payload length, but short
+        // packets don't so just use the remaining space in the buffer.
+        let (pn_off, payload_len) = if hdr.ty == Type::Short {
+            (pn_off, buf_len - pn_off)
+        } else {
+            let mut b = octets::OctetsMut::with_slice(buf);
+            b.skip(pn_off)?;
+
+            let payload_len = b.get_varint()? as usize;
+
+            if payload_len > b.cap() {
+                return Err(Error::InvalidPacket);
+            }
+
+            (b.off(), payload_len)
+        };
+
+        let pkt_len = pn_off + payload_len;
+        let pkt = &buf[..pkt_len];
+
+        let secrets = conn
+            .client_random
+            .as_ref()
+            .and_then(|random| secrets.get(random));
+
+        if let Some(secrets) = secrets {
+            conn.derive_keys(secrets);
+        }
+
+        let epoch = hdr.ty.to_epoch()?;
+        let largest_pn = conn.largest_pn[sender][epoch as usize];
+
+        let (pn, payload) = match hdr.ty {
+            Type::Initial => open_pkt(
+                pkt,
+                pn_off,
+                largest_pn,
+                &mut hdr,
+                &conn.initial[sender],
+            )?,
+
+            Type::Handshake => {
+                let aead =
+                    conn.handshake[sender].as_ref().ok_or(Error::CryptoFail)?;
+
+                open_pkt(pkt, pn_off, largest_pn, &mut hdr, aead)?
+            },
+
+            Type::ZeroRTT => {
+                let secret = secrets
+                    .and_then(|secrets| secrets.early.as_ref())
+                    .ok_or(Error::CryptoFail)?;
+
+                // The 0-RTT cipher suite is the one of the resumed session,
+                // which isn't visible on the wire, so try all of them.
+                [
+                    crypto::Algorithm::AES128_GCM,
+                    crypto::Algorithm::AES256_GCM,
+                    crypto::Algorithm::ChaCha20_Poly1305,
+                ]
+                .iter()
+                .filter_map(|alg| {
+                    crypto::Open::from_secret(*alg, secret.clone()).ok()
+                })
+                .find_map(|aead| {
+                    open_pkt(pkt, pn_off, largest_pn, &mut hdr, &aead).ok()
+                })
+                .ok_or(Error::CryptoFail)?
+            },
+
+            Type::Short => {
+                let keys =
+                    conn.traffic[sender].as_mut().ok_or(Error::CryptoFail)?;
+
+                match open_pkt(pkt, pn_off, largest_pn, &mut hdr, &keys.aead) {
+                    Ok(v) => v,
+
+                    // The key phase changed, so try with the next keys.
+                    Err(_) if hdr.key_phase != keys.key_phase => {
+                        let next = keys.aead.derive_next_packet_key()?;
+
+                        let v =
+                            open_pkt(pkt, pn_off, largest_pn, &mut hdr, &next)?;
+
+                        keys.aead = next;
+                        keys.key_phase = hdr.key_phase;
+
+                        v
+                    },
+
+                    Err(e) => return Err(e),
+                }
+            },
+
+            _ => return Err(Error::InvalidPacket),
+        };
+
+        hdr.pkt_num = pn;
+
+        let largest_pn = &mut conn.largest_pn[sender][epoch as usize];
+        *largest_pn = (*largest_pn).max(pn);
+
+        let mut b = octets::Octets::with_slice(&payload);
+        let mut frames = Vec::new();
+
+        while b.cap() > 0 {
+            let frame = frame::Frame::from_bytes(&mut b, hdr.ty)?;
+
+            match &frame {
+                frame::Frame::Crypto { data } if data.off() == 0 =>
+                    conn.on_hello(data, sender),
+
+                frame::Frame::NewConnectionId { conn_id, .. } =>
+                    conn.cids[sender].push(conn_id.clone().into()),
+
+                _ => (),
+            }
+
+            frames.push(format!("{frame:?}"));
+        }
+
+        Ok((DecryptedPacket::new(hdr, sender, frames), pkt_len))
+    }
+
+    /// Returns the connection a long header packet belongs to, and the index
+    /// of its sender.
+    fn find_long(&self, hdr: &Header<'static>) -> Option<(usize, usize)> {
+        self.conns.iter().enumerate().find_map(|(idx, conn)| {
+            if conn.cids[SERVER].contains(&hdr.dcid) {
+                return Some((idx, CLIENT));
+            }
+
+            if conn.cids[CLIENT].contains(&hdr.dcid) {
+                return Some((idx, SERVER));
+            }
+
+            None
+        })
+    }
+
+    /// Returns the connection a short header packet belongs to, the index of
+    /// its sender and the length of its destination connection ID.
+    fn find_short(&self, buf: &[u8]) -> Option<(usize, usize, usize)> {
+        self.conns.iter().enumerate().find_map(|(idx, conn)| {
+            [CLIENT, SERVER].iter().find_map(|&sender| {
+                conn.cids[1 - sender]
+                    .iter()
+                    .find(|cid| buf.starts_with(cid))
+                    .map(|cid| (idx, sender, cid.len()))
+            })
+        })
+    }
+}
+
+impl DecryptedPacket {
+    fn new(hdr: Header<'static>, sender: usize, frames: Vec<String>) -> Self {
+        DecryptedPacket {
+            pkt_num: hdr.pkt_num,
+            hdr,
+            from_client: sender == CLIENT,
+            frames,
+        }
+    }
+}
+
+/// TLS secrets of a connection, as found in a keylog.
+#[derive(Default)]
+struct Secrets {
+    early: Option<Vec<u8>>,
+
+    handshake: [Option<Vec<u8>>; 2],
+
+    traffic: [Option<Vec<u8>>; 2],
+}
+
+/// 1-RTT keys of one endpoint.
+struct TrafficKeys {
+    aead: crypto::Open,
+
+    key_phase: bool,
+}
+
+/// Decryption state of a connection seen by a [`Decryptor`].
+struct CapturedConnection {
+    version: u32,
+
+    /// Connection IDs chosen by each endpoint.
+    cids: [Vec<ConnectionId<'static>>; 2],
+
+    /// Random from the ClientHello, used to look up secrets.
+    client_random: Option<Vec<u8>>,
+
+    /// Cipher suite negotiated by the ServerHello.
+    alg: Option<crypto::Algorithm>,
+
+    initial: [crypto::Open; 2],
+
+    handshake: [Option<crypto::Open>; 2],
+
+    traffic: [Option<TrafficKeys>; 2],
+
+    largest_pn: [[u64; packet::Epoch::count()]; 2],
+}
+
+impl CapturedConnection {
+    fn new(hdr: &Header) -> Result<Self> {
+        let client_cid = hdr.scid.clone().into_owned();
+        let server_cid = hdr.dcid.clone().into_owned();
+
+        Ok(CapturedConnection {
+            version: hdr.version,
+
+            cids: [vec![client_cid], vec![server_cid]],
+
+            client_random: None,
+
+            alg: None,
+
+            initial: initial_keys(&hdr.dcid, hdr.version)?,
+
+            handshake: [None, None],
+
+            traffic: [None, None],
+
+            largest_pn: [[0; packet::Epoch::count()]; 2],
+        })
+    }
+
+    /// Extracts the client random or the cipher suite from the first CRYPTO
+    /// frame sent by the endpoint.
+    fn on_hello(&mut self, data: &[u8], sender: usize) {
+        // Handshake type (1) + length (3) + legacy_version (2).
+        const RANDOM_OFF: usize = 6;
+        const RANDOM_LEN: usize = 32;
+
+        if sender == CLIENT {
+            if data.first() == Some(&0x01) &&
+                data.len() >= RANDOM_OFF + RANDOM_LEN
+            {
+                self.client_random =
+                    Some(data[RANDOM_OFF..RANDOM_OFF + RANDOM_LEN].to_vec());
+            }
+
+            return;
+        }
+
+        if data.first() != Some(&0x02) {
+            return;
+        }
+
+        // The cipher suite follows the legacy_session_id_echo field.
+        let suite_off = match data.get(RANDOM_OFF + RANDOM_LEN) {
+            Some(&sid_len) => RANDOM_OFF + RANDOM_LEN + 1 + usize::from(sid_len),
+
+            None => return,
+        };
+
+        self.alg = match data.get(suite_off..suite_off + 2) {
+            Some([0x13, 0x01]) => Some(crypto::Algorithm::AES128_GCM),
+            Some([0x13, 0x02]) => Some(crypto::Algorithm::AES256_GCM),
+            Some([0x13, 0x03]) => Some(crypto::Algorithm::ChaCha20_Poly1305),
+            _ => None,
+        };
+    }
+
+    /// Derives the Handshake and 1-RTT keys that have become available.
+    fn derive_keys(&mut self, secrets: &Secrets) {
+        let alg = match self.alg {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        for sender in [CLIENT, SERVER] {
+            if self.handshake[sender].is_none() {
+                self.handshake[sender] = secrets.handshake[sender]
+                    .as_ref()
+                    .and_then(|s| crypto::Open::from_secret(alg, s.clone()).ok());
+            }
+
+            if self.traffic[sender].is_none() {
+                self.traffic[sender] = secrets.traffic[sender]
+                    .as_ref()
+                    .and_then(|s| crypto::Open::from_secret(alg, s.clone()).ok())
+                    .map(|aead| TrafficKeys {
+                        aead,
+                        key_phase: false,
+                    });
+            }
+        }
+    }
+}
+
+/// Derives the Initial keys used by the client and the server respectively.
+fn initial_keys(dcid: &[u8], version: u32) -> Result<[crypto::Open; 2]> {
+    // The opening key of an endpoint is the sealing key of its peer.
+    let (client, _) = crypto::derive_initial_key_material(dcid, version, true)?;
+    let (server, _) = crypto::derive_initial_key_material(dcid, version, false)?;
+
+    Ok([client, server])
+}
+
+/// Removes header protection and decrypts the payload of `pkt`, whose packet
+/// number starts at `pn_off`.
+///
+/// The input is left untouched, so decryption can be retried with other keys.
+fn open_pkt(
+    pkt: &[u8], pn_off: usize, largest_pn: u64, hdr: &mut Header,
+    aead: &crypto::Open,
+) -> Result<(u64, Vec<u8>)> {
+    let mut pkt = pkt.to_vec();
+    let payload_len = pkt.len() - pn_off;
+
+    let mut b = octets::OctetsMut::with_slice(&mut pkt);
+    b.skip(pn_off)?;
+
+    packet::decrypt_hdr(&mut b, hdr, aead)?;
+
+    let pn = packet::decode_pkt_num(largest_pn, hdr.pkt_num, hdr.pkt_num_len);
+
+    let payload =
+        packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len, payload_len, aead)?;
+
+    Ok((pn, payload.to_vec()))
+}
+
+pub(crate) fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Keylog(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Keylog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs a handshake and a request, returning the datagrams exchanged and
+    /// the client's keylog.
+    fn capture() -> (Vec<Vec<u8>>, String) {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.log_keys();
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        let keylog = Keylog::default();
+        pipe.client.set_keylog(Box::new(keylog.clone()));
+
+        let mut dgrams = Vec::new();
+
+        let mut exchange = |pipe: &mut testing::Pipe| loop {
+            let client_flight = testing::emit_flight(&mut pipe.client);
+            if let Ok(flight) = &client_flight {
+                dgrams.extend(flight.iter().map(|(d, _)| d.clone()));
+                testing::process_flight(&mut pipe.server, flight.clone())
+                    .unwrap();
+            }
+
+            let server_flight = testing::emit_flight(&mut pipe.server);
+            if let Ok(flight) = &server_flight {
+                dgrams.extend(flight.iter().map(|(d, _)| d.clone()));
+                testing::process_flight(&mut pipe.client, flight.clone())
+                    .unwrap();
+            }
+
+            if client_flight.is_err() && server_flight.is_err() {
+                break;
+            }
+        };
+
+        exchange(&mut pipe);
+        assert!(pipe.client.is_established());
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        exchange(&mut pipe);
+
+        let keylog = String::from_utf8(keylog.0.lock().unwrap().clone());
+
+        (dgrams, keylog.unwrap())
+    }
+
+    #[test]
+    fn decrypt_capture() {
+        let (dgrams, keylog) = capture();
+
+        let mut decryptor = Decryptor::new();
+        decryptor.add_keylog(&keylog);
+
+        let pkts: Vec<DecryptedPacket> = dgrams
+            .iter()
+            .flat_map(|dgram| decryptor.decrypt(dgram))
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let first = &pkts[0];
+        assert_eq!(first.hdr.ty, Type::Initial);
+        assert!(first.from_client);
+        assert_eq!(first.pkt_num, 0);
+        assert!(first.frames[0].starts_with("CRYPTO off=0"));
+
+        for ty in [Type::Initial, Type::Handshake] {
+            assert!(pkts.iter().any(|p| p.hdr.ty == ty && !p.from_client));
+        }
+
+        let request = pkts
+            .iter()
+            .find(|p| {
+                p.frames
+                    .iter()
+                    .any(|f| f.starts_with("STREAM id=4 off=0 len=5 fin=true"))
+            })
+            .unwrap();
+        assert_eq!(request.hdr.ty, Type::Short);
+        assert!(request.from_client);
+
+        assert!(pkts
+            .iter()
+            .any(|p| p.frames.iter().any(|f| f.starts_with("HANDSHAKE_DONE"))));
+    }
+
+    #[test]
+    fn decrypt_capture_without_secrets() {
+        let (dgrams, _) = capture();
+
+        let mut decryptor = Decryptor::new();
+
+        let pkts: Vec<Result<DecryptedPacket>> = dgrams
+            .iter()
+            .flat_map(|dgram| decryptor.decrypt(dgram))
+            .collect();
+
+        // Initial packets don't need secrets.
+        assert!(pkts[0].is_ok());
+
+        assert!(pkts
+            .iter()
+            .any(|p| p.as_ref().err() == Some(&Error::CryptoFail)));
+        assert!(pkts.iter().all(|p| match p {
+            Ok(p) => p.hdr.ty == Type::Initial,
+
+            Err(_) => true,
+        }));
+    }
+}
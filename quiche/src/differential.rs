@@ -0,0 +1,404 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Differential testing of the wire format against other implementations.
+//!
+//! Test vectors describe how a reference implementation parses the frames in
+//! a packet payload, so that the same inputs can be replayed against this
+//! crate and any divergence reported. Vectors are exchanged as plain text, one
+//! per line:
+//!
+//! ```text
+//! <name> <initial|handshake|0rtt|1rtt> <hex payload> ok [<type>,...]
+//! <name> <initial|handshake|0rtt|1rtt> <hex payload> err <type> <error code>
+//! ```
+//!
+//! Accepted payloads list the type of every frame in order, with consecutive
+//! PADDING bytes counted as a single frame. Rejected payloads record the type
+//! of the offending frame and the transport error code that would be sent in
+//! the resulting CONNECTION_CLOSE frame. Numbers are written in hexadecimal
+//! with a `0x` prefix, and empty lines and lines starting with `#` are
+//! ignored.
+
+use std::fmt;
+
+use crate::Error;
+use crate::Result;
+
+use crate::decrypt::hex_decode;
+use crate::frame;
+use crate::packet;
+
+/// The result of parsing a packet payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The payload was accepted, and contained frames of the given types.
+    Accepted(Vec<u64>),
+
+    /// The payload was rejected.
+    Rejected {
+        /// The type of the frame that caused the payload to be rejected.
+        frame_type: u64,
+
+        /// The transport error code sent to the peer.
+        error_code: u64,
+    },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Accepted(types) => {
+                write!(f, "ok")?;
+
+                for (i, ty) in types.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { "," };
+
+                    write!(f, "{}{:#x}", sep, ty)?;
+                }
+
+                Ok(())
+            },
+
+            Outcome::Rejected {
+                frame_type,
+                error_code,
+            } => write!(f, "err {:#x} {:#x}", frame_type, error_code),
+        }
+    }
+}
+
+/// A test vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vector {
+    /// The name of the vector, used when reporting divergences.
+    pub name: String,
+
+    /// The type of the packet carrying the payload.
+    pub pkt_type: packet::Type,
+
+    /// The decrypted packet payload.
+    pub payload: Vec<u8>,
+
+    /// The outcome observed with the reference implementation.
+    pub expected: Outcome,
+}
+
+impl Vector {
+    /// Creates a vector recording how this crate parses `payload`.
+    ///
+    /// This can be used to export inputs, e.g. from a fuzzing corpus, for
+    /// replaying against other implementations.
+    pub fn new(name: &str, pkt_type: packet::Type, payload: &[u8]) -> Vector {
+        Vector {
+            name: name.to_string(),
+            pkt_type,
+            payload: payload.to_vec(),
+            expected: parse_payload(payload, pkt_type),
+        }
+    }
+
+    /// Parses the vector's payload and compares the outcome with the expected
+    /// one.
+    pub fn check(&self) -> Option<Divergence> {
+        let actual = parse_payload(&self.payload, self.pkt_type);
+
+        if actual == self.expected {
+            return None;
+        }
+
+        Some(Divergence {
+            name: self.name.clone(),
+            expected: self.expected.clone(),
+            actual,
+        })
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pkt_type = match self.pkt_type {
+            packet::Type::Initial => "initial",
+
+            packet::Type::Handshake => "handshake",
+
+            packet::Type::ZeroRTT => "0rtt",
+
+            _ => "1rtt",
+        };
+
+        write!(f, "{} {} ", self.name, pkt_type)?;
+
+        for b in &self.payload {
+            write!(f, "{:02x}", b)?;
+        }
+
+        write!(f, " {}", self.expected)
+    }
+}
+
+/// A vector for which this crate's outcome differs from the reference one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The name of the vector.
+    pub name: String,
+
+    /// The outcome observed with the reference implementation.
+    pub expected: Outcome,
+
+    /// The outcome observed with this crate.
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected \"{}\", got \"{}\"",
+            self.name, self.expected, self.actual
+        )
+    }
+}
+
+/// Parses the frames in a packet payload the same way received packets are.
+pub fn parse_payload(payload: &[u8], pkt_type: packet::Type) -> Outcome {
+    let mut b = octets::Octets::with_slice(payload);
+
+    let mut types = Vec::new();
+
+    while b.cap() > 0 {
+        let frame_type = octets::Octets::with_slice(b.as_ref())
+            .get_varint()
+            .unwrap_or(0);
+
+        if let Err(e) = frame::Frame::from_bytes(&mut b, pkt_type) {
+            // Truncated frames are badly formatted, like on the receive path.
+            let e = match e {
+                Error::BufferTooShort => Error::InvalidFrame,
+
+                e => e,
+            };
+
+            return Outcome::Rejected {
+                frame_type,
+                error_code: e.to_wire(),
+            };
+        }
+
+        types.push(frame_type);
+    }
+
+    Outcome::Accepted(types)
+}
+
+/// Parses test vectors in the text format described in the [module docs].
+///
+/// [module docs]: index.html
+pub fn parse_vectors(text: &str) -> Result<Vec<Vector>> {
+    let mut vectors = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+
+        let name = fields.next().ok_or(Error::InvalidState)?;
+
+        let pkt_type = match fields.next() {
+            Some("initial") => packet::Type::Initial,
+
+            Some("handshake") => packet::Type::Handshake,
+
+            Some("0rtt") => packet::Type::ZeroRTT,
+
+            Some("1rtt") => packet::Type::Short,
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        let payload = fields
+            .next()
+            .and_then(hex_decode)
+            .ok_or(Error::InvalidState)?;
+
+        let expected = match fields.next() {
+            Some("ok") => {
+                let types = match fields.next() {
+                    Some(v) => v
+                        .split(',')
+                        .map(parse_hex_u64)
+                        .collect::<Result<Vec<_>>>()?,
+
+                    None => Vec::new(),
+                };
+
+                Outcome::Accepted(types)
+            },
+
+            Some("err") => Outcome::Rejected {
+                frame_type: parse_hex_u64(
+                    fields.next().ok_or(Error::InvalidState)?,
+                )?,
+                error_code: parse_hex_u64(
+                    fields.next().ok_or(Error::InvalidState)?,
+                )?,
+            },
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        if fields.next().is_some() {
+            return Err(Error::InvalidState);
+        }
+
+        vectors.push(Vector {
+            name: name.to_string(),
+            pkt_type,
+            payload,
+            expected,
+        });
+    }
+
+    Ok(vectors)
+}
+
+/// Checks all the given vectors, returning the ones that diverge.
+pub fn check_vectors(vectors: &[Vector]) -> Vec<Divergence> {
+    vectors.iter().filter_map(Vector::check).collect()
+}
+
+fn parse_hex_u64(s: &str) -> Result<u64> {
+    let s = s.strip_prefix("0x").ok_or(Error::InvalidState)?;
+
+    u64::from_str_radix(s, 16).map_err(|_| Error::InvalidState)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vectors agreed upon by other implementations, following RFC 9000.
+    const VECTORS: &str = "
+        # Accepted payloads.
+        ping                 1rtt      01                 ok 0x1
+        padding_ping         initial   00000001           ok 0x0,0x1
+        max_data             1rtt      104064             ok 0x10
+        ack_crypto           handshake 0200000000060000   ok 0x2,0x6
+        handshake_done       1rtt      1e                 ok 0x1e
+        stream_fin           0rtt      0f080040026869     ok 0xf
+
+        # Rejected payloads.
+        truncated_reset      1rtt      0404               err 0x4 0x7
+        empty_new_token      1rtt      0700               err 0x7 0x7
+        unknown_type         1rtt      01210000           err 0x21 0x7
+        non_minimal_type     1rtt      4001               err 0x1 0x7
+        stream_in_initial    initial   0800               err 0x8 0xa
+        done_in_handshake    handshake 1e                 err 0x1e 0xa
+        new_token_in_0rtt    0rtt      070101             err 0x7 0xa
+    ";
+
+    #[test]
+    fn reference_vectors() {
+        let vectors = parse_vectors(VECTORS).unwrap();
+        assert_eq!(vectors.len(), 13);
+
+        let divergences = check_vectors(&vectors);
+        assert!(divergences.is_empty(), "{:?}", divergences);
+    }
+
+    #[test]
+    fn external_vectors() {
+        // Vectors exported from another implementation can be checked by
+        // pointing this variable to the file holding them.
+        let path = match std::env::var("QUICHE_DIFF_VECTORS") {
+            Ok(v) => v,
+
+            Err(_) => return,
+        };
+
+        let text = std::fs::read_to_string(path).unwrap();
+        let vectors = parse_vectors(&text).unwrap();
+
+        let divergences = check_vectors(&vectors);
+
+        for d in &divergences {
+            eprintln!("{}", d);
+        }
+
+        assert!(divergences.is_empty());
+    }
+
+    #[test]
+    fn vector_round_trip() {
+        let vectors = parse_vectors(VECTORS).unwrap();
+
+        for v in &vectors {
+            let line = v.to_string();
+            assert_eq!(parse_vectors(&line), Ok(vec![v.clone()]));
+        }
+
+        let v = Vector::new("exported", packet::Type::Short, &[0x01, 0x21]);
+        assert_eq!(v.expected, Outcome::Rejected {
+            frame_type: 0x21,
+            error_code: 0x7,
+        });
+        assert_eq!(v.to_string(), "exported 1rtt 0121 err 0x21 0x7");
+        assert_eq!(v.check(), None);
+    }
+
+    #[test]
+    fn divergence() {
+        let vectors = parse_vectors("lenient_token 1rtt 0700 ok 0x7\n").unwrap();
+
+        assert_eq!(check_vectors(&vectors), vec![Divergence {
+            name: "lenient_token".to_string(),
+            expected: Outcome::Accepted(vec![0x7]),
+            actual: Outcome::Rejected {
+                frame_type: 0x7,
+                error_code: 0x7,
+            },
+        }]);
+
+        assert_eq!(
+            check_vectors(&vectors)[0].to_string(),
+            "lenient_token: expected \"ok 0x7\", got \"err 0x7 0x7\""
+        );
+    }
+
+    #[test]
+    fn invalid_vectors() {
+        assert!(parse_vectors("ping 1rtt 01").is_err());
+        assert!(parse_vectors("ping 2rtt 01 ok 0x1").is_err());
+        assert!(parse_vectors("ping 1rtt 0g ok 0x1").is_err());
+        assert!(parse_vectors("ping 1rtt 01 ok 1").is_err());
+        assert!(parse_vectors("ping 1rtt 01 err 0x1").is_err());
+        assert!(parse_vectors("ping 1rtt 01 ok 0x1 extra").is_err());
+    }
+}
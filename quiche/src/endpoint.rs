@@ -0,0 +1,297 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::time;
+
+use crate::Connection;
+use crate::ConnectionRace;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+
+/// A QUIC endpoint driven by the application's event loop.
+///
+/// Types implementing this trait, such as [`Connection`] and
+/// [`ConnectionRace`], don't perform any I/O themselves. Instead the
+/// application's event loop drives them as follows:
+///
+/// 1. Each datagram received from the network is passed to
+///    [`handle_datagram()`].
+///
+/// 2. [`poll_transmit()`] is called until it returns [`Done`], and each
+///    datagram it returns is sent.
+///
+/// 3. The loop waits for the next datagram, for at most the duration returned
+///    by [`poll_timeout()`]. If that elapses, [`handle_timeout()`] is called.
+///
+/// Code written against this trait can drive any of the endpoints, as well
+/// as mock endpoints in application tests.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// use quiche::QuicEndpoint;
+///
+/// fn run<E: QuicEndpoint>(
+///     endpoint: &mut E, socket: &std::net::UdpSocket,
+/// ) -> quiche::Result<()> {
+///     let mut buf = [0; 65535];
+///     let mut out = [0; 1350];
+///
+///     let local = socket.local_addr().unwrap();
+///
+///     loop {
+///         loop {
+///             let (write, send_info) = match endpoint.poll_transmit(&mut out) {
+///                 Ok(v) => v,
+///
+///                 Err(quiche::Error::Done) => break,
+///
+///                 Err(e) => return Err(e),
+///             };
+///
+///             socket.send_to(&out[..write], send_info.to).unwrap();
+///         }
+///
+///         match endpoint.poll_timeout() {
+///             Some(timeout) if timeout.is_zero() => {
+///                 endpoint.handle_timeout();
+///                 continue;
+///             },
+///
+///             timeout => socket.set_read_timeout(timeout).unwrap(),
+///         }
+///
+///         match socket.recv_from(&mut buf) {
+///             Ok((len, from)) => {
+///                 let info = quiche::RecvInfo { from, to: local };
+///
+///                 endpoint.handle_datagram(&mut buf[..len], info)?;
+///             },
+///
+///             Err(_) => endpoint.handle_timeout(),
+///         }
+///     }
+/// }
+/// ```
+///
+/// [`Connection`]: struct.Connection.html
+/// [`ConnectionRace`]: struct.ConnectionRace.html
+/// [`handle_datagram()`]: trait.QuicEndpoint.html#tymethod.handle_datagram
+/// [`poll_transmit()`]: trait.QuicEndpoint.html#tymethod.poll_transmit
+/// [`poll_timeout()`]: trait.QuicEndpoint.html#tymethod.poll_timeout
+/// [`handle_timeout()`]: trait.QuicEndpoint.html#tymethod.handle_timeout
+/// [`Done`]: enum.Error.html#variant.Done
+pub trait QuicEndpoint {
+    /// Processes a datagram received from the network.
+    ///
+    /// On success the number of bytes processed is returned.
+    fn handle_datagram(
+        &mut self, buf: &mut [u8], info: RecvInfo,
+    ) -> Result<usize>;
+
+    /// Writes a datagram to be sent to the network into `out`.
+    ///
+    /// On success the number of bytes written is returned, along with the
+    /// addresses the datagram must be sent from and to. [`Done`] is returned
+    /// when there is nothing to send.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    fn poll_transmit(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)>;
+
+    /// Returns the amount of time until [`handle_timeout()`] must be called,
+    /// or `None` if there is no timer armed.
+    ///
+    /// [`handle_timeout()`]: trait.QuicEndpoint.html#tymethod.handle_timeout
+    fn poll_timeout(&self) -> Option<time::Duration>;
+
+    /// Processes the expiration of the timer.
+    fn handle_timeout(&mut self);
+}
+
+impl QuicEndpoint for Connection {
+    fn handle_datagram(
+        &mut self, buf: &mut [u8], info: RecvInfo,
+    ) -> Result<usize> {
+        self.recv(buf, info)
+    }
+
+    fn poll_transmit(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        self.send(out)
+    }
+
+    fn poll_timeout(&self) -> Option<time::Duration> {
+        self.timeout()
+    }
+
+    fn handle_timeout(&mut self) {
+        self.on_timeout()
+    }
+}
+
+impl QuicEndpoint for ConnectionRace {
+    fn handle_datagram(
+        &mut self, buf: &mut [u8], info: RecvInfo,
+    ) -> Result<usize> {
+        self.recv(buf, info)
+    }
+
+    fn poll_transmit(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        self.send(out)
+    }
+
+    fn poll_timeout(&self) -> Option<time::Duration> {
+        self.timeout()
+    }
+
+    fn handle_timeout(&mut self) {
+        self.on_timeout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::testing;
+    use crate::Config;
+    use crate::Error;
+
+    /// Moves all pending datagrams from `from` to `to`, returning how many
+    /// were moved.
+    fn flush(from: &mut dyn QuicEndpoint, to: &mut dyn QuicEndpoint) -> usize {
+        let mut out = [0; 65535];
+        let mut count = 0;
+
+        loop {
+            let (len, info) = match from.poll_transmit(&mut out) {
+                Ok(v) => v,
+
+                Err(Error::Done) => break,
+
+                Err(e) => panic!("poll_transmit failed: {:?}", e),
+            };
+
+            let info = RecvInfo {
+                from: info.from,
+                to: info.to,
+            };
+
+            assert_eq!(to.handle_datagram(&mut out[..len], info), Ok(len));
+
+            count += 1;
+        }
+
+        count
+    }
+
+    /// An endpoint that silently drops everything it receives.
+    #[derive(Default)]
+    struct Blackhole {
+        received: usize,
+    }
+
+    impl QuicEndpoint for Blackhole {
+        fn handle_datagram(
+            &mut self, buf: &mut [u8], _info: RecvInfo,
+        ) -> Result<usize> {
+            self.received += 1;
+
+            Ok(buf.len())
+        }
+
+        fn poll_transmit(
+            &mut self, _out: &mut [u8],
+        ) -> Result<(usize, SendInfo)> {
+            Err(Error::Done)
+        }
+
+        fn poll_timeout(&self) -> Option<time::Duration> {
+            None
+        }
+
+        fn handle_timeout(&mut self) {}
+    }
+
+    #[test]
+    fn connection_handshake() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        loop {
+            let sent = flush(&mut pipe.client, &mut pipe.server) +
+                flush(&mut pipe.server, &mut pipe.client);
+
+            if sent == 0 {
+                break;
+            }
+        }
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+
+        assert!(QuicEndpoint::poll_timeout(&pipe.client).is_some());
+    }
+
+    #[test]
+    fn mock_endpoint() {
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_clock(clock.clone());
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        let mut server = Blackhole::default();
+
+        assert!(flush(&mut pipe.client, &mut server) > 0);
+        assert_eq!(flush(&mut pipe.client, &mut server), 0);
+
+        let received = server.received;
+
+        // The client retransmits once its timer fires, as the mock server
+        // never replies.
+        let timeout = QuicEndpoint::poll_timeout(&pipe.client).unwrap();
+        clock.advance(timeout);
+        QuicEndpoint::handle_timeout(&mut pipe.client);
+
+        assert!(flush(&mut pipe.client, &mut server) > 0);
+        assert!(server.received > received);
+
+        assert!(!pipe.client.is_established());
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::Result;
+
+/// Handles extension frames that aren't natively supported.
+///
+/// QUIC frames don't carry an explicit length, so a frame with an unknown type
+/// can't be skipped without knowing how it is laid out. Applications that
+/// want to exchange frames defined by a QUIC extension can implement this
+/// trait and register it for the extension's frame types with
+/// [`register_extension_frame()`].
+///
+/// Extension frames are ack-eliciting. Their bodies can be sent in 1-RTT
+/// packets with [`extension_frame_send()`], and received ones can be retrieved
+/// with [`extension_frame_recv()`].
+///
+/// [`register_extension_frame()`]:
+/// struct.Config.html#method.register_extension_frame
+/// [`extension_frame_send()`]:
+/// struct.Connection.html#method.extension_frame_send
+/// [`extension_frame_recv()`]:
+/// struct.Connection.html#method.extension_frame_recv
+pub trait ExtensionFrameHandler: Send + Sync {
+    /// Returns the length of the body of a frame of the given type.
+    ///
+    /// The `buf` argument holds the remainder of the packet payload, starting
+    /// right after the frame type. If the frame is badly formatted
+    /// [`InvalidFrame`] should be returned, which closes the connection with
+    /// a `FRAME_ENCODING_ERROR`.
+    ///
+    /// [`InvalidFrame`]: enum.Error.html#variant.InvalidFrame
+    fn frame_len(&self, frame_type: u64, buf: &[u8]) -> Result<usize>;
+
+    /// Returns whether a frame of the given type should be sent again when the
+    /// packet carrying it is declared lost.
+    ///
+    /// The `data` argument holds the body of the lost frame. Like DATAGRAM
+    /// frames, extension frames are not retransmitted by default.
+    fn retransmit(&self, _frame_type: u64, _data: &[u8]) -> bool {
+        false
+    }
+}
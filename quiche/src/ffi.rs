@@ -139,6 +139,11 @@ pub extern fn quiche_enable_debug_logging(
     0
 }
 
+#[no_mangle]
+pub extern fn quiche_set_log_payloads(v: bool) {
+    set_log_payloads(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_new(version: u32) -> *mut Config {
     match Config::new(version) {
@@ -174,6 +179,45 @@ pub extern fn quiche_config_load_priv_key_from_pem_file(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_config_load_cert_chain_from_pem(
+    config: &mut Config, pem: *const u8, pem_len: size_t,
+) -> c_int {
+    let pem = unsafe { slice::from_raw_parts(pem, pem_len) };
+
+    match config.load_cert_chain_from_pem(pem) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_load_priv_key_from_pem(
+    config: &mut Config, pem: *const u8, pem_len: size_t,
+) -> c_int {
+    let pem = unsafe { slice::from_raw_parts(pem, pem_len) };
+
+    match config.load_priv_key_from_pem(pem) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_load_priv_key_from_der(
+    config: &mut Config, der: *const u8, der_len: size_t,
+) -> c_int {
+    let der = unsafe { slice::from_raw_parts(der, der_len) };
+
+    match config.load_priv_key_from_der(der) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_load_verify_locations_from_file(
     config: &mut Config, path: *const c_char,
@@ -210,6 +254,13 @@ pub extern fn quiche_config_grease(config: &mut Config, v: bool) {
     config.grease(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_spin_bit_mode(
+    config: &mut Config, mode: SpinBitMode,
+) {
+    config.set_spin_bit_mode(mode);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_discover_pmtu(config: &mut Config, v: bool) {
     config.discover_pmtu(v);
@@ -252,6 +303,11 @@ pub extern fn quiche_config_set_max_idle_timeout(config: &mut Config, v: u64) {
     config.set_max_idle_timeout(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_handshake_timeout(config: &mut Config, v: u64) {
+    config.set_handshake_timeout(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_max_recv_udp_payload_size(
     config: &mut Config, v: size_t,
@@ -342,6 +398,39 @@ pub extern fn quiche_config_set_initial_congestion_window_packets(
     config.set_initial_congestion_window_packets(packets);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_minimum_congestion_window_packets(
+    config: &mut Config, packets: size_t,
+) -> c_int {
+    match config.set_minimum_congestion_window_packets(packets) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_packet_reordering_threshold(
+    config: &mut Config, v: u64,
+) -> c_int {
+    match config.set_packet_reordering_threshold(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_time_reordering_threshold(
+    config: &mut Config, v: f64,
+) -> c_int {
+    match config.set_time_reordering_threshold(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_enable_hystart(config: &mut Config, v: bool) {
     config.enable_hystart(v);
@@ -365,6 +454,29 @@ pub extern fn quiche_config_enable_dgram(
     config.enable_dgram(enabled, recv_queue_len, send_queue_len);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_enable_ack_frequency(config: &mut Config, v: bool) {
+    config.enable_ack_frequency(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_grease_quic_bit(config: &mut Config, v: bool) {
+    config.enable_grease_quic_bit(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_custom_transport_param(
+    config: &mut Config, id: u64, value: *const u8, value_len: size_t,
+) -> c_int {
+    let value = unsafe { slice::from_raw_parts(value, value_len) };
+
+    match config.set_custom_transport_param(id, value) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_max_send_udp_payload_size(
     config: &mut Config, v: size_t,
@@ -384,6 +496,28 @@ pub extern fn quiche_config_set_max_stream_window(config: &mut Config, v: u64) {
     config.set_max_stream_window(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_stream_window_update_threshold(
+    config: &mut Config, v: f64,
+) -> c_int {
+    match config.set_stream_window_update_threshold(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_crypto_buffer_size(
+    config: &mut Config, v: u64,
+) -> c_int {
+    match config.set_max_crypto_buffer_size(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_active_connection_id_limit(
     config: &mut Config, v: u64,
@@ -404,11 +538,60 @@ pub extern fn quiche_config_set_stateless_reset_token(
     config.set_stateless_reset_token(Some(reset_token));
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_stateless_reset_key(
+    config: &mut Config, key: *const u8, key_len: size_t,
+) -> c_int {
+    let key = unsafe { slice::from_raw_parts(key, key_len) };
+
+    let key = match key.try_into() {
+        Ok(v) => v,
+
+        Err(_) => return -1,
+    };
+
+    config.set_stateless_reset_key(key);
+
+    0
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_disable_dcid_reuse(config: &mut Config, v: bool) {
     config.set_disable_dcid_reuse(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_trace_label(
+    config: &mut Config, label: *const c_char,
+) {
+    let label = unsafe { ffi::CStr::from_ptr(label).to_str().unwrap() };
+    config.set_trace_label(label);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_multipath(config: &mut Config, v: bool) {
+    config.set_multipath(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_autoflush(config: &mut Config, v: bool) {
+    config.set_autoflush(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_pre_handshake_data(
+    config: &mut Config, v: size_t,
+) {
+    config.set_max_pre_handshake_data(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_send_coalescing_delay(
+    config: &mut Config, v: u64,
+) {
+    config.set_send_coalescing_delay(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_ticket_key(
     config: &mut Config, key: *const u8, key_len: size_t,
@@ -544,6 +727,34 @@ pub extern fn quiche_connect(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_connect_with_dcid(
+    server_name: *const c_char, scid: *const u8, scid_len: size_t,
+    dcid: *const u8, dcid_len: size_t, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, config: &mut Config,
+) -> *mut Connection {
+    let server_name = if server_name.is_null() {
+        None
+    } else {
+        Some(unsafe { ffi::CStr::from_ptr(server_name).to_str().unwrap() })
+    };
+
+    let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
+    let scid = ConnectionId::from_ref(scid);
+
+    let dcid = unsafe { slice::from_raw_parts(dcid, dcid_len) };
+    let dcid = ConnectionId::from_ref(dcid);
+
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match connect_with_dcid(server_name, &scid, &dcid, local, peer, config) {
+        Ok(c) => Box::into_raw(Box::new(c)),
+
+        Err(_) => ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_negotiate_version(
     scid: *const u8, scid_len: size_t, dcid: *const u8, dcid_len: size_t,
@@ -631,6 +842,54 @@ pub extern fn quiche_conn_new_with_tls(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_accept_with_tls(
+    scid: *const u8, scid_len: size_t, odcid: *const u8, odcid_len: size_t,
+    local: &sockaddr, local_len: socklen_t, peer: &sockaddr, peer_len: socklen_t,
+    config: &Config, ssl: *mut c_void,
+) -> *mut Connection {
+    quiche_conn_new_with_tls(
+        scid, scid_len, odcid, odcid_len, local, local_len, peer, peer_len,
+        config, ssl, true,
+    )
+}
+
+#[no_mangle]
+pub extern fn quiche_connect_with_tls(
+    server_name: *const c_char, scid: *const u8, scid_len: size_t,
+    local: &sockaddr, local_len: socklen_t, peer: &sockaddr, peer_len: socklen_t,
+    config: &Config, ssl: *mut c_void,
+) -> *mut Connection {
+    let conn = quiche_conn_new_with_tls(
+        scid,
+        scid_len,
+        ptr::null(),
+        0,
+        local,
+        local_len,
+        peer,
+        peer_len,
+        config,
+        ssl,
+        false,
+    );
+
+    if conn.is_null() || server_name.is_null() {
+        return conn;
+    }
+
+    let server_name =
+        unsafe { ffi::CStr::from_ptr(server_name).to_str().unwrap() };
+
+    if unsafe { (*conn).handshake.set_host_name(server_name) }.is_err() {
+        drop(unsafe { Box::from_raw(conn) });
+
+        return ptr::null_mut();
+    }
+
+    conn
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_set_keylog_path(
     conn: &mut Connection, path: *const c_char,
@@ -850,6 +1109,24 @@ pub extern fn quiche_conn_stream_recv(
     out_len as ssize_t
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stream_open_bidi(conn: &mut Connection) -> i64 {
+    match conn.stream_open_bidi() {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stream_open_uni(conn: &mut Connection) -> i64 {
+    match conn.stream_open_uni() {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_send(
     conn: &mut Connection, stream_id: u64, buf: *const u8, buf_len: size_t,
@@ -897,6 +1174,59 @@ pub extern fn quiche_conn_stream_shutdown(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stream_flush(conn: &mut Connection, stream_id: u64) {
+    conn.stream_flush(stream_id)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_flush(conn: &mut Connection) {
+    conn.flush()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_send_immediate(conn: &mut Connection, v: bool) {
+    conn.set_send_immediate(v)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_max_pacing_rate(conn: &mut Connection, v: u64) {
+    conn.set_max_pacing_rate(v)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stream_group(
+    conn: &mut Connection, stream_id: u64, group_id: u64,
+) -> c_int {
+    match conn.stream_group(stream_id, group_id) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_group_set_max_data(
+    conn: &mut Connection, group_id: u64, max: u64,
+) -> c_int {
+    match conn.group_set_max_data(group_id, max) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_group_close(
+    conn: &mut Connection, group_id: u64, err: u64,
+) -> c_int {
+    match conn.group_close(group_id, err) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_capacity(
     conn: &Connection, stream_id: u64,
@@ -908,6 +1238,31 @@ pub extern fn quiche_conn_stream_capacity(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stream_cancel_pending(
+    conn: &mut Connection, stream_id: u64, from_offset: u64,
+) -> ssize_t {
+    match conn.stream_cancel_pending(stream_id, from_offset) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stream_acked_offset(
+    conn: &Connection, stream_id: u64, out: *mut u64,
+) -> c_int {
+    match conn.stream_acked_offset(stream_id) {
+        Ok(v) => {
+            unsafe { *out = v }
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_readable(
     conn: &Connection, stream_id: u64,
@@ -960,6 +1315,11 @@ pub extern fn quiche_conn_max_send_udp_payload_size(conn: &Connection) -> usize
     conn.max_send_udp_payload_size()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_max_recv_udp_payload_size(conn: &Connection) -> usize {
+    conn.max_recv_udp_payload_size()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_is_readable(conn: &Connection) -> bool {
     conn.is_readable()
@@ -1122,6 +1482,11 @@ pub extern fn quiche_conn_is_established(conn: &Connection) -> bool {
     conn.is_established()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_is_handshake_confirmed(conn: &Connection) -> bool {
+    conn.is_handshake_confirmed()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_is_resumed(conn: &Connection) -> bool {
     conn.is_resumed()
@@ -1132,6 +1497,16 @@ pub extern fn quiche_conn_is_in_early_data(conn: &Connection) -> bool {
     conn.is_in_early_data()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_is_app_data_ready(conn: &Connection) -> bool {
+    conn.is_app_data_ready()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_pre_handshake_data_len(conn: &Connection) -> size_t {
+    conn.pre_handshake_data_len()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_is_draining(conn: &Connection) -> bool {
     conn.is_draining()
@@ -1288,6 +1663,25 @@ pub extern fn quiche_conn_peer_transport_params(
     true
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_peer_custom_transport_param(
+    conn: &Connection, id: u64, out: &mut *const u8, out_len: &mut size_t,
+) -> bool {
+    let value = match conn
+        .peer_transport_params()
+        .and_then(|tps| tps.custom_param(id))
+    {
+        Some(v) => v,
+
+        None => return false,
+    };
+
+    *out = value.as_ptr();
+    *out_len = value.len();
+
+    true
+}
+
 #[repr(C)]
 pub struct PathStats {
     local_addr: sockaddr_storage,
@@ -1452,6 +1846,14 @@ pub extern fn quiche_conn_send_ack_eliciting(conn: &mut Connection) -> ssize_t {
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_probe_rtt(conn: &mut Connection) -> ssize_t {
+    match conn.probe_rtt() {
+        Ok(()) => 0,
+        Err(e) => e.to_c(),
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_send_ack_eliciting_on_path(
     conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
@@ -1465,6 +1867,14 @@ pub extern fn quiche_conn_send_ack_eliciting_on_path(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_initiate_key_update(conn: &mut Connection) -> c_int {
+    match conn.initiate_key_update() {
+        Ok(()) => 0,
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_free(conn: *mut Connection) {
     drop(unsafe { Box::from_raw(conn) });
@@ -1485,6 +1895,11 @@ pub extern fn quiche_conn_send_quantum(conn: &Connection) -> size_t {
     conn.send_quantum() as size_t
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_send_capacity(conn: &Connection) -> size_t {
+    conn.send_capacity() as size_t
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_active_scids(conn: &Connection) -> size_t {
     conn.active_scids() as size_t
@@ -27,6 +27,9 @@
 use std::time::Duration;
 use std::time::Instant;
 
+use crate::Error;
+use crate::Result;
+
 // When autotuning the receiver window, decide how much
 // we increase the window.
 const WINDOW_INCREASE_FACTOR: u64 = 2;
@@ -35,6 +38,10 @@ const WINDOW_INCREASE_FACTOR: u64 = 2;
 // update is within RTT * this constant.
 const WINDOW_TRIGGER_FACTOR: u32 = 2;
 
+// The default fraction of the window that needs to be consumed before
+// max_data is updated.
+pub const DEFAULT_UPDATE_THRESHOLD: f64 = 0.5;
+
 #[derive(Default, Debug)]
 pub struct FlowControl {
     /// Total consumed bytes by the receiver.
@@ -52,6 +59,10 @@ pub struct FlowControl {
 
     /// Last update time of max_data for autotuning the window.
     last_update: Option<Instant>,
+
+    /// The fraction of the window that needs to be consumed before max_data
+    /// is updated.
+    update_threshold: f64,
 }
 
 impl FlowControl {
@@ -63,6 +74,8 @@ impl FlowControl {
 
             max_window,
 
+            update_threshold: DEFAULT_UPDATE_THRESHOLD,
+
             ..Default::default()
         }
     }
@@ -77,19 +90,42 @@ impl FlowControl {
         self.max_data
     }
 
+    /// Sets the current flow limit, e.g. when restoring a connection.
+    #[cfg(feature = "checkpoint")]
+    pub fn set_max_data(&mut self, max_data: u64) {
+        self.max_data = max_data;
+    }
+
     /// Update consumed bytes.
     pub fn add_consumed(&mut self, consumed: u64) {
         self.consumed += consumed;
     }
 
+    /// Sets the fraction of the window that needs to be consumed before
+    /// max_data is updated.
+    ///
+    /// The value must be greater than 0 and less than 1.
+    pub fn set_update_threshold(&mut self, v: f64) -> Result<()> {
+        if !(v > 0.0 && v < 1.0) {
+            return Err(Error::InvalidState);
+        }
+
+        self.update_threshold = v;
+
+        Ok(())
+    }
+
     /// Returns true if the flow control needs to update max_data.
     ///
-    /// This happens when the available window is smaller than the half
-    /// of the current window.
+    /// This happens when more than the update threshold (by default half) of
+    /// the current window was consumed.
     pub fn should_update_max_data(&self) -> bool {
         let available_window = self.max_data - self.consumed;
 
-        available_window < (self.window / 2)
+        let min_available =
+            (self.window as f64 * (1.0 - self.update_threshold)) as u64;
+
+        available_window < min_available
     }
 
     /// Returns the new max_data limit.
@@ -148,6 +184,41 @@ mod tests {
         assert!(fc.should_update_max_data());
     }
 
+    #[test]
+    fn should_update_max_data_threshold() {
+        let mut fc = FlowControl::new(100, 20, 100);
+        assert_eq!(fc.set_update_threshold(0.25), Ok(()));
+
+        fc.add_consumed(85);
+        assert!(!fc.should_update_max_data());
+
+        fc.add_consumed(1);
+        assert!(fc.should_update_max_data());
+    }
+
+    #[test]
+    fn update_threshold_out_of_range() {
+        let mut fc = FlowControl::new(100, 20, 100);
+
+        assert_eq!(fc.set_update_threshold(0.0), Err(Error::InvalidState));
+        assert_eq!(fc.set_update_threshold(-0.5), Err(Error::InvalidState));
+        assert_eq!(fc.set_update_threshold(1.0), Err(Error::InvalidState));
+        assert_eq!(fc.set_update_threshold(1.5), Err(Error::InvalidState));
+        assert_eq!(fc.set_update_threshold(f64::NAN), Err(Error::InvalidState));
+
+        assert_eq!(fc.set_update_threshold(f64::MIN_POSITIVE), Ok(()));
+        assert_eq!(fc.set_update_threshold(0.999), Ok(()));
+
+        // Rejected values leave the previous threshold in place.
+        assert_eq!(fc.set_update_threshold(1.0), Err(Error::InvalidState));
+
+        fc.add_consumed(99);
+        assert!(!fc.should_update_max_data());
+
+        fc.add_consumed(1);
+        assert!(fc.should_update_max_data());
+    }
+
     #[test]
     fn max_data_next() {
         let mut fc = FlowControl::new(100, 20, 100);
@@ -29,8 +29,11 @@ use std::convert::TryInto;
 use crate::Error;
 use crate::Result;
 
+use crate::extension::ExtensionFrameHandler;
 use crate::packet;
 use crate::ranges;
+use crate::redact::Payload;
+use crate::redact::Redacted;
 use crate::stream;
 
 #[cfg(feature = "qlog")]
@@ -184,13 +187,27 @@ pub enum Frame {
     DatagramHeader {
         length: usize,
     },
+
+    AckFrequency {
+        seq_num: u64,
+        ack_eliciting_threshold: u64,
+        request_max_ack_delay: u64,
+        reordering_threshold: u64,
+    },
+
+    ImmediateAck,
+
+    Extension {
+        frame_type: u64,
+        data: Vec<u8>,
+    },
 }
 
 impl Frame {
     pub fn from_bytes(
         b: &mut octets::Octets, pkt: packet::Type,
     ) -> Result<Frame> {
-        let frame_type = b.get_varint()?;
+        let frame_type = parse_frame_type(b)?;
 
         let frame = match frame_type {
             0x00 => {
@@ -329,8 +346,17 @@ impl Frame {
 
             0x1e => Frame::HandshakeDone,
 
+            0x1f => Frame::ImmediateAck,
+
             0x30 | 0x31 => parse_datagram_frame(frame_type, b)?,
 
+            0xaf => Frame::AckFrequency {
+                seq_num: b.get_varint()?,
+                ack_eliciting_threshold: b.get_varint()?,
+                request_max_ack_delay: b.get_varint()?,
+                reordering_threshold: b.get_varint()?,
+            },
+
             _ => return Err(Error::InvalidFrame),
         };
 
@@ -348,6 +374,11 @@ impl Frame {
             (packet::Type::ZeroRTT, Frame::RetireConnectionId { .. }) => false,
             (packet::Type::ZeroRTT, Frame::ConnectionClose { .. }) => false,
 
+            // ACK_FREQUENCY and IMMEDIATE_ACK can only be sent on 1-RTT
+            // packets.
+            (packet::Type::ZeroRTT, Frame::AckFrequency { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::ImmediateAck) => false,
+
             // ACK, CRYPTO and CONNECTION_CLOSE can be sent on all other packet
             // types.
             (_, Frame::ACK { .. }) => true,
@@ -369,6 +400,26 @@ impl Frame {
         Ok(frame)
     }
 
+    /// Parses an extension frame, using the given handler to determine the
+    /// length of its body.
+    pub fn from_extension_bytes(
+        b: &mut octets::Octets, pkt: packet::Type,
+        handler: &dyn ExtensionFrameHandler,
+    ) -> Result<Frame> {
+        let frame_type = parse_frame_type(b)?;
+
+        let len = handler.frame_len(frame_type, b.as_ref())?;
+
+        let data = b.get_bytes(len)?.to_vec();
+
+        // Extension frames are only allowed on 0-RTT and 1-RTT packets.
+        if pkt != packet::Type::Short && pkt != packet::Type::ZeroRTT {
+            return Err(Error::InvalidPacket);
+        }
+
+        Ok(Frame::Extension { frame_type, data })
+    }
+
     pub fn to_bytes(&self, b: &mut octets::OctetsMut) -> Result<usize> {
         let before = b.cap();
 
@@ -593,6 +644,30 @@ impl Frame {
             },
 
             Frame::DatagramHeader { .. } => (),
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                b.put_varint(0xaf)?;
+
+                b.put_varint(*seq_num)?;
+                b.put_varint(*ack_eliciting_threshold)?;
+                b.put_varint(*request_max_ack_delay)?;
+                b.put_varint(*reordering_threshold)?;
+            },
+
+            Frame::ImmediateAck => {
+                b.put_varint(0x1f)?;
+            },
+
+            Frame::Extension { frame_type, data } => {
+                b.put_varint(*frame_type)?;
+
+                b.put_bytes(data.as_ref())?;
+            },
         }
 
         Ok(before - b.cap())
@@ -808,6 +883,26 @@ impl Frame {
                 2 + // length, always encode as 2-byte varint
                 *length // data
             },
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                2 + // frame type
+                octets::varint_len(*seq_num) + // seq_num
+                octets::varint_len(*ack_eliciting_threshold) + // threshold
+                octets::varint_len(*request_max_ack_delay) + // max_ack_delay
+                octets::varint_len(*reordering_threshold) // reordering
+            },
+
+            Frame::ImmediateAck => 1,
+
+            Frame::Extension { frame_type, data } => {
+                octets::varint_len(*frame_type) + // frame type
+                data.len() // data
+            },
         }
     }
 
@@ -1032,6 +1127,24 @@ impl Frame {
                 length: *length as u64,
                 raw: None,
             },
+
+            Frame::AckFrequency { .. } => QuicFrame::Unknown {
+                raw_frame_type: 0xaf,
+                frame_type_value: None,
+                raw: None,
+            },
+
+            Frame::ImmediateAck => QuicFrame::Unknown {
+                raw_frame_type: 0x1f,
+                frame_type_value: None,
+                raw: None,
+            },
+
+            Frame::Extension { frame_type, .. } => QuicFrame::Unknown {
+                raw_frame_type: *frame_type,
+                frame_type_value: None,
+                raw: None,
+            },
         }
     }
 }
@@ -1077,7 +1190,13 @@ impl std::fmt::Debug for Frame {
             },
 
             Frame::Crypto { data } => {
-                write!(f, "CRYPTO off={} len={}", data.off(), data.len())?;
+                write!(
+                    f,
+                    "CRYPTO off={} len={}{}",
+                    data.off(),
+                    data.len(),
+                    Payload(data)
+                )?;
             },
 
             Frame::CryptoHeader { offset, length } => {
@@ -1085,17 +1204,18 @@ impl std::fmt::Debug for Frame {
             },
 
             Frame::NewToken { token } => {
-                write!(f, "NEW_TOKEN len={}", token.len())?;
+                write!(f, "NEW_TOKEN len={}{}", token.len(), Payload(token))?;
             },
 
             Frame::Stream { stream_id, data } => {
                 write!(
                     f,
-                    "STREAM id={} off={} len={} fin={}",
+                    "STREAM id={} off={} len={} fin={}{}",
                     stream_id,
                     data.off(),
                     data.len(),
-                    data.fin()
+                    data.fin(),
+                    Payload(data)
                 )?;
             },
 
@@ -1154,7 +1274,8 @@ impl std::fmt::Debug for Frame {
             } => {
                 write!(
                     f,
-                    "NEW_CONNECTION_ID seq_num={seq_num} retire_prior_to={retire_prior_to} conn_id={conn_id:02x?} reset_token={reset_token:02x?}",
+                    "NEW_CONNECTION_ID seq_num={seq_num} retire_prior_to={retire_prior_to} conn_id={conn_id:02x?} reset_token={}",
+                    Redacted(reset_token),
                 )?;
             },
 
@@ -1177,14 +1298,16 @@ impl std::fmt::Debug for Frame {
             } => {
                 write!(
                     f,
-                    "CONNECTION_CLOSE err={error_code:x} frame={frame_type:x} reason={reason:x?}"
+                    "CONNECTION_CLOSE err={error_code:x} frame={frame_type:x} reason={}",
+                    Redacted(reason)
                 )?;
             },
 
             Frame::ApplicationClose { error_code, reason } => {
                 write!(
                     f,
-                    "APPLICATION_CLOSE err={error_code:x} reason={reason:x?}"
+                    "APPLICATION_CLOSE err={error_code:x} reason={}",
+                    Redacted(reason)
                 )?;
             },
 
@@ -1193,18 +1316,62 @@ impl std::fmt::Debug for Frame {
             },
 
             Frame::Datagram { data } => {
-                write!(f, "DATAGRAM len={}", data.len())?;
+                write!(f, "DATAGRAM len={}{}", data.len(), Payload(data))?;
             },
 
             Frame::DatagramHeader { length } => {
                 write!(f, "DATAGRAM len={length}")?;
             },
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                write!(
+                    f,
+                    "ACK_FREQUENCY seq_num={seq_num} threshold={ack_eliciting_threshold} max_ack_delay={request_max_ack_delay} reordering={reordering_threshold}"
+                )?;
+            },
+
+            Frame::ImmediateAck => {
+                write!(f, "IMMEDIATE_ACK")?;
+            },
+
+            Frame::Extension { frame_type, data } => {
+                write!(
+                    f,
+                    "EXTENSION type={frame_type:x} len={}{}",
+                    data.len(),
+                    Payload(data)
+                )?;
+            },
         }
 
         Ok(())
     }
 }
 
+/// Returns true if frames of the given type are natively supported.
+pub fn is_native_type(frame_type: u64) -> bool {
+    matches!(frame_type, 0x00..=0x1f | 0x30 | 0x31 | 0xaf)
+}
+
+/// Parses a frame type, rejecting types that are not encoded using the
+/// shortest possible form, as required by RFC 9000 Section 12.4.
+fn parse_frame_type(b: &mut octets::Octets) -> Result<u64> {
+    let len = octets::varint_parse_len(b.peek_u8()?);
+
+    let frame_type = b.get_varint()?;
+
+    if len != octets::varint_len(frame_type) {
+        return Err(Error::InvalidFrame);
+    }
+
+    Ok(frame_type)
+}
+
 fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
     let first = ty as u8;
 
@@ -1417,6 +1584,27 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
+    #[test]
+    fn non_minimal_frame_type() {
+        // PING frame type encoded in 2 bytes.
+        let d = [0x40, 0x01];
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Err(Error::InvalidFrame)
+        );
+
+        // PING frame type encoded in 8 bytes.
+        let d = [0xc0, 0, 0, 0, 0, 0, 0, 0x01];
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Err(Error::InvalidFrame)
+        );
+    }
+
     #[test]
     fn ack() {
         let mut d = [42; 128];
@@ -2080,6 +2268,65 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
+    #[test]
+    fn ack_frequency() {
+        let mut d = [42; 128];
+
+        let frame = Frame::AckFrequency {
+            seq_num: 3,
+            ack_eliciting_threshold: 10,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: 1,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 9);
+        assert_eq!(frame.wire_len(), wire_len);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn immediate_ack() {
+        let mut d = [42; 128];
+
+        let frame = Frame::ImmediateAck;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 1);
+        assert!(frame.ack_eliciting());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
     #[test]
     fn datagram() {
         let mut d = [42; 128];
@@ -2118,4 +2365,519 @@ mod tests {
 
         assert_eq!(frame_data, data);
     }
+
+    #[test]
+    fn extension() {
+        // Extension frames with a fixed 4-byte body.
+        struct Fixed;
+
+        impl ExtensionFrameHandler for Fixed {
+            fn frame_len(&self, _frame_type: u64, _buf: &[u8]) -> Result<usize> {
+                Ok(4)
+            }
+        }
+
+        let mut d = [42; 128];
+
+        let frame = Frame::Extension {
+            frame_type: 0x3f00_0000_0000_00af,
+            data: vec![1, 2, 3, 4],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 12);
+        assert_eq!(frame.wire_len(), wire_len);
+        assert!(frame.ack_eliciting());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_extension_bytes(&mut b, packet::Type::Short, &Fixed),
+            Ok(frame)
+        );
+        assert_eq!(b.off(), wire_len);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_extension_bytes(
+            &mut b,
+            packet::Type::Initial,
+            &Fixed
+        )
+        .is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_extension_bytes(
+            &mut b,
+            packet::Type::ZeroRTT,
+            &Fixed
+        )
+        .is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_extension_bytes(
+            &mut b,
+            packet::Type::Handshake,
+            &Fixed
+        )
+        .is_err());
+
+        // Without a handler the frame can't be parsed.
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Err(Error::InvalidFrame)
+        );
+
+        let mut b = octets::Octets::with_slice(&d[..wire_len - 1]);
+        assert_eq!(
+            Frame::from_extension_bytes(&mut b, packet::Type::Short, &Fixed),
+            Err(Error::BufferTooShort)
+        );
+    }
+
+    const MAX_VARINT: u64 = (1 << 62) - 1;
+
+    /// Varint values at the boundaries of each encoding length.
+    const VARINTS: [u64; 9] = [
+        0,
+        1,
+        63,
+        64,
+        16_383,
+        16_384,
+        1_073_741_823,
+        1_073_741_824,
+        MAX_VARINT,
+    ];
+
+    /// Encodes the frame and checks it against the expected wire image, then
+    /// parses the wire image back and checks it against the original frame.
+    fn assert_wire_format(frame: &Frame, expected: &[u8]) {
+        let mut d = [42; 128];
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(&d[..wire_len], expected, "{:?}", frame);
+        assert_eq!(frame.wire_len(), wire_len, "{:?}", frame);
+
+        let mut b = octets::Octets::with_slice(expected);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short).as_ref(),
+            Ok(frame)
+        );
+        assert_eq!(b.cap(), 0);
+    }
+
+    /// Encodes and parses back the frame, checking that nothing is lost.
+    fn assert_round_trip(frame: &Frame) {
+        let mut d = [42; 128];
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(frame.wire_len(), wire_len, "{:?}", frame);
+
+        let mut b = octets::Octets::with_slice(&d[..wire_len]);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short).as_ref(),
+            Ok(frame)
+        );
+        assert_eq!(b.cap(), 0);
+    }
+
+    fn golden_vectors() -> Vec<(Frame, Vec<u8>)> {
+        let mut ack_single = ranges::RangeSet::default();
+        ack_single.insert(0..1);
+
+        let mut ack_gap = ranges::RangeSet::default();
+        ack_gap.insert(2..4);
+        ack_gap.insert(6..11);
+
+        let mut v = vec![
+            (Frame::Padding { len: 3 }, vec![0x00, 0x00, 0x00]),
+            (Frame::Ping { mtu_probe: None }, vec![0x01]),
+            // ACK without any additional range.
+            (
+                Frame::ACK {
+                    ack_delay: 0,
+                    ranges: ack_single.clone(),
+                    ecn_counts: None,
+                },
+                vec![0x02, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (
+                Frame::ACK {
+                    ack_delay: 100,
+                    ranges: ack_gap,
+                    ecn_counts: None,
+                },
+                vec![0x02, 0x0a, 0x40, 0x64, 0x01, 0x04, 0x01, 0x01],
+            ),
+            (
+                Frame::ACK {
+                    ack_delay: 0,
+                    ranges: ack_single,
+                    ecn_counts: Some(EcnCounts {
+                        ect0_count: 1,
+                        ect1_count: 2,
+                        ecn_ce_count: 3,
+                    }),
+                },
+                vec![0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03],
+            ),
+            (
+                Frame::ResetStream {
+                    stream_id: 4,
+                    error_code: 0x1234,
+                    final_size: 1000,
+                },
+                vec![0x04, 0x04, 0x52, 0x34, 0x43, 0xe8],
+            ),
+            (
+                Frame::StopSending {
+                    stream_id: MAX_VARINT,
+                    error_code: 0,
+                },
+                vec![0x05, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00],
+            ),
+            (
+                Frame::Crypto {
+                    data: stream::RangeBuf::from(b"abc", 1_073_741_823, false),
+                },
+                vec![0x06, 0xbf, 0xff, 0xff, 0xff, 0x40, 0x03, b'a', b'b', b'c'],
+            ),
+            (
+                Frame::NewToken {
+                    token: vec![1, 2, 3],
+                },
+                vec![0x07, 0x03, 0x01, 0x02, 0x03],
+            ),
+            (
+                Frame::Stream {
+                    stream_id: 8,
+                    data: stream::RangeBuf::from(b"hi", 0, true),
+                },
+                vec![0x0f, 0x08, 0x00, 0x40, 0x02, b'h', b'i'],
+            ),
+            (
+                Frame::Stream {
+                    stream_id: 1,
+                    data: stream::RangeBuf::from(b"", 64, false),
+                },
+                vec![0x0e, 0x01, 0x40, 0x40, 0x40, 0x00],
+            ),
+            (Frame::MaxData { max: 16_384 }, vec![
+                0x10, 0x80, 0x00, 0x40, 0x00,
+            ]),
+            (
+                Frame::MaxStreamData {
+                    stream_id: 0,
+                    max: 63,
+                },
+                vec![0x11, 0x00, 0x3f],
+            ),
+            (Frame::MaxStreamsBidi { max: 64 }, vec![0x12, 0x40, 0x40]),
+            (Frame::MaxStreamsUni { max: 1 << 60 }, vec![
+                0x13, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]),
+            (Frame::DataBlocked { limit: 0 }, vec![0x14, 0x00]),
+            // Varint examples from RFC 9000, Appendix A.1.
+            (
+                Frame::StreamDataBlocked {
+                    stream_id: 2,
+                    limit: 15_293,
+                },
+                vec![0x15, 0x02, 0x7b, 0xbd],
+            ),
+            (Frame::StreamsBlockedBidi { limit: 494_878_333 }, vec![
+                0x16, 0x9d, 0x7f, 0x3e, 0x7d,
+            ]),
+            (
+                Frame::StreamsBlockedUni {
+                    limit: 151_288_809_941_952_652,
+                },
+                vec![0x17, 0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c],
+            ),
+            (Frame::RetireConnectionId { seq_num: 37 }, vec![0x19, 0x25]),
+            (
+                Frame::PathChallenge {
+                    data: [1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                vec![0x1a, 1, 2, 3, 4, 5, 6, 7, 8],
+            ),
+            (
+                Frame::PathResponse {
+                    data: [8, 7, 6, 5, 4, 3, 2, 1],
+                },
+                vec![0x1b, 8, 7, 6, 5, 4, 3, 2, 1],
+            ),
+            (
+                Frame::ConnectionClose {
+                    error_code: 0x0a,
+                    frame_type: 0x08,
+                    reason: b"bad".to_vec(),
+                },
+                vec![0x1c, 0x0a, 0x08, 0x03, b'b', b'a', b'd'],
+            ),
+            (
+                Frame::ConnectionClose {
+                    error_code: 0,
+                    frame_type: 0,
+                    reason: vec![],
+                },
+                vec![0x1c, 0x00, 0x00, 0x00],
+            ),
+            (
+                Frame::ApplicationClose {
+                    error_code: 0x100,
+                    reason: vec![],
+                },
+                vec![0x1d, 0x41, 0x00, 0x00],
+            ),
+            (Frame::HandshakeDone, vec![0x1e]),
+            (Frame::Datagram { data: vec![1, 2] }, vec![
+                0x31, 0x40, 0x02, 0x01, 0x02,
+            ]),
+            (
+                Frame::AckFrequency {
+                    seq_num: 1,
+                    ack_eliciting_threshold: 10,
+                    request_max_ack_delay: 25_000,
+                    reordering_threshold: 1,
+                },
+                vec![0x40, 0xaf, 0x01, 0x0a, 0x80, 0x00, 0x61, 0xa8, 0x01],
+            ),
+            (Frame::ImmediateAck, vec![0x1f]),
+        ];
+
+        let mut ncid = vec![0x18, 0x01, 0x00, 0x04];
+        ncid.extend_from_slice(&[0xaa; 4]);
+        ncid.extend_from_slice(&[0xbb; 16]);
+
+        v.push((
+            Frame::NewConnectionId {
+                seq_num: 1,
+                retire_prior_to: 0,
+                conn_id: vec![0xaa; 4],
+                reset_token: [0xbb; 16],
+            },
+            ncid,
+        ));
+
+        v
+    }
+
+    #[test]
+    fn golden_wire_format() {
+        for (frame, expected) in golden_vectors() {
+            assert_wire_format(&frame, &expected);
+        }
+    }
+
+    #[test]
+    fn golden_truncated() {
+        for (frame, expected) in golden_vectors() {
+            // Truncated padding is just shorter padding.
+            if let Frame::Padding { .. } = frame {
+                continue;
+            }
+
+            let mut b =
+                octets::Octets::with_slice(&expected[..expected.len() - 1]);
+            assert!(
+                Frame::from_bytes(&mut b, packet::Type::Short).is_err(),
+                "{:?}",
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn parse_implicit_fields() {
+        // STREAM without offset and length fields extends to the end of the
+        // packet.
+        let d = [0x08, 0x04, b'x', b'y'];
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Ok(Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"xy", 0, false),
+            })
+        );
+
+        // DATAGRAM without length field extends to the end of the packet.
+        let d = [0x30, 0x01, 0x02, 0x03];
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Ok(Frame::Datagram {
+                data: vec![1, 2, 3]
+            })
+        );
+
+        // Varints don't need to use the shortest encoding.
+        let d = [0x10, 0x40, 0x05];
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Ok(Frame::MaxData { max: 5 })
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let invalid: [&[u8]; 6] = [
+            // Unknown frame type.
+            &[0x20],
+            // ACK first range larger than the largest acknowledged.
+            &[0x02, 0x01, 0x00, 0x00, 0x02],
+            // ACK gap going below zero.
+            &[0x02, 0x05, 0x00, 0x01, 0x00, 0x05, 0x00],
+            // NEW_TOKEN with empty token.
+            &[0x07, 0x00],
+            // NEW_CONNECTION_ID with empty connection ID.
+            &[0x18, 0x01, 0x00, 0x00],
+            // STREAM exceeding the maximum stream size.
+            &[
+                0x0c, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, b'x',
+            ],
+        ];
+
+        for d in invalid {
+            let mut b = octets::Octets::with_slice(d);
+            assert_eq!(
+                Frame::from_bytes(&mut b, packet::Type::Short),
+                Err(Error::InvalidFrame),
+                "{:x?}",
+                d
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_varints() {
+        for &v in VARINTS.iter() {
+            let frames = [
+                Frame::ResetStream {
+                    stream_id: v,
+                    error_code: v,
+                    final_size: v,
+                },
+                Frame::StopSending {
+                    stream_id: v,
+                    error_code: v,
+                },
+                Frame::MaxData { max: v },
+                Frame::MaxStreamData {
+                    stream_id: v,
+                    max: v,
+                },
+                Frame::MaxStreamsBidi { max: v },
+                Frame::MaxStreamsUni { max: v },
+                Frame::DataBlocked { limit: v },
+                Frame::StreamDataBlocked {
+                    stream_id: v,
+                    limit: v,
+                },
+                Frame::StreamsBlockedBidi { limit: v },
+                Frame::StreamsBlockedUni { limit: v },
+                Frame::RetireConnectionId { seq_num: v },
+                Frame::NewConnectionId {
+                    seq_num: v,
+                    retire_prior_to: v,
+                    conn_id: vec![0xba; packet::MAX_CID_LEN as usize],
+                    reset_token: [0xcd; 16],
+                },
+                Frame::ConnectionClose {
+                    error_code: v,
+                    frame_type: v,
+                    reason: b"reason".to_vec(),
+                },
+                Frame::ApplicationClose {
+                    error_code: v,
+                    reason: b"reason".to_vec(),
+                },
+                Frame::ACK {
+                    ack_delay: v,
+                    ranges: {
+                        let mut r = ranges::RangeSet::default();
+                        r.insert(0..v + 1);
+                        r
+                    },
+                    ecn_counts: Some(EcnCounts {
+                        ect0_count: v,
+                        ect1_count: v,
+                        ecn_ce_count: v,
+                    }),
+                },
+            ];
+
+            for frame in frames.iter() {
+                assert_round_trip(frame);
+            }
+
+            // Stream offsets are limited by the maximum stream size.
+            let off = v.min(MAX_STREAM_SIZE - 5);
+
+            assert_round_trip(&Frame::Stream {
+                stream_id: v,
+                data: stream::RangeBuf::from(b"data", off, true),
+            });
+
+            assert_round_trip(&Frame::Crypto {
+                data: stream::RangeBuf::from(b"data", v, false),
+            });
+        }
+    }
+
+    #[test]
+    fn round_trip_ack_ranges() {
+        // Simple xorshift generator, so failures are reproducible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move |max: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % max
+        };
+
+        for _ in 0..1000 {
+            let mut ranges = ranges::RangeSet::default();
+            let mut start = next(1 << 20);
+
+            for _ in 0..=next(8) {
+                let end = start + 1 + next(100);
+
+                ranges.insert(start..end);
+
+                // Leave a gap of at least one packet between ranges.
+                start = end + 1 + next(100);
+            }
+
+            let ecn_counts = if next(2) == 0 {
+                None
+            } else {
+                Some(EcnCounts {
+                    ect0_count: next(MAX_VARINT),
+                    ect1_count: next(MAX_VARINT),
+                    ecn_ce_count: next(MAX_VARINT),
+                })
+            };
+
+            assert_round_trip(&Frame::ACK {
+                ack_delay: next(MAX_VARINT),
+                ranges,
+                ecn_counts,
+            });
+        }
+    }
 }
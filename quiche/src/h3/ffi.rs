@@ -77,6 +77,14 @@ pub extern fn quiche_h3_config_enable_extended_connect(
     config.enable_extended_connect(enabled);
 }
 
+#[no_mangle]
+#[cfg(feature = "sfv")]
+pub extern fn quiche_h3_config_enable_extensible_priorities(
+    config: &mut h3::Config, enabled: bool,
+) {
+    config.enable_extensible_priorities(enabled);
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_config_free(config: *mut h3::Config) {
     drop(unsafe { Box::from_raw(config) });
@@ -148,6 +156,10 @@ pub extern fn quiche_h3_event_type(ev: &h3::Event) -> u32 {
         h3::Event::Reset { .. } => 4,
 
         h3::Event::PriorityUpdate { .. } => 5,
+
+        h3::Event::PushPromise { .. } => 6,
+
+        h3::Event::PushCancelled { .. } => 7,
     }
 }
 
@@ -166,7 +178,7 @@ pub extern fn quiche_h3_event_for_each_header(
     argp: *mut c_void,
 ) -> c_int {
     match ev {
-        h3::Event::Headers { list, .. } =>
+        h3::Event::Headers { list, .. } | h3::Event::PushPromise { list, .. } =>
             for h in list {
                 let rc = cb(
                     h.name().as_ptr(),
@@ -301,6 +313,80 @@ pub extern fn quiche_h3_recv_body(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_event_push_promise_push_id(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::PushPromise { push_id, .. } |
+        h3::Event::PushCancelled { push_id } => *push_id,
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_push_promise(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    headers: *const Header, headers_len: size_t,
+) -> i64 {
+    let req_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_push_promise(quic_conn, stream_id, &req_headers) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_push_response(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+    headers: *const Header, headers_len: size_t, fin: bool,
+) -> i64 {
+    let resp_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_push_response(quic_conn, push_id, &resp_headers, fin) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_push_id(
+    conn: &h3::Connection, stream_id: u64, out: &mut u64,
+) -> bool {
+    match conn.push_id(stream_id) {
+        Some(v) => {
+            *out = v;
+            true
+        },
+
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_max_push_id(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.send_max_push_id(quic_conn, push_id) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_cancel_push(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.cancel_push(quic_conn, push_id) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_send_goaway(
     conn: &mut h3::Connection, quic_conn: &mut Connection, id: u64,
@@ -178,6 +178,12 @@
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((_stream_id, quiche::h3::Event::PushCancelled { push_id })) => {
+//!              // Client is not interested in a pushed response, stop it.
+//!         },
+//!
+//! #       Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -239,6 +245,14 @@
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((stream_id, quiche::h3::Event::PushPromise{push_id, list})) => {
+//!              // Server promised a response, accept or cancel it.
+//!         },
+//!
+//!         Ok((_stream_id, quiche::h3::Event::PushCancelled { push_id })) => {
+//!              // Server withdrew a promised response.
+//!         },
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -282,10 +296,10 @@
 //! [`send_response()`]: struct.Connection.html#method.send_response
 //! [`send_body()`]: struct.Connection.html#method.send_body
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
-#[cfg(feature = "sfv")]
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Write;
@@ -476,6 +490,46 @@ pub enum WireErrorCode {
     VersionFallback      = 0x110,
 }
 
+impl TryFrom<u64> for WireErrorCode {
+    type Error = crate::h3::Error;
+
+    /// Converts an error code received from the peer, e.g. in a
+    /// `RESET_STREAM` frame surfaced as [`StreamReset`], or in a
+    /// `CONNECTION_CLOSE` frame surfaced by [`peer_error()`].
+    ///
+    /// Error::Done is returned for unknown error codes, which [RFC 9114]
+    /// requires to be treated as equivalent to `H3_NO_ERROR`.
+    ///
+    /// [`StreamReset`]: ../enum.Error.html#variant.StreamReset
+    /// [`peer_error()`]: ../struct.Connection.html#method.peer_error
+    /// [RFC 9114]: https://www.rfc-editor.org/rfc/rfc9114.html#section-8.1
+    fn try_from(value: u64) -> std::result::Result<Self, Self::Error> {
+        let code = match value {
+            0x100 => WireErrorCode::NoError,
+            0x101 => WireErrorCode::GeneralProtocolError,
+            0x102 => WireErrorCode::InternalError,
+            0x103 => WireErrorCode::StreamCreationError,
+            0x104 => WireErrorCode::ClosedCriticalStream,
+            0x105 => WireErrorCode::FrameUnexpected,
+            0x106 => WireErrorCode::FrameError,
+            0x107 => WireErrorCode::ExcessiveLoad,
+            0x108 => WireErrorCode::IdError,
+            0x109 => WireErrorCode::SettingsError,
+            0x10a => WireErrorCode::MissingSettings,
+            0x10b => WireErrorCode::RequestRejected,
+            0x10c => WireErrorCode::RequestCancelled,
+            0x10d => WireErrorCode::RequestIncomplete,
+            0x10e => WireErrorCode::MessageError,
+            0x10f => WireErrorCode::ConnectError,
+            0x110 => WireErrorCode::VersionFallback,
+
+            _ => return Err(Error::Done),
+        };
+
+        Ok(code)
+    }
+}
+
 impl Error {
     fn to_wire(self) -> u64 {
         match self {
@@ -569,6 +623,7 @@ pub struct Config {
     /// additional settings are settings that are not part of the H3
     /// settings explicitly handled above
     additional_settings: Option<Vec<(u64, u64)>>,
+    extensible_priorities: bool,
 }
 
 impl Config {
@@ -580,6 +635,7 @@ impl Config {
             qpack_blocked_streams: None,
             connect_protocol_enabled: None,
             additional_settings: None,
+            extensible_priorities: false,
         })
     }
 
@@ -621,6 +677,27 @@ impl Config {
         }
     }
 
+    /// Configures whether priorities signaled by the peer are applied to
+    /// streams automatically.
+    ///
+    /// When enabled, a server parses the Priority header field of incoming
+    /// requests, as well as PRIORITY_UPDATE frames, and applies the resulting
+    /// urgency and incremental parameters to the underlying QUIC streams. The
+    /// same priority is then used when responding with [`send_response()`],
+    /// while [`send_response_with_priority()`] can still be used to override
+    /// it. [`PriorityUpdate`] events are still reported to the application.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`send_response()`]: struct.Connection.html#method.send_response
+    /// [`send_response_with_priority()`]: struct.Connection.html#method.send_response_with_priority
+    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    #[cfg(feature = "sfv")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sfv")))]
+    pub fn enable_extensible_priorities(&mut self, enabled: bool) {
+        self.extensible_priorities = enabled;
+    }
+
     /// Sets additional HTTP/3 settings.
     ///
     /// The default value is no additional settings.
@@ -800,6 +877,31 @@ pub enum Event {
 
     /// GOAWAY was received.
     GoAway,
+
+    /// PUSH_PROMISE was received.
+    ///
+    /// The stream ID associated with this event is the ID of the request
+    /// stream the promise was received on. The pushed response will be
+    /// delivered on a separate push stream, which can be associated with the
+    /// promise using the [`push_id()`] method.
+    ///
+    /// [`push_id()`]: struct.Connection.html#method.push_id
+    PushPromise {
+        /// The push ID of the promised response.
+        push_id: u64,
+
+        /// The list of header fields of the promised request.
+        list: Vec<Header>,
+    },
+
+    /// A server push was cancelled by the peer.
+    ///
+    /// The stream ID associated with this event is the ID of the peer's
+    /// control stream, which the cancellation was received on.
+    PushCancelled {
+        /// The push ID of the cancelled push.
+        push_id: u64,
+    },
 }
 
 /// Extensible Priorities parameters.
@@ -808,7 +910,7 @@ pub enum Event {
 /// Structured Fields Dictionary field value. I.e, use `TryFrom` to parse the
 /// value of a Priority header field or a PRIORITY_UPDATE frame. Using this
 /// trait requires the `sfv` feature to be enabled.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct Priority {
     urgency: u8,
@@ -936,7 +1038,12 @@ pub struct Connection {
     local_qpack_streams: QpackStreams,
     peer_qpack_streams: QpackStreams,
 
-    max_push_id: u64,
+    max_push_id: Option<u64>,
+    local_max_push_id: Option<u64>,
+    next_push_id: u64,
+
+    push_streams: HashMap<u64, u64>,
+    cancelled_pushes: HashSet<u64>,
 
     finished_streams: VecDeque<u64>,
 
@@ -944,6 +1051,8 @@ pub struct Connection {
 
     local_goaway_id: Option<u64>,
     peer_goaway_id: Option<u64>,
+
+    extensible_priorities: bool,
 }
 
 impl Connection {
@@ -998,7 +1107,12 @@ impl Connection {
                 decoder_stream_id: None,
             },
 
-            max_push_id: 0,
+            max_push_id: None,
+            local_max_push_id: None,
+            next_push_id: 0,
+
+            push_streams: HashMap::new(),
+            cancelled_pushes: HashSet::new(),
 
             finished_streams: VecDeque::new(),
 
@@ -1006,6 +1120,8 @@ impl Connection {
 
             local_goaway_id: None,
             peer_goaway_id: None,
+
+            extensible_priorities: config.extensible_priorities,
         })
     }
 
@@ -1122,6 +1238,10 @@ impl Connection {
     /// body, set `fin` as `false` and subsequently call [`send_body()`] with
     /// the same `conn` and `stream_id`.
     ///
+    /// When extensible priorities are enabled in the [`Config`], the priority
+    /// signaled by the client for the request is used instead of the default
+    /// one.
+    ///
     /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
     /// doesn't have enough capacity for the operation to complete. When this
     /// happens the application should retry the operation once the stream is
@@ -1129,11 +1249,17 @@ impl Connection {
     ///
     /// [`send_body()`]: struct.Connection.html#method.send_body
     /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    /// [`Config`]: struct.Config.html
     pub fn send_response<T: NameValue>(
         &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
         fin: bool,
     ) -> Result<()> {
-        let priority = Default::default();
+        let priority = self
+            .streams
+            .get(&stream_id)
+            .and_then(|s| s.priority())
+            .filter(|_| self.extensible_priorities)
+            .unwrap_or_default();
 
         self.send_response_with_priority(
             conn, stream_id, headers, &priority, fin,
@@ -1166,6 +1292,14 @@ impl Connection {
 
         self.send_headers(conn, stream_id, headers, fin)?;
 
+        Self::apply_priority(conn, stream_id, priority)?;
+
+        Ok(())
+    }
+
+    fn apply_priority(
+        conn: &mut super::Connection, stream_id: u64, priority: &Priority,
+    ) -> Result<()> {
         // Clamp and shift urgency into quiche-priority space
         let urgency = priority
             .urgency
@@ -1177,6 +1311,193 @@ impl Connection {
         Ok(())
     }
 
+    /// Sends a PUSH_PROMISE frame on the specified request stream.
+    ///
+    /// The promise is encoded from the provided list of `headers`, which
+    /// describe the request the server is going to respond to. The response
+    /// itself is then sent using [`send_push_response()`] with the push ID
+    /// returned from this method.
+    ///
+    /// Only servers can send PUSH_PROMISE frames, and only when the client
+    /// allowed it by sending a MAX_PUSH_ID frame with a large enough limit.
+    ///
+    /// On success the newly allocated push ID is returned.
+    ///
+    /// The [`IdError`] error is returned when the client did not allow any more
+    /// pushes. The [`StreamBlocked`] error is returned when the underlying
+    /// QUIC stream doesn't have enough capacity for the operation to complete.
+    /// When this happens the application should retry the operation once the
+    /// stream is reported as writable again.
+    ///
+    /// [`send_push_response()`]: struct.Connection.html#method.send_push_response
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_push_promise<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+    ) -> Result<u64> {
+        if !self.is_server ||
+            stream_id % 4 != 0 ||
+            !self.streams.contains_key(&stream_id)
+        {
+            return Err(Error::FrameUnexpected);
+        }
+
+        let push_id = self.next_push_id;
+
+        match self.max_push_id {
+            Some(max) if push_id <= max => (),
+
+            _ => return Err(Error::IdError),
+        }
+
+        // The client asked not to receive any more pushes.
+        if let Some(goaway_id) = self.peer_goaway_id {
+            if push_id >= goaway_id {
+                return Err(Error::IdError);
+            }
+        }
+
+        let header_block = self.encode_header_block(headers)?;
+        let header_block_len = header_block.len();
+
+        let frame = frame::Frame::PushPromise {
+            push_id,
+            header_block,
+        };
+
+        let mut d = vec![0; header_block_len + 24];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let wire_len = frame.to_bytes(&mut b)?;
+
+        // The promise needs to be sent atomically, so make sure the stream has
+        // enough capacity.
+        match conn.stream_writable(stream_id, wire_len) {
+            Ok(true) => (),
+
+            Ok(false) => return Err(Error::StreamBlocked),
+
+            Err(e) => return Err(e.into()),
+        };
+
+        conn.stream_send(stream_id, &d[..wire_len], false)?;
+
+        trace!(
+            "{} tx frm PUSH_PROMISE stream={} push_id={} len={}",
+            conn.trace_id(),
+            stream_id,
+            push_id,
+            header_block_len
+        );
+
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: Some(
+                    (octets::varint_len(push_id) + header_block_len) as u64,
+                ),
+                frame: frame.to_qlog(),
+                raw: None,
+            });
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        self.next_push_id =
+            self.next_push_id.checked_add(1).ok_or(Error::IdError)?;
+
+        Ok(push_id)
+    }
+
+    /// Sends the response of a promised server push.
+    ///
+    /// This opens a new push stream for the given `push_id`, as returned by
+    /// [`send_push_promise()`], and sends the provided `headers` on it. To
+    /// include a body, set `fin` as `false` and subsequently call
+    /// [`send_body()`] with the same `conn` and the `stream_id` returned from
+    /// this method.
+    ///
+    /// On success the ID of the push stream is returned.
+    ///
+    /// The [`IdError`] error is returned when the push ID was never promised.
+    /// The [`RequestCancelled`] error is returned when the push was cancelled
+    /// by either endpoint. The [`StreamBlocked`] error is returned when the
+    /// underlying QUIC stream doesn't have enough capacity for the operation
+    /// to complete. When this happens the application should retry the
+    /// operation once the stream is reported as writable again.
+    ///
+    /// [`send_push_promise()`]: struct.Connection.html#method.send_push_promise
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`RequestCancelled`]: enum.Error.html#variant.RequestCancelled
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_push_response<T: NameValue>(
+        &mut self, conn: &mut super::Connection, push_id: u64, headers: &[T],
+        fin: bool,
+    ) -> Result<u64> {
+        if !self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
+
+        if push_id >= self.next_push_id {
+            return Err(Error::IdError);
+        }
+
+        if self.cancelled_pushes.contains(&push_id) {
+            return Err(Error::RequestCancelled);
+        }
+
+        // A previous attempt might have opened the push stream without being
+        // able to send the headers, in which case the stream is reused.
+        let stream_id = match self.push_streams.get(&push_id) {
+            Some(v) => *v,
+
+            None => {
+                let stream_id = self
+                    .open_uni_stream(conn, stream::HTTP3_PUSH_STREAM_TYPE_ID)?;
+
+                let mut d = [0; 8];
+                let mut b = octets::OctetsMut::with_slice(&mut d);
+                conn.stream_send(stream_id, b.put_varint(push_id)?, false)?;
+
+                qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+                    let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                        stream_id,
+                        owner: Some(H3Owner::Local),
+                        stream_type: H3StreamType::Push,
+                        stream_type_value: None,
+                        associated_push_id: Some(push_id),
+                    });
+
+                    q.add_event_data_now(ev_data).ok();
+                });
+
+                self.streams.insert(
+                    stream_id,
+                    stream::Stream::new_push(stream_id, push_id),
+                );
+                self.push_streams.insert(push_id, stream_id);
+
+                stream_id
+            },
+        };
+
+        match self.streams.get(&stream_id) {
+            Some(s) if !s.local_initialized() => (),
+
+            _ => return Err(Error::FrameUnexpected),
+        }
+
+        self.send_headers(conn, stream_id, headers, fin)?;
+
+        Ok(stream_id)
+    }
+
+    /// Returns the push ID associated with the given push stream, if any.
+    pub fn push_id(&self, stream_id: u64) -> Option<u64> {
+        self.streams.get(&stream_id).and_then(|s| s.push_id())
+    }
+
     fn encode_header_block<T: NameValue>(
         &mut self, headers: &[T],
     ) -> Result<Vec<u8>> {
@@ -1299,7 +1620,13 @@ impl Connection {
         let mut b = octets::OctetsMut::with_slice(&mut d);
 
         // Validate that it is sane to send data on the stream.
-        if stream_id % 4 != 0 {
+        let is_local_push = self.is_server &&
+            matches!(
+                self.streams.get(&stream_id).and_then(|s| s.ty()),
+                Some(stream::Type::Push)
+            );
+
+        if stream_id % 4 != 0 && !is_local_push {
             return Err(Error::FrameUnexpected);
         }
 
@@ -1751,24 +2078,24 @@ impl Connection {
     /// When quiche is used in the server role, the `id` parameter is the stream
     /// ID of the highest processed request. This can be any valid ID between 0
     /// and 2^62-4. However, the ID cannot be increased. Failure to satisfy
-    /// these conditions will return an error.
+    /// these conditions will return an error. Once the GOAWAY is sent, new
+    /// requests received on streams with an ID greater than or equal to `id`
+    /// are rejected with the H3_REQUEST_REJECTED error code without being
+    /// reported to the application, while requests that are already in
+    /// progress can be completed as usual.
+    ///
+    /// When quiche is used in the client role, the `id` parameter is the
+    /// smallest push ID the server is no longer allowed to promise. The ID
+    /// cannot be increased either.
     ///
     /// This method does not close the QUIC connection. Applications are
-    /// required to call [`close()`] themselves.
+    /// required to call [`close()`] themselves, for example once all in-flight
+    /// requests have completed.
     ///
     /// [`close()`]: ../struct.Connection.html#method.close
     pub fn send_goaway(
         &mut self, conn: &mut super::Connection, id: u64,
     ) -> Result<()> {
-        let mut id = id;
-
-        // TODO: server push
-        //
-        // In the meantime always send 0 from client.
-        if !self.is_server {
-            id = 0;
-        }
-
         if self.is_server && id % 4 != 0 {
             return Err(Error::IdError);
         }
@@ -1814,6 +2141,64 @@ impl Connection {
         Ok(())
     }
 
+    /// Sends a MAX_PUSH_ID frame to allow the server to push responses.
+    ///
+    /// The `push_id` parameter is the maximum push ID the server is allowed to
+    /// use. The limit cannot be reduced once sent, in which case the
+    /// [`IdError`] error is returned.
+    ///
+    /// Only clients can send MAX_PUSH_ID frames.
+    ///
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn send_max_push_id(
+        &mut self, conn: &mut super::Connection, push_id: u64,
+    ) -> Result<()> {
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
+
+        if let Some(max) = self.local_max_push_id {
+            if push_id < max {
+                return Err(Error::IdError);
+            }
+        }
+
+        self.send_control_frame(conn, &frame::Frame::MaxPushId { push_id })?;
+
+        self.local_max_push_id = Some(push_id);
+
+        Ok(())
+    }
+
+    /// Cancels a server push.
+    ///
+    /// When used by a client, this indicates that the promised response is not
+    /// wanted, and the push stream is aborted if it was already opened. When
+    /// used by a server, this withdraws a previously sent promise, and the push
+    /// stream is reset if it was already opened.
+    ///
+    /// The [`IdError`] error is returned when the push ID was never allowed
+    /// (for clients) or promised (for servers).
+    ///
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn cancel_push(
+        &mut self, conn: &mut super::Connection, push_id: u64,
+    ) -> Result<()> {
+        if !self.is_valid_push_id(push_id) {
+            return Err(Error::IdError);
+        }
+
+        if self.cancelled_pushes.contains(&push_id) {
+            return Err(Error::Done);
+        }
+
+        self.send_control_frame(conn, &frame::Frame::CancelPush { push_id })?;
+
+        self.cancel_push_stream(conn, push_id)?;
+
+        Ok(())
+    }
+
     /// Gets the raw settings from peer including unknown and reserved types.
     ///
     /// The order of settings is the same as received in the SETTINGS frame.
@@ -1837,8 +2222,14 @@ impl Connection {
                 conn.stream_priority(stream_id, 0, false)?;
             },
 
-            // TODO: Server push
-            stream::HTTP3_PUSH_STREAM_TYPE_ID => (),
+            // Push streams carry responses, so use the default priority.
+            stream::HTTP3_PUSH_STREAM_TYPE_ID => {
+                conn.stream_priority(
+                    stream_id,
+                    PRIORITY_URGENCY_DEFAULT + PRIORITY_URGENCY_OFFSET,
+                    PRIORITY_INCREMENTAL_DEFAULT,
+                )?;
+            },
 
             // Anything else is a GREASE stream, so make it the least important.
             _ => {
@@ -1858,6 +2249,75 @@ impl Connection {
         Ok(stream_id)
     }
 
+    fn send_control_frame(
+        &mut self, conn: &mut super::Connection, frame: &frame::Frame,
+    ) -> Result<()> {
+        let stream_id = self.control_stream_id.ok_or(Error::InternalError)?;
+
+        let mut d = [42; 16];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let wire_len = frame.to_bytes(&mut b)?;
+        let stream_cap = conn.stream_capacity(stream_id)?;
+
+        if stream_cap < wire_len {
+            return Err(Error::StreamBlocked);
+        }
+
+        trace!("{} tx frm {:?}", conn.trace_id(), frame);
+
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: None,
+                frame: frame.to_qlog(),
+                raw: None,
+            });
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        conn.stream_send(stream_id, &d[..wire_len], false)?;
+
+        Ok(())
+    }
+
+    /// Returns whether the given push ID can be referenced by the peer.
+    fn is_valid_push_id(&self, push_id: u64) -> bool {
+        if self.is_server {
+            push_id < self.next_push_id
+        } else {
+            matches!(self.local_max_push_id, Some(max) if push_id <= max)
+        }
+    }
+
+    /// Marks the given push as cancelled and aborts its stream, if any.
+    fn cancel_push_stream(
+        &mut self, conn: &mut super::Connection, push_id: u64,
+    ) -> Result<()> {
+        self.cancelled_pushes.insert(push_id);
+
+        if let Some(stream_id) = self.push_streams.get(&push_id) {
+            let direction = if self.is_server {
+                crate::Shutdown::Write
+            } else {
+                crate::Shutdown::Read
+            };
+
+            match conn.stream_shutdown(
+                *stream_id,
+                direction,
+                Error::RequestCancelled.to_wire(),
+            ) {
+                Ok(()) | Err(crate::Error::Done) => (),
+
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
     fn open_qpack_encoder_stream(
         &mut self, conn: &mut super::Connection,
     ) -> Result<()> {
@@ -2154,6 +2614,21 @@ impl Connection {
     fn process_readable_stream(
         &mut self, conn: &mut super::Connection, stream_id: u64, polling: bool,
     ) -> Result<(u64, Event)> {
+        // After sending GOAWAY, new requests above the advertised ID are
+        // refused.
+        if self.is_server &&
+            crate::stream::is_bidi(stream_id) &&
+            !self.streams.contains_key(&stream_id)
+        {
+            if let Some(goaway_id) = self.local_goaway_id {
+                if stream_id >= goaway_id {
+                    self.reject_request(conn, stream_id)?;
+
+                    return Err(Error::Done);
+                }
+            }
+        }
+
         self.streams
             .entry(stream_id)
             .or_insert_with(|| stream::Stream::new(stream_id, false));
@@ -2290,6 +2765,30 @@ impl Connection {
                         conn.close(true, e.to_wire(), b"")?;
                         return Err(e);
                     }
+
+                    if !self.is_valid_push_id(varint) {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"Push stream received with ID above MAX_PUSH_ID",
+                        )?;
+
+                        return Err(Error::IdError);
+                    }
+
+                    self.push_streams.insert(varint, stream_id);
+
+                    // The push was cancelled before its stream was opened, so
+                    // abort reading it right away.
+                    if self.cancelled_pushes.contains(&varint) {
+                        conn.stream_shutdown(
+                            stream_id,
+                            crate::Shutdown::Read,
+                            Error::RequestCancelled.to_wire(),
+                        )?;
+
+                        return Err(Error::Done);
+                    }
                 },
 
                 stream::State::FrameType => {
@@ -2450,14 +2949,63 @@ impl Connection {
         Err(Error::Done)
     }
 
-    fn process_finished_stream(&mut self, stream_id: u64) {
-        let stream = match self.streams.get_mut(&stream_id) {
-            Some(v) => v,
+    fn reject_request(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+    ) -> Result<()> {
+        trace!(
+            "{} rejecting request stream {} after GOAWAY",
+            conn.trace_id(),
+            stream_id
+        );
 
-            None => return,
-        };
+        let code = Error::RequestRejected.to_wire();
 
-        if stream.state() == stream::State::Finished {
+        for direction in [crate::Shutdown::Read, crate::Shutdown::Write] {
+            match conn.stream_shutdown(stream_id, direction, code) {
+                Ok(()) | Err(crate::Error::Done) => (),
+
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "sfv")]
+    fn process_priority_header(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        headers: &[Header],
+    ) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        // A PRIORITY_UPDATE received before the headers takes precedence.
+        if stream.priority().is_some() {
+            return;
+        }
+
+        let priority = headers
+            .iter()
+            .find(|h| h.name() == b"priority")
+            .and_then(|h| Priority::try_from(h.value()).ok())
+            .unwrap_or_default();
+
+        stream.set_priority(priority);
+
+        Self::apply_priority(conn, stream_id, &priority).ok();
+    }
+
+    fn process_finished_stream(&mut self, stream_id: u64) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if stream.state() == stream::State::Finished {
             return;
         }
 
@@ -2472,6 +3020,33 @@ impl Connection {
         };
     }
 
+    fn decode_header_block(
+        &mut self, conn: &mut super::Connection, header_block: &[u8],
+    ) -> Result<Vec<Header>> {
+        // Use "infinite" as default value for max_field_section_size if
+        // it is not configured by the application.
+        let max_size = self
+            .local_settings
+            .max_field_section_size
+            .unwrap_or(u64::MAX);
+
+        match self.qpack_decoder.decode(header_block, max_size) {
+            Ok(v) => Ok(v),
+
+            Err(e) => {
+                let e = match e {
+                    qpack::Error::HeaderListTooLarge => Error::ExcessiveLoad,
+
+                    _ => Error::QpackDecompressionFailed,
+                };
+
+                conn.close(true, e.to_wire(), b"Error parsing headers.")?;
+
+                Err(e)
+            },
+        }
+    }
+
     fn process_frame(
         &mut self, conn: &mut super::Connection, stream_id: u64,
         frame: frame::Frame, payload_len: u64,
@@ -2545,32 +3120,7 @@ impl Connection {
                     return Err(Error::FrameUnexpected);
                 }
 
-                // Use "infinite" as default value for max_field_section_size if
-                // it is not configured by the application.
-                let max_size = self
-                    .local_settings
-                    .max_field_section_size
-                    .unwrap_or(u64::MAX);
-
-                let headers = match self
-                    .qpack_decoder
-                    .decode(&header_block[..], max_size)
-                {
-                    Ok(v) => v,
-
-                    Err(e) => {
-                        let e = match e {
-                            qpack::Error::HeaderListTooLarge =>
-                                Error::ExcessiveLoad,
-
-                            _ => Error::QpackDecompressionFailed,
-                        };
-
-                        conn.close(true, e.to_wire(), b"Error parsing headers.")?;
-
-                        return Err(e);
-                    },
-                };
+                let headers = self.decode_header_block(conn, &header_block)?;
 
                 qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
                     let qlog_headers = headers
@@ -2596,6 +3146,11 @@ impl Connection {
                     q.add_event_data_now(ev_data).ok();
                 });
 
+                #[cfg(feature = "sfv")]
+                if self.is_server && self.extensible_priorities {
+                    self.process_priority_header(conn, stream_id, &headers);
+                }
+
                 let has_body = !conn.stream_finished(stream_id);
 
                 return Ok((stream_id, Event::Headers {
@@ -2677,20 +3232,25 @@ impl Connection {
                     return Err(Error::FrameUnexpected);
                 }
 
-                if push_id < self.max_push_id {
-                    conn.close(
-                        true,
-                        Error::IdError.to_wire(),
-                        b"MAX_PUSH_ID reduced limit",
-                    )?;
+                if let Some(max) = self.max_push_id {
+                    if push_id < max {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"MAX_PUSH_ID reduced limit",
+                        )?;
 
-                    return Err(Error::IdError);
+                        return Err(Error::IdError);
+                    }
                 }
 
-                self.max_push_id = push_id;
+                self.max_push_id = Some(push_id);
             },
 
-            frame::Frame::PushPromise { .. } => {
+            frame::Frame::PushPromise {
+                push_id,
+                header_block,
+            } => {
                 if self.is_server {
                     conn.close(
                         true,
@@ -2711,10 +3271,31 @@ impl Connection {
                     return Err(Error::FrameUnexpected);
                 }
 
-                // TODO: implement more checks and PUSH_PROMISE event
+                if !self.is_valid_push_id(push_id) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PUSH_PROMISE received with ID above MAX_PUSH_ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                let headers = self.decode_header_block(conn, &header_block)?;
+
+                // Promises for pushes that were already cancelled are not
+                // reported to the application.
+                if self.cancelled_pushes.contains(&push_id) {
+                    return Err(Error::Done);
+                }
+
+                return Ok((stream_id, Event::PushPromise {
+                    push_id,
+                    list: headers,
+                }));
             },
 
-            frame::Frame::CancelPush { .. } => {
+            frame::Frame::CancelPush { push_id } => {
                 if Some(stream_id) != self.peer_control_stream_id {
                     conn.close(
                         true,
@@ -2725,7 +3306,23 @@ impl Connection {
                     return Err(Error::FrameUnexpected);
                 }
 
-                // TODO: implement CANCEL_PUSH frame
+                if !self.is_valid_push_id(push_id) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"CANCEL_PUSH received with unknown push ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                if self.cancelled_pushes.contains(&push_id) {
+                    return Err(Error::Done);
+                }
+
+                self.cancel_push_stream(conn, push_id)?;
+
+                return Ok((stream_id, Event::PushCancelled { push_id }));
             },
 
             frame::Frame::PriorityUpdateRequest {
@@ -2789,6 +3386,25 @@ impl Connection {
                     );
 
                 let had_priority_update = stream.has_last_priority_update();
+
+                // The update overrides any priority signaled by the request's
+                // header, so apply it right away when the stream exists.
+                #[cfg(feature = "sfv")]
+                if self.extensible_priorities {
+                    if let Ok(priority) =
+                        Priority::try_from(priority_field_value.as_slice())
+                    {
+                        stream.set_priority(priority);
+
+                        Self::apply_priority(
+                            conn,
+                            prioritized_element_id,
+                            &priority,
+                        )
+                        .ok();
+                    }
+                }
+
                 stream.set_last_priority_update(Some(priority_field_value));
 
                 // Only trigger the event when there wasn't already a stored
@@ -3219,6 +3835,29 @@ mod tests {
         assert!(grease_value() < 2u64.pow(62) - 1);
     }
 
+    #[test]
+    fn wire_error_code_from_u64() {
+        for err in [
+            Error::InternalError,
+            Error::FrameUnexpected,
+            Error::RequestRejected,
+            Error::RequestCancelled,
+            Error::VersionFallback,
+        ] {
+            assert_eq!(
+                WireErrorCode::try_from(err.to_wire()).map(|c| c as u64),
+                Ok(err.to_wire())
+            );
+        }
+
+        assert_eq!(WireErrorCode::try_from(0x100), Ok(WireErrorCode::NoError));
+
+        // Reserved and unknown codes.
+        assert_eq!(WireErrorCode::try_from(0x21), Err(Error::Done));
+        assert_eq!(WireErrorCode::try_from(0x111), Err(Error::Done));
+        assert_eq!(WireErrorCode::try_from(0x0), Err(Error::Done));
+    }
+
     #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
     fn h3_handshake_0rtt() {
@@ -3918,6 +4557,47 @@ mod tests {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
+        s.client.send_max_push_id(&mut s.pipe.client, 2).unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &req)
+            .unwrap();
+        assert_eq!(push_id, 0);
+
+        s.send_frame_client(
+            frame::Frame::CancelPush { push_id },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((s.client.control_stream_id.unwrap(), Event::PushCancelled {
+                push_id
+            }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a CANCEL_PUSH frame from the client for a push that was never
+    /// promised.
+    fn cancel_push_from_client_unknown_id() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
         s.send_frame_client(
             frame::Frame::CancelPush { push_id: 1 },
             s.client.control_stream_id.unwrap(),
@@ -3925,7 +4605,7 @@ mod tests {
         )
         .unwrap();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::IdError));
     }
 
     #[test]
@@ -3953,11 +4633,14 @@ mod tests {
     }
 
     #[test]
-    /// Send a CANCEL_PUSH frame from the client.
+    /// Send a CANCEL_PUSH frame from the server.
     fn cancel_push_from_server() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
+        s.client.send_max_push_id(&mut s.pipe.client, 2).unwrap();
+        s.advance().ok();
+
         s.send_frame_server(
             frame::Frame::CancelPush { push_id: 1 },
             s.server.control_stream_id.unwrap(),
@@ -3965,9 +4648,269 @@ mod tests {
         )
         .unwrap();
 
+        assert_eq!(
+            s.poll_client(),
+            Ok((s.server.control_stream_id.unwrap(), Event::PushCancelled {
+                push_id: 1
+            }))
+        );
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a CANCEL_PUSH frame from the server with a push ID that was never
+    /// allowed by the client.
+    fn cancel_push_from_server_above_max_push_id() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.send_frame_server(
+            frame::Frame::CancelPush { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_client(), Err(Error::IdError));
+    }
+
+    #[test]
+    /// Push a response from the server.
+    fn server_push() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_max_push_id(&mut s.pipe.client, 0).unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        let promise = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/style.css"),
+        ];
+
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &promise)
+            .unwrap();
+        assert_eq!(push_id, 0);
+
+        // The client only allowed a single push.
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &promise),
+            Err(Error::IdError)
+        );
+
+        s.send_response(stream, true).unwrap();
+
+        let ev_promise = Event::PushPromise {
+            push_id,
+            list: promise,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_promise)));
+
+        let resp = vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"server", b"quiche-test"),
+        ];
+
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, push_id, &resp, false)
+            .unwrap();
+        assert!(!crate::stream::is_bidi(push_stream));
+
+        let body = s.send_body_server(push_stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        let mut events = Vec::new();
+        while let Ok(ev) = s.poll_client() {
+            events.push(ev);
+        }
+
+        assert!(events.contains(&(stream, Event::Finished)));
+        assert!(events.contains(&(push_stream, ev_headers)));
+        assert!(events.contains(&(push_stream, Event::Data)));
+
+        assert_eq!(s.client.push_id(push_stream), Some(push_id));
+        assert_eq!(
+            s.recv_body_client(push_stream, &mut recv_buf),
+            Ok(body.len())
+        );
+        assert_eq!(recv_buf, body);
+
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
+    #[test]
+    /// Send a PUSH_PROMISE without the client allowing any pushes.
+    fn push_promise_without_max_push_id() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Err(Error::IdError)
+        );
+
+        let header_block = s.server.encode_header_block(&req).unwrap();
+
+        s.send_frame_server(
+            frame::Frame::PushPromise {
+                push_id: 0,
+                header_block,
+            },
+            stream,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_client(), Err(Error::IdError));
+    }
+
+    #[test]
+    /// Cancel a push from the client after it was promised.
+    fn client_cancel_push() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_max_push_id(&mut s.pipe.client, 4).unwrap();
+
+        // The limit can't be reduced.
+        assert_eq!(
+            s.client.send_max_push_id(&mut s.pipe.client, 3),
+            Err(Error::IdError)
+        );
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &req)
+            .unwrap();
+        s.advance().ok();
+
+        let ev_promise = Event::PushPromise {
+            push_id,
+            list: req.clone(),
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_promise)));
+
+        s.client.cancel_push(&mut s.pipe.client, push_id).unwrap();
+        assert_eq!(
+            s.client.cancel_push(&mut s.pipe.client, push_id),
+            Err(Error::Done)
+        );
+
+        // Pushes that were never allowed can't be cancelled.
+        assert_eq!(
+            s.client.cancel_push(&mut s.pipe.client, 5),
+            Err(Error::IdError)
+        );
+
+        s.advance().ok();
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((s.client.control_stream_id.unwrap(), Event::PushCancelled {
+                push_id
+            }))
+        );
+
+        assert_eq!(
+            s.server
+                .send_push_response(&mut s.pipe.server, push_id, &req, true),
+            Err(Error::RequestCancelled)
+        );
+    }
+
+    #[test]
+    /// Cancel a push from the server after its stream was opened.
+    fn server_cancel_push() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_max_push_id(&mut s.pipe.client, 4).unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &req)
+            .unwrap();
+
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, push_id, &req, false)
+            .unwrap();
+
+        s.server.cancel_push(&mut s.pipe.server, push_id).unwrap();
+        s.advance().ok();
+
+        let mut events = Vec::new();
+        while let Ok(ev) = s.poll_client() {
+            events.push(ev);
+        }
+
+        assert!(events.contains(&(
+            s.server.control_stream_id.unwrap(),
+            Event::PushCancelled { push_id }
+        )));
+
+        assert_eq!(
+            s.server
+                .send_body(&mut s.pipe.server, push_stream, b"", true),
+            Err(Error::TransportError(crate::Error::InvalidStreamState(
+                push_stream
+            )))
+        );
+    }
+
     #[test]
     /// Send a GOAWAY frame from the client.
     fn goaway_from_client_good() {
@@ -3978,8 +4921,7 @@ mod tests {
 
         s.advance().ok();
 
-        // TODO: server push
-        assert_eq!(s.poll_server(), Ok((0, Event::GoAway)));
+        assert_eq!(s.poll_server(), Ok((100, Event::GoAway)));
     }
 
     #[test]
@@ -4010,6 +4952,91 @@ mod tests {
         assert_eq!(s.send_request(true), Err(Error::FrameUnexpected));
     }
 
+    #[test]
+    /// Requests above the GOAWAY ID are rejected by the server, while requests
+    /// in progress can complete.
+    fn server_rejects_requests_after_goaway() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        s.server.send_goaway(&mut s.pipe.server, 4).unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_client(), Ok((4, Event::GoAway)));
+
+        // Send a request from the client on a stream it allocates itself, as
+        // the HTTP/3 layer refuses to do so after receiving GOAWAY.
+        let rejected =
+            s.send_arbitrary_stream_data_client(&[0x01, 0x00], 4, true);
+        assert_eq!(rejected, Ok(()));
+
+        // The rejected request is never reported to the server application.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.advance().ok();
+
+        let mut b = [0; 1];
+        assert_eq!(
+            s.pipe.client.stream_recv(4, &mut b),
+            Err(crate::Error::StreamReset(Error::RequestRejected.to_wire()))
+        );
+
+        // The request that was already in progress completes normally.
+        let body = s.send_body_client(stream, true).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        let mut recv_buf = vec![0; body.len()];
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        s.send_response(stream, true).unwrap();
+    }
+
+    #[test]
+    /// The server can't promise pushes above the client's GOAWAY ID.
+    fn server_push_after_client_goaway() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_max_push_id(&mut s.pipe.client, 10).unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Ok(0)
+        );
+
+        s.client.send_goaway(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((1, Event::GoAway)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        assert_eq!(
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Err(Error::IdError)
+        );
+    }
+
     #[test]
     /// Send a GOAWAY frame from the server, using an invalid goaway ID.
     fn goaway_from_server_invalid_id() {
@@ -4156,6 +5183,120 @@ mod tests {
         assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
+    #[test]
+    #[cfg(feature = "sfv")]
+    /// Priorities signaled by the client are applied to the server's streams.
+    fn extensible_priorities() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.enable_extensible_priorities(true);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        s.handshake().unwrap();
+
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+            Header::new(b"priority", b"u=1, i"),
+        ];
+
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, false)
+            .unwrap();
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        let server_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(server_stream.urgency, 1 + PRIORITY_URGENCY_OFFSET);
+        assert!(server_stream.incremental);
+
+        // A PRIORITY_UPDATE overrides the header.
+        s.client
+            .send_priority_update_for_request(
+                &mut s.pipe.client,
+                stream,
+                &Priority::new(5, false),
+            )
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let server_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(server_stream.urgency, 5 + PRIORITY_URGENCY_OFFSET);
+        assert!(!server_stream.incremental);
+
+        // The response uses the signaled priority.
+        s.send_response(stream, false).unwrap();
+
+        let server_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(server_stream.urgency, 5 + PRIORITY_URGENCY_OFFSET);
+        assert!(!server_stream.incremental);
+    }
+
+    #[test]
+    #[cfg(feature = "sfv")]
+    /// Priorities signaled by the client are ignored unless enabled.
+    fn extensible_priorities_disabled() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+            Header::new(b"priority", b"u=1, i"),
+        ];
+
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, true)
+            .unwrap();
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        s.send_response(stream, false).unwrap();
+
+        let server_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(
+            server_stream.urgency,
+            PRIORITY_URGENCY_DEFAULT + PRIORITY_URGENCY_OFFSET
+        );
+        assert!(!server_stream.incremental);
+    }
+
     #[test]
     /// Send a PRIORITY_UPDATE for request stream from the client.
     fn priority_update_single_stream_rearm() {
@@ -28,6 +28,7 @@ use super::Error;
 use super::Result;
 
 use crate::h3::Header;
+use crate::redact::Redacted;
 
 use super::INDEXED;
 use super::INDEXED_WITH_POST_BASE;
@@ -146,9 +147,9 @@ impl Decoder {
                     let value = decode_str(&mut b)?;
 
                     trace!(
-                        "Literal Without Name Reference name={:?} value={:?}",
+                        "Literal Without Name Reference name={:?} value={}",
                         name,
-                        value,
+                        Redacted(&value),
                     );
 
                     left = left
@@ -169,10 +170,10 @@ impl Decoder {
                     let value = decode_str(&mut b)?;
 
                     trace!(
-                        "Literal name_idx={} static={} value={:?}",
+                        "Literal name_idx={} static={} value={}",
                         name_idx,
                         s,
-                        value
+                        Redacted(&value)
                     );
 
                     if !s {
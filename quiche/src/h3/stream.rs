@@ -25,6 +25,7 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use super::Error;
+use super::Priority;
 use super::Result;
 
 use super::frame;
@@ -156,6 +157,12 @@ pub struct Stream {
 
     /// The last `PRIORITY_UPDATE` frame encoded field value, if any.
     last_priority_update: Option<Vec<u8>>,
+
+    /// The priority signaled by the peer, if any.
+    priority: Option<Priority>,
+
+    /// The push ID associated with the stream, if it is a push stream.
+    push_id: Option<u64>,
 }
 
 impl Stream {
@@ -196,9 +203,23 @@ impl Stream {
             data_event_triggered: false,
 
             last_priority_update: None,
+
+            priority: None,
+
+            push_id: None,
         }
     }
 
+    /// Creates a new locally-initiated HTTP/3 push stream.
+    pub fn new_push(id: u64, push_id: u64) -> Stream {
+        let mut stream = Stream::new(id, true);
+
+        stream.ty = Some(Type::Push);
+        stream.push_id = Some(push_id);
+
+        stream
+    }
+
     pub fn ty(&self) -> Option<Type> {
         self.ty
     }
@@ -207,6 +228,11 @@ impl Stream {
         self.state
     }
 
+    /// Returns the push ID associated with the stream, if any.
+    pub fn push_id(&self) -> Option<u64> {
+        self.push_id
+    }
+
     /// Sets the stream's type and transitions to the next state.
     pub fn set_ty(&mut self, ty: Type) -> Result<()> {
         assert_eq!(self.state, State::StreamType);
@@ -233,10 +259,10 @@ impl Stream {
     }
 
     /// Sets the push ID and transitions to the next state.
-    pub fn set_push_id(&mut self, _id: u64) -> Result<()> {
+    pub fn set_push_id(&mut self, id: u64) -> Result<()> {
         assert_eq!(self.state, State::PushId);
 
-        // TODO: implement push ID.
+        self.push_id = Some(id);
 
         self.state_transition(State::FrameType, 1, true)?;
 
@@ -598,6 +624,17 @@ impl Stream {
         self.last_priority_update.is_some()
     }
 
+    /// Sets the priority signaled by the peer.
+    #[cfg(feature = "sfv")]
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = Some(priority);
+    }
+
+    /// Returns the priority signaled by the peer, if any.
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
     /// Returns true if the state buffer has enough data to complete the state.
     fn state_buffer_complete(&self) -> bool {
         self.state_off == self.state_len
@@ -1065,6 +1102,7 @@ mod tests {
 
         stream.set_push_id(push_id).unwrap();
         assert_eq!(stream.state, State::FrameType);
+        assert_eq!(stream.push_id(), Some(1));
 
         // Parse the HEADERS frame type.
         stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
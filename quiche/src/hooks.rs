@@ -0,0 +1,147 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::packet;
+use crate::recovery::CongestionTransition;
+
+/// A callback invoked for every packet sent by a connection.
+pub type TxHook = Box<dyn FnMut(&TxEvent) + Send + Sync>;
+
+/// A callback invoked for every ACK frame processed by a connection.
+pub type AckHook = Box<dyn FnMut(&AckEvent) + Send + Sync>;
+
+/// A callback invoked for every state transition of a path's congestion
+/// controller.
+pub type CongestionHook = Box<dyn FnMut(&CongestionEvent) + Send + Sync>;
+
+/// Metadata about a packet that was just sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxEvent {
+    /// The packet number.
+    pub pkt_num: u64,
+
+    /// The type of the packet.
+    pub ty: packet::Type,
+
+    /// The size of the packet, in bytes.
+    pub size: usize,
+
+    /// Whether the packet is ack-eliciting.
+    pub ack_eliciting: bool,
+
+    /// Whether the packet counts towards bytes in flight.
+    pub in_flight: bool,
+
+    /// The number of bytes in flight on the path, including this packet.
+    pub bytes_in_flight: usize,
+
+    /// The congestion window of the path, in bytes.
+    pub cwnd: usize,
+
+    /// The smoothed round-trip time of the path.
+    pub rtt: Duration,
+
+    /// The local address the packet was sent from.
+    pub from: SocketAddr,
+
+    /// The remote address the packet was sent to.
+    pub to: SocketAddr,
+
+    /// The time the packet was sent.
+    pub time: Instant,
+}
+
+/// Metadata about an ACK frame that was just processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AckEvent {
+    /// The largest packet number acknowledged by the frame.
+    pub largest_acked: u64,
+
+    /// The number of bytes newly acknowledged on the path.
+    pub acked_bytes: usize,
+
+    /// The number of packets newly declared lost on the path.
+    pub lost_packets: usize,
+
+    /// The number of bytes newly declared lost on the path.
+    pub lost_bytes: usize,
+
+    /// The number of bytes in flight on the path, after processing the frame.
+    pub bytes_in_flight: usize,
+
+    /// The congestion window of the path, in bytes.
+    pub cwnd: usize,
+
+    /// The most recent round-trip time sample of the path.
+    pub latest_rtt: Duration,
+
+    /// The smoothed round-trip time of the path.
+    pub rtt: Duration,
+
+    /// The minimum round-trip time observed on the path, if any.
+    pub min_rtt: Option<Duration>,
+
+    /// The local address of the path.
+    pub from: SocketAddr,
+
+    /// The remote address of the path.
+    pub to: SocketAddr,
+
+    /// The time the frame was processed.
+    pub time: Instant,
+}
+
+/// A state transition of a path's congestion controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CongestionEvent {
+    /// The transition that happened.
+    pub transition: CongestionTransition,
+
+    /// The congestion window of the path after the transition, in bytes.
+    pub cwnd: usize,
+
+    /// The slow start threshold of the path after the transition, in bytes.
+    pub ssthresh: usize,
+
+    /// The number of bytes in flight on the path.
+    pub bytes_in_flight: usize,
+
+    /// The smoothed round-trip time of the path.
+    pub rtt: Duration,
+
+    /// The local address of the path.
+    pub from: SocketAddr,
+
+    /// The remote address of the path.
+    pub to: SocketAddr,
+
+    /// The time the transition was detected.
+    pub time: Instant,
+}
@@ -393,8 +393,6 @@ use qlog::events::quic::TransportEventType;
 #[cfg(feature = "qlog")]
 use qlog::events::DataRecipient;
 #[cfg(feature = "qlog")]
-use qlog::events::Event;
-#[cfg(feature = "qlog")]
 use qlog::events::EventData;
 #[cfg(feature = "qlog")]
 use qlog::events::EventImportance;
@@ -405,6 +403,7 @@ use qlog::events::RawInfo;
 use stream::StreamPriorityKey;
 
 use std::cmp;
+use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::time;
 
@@ -414,6 +413,7 @@ use std::net::SocketAddr;
 
 use std::str::FromStr;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
@@ -453,15 +453,25 @@ const MAX_ACK_RANGES: usize = 68;
 // The highest possible stream ID allowed.
 const MAX_STREAM_ID: u64 = 1 << 60;
 
+// The highest value that can be encoded as a variable-length integer.
+const MAX_VARINT: u64 = (1 << 62) - 1;
+
 // The default max_datagram_size used in congestion control.
 const MAX_SEND_UDP_PAYLOAD_SIZE: usize = 1200;
 
+// The largest UDP payload that can be advertised in the max_udp_payload_size
+// transport parameter.
+const MAX_RECV_UDP_PAYLOAD_SIZE: usize = 65527;
+
 // The default length of DATAGRAM queues.
 const DEFAULT_MAX_DGRAM_QUEUE_LEN: usize = 0;
 
 // The default length of PATH_CHALLENGE receive queue.
 const DEFAULT_MAX_PATH_CHALLENGE_RX_QUEUE_LEN: usize = 3;
 
+// The default length of the extension frame receive queue.
+const DEFAULT_MAX_EXTENSION_FRAME_RX_QUEUE_LEN: usize = 32;
+
 // The DATAGRAM standard recommends either none or 65536 as maximum DATAGRAM
 // frames size. We enforce the recommendation for forward compatibility.
 const MAX_DGRAM_FRAME_SIZE: u64 = 65536;
@@ -472,6 +482,9 @@ const PAYLOAD_LENGTH_LEN: usize = 2;
 // The number of undecryptable that can be buffered.
 const MAX_UNDECRYPTABLE_PACKETS: usize = 10;
 
+// The maximum number of connection events that can be queued.
+const MAX_EVENTS_QUEUE_LEN: usize = 1024;
+
 const RESERVED_VERSION_MASK: u32 = 0xfafafafa;
 
 // The default size of the receiver connection flow control window.
@@ -491,8 +504,12 @@ const MAX_PROBING_TIMEOUTS: usize = 3;
 // The default initial congestion window size in terms of packet count.
 const DEFAULT_INITIAL_CONGESTION_WINDOW_PACKETS: usize = 10;
 
-// The maximum data offset that can be stored in a crypto stream.
-const MAX_CRYPTO_STREAM_OFFSET: u64 = 1 << 16;
+// The default maximum data offset that can be stored in a crypto stream.
+const DEFAULT_MAX_CRYPTO_STREAM_OFFSET: u64 = 1 << 16;
+
+// The smallest maximum crypto stream offset that can be configured, so that
+// a typical handshake still fits.
+const MIN_MAX_CRYPTO_STREAM_OFFSET: u64 = 4096;
 
 /// A specialized [`Result`] type for quiche operations.
 ///
@@ -575,6 +592,17 @@ pub enum Error {
 
     /// The peer sent more data in CRYPTO frames than we can buffer.
     CryptoBufferExceeded,
+
+    /// The operation cannot be completed because the connection is closing,
+    /// draining or closed.
+    ///
+    /// The error code of the connection close is provided as associated data.
+    /// The full error, including the reason phrase, can be retrieved with
+    /// [`peer_error()`] or [`local_error()`].
+    ///
+    /// [`peer_error()`]: struct.Connection.html#method.peer_error
+    /// [`local_error()`]: struct.Connection.html#method.local_error
+    ConnectionClosed(u64),
 }
 
 /// QUIC error codes sent on the wire.
@@ -643,24 +671,90 @@ pub enum WireErrorCode {
     NoViablePath         = 0x10,
 }
 
+impl TryFrom<u64> for WireErrorCode {
+    type Error = crate::Error;
+
+    /// Converts a transport error code, e.g. one carried by a
+    /// `CONNECTION_CLOSE` frame and surfaced by [`local_error()`] or
+    /// [`peer_error()`].
+    ///
+    /// [`Done`] is returned for unknown error codes, as well as for the
+    /// `CRYPTO_ERROR` range which carries TLS alerts.
+    ///
+    /// [`local_error()`]: struct.Connection.html#method.local_error
+    /// [`peer_error()`]: struct.Connection.html#method.peer_error
+    /// [`Done`]: enum.Error.html#variant.Done
+    fn try_from(value: u64) -> std::result::Result<Self, Self::Error> {
+        let code = match value {
+            0x0 => WireErrorCode::NoError,
+            0x1 => WireErrorCode::InternalError,
+            0x2 => WireErrorCode::ConnectionRefused,
+            0x3 => WireErrorCode::FlowControlError,
+            0x4 => WireErrorCode::StreamLimitError,
+            0x5 => WireErrorCode::StreamStateError,
+            0x6 => WireErrorCode::FinalSizeError,
+            0x7 => WireErrorCode::FrameEncodingError,
+            0x8 => WireErrorCode::TransportParameterError,
+            0x9 => WireErrorCode::ConnectionIdLimitError,
+            0xa => WireErrorCode::ProtocolViolation,
+            0xb => WireErrorCode::InvalidToken,
+            0xc => WireErrorCode::ApplicationError,
+            0xd => WireErrorCode::CryptoBufferExceeded,
+            0xe => WireErrorCode::KeyUpdateError,
+            0xf => WireErrorCode::AeadLimitReached,
+            0x10 => WireErrorCode::NoViablePath,
+
+            _ => return Err(Error::Done),
+        };
+
+        Ok(code)
+    }
+}
+
 impl Error {
+    /// Returns the transport error code a connection is closed with when
+    /// processing a packet fails with this error.
+    ///
+    /// Errors caused by the peer map to the matching code, while those that
+    /// only arise from local conditions map to `INTERNAL_ERROR`.
     fn to_wire(self) -> u64 {
-        match self {
-            Error::Done => WireErrorCode::NoError as u64,
-            Error::InvalidFrame => WireErrorCode::FrameEncodingError as u64,
-            Error::InvalidStreamState(..) =>
-                WireErrorCode::StreamStateError as u64,
+        let code = match self {
+            Error::Done => WireErrorCode::NoError,
+
+            Error::InvalidFrame => WireErrorCode::FrameEncodingError,
+
+            Error::InvalidStreamState(..) => WireErrorCode::StreamStateError,
+
             Error::InvalidTransportParam =>
-                WireErrorCode::TransportParameterError as u64,
-            Error::FlowControl => WireErrorCode::FlowControlError as u64,
-            Error::StreamLimit => WireErrorCode::StreamLimitError as u64,
-            Error::IdLimit => WireErrorCode::ConnectionIdLimitError as u64,
-            Error::FinalSize => WireErrorCode::FinalSizeError as u64,
-            Error::CryptoBufferExceeded =>
-                WireErrorCode::CryptoBufferExceeded as u64,
-            Error::KeyUpdate => WireErrorCode::KeyUpdateError as u64,
-            _ => WireErrorCode::ProtocolViolation as u64,
-        }
+                WireErrorCode::TransportParameterError,
+
+            Error::FlowControl => WireErrorCode::FlowControlError,
+
+            Error::StreamLimit => WireErrorCode::StreamLimitError,
+
+            Error::IdLimit => WireErrorCode::ConnectionIdLimitError,
+
+            Error::FinalSize => WireErrorCode::FinalSizeError,
+
+            Error::CryptoBufferExceeded => WireErrorCode::CryptoBufferExceeded,
+
+            Error::KeyUpdate => WireErrorCode::KeyUpdateError,
+
+            Error::BufferTooShort |
+            Error::UnknownVersion |
+            Error::InvalidPacket |
+            Error::InvalidState |
+            Error::CryptoFail |
+            Error::TlsFail => WireErrorCode::ProtocolViolation,
+
+            Error::CongestionControl |
+            Error::OutOfIdentifiers |
+            Error::StreamStopped(..) |
+            Error::StreamReset(..) |
+            Error::ConnectionClosed(..) => WireErrorCode::InternalError,
+        };
+
+        code as u64
     }
 
     #[cfg(feature = "ffi")]
@@ -686,6 +780,7 @@ impl Error {
             Error::OutOfIdentifiers => -18,
             Error::KeyUpdate => -19,
             Error::CryptoBufferExceeded => -20,
+            Error::ConnectionClosed(_) => -21,
         }
     }
 }
@@ -729,7 +824,9 @@ pub struct SendInfo {
 
     /// The time to send the packet out.
     ///
-    /// See [Pacing] for more details.
+    /// This is never earlier than the time the packet was written, which is
+    /// also the value used when pacing is disabled. See [Pacing] for more
+    /// details.
     ///
     /// [Pacing]: index.html#pacing
     pub at: time::Instant,
@@ -748,6 +845,176 @@ pub struct ConnectionError {
     pub reason: Vec<u8>,
 }
 
+impl ConnectionError {
+    /// Returns the transport error code, if this is a transport error with a
+    /// code defined by [RFC 9000].
+    ///
+    /// [RFC 9000]: https://www.rfc-editor.org/rfc/rfc9000.html#name-error-codes
+    pub fn wire_error_code(&self) -> Option<WireErrorCode> {
+        if self.is_app {
+            return None;
+        }
+
+        WireErrorCode::try_from(self.error_code).ok()
+    }
+}
+
+/// The parameters negotiated during the handshake.
+///
+/// This is returned by [`handshake_info()`], and delivered along with the
+/// [`HandshakeCompleted`] event.
+///
+/// [`handshake_info()`]: struct.Connection.html#method.handshake_info
+/// [`HandshakeCompleted`]: enum.Event.html#variant.HandshakeCompleted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    /// The negotiated QUIC version.
+    pub version: u32,
+
+    /// The negotiated ALPN protocol, empty if none was negotiated.
+    pub application_proto: Vec<u8>,
+
+    /// The server name requested by the client, if any.
+    pub server_name: Option<String>,
+
+    /// The negotiated AEAD algorithm.
+    pub cipher: Option<crypto::Algorithm>,
+
+    /// The transport parameters sent by the peer.
+    pub peer_transport_params: TransportParams,
+
+    /// Whether the session was resumed.
+    pub resumed: bool,
+
+    /// Whether early data was accepted by the server.
+    pub early_data_accepted: bool,
+}
+
+/// A connection event.
+///
+/// Events are only queued when enabled with [`enable_events()`], and are
+/// retrieved with [`poll()`].
+///
+/// [`enable_events()`]: struct.Config.html#method.enable_events
+/// [`poll()`]: struct.Connection.html#method.poll
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The handshake has completed, with the given parameters.
+    HandshakeCompleted(Box<HandshakeInfo>),
+
+    /// The stream has data to read, or an error to report.
+    StreamReadable(u64),
+
+    /// The stream has become writable, after being blocked by flow control
+    /// or stopped by the peer.
+    StreamWritable(u64),
+
+    /// All data sent on the stream, including the fin flag, has been
+    /// acknowledged by the peer.
+    StreamFinished(u64),
+
+    /// The peer has closed the connection.
+    PeerClosed {
+        /// Whether the error came from the application or the transport layer.
+        is_app: bool,
+
+        /// The error code carried by the `CONNECTION_CLOSE` frame.
+        error_code: u64,
+
+        /// The reason carried by the `CONNECTION_CLOSE` frame.
+        reason: Vec<u8>,
+    },
+
+    /// The server has provided a token that can be used for future
+    /// connections.
+    NewToken(Vec<u8>),
+
+    /// The packet carrying a probe requested with [`probe_rtt()`] was
+    /// acknowledged, yielding the given round-trip time sample.
+    ///
+    /// [`probe_rtt()`]: struct.Connection.html#method.probe_rtt
+    RttSample(time::Duration),
+
+    /// The latency spin bit value sent on the given path changed, i.e. a new
+    /// round trip started.
+    ///
+    /// This is only raised on paths taking part in the spin bit, see
+    /// [`set_spin_bit_mode()`].
+    ///
+    /// [`set_spin_bit_mode()`]: struct.Config.html#method.set_spin_bit_mode
+    SpinBitFlipped {
+        /// The local address of the path.
+        local_addr: SocketAddr,
+
+        /// The peer address of the path.
+        peer_addr: SocketAddr,
+
+        /// The new spin bit value.
+        value: bool,
+    },
+
+    /// Packets of the given type were dropped because they couldn't be
+    /// decrypted, e.g. because they were corrupted or forged, or because the
+    /// keys needed to decrypt them aren't available.
+    ///
+    /// Drops are counted until the event is returned by [`poll()`], after
+    /// all other queued events, so that forged packets can't grow the event
+    /// queue.
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    UndecryptablePacket {
+        /// The type of the packets.
+        ty: Type,
+
+        /// The number of packets dropped.
+        count: u64,
+    },
+
+    /// Packets of the given type were dropped, because packets with the same
+    /// numbers were already received.
+    ///
+    /// Drops are counted until the event is returned by [`poll()`], after
+    /// all other queued events, so that replayed packets can't grow the
+    /// event queue.
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    DuplicatePacket {
+        /// The type of the packets.
+        ty: Type,
+
+        /// The number of packets dropped.
+        count: u64,
+    },
+
+    /// The peer sent more data than allowed by flow control, in a frame of
+    /// the given type. The connection is closed with a `FLOW_CONTROL_ERROR`.
+    FlowControlViolation(u64),
+
+    /// The probe timeout expired on the given path without an acknowledgement
+    /// having been received, so probe packets are sent.
+    ProbeTimeout {
+        /// The local address of the path.
+        local_addr: SocketAddr,
+
+        /// The peer address of the path.
+        peer_addr: SocketAddr,
+
+        /// How many consecutive times the probe timeout expired.
+        count: u32,
+    },
+
+    /// Persistent congestion was declared on the given path, following the
+    /// loss of all packets sent over a long enough period, and its
+    /// congestion window was collapsed to its minimum.
+    PersistentCongestion {
+        /// The local address of the path.
+        local_addr: SocketAddr,
+
+        /// The peer address of the path.
+        peer_addr: SocketAddr,
+    },
+}
+
 /// The side of the stream to be shut down.
 ///
 /// This should be used when calling [`stream_shutdown()`].
@@ -792,6 +1059,10 @@ pub struct Config {
 
     cc_algorithm: CongestionControlAlgorithm,
     initial_congestion_window_packets: usize,
+    minimum_congestion_window_packets: usize,
+
+    packet_reordering_threshold: u64,
+    time_reordering_threshold: f64,
 
     pmtud: bool,
 
@@ -809,10 +1080,48 @@ pub struct Config {
 
     max_connection_window: u64,
     max_stream_window: u64,
+    stream_window_update_threshold: f64,
 
     max_amplification_factor: usize,
 
+    max_crypto_stream_offset: u64,
+
+    max_frames_per_packet: usize,
+
+    max_early_data: u64,
+
+    stateless_reset_key: Option<ring::hmac::Key>,
+
     disable_dcid_reuse: bool,
+
+    spin_bit_mode: SpinBitMode,
+
+    trace_label: Option<String>,
+
+    multipath: bool,
+
+    autoflush: bool,
+
+    max_pre_handshake_data: usize,
+
+    send_coalescing_delay: Option<time::Duration>,
+
+    events: bool,
+
+    handshake_timeout: Option<time::Duration>,
+
+    cid_generator: Arc<dyn ConnectionIdGenerator>,
+
+    clock: Arc<dyn Clock>,
+
+    rng: Arc<dyn rand::Rng>,
+
+    extension_frames: HashMap<u64, Arc<dyn ExtensionFrameHandler>>,
+    extension_frame_recv_max_queue_len: usize,
+
+    client_hello_handler: Option<Arc<dyn ClientHelloHandler>>,
+
+    anti_replay: Option<Arc<dyn AntiReplay>>,
 }
 
 // See https://quicwg.org/base-drafts/rfc9000.html#section-15
@@ -862,6 +1171,9 @@ impl Config {
             cc_algorithm: CongestionControlAlgorithm::CUBIC,
             initial_congestion_window_packets:
                 DEFAULT_INITIAL_CONGESTION_WINDOW_PACKETS,
+            minimum_congestion_window_packets: recovery::MINIMUM_WINDOW_PACKETS,
+            packet_reordering_threshold: recovery::INITIAL_PACKET_THRESHOLD,
+            time_reordering_threshold: recovery::INITIAL_TIME_THRESHOLD,
             pmtud: false,
             hystart: true,
             pacing: true,
@@ -877,10 +1189,40 @@ impl Config {
 
             max_connection_window: MAX_CONNECTION_WINDOW,
             max_stream_window: stream::MAX_STREAM_WINDOW,
+            stream_window_update_threshold: flowcontrol::DEFAULT_UPDATE_THRESHOLD,
 
             max_amplification_factor: MAX_AMPLIFICATION_FACTOR,
 
+            max_crypto_stream_offset: DEFAULT_MAX_CRYPTO_STREAM_OFFSET,
+
+            max_frames_per_packet: usize::MAX,
+
+            max_early_data: u64::MAX,
+
+            stateless_reset_key: None,
+
             disable_dcid_reuse: false,
+            spin_bit_mode: SpinBitMode::Disabled,
+            trace_label: None,
+            multipath: false,
+            autoflush: true,
+            max_pre_handshake_data: 0,
+            send_coalescing_delay: None,
+            events: false,
+            handshake_timeout: None,
+
+            cid_generator: Arc::new(RandomConnectionIdGenerator::default()),
+
+            clock: Arc::new(SystemClock),
+            rng: Arc::new(rand::SystemRng),
+
+            extension_frames: HashMap::new(),
+            extension_frame_recv_max_queue_len:
+                DEFAULT_MAX_EXTENSION_FRAME_RX_QUEUE_LEN,
+
+            client_hello_handler: None,
+
+            anti_replay: None,
         })
     }
 
@@ -915,6 +1257,93 @@ impl Config {
         self.tls_ctx.use_privkey_file(file)
     }
 
+    /// Configures the given certificate chain from memory.
+    ///
+    /// The content of `pem` is parsed as a PEM-encoded leaf certificate,
+    /// followed by optional intermediate certificates.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// # let pem = std::fs::read("/path/to/cert.pem").unwrap();
+    /// config.load_cert_chain_from_pem(&pem)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn load_cert_chain_from_pem(&mut self, pem: &[u8]) -> Result<()> {
+        self.tls_ctx.use_certificate_chain(pem)
+    }
+
+    /// Configures the given private key from memory.
+    ///
+    /// The content of `pem` is parsed as a PEM-encoded private key.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// # let pem = std::fs::read("/path/to/key.pem").unwrap();
+    /// config.load_priv_key_from_pem(&pem)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn load_priv_key_from_pem(&mut self, pem: &[u8]) -> Result<()> {
+        self.tls_ctx.use_privkey(pem)
+    }
+
+    /// Configures the given private key from memory.
+    ///
+    /// The content of `der` is parsed as a DER-encoded private key, either
+    /// in PKCS#8 or in the algorithm-specific format.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// # let der = std::fs::read("/path/to/key.der").unwrap();
+    /// config.load_priv_key_from_der(&der)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn load_priv_key_from_der(&mut self, der: &[u8]) -> Result<()> {
+        self.tls_ctx.use_privkey_der(der)
+    }
+
+    /// Configures the certificate chain and private key to present to
+    /// clients requesting the given server name.
+    ///
+    /// Servers hosting multiple domains can call this method once per domain.
+    /// During the handshake the certificate is selected based on the server
+    /// name indication (SNI) sent by the client, falling back to the one
+    /// configured with [`load_cert_chain_from_pem_file()`] and
+    /// [`load_priv_key_from_pem_file()`] when no match is found.
+    ///
+    /// The `server_name` parameter is matched case-insensitively, and can be a
+    /// wildcard name (e.g. `*.example.com`) covering a single label.
+    ///
+    /// The content of `cert_file` is parsed as a PEM-encoded leaf certificate,
+    /// followed by optional intermediate certificates, and the content of
+    /// `key_file` is parsed as a PEM-encoded private key.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.add_cert_chain_for_server_name(
+    ///     "example.com",
+    ///     "/path/to/example.com.pem",
+    ///     "/path/to/example.com.key",
+    /// )?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`load_cert_chain_from_pem_file()`]: struct.Config.html#method.load_cert_chain_from_pem_file
+    /// [`load_priv_key_from_pem_file()`]: struct.Config.html#method.load_priv_key_from_pem_file
+    pub fn add_cert_chain_for_server_name(
+        &mut self, server_name: &str, cert_file: &str, key_file: &str,
+    ) -> Result<()> {
+        self.tls_ctx
+            .add_server_cert_files(server_name, cert_file, key_file)
+    }
+
     /// Specifies a file where trusted CA certificates are stored for the
     /// purposes of certificate verification.
     ///
@@ -1005,11 +1434,79 @@ impl Config {
         self.tls_ctx.set_ticket_key(key)
     }
 
+    /// Sets the lifetime of the session tickets issued by the server, in
+    /// seconds.
+    ///
+    /// Clients can't resume sessions, or send early data, with tickets older
+    /// than this. Shorter lifetimes also limit the amount of state that an
+    /// [`AntiReplay`] store needs to keep.
+    ///
+    /// The default value is 7200 seconds (2 hours).
+    ///
+    /// [`AntiReplay`]: trait.AntiReplay.html
+    pub fn set_ticket_lifetime(&mut self, secs: u32) {
+        self.tls_ctx.set_ticket_lifetime(secs);
+    }
+
+    /// Sets the maximum number of session tickets issued by the server on each
+    /// connection.
+    ///
+    /// Setting this to 0 disables session resumption. Values above 16 are
+    /// capped to 16.
+    ///
+    /// The default value is 2.
+    pub fn set_max_tickets(&mut self, v: usize) -> Result<()> {
+        self.tls_ctx.set_max_tickets(v)
+    }
+
     /// Enables sending or receiving early data.
     pub fn enable_early_data(&mut self) {
         self.tls_ctx.set_early_data_enabled(true);
     }
 
+    /// Configures the OCSP response to staple to the server's certificate.
+    ///
+    /// The response is only sent to clients that request it, and is not
+    /// validated in any way, so it is up to the application to refresh it
+    /// before it expires.
+    pub fn set_ocsp_response(&mut self, response: &[u8]) -> Result<()> {
+        self.tls_ctx.set_ocsp_response(response)
+    }
+
+    /// Configures the list of signed certificate timestamps (SCTs) to send
+    /// along with the server's certificate.
+    ///
+    /// The `list` parameter must be in the TLS wire format of the
+    /// `signed_certificate_timestamp` extension (see [RFC 6962]). It is only
+    /// sent to clients that request it.
+    ///
+    /// [RFC 6962]: https://www.rfc-editor.org/rfc/rfc6962.html#section-3.3
+    pub fn set_signed_cert_timestamp_list(&mut self, list: &[u8]) -> Result<()> {
+        self.tls_ctx.set_signed_cert_timestamp_list(list)
+    }
+
+    /// Configures whether clients request a stapled OCSP response from the
+    /// server.
+    ///
+    /// The response can be retrieved with [`peer_ocsp_response()`] once the
+    /// handshake is completed.
+    ///
+    /// [`peer_ocsp_response()`]: struct.Connection.html#method.peer_ocsp_response
+    pub fn enable_ocsp_stapling(&mut self) {
+        self.tls_ctx.enable_ocsp_stapling();
+    }
+
+    /// Configures whether clients request signed certificate timestamps from
+    /// the server.
+    ///
+    /// The timestamps can be retrieved with
+    /// [`peer_signed_cert_timestamp_list()`] once the handshake is completed.
+    ///
+    /// [`peer_signed_cert_timestamp_list()`]: struct.Connection.html#method.peer_signed_cert_timestamp_list
+    pub fn enable_signed_cert_timestamps(&mut self) {
+        self.tls_ctx.enable_signed_cert_timestamps();
+    }
+
     /// Configures the list of supported application protocols.
     ///
     /// On the client this configures the list of protocols to send to the
@@ -1080,10 +1577,47 @@ impl Config {
         self.local_transport_params.max_idle_timeout = v;
     }
 
+    /// Sets the maximum time allowed to complete the handshake, in
+    /// milliseconds.
+    ///
+    /// Connections that haven't completed the handshake when the timeout
+    /// expires are closed without sending a CONNECTION_CLOSE frame, like when
+    /// the idle timeout expires. Unlike the idle timeout, this is not reset
+    /// when packets are received, so a peer can't keep a half-open connection
+    /// alive indefinitely.
+    ///
+    /// The timeout is surfaced through [`timeout()`] and [`on_timeout()`].
+    ///
+    /// The default value is infinite, that is, no timeout is used.
+    ///
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn set_handshake_timeout(&mut self, v: u64) {
+        self.handshake_timeout = match v {
+            0 => None,
+
+            v => Some(time::Duration::from_millis(v)),
+        };
+    }
+
     /// Sets the `max_udp_payload_size transport` parameter.
     ///
-    /// The default value is `65527`.
+    /// This is the maximum size of the UDP payloads the local endpoint is
+    /// willing to receive, which should match the size of the buffers used to
+    /// receive datagrams (see [`max_recv_udp_payload_size()`]) as well as the
+    /// MTU of the local network. Once the handshake has completed, and the
+    /// peer is thus aware of the limit, larger datagrams are dropped by
+    /// [`recv()`].
+    ///
+    /// The value is clamped between `1200` and `65527`. The default value is
+    /// `65527`.
+    ///
+    /// [`max_recv_udp_payload_size()`]:
+    /// struct.Connection.html#method.max_recv_udp_payload_size
+    /// [`recv()`]: struct.Connection.html#method.recv
     pub fn set_max_recv_udp_payload_size(&mut self, v: usize) {
+        let v = v.clamp(MIN_CLIENT_INITIAL_LEN, MAX_RECV_UDP_PAYLOAD_SIZE);
+
         self.local_transport_params.max_udp_payload_size = v as u64;
     }
 
@@ -1227,6 +1761,11 @@ impl Config {
 
     /// Sets the `disable_active_migration` transport parameter.
     ///
+    /// When enabled, packets the peer sends from a new address using a new
+    /// connection ID, i.e. when it actively migrates the connection, are
+    /// dropped. Address changes caused by NAT rebinding, where the peer keeps
+    /// using the same connection ID, are still validated and accepted.
+    ///
     /// The default value is `false`.
     pub fn set_disable_active_migration(&mut self, v: bool) {
         self.local_transport_params.disable_active_migration = v;
@@ -1257,6 +1796,67 @@ impl Config {
         self.initial_congestion_window_packets = packets;
     }
 
+    /// Sets minimum congestion window size in terms of packet count.
+    ///
+    /// The congestion window is never reduced below this value in response
+    /// to packet loss. [`InvalidState`] is returned if `packets` is 0.
+    ///
+    /// The default value is 2.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_minimum_congestion_window_packets(
+        &mut self, packets: usize,
+    ) -> Result<()> {
+        if packets == 0 {
+            return Err(Error::InvalidState);
+        }
+
+        self.minimum_congestion_window_packets = packets;
+
+        Ok(())
+    }
+
+    /// Sets the initial packet reordering threshold used for loss detection.
+    ///
+    /// A packet is declared lost when a packet sent this many packets after
+    /// it has been acknowledged. The threshold is increased automatically
+    /// when spurious losses are detected. [`InvalidState`] is returned if `v`
+    /// is 0.
+    ///
+    /// The default value is 3.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_packet_reordering_threshold(&mut self, v: u64) -> Result<()> {
+        if v == 0 {
+            return Err(Error::InvalidState);
+        }
+
+        self.packet_reordering_threshold = v;
+
+        Ok(())
+    }
+
+    /// Sets the time reordering threshold used for loss detection, as a
+    /// multiplier of the round-trip time.
+    ///
+    /// A packet is declared lost when a packet sent after it has been
+    /// acknowledged, and more than this fraction of the round-trip time has
+    /// elapsed since it was sent. [`InvalidState`] is returned if `v` is not a
+    /// finite value of at least 1.0.
+    ///
+    /// The default value is 1.125 (i.e. 9/8).
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_time_reordering_threshold(&mut self, v: f64) -> Result<()> {
+        if !v.is_finite() || v < 1.0 {
+            return Err(Error::InvalidState);
+        }
+
+        self.time_reordering_threshold = v;
+
+        Ok(())
+    }
+
     /// Sets the congestion control algorithm used.
     ///
     /// The default value is `CongestionControlAlgorithm::CUBIC`.
@@ -1303,28 +1903,167 @@ impl Config {
         self.dgram_send_max_queue_len = send_queue_len;
     }
 
-    /// Configures the max number of queued received PATH_CHALLENGE frames.
+    /// Configures whether to enable the ACK frequency extension.
     ///
-    /// When an endpoint receives a PATH_CHALLENGE frame and the queue is full,
-    /// the frame is discarded.
+    /// When enabled, the `min_ack_delay` transport parameter is advertised,
+    /// which allows the peer to request less frequent ACKs. If the peer also
+    /// supports the extension, it is asked to acknowledge packets as often as
+    /// the congestion controller needs.
     ///
-    /// The default is 3.
-    pub fn set_path_challenge_recv_max_queue_len(&mut self, queue_len: usize) {
-        self.path_challenge_recv_max_queue_len = queue_len;
+    /// The default is `false`.
+    pub fn enable_ack_frequency(&mut self, v: bool) {
+        self.local_transport_params.min_ack_delay = if v {
+            Some(ackfreq::MIN_ACK_DELAY)
+        } else {
+            None
+        };
     }
 
-    /// Sets the maximum size of the connection window.
+    /// Configures whether to advertise support for greasing the QUIC bit.
     ///
-    /// The default value is MAX_CONNECTION_WINDOW (24MBytes).
-    pub fn set_max_connection_window(&mut self, v: u64) {
-        self.max_connection_window = v;
-    }
-
-    /// Sets the maximum size of the stream window.
+    /// When enabled, the `grease_quic_bit` transport parameter is advertised,
+    /// which allows the peer to send packets with the fixed bit of their
+    /// header set to a random value ([RFC 9287]). Such packets are always
+    /// accepted. When the peer advertises the parameter, the fixed bit of
+    /// short header packets sent to it is randomized, independently of this
+    /// setting.
     ///
-    /// The default value is MAX_STREAM_WINDOW (16MBytes).
-    pub fn set_max_stream_window(&mut self, v: u64) {
-        self.max_stream_window = v;
+    /// The default is `false`.
+    ///
+    /// [RFC 9287]: https://www.rfc-editor.org/rfc/rfc9287.html
+    pub fn enable_grease_quic_bit(&mut self, v: bool) {
+        self.local_transport_params.grease_quic_bit = v;
+    }
+
+    /// Adds a transport parameter that is not natively supported, e.g. one
+    /// defined by an experimental extension, to those sent to the peer.
+    ///
+    /// The value is sent as-is, replacing any value previously set for the
+    /// same parameter ID. The custom parameters received from the peer can be
+    /// retrieved with [`custom_param()`].
+    ///
+    /// [`InvalidTransportParam`] is returned if the ID is not a valid
+    /// variable-length integer, or if it identifies a natively supported
+    /// parameter.
+    ///
+    /// [`custom_param()`]: struct.TransportParams.html#method.custom_param
+    /// [`InvalidTransportParam`]: enum.Error.html#variant.InvalidTransportParam
+    pub fn set_custom_transport_param(
+        &mut self, id: u64, value: &[u8],
+    ) -> Result<()> {
+        if id > MAX_VARINT || TransportParams::is_supported(id) {
+            return Err(Error::InvalidTransportParam);
+        }
+
+        let params = &mut self.local_transport_params.custom_params;
+
+        match params.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, v)) => *v = value.to_vec(),
+
+            None => params.push((id, value.to_vec())),
+        }
+
+        Ok(())
+    }
+
+    /// Configures the max number of queued received PATH_CHALLENGE frames.
+    ///
+    /// When an endpoint receives a PATH_CHALLENGE frame and the queue is full,
+    /// the frame is discarded.
+    ///
+    /// The default is 3.
+    pub fn set_path_challenge_recv_max_queue_len(&mut self, queue_len: usize) {
+        self.path_challenge_recv_max_queue_len = queue_len;
+    }
+
+    /// Sets the maximum size of the connection window.
+    ///
+    /// The default value is MAX_CONNECTION_WINDOW (24MBytes).
+    pub fn set_max_connection_window(&mut self, v: u64) {
+        self.max_connection_window = v;
+    }
+
+    /// Sets the maximum size of the stream window.
+    ///
+    /// The default value is MAX_STREAM_WINDOW (16MBytes).
+    pub fn set_max_stream_window(&mut self, v: u64) {
+        self.max_stream_window = v;
+    }
+
+    /// Sets the fraction of a stream's receive window that needs to be read
+    /// by the application before a MAX_STREAM_DATA frame is sent to extend
+    /// the peer's credit.
+    ///
+    /// Lower values keep the peer's credit closer to the full window, at the
+    /// cost of sending more frequent updates. [`InvalidState`] is returned if
+    /// `v` is not greater than 0 and less than 1.
+    ///
+    /// The default value is 0.5.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_stream_window_update_threshold(&mut self, v: f64) -> Result<()> {
+        if !(v > 0.0 && v < 1.0) {
+            return Err(Error::InvalidState);
+        }
+
+        self.stream_window_update_threshold = v;
+
+        Ok(())
+    }
+
+    /// Sets the maximum amount of CRYPTO data, in bytes, that is accepted from
+    /// the peer in each packet number space.
+    ///
+    /// CRYPTO data received beyond this offset, including data received out of
+    /// order, causes the connection to be closed with a CRYPTO_BUFFER_EXCEEDED
+    /// error. After the handshake, this bounds the total size of the session
+    /// tickets a server can send. [`InvalidState`] is returned if `v` is less
+    /// than 4096.
+    ///
+    /// The default value is 65536.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_max_crypto_buffer_size(&mut self, v: u64) -> Result<()> {
+        if v < MIN_MAX_CRYPTO_STREAM_OFFSET {
+            return Err(Error::InvalidState);
+        }
+
+        self.max_crypto_stream_offset = v;
+
+        Ok(())
+    }
+
+    /// Sets the maximum number of frames that are processed in a single
+    /// packet received from the peer.
+    ///
+    /// Packets carrying more frames than this, e.g. thousands of tiny frames
+    /// meant to waste CPU time, cause the connection to be closed with a
+    /// PROTOCOL_VIOLATION error. Runs of PADDING frames count as a single
+    /// frame.
+    ///
+    /// The default value is unlimited.
+    pub fn set_max_frames_per_packet(&mut self, v: usize) {
+        self.max_frames_per_packet = v;
+    }
+
+    /// Sets the maximum amount of early data, in bytes, that a server accepts
+    /// in 0-RTT packets on each connection.
+    ///
+    /// Early data can be replayed by an attacker, so capping it limits the
+    /// amount of work a replayed ClientHello can cause. The size of 0-RTT
+    /// packets is counted against this limit, and 0-RTT packets that would
+    /// exceed it are dropped without being acknowledged. The client then
+    /// retransmits their content in 1-RTT packets once the handshake
+    /// completes.
+    ///
+    /// Early data can also be refused altogether for specific connections
+    /// from a [`ClientHelloHandler`].
+    ///
+    /// This has no effect on clients. The default value is unlimited.
+    ///
+    /// [`ClientHelloHandler`]: trait.ClientHelloHandler.html
+    pub fn set_max_early_data(&mut self, v: u64) {
+        self.max_early_data = v;
     }
 
     /// Sets the initial stateless reset token.
@@ -1337,6 +2076,31 @@ impl Config {
         self.local_transport_params.stateless_reset_token = v;
     }
 
+    /// Sets the key that stateless reset tokens are derived from.
+    ///
+    /// When set, the stateless reset token of each source connection ID is
+    /// derived from the connection ID and the key, instead of being provided
+    /// by the application. This applies to the initial token advertised by
+    /// servers, which takes precedence over [`set_stateless_reset_token()`],
+    /// as well as to the tokens of connection IDs added with [`new_scid()`]
+    /// and [`generate_scid()`].
+    ///
+    /// As no per-connection state is needed to recompute the tokens, a
+    /// [`StatelessReset`] configured with the same key can reset connections
+    /// that were lost, e.g. after a restart. The key must then be kept secret
+    /// and persisted across restarts.
+    ///
+    /// By default no key is set.
+    ///
+    /// [`set_stateless_reset_token()`]:
+    /// struct.Config.html#method.set_stateless_reset_token
+    /// [`new_scid()`]: struct.Connection.html#method.new_scid
+    /// [`generate_scid()`]: struct.Connection.html#method.generate_scid
+    /// [`StatelessReset`]: struct.StatelessReset.html
+    pub fn set_stateless_reset_key(&mut self, key: &[u8; 32]) {
+        self.stateless_reset_key = Some(stateless_reset::new_key(key));
+    }
+
     /// Sets whether the QUIC connection should avoid reusing DCIDs over
     /// different paths.
     ///
@@ -1350,6 +2114,286 @@ impl Config {
     pub fn set_disable_dcid_reuse(&mut self, v: bool) {
         self.disable_dcid_reuse = v;
     }
+
+    /// Sets whether network paths take part in the latency spin bit.
+    ///
+    /// Using the spin bit lets on-path observers measure the connection's
+    /// round-trip time. Each path decides whether to use it when created, and
+    /// the number of transitions it observed is reported in [`PathStats`].
+    /// Transitions are also reported with [`Event::SpinBitFlipped`] when
+    /// [`enable_events()`] is used.
+    ///
+    /// The default value is `SpinBitMode::Disabled`.
+    ///
+    /// [`PathStats`]: struct.PathStats.html
+    /// [`Event::SpinBitFlipped`]: enum.Event.html#variant.SpinBitFlipped
+    /// [`enable_events()`]: struct.Config.html#method.enable_events
+    pub fn set_spin_bit_mode(&mut self, mode: SpinBitMode) {
+        self.spin_bit_mode = mode;
+    }
+
+    /// Sets a label to prepend to the trace ID of new connections.
+    ///
+    /// The trace ID (see [`trace_id()`]) is built from the connection's
+    /// source connection ID, which is unique but hard to correlate with the
+    /// application's own logs. When a label is set, the trace ID becomes
+    /// `<label>-<scid>` instead, e.g. to identify the listener or worker that
+    /// owns the connection.
+    ///
+    /// The default value is no label.
+    ///
+    /// [`trace_id()`]: struct.Connection.html#method.trace_id
+    pub fn set_trace_label(&mut self, label: &str) {
+        self.trace_label = Some(label.to_string());
+    }
+
+    /// Configures whether to use multiple validated paths concurrently.
+    ///
+    /// When enabled, non-probing packets can be sent on any validated path
+    /// instead of only on the active one, and [`send()`] schedules packets on
+    /// the usable path with the lowest RTT that has congestion window
+    /// available. Each path is bound to its own connection IDs and runs its
+    /// own loss recovery and congestion control, while packet numbers are
+    /// shared by all paths. Receiving non-probing packets on a non-active
+    /// path doesn't cause the connection to migrate to it.
+    ///
+    /// Note that this is not the multipath extension for QUIC, and doesn't
+    /// negotiate anything with the peer. Since an endpoint that doesn't
+    /// enable it treats non-probing packets on a new path as a migration,
+    /// it should only be enabled when the peer is known to enable it as
+    /// well.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn set_multipath(&mut self, v: bool) {
+        self.multipath = v;
+    }
+
+    /// Configures whether data written to streams can be sent immediately.
+    ///
+    /// When disabled, data passed to [`stream_send()`] is buffered but held
+    /// back until [`flush()`] or [`stream_flush()`] is called, so that
+    /// multiple small writes to a stream are coalesced into a single
+    /// `STREAM` frame, even if [`send()`] is called in between. Retransmission
+    /// of data that was already sent is not affected.
+    ///
+    /// The default value is `true`.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`flush()`]: struct.Connection.html#method.flush
+    /// [`stream_flush()`]: struct.Connection.html#method.stream_flush
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn set_autoflush(&mut self, v: bool) {
+        self.autoflush = v;
+    }
+
+    /// Sets the maximum amount of stream data, in bytes, that can be queued
+    /// before the connection is ready for application data.
+    ///
+    /// When set, data passed to [`stream_send()`] before the peer's transport
+    /// parameters are known is queued, and written to the streams as soon as
+    /// the connection is ready, in the order it was queued. Data is sent as
+    /// 0-RTT data when early data is available.
+    ///
+    /// The default value is `0`, meaning that no data is queued.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    pub fn set_max_pre_handshake_data(&mut self, v: usize) {
+        self.max_pre_handshake_data = v;
+    }
+
+    /// Sets the maximum time, in milliseconds, that [`send()`] can wait for
+    /// more data to be queued before sending a mostly-empty packet.
+    ///
+    /// When enabled, and the only data waiting to be sent is stream or
+    /// datagram data that doesn't fill a full packet, [`send()`] returns
+    /// [`Done`] until either enough data is queued to fill a packet, or the
+    /// delay expires. Packets that carry acknowledgements, retransmissions or
+    /// probes are never delayed. The delay is surfaced through [`timeout()`],
+    /// and can be bypassed with [`set_send_immediate()`].
+    ///
+    /// The default value is `0`, that is, packets are never delayed.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`set_send_immediate()`]:
+    /// struct.Connection.html#method.set_send_immediate
+    pub fn set_send_coalescing_delay(&mut self, v: u64) {
+        self.send_coalescing_delay = match v {
+            0 => None,
+
+            v => Some(time::Duration::from_millis(v)),
+        };
+    }
+
+    /// Configures whether connection events are queued.
+    ///
+    /// When enabled, the connection queues an [`Event`] for state changes
+    /// relevant to the application, which can then be retrieved with
+    /// [`poll()`] instead of checking the connection's state after each call
+    /// to [`recv()`]. Applications that enable this should drain the queue
+    /// regularly, as events raised while it is full are discarded.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Event`]: enum.Event.html
+    /// [`poll()`]: struct.Connection.html#method.poll
+    /// [`recv()`]: struct.Connection.html#method.recv
+    pub fn enable_events(&mut self, v: bool) {
+        self.events = v;
+    }
+
+    /// Sets the generator used to mint source connection IDs.
+    ///
+    /// The generator is used by [`generate_scid()`] to mint the initial
+    /// source connection ID to pass to [`connect()`] or [`accept()`], and by
+    /// connections created from this configuration when calling
+    /// [`Connection::generate_scid()`].
+    ///
+    /// The default generator mints random connection IDs of
+    /// [`MAX_CONN_ID_LEN`] bytes.
+    ///
+    /// [`generate_scid()`]: struct.Config.html#method.generate_scid
+    /// [`connect()`]: fn.connect.html
+    /// [`accept()`]: fn.accept.html
+    /// [`Connection::generate_scid()`]: struct.Connection.html#method.generate_scid
+    /// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+    pub fn set_connection_id_generator(
+        &mut self, generator: Arc<dyn ConnectionIdGenerator>,
+    ) {
+        self.cid_generator = generator;
+    }
+
+    /// Mints a new initial source connection ID using the configured
+    /// [`ConnectionIdGenerator`].
+    ///
+    /// [`InvalidState`] is returned if the generator produced a connection ID
+    /// of an invalid length.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// # let local = "127.0.0.1:0".parse().unwrap();
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// let scid = config.generate_scid()?;
+    /// let conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`ConnectionIdGenerator`]: trait.ConnectionIdGenerator.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn generate_scid(&self) -> Result<ConnectionId<'static>> {
+        cid::generate_cid(self.cid_generator.as_ref(), 0)
+    }
+
+    /// Sets the [`Clock`] used by connections created from this configuration.
+    ///
+    /// All time-dependent processing of a connection, including loss detection,
+    /// congestion control and the values returned by [`timeout()`], is based on
+    /// the time reported by the clock.
+    ///
+    /// The default clock reads the system's monotonic clock.
+    ///
+    /// [`Clock`]: trait.Clock.html
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the [`Rng`] used by connections created from this configuration.
+    ///
+    /// The generator is used for the client's initial destination connection
+    /// ID and for PATH_CHALLENGE data. Source connection IDs are minted by the
+    /// [`ConnectionIdGenerator`], so to make those reproducible as well
+    /// configure a [`RandomConnectionIdGenerator::with_rng()`] sharing the
+    /// same generator.
+    ///
+    /// The default generator reads from the system's random number generator.
+    ///
+    /// [`Rng`]: trait.Rng.html
+    /// [`ConnectionIdGenerator`]: trait.ConnectionIdGenerator.html
+    /// [`RandomConnectionIdGenerator::with_rng()`]: struct.RandomConnectionIdGenerator.html#method.with_rng
+    #[cfg(feature = "deterministic-rng")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "deterministic-rng")))]
+    pub fn set_rng(&mut self, rng: Arc<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    /// Registers a handler for extension frames of the given type.
+    ///
+    /// By default frames with a type that is not natively supported cause the
+    /// connection to be closed with a `FRAME_ENCODING_ERROR`. Once a handler
+    /// is registered for a frame type, frames of that type received on 0-RTT
+    /// and 1-RTT packets are parsed with it, and can be retrieved with
+    /// [`extension_frame_recv()`]. Frames of that type can also be sent with
+    /// [`extension_frame_send()`].
+    ///
+    /// Registering a handler for the same frame type again replaces the
+    /// previous one. [`InvalidFrame`] is returned if the frame type is
+    /// natively supported.
+    ///
+    /// [`extension_frame_recv()`]:
+    /// struct.Connection.html#method.extension_frame_recv
+    /// [`extension_frame_send()`]:
+    /// struct.Connection.html#method.extension_frame_send
+    /// [`InvalidFrame`]: enum.Error.html#variant.InvalidFrame
+    pub fn register_extension_frame(
+        &mut self, frame_type: u64, handler: Arc<dyn ExtensionFrameHandler>,
+    ) -> Result<()> {
+        if frame::is_native_type(frame_type) {
+            return Err(Error::InvalidFrame);
+        }
+
+        self.extension_frames.insert(frame_type, handler);
+
+        Ok(())
+    }
+
+    /// Configures the max number of queued received extension frames.
+    ///
+    /// When an extension frame is received and the queue is full, the frame
+    /// is discarded.
+    ///
+    /// The default is 32.
+    pub fn set_extension_frame_recv_max_queue_len(&mut self, queue_len: usize) {
+        self.extension_frame_recv_max_queue_len = queue_len;
+    }
+
+    /// Sets the [`ClientHelloHandler`] used by server-side connections created
+    /// from this configuration to decide whether to continue the handshake.
+    ///
+    /// The handshake doesn't progress until the client's ClientHello has been
+    /// received in full and the handler accepted it. Rejected connections are
+    /// closed with the transport error code chosen by the handler.
+    ///
+    /// The handler is ignored by client-side connections. By default no
+    /// handler is set, and all connections are accepted.
+    ///
+    /// [`ClientHelloHandler`]: trait.ClientHelloHandler.html
+    pub fn set_client_hello_handler(
+        &mut self, handler: Arc<dyn ClientHelloHandler>,
+    ) {
+        self.client_hello_handler = Some(handler);
+    }
+
+    /// Sets the [`AntiReplay`] store consulted by server-side connections
+    /// created from this configuration before accepting early data.
+    ///
+    /// Early data sent with a ticket that the store has already seen is
+    /// rejected, while the handshake itself continues. The store is not
+    /// consulted when a [`ClientHelloHandler`] already rejected early data.
+    ///
+    /// The store is ignored by client-side connections. By default no store
+    /// is set, and early data is accepted whenever it is enabled.
+    ///
+    /// [`AntiReplay`]: trait.AntiReplay.html
+    /// [`ClientHelloHandler`]: trait.ClientHelloHandler.html
+    pub fn set_anti_replay(&mut self, store: Arc<dyn AntiReplay>) {
+        self.anti_replay = Some(store);
+    }
 }
 
 /// A QUIC connection.
@@ -1466,6 +2510,14 @@ pub struct Connection {
     /// frame.
     local_error: Option<ConnectionError>,
 
+    /// Type of the received frame that caused the local transport error, if
+    /// any, to be sent in the CONNECTION_CLOSE frame.
+    local_error_frame_type: u64,
+
+    /// Type of the frame that failed to be processed in the last received
+    /// packet, until the connection is closed because of it.
+    recv_error_frame_type: Option<u64>,
+
     /// Error code and reason received from the peer in a CONNECTION_CLOSE
     /// frame.
     peer_error: Option<ConnectionError>,
@@ -1476,6 +2528,19 @@ pub struct Connection {
     /// Idle timeout expiration time.
     idle_timer: Option<time::Instant>,
 
+    /// Handshake timeout expiration time.
+    handshake_timer: Option<time::Instant>,
+
+    /// The maximum time to wait for more data before sending a mostly-empty
+    /// packet.
+    send_coalescing_delay: Option<time::Duration>,
+
+    /// Whether packets are sent without waiting for more data.
+    send_immediate: bool,
+
+    /// Send coalescing expiration time.
+    send_coalescing_timer: Option<time::Instant>,
+
     /// Draining timeout expiration time.
     draining_timer: Option<time::Instant>,
 
@@ -1538,9 +2603,25 @@ pub struct Connection {
     /// TLS keylog writer.
     keylog: Option<Box<dyn std::io::Write + Send + Sync>>,
 
+    /// Callback invoked for every packet sent.
+    #[cfg(feature = "instrumentation")]
+    tx_hook: Option<hooks::TxHook>,
+
+    /// Callback invoked for every ACK frame processed.
+    #[cfg(feature = "instrumentation")]
+    ack_hook: Option<hooks::AckHook>,
+
+    /// Callback invoked for every congestion controller state transition.
+    #[cfg(feature = "instrumentation")]
+    congestion_hook: Option<hooks::CongestionHook>,
+
     #[cfg(feature = "qlog")]
     qlog: QlogInfo,
 
+    /// Binary trace writer.
+    #[cfg(feature = "btrace")]
+    btrace: Option<btrace::Writer>,
+
     /// DATAGRAM queues.
     dgram_recv_queue: dgram::DatagramQueue,
     dgram_send_queue: dgram::DatagramQueue,
@@ -1552,6 +2633,34 @@ pub struct Connection {
     /// Connection IDs when the peer migrates.
     disable_dcid_reuse: bool,
 
+    /// Whether new paths take part in the latency spin bit.
+    spin_bit_mode: SpinBitMode,
+
+    /// Whether multiple paths can be used concurrently.
+    multipath: bool,
+
+    /// Whether stream data can be sent without being explicitly flushed.
+    autoflush: bool,
+
+    /// Stream data written before the connection was ready for application
+    /// data, together with the stream ID and fin flag.
+    pre_handshake_data: VecDeque<(u64, Vec<u8>, bool)>,
+
+    /// Total length of the data in `pre_handshake_data`.
+    pre_handshake_data_len: usize,
+
+    /// The maximum value of `pre_handshake_data_len`.
+    max_pre_handshake_data: usize,
+
+    /// Whether connection events are queued.
+    events_enabled: bool,
+
+    /// Queue of connection events not yet retrieved by the application.
+    events: VecDeque<Event>,
+
+    /// Events counting dropped packets, returned once `events` is empty.
+    dropped_pkt_events: Vec<Event>,
+
     /// The number of streams reset by local.
     reset_stream_local_count: u64,
 
@@ -1566,6 +2675,59 @@ pub struct Connection {
 
     /// The anti-amplification limit factor.
     max_amplification_factor: usize,
+
+    /// The maximum data offset that can be stored in a crypto stream.
+    max_crypto_stream_offset: u64,
+
+    /// The maximum number of frames processed in a single received packet.
+    max_frames_per_packet: usize,
+
+    /// The maximum number of bytes accepted in 0-RTT packets.
+    max_early_data: u64,
+
+    /// The number of bytes accepted in 0-RTT packets so far.
+    early_data_recv: u64,
+
+    /// Key the stateless reset tokens of source Connection IDs are derived
+    /// from, if any.
+    stateless_reset_key: Option<ring::hmac::Key>,
+
+    /// Generator used to mint new source Connection IDs.
+    cid_generator: Arc<dyn ConnectionIdGenerator>,
+
+    /// Source of time for the connection.
+    clock: Arc<dyn Clock>,
+
+    /// Source of randomness for the connection.
+    rng: Arc<dyn rand::Rng>,
+
+    /// Handlers for the extension frames that can be received.
+    extension_frames: HashMap<u64, Arc<dyn ExtensionFrameHandler>>,
+
+    /// Handler deciding whether to accept the client's ClientHello. This is
+    /// cleared once the handler has been invoked.
+    client_hello_handler: Option<Arc<dyn ClientHelloHandler>>,
+
+    /// Store of the tickets already used for early data. This is cleared once
+    /// the ClientHello has been received.
+    anti_replay: Option<Arc<dyn AntiReplay>>,
+
+    /// Initial CRYPTO data received before the ClientHello is admitted.
+    client_hello_buf: Vec<u8>,
+
+    /// ACK frequency extension state.
+    ack_freq: ackfreq::AckFrequency,
+
+    /// Extension frames received from the peer, not yet read by the
+    /// application.
+    extension_frames_recv: VecDeque<(u64, Vec<u8>)>,
+
+    /// Extension frame receive queue max length.
+    extension_frame_recv_max_queue_len: usize,
+
+    /// Extension frames queued by the application, including lost ones that
+    /// need to be retransmitted.
+    extension_frames_send: VecDeque<frame::Frame>,
 }
 
 /// Creates a new server-side connection.
@@ -1629,12 +2791,91 @@ pub fn connect(
     Ok(conn)
 }
 
+/// Creates a new client-side connection, using the given destination
+/// connection ID for the first Initial packet.
+///
+/// This is the same as [`connect()`], but rather than a random destination
+/// connection ID, `dcid` is used to derive the Initial secrets, e.g. so that
+/// the connection can be routed by a load balancer that encodes information
+/// in it, or to reproduce test vectors.
+///
+/// The connection ID must be between 8 and [`MAX_CONN_ID_LEN`] bytes long,
+/// and should be unpredictable, otherwise [`InvalidState`] is returned.
+///
+/// [`connect()`]: fn.connect.html
+/// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+/// [`InvalidState`]: enum.Error.html#variant.InvalidState
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// # let server_name = "quic.tech";
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let dcid = quiche::ConnectionId::from_ref(&[0xbb; 16]);
+/// # let local = "127.0.0.1:4321".parse().unwrap();
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// let conn = quiche::connect_with_dcid(
+///     Some(&server_name),
+///     &scid,
+///     &dcid,
+///     local,
+///     peer,
+///     &mut config,
+/// )?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
+#[inline]
+pub fn connect_with_dcid(
+    server_name: Option<&str>, scid: &ConnectionId, dcid: &ConnectionId,
+    local: SocketAddr, peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    // The Destination Connection ID field of the first Initial packet must be
+    // at least 8 bytes long (RFC 9000, Section 7.2).
+    if dcid.len() < 8 || dcid.len() > MAX_CONN_ID_LEN {
+        return Err(Error::InvalidState);
+    }
+
+    let mut conn = connect(server_name, scid, local, peer, config)?;
+
+    conn.set_client_initial_dcid(dcid.to_vec().into())?;
+
+    Ok(conn)
+}
+
+/// Recreates a connection from the state written by [`export_state()`].
+///
+/// The connection resumes where the exporting instance left off, on the
+/// path between `local` and `peer`. The `config` should be equivalent to
+/// the one used to create the exported connection, as local transport
+/// parameters are not part of the state.
+///
+/// The TLS session is not carried over, so properties only known to the
+/// TLS library (e.g. the peer's certificate) are not available on the
+/// imported connection, and post-handshake TLS messages can't be
+/// processed. [`InvalidState`] is returned if the state can't be parsed.
+///
+/// [`export_state()`]: struct.Connection.html#method.export_state
+/// [`InvalidState`]: enum.Error.html#variant.InvalidState
+#[cfg(feature = "checkpoint")]
+#[cfg_attr(docsrs, doc(cfg(feature = "checkpoint")))]
+pub fn import(
+    state: &[u8], local: SocketAddr, peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    checkpoint::import(state, local, peer, config)
+}
+
 /// Writes a version negotiation packet.
 ///
 /// The `scid` and `dcid` parameters are the source connection ID and the
 /// destination connection ID extracted from the received client's Initial
 /// packet that advertises an unsupported version.
 ///
+/// As clients using other versions may use connection IDs of up to 255 bytes,
+/// the connection IDs are not limited to [`MAX_CONN_ID_LEN`].
+///
+/// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+///
 /// ## Examples:
 ///
 /// ```no_run
@@ -1769,15 +3010,50 @@ macro_rules! qlog_with_type {
     }};
 }
 
-#[cfg(feature = "qlog")]
-const QLOG_PARAMS_SET: EventType =
-    EventType::TransportEventType(TransportEventType::ParametersSet);
-
-#[cfg(feature = "qlog")]
-const QLOG_PACKET_RX: EventType =
-    EventType::TransportEventType(TransportEventType::PacketReceived);
+/// Reports the congestion controller state transitions of a path to qlog and
+/// to the congestion hook.
+macro_rules! report_congestion_transitions {
+    ($conn:expr, $path:expr, $now:expr) => {{
+        #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+        {
+            let transitions = $path.recovery.congestion_transitions();
 
-#[cfg(feature = "qlog")]
+            qlog_with_type!(QLOG_CONGESTION_STATE, $conn.qlog, q, {
+                if let Some(ev_data) =
+                    recovery::qlog_congestion_state(&transitions)
+                {
+                    q.add_event_data_with_instant(ev_data, $now).ok();
+                }
+            });
+
+            #[cfg(feature = "instrumentation")]
+            if let Some(hook) = $conn.congestion_hook.as_mut() {
+                for transition in transitions {
+                    hook(&CongestionEvent {
+                        transition,
+                        cwnd: $path.recovery.cwnd(),
+                        ssthresh: $path.recovery.ssthresh(),
+                        bytes_in_flight: $path.recovery.bytes_in_flight(),
+                        rtt: $path.recovery.rtt(),
+                        from: $path.local_addr(),
+                        to: $path.peer_addr(),
+                        time: $now,
+                    });
+                }
+            }
+        }
+    }};
+}
+
+#[cfg(feature = "qlog")]
+const QLOG_PARAMS_SET: EventType =
+    EventType::TransportEventType(TransportEventType::ParametersSet);
+
+#[cfg(feature = "qlog")]
+const QLOG_PACKET_RX: EventType =
+    EventType::TransportEventType(TransportEventType::PacketReceived);
+
+#[cfg(feature = "qlog")]
 const QLOG_PACKET_TX: EventType =
     EventType::TransportEventType(TransportEventType::PacketSent);
 
@@ -1789,6 +3065,10 @@ const QLOG_DATA_MV: EventType =
 const QLOG_METRICS: EventType =
     EventType::RecoveryEventType(RecoveryEventType::MetricsUpdated);
 
+#[cfg(feature = "qlog")]
+const QLOG_CONGESTION_STATE: EventType =
+    EventType::RecoveryEventType(RecoveryEventType::CongestionStateUpdated);
+
 #[cfg(feature = "qlog")]
 const QLOG_CONNECTION_CLOSED: EventType =
     EventType::ConnectivityEventType(ConnectivityEventType::ConnectionClosed);
@@ -1824,15 +3104,34 @@ impl Connection {
         scid: &ConnectionId, odcid: Option<&ConnectionId>, local: SocketAddr,
         peer: SocketAddr, config: &Config, tls: tls::Handshake, is_server: bool,
     ) -> Result<Connection> {
+        let now = config.clock.now();
+
         let max_rx_data = config.local_transport_params.initial_max_data;
 
-        let scid_as_hex: Vec<String> =
-            scid.iter().map(|b| format!("{b:02x}")).collect();
+        // The trace ID is computed once here, so that logging doesn't need to
+        // allocate on every packet.
+        let mut trace_id = String::with_capacity(
+            config.trace_label.as_ref().map_or(0, |l| l.len() + 1) +
+                scid.len() * 2,
+        );
+
+        if let Some(label) = &config.trace_label {
+            trace_id.push_str(label);
+            trace_id.push('-');
+        }
 
-        let reset_token = if is_server {
-            config.local_transport_params.stateless_reset_token
-        } else {
-            None
+        for b in scid.iter() {
+            use std::fmt::Write;
+
+            write!(trace_id, "{b:02x}").unwrap();
+        }
+
+        let reset_token = match (is_server, &config.stateless_reset_key) {
+            (true, Some(key)) => Some(stateless_reset::derive_token(key, scid)),
+
+            (true, None) => config.local_transport_params.stateless_reset_token,
+
+            (false, _) => None,
         };
 
         let recovery_config = recovery::RecoveryConfig::from_config(config);
@@ -1844,6 +3143,7 @@ impl Connection {
             config.path_challenge_recv_max_queue_len,
             MIN_CLIENT_INITIAL_LEN,
             true,
+            now,
         );
 
         // If we did stateless retry assume the peer's address is verified.
@@ -1851,6 +3151,8 @@ impl Connection {
         // Assume clients validate the server's address implicitly.
         path.peer_verified_local_address = is_server;
 
+        path.init_spin_bit(config.spin_bit_mode, config.rng.as_ref());
+
         // Do not allocate more than the number of active CIDs.
         let paths = path::PathMap::new(
             path,
@@ -1874,12 +3176,12 @@ impl Connection {
 
             ids,
 
-            trace_id: scid_as_hex.join(""),
+            trace_id,
 
             pkt_num_spaces: [
-                packet::PktNumSpace::new(),
-                packet::PktNumSpace::new(),
-                packet::PktNumSpace::new(),
+                packet::PktNumSpace::new(now),
+                packet::PktNumSpace::new(now),
+                packet::PktNumSpace::new(now),
             ],
 
             peer_transport_params: TransportParams::default(),
@@ -1930,6 +3232,7 @@ impl Connection {
                 config.local_transport_params.initial_max_streams_bidi,
                 config.local_transport_params.initial_max_streams_uni,
                 config.max_stream_window,
+                config.stream_window_update_threshold,
             ),
 
             odcid: None,
@@ -1940,12 +3243,26 @@ impl Connection {
 
             local_error: None,
 
+            local_error_frame_type: 0,
+
+            recv_error_frame_type: None,
+
             peer_error: None,
 
             blocked_limit: None,
 
             idle_timer: None,
 
+            handshake_timer: config
+                .handshake_timeout
+                .map(|timeout| now + timeout),
+
+            send_coalescing_delay: config.send_coalescing_delay,
+
+            send_immediate: false,
+
+            send_coalescing_timer: None,
+
             draining_timer: None,
 
             undecryptable_pkts: VecDeque::new(),
@@ -1986,9 +3303,21 @@ impl Connection {
 
             keylog: None,
 
+            #[cfg(feature = "instrumentation")]
+            tx_hook: None,
+
+            #[cfg(feature = "instrumentation")]
+            ack_hook: None,
+
+            #[cfg(feature = "instrumentation")]
+            congestion_hook: None,
+
             #[cfg(feature = "qlog")]
             qlog: Default::default(),
 
+            #[cfg(feature = "btrace")]
+            btrace: None,
+
             dgram_recv_queue: dgram::DatagramQueue::new(
                 config.dgram_recv_max_queue_len,
             ),
@@ -2001,12 +3330,68 @@ impl Connection {
 
             disable_dcid_reuse: config.disable_dcid_reuse,
 
+            spin_bit_mode: config.spin_bit_mode,
+
+            multipath: config.multipath,
+
+            autoflush: config.autoflush,
+
+            pre_handshake_data: VecDeque::new(),
+
+            pre_handshake_data_len: 0,
+
+            max_pre_handshake_data: config.max_pre_handshake_data,
+
+            events_enabled: config.events,
+
+            events: VecDeque::new(),
+            dropped_pkt_events: Vec::new(),
+
             reset_stream_local_count: 0,
             stopped_stream_local_count: 0,
             reset_stream_remote_count: 0,
             stopped_stream_remote_count: 0,
 
             max_amplification_factor: config.max_amplification_factor,
+
+            max_crypto_stream_offset: config.max_crypto_stream_offset,
+            max_frames_per_packet: config.max_frames_per_packet,
+
+            max_early_data: config.max_early_data,
+            early_data_recv: 0,
+
+            stateless_reset_key: config.stateless_reset_key.clone(),
+
+            cid_generator: config.cid_generator.clone(),
+
+            clock: config.clock.clone(),
+
+            rng: config.rng.clone(),
+
+            extension_frames: config.extension_frames.clone(),
+
+            client_hello_handler: if is_server {
+                config.client_hello_handler.clone()
+            } else {
+                None
+            },
+
+            anti_replay: if is_server {
+                config.anti_replay.clone()
+            } else {
+                None
+            },
+
+            client_hello_buf: Vec::new(),
+
+            ack_freq: ackfreq::AckFrequency::default(),
+
+            extension_frames_recv: VecDeque::new(),
+
+            extension_frame_recv_max_queue_len: config
+                .extension_frame_recv_max_queue_len,
+
+            extension_frames_send: VecDeque::new(),
         };
 
         if let Some(odcid) = odcid {
@@ -2022,6 +3407,10 @@ impl Connection {
         conn.local_transport_params.initial_source_connection_id =
             Some(conn.ids.get_scid(0)?.cid.to_vec().into());
 
+        if is_server {
+            conn.local_transport_params.stateless_reset_token = reset_token;
+        }
+
         conn.handshake.init(is_server)?;
 
         conn.handshake
@@ -2033,30 +3422,33 @@ impl Connection {
         // we already generated the random destination connection ID.
         if !is_server {
             let mut dcid = [0; 16];
-            rand::rand_bytes(&mut dcid[..]);
+            conn.rng.fill_bytes(&mut dcid[..]);
 
-            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
-                &dcid,
-                conn.version,
-                conn.is_server,
-            )?;
+            conn.set_client_initial_dcid(dcid.to_vec().into())?;
+        }
 
-            let reset_token = conn.peer_transport_params.stateless_reset_token;
-            conn.set_initial_dcid(
-                dcid.to_vec().into(),
-                reset_token,
-                active_path_id,
-            )?;
+        Ok(conn)
+    }
 
-            conn.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
-                Some(aead_open);
-            conn.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
-                Some(aead_seal);
+    /// Sets the destination connection ID of the client's first Initial
+    /// packet, and derives the Initial secrets from it.
+    fn set_client_initial_dcid(
+        &mut self, dcid: ConnectionId<'static>,
+    ) -> Result<()> {
+        let (aead_open, aead_seal) =
+            crypto::derive_initial_key_material(&dcid, self.version, false)?;
 
-            conn.derived_initial_secrets = true;
-        }
+        let reset_token = self.peer_transport_params.stateless_reset_token;
+        let active_path_id = self.paths.get_active_path_id()?;
 
-        Ok(conn)
+        self.set_initial_dcid(dcid, reset_token, active_path_id)?;
+
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_open = Some(aead_open);
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal = Some(aead_seal);
+
+        self.derived_initial_secrets = true;
+
+        Ok(())
     }
 
     /// Sets keylog output to the designated [`Writer`].
@@ -2070,6 +3462,49 @@ impl Connection {
         self.keylog = Some(writer);
     }
 
+    /// Sets a callback to be invoked for every packet sent.
+    ///
+    /// The callback is invoked from [`send()`] with metadata about the
+    /// packet, and the state of the path it was sent on. It should return
+    /// quickly, as it runs on the send path.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    #[cfg(feature = "instrumentation")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "instrumentation")))]
+    pub fn set_tx_hook(&mut self, hook: TxHook) {
+        self.tx_hook = Some(hook);
+    }
+
+    /// Sets a callback to be invoked for every ACK frame processed.
+    ///
+    /// The callback is invoked from [`recv()`] once for each path on which
+    /// packets were newly acknowledged or declared lost as a result of the
+    /// frame, with the resulting state of that path.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    #[cfg(feature = "instrumentation")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "instrumentation")))]
+    pub fn set_ack_hook(&mut self, hook: AckHook) {
+        self.ack_hook = Some(hook);
+    }
+
+    /// Sets a callback to be invoked for every state transition of the
+    /// congestion controller of a path.
+    ///
+    /// Transitions include leaving slow start (and why), entering a new
+    /// recovery period and changes of the pacing rate. They are detected
+    /// after packets are sent, acknowledged or declared lost, so the callback
+    /// is invoked from [`send()`], [`recv()`] and [`on_timeout()`].
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    #[cfg(feature = "instrumentation")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "instrumentation")))]
+    pub fn set_congestion_hook(&mut self, hook: CongestionHook) {
+        self.congestion_hook = Some(hook);
+    }
+
     /// Sets qlog output to the designated [`Writer`].
     ///
     /// Only events included in `QlogLevel::Base` are written. The serialization
@@ -2139,7 +3574,7 @@ impl Connection {
             Some(title),
             Some(description),
             None,
-            time::Instant::now(),
+            self.clock.now(),
             trace,
             self.qlog.level,
             writer,
@@ -2152,7 +3587,9 @@ impl Connection {
             .to_qlog(TransportOwner::Local, self.handshake.cipher());
 
         // This event occurs very early, so just mark the relative time as 0.0.
-        streamer.add_event(Event::with_time(0.0, ev_data)).ok();
+        streamer
+            .add_event(qlog::events::Event::with_time(0.0, ev_data))
+            .ok();
 
         self.qlog.streamer = Some(streamer);
     }
@@ -2164,6 +3601,27 @@ impl Connection {
         self.qlog.streamer.as_mut()
     }
 
+    /// Sets the designated [`Writer`] as the output of a binary trace.
+    ///
+    /// A fixed-size record is written for every packet sent or received, and
+    /// whenever the recovery metrics of a path change. This is much cheaper
+    /// than qlog, and the trace can be converted to qlog offline with
+    /// [`btrace::to_qlog()`]. Records are written individually, so the writer
+    /// should be buffered.
+    ///
+    /// This needs to be called as soon as the connection is created, to avoid
+    /// missing some early records.
+    ///
+    /// [`Writer`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    /// [`btrace::to_qlog()`]: btrace/fn.to_qlog.html
+    #[cfg(feature = "btrace")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "btrace")))]
+    pub fn set_btrace(&mut self, writer: Box<dyn std::io::Write + Send + Sync>) {
+        let now = self.clock.now();
+
+        self.btrace = Some(btrace::Writer::new(writer, self.is_server, now));
+    }
+
     /// Configures the given session for resumption.
     ///
     /// On the client, this can be used to offer the given serialized session,
@@ -2199,7 +3657,13 @@ impl Connection {
     /// returned. On error the connection will be closed by calling [`close()`]
     /// with the appropriate error code.
     ///
-    /// Coalesced packets will be processed as necessary.
+    /// Coalesced packets will be processed as necessary. Once a packet in the
+    /// datagram can't be processed, e.g. because it can't be decrypted, or it
+    /// uses a different version than the connection, it is dropped along with
+    /// the rest of the datagram, which can't be reliably split into packets.
+    /// Such bytes still count as processed, so the whole input buffer is
+    /// consumed on success, and trailing garbage after valid packets doesn't
+    /// cause an error.
     ///
     /// Note that the contents of the input buffer `buf` might be modified by
     /// this function due to, for example, in-place decryption.
@@ -2242,6 +3706,19 @@ impl Connection {
             return Err(Error::BufferTooShort);
         }
 
+        // Once the handshake has completed the peer knows the maximum UDP
+        // payload size it is allowed to send, so larger datagrams are dropped
+        // before they can count towards the anti-amplification limit.
+        if self.handshake_completed && len > self.max_recv_udp_payload_size() {
+            trace!(
+                "{} dropped datagram of {} bytes over max_udp_payload_size",
+                self.trace_id,
+                len,
+            );
+
+            return Ok(len);
+        }
+
         let recv_pid = self.paths.path_id_from_addrs(&(info.to, info.from));
 
         if let Some(recv_pid) = recv_pid {
@@ -2286,6 +3763,8 @@ impl Connection {
                 Ok(v) => v,
 
                 Err(Error::Done) => {
+                    self.recv_error_frame_type = None;
+
                     // If the packet can't be processed or decrypted, check if
                     // it's a stateless reset.
                     if self.is_stateless_reset(&buf[len - left..len]) {
@@ -2298,9 +3777,14 @@ impl Connection {
                 },
 
                 Err(e) => {
+                    let frame_type = self.recv_error_frame_type.take();
+
                     // In case of error processing the incoming packet, close
-                    // the connection.
-                    self.close(false, e.to_wire(), b"").ok();
+                    // the connection, reporting the frame that caused it.
+                    if self.close(false, e.to_wire(), b"").is_ok() {
+                        self.local_error_frame_type = frame_type.unwrap_or(0);
+                    }
+
                     return Err(e);
                 },
             };
@@ -2318,6 +3802,23 @@ impl Connection {
     }
 
     fn process_undecrypted_0rtt_packets(&mut self) -> Result<()> {
+        // Buffered 0-RTT packets will never be decryptable if early data was
+        // rejected, so drop them right away.
+        if self.is_early_data_rejected() && !self.undecryptable_pkts.is_empty() {
+            trace!(
+                "{} dropped {} buffered 0-RTT packets, early data rejected",
+                self.trace_id,
+                self.undecryptable_pkts.len()
+            );
+
+            self.notify_dropped_packet(Event::UndecryptablePacket {
+                ty: Type::ZeroRTT,
+                count: self.undecryptable_pkts.len() as u64,
+            });
+
+            self.undecryptable_pkts.clear();
+        }
+
         // Process previously undecryptable 0-RTT packets if the decryption key
         // is now available.
         if self.pkt_num_spaces[packet::Epoch::Application]
@@ -2336,6 +3837,22 @@ impl Connection {
         Ok(())
     }
 
+    /// Returns true if the server processed the client's ClientHello without
+    /// accepting early data, meaning that 0-RTT packets can't be decrypted.
+    ///
+    /// The 0-RTT read key is installed together with the Handshake one when
+    /// early data is accepted, so the absence of the former while the latter
+    /// is available means that early data was rejected.
+    fn is_early_data_rejected(&self) -> bool {
+        self.is_server &&
+            self.pkt_num_spaces[packet::Epoch::Handshake]
+                .crypto_open
+                .is_some() &&
+            self.pkt_num_spaces[packet::Epoch::Application]
+                .crypto_0rtt_open
+                .is_none()
+    }
+
     /// Returns true if a QUIC packet is a stateless reset.
     fn is_stateless_reset(&self, buf: &[u8]) -> bool {
         // If the packet is too small, then we just throw it away.
@@ -2349,11 +3866,10 @@ impl Connection {
         match self.peer_transport_params.stateless_reset_token {
             Some(token) => {
                 let token_len = 16;
-                ring::constant_time::verify_slices_are_equal(
+                crypto::constant_time_eq(
                     &token.to_be_bytes(),
                     &buf[buf_len - token_len..buf_len],
                 )
-                .is_ok()
             },
 
             None => false,
@@ -2377,7 +3893,7 @@ impl Connection {
     fn recv_single(
         &mut self, buf: &mut [u8], info: &RecvInfo, recv_pid: Option<usize>,
     ) -> Result<usize> {
-        let now = time::Instant::now();
+        let now = self.clock.now();
 
         if buf.is_empty() {
             return Err(Error::Done);
@@ -2638,7 +4154,27 @@ impl Connection {
             Some(v) => v,
 
             None => {
+                // Drop 0-RTT packets once it's known that early data was
+                // rejected, without affecting other packets in the datagram.
+                // The client will retransmit their contents in 1-RTT packets.
+                if hdr.ty == packet::Type::ZeroRTT &&
+                    self.is_early_data_rejected()
+                {
+                    trace!(
+                        "{} dropped 0-RTT packet, early data rejected",
+                        self.trace_id
+                    );
+
+                    self.notify_dropped_packet(Event::UndecryptablePacket {
+                        ty: hdr.ty,
+                        count: 1,
+                    });
+
+                    return Ok(b.off() + payload_len);
+                }
+
                 if hdr.ty == packet::Type::ZeroRTT &&
+                    self.is_server &&
                     self.undecryptable_pkts.len() < MAX_UNDECRYPTABLE_PACKETS &&
                     !self.is_established()
                 {
@@ -2654,6 +4190,11 @@ impl Connection {
                     return Ok(pkt_len);
                 }
 
+                self.notify_dropped_packet(Event::UndecryptablePacket {
+                    ty: hdr.ty,
+                    count: 1,
+                });
+
                 let e = drop_pkt_on_err(
                     Error::CryptoFail,
                     self.recv_count,
@@ -2665,8 +4206,33 @@ impl Connection {
             },
         };
 
+        // Drop 0-RTT packets beyond the early data limit. They will be
+        // retransmitted by the client in 1-RTT packets.
+        if hdr.ty == packet::Type::ZeroRTT &&
+            self.early_data_recv.saturating_add(payload_len as u64) >
+                self.max_early_data
+        {
+            trace!("{} dropped 0-RTT packet over limit", self.trace_id);
+
+            return Ok(b.off() + payload_len);
+        }
+
         let aead_tag_len = aead.alg().tag_len();
 
+        // Packets too short to carry a complete header protection sample are
+        // dropped upfront, so that the protected packet number length doesn't
+        // decide whether they are processed further.
+        if payload_len < packet::MAX_PKT_NUM_LEN + packet::SAMPLE_LEN {
+            let e = drop_pkt_on_err(
+                Error::InvalidPacket,
+                self.recv_count,
+                self.is_server,
+                &self.trace_id,
+            );
+
+            return Err(e);
+        }
+
         packet::decrypt_hdr(&mut b, &mut hdr, aead).map_err(|e| {
             drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
         })?;
@@ -2729,22 +4295,55 @@ impl Connection {
             }
         }
 
-        let mut payload = packet::decrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            aead,
-        )
-        .map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
+        let mut payload =
+            match packet::decrypt_pkt(&mut b, pn, pn_len, payload_len, aead) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    self.notify_dropped_packet(Event::UndecryptablePacket {
+                        ty: hdr.ty,
+                        count: 1,
+                    });
+
+                    return Err(drop_pkt_on_err(
+                        e,
+                        self.recv_count,
+                        self.is_server,
+                        &self.trace_id,
+                    ));
+                },
+            };
 
         if self.pkt_num_spaces[epoch].recv_pkt_num.contains(pn) {
             trace!("{} ignored duplicate packet {}", self.trace_id, pn);
+
+            self.notify_dropped_packet(Event::DuplicatePacket {
+                ty: hdr.ty,
+                count: 1,
+            });
+
             return Err(Error::Done);
         }
 
+        if hdr.ty == packet::Type::ZeroRTT {
+            self.early_data_recv += payload_len as u64;
+        }
+
+        // Older packets from the peer still use the keys that preceded a
+        // locally-initiated update, until the peer starts using the new ones.
+        if hdr.ty == Type::Short &&
+            aead_next.is_none() &&
+            hdr.key_phase == self.key_phase
+        {
+            if let Some(key_update) = self.pkt_num_spaces[epoch]
+                .key_update
+                .as_mut()
+                .filter(|key_update| key_update.pn_on_update == u64::MAX)
+            {
+                key_update.pn_on_update = pn;
+            }
+        }
+
         // Packets with no frames are invalid.
         if payload.cap() == 0 {
             return Err(Error::InvalidPacket);
@@ -2866,9 +4465,51 @@ impl Connection {
         // whether this is a non-probing packet.
         let mut probing = true;
 
+        let mut frame_count = 0;
+
         // Process packet payload.
         while payload.cap() > 0 {
-            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+            // Peek the frame type, so it can be reported to the peer in case
+            // the frame turns out to be invalid.
+            let frame_type = octets::Octets::with_slice(payload.as_ref())
+                .get_varint()
+                .unwrap_or(0);
+
+            frame_count += 1;
+
+            if frame_count > self.max_frames_per_packet {
+                trace!("{} too many frames in packet {}", self.trace_id, pn);
+
+                self.recv_error_frame_type = Some(frame_type);
+
+                return Err(Error::InvalidPacket);
+            }
+
+            let frame = match self.extension_frames.get(&frame_type) {
+                Some(handler) => frame::Frame::from_extension_bytes(
+                    &mut payload,
+                    hdr.ty,
+                    handler.as_ref(),
+                ),
+
+                None => frame::Frame::from_bytes(&mut payload, hdr.ty),
+            };
+
+            let frame = match frame {
+                Ok(v) => v,
+
+                Err(e) => {
+                    self.recv_error_frame_type = Some(frame_type);
+
+                    // A frame that can't be parsed is badly formatted,
+                    // regardless of where parsing stopped.
+                    return Err(match e {
+                        Error::BufferTooShort => Error::InvalidFrame,
+
+                        e => e,
+                    });
+                },
+            };
 
             qlog_with_type!(QLOG_PACKET_RX, self.qlog, _q, {
                 qlog_frames.push(frame.to_qlog());
@@ -2884,9 +4525,21 @@ impl Connection {
 
             if let Err(e) = self.process_frame(frame, &hdr, recv_pid, epoch, now)
             {
+                self.recv_error_frame_type = Some(frame_type);
+
+                if e == Error::FlowControl {
+                    self.notify_event(Event::FlowControlViolation(frame_type));
+                }
+
                 frame_processing_err = Some(e);
                 break;
             }
+
+            // Once the peer closed the connection, the remaining frames in the
+            // packet must not be processed.
+            if self.is_draining() {
+                break;
+            }
         }
 
         qlog_with_type!(QLOG_PACKET_RX, self.qlog, q, {
@@ -2929,6 +4582,24 @@ impl Connection {
             }
         });
 
+        #[cfg(feature = "btrace")]
+        if let Some(t) = self.btrace.as_mut() {
+            let recv_path = self.paths.get(recv_pid)?;
+
+            t.record(now, btrace::Record::PacketReceived {
+                ty: hdr.ty,
+                pkt_num: pn,
+                size: b.len(),
+            });
+
+            t.metrics(
+                now,
+                recv_path.recovery.rtt(),
+                recv_path.recovery.cwnd(),
+                recv_path.recovery.bytes_in_flight(),
+            );
+        }
+
         if let Some(e) = frame_processing_err {
             // Any frame error is terminal, so now just return.
             return Err(e);
@@ -3006,8 +4677,20 @@ impl Connection {
                             None => continue,
                         };
 
+                        let was_complete = stream.send.is_complete();
+
                         stream.send.ack_and_drop(offset, length);
 
+                        if !was_complete &&
+                            stream.send.is_complete() &&
+                            self.events_enabled
+                        {
+                            queue_event(
+                                &mut self.events,
+                                Event::StreamFinished(stream_id),
+                            );
+                        }
+
                         self.tx_buffered =
                             self.tx_buffered.saturating_sub(length);
 
@@ -3091,7 +4774,7 @@ impl Connection {
                 );
 
                 p.recovery
-                    .pmtud_update_max_datagram_size(p.pmtud.get_current());
+                    .pmtud_update_max_datagram_size(p.pmtud.get_current(), now);
             }
         }
 
@@ -3128,6 +4811,15 @@ impl Connection {
 
         self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
 
+        // The peer might have asked for ACKs to be sent less frequently.
+        let ack_elicited = ack_elicited &&
+            (epoch != packet::Epoch::Application ||
+                self.ack_freq.on_ack_eliciting_packet(
+                    pn,
+                    self.pkt_num_spaces[epoch].largest_rx_pkt_num,
+                    now,
+                ));
+
         self.pkt_num_spaces[epoch].ack_elicited =
             cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
 
@@ -3144,6 +4836,7 @@ impl Connection {
             let active_path_id = self.paths.get_active_path_id()?;
 
             if self.is_server &&
+                !self.multipath &&
                 recv_pid != active_path_id &&
                 self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num == pn
             {
@@ -3151,6 +4844,23 @@ impl Connection {
             }
         }
 
+        if hdr.ty == packet::Type::Short {
+            let is_server = self.is_server;
+            let recv_path = self.paths.get_mut(recv_pid)?;
+
+            if recv_path.on_spin_bit_received(pn, hdr.spin_bit, is_server) {
+                let local_addr = recv_path.local_addr();
+                let peer_addr = recv_path.peer_addr();
+                let value = recv_path.spin_bit();
+
+                self.notify_event(Event::SpinBitFlipped {
+                    local_addr,
+                    peer_addr,
+                    value,
+                });
+            }
+        }
+
         if let Some(idle_timeout) = self.idle_timeout() {
             self.idle_timer = Some(now + idle_timeout);
         }
@@ -3340,12 +5050,16 @@ impl Connection {
             return Err(Error::Done);
         }
 
-        let now = time::Instant::now();
+        let now = self.clock.now();
 
         if self.local_error.is_none() {
             self.do_handshake(now)?;
         }
 
+        if !self.pre_handshake_data.is_empty() && self.is_app_data_ready() {
+            self.flush_pre_handshake_data();
+        }
+
         // Forwarding the error value here could confuse
         // applications, as they may not expect getting a `recv()`
         // error when calling `send()`.
@@ -3387,7 +5101,7 @@ impl Connection {
                 send_path.pmtud.get_current()
             };
 
-            send_path.recovery.pmtud_update_max_datagram_size(size);
+            send_path.recovery.pmtud_update_max_datagram_size(size, now);
 
             left = cmp::min(out.len(), send_path.recovery.max_datagram_size());
         }
@@ -3398,6 +5112,12 @@ impl Connection {
             left = cmp::min(left, send_path.max_send_bytes);
         }
 
+        // Wait for more data to be queued before sending a mostly-empty
+        // packet, if configured to do so.
+        if self.should_delay_send(send_pid, now) {
+            return Err(Error::Done);
+        }
+
         // Generate coalesced packets.
         while left > 0 {
             let (ty, written) = match self.send_single(
@@ -3465,7 +5185,7 @@ impl Connection {
             from: send_path.local_addr(),
             to: send_path.peer_addr(),
 
-            at: send_path.recovery.get_packet_send_time(),
+            at: send_path.recovery.get_packet_send_time(now),
         };
 
         Ok((done, info))
@@ -3485,6 +5205,8 @@ impl Connection {
 
         let is_closing = self.local_error.is_some();
 
+        let ack_freq_enabled = self.ack_frequency_enabled();
+
         let out_len = out.len();
 
         let mut b = octets::OctetsMut::with_slice(out);
@@ -3500,6 +5222,9 @@ impl Connection {
         let epoch = pkt_type.to_epoch()?;
         let pkt_space = &mut self.pkt_num_spaces[epoch];
 
+        // Whether any stream was reset because its lost data expired.
+        let mut expired_streams = false;
+
         // Process lost frames. There might be several paths having lost frames.
         for (_, p) in self.paths.iter_mut() {
             for lost in p.recovery.get_lost_frames(epoch) {
@@ -3526,9 +5251,51 @@ impl Connection {
                             None => continue,
                         };
 
-                        let was_flushable = stream.is_flushable();
+                        // If the lost data has expired according to the
+                        // stream's reliability mode, don't retransmit it and
+                        // reset the stream instead, so that the peer can skip
+                        // past it.
+                        if !stream.can_retransmit(now, offset, length) {
+                            let priority_key = Arc::clone(&stream.priority_key);
+                            let error_code = stream.reliability_error_code;
 
-                        let empty_fin = length == 0 && fin;
+                            // The stream might have already been reset.
+                            let (final_size, unsent) =
+                                match stream.send.shutdown() {
+                                    Ok(v) => v,
+
+                                    Err(_) => continue,
+                                };
+
+                            // Claw back some flow control allowance from data
+                            // that was buffered but not actually sent.
+                            self.tx_data = self.tx_data.saturating_sub(unsent);
+
+                            self.tx_buffered =
+                                self.tx_buffered.saturating_sub(unsent as usize);
+
+                            self.streams
+                                .insert_reset(stream_id, error_code, final_size);
+
+                            self.streams.remove_writable(&priority_key);
+
+                            self.reset_stream_local_count =
+                                self.reset_stream_local_count.saturating_add(1);
+
+                            expired_streams = true;
+
+                            continue;
+                        }
+
+                        if let StreamReliability::MaxRetransmissions(_) =
+                            stream.reliability
+                        {
+                            stream.send.count_retransmission(offset, length);
+                        }
+
+                        let was_flushable = stream.is_flushable();
+
+                        let empty_fin = length == 0 && fin;
 
                         stream.send.retransmit(offset, length);
 
@@ -3582,6 +5349,10 @@ impl Connection {
                         self.almost_full = true;
                     },
 
+                    frame::Frame::AckFrequency { seq_num, .. } => {
+                        self.ack_freq.on_frame_lost(seq_num);
+                    },
+
                     frame::Frame::NewConnectionId { seq_num, .. } => {
                         self.ids.mark_advertise_new_scid_seq(seq_num, true);
                     },
@@ -3594,15 +5365,40 @@ impl Connection {
                         p.pmtud.pmtu_probe_lost();
                     },
 
+                    frame::Frame::Extension { frame_type, data } => {
+                        let retransmit = self
+                            .extension_frames
+                            .get(&frame_type)
+                            .map_or(false, |h| h.retransmit(frame_type, &data));
+
+                        if retransmit {
+                            self.extension_frames_send.push_back(
+                                frame::Frame::Extension { frame_type, data },
+                            );
+
+                            self.retrans_count += 1;
+                            p.retrans_count += 1;
+                        }
+                    },
+
                     _ => (),
                 }
             }
         }
 
+        if expired_streams {
+            // Update send capacity.
+            self.update_tx_cap();
+        }
+
         let is_app_limited = self.delivery_rate_check_if_app_limited();
         let n_paths = self.paths.len();
         let path = self.paths.get_mut(send_pid)?;
         let flow_control = &mut self.flow_control;
+
+        // With multipath enabled, any usable path can carry non-probing
+        // frames, not only the active one.
+        let path_active = path.active() || (self.multipath && path.usable());
         let pkt_space = &mut self.pkt_num_spaces[epoch];
 
         let mut left = if path.pmtud.is_enabled() {
@@ -3613,8 +5409,16 @@ impl Connection {
         };
 
         let pn = pkt_space.next_pkt_num;
-        let largest_acked_pkt =
-            path.recovery.get_largest_acked_on_epoch(epoch).unwrap_or(0);
+
+        // Packet numbers are shared by all paths, so the largest packet
+        // acknowledged on any of them bounds the number of packets in flight.
+        // Recovery state is per-path though, and a new path (e.g. after the
+        // peer's address changed following an idle period) hasn't seen any
+        // ACK yet.
+        let largest_acked_pkt = cmp::max(
+            pkt_space.largest_acked_pkt_num,
+            path.recovery.get_largest_acked_on_epoch(epoch),
+        );
         let pn_len = packet::pkt_num_len(pn, largest_acked_pkt);
 
         // The AEAD overhead at the current encryption level.
@@ -3655,6 +5459,7 @@ impl Connection {
 
             versions: None,
             key_phase: self.key_phase,
+            spin_bit: path.spin_bit(),
         };
 
         hdr.to_bytes(&mut b)?;
@@ -3747,14 +5552,14 @@ impl Connection {
         // generate an ACK (if there's anything to ACK) since we're going to
         // send a packet with PING anyways, even if we haven't received anything
         // ACK eliciting.
-        if pkt_space.recv_pkt_need_ack.len() > 0 &&
+        if !pkt_space.recv_pkt_need_ack.is_empty() &&
             (pkt_space.ack_elicited || ack_elicit_required) &&
             (!is_closing ||
                 (pkt_type == Type::Handshake &&
                     self.local_error
                         .as_ref()
                         .map_or(false, |le| le.is_app))) &&
-            path.active()
+            path_active
         {
             let ack_delay = pkt_space.largest_rx_pkt_time.elapsed();
 
@@ -3778,6 +5583,10 @@ impl Connection {
                 // available cwnd.
                 if push_frame_to_pkt!(b, frames, frame, left) {
                     pkt_space.ack_elicited = false;
+
+                    if epoch == packet::Epoch::Application {
+                        self.ack_freq.on_ack_sent();
+                    }
                 }
             }
         }
@@ -3882,7 +5691,8 @@ impl Connection {
             // Create PATH_CHALLENGE frame if needed.
             if path.validation_requested() {
                 // TODO: ensure that data is unique over paths.
-                let data = rand::rand_u64().to_be_bytes();
+                let mut data = [0; 8];
+                self.rng.fill_bytes(&mut data);
 
                 let frame = frame::Frame::PathChallenge { data };
 
@@ -3918,7 +5728,7 @@ impl Connection {
             }
         }
 
-        if pkt_type == packet::Type::Short && !is_closing && path.active() {
+        if pkt_type == packet::Type::Short && !is_closing && path_active {
             // Create HANDSHAKE_DONE frame.
             // self.should_send_handshake_done() but without the need to borrow
             if self.handshake_completed &&
@@ -3935,6 +5745,32 @@ impl Connection {
                 }
             }
 
+            // Create ACK_FREQUENCY frame.
+            if ack_freq_enabled && self.handshake_confirmed {
+                let threshold = path.recovery.ack_eliciting_threshold();
+
+                if self.ack_freq.should_update(threshold) {
+                    // Keep the peer's ACK delay, so that the PTO doesn't
+                    // change, but never request less than the peer's
+                    // minimum, which it would treat as a protocol violation.
+                    let min_ack_delay = time::Duration::from_micros(
+                        self.peer_transport_params.min_ack_delay.unwrap_or(0),
+                    );
+
+                    let max_ack_delay =
+                        self.recovery_config.max_ack_delay.max(min_ack_delay);
+
+                    let frame = self.ack_freq.frame(threshold, max_ack_delay);
+
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        self.ack_freq.on_frame_sent(threshold);
+
+                        ack_eliciting = true;
+                        in_flight = true;
+                    }
+                }
+            }
+
             // Create MAX_STREAMS_BIDI frame.
             if self.streams.should_update_max_streams_bidi() {
                 let frame = frame::Frame::MaxStreamsBidi {
@@ -4120,6 +5956,21 @@ impl Connection {
                     break;
                 }
             }
+
+            // Create extension frames as needed, in the order they were
+            // queued.
+            while self
+                .extension_frames_send
+                .front()
+                .map_or(false, |f| f.wire_len() <= left)
+            {
+                if let Some(frame) = self.extension_frames_send.pop_front() {
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        ack_eliciting = true;
+                        in_flight = true;
+                    }
+                }
+            }
         }
 
         // Create CONNECTION_CLOSE frame. Try to send this only on the active
@@ -4146,7 +5997,7 @@ impl Connection {
                     // Create ConnectionClose frame.
                     let frame = frame::Frame::ConnectionClose {
                         error_code: conn_err.error_code,
-                        frame_type: 0,
+                        frame_type: self.local_error_frame_type,
                         reason: conn_err.reason.clone(),
                     };
 
@@ -4165,7 +6016,7 @@ impl Connection {
         if pkt_space.crypto_stream.is_flushable() &&
             left > frame::MAX_CRYPTO_OVERHEAD &&
             !is_closing &&
-            path.active()
+            path_active
         {
             let crypto_off = pkt_space.crypto_stream.send.off_front();
 
@@ -4247,7 +6098,7 @@ impl Connection {
         if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
             left > frame::MAX_DGRAM_OVERHEAD &&
             !is_closing &&
-            path.active() &&
+            path_active &&
             do_dgram
         {
             if let Some(max_dgram_payload) = max_dgram_len {
@@ -4325,7 +6176,7 @@ impl Connection {
         if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
             left > frame::MAX_STREAM_OVERHEAD &&
             !is_closing &&
-            path.active() &&
+            path_active &&
             !dgram_emitted
         {
             while let Some(priority_key) = self.streams.peek_flushable() {
@@ -4441,12 +6292,22 @@ impl Connection {
         // - if we've sent too many non ack-eliciting packets without having
         // sent an ACK eliciting one; OR
         // - the application requested an ack-eliciting frame be sent.
+        //
+        // When the peer might be delaying ACKs, PTO probes carry an
+        // IMMEDIATE_ACK frame instead.
         if (ack_elicit_required || path.needs_ack_eliciting) &&
             !ack_eliciting &&
             left >= 1 &&
             !is_closing
         {
-            let frame = frame::Frame::Ping { mtu_probe: None };
+            let frame = if ack_elicit_required &&
+                ack_freq_enabled &&
+                pkt_type == packet::Type::Short
+            {
+                frame::Frame::ImmediateAck
+            } else {
+                frame::Frame::Ping { mtu_probe: None }
+            };
 
             if push_frame_to_pkt!(b, frames, frame, left) {
                 ack_eliciting = true;
@@ -4457,6 +6318,11 @@ impl Connection {
         if ack_eliciting && !pmtud_probe {
             path.needs_ack_eliciting = false;
             path.recovery.ping_sent(epoch);
+
+            if path.rtt_probe_pending && epoch == packet::Epoch::Application {
+                path.rtt_probe_pending = false;
+                path.rtt_probe_pkt_num = Some(pn);
+            }
         }
 
         if frames.is_empty() {
@@ -4566,6 +6432,19 @@ impl Connection {
             }
         });
 
+        // Randomize the fixed bit if the peer supports it, before the header
+        // is authenticated.
+        if pkt_type == packet::Type::Short &&
+            self.peer_transport_params.grease_quic_bit
+        {
+            let mut grease = [0];
+            self.rng.fill_bytes(&mut grease);
+
+            if grease[0] & 1 == 0 {
+                packet::grease_fixed_bit(&mut b)?;
+            }
+        }
+
         let aead = match pkt_space.crypto_seal {
             Some(ref v) => v,
             None => return Err(Error::InvalidState),
@@ -4581,6 +6460,9 @@ impl Connection {
             aead,
         )?;
 
+        #[cfg(feature = "instrumentation")]
+        let (sent_ack_eliciting, sent_in_flight) = (ack_eliciting, in_flight);
+
         let sent_pkt = recovery::Sent {
             pkt_num: pn,
             frames,
@@ -4621,12 +6503,47 @@ impl Connection {
             &self.trace_id,
         );
 
+        #[cfg(feature = "instrumentation")]
+        if let Some(hook) = self.tx_hook.as_mut() {
+            hook(&TxEvent {
+                pkt_num: pn,
+                ty: hdr_ty,
+                size: written,
+                ack_eliciting: sent_ack_eliciting,
+                in_flight: sent_in_flight,
+                bytes_in_flight: path.recovery.bytes_in_flight(),
+                cwnd: path.recovery.cwnd(),
+                rtt: path.recovery.rtt(),
+                from: path.local_addr(),
+                to: path.peer_addr(),
+                time: now,
+            });
+        }
+
         qlog_with_type!(QLOG_METRICS, self.qlog, q, {
             if let Some(ev_data) = path.recovery.maybe_qlog() {
                 q.add_event_data_with_instant(ev_data, now).ok();
             }
         });
 
+        #[cfg(feature = "btrace")]
+        if let Some(t) = self.btrace.as_mut() {
+            t.record(now, btrace::Record::PacketSent {
+                ty: hdr_ty,
+                pkt_num: pn,
+                size: written,
+            });
+
+            t.metrics(
+                now,
+                path.recovery.rtt(),
+                path.recovery.cwnd(),
+                path.recovery.bytes_in_flight(),
+            );
+        }
+
+        report_congestion_transitions!(self, path, now);
+
         // Record sent packet size if we probe the path.
         if let Some(data) = challenge_data {
             path.add_challenge_sent(data, written, now);
@@ -4662,9 +6579,10 @@ impl Connection {
 
         let active_path = self.paths.get_active_mut()?;
         if active_path.pmtud.is_enabled() {
-            active_path
-                .recovery
-                .pmtud_update_max_datagram_size(active_path.pmtud.get_current());
+            active_path.recovery.pmtud_update_max_datagram_size(
+                active_path.pmtud.get_current(),
+                now,
+            );
         }
 
         Ok((pkt_type, written))
@@ -4707,6 +6625,34 @@ impl Connection {
             .unwrap_or(0)
     }
 
+    /// Returns the amount of new stream data, in bytes, that can currently be
+    /// sent on the connection.
+    ///
+    /// This is the minimum between the congestion window space left on the
+    /// active path and the connection-level flow control limit set by the
+    /// peer, minus stream data that was already written but not sent yet.
+    ///
+    /// Applications can use it to avoid generating more data than what can
+    /// actually be sent right away. The capacity of an individual stream, which
+    /// also takes into account the stream's flow control limit, is returned by
+    /// [`stream_capacity()`].
+    ///
+    /// Note that pacing is not taken into account, the time at which packets
+    /// should be sent is returned by [`send()`] instead.
+    ///
+    /// Once the connection is closing or closed, this method returns 0.
+    ///
+    /// [`stream_capacity()`]: struct.Connection.html#method.stream_capacity
+    /// [`send()`]: struct.Connection.html#method.send
+    #[inline]
+    pub fn send_capacity(&self) -> usize {
+        if self.closed_stream_error().is_some() {
+            return 0;
+        }
+
+        self.tx_cap
+    }
+
     /// Reads contiguous data from a stream into the provided slice.
     ///
     /// The slice must be sized by the caller and will be populated up to its
@@ -4715,10 +6661,14 @@ impl Connection {
     /// On success the amount of bytes read and a flag indicating the fin state
     /// is returned as a tuple, or [`Done`] if there is no data to read.
     ///
+    /// Once the connection is closing or closed and all buffered data was
+    /// read, [`ConnectionClosed`] is returned instead of [`Done`].
+    ///
     /// Reading data from a stream may trigger queueing of control messages
     /// (e.g. MAX_STREAM_DATA). [`send()`] should be called after reading.
     ///
     /// [`Done`]: enum.Error.html#variant.Done
+    /// [`ConnectionClosed`]: enum.Error.html#variant.ConnectionClosed
     /// [`send()`]: struct.Connection.html#method.send
     ///
     /// ## Examples:
@@ -4747,16 +6697,21 @@ impl Connection {
             return Err(Error::InvalidStreamState(stream_id));
         }
 
+        let closed_err = self.closed_stream_error();
+
         let stream = self
             .streams
             .get_mut(stream_id)
             .ok_or(Error::InvalidStreamState(stream_id))?;
 
         if !stream.is_readable() {
-            return Err(Error::Done);
+            // Data that was already buffered can still be read after the
+            // connection is closed, but no more data will ever arrive.
+            return Err(closed_err.unwrap_or(Error::Done));
         }
 
         let local = stream.local;
+        let group = stream.group;
         let priority_key = Arc::clone(&stream.priority_key);
 
         #[cfg(feature = "qlog")]
@@ -4793,6 +6748,10 @@ impl Connection {
             self.streams.remove_readable(&priority_key);
         }
 
+        if let Some(group) = group.and_then(|g| self.streams.group_mut(g)) {
+            group.stats.rx_data += read as u64;
+        }
+
         if complete {
             self.streams.collect(stream_id, local);
         }
@@ -4807,7 +6766,7 @@ impl Connection {
                 raw: None,
             });
 
-            let now = time::Instant::now();
+            let now = self.clock.now();
             q.add_event_data_with_instant(ev_data, now).ok();
         });
 
@@ -4824,6 +6783,41 @@ impl Connection {
         Ok((read, fin))
     }
 
+    /// Opens a new locally-initiated bidirectional stream.
+    ///
+    /// On success the ID of the new stream is returned. Stream IDs are
+    /// allocated in order, skipping any ID that was already used by the
+    /// application (e.g. by calling [`stream_send()`] directly).
+    ///
+    /// The [`StreamLimit`] error is returned when the peer's stream count
+    /// limit has been reached, in which case the application should wait for
+    /// [`peer_streams_left_bidi()`] to increase.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`StreamLimit`]: enum.Error.html#variant.StreamLimit
+    /// [`peer_streams_left_bidi()`]: struct.Connection.html#method.peer_streams_left_bidi
+    pub fn stream_open_bidi(&mut self) -> Result<u64> {
+        self.stream_open(true)
+    }
+
+    /// Opens a new locally-initiated unidirectional stream.
+    ///
+    /// This behaves like [`stream_open_bidi()`], but for unidirectional
+    /// streams.
+    ///
+    /// [`stream_open_bidi()`]: struct.Connection.html#method.stream_open_bidi
+    pub fn stream_open_uni(&mut self) -> Result<u64> {
+        self.stream_open(false)
+    }
+
+    fn stream_open(&mut self, bidi: bool) -> Result<u64> {
+        let stream_id = self.streams.next_local_stream_id(bidi, self.is_server);
+
+        self.get_or_create_stream(stream_id, true)?;
+
+        Ok(stream_id)
+    }
+
     /// Writes data to a stream.
     ///
     /// On success the number of bytes written is returned, or [`Done`] if no
@@ -4831,13 +6825,19 @@ impl Connection {
     ///
     /// Applications can provide a 0-length buffer with the fin flag set to
     /// true. This will lead to a 0-length FIN STREAM frame being sent at the
-    /// latest offset. The `Ok(0)` value is only returned when the application
-    /// provided a 0-length buffer.
+    /// latest offset, even when the stream or connection flow control limits
+    /// don't allow any more data to be sent. The `Ok(0)` value is only
+    /// returned when the application provided a 0-length buffer.
     ///
     /// In addition, if the peer has signalled that it doesn't want to receive
     /// any more data from this stream by sending the `STOP_SENDING` frame, the
     /// [`StreamStopped`] error will be returned instead of any data.
     ///
+    /// Writing to a stream that can only be initiated by the peer, and that
+    /// the peer hasn't opened yet, returns the [`InvalidStreamState`] error.
+    /// New local streams can be allocated with [`stream_open_bidi()`] and
+    /// [`stream_open_uni()`].
+    ///
     /// Note that in order to avoid buffering an infinite amount of data in the
     /// stream's send buffer, streams are only allowed to buffer outgoing data
     /// up to the amount that the peer allows it to send (that is, up to the
@@ -4852,10 +6852,26 @@ impl Connection {
     /// completed (whenever [`is_established()`] returns `true`) or during
     /// early data if enabled (whenever [`is_in_early_data()`] returns `true`).
     ///
+    /// Alternatively, when buffering of data before the handshake is enabled
+    /// with [`set_max_pre_handshake_data()`], data written to locally-initiated
+    /// streams before the connection is ready for application data (see
+    /// [`is_app_data_ready()`]) is queued, and automatically written to the
+    /// streams once the peer's limits are known.
+    ///
+    /// Once the connection is closing or closed, [`ConnectionClosed`] is
+    /// returned.
+    ///
     /// [`Done`]: enum.Error.html#variant.Done
     /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
+    /// [`ConnectionClosed`]: enum.Error.html#variant.ConnectionClosed
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    /// [`stream_open_bidi()`]: struct.Connection.html#method.stream_open_bidi
+    /// [`stream_open_uni()`]: struct.Connection.html#method.stream_open_uni
     /// [`is_established()`]: struct.Connection.html#method.is_established
     /// [`is_in_early_data()`]: struct.Connection.html#method.is_in_early_data
+    /// [`set_max_pre_handshake_data()`]:
+    /// struct.Config.html#method.set_max_pre_handshake_data
+    /// [`is_app_data_ready()`]: struct.Connection.html#method.is_app_data_ready
     ///
     /// ## Examples:
     ///
@@ -4881,6 +6897,107 @@ impl Connection {
             return Err(Error::InvalidStreamState(stream_id));
         }
 
+        if let Some(e) = self.closed_stream_error() {
+            return Err(e);
+        }
+
+        if self.max_pre_handshake_data > 0 {
+            if self.is_app_data_ready() {
+                self.flush_pre_handshake_data();
+            }
+
+            // Data queued for the stream needs to be written first, so keep
+            // queueing until that happens.
+            let queued = self
+                .pre_handshake_data
+                .iter()
+                .any(|(id, ..)| *id == stream_id);
+
+            if !self.is_app_data_ready() || queued {
+                return self.stream_send_pre_handshake(stream_id, buf, fin);
+            }
+        }
+
+        self.stream_write(stream_id, buf, fin)
+    }
+
+    /// Queues data written before the connection is ready for application
+    /// data.
+    fn stream_send_pre_handshake(
+        &mut self, stream_id: u64, buf: &[u8], fin: bool,
+    ) -> Result<usize> {
+        // Peer-initiated streams can't exist yet.
+        if !stream::is_local(stream_id, self.is_server) {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let cap = self.max_pre_handshake_data - self.pre_handshake_data_len;
+
+        if cap == 0 && !buf.is_empty() {
+            return Err(Error::Done);
+        }
+
+        let (buf, fin) = if cap < buf.len() {
+            (&buf[..cap], false)
+        } else {
+            (buf, fin)
+        };
+
+        self.pre_handshake_data
+            .push_back((stream_id, buf.to_vec(), fin));
+        self.pre_handshake_data_len += buf.len();
+
+        Ok(buf.len())
+    }
+
+    /// Writes data queued before the connection was ready for application
+    /// data to the respective streams, as far as their limits allow.
+    fn flush_pre_handshake_data(&mut self) {
+        while let Some((stream_id, data, fin)) =
+            self.pre_handshake_data.pop_front()
+        {
+            let written = match self.stream_write(stream_id, &data, fin) {
+                Ok(v) => v,
+
+                // Retry once the stream or connection limits are raised.
+                Err(Error::Done) | Err(Error::StreamLimit) => {
+                    self.pre_handshake_data.push_front((stream_id, data, fin));
+
+                    break;
+                },
+
+                // The data can't be sent at all, so drop it.
+                Err(e) => {
+                    trace!(
+                        "{} dropped queued data on stream {}: {:?}",
+                        self.trace_id,
+                        stream_id,
+                        e
+                    );
+
+                    self.pre_handshake_data_len -= data.len();
+
+                    continue;
+                },
+            };
+
+            self.pre_handshake_data_len -= written;
+
+            if written < data.len() {
+                self.pre_handshake_data.push_front((
+                    stream_id,
+                    data[written..].to_vec(),
+                    fin,
+                ));
+
+                break;
+            }
+        }
+    }
+
+    fn stream_write(
+        &mut self, stream_id: u64, buf: &[u8], fin: bool,
+    ) -> Result<usize> {
         // Mark the connection as blocked if the connection-level flow control
         // limit doesn't let us buffer all the data.
         //
@@ -4890,9 +7007,16 @@ impl Connection {
             self.blocked_limit = Some(self.max_tx_data);
         }
 
-        let cap = self.tx_cap;
+        // Also limit the data to what the stream's group allows, if any.
+        let cap = match self.streams.group_cap(stream_id) {
+            Some(group_cap) => cmp::min(self.tx_cap as u64, group_cap) as usize,
+
+            None => self.tx_cap,
+        };
 
         // Get existing stream or create a new one.
+        let autoflush = self.autoflush;
+
         let stream = self.get_or_create_stream(stream_id, true)?;
 
         #[cfg(feature = "qlog")]
@@ -4929,6 +7053,11 @@ impl Connection {
             (buf, fin, false)
         };
 
+        // Hold back the new data until the stream is flushed.
+        if !autoflush {
+            stream.send.hold();
+        }
+
         let sent = match stream.send.write(buf, fin) {
             Ok(v) => v,
 
@@ -4939,6 +7068,7 @@ impl Connection {
         };
 
         let incremental = stream.incremental;
+        let group = stream.group;
         let priority_key = Arc::clone(&stream.priority_key);
 
         let flushable = stream.is_flushable();
@@ -4964,10 +7094,14 @@ impl Connection {
         //
         // Consider the stream flushable also when we are sending a zero-length
         // frame that has the fin flag set.
-        if (flushable || empty_fin) && !was_flushable {
+        if (flushable || (empty_fin && self.autoflush)) && !was_flushable {
             self.streams.insert_flushable(&priority_key);
         }
 
+        if !self.autoflush {
+            self.streams.insert_held(stream_id, empty_fin);
+        }
+
         if !writable {
             self.streams.remove_writable(&priority_key);
         } else if was_writable && blocked_by_cap {
@@ -4986,6 +7120,10 @@ impl Connection {
 
         self.tx_buffered += sent;
 
+        if let Some(group) = group.and_then(|g| self.streams.group_mut(g)) {
+            group.stats.tx_data += sent as u64;
+        }
+
         qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
             let ev_data = EventData::DataMoved(qlog::events::quic::DataMoved {
                 stream_id: Some(stream_id),
@@ -4996,7 +7134,7 @@ impl Connection {
                 raw: None,
             });
 
-            let now = time::Instant::now();
+            let now = self.clock.now();
             q.add_event_data_with_instant(ev_data, now).ok();
         });
 
@@ -5013,6 +7151,92 @@ impl Connection {
         Ok(sent)
     }
 
+    /// Allows data held back on a stream to be sent.
+    ///
+    /// When automatic flushing is disabled (see [`set_autoflush()`]), data
+    /// written with [`stream_send()`] is only sent once the stream is
+    /// flushed. Calling this on a stream that has no data held back has no
+    /// effect.
+    ///
+    /// [`set_autoflush()`]: struct.Config.html#method.set_autoflush
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    pub fn stream_flush(&mut self, stream_id: u64) {
+        let empty_fin = match self.streams.remove_held(stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let stream = match self.streams.get_mut(stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let was_flushable = stream.is_flushable();
+
+        stream.send.flush();
+
+        // If the stream is now flushable push it to the flushable queue, but
+        // only if it wasn't already queued.
+        if (stream.is_flushable() || empty_fin) && !was_flushable {
+            let priority_key = Arc::clone(&stream.priority_key);
+            self.streams.insert_flushable(&priority_key);
+        }
+    }
+
+    /// Allows data held back on all streams to be sent.
+    ///
+    /// This is equivalent to calling [`stream_flush()`] on every stream.
+    ///
+    /// [`stream_flush()`]: struct.Connection.html#method.stream_flush
+    pub fn flush(&mut self) {
+        for stream_id in self.streams.held() {
+            self.stream_flush(stream_id);
+        }
+    }
+
+    /// Configures whether packets are sent without waiting for more data.
+    ///
+    /// When enabled, [`send()`] never delays packets, even if a send
+    /// coalescing delay was configured with [`set_send_coalescing_delay()`].
+    /// This allows switching to a low-latency mode for latency-sensitive
+    /// phases of the connection.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`set_send_coalescing_delay()`]:
+    /// struct.Config.html#method.set_send_coalescing_delay
+    pub fn set_send_immediate(&mut self, v: bool) {
+        self.send_immediate = v;
+
+        if v {
+            self.send_coalescing_timer = None;
+        }
+    }
+
+    /// Sets the max value for pacing rate, in bytes per second.
+    ///
+    /// This overrides the value set with [`Config::set_max_pacing_rate()`],
+    /// and applies to all paths of the connection, including the ones
+    /// created later. The rate is enforced by the pacer regardless of the
+    /// congestion window, so it can be used to limit the send rate of a
+    /// connection (e.g. to the bitrate of a media stream), and can be updated
+    /// at any time.
+    ///
+    /// The pacing timestamps are returned in [`SendInfo`], so they are only
+    /// enforced if the application delays sending packets accordingly.
+    ///
+    /// [`Config::set_max_pacing_rate()`]:
+    /// struct.Config.html#method.set_max_pacing_rate
+    /// [`SendInfo`]: struct.SendInfo.html
+    pub fn set_max_pacing_rate(&mut self, v: u64) {
+        self.recovery_config.max_pacing_rate = Some(v);
+
+        for (_, path) in self.paths.iter_mut() {
+            path.recovery.set_max_pacing_rate(Some(v));
+        }
+    }
+
     /// Sets the priority for a stream.
     ///
     /// A stream's priority determines the order in which stream data is sent
@@ -5057,39 +7281,183 @@ impl Connection {
         Ok(())
     }
 
-    /// Shuts down reading or writing from/to the specified stream.
-    ///
-    /// When the `direction` argument is set to [`Shutdown::Read`], outstanding
-    /// data in the stream's receive buffer is dropped, and no additional data
-    /// is added to it. Data received after calling this method is still
-    /// validated and acked but not stored, and [`stream_recv()`] will not
-    /// return it to the application. In addition, a `STOP_SENDING` frame will
-    /// be sent to the peer to signal it to stop sending data.
+    /// Sets the reliability mode for a stream.
     ///
-    /// When the `direction` argument is set to [`Shutdown::Write`], outstanding
-    /// data in the stream's send buffer is dropped, and no additional data is
-    /// added to it. Data passed to [`stream_send()`] after calling this method
-    /// will be ignored. In addition, a `RESET_STREAM` frame will be sent to the
-    /// peer to signal the reset.
+    /// By default streams are fully reliable, and lost data is retransmitted
+    /// until it is acknowledged. With a [`Deadline`] or [`MaxRetransmissions`]
+    /// mode, lost data that can no longer be retransmitted is dropped instead,
+    /// and the stream is reset with the given `err` code, as if
+    /// [`stream_shutdown()`] had been called with [`Shutdown::Write`].
     ///
-    /// Locally-initiated unidirectional streams can only be closed in the
-    /// [`Shutdown::Write`] direction. Remotely-initiated unidirectional streams
-    /// can only be closed in the [`Shutdown::Read`] direction. Using an
-    /// incorrect direction will return [`InvalidStreamState`].
+    /// The target stream is created if it did not exist before calling this
+    /// method.
     ///
-    /// [`Shutdown::Read`]: enum.Shutdown.html#variant.Read
+    /// [`Deadline`]: enum.StreamReliability.html#variant.Deadline
+    /// [`MaxRetransmissions`]: enum.StreamReliability.html#variant.MaxRetransmissions
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
     /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
-    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
-    /// [`stream_send()`]: struct.Connection.html#method.stream_send
-    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
-    pub fn stream_shutdown(
-        &mut self, stream_id: u64, direction: Shutdown, err: u64,
+    pub fn stream_reliability(
+        &mut self, stream_id: u64, reliability: StreamReliability, err: u64,
     ) -> Result<()> {
-        // Don't try to stop a local unidirectional stream.
-        if direction == Shutdown::Read &&
-            stream::is_local(stream_id, self.is_server) &&
-            !stream::is_bidi(stream_id)
-        {
+        // Get existing stream or create a new one, but if the stream
+        // has already been closed and collected, ignore the setting.
+        let stream = match self.get_or_create_stream(stream_id, true) {
+            Ok(v) => v,
+
+            Err(Error::Done) => return Ok(()),
+
+            Err(e) => return Err(e),
+        };
+
+        stream.reliability = reliability;
+        stream.reliability_error_code = err;
+
+        Ok(())
+    }
+
+    /// Adds a stream to a group.
+    ///
+    /// Stream groups allow the application to associate streams with each
+    /// other, for example the streams belonging to the same session, and to
+    /// track, limit and close them together. A group is created when the first
+    /// stream is added to it, and removed once its last stream is completed
+    /// and collected.
+    ///
+    /// A stream can only belong to a single group, so if the stream is already
+    /// part of a different group the [`InvalidStreamState`] error will be
+    /// returned.
+    ///
+    /// The target stream is created if it did not exist before calling this
+    /// method.
+    ///
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_group(&mut self, stream_id: u64, group_id: u64) -> Result<()> {
+        // Get existing stream or create a new one.
+        self.get_or_create_stream(stream_id, true)?;
+
+        self.streams.insert_group(stream_id, group_id)
+    }
+
+    /// Returns the accounting state of a stream group.
+    ///
+    /// If the group doesn't exist (including when it has already been closed,
+    /// or all of its streams have been collected), `None` is returned.
+    pub fn group_stats(&self, group_id: u64) -> Option<StreamGroupStats> {
+        self.streams.group(group_id).map(|g| g.stats)
+    }
+
+    /// Limits the amount of data the application can write on a stream group.
+    ///
+    /// Once the group's streams have been written `max` bytes in total
+    /// (including data written before the limit was set), [`stream_send()`]
+    /// on any of them returns [`Done`], as if they were blocked by flow
+    /// control, until the limit is raised.
+    ///
+    /// If the group doesn't exist, the [`Done`] error will be returned.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn group_set_max_data(&mut self, group_id: u64, max: u64) -> Result<()> {
+        let group = self.streams.group_mut(group_id).ok_or(Error::Done)?;
+
+        group.max_tx_data = Some(max);
+
+        // Streams blocked by the group's previous limit might be writable now.
+        if group.tx_cap() > Some(0) {
+            for stream_id in self.streams.group_streams(group_id) {
+                let stream = match self.streams.get(stream_id) {
+                    Some(v) => v,
+
+                    None => continue,
+                };
+
+                if stream.is_writable() {
+                    let priority_key = Arc::clone(&stream.priority_key);
+
+                    self.streams.insert_writable(&priority_key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the streams that belong to a stream group.
+    ///
+    /// Streams that have already been completed and collected are not
+    /// included.
+    pub fn group_streams(&self, group_id: u64) -> StreamIter {
+        self.streams.group_streams(group_id)
+    }
+
+    /// Closes a stream group.
+    ///
+    /// Both directions of every stream in the group are shut down with the
+    /// given `err` code, as if [`stream_shutdown()`] had been called on each
+    /// of them, and the group is removed.
+    ///
+    /// If the group doesn't exist, the [`Done`] error will be returned.
+    ///
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn group_close(&mut self, group_id: u64, err: u64) -> Result<()> {
+        if self.streams.group(group_id).is_none() {
+            return Err(Error::Done);
+        }
+
+        for stream_id in self.streams.group_streams(group_id) {
+            let local = stream::is_local(stream_id, self.is_server);
+            let bidi = stream::is_bidi(stream_id);
+
+            // Streams might have already been shut down by the application,
+            // so errors from individual directions are ignored.
+            if bidi || !local {
+                self.stream_shutdown(stream_id, Shutdown::Read, err).ok();
+            }
+
+            if bidi || local {
+                self.stream_shutdown(stream_id, Shutdown::Write, err).ok();
+            }
+        }
+
+        self.streams.remove_group(group_id);
+
+        Ok(())
+    }
+
+    /// Shuts down reading or writing from/to the specified stream.
+    ///
+    /// When the `direction` argument is set to [`Shutdown::Read`], outstanding
+    /// data in the stream's receive buffer is dropped, and no additional data
+    /// is added to it. Data received after calling this method is still
+    /// validated and acked but not stored, and [`stream_recv()`] will not
+    /// return it to the application. In addition, a `STOP_SENDING` frame will
+    /// be sent to the peer to signal it to stop sending data.
+    ///
+    /// When the `direction` argument is set to [`Shutdown::Write`], outstanding
+    /// data in the stream's send buffer is dropped, and no additional data is
+    /// added to it. Data passed to [`stream_send()`] after calling this method
+    /// will be ignored. In addition, a `RESET_STREAM` frame will be sent to the
+    /// peer to signal the reset.
+    ///
+    /// Locally-initiated unidirectional streams can only be closed in the
+    /// [`Shutdown::Write`] direction. Remotely-initiated unidirectional streams
+    /// can only be closed in the [`Shutdown::Read`] direction. Using an
+    /// incorrect direction will return [`InvalidStreamState`].
+    ///
+    /// [`Shutdown::Read`]: enum.Shutdown.html#variant.Read
+    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_shutdown(
+        &mut self, stream_id: u64, direction: Shutdown, err: u64,
+    ) -> Result<()> {
+        // Don't try to stop a local unidirectional stream.
+        if direction == Shutdown::Read &&
+            stream::is_local(stream_id, self.is_server) &&
+            !stream::is_bidi(stream_id)
+        {
             return Err(Error::InvalidStreamState(stream_id));
         }
 
@@ -5108,8 +7476,15 @@ impl Connection {
 
         match direction {
             Shutdown::Read => {
+                let consumed_off = stream.recv.consumed_off();
+
                 stream.recv.shutdown()?;
 
+                // Data that was buffered but not read by the application will
+                // never be, so consider it consumed, which might trigger a flow
+                // control update.
+                let discarded = stream.recv.consumed_off() - consumed_off;
+
                 if !stream.recv.is_fin() {
                     self.streams.insert_stopped(stream_id, err);
                 }
@@ -5119,6 +7494,12 @@ impl Connection {
 
                 self.stopped_stream_local_count =
                     self.stopped_stream_local_count.saturating_add(1);
+
+                self.flow_control.add_consumed(discarded);
+
+                if self.should_update_max_data() {
+                    self.almost_full = true;
+                }
             },
 
             Shutdown::Write => {
@@ -5147,6 +7528,72 @@ impl Connection {
         Ok(())
     }
 
+    /// Drops data written to the stream that hasn't been sent yet.
+    ///
+    /// All the data written at or after `from_offset` is removed from the
+    /// stream's send buffer, as if it had never been written, and the
+    /// connection-level flow control credit it used is recovered. If the final
+    /// size of the stream was set with the data, it is cleared as well. New
+    /// data written with [`stream_send()`] continues from `from_offset`.
+    ///
+    /// This allows applications to abort e.g. a response cheaply, without
+    /// resetting the stream, as long as none of the data has been sent.
+    ///
+    /// On success the number of bytes dropped is returned. If there is no
+    /// data at or after `from_offset`, [`Done`] is returned.
+    ///
+    /// If the specified stream doesn't exist, or if some of the data at or
+    /// after `from_offset` was already sent, the [`InvalidStreamState`] error
+    /// will be returned. In the latter case the stream can be reset with
+    /// [`stream_shutdown()`] instead.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_cancel_pending(
+        &mut self, stream_id: u64, from_offset: u64,
+    ) -> Result<usize> {
+        let stream = self
+            .streams
+            .get_mut(stream_id)
+            .ok_or(Error::InvalidStreamState(stream_id))?;
+
+        let was_writable = stream.is_writable();
+
+        let dropped = match stream.send.truncate(from_offset) {
+            Ok(v) => v,
+
+            Err(Error::InvalidState) =>
+                return Err(Error::InvalidStreamState(stream_id)),
+
+            Err(e) => return Err(e),
+        };
+
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        let flushable = stream.is_flushable();
+
+        let writable = stream.is_writable();
+
+        if !flushable {
+            self.streams.remove_flushable(&priority_key);
+        }
+
+        if !was_writable && writable {
+            self.streams.insert_writable(&priority_key);
+        }
+
+        // The dropped data no longer counts towards flow control.
+        self.tx_data = self.tx_data.saturating_sub(dropped);
+
+        self.tx_buffered = self.tx_buffered.saturating_sub(dropped as usize);
+
+        self.update_tx_cap();
+
+        Ok(dropped as usize)
+    }
+
     /// Returns the stream's send capacity in bytes.
     ///
     /// If the specified stream doesn't exist (including when it has already
@@ -5162,13 +7609,42 @@ impl Connection {
     #[inline]
     pub fn stream_capacity(&self, stream_id: u64) -> Result<usize> {
         if let Some(stream) = self.streams.get(stream_id) {
-            let cap = cmp::min(self.tx_cap, stream.send.cap()?);
+            let mut cap = cmp::min(self.tx_cap, stream.send.cap()?);
+
+            if let Some(group_cap) = self.streams.group_cap(stream_id) {
+                cap = cmp::min(cap as u64, group_cap) as usize;
+            }
+
             return Ok(cap);
         };
 
         Err(Error::InvalidStreamState(stream_id))
     }
 
+    /// Returns the offset up to which data sent on the stream has been
+    /// acknowledged by the peer.
+    ///
+    /// All the stream's data before the returned offset has been received by
+    /// the peer, so applications managing their own buffers can release it.
+    /// Data acknowledged beyond a gap (e.g. because an earlier packet was lost)
+    /// is only accounted for once the gap is filled by a retransmission.
+    ///
+    /// If the specified stream doesn't exist (including when it has already
+    /// been completed and closed), the [`InvalidStreamState`] error will be
+    /// returned. The [`StreamFinished`] event notifies applications when all
+    /// of the stream's data has been acknowledged.
+    ///
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    /// [`StreamFinished`]: enum.Event.html#variant.StreamFinished
+    #[inline]
+    pub fn stream_acked_offset(&self, stream_id: u64) -> Result<u64> {
+        match self.streams.get(stream_id) {
+            Some(stream) => Ok(stream.send.ack_off()),
+
+            None => Err(Error::InvalidStreamState(stream_id)),
+        }
+    }
+
     /// Returns the next stream that has data to read.
     ///
     /// Note that once returned by this method, a stream ID will not be returned
@@ -5468,6 +7944,21 @@ impl Connection {
         MIN_CLIENT_INITIAL_LEN
     }
 
+    /// Returns the maximum size of UDP payloads that the peer is allowed to
+    /// send, as advertised in the `max_udp_payload_size` transport parameter.
+    ///
+    /// Buffers used to receive datagrams for this connection need to be at
+    /// least this large. Datagrams exceeding it are dropped once the
+    /// handshake has completed.
+    ///
+    /// This can be configured with [`set_max_recv_udp_payload_size()`].
+    ///
+    /// [`set_max_recv_udp_payload_size()`]:
+    /// struct.Config.html#method.set_max_recv_udp_payload_size
+    pub fn max_recv_udp_payload_size(&self) -> usize {
+        self.local_transport_params.max_udp_payload_size as usize
+    }
+
     /// Schedule an ack-eliciting packet on the active path.
     ///
     /// QUIC packets might not contain ack-eliciting frames during normal
@@ -5487,6 +7978,31 @@ impl Connection {
         Ok(())
     }
 
+    /// Probes the round-trip time of the active path.
+    ///
+    /// This schedules an ack-eliciting packet, like [`send_ack_eliciting()`],
+    /// and reports the RTT sample taken when it is acknowledged with an
+    /// [`RttSample`] event, provided events are enabled. The sample is also
+    /// reflected in the RTT estimate returned by [`path_stats()`].
+    ///
+    /// Calling this method again before the probe is acknowledged replaces
+    /// the pending probe.
+    ///
+    /// [`send_ack_eliciting()`]: struct.Connection.html#method.send_ack_eliciting
+    /// [`RttSample`]: enum.Event.html#variant.RttSample
+    /// [`path_stats()`]: struct.Connection.html#method.path_stats
+    pub fn probe_rtt(&mut self) -> Result<()> {
+        if self.is_closed() || self.is_draining() {
+            return Ok(());
+        }
+
+        let path = self.paths.get_active_mut()?;
+        path.needs_ack_eliciting = true;
+        path.rtt_probe_pending = true;
+
+        Ok(())
+    }
+
     /// Schedule an ack-eliciting packet on the specified path.
     ///
     /// See [`send_ack_eliciting()`] for more detail. [`InvalidState`] is
@@ -5508,6 +8024,57 @@ impl Connection {
         Ok(())
     }
 
+    /// Initiates an update of the 1-RTT packet protection keys.
+    ///
+    /// Packets sent after this call are protected with the next generation of
+    /// keys, and the peer is expected to update its own keys in response.
+    ///
+    /// [`InvalidState`] is returned if the handshake is not confirmed yet, or
+    /// if the keys used before a previous update are still retained, as a new
+    /// update can only be initiated once the previous one has completed.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        if !self.handshake_confirmed || self.is_closed() || self.is_draining() {
+            return Err(Error::InvalidState);
+        }
+
+        let now = self.clock.now();
+        let pto = self.paths.get_active()?.recovery.pto();
+
+        let space = &mut self.pkt_num_spaces[packet::Epoch::Application];
+
+        if space.key_update.is_some() {
+            return Err(Error::InvalidState);
+        }
+
+        let (open, seal) = match (&space.crypto_open, &space.crypto_seal) {
+            (Some(open), Some(seal)) => (open, seal),
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        let open_next = open.derive_next_packet_key()?;
+        let seal_next = seal.derive_next_packet_key()?;
+
+        let open_prev = space.crypto_open.replace(open_next).unwrap();
+        space.crypto_seal.replace(seal_next);
+
+        space.key_update = Some(packet::KeyUpdate {
+            crypto_open: open_prev,
+            // Not known until the peer starts using the new keys.
+            pn_on_update: u64::MAX,
+            update_acked: true,
+            timer: now + (pto * 3),
+        });
+
+        self.key_phase = !self.key_phase;
+
+        trace!("{} local key update", self.trace_id);
+
+        Ok(())
+    }
+
     /// Reads the first received DATAGRAM.
     ///
     /// On success the DATAGRAM's data is returned along with its size.
@@ -5783,6 +8350,88 @@ impl Connection {
         }
     }
 
+    /// Reads the first received extension frame from the queue.
+    ///
+    /// On success the frame's type and body are returned. Only frames with a
+    /// type registered with [`register_extension_frame()`] are received.
+    ///
+    /// Frames received while [`set_extension_frame_recv_max_queue_len()`]
+    /// frames are already queued are discarded.
+    ///
+    /// [`Done`] is returned if there is no frame to read.
+    ///
+    /// [`register_extension_frame()`]:
+    /// struct.Config.html#method.register_extension_frame
+    /// [`set_extension_frame_recv_max_queue_len()`]:
+    /// struct.Config.html#method.set_extension_frame_recv_max_queue_len
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn extension_frame_recv(&mut self) -> Result<(u64, Vec<u8>)> {
+        self.extension_frames_recv.pop_front().ok_or(Error::Done)
+    }
+
+    /// Queues an extension frame with the given type and body for sending.
+    ///
+    /// The frame is sent in a 1-RTT packet, after any previously queued
+    /// extension frame. Whether it is sent again when lost is decided by the
+    /// [`retransmit()`] method of the handler registered for the frame type.
+    ///
+    /// [`InvalidFrame`] is returned if no handler is registered for the frame
+    /// type with [`register_extension_frame()`], or if the handler doesn't
+    /// parse `buf` as a complete frame body. [`BufferTooShort`] is returned if
+    /// the frame doesn't fit in a single packet, and [`InvalidState`] if 1-RTT
+    /// keys aren't available yet.
+    ///
+    /// [`retransmit()`]:
+    /// trait.ExtensionFrameHandler.html#method.retransmit
+    /// [`register_extension_frame()`]:
+    /// struct.Config.html#method.register_extension_frame
+    /// [`InvalidFrame`]: enum.Error.html#variant.InvalidFrame
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn extension_frame_send(
+        &mut self, frame_type: u64, buf: &[u8],
+    ) -> Result<()> {
+        let handler = self
+            .extension_frames
+            .get(&frame_type)
+            .ok_or(Error::InvalidFrame)?;
+
+        // Make sure the peer will be able to find where the frame ends.
+        if handler.frame_len(frame_type, buf)? != buf.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        let crypto_overhead = self.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_overhead()
+            .ok_or(Error::InvalidState)?;
+
+        let frame = frame::Frame::Extension {
+            frame_type,
+            data: buf.to_vec(),
+        };
+
+        // Short packet header (first byte and DCID), packet number and AEAD
+        // tag.
+        let overhead = 1 +
+            self.destination_id().len() +
+            packet::MAX_PKT_NUM_LEN +
+            crypto_overhead;
+
+        if frame.wire_len() + overhead > self.max_send_udp_payload_size() {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.extension_frames_send.push_back(frame);
+
+        Ok(())
+    }
+
+    /// Returns true if both endpoints support the ACK frequency extension.
+    fn ack_frequency_enabled(&self) -> bool {
+        self.local_transport_params.min_ack_delay.is_some() &&
+            self.peer_transport_params.min_ack_delay.is_some()
+    }
+
     fn dgram_enabled(&self) -> bool {
         self.local_transport_params
             .max_datagram_frame_size
@@ -5823,7 +8472,14 @@ impl Connection {
                 .as_ref()
                 .map(|key_update| key_update.timer);
 
-            let timers = [self.idle_timer, path_timer, key_update_timer];
+            let timers = [
+                self.idle_timer,
+                self.handshake_timer,
+                self.send_coalescing_timer,
+                path_timer,
+                key_update_timer,
+                self.ack_freq.ack_timer(),
+            ];
 
             timers.iter().filter_map(|&x| x).min()
         }
@@ -5837,7 +8493,7 @@ impl Connection {
     /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
     pub fn timeout(&self) -> Option<time::Duration> {
         self.timeout_instant().map(|timeout| {
-            let now = time::Instant::now();
+            let now = self.clock.now();
 
             if timeout <= now {
                 time::Duration::ZERO
@@ -5851,7 +8507,7 @@ impl Connection {
     ///
     /// If no timeout has occurred it does nothing.
     pub fn on_timeout(&mut self) {
-        let now = time::Instant::now();
+        let now = self.clock.now();
 
         if let Some(draining_timer) = self.draining_timer {
             if draining_timer <= now {
@@ -5876,6 +8532,16 @@ impl Connection {
             }
         }
 
+        if let Some(timer) = self.handshake_timer {
+            if timer <= now {
+                trace!("{} handshake timeout expired", self.trace_id);
+
+                self.mark_closed();
+                self.timed_out = true;
+                return;
+            }
+        }
+
         if let Some(timer) = self.pkt_num_spaces[packet::Epoch::Application]
             .key_update
             .as_ref()
@@ -5889,6 +8555,11 @@ impl Connection {
             }
         }
 
+        // Send the delayed ACK once the delay requested by the peer expired.
+        if self.ack_freq.on_timeout(now) {
+            self.pkt_num_spaces[packet::Epoch::Application].ack_elicited = true;
+        }
+
         let handshake_status = self.handshake_status();
 
         for (_, p) in self.paths.iter_mut() {
@@ -5896,6 +8567,10 @@ impl Connection {
                 if timer <= now {
                     trace!("{} loss detection timeout expired", self.trace_id);
 
+                    let pto_count = p.recovery.pto_count();
+                    let persistent_congestion_count =
+                        p.recovery.persistent_congestion_count();
+
                     let (lost_packets, lost_bytes) = p.on_loss_detection_timeout(
                         handshake_status,
                         now,
@@ -5906,14 +8581,48 @@ impl Connection {
                     self.lost_count += lost_packets;
                     self.lost_bytes += lost_bytes as u64;
 
+                    if self.events_enabled {
+                        if p.recovery.pto_count() > pto_count {
+                            queue_event(&mut self.events, Event::ProbeTimeout {
+                                local_addr: p.local_addr(),
+                                peer_addr: p.peer_addr(),
+                                count: p.recovery.pto_count(),
+                            });
+                        }
+
+                        if p.recovery.persistent_congestion_count() >
+                            persistent_congestion_count
+                        {
+                            queue_event(
+                                &mut self.events,
+                                Event::PersistentCongestion {
+                                    local_addr: p.local_addr(),
+                                    peer_addr: p.peer_addr(),
+                                },
+                            );
+                        }
+                    }
+
                     qlog_with_type!(QLOG_METRICS, self.qlog, q, {
                         if let Some(ev_data) = p.recovery.maybe_qlog() {
                             q.add_event_data_with_instant(ev_data, now).ok();
                         }
                     });
-                }
-            }
-        }
+
+                    #[cfg(feature = "btrace")]
+                    if let Some(t) = self.btrace.as_mut() {
+                        t.metrics(
+                            now,
+                            p.recovery.rtt(),
+                            p.recovery.cwnd(),
+                            p.recovery.bytes_in_flight(),
+                        );
+                    }
+
+                    report_congestion_transitions!(self, p, now);
+                }
+            }
+        }
 
         // Notify timeout events to the application.
         self.paths.notify_failed_validations();
@@ -5949,6 +8658,10 @@ impl Connection {
     /// The caller might also want to probe an existing path. In such case, it
     /// triggers a PATH_CHALLENGE frame, but it does not require spare CIDs.
     ///
+    /// New paths can't be probed if the peer sent the
+    /// `disable_active_migration` transport parameter, in which case this
+    /// call raises an [`InvalidState`].
+    ///
     /// A server always probes a new path it observes. Calling this method is
     /// hence not required to validate a new path. However, a server can still
     /// request an additional path validation of the proposed 4-tuple.
@@ -5998,7 +8711,9 @@ impl Connection {
     /// and `peer_addr`.
     ///
     /// Connection migration can only be initiated by the client. Calling this
-    /// method as a server returns [`InvalidState`].
+    /// method as a server, or migrating to a new path after the peer sent the
+    /// `disable_active_migration` transport parameter, returns
+    /// [`InvalidState`].
     ///
     /// To initiate voluntary migration, there should be enough Connection IDs
     /// at both sides. If this requirement is not satisfied, this call returns
@@ -6065,7 +8780,7 @@ impl Connection {
         };
 
         // Change the active path.
-        self.set_active_path(pid, time::Instant::now())?;
+        self.set_active_path(pid, self.clock.now())?;
 
         Ok(dcid_seq)
     }
@@ -6097,14 +8812,26 @@ impl Connection {
     /// advertise Source Connection IDs and calling this method returns an
     /// [`InvalidState`].
     ///
+    /// If a stateless reset key was configured with
+    /// [`set_stateless_reset_key()`], the reset token is derived from it and
+    /// the `reset_token` parameter is ignored.
+    ///
     /// Returns the sequence number associated to the provided Connection ID.
     ///
     /// [`scids_left()`]: struct.Connection.html#method.scids_left
     /// [`IdLimit`]: enum.Error.html#IdLimit
     /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`set_stateless_reset_key()`]:
+    /// struct.Config.html#method.set_stateless_reset_key
     pub fn new_scid(
         &mut self, scid: &ConnectionId, reset_token: u128, retire_if_needed: bool,
     ) -> Result<u64> {
+        let reset_token = match &self.stateless_reset_key {
+            Some(key) => stateless_reset::derive_token(key, scid),
+
+            None => reset_token,
+        };
+
         self.ids.new_scid(
             scid.to_vec().into(),
             Some(reset_token),
@@ -6114,6 +8841,33 @@ impl Connection {
         )
     }
 
+    /// Mints a new source Connection ID using the configured
+    /// [`ConnectionIdGenerator`] and provides it to the peer.
+    ///
+    /// This behaves like [`new_scid()`], except that the Connection ID is
+    /// generated by quiche instead of being provided by the caller.
+    ///
+    /// Returns the new Connection ID along with its sequence number, so that
+    /// the application can start routing packets carrying it to this
+    /// connection. [`InvalidState`] is returned if the generator produced a
+    /// Connection ID of an invalid length.
+    ///
+    /// [`ConnectionIdGenerator`]: trait.ConnectionIdGenerator.html
+    /// [`new_scid()`]: struct.Connection.html#method.new_scid
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn generate_scid(
+        &mut self, reset_token: u128, retire_if_needed: bool,
+    ) -> Result<(ConnectionId<'static>, u64)> {
+        let scid = cid::generate_cid(
+            self.cid_generator.as_ref(),
+            self.ids.next_scid_seq(),
+        )?;
+
+        let seq = self.new_scid(&scid, reset_token, retire_if_needed)?;
+
+        Ok((scid, seq))
+    }
+
     /// Returns the number of source Connection IDs that are active. This is
     /// only meaningful if the host uses non-zero length Source Connection IDs.
     pub fn active_scids(&self) -> usize {
@@ -6214,6 +8968,101 @@ impl Connection {
         self.paths.pop_event()
     }
 
+    /// Processes connection events.
+    ///
+    /// On success it returns an [`Event`], or `None` when there are no events
+    /// to report. Events are only queued when enabled with
+    /// [`enable_events()`]. Please refer to [`Event`] for the exhaustive event
+    /// list.
+    ///
+    /// Note that all events are edge-triggered, meaning that once reported they
+    /// will not be reported again by calling this method again, until the event
+    /// is re-armed.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// while let Some(ev) = conn.poll() {
+    ///     match ev {
+    ///         quiche::Event::StreamReadable(stream_id) => {
+    ///             // Read from the stream.
+    ///         },
+    ///
+    ///         quiche::Event::PeerClosed { error_code, .. } => {
+    ///             println!("Peer closed with error {}", error_code);
+    ///         },
+    ///
+    ///         _ => (),
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`Event`]: enum.Event.html
+    /// [`enable_events()`]: struct.Config.html#method.enable_events
+    pub fn poll(&mut self) -> Option<Event> {
+        if let Some(ev) = self.events.pop_front() {
+            return Some(ev);
+        }
+
+        if self.dropped_pkt_events.is_empty() {
+            return None;
+        }
+
+        Some(self.dropped_pkt_events.remove(0))
+    }
+
+    /// Queues a connection event, if events are enabled.
+    fn notify_event(&mut self, ev: Event) {
+        if self.events_enabled {
+            queue_event(&mut self.events, ev);
+        }
+    }
+
+    /// Adds the count of dropped packets in `ev` to the pending
+    /// [`UndecryptablePacket`] or [`DuplicatePacket`] event of the same type,
+    /// or queues `ev` if there's none yet.
+    ///
+    /// [`UndecryptablePacket`]: enum.Event.html#variant.UndecryptablePacket
+    /// [`DuplicatePacket`]: enum.Event.html#variant.DuplicatePacket
+    fn notify_dropped_packet(&mut self, ev: Event) {
+        if !self.events_enabled {
+            return;
+        }
+
+        // There is at most one pending event per kind and packet type.
+        for pending in self.dropped_pkt_events.iter_mut() {
+            match (pending, &ev) {
+                (
+                    Event::UndecryptablePacket { ty, count },
+                    Event::UndecryptablePacket {
+                        ty: new_ty,
+                        count: new_count,
+                    },
+                ) |
+                (
+                    Event::DuplicatePacket { ty, count },
+                    Event::DuplicatePacket {
+                        ty: new_ty,
+                        count: new_count,
+                    },
+                ) if ty == new_ty => {
+                    *count += new_count;
+                    return;
+                },
+
+                _ => (),
+            }
+        }
+
+        self.dropped_pkt_events.push(ev);
+    }
+
     /// Returns the number of source Connection IDs that are retired.
     pub fn retired_scids(&self) -> usize {
         self.ids.retired_source_cids()
@@ -6402,6 +9251,31 @@ impl Connection {
         self.handshake.peer_cert_chain()
     }
 
+    /// Returns the OCSP response stapled by the peer to its certificate, if
+    /// any.
+    ///
+    /// This is only available on the client, when OCSP stapling has been
+    /// requested with [`enable_ocsp_stapling()`].
+    ///
+    /// [`enable_ocsp_stapling()`]: struct.Config.html#method.enable_ocsp_stapling
+    #[inline]
+    pub fn peer_ocsp_response(&self) -> Option<&[u8]> {
+        self.handshake.ocsp_response()
+    }
+
+    /// Returns the list of signed certificate timestamps sent by the peer, if
+    /// any.
+    ///
+    /// This is only available on the client, when signed certificate
+    /// timestamps have been requested with
+    /// [`enable_signed_cert_timestamps()`].
+    ///
+    /// [`enable_signed_cert_timestamps()`]: struct.Config.html#method.enable_signed_cert_timestamps
+    #[inline]
+    pub fn peer_signed_cert_timestamp_list(&self) -> Option<&[u8]> {
+        self.handshake.signed_cert_timestamp_list()
+    }
+
     /// Returns the serialized cryptographic session for the connection.
     ///
     /// This can be used by a client to cache a connection's session, and resume
@@ -6467,6 +9341,20 @@ impl Connection {
         self.handshake_completed
     }
 
+    /// Returns true if the connection handshake is confirmed.
+    ///
+    /// Unlike [`is_established()`], this guarantees that the peer has
+    /// completed the handshake too, so Handshake keys have been discarded and
+    /// the peer has validated the path. Servers confirm the handshake as soon
+    /// as it completes, while clients need to receive a `HANDSHAKE_DONE` frame
+    /// or an acknowledgment for a 1-RTT packet.
+    ///
+    /// [`is_established()`]: struct.Connection.html#method.is_established
+    #[inline]
+    pub fn is_handshake_confirmed(&self) -> bool {
+        self.handshake_confirmed
+    }
+
     /// Returns true if the connection is resumed.
     #[inline]
     pub fn is_resumed(&self) -> bool {
@@ -6480,6 +9368,27 @@ impl Connection {
         self.handshake.is_in_early_data()
     }
 
+    /// Returns true if the connection is ready for application data.
+    ///
+    /// This is the case once the peer's transport parameters are known, which
+    /// happens during the handshake, or when early data is available.
+    /// Before that, stream data can only be queued, see
+    /// [`set_max_pre_handshake_data()`].
+    ///
+    /// [`set_max_pre_handshake_data()`]:
+    /// struct.Config.html#method.set_max_pre_handshake_data
+    #[inline]
+    pub fn is_app_data_ready(&self) -> bool {
+        self.parsed_peer_transport_params || self.is_in_early_data()
+    }
+
+    /// Returns the amount of stream data queued before the connection was
+    /// ready for application data, that wasn't written to the streams yet.
+    #[inline]
+    pub fn pre_handshake_data_len(&self) -> usize {
+        self.pre_handshake_data_len
+    }
+
     /// Returns whether there is stream or DATAGRAM data available to read.
     #[inline]
     pub fn is_readable(&self) -> bool {
@@ -6514,6 +9423,9 @@ impl Connection {
     /// In contrast, once `is_draining()` returns `true`, calling [`send()`]
     /// is not required because no new outgoing packets will be generated.
     ///
+    /// Packets received while draining are discarded without being processed.
+    /// The draining state expires after three times the current PTO.
+    ///
     /// [`recv()`]: struct.Connection.html#method.recv
     /// [`send()`]: struct.Connection.html#method.send
     /// [`timeout()`]: struct.Connection.html#method.timeout
@@ -6532,12 +9444,29 @@ impl Connection {
         self.closed
     }
 
-    /// Returns true if the connection was closed due to the idle timeout.
+    /// Returns true if the connection was closed due to the idle timeout, or
+    /// due to the handshake timeout.
     #[inline]
     pub fn is_timed_out(&self) -> bool {
         self.timed_out
     }
 
+    /// Returns the local and peer addresses of the paths that can currently
+    /// be used to send non-probing packets.
+    ///
+    /// Without multipath (see [`set_multipath()`]), this only includes the
+    /// active path. With multipath enabled, it includes every validated path,
+    /// and [`send_on_path()`] can be used to send data on each of them.
+    ///
+    /// [`set_multipath()`]: struct.Config.html#method.set_multipath
+    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
+    pub fn paths(&self) -> impl Iterator<Item = (SocketAddr, SocketAddr)> + '_ {
+        self.paths
+            .iter()
+            .filter(move |(_, p)| p.active() || (self.multipath && p.usable()))
+            .map(|(_, p)| (p.local_addr(), p.peer_addr()))
+    }
+
     /// Returns the error received from the peer, if any.
     ///
     /// Note that a `Some` return value does not necessarily imply
@@ -6564,6 +9493,51 @@ impl Connection {
         self.local_error.as_ref()
     }
 
+    /// Returns the error stream operations fail with once the connection is
+    /// closing, draining or closed.
+    ///
+    /// The error code is taken from the peer's error if any, then from the
+    /// local one. Connections closed silently (e.g. due to the idle timeout)
+    /// report `NO_ERROR`.
+    fn closed_stream_error(&self) -> Option<Error> {
+        if !self.is_closed() && !self.is_draining() && self.local_error.is_none()
+        {
+            return None;
+        }
+
+        let error_code = self
+            .peer_error
+            .as_ref()
+            .or(self.local_error.as_ref())
+            .map_or(WireErrorCode::NoError as u64, |e| e.error_code);
+
+        Some(Error::ConnectionClosed(error_code))
+    }
+
+    /// Writes the state needed to hand the connection over to another
+    /// instance into the provided buffer.
+    ///
+    /// The state includes the connection IDs, the 1-RTT packet protection
+    /// keys, packet numbers, flow control state and stream limits, in a
+    /// versioned binary format that can be passed to [`import()`]. As it
+    /// contains key material, it needs to be protected accordingly while in
+    /// transit.
+    ///
+    /// Stream data and packets in flight are not carried over, so the
+    /// connection needs to be drained first: [`InvalidState`] is returned
+    /// unless the handshake is confirmed, all streams are complete and all
+    /// packets sent were acknowledged. On success the number of bytes
+    /// written is returned, and the connection should then be dropped
+    /// without sending anything else.
+    ///
+    /// [`import()`]: fn.import.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    #[cfg(feature = "checkpoint")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "checkpoint")))]
+    pub fn export_state(&self, out: &mut [u8]) -> Result<usize> {
+        checkpoint::export(self, out)
+    }
+
     /// Collects and returns statistics about the connection.
     #[inline]
     pub fn stats(&self) -> Stats {
@@ -6577,6 +9551,16 @@ impl Connection {
             acked_bytes: self.acked_bytes,
             lost_bytes: self.lost_bytes,
             stream_retrans_bytes: self.stream_retrans_bytes,
+            bytes_in_flight: self
+                .paths
+                .iter()
+                .map(|(_, p)| p.recovery.bytes_in_flight())
+                .sum(),
+            app_limited_periods: self
+                .paths
+                .iter()
+                .map(|(_, p)| p.recovery.app_limited_periods())
+                .sum(),
             paths_count: self.paths.len(),
             reset_stream_count_local: self.reset_stream_local_count,
             stopped_stream_count_local: self.stopped_stream_local_count,
@@ -6586,6 +9570,26 @@ impl Connection {
         }
     }
 
+    /// Returns the parameters negotiated during the handshake, gathered in a
+    /// single snapshot.
+    ///
+    /// Returns `None` if the handshake hasn't completed yet.
+    pub fn handshake_info(&self) -> Option<HandshakeInfo> {
+        if !self.handshake_completed || !self.parsed_peer_transport_params {
+            return None;
+        }
+
+        Some(HandshakeInfo {
+            version: self.version,
+            application_proto: self.alpn.clone(),
+            server_name: self.handshake.server_name().map(str::to_string),
+            cipher: self.handshake.cipher(),
+            peer_transport_params: self.peer_transport_params.clone(),
+            resumed: self.handshake.is_resumed(),
+            early_data_accepted: self.handshake.early_data_accepted(),
+        })
+    }
+
     /// Returns reference to peer's transport parameters. Returns `None` if we
     /// have not yet processed the peer's transport parameters.
     pub fn peer_transport_params(&self) -> Option<&TransportParams> {
@@ -6608,7 +9612,8 @@ impl Connection {
     }
 
     fn encode_transport_params(&mut self) -> Result<()> {
-        let mut raw_params = [0; 128];
+        let mut raw_params =
+            vec![0; self.local_transport_params.max_encoded_len()];
 
         let raw_params = TransportParams::encode(
             &self.local_transport_params,
@@ -6667,6 +9672,15 @@ impl Connection {
             }
         }
 
+        // retry_source_connection_id must not be sent by the server when no
+        // Retry was performed.
+        if !self.is_server &&
+            self.rscid.is_none() &&
+            peer_params.retry_source_connection_id.is_some()
+        {
+            return Err(Error::InvalidTransportParam);
+        }
+
         self.process_peer_transport_params(peer_params)?;
 
         self.parsed_peer_transport_params = true;
@@ -6692,6 +9706,8 @@ impl Connection {
 
         self.recovery_config.max_ack_delay = max_ack_delay;
 
+        let now = self.clock.now();
+
         let active_path = self.paths.get_active_mut()?;
 
         active_path.recovery.update_max_ack_delay(max_ack_delay);
@@ -6702,10 +9718,12 @@ impl Connection {
                     .pmtud
                     .get_probe_size()
                     .min(peer_params.max_udp_payload_size as usize),
+                now,
             );
         } else {
             active_path.recovery.update_max_datagram_size(
                 peer_params.max_udp_payload_size as usize,
+                now,
             );
         }
 
@@ -6767,8 +9785,14 @@ impl Connection {
             Err(e) => return Err(e),
         };
 
+        let was_completed = self.handshake_completed;
+
         self.handshake_completed = self.handshake.is_completed();
 
+        if self.handshake_completed {
+            self.handshake_timer = None;
+        }
+
         self.alpn = self.handshake.alpn_protocol().to_vec();
 
         let raw_params = self.handshake.quic_transport_params();
@@ -6802,6 +9826,14 @@ impl Connection {
                    self.handshake.sigalg(),
                    self.handshake.is_resumed(),
                    self.peer_transport_params);
+
+            if !was_completed && self.events_enabled {
+                if let Some(info) = self.handshake_info() {
+                    let ev = Event::HandshakeCompleted(Box::new(info));
+
+                    self.notify_event(ev);
+                }
+            }
         }
 
         Ok(())
@@ -6878,6 +9910,7 @@ impl Connection {
                 self.almost_full ||
                 self.blocked_limit.is_some() ||
                 self.dgram_send_queue.has_pending() ||
+                !self.extension_frames_send.is_empty() ||
                 self.local_error
                     .as_ref()
                     .map_or(false, |conn_err| conn_err.is_app) ||
@@ -6905,6 +9938,92 @@ impl Connection {
         Err(Error::Done)
     }
 
+    /// Returns true if sending a packet on the given path should be delayed
+    /// to allow more data to be queued.
+    ///
+    /// This also arms the send coalescing timer when the delay starts, and
+    /// clears it once it expires or the delay is no longer needed.
+    fn should_delay_send(&mut self, send_pid: usize, now: time::Instant) -> bool {
+        let delay = match self.send_coalescing_delay {
+            Some(v) if !self.send_immediate => v,
+
+            _ => return false,
+        };
+
+        let epoch = packet::Epoch::Application;
+
+        let max_datagram_size = match self.paths.get(send_pid) {
+            Ok(p) => p.recovery.max_datagram_size(),
+
+            Err(_) => return false,
+        };
+
+        let pending =
+            self.streams.flushable_len() + self.dgram_send_queue.byte_size();
+
+        // Only delay packets carrying nothing but a small amount of
+        // application data, as anything else (acknowledgements, probes,
+        // retransmissions, control frames, connection close) is
+        // time-sensitive.
+        let can_delay = self.local_error.is_none() &&
+            self.write_pkt_type(send_pid) == Ok(packet::Type::Short) &&
+            !self.pkt_num_spaces[epoch].ready() &&
+            !self.paths.iter().any(|(_, p)| {
+                p.recovery.has_lost_frames(epoch) ||
+                    p.recovery.loss_probes(epoch) > 0
+            }) &&
+            !self.has_pending_control_frames(send_pid) &&
+            pending > 0 &&
+            pending < max_datagram_size;
+
+        if !can_delay {
+            self.send_coalescing_timer = None;
+            return false;
+        }
+
+        match self.send_coalescing_timer {
+            Some(timer) if timer <= now => {
+                self.send_coalescing_timer = None;
+                false
+            },
+
+            Some(_) => true,
+
+            None => {
+                trace!("{} delaying send by {:?}", self.trace_id, delay);
+
+                self.send_coalescing_timer = Some(now + delay);
+                true
+            },
+        }
+    }
+
+    /// Returns true if there are frames other than stream or datagram data
+    /// waiting to be sent in the Application epoch, e.g. flow control updates.
+    fn has_pending_control_frames(&self, send_pid: usize) -> bool {
+        let send_path = match self.paths.get(send_pid) {
+            Ok(v) => v,
+
+            Err(_) => return false,
+        };
+
+        self.should_send_handshake_done() ||
+            self.almost_full ||
+            self.blocked_limit.is_some() ||
+            !self.extension_frames_send.is_empty() ||
+            self.streams.should_update_max_streams_bidi() ||
+            self.streams.should_update_max_streams_uni() ||
+            self.streams.has_almost_full() ||
+            self.streams.has_blocked() ||
+            self.streams.has_reset() ||
+            self.streams.has_stopped() ||
+            self.ids.has_new_scids() ||
+            self.ids.has_retire_dcids() ||
+            send_path.pmtud.get_probe_status() ||
+            send_path.needs_ack_eliciting ||
+            self.paths.iter().any(|(_, p)| p.probing_required())
+    }
+
     /// Returns the mutable stream with the given ID if it exists, or creates
     /// a new one otherwise.
     fn get_or_create_stream(
@@ -6919,6 +10038,83 @@ impl Connection {
         )
     }
 
+    /// Invokes the ClientHello handler and the anti-replay store once the
+    /// ClientHello has been received.
+    ///
+    /// Returns `false` if the handshake must not progress, either because the
+    /// ClientHello is still incomplete or because the connection was rejected.
+    fn admit_client_hello(&mut self) -> bool {
+        if self.client_hello_handler.is_none() && self.anti_replay.is_none() {
+            return true;
+        }
+
+        let hello = match ClientHello::from_bytes(&self.client_hello_buf) {
+            Ok(v) => Some(v),
+
+            Err(Error::BufferTooShort) => return false,
+
+            // Let the TLS stack reject a badly formatted ClientHello.
+            Err(_) => None,
+        };
+
+        let handler = self.client_hello_handler.take();
+        let anti_replay = self.anti_replay.take();
+
+        self.client_hello_buf = Vec::new();
+
+        let hello = match hello {
+            Some(v) => v,
+
+            None => return true,
+        };
+
+        let mut action = match handler {
+            Some(handler) => handler.on_client_hello(&hello),
+
+            None => ClientHelloAction::Accept,
+        };
+
+        // Only the first ticket offered by the client can be used to send
+        // early data.
+        if let (ClientHelloAction::Accept, Some(anti_replay), Some(ticket)) =
+            (action, anti_replay, hello.psk_identities().first())
+        {
+            if hello.early_data() && !anti_replay.insert(ticket) {
+                trace!("{} replayed ticket used for early data", self.trace_id);
+
+                action = ClientHelloAction::RejectEarlyData;
+            }
+        }
+
+        match action {
+            ClientHelloAction::Accept => true,
+
+            ClientHelloAction::RejectEarlyData => {
+                trace!("{} early data rejected", self.trace_id);
+
+                self.handshake.set_early_data_enabled(false);
+
+                true
+            },
+
+            ClientHelloAction::Reject(error_code) => {
+                trace!(
+                    "{} ClientHello rejected with error {:#x}",
+                    self.trace_id,
+                    error_code
+                );
+
+                self.local_error = Some(ConnectionError {
+                    is_app: false,
+                    error_code,
+                    reason: vec![],
+                });
+
+                false
+            },
+        }
+    }
+
     /// Processes an incoming frame.
     fn process_frame(
         &mut self, frame: frame::Frame, hdr: &packet::Header,
@@ -6951,11 +10147,24 @@ impl Connection {
 
                 let is_app_limited = self.delivery_rate_check_if_app_limited();
 
+                let mut rtt_samples: SmallVec<[time::Duration; 1]> =
+                    SmallVec::new();
+
                 for (_, p) in self.paths.iter_mut() {
                     if is_app_limited {
                         p.recovery.delivery_rate_update_app_limited(true);
                     }
 
+                    let persistent_congestion_count =
+                        p.recovery.persistent_congestion_count();
+
+                    let rtt_probe_sent = match p.rtt_probe_pkt_num {
+                        Some(pn) if epoch == packet::Epoch::Application =>
+                            p.recovery.unacked_time_sent(epoch, pn),
+
+                        _ => None,
+                    };
+
                     let (lost_packets, lost_bytes, acked_bytes) =
                         p.recovery.on_ack_received(
                             &ranges,
@@ -6969,6 +10178,74 @@ impl Connection {
                     self.lost_count += lost_packets;
                     self.lost_bytes += lost_bytes as u64;
                     self.acked_bytes += acked_bytes as u64;
+
+                    if p.recovery.persistent_congestion_count() >
+                        persistent_congestion_count &&
+                        self.events_enabled
+                    {
+                        queue_event(
+                            &mut self.events,
+                            Event::PersistentCongestion {
+                                local_addr: p.local_addr(),
+                                peer_addr: p.peer_addr(),
+                            },
+                        );
+                    }
+
+                    #[cfg(feature = "instrumentation")]
+                    if acked_bytes > 0 || lost_packets > 0 {
+                        if let Some(hook) = self.ack_hook.as_mut() {
+                            hook(&AckEvent {
+                                largest_acked: ranges.last().unwrap_or(0),
+                                acked_bytes,
+                                lost_packets,
+                                lost_bytes,
+                                bytes_in_flight: p.recovery.bytes_in_flight(),
+                                cwnd: p.recovery.cwnd(),
+                                latest_rtt: p.recovery.latest_rtt(),
+                                rtt: p.recovery.rtt(),
+                                min_rtt: p.recovery.min_rtt(),
+                                from: p.local_addr(),
+                                to: p.peer_addr(),
+                                time: now,
+                            });
+                        }
+                    }
+
+                    report_congestion_transitions!(self, p, now);
+
+                    if let Some(pn) = p.rtt_probe_pkt_num {
+                        if let Some(time_sent) = rtt_probe_sent {
+                            if ranges.iter().any(|r| r.contains(&pn)) {
+                                rtt_samples.push(
+                                    now.saturating_duration_since(time_sent),
+                                );
+                            }
+                        }
+
+                        // Forget the probe once it's acknowledged or lost.
+                        if epoch == packet::Epoch::Application &&
+                            p.recovery.unacked_time_sent(epoch, pn).is_none()
+                        {
+                            p.rtt_probe_pkt_num = None;
+                        }
+                    }
+                }
+
+                // Only packets that were actually sent count towards the
+                // packet number encoding.
+                let pkt_space = &mut self.pkt_num_spaces[epoch];
+                if let Some(largest) = ranges.last() {
+                    if largest < pkt_space.next_pkt_num {
+                        pkt_space.largest_acked_pkt_num = cmp::max(
+                            pkt_space.largest_acked_pkt_num,
+                            Some(largest),
+                        );
+                    }
+                }
+
+                for rtt in rtt_samples {
+                    self.notify_event(Event::RttSample(rtt));
                 }
             },
 
@@ -7007,6 +10284,8 @@ impl Connection {
                 let was_readable = stream.is_readable();
                 let priority_key = Arc::clone(&stream.priority_key);
 
+                let consumed_off = stream.recv.consumed_off();
+
                 let max_off_delta =
                     stream.recv.reset(error_code, final_size)? as u64;
 
@@ -7014,12 +10293,26 @@ impl Connection {
                     return Err(Error::FlowControl);
                 }
 
+                // All data up to the final size counts against the connection
+                // flow control limit, but any of it that wasn't read by the
+                // application yet never will be, so consider it consumed.
+                // Otherwise the window would shrink every time a stream is
+                // reset, eventually blocking the peer.
+                let discarded = stream.recv.consumed_off() - consumed_off;
+
                 if !was_readable && stream.is_readable() {
                     self.streams.insert_readable(&priority_key);
+                    self.notify_event(Event::StreamReadable(stream_id));
                 }
 
                 self.rx_data += max_off_delta;
 
+                self.flow_control.add_consumed(discarded);
+
+                if self.should_update_max_data() {
+                    self.almost_full = true;
+                }
+
                 self.reset_stream_remote_count =
                     self.reset_stream_remote_count.saturating_add(1);
             },
@@ -7074,6 +10367,7 @@ impl Connection {
 
                     if !was_writable {
                         self.streams.insert_writable(&priority_key);
+                        self.notify_event(Event::StreamWritable(stream_id));
                     }
 
                     self.stopped_stream_remote_count =
@@ -7084,10 +10378,17 @@ impl Connection {
             },
 
             frame::Frame::Crypto { data } => {
-                if data.max_off() >= MAX_CRYPTO_STREAM_OFFSET {
+                if data.max_off() >= self.max_crypto_stream_offset {
                     return Err(Error::CryptoBufferExceeded);
                 }
 
+                // Clients don't send any post-handshake TLS messages, as
+                // QUIC forbids both KeyUpdate messages and post-handshake
+                // client authentication.
+                if self.is_server && epoch == packet::Epoch::Application {
+                    return Err(Error::InvalidPacket);
+                }
+
                 // Push the data to the stream so it can be re-ordered.
                 self.pkt_num_spaces[epoch].crypto_stream.recv.write(data)?;
 
@@ -7101,19 +10402,34 @@ impl Connection {
 
                 while let Ok((read, _)) = stream.recv.emit(&mut crypto_buf) {
                     let recv_buf = &crypto_buf[..read];
+
+                    if epoch == packet::Epoch::Initial &&
+                        (self.client_hello_handler.is_some() ||
+                            self.anti_replay.is_some())
+                    {
+                        self.client_hello_buf.extend_from_slice(recv_buf);
+                    }
+
                     self.handshake.provide_data(level, recv_buf)?;
                 }
 
+                if !self.admit_client_hello() {
+                    return Ok(());
+                }
+
                 self.do_handshake(now)?;
             },
 
             frame::Frame::CryptoHeader { .. } => unreachable!(),
 
             // TODO: implement stateless retry
-            frame::Frame::NewToken { .. } =>
+            frame::Frame::NewToken { token } => {
                 if self.is_server {
                     return Err(Error::InvalidPacket);
-                },
+                }
+
+                self.notify_event(Event::NewToken(token));
+            },
 
             frame::Frame::Stream { stream_id, data } => {
                 // Peer can't send on our unidirectional streams.
@@ -7143,6 +10459,10 @@ impl Connection {
                     Err(e) => return Err(e),
                 };
 
+                // Data that violates the stream's final size is rejected as
+                // such, before it is accounted for flow control.
+                stream.recv.check_final_size(data.max_off(), data.fin())?;
+
                 // Check for the connection-level flow control limit.
                 let max_off_delta =
                     data.max_off().saturating_sub(stream.recv.max_off());
@@ -7160,6 +10480,7 @@ impl Connection {
 
                 if !was_readable && stream.is_readable() {
                     self.streams.insert_readable(&priority_key);
+                    self.notify_event(Event::StreamReadable(stream_id));
                 }
 
                 self.rx_data += max_off_delta;
@@ -7210,6 +10531,7 @@ impl Connection {
                 };
 
                 let was_flushable = stream.is_flushable();
+                let was_writable = stream.is_writable();
 
                 stream.send.update_max_data(max);
 
@@ -7227,6 +10549,10 @@ impl Connection {
                 if writable {
                     self.streams.insert_writable(&priority_key);
                 }
+
+                if writable && !was_writable {
+                    self.notify_event(Event::StreamWritable(stream_id));
+                }
             },
 
             frame::Frame::MaxStreamsBidi { max } => {
@@ -7350,25 +10676,35 @@ impl Connection {
             frame::Frame::ConnectionClose {
                 error_code, reason, ..
             } => {
+                self.notify_event(Event::PeerClosed {
+                    is_app: false,
+                    error_code,
+                    reason: reason.clone(),
+                });
+
                 self.peer_error = Some(ConnectionError {
                     is_app: false,
                     error_code,
                     reason,
                 });
 
-                let path = self.paths.get_active()?;
-                self.draining_timer = Some(now + (path.recovery.pto() * 3));
+                self.enter_draining(now)?;
             },
 
             frame::Frame::ApplicationClose { error_code, reason } => {
+                self.notify_event(Event::PeerClosed {
+                    is_app: true,
+                    error_code,
+                    reason: reason.clone(),
+                });
+
                 self.peer_error = Some(ConnectionError {
                     is_app: true,
                     error_code,
                     reason,
                 });
 
-                let path = self.paths.get_active()?;
-                self.draining_timer = Some(now + (path.recovery.pto() * 3));
+                self.enter_draining(now)?;
             },
 
             frame::Frame::HandshakeDone => {
@@ -7402,8 +10738,79 @@ impl Connection {
             },
 
             frame::Frame::DatagramHeader { .. } => unreachable!(),
+
+            frame::Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                // Close the connection if the extension is not enabled, or if
+                // the requested delay is below the advertised minimum.
+                match self.local_transport_params.min_ack_delay {
+                    Some(min_ack_delay)
+                        if request_max_ack_delay >= min_ack_delay =>
+                        (),
+
+                    _ => return Err(Error::InvalidState),
+                }
+
+                self.ack_freq.on_frame_received(
+                    seq_num,
+                    ack_eliciting_threshold,
+                    request_max_ack_delay,
+                    reordering_threshold,
+                );
+            },
+
+            frame::Frame::ImmediateAck => {
+                if self.local_transport_params.min_ack_delay.is_none() {
+                    return Err(Error::InvalidState);
+                }
+
+                self.pkt_num_spaces[epoch].ack_elicited = true;
+            },
+
+            frame::Frame::Extension { frame_type, data } => {
+                // If recv queue is full, discard the frame.
+                if self.extension_frames_recv.len() <
+                    self.extension_frame_recv_max_queue_len
+                {
+                    self.extension_frames_recv.push_back((frame_type, data));
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Enters the draining state after the peer closed the connection.
+    ///
+    /// The connection stays in this state for three times the current PTO,
+    /// during which no packets are sent and received packets are discarded.
+    /// State that is only needed to send further packets is released right
+    /// away.
+    fn enter_draining(&mut self, now: time::Instant) -> Result<()> {
+        let pto = self.paths.get_active()?.recovery.pto();
+        self.draining_timer = Some(now + (pto * 3));
+
+        let handshake_status = self.handshake_status();
+        for (_, p) in self.paths.iter_mut() {
+            for &epoch in packet::Epoch::epochs(
+                packet::Epoch::Initial..=packet::Epoch::Application,
+            ) {
+                p.recovery.on_pkt_num_space_discarded(
+                    epoch,
+                    handshake_status,
+                    now,
+                );
+            }
         }
 
+        self.dgram_send_queue.purge(|_| true);
+
+        trace!("{} entered draining state", self.trace_id);
+
         Ok(())
     }
 
@@ -7599,8 +11006,26 @@ impl Connection {
         // Ignore this step if are using zero-length SCID.
         if ids.zero_length_scid() {
             in_scid_pid = None;
+        } else if in_scid_pid.is_none() &&
+            self.local_transport_params.disable_active_migration
+        {
+            // A peer that switches to a new CID on a new 4-tuple is actively
+            // migrating, which we asked it not to do. Unlike NAT rebinding,
+            // where the peer keeps using the same CID, such packets are
+            // dropped without validating the new path.
+            trace!(
+                "{} dropping packet from ({},{}), active migration is disabled",
+                self.trace_id,
+                info.to,
+                info.from
+            );
+
+            return Err(Error::Done);
         }
 
+        // Path whose RTT estimate can be carried over to the new path.
+        let mut rebound_pid = None;
+
         if let Some(in_scid_pid) = in_scid_pid {
             // This CID has been used by another path. If we have the
             // room to do so, create a new `Path` structure holding this
@@ -7609,6 +11034,13 @@ impl Connection {
             let old_local_addr = old_path.local_addr();
             let old_peer_addr = old_path.peer_addr();
 
+            // A change limited to the peer's port is most likely a NAT
+            // rebinding, for which the RTT estimator doesn't need to be
+            // reset (RFC 9000, Section 9.4).
+            if old_local_addr == info.to && old_peer_addr.ip() == info.from.ip() {
+                rebound_pid = Some(in_scid_pid);
+            }
+
             trace!(
                 "{} reused CID seq {} of ({},{}) (path {}) on ({},{})",
                 self.trace_id,
@@ -7637,10 +11069,17 @@ impl Connection {
             self.path_challenge_recv_max_queue_len,
             MIN_CLIENT_INITIAL_LEN,
             false,
+            self.clock.now(),
         );
 
         path.max_send_bytes = buf_len * self.max_amplification_factor;
         path.active_scid_seq = Some(in_scid_seq);
+        path.init_spin_bit(self.spin_bit_mode, self.rng.as_ref());
+
+        if let Some(rebound_pid) = rebound_pid {
+            let old_path = self.paths.get(rebound_pid)?;
+            path.recovery.inherit_rtt_stats(&old_path.recovery);
+        }
 
         // Automatically probes the new path.
         path.request_validation();
@@ -7674,6 +11113,25 @@ impl Connection {
             if let Some(pid) = probing.next() {
                 return Ok(pid);
             }
+
+            // With multipath, use the lowest-RTT path that can send more data.
+            if self.multipath {
+                let scheduled = self
+                    .paths
+                    .iter()
+                    .filter(|(_, p)| {
+                        from.is_none() || Some(p.local_addr()) == from
+                    })
+                    .filter(|(_, p)| to.is_none() || Some(p.peer_addr()) == to)
+                    .filter(|(_, p)| p.usable())
+                    .filter(|(_, p)| p.recovery.cwnd_available() > 0)
+                    .min_by_key(|(_, p)| p.recovery.rtt())
+                    .map(|(pid, _)| pid);
+
+                if let Some(pid) = scheduled {
+                    return Ok(pid);
+                }
+            }
         }
 
         if let Some((pid, p)) = self.paths.get_active_with_pid() {
@@ -7742,6 +11200,11 @@ impl Connection {
             return Err(Error::InvalidState);
         }
 
+        // The peer asked us not to migrate the connection.
+        if self.peer_transport_params.disable_active_migration {
+            return Err(Error::InvalidState);
+        }
+
         // If we use zero-length SCID and go over our local active CID limit,
         // the `insert_path()` call will raise an error.
         if !self.ids.zero_length_scid() && self.ids.available_scids() == 0 {
@@ -7766,8 +11229,10 @@ impl Connection {
             self.path_challenge_recv_max_queue_len,
             MIN_CLIENT_INITIAL_LEN,
             false,
+            self.clock.now(),
         );
         path.active_dcid_seq = Some(dcid_seq);
+        path.init_spin_bit(self.spin_bit_mode, self.rng.as_ref());
 
         let pid = self
             .paths
@@ -7881,6 +11346,13 @@ impl AsMut<boring::ssl::SslRef> for Connection {
 /// This must only be used for errors preceding packet authentication. Failures
 /// happening after a packet has been authenticated should still cause the
 /// connection to be aborted.
+/// Queues a connection event, unless the queue is full.
+fn queue_event(events: &mut VecDeque<Event>, ev: Event) {
+    if events.len() < MAX_EVENTS_QUEUE_LEN {
+        events.push_back(ev);
+    }
+}
+
 fn drop_pkt_on_err(
     e: Error, recv_count: usize, is_server: bool, trace_id: &str,
 ) -> Error {
@@ -7946,6 +11418,15 @@ pub struct Stats {
     /// The number of stream bytes retransmitted.
     pub stream_retrans_bytes: u64,
 
+    /// The number of bytes in flight on all paths, i.e. sent in packets that
+    /// count towards congestion control and are neither acknowledged nor
+    /// declared lost yet.
+    pub bytes_in_flight: usize,
+
+    /// The number of times the sender became application-limited on any
+    /// path, i.e. it didn't have enough data to fill the congestion window.
+    pub app_limited_periods: u64,
+
     /// The number of known paths for the connection.
     pub paths_count: usize,
 
@@ -7985,7 +11466,7 @@ impl std::fmt::Debug for Stats {
 }
 
 /// QUIC Transport Parameters
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TransportParams {
     /// Value of Destination CID field from first Initial packet sent by client
     pub original_destination_connection_id: Option<ConnectionId<'static>>,
@@ -8023,6 +11504,13 @@ pub struct TransportParams {
     pub retry_source_connection_id: Option<ConnectionId<'static>>,
     /// DATAGRAM frame extension parameter, if any.
     pub max_datagram_frame_size: Option<u64>,
+    /// ACK frequency extension parameter, if any.
+    pub min_ack_delay: Option<u64>,
+    /// Whether packets with the QUIC bit greased are accepted.
+    pub grease_quic_bit: bool,
+    /// Parameters that are not natively supported, as `(id, value)` pairs in
+    /// the order they were received.
+    pub custom_params: Vec<(u64, Vec<u8>)>,
     // pub preferred_address: ...,
 }
 
@@ -8046,11 +11534,44 @@ impl Default for TransportParams {
             initial_source_connection_id: None,
             retry_source_connection_id: None,
             max_datagram_frame_size: None,
+            min_ack_delay: None,
+            grease_quic_bit: false,
+            custom_params: Vec::new(),
         }
     }
 }
 
 impl TransportParams {
+    /// Returns the value of the custom parameter with the given ID, if it was
+    /// received.
+    ///
+    /// Custom parameters are those not natively supported, whose values are
+    /// exposed without being interpreted. See [`set_custom_transport_param()`]
+    /// for sending them.
+    ///
+    /// [`set_custom_transport_param()`]:
+    /// struct.Config.html#method.set_custom_transport_param
+    pub fn custom_param(&self, id: u64) -> Option<&[u8]> {
+        self.custom_params
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Returns true if the parameter with the given ID is natively supported.
+    fn is_supported(id: u64) -> bool {
+        matches!(id, 0x0000..=0x0010 | 0x0020 | 0x2ab2 | 0xff04_de1b)
+    }
+
+    /// Returns an upper bound of the length of the encoded parameters.
+    fn max_encoded_len(&self) -> usize {
+        // Natively supported parameters always fit in 256 bytes, and each
+        // custom one has an ID and length of at most 8 bytes each.
+        self.custom_params
+            .iter()
+            .fold(256, |len, (_, v)| len + 16 + v.len())
+    }
+
     fn decode(buf: &[u8], is_server: bool) -> Result<TransportParams> {
         let mut params = octets::Octets::with_slice(buf);
         let mut seen_params = HashSet::new();
@@ -8073,6 +11594,10 @@ impl TransportParams {
                         return Err(Error::InvalidTransportParam);
                     }
 
+                    if val.cap() > MAX_CONN_ID_LEN {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
                     tp.original_destination_connection_id =
                         Some(val.to_vec().into());
                 },
@@ -8181,6 +11706,10 @@ impl TransportParams {
                 },
 
                 0x000f => {
+                    if val.cap() > MAX_CONN_ID_LEN {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
                     tp.initial_source_connection_id = Some(val.to_vec().into());
                 },
 
@@ -8189,6 +11718,10 @@ impl TransportParams {
                         return Err(Error::InvalidTransportParam);
                     }
 
+                    if val.cap() > MAX_CONN_ID_LEN {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
                     tp.retry_source_connection_id = Some(val.to_vec().into());
                 },
 
@@ -8196,8 +11729,29 @@ impl TransportParams {
                     tp.max_datagram_frame_size = Some(val.get_varint()?);
                 },
 
-                // Ignore unknown parameters.
-                _ => (),
+                0xff04_de1b => {
+                    tp.min_ack_delay = Some(val.get_varint()?);
+                },
+
+                0x2ab2 => {
+                    if val.cap() != 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.grease_quic_bit = true;
+                },
+
+                // Keep unknown parameters for the application to interpret.
+                _ => {
+                    tp.custom_params.push((id, val.to_vec()));
+                },
+            }
+        }
+
+        // The minimum ACK delay can't be larger than the maximum one.
+        if let Some(min_ack_delay) = tp.min_ack_delay {
+            if min_ack_delay > tp.max_ack_delay * 1000 {
+                return Err(Error::InvalidTransportParam);
             }
         }
 
@@ -8216,6 +11770,27 @@ impl TransportParams {
     fn encode<'a>(
         tp: &TransportParams, is_server: bool, out: &'a mut [u8],
     ) -> Result<&'a mut [u8]> {
+        // Integer parameters need to fit in a variable-length integer.
+        let values = [
+            tp.max_idle_timeout,
+            tp.max_udp_payload_size,
+            tp.initial_max_data,
+            tp.initial_max_stream_data_bidi_local,
+            tp.initial_max_stream_data_bidi_remote,
+            tp.initial_max_stream_data_uni,
+            tp.initial_max_streams_bidi,
+            tp.initial_max_streams_uni,
+            tp.ack_delay_exponent,
+            tp.max_ack_delay,
+            tp.active_conn_id_limit,
+            tp.max_datagram_frame_size.unwrap_or(0),
+            tp.min_ack_delay.unwrap_or(0),
+        ];
+
+        if values.iter().any(|v| *v > MAX_VARINT) {
+            return Err(Error::InvalidTransportParam);
+        }
+
         let mut b = octets::OctetsMut::with_slice(out);
 
         if is_server {
@@ -8358,6 +11933,24 @@ impl TransportParams {
             b.put_varint(max_datagram_frame_size)?;
         }
 
+        if let Some(min_ack_delay) = tp.min_ack_delay {
+            TransportParams::encode_param(
+                &mut b,
+                0xff04_de1b,
+                octets::varint_len(min_ack_delay),
+            )?;
+            b.put_varint(min_ack_delay)?;
+        }
+
+        if tp.grease_quic_bit {
+            TransportParams::encode_param(&mut b, 0x2ab2, 0)?;
+        }
+
+        for (id, value) in &tp.custom_params {
+            TransportParams::encode_param(&mut b, *id, value.len())?;
+            b.put_bytes(value)?;
+        }
+
         let out_len = b.off();
 
         Ok(&mut out[..out_len])
@@ -8376,6 +11969,14 @@ impl TransportParams {
             self.stateless_reset_token.map(|s| s.to_be_bytes()).as_ref(),
         );
 
+        let initial_source_connection_id = qlog::HexSlice::maybe_string(
+            self.initial_source_connection_id.as_ref(),
+        );
+
+        let retry_source_connection_id = qlog::HexSlice::maybe_string(
+            self.retry_source_connection_id.as_ref(),
+        );
+
         EventData::TransportParametersSet(
             qlog::events::quic::TransportParametersSet {
                 owner: Some(owner),
@@ -8384,8 +11985,8 @@ impl TransportParams {
                 tls_cipher: Some(format!("{cipher:?}")),
                 aead_tag_length: None,
                 original_destination_connection_id,
-                initial_source_connection_id: None,
-                retry_source_connection_id: None,
+                initial_source_connection_id,
+                retry_source_connection_id,
                 stateless_reset_token,
                 disable_active_migration: Some(self.disable_active_migration),
                 max_idle_timeout: Some(self.max_idle_timeout),
@@ -8742,155 +12343,532 @@ pub mod testing {
         Ok(off)
     }
 
-    pub fn process_flight(
-        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
-    ) -> Result<()> {
-        for (mut pkt, si) in flight {
-            let info = RecvInfo {
-                to: si.to,
-                from: si.from,
-            };
+    /// A [`Clock`] that only moves forward when explicitly advanced.
+    pub struct ManualClock(std::sync::Mutex<time::Instant>);
 
-            conn.recv(&mut pkt, info)?;
+    impl ManualClock {
+        pub fn new(now: time::Instant) -> ManualClock {
+            ManualClock(std::sync::Mutex::new(now))
         }
 
-        Ok(())
+        pub fn advance(&self, d: time::Duration) {
+            *self.0.lock().unwrap() += d;
+        }
     }
 
-    pub fn emit_flight_with_max_buffer(
-        conn: &mut Connection, out_size: usize, from: Option<SocketAddr>,
-        to: Option<SocketAddr>,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        let mut flight = Vec::new();
+    impl Clock for ManualClock {
+        fn now(&self) -> time::Instant {
+            *self.0.lock().unwrap()
+        }
+    }
 
-        loop {
-            let mut out = vec![0u8; out_size];
+    /// The pattern used by a [`Link`] to decide which packets to drop.
+    #[derive(Clone, Debug, Default)]
+    pub enum Loss {
+        /// No packets are dropped.
+        #[default]
+        None,
 
-            let info = match conn.send_on_path(&mut out, from, to) {
-                Ok((written, info)) => {
-                    out.truncate(written);
-                    info
-                },
+        /// Each packet is dropped independently with the given probability.
+        Random(f64),
 
-                Err(Error::Done) => break,
+        /// Every n-th packet is dropped.
+        Periodic(u64),
 
-                Err(e) => return Err(e),
-            };
+        /// With the given probability a packet starts a burst of losses, in
+        /// which it and the following packets, up to the given burst length,
+        /// are all dropped.
+        Burst { probability: f64, len: u64 },
 
-            flight.push((out, info));
-        }
+        /// The packets with the given indices (starting from zero) are dropped.
+        Indices(Vec<u64>),
+    }
 
-        if flight.is_empty() {
-            return Err(Error::Done);
-        }
+    /// The properties of one direction of an emulated network path.
+    #[derive(Clone, Debug, Default)]
+    pub struct LinkConfig {
+        /// The one-way propagation delay.
+        pub delay: time::Duration,
 
-        Ok(flight)
-    }
+        /// The maximum random delay added on top of `delay` for each packet.
+        /// Packets can be reordered when this is non-zero.
+        pub jitter: time::Duration,
 
-    pub fn emit_flight_on_path(
-        conn: &mut Connection, from: Option<SocketAddr>, to: Option<SocketAddr>,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        emit_flight_with_max_buffer(conn, 65535, from, to)
+        /// The bottleneck bandwidth in bytes per second, if any.
+        pub bandwidth: Option<u64>,
+
+        /// The loss pattern applied to packets entering the link.
+        pub loss: Loss,
+
+        /// The seed of the pseudo-random generator used for jitter and loss.
+        pub seed: u64,
     }
 
-    pub fn emit_flight(
-        conn: &mut Connection,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        emit_flight_on_path(conn, None, None)
+    /// Deterministic xorshift64* generator, so scenarios can be replayed.
+    struct LinkRng(u64);
+
+    impl LinkRng {
+        fn new(seed: u64) -> LinkRng {
+            // Run the seed through splitmix64 so that small or zero seeds still
+            // yield a usable state.
+            let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+
+            LinkRng(z.max(1))
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn next_f64(&mut self) -> f64 {
+            (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+        }
     }
 
-    pub fn encode_pkt(
-        conn: &mut Connection, pkt_type: packet::Type, frames: &[frame::Frame],
-        buf: &mut [u8],
-    ) -> Result<usize> {
-        let mut b = octets::OctetsMut::with_slice(buf);
+    /// One direction of an emulated network path.
+    ///
+    /// Packets are delayed, serialized at the configured bandwidth and dropped
+    /// according to the configured [`Loss`] pattern. All randomness is derived
+    /// from the configured seed, so the same inputs always produce the same
+    /// outputs.
+    pub struct Link {
+        config: LinkConfig,
 
-        let epoch = pkt_type.to_epoch()?;
+        rng: LinkRng,
 
-        let space = &mut conn.pkt_num_spaces[epoch];
+        queue: VecDeque<(time::Instant, Vec<u8>, SendInfo)>,
 
-        let pn = space.next_pkt_num;
-        let pn_len = 4;
+        busy_until: Option<time::Instant>,
 
-        let send_path = conn.paths.get_active()?;
-        let active_dcid_seq = send_path
-            .active_dcid_seq
-            .as_ref()
-            .ok_or(Error::InvalidState)?;
-        let active_scid_seq = send_path
-            .active_scid_seq
-            .as_ref()
-            .ok_or(Error::InvalidState)?;
+        burst_left: u64,
 
-        let hdr = Header {
-            ty: pkt_type,
-            version: conn.version,
-            dcid: ConnectionId::from_ref(
-                conn.ids.get_dcid(*active_dcid_seq)?.cid.as_ref(),
-            ),
-            scid: ConnectionId::from_ref(
-                conn.ids.get_scid(*active_scid_seq)?.cid.as_ref(),
-            ),
-            pkt_num: pn,
-            pkt_num_len: pn_len,
-            token: conn.token.clone(),
-            versions: None,
-            key_phase: conn.key_phase,
-        };
+        /// The number of packets that entered the link.
+        pub sent: u64,
 
-        hdr.to_bytes(&mut b)?;
+        /// The number of packets dropped by the link.
+        pub dropped: u64,
 
-        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+        /// The number of packets that left the link.
+        pub delivered: u64,
+    }
 
-        if pkt_type != packet::Type::Short {
-            let len = pn_len + payload_len + space.crypto_overhead().unwrap();
-            b.put_varint(len as u64)?;
+    impl Link {
+        pub fn new(config: LinkConfig) -> Link {
+            Link {
+                rng: LinkRng::new(config.seed),
+                config,
+                queue: VecDeque::new(),
+                busy_until: None,
+                burst_left: 0,
+                sent: 0,
+                dropped: 0,
+                delivered: 0,
+            }
         }
 
-        // Always encode packet number in 4 bytes, to allow encoding packets
-        // with empty payloads.
-        b.put_u32(pn as u32)?;
+        fn should_drop(&mut self, idx: u64) -> bool {
+            match self.config.loss {
+                Loss::None => false,
 
-        let payload_offset = b.off();
+                Loss::Random(p) => self.rng.next_f64() < p,
 
-        for frame in frames {
-            frame.to_bytes(&mut b)?;
+                Loss::Periodic(n) => n > 0 && (idx + 1) % n == 0,
+
+                Loss::Burst { probability, len } => {
+                    if self.burst_left == 0 && self.rng.next_f64() < probability {
+                        self.burst_left = len;
+                    }
+
+                    if self.burst_left > 0 {
+                        self.burst_left -= 1;
+                        return true;
+                    }
+
+                    false
+                },
+
+                Loss::Indices(ref v) => v.contains(&idx),
+            }
         }
 
-        let aead = match space.crypto_seal {
-            Some(ref v) => v,
-            None => return Err(Error::InvalidState),
-        };
+        /// Queues a packet sent at time `now`.
+        pub fn send(&mut self, pkt: Vec<u8>, info: SendInfo, now: time::Instant) {
+            let idx = self.sent;
+            self.sent += 1;
 
-        let written = packet::encrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            payload_offset,
-            None,
-            aead,
-        )?;
+            // Packets are not sent before the time requested by the sender,
+            // e.g. due to pacing.
+            let mut departure = cmp::max(now, info.at);
 
-        space.next_pkt_num += 1;
+            if let Some(bandwidth) = self.config.bandwidth {
+                if let Some(busy_until) = self.busy_until {
+                    departure = cmp::max(departure, busy_until);
+                }
 
-        Ok(written)
-    }
+                let nanos = pkt.len() as u128 * 1_000_000_000 /
+                    cmp::max(bandwidth, 1) as u128;
 
-    pub fn decode_pkt(
-        conn: &mut Connection, buf: &mut [u8],
-    ) -> Result<Vec<frame::Frame>> {
-        let mut b = octets::OctetsMut::with_slice(buf);
+                departure += time::Duration::from_nanos(nanos as u64);
 
-        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
+                self.busy_until = Some(departure);
+            }
 
-        let epoch = hdr.ty.to_epoch()?;
+            if self.should_drop(idx) {
+                self.dropped += 1;
+                return;
+            }
 
-        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
+            let mut arrival = departure + self.config.delay;
 
-        let payload_len = b.cap();
+            if !self.config.jitter.is_zero() {
+                let jitter = self.config.jitter.as_nanos() as u64;
+                let extra = self.rng.next_u64() % (jitter + 1);
 
-        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
+                arrival += time::Duration::from_nanos(extra);
+            }
+
+            // Keep the queue sorted by arrival time, preserving the order in
+            // which packets were sent for equal arrival times.
+            let pos = self.queue.partition_point(|(t, ..)| *t <= arrival);
+            self.queue.insert(pos, (arrival, pkt, info));
+        }
+
+        /// Returns the arrival time of the next queued packet, if any.
+        pub fn next_arrival(&self) -> Option<time::Instant> {
+            self.queue.front().map(|(t, ..)| *t)
+        }
+
+        /// Removes the next packet that has arrived by `now`, if any.
+        pub fn recv(
+            &mut self, now: time::Instant,
+        ) -> Option<(Vec<u8>, SendInfo)> {
+            match self.queue.front() {
+                Some((t, ..)) if *t <= now => (),
+
+                _ => return None,
+            }
+
+            self.delivered += 1;
+
+            self.queue.pop_front().map(|(_, pkt, info)| (pkt, info))
+        }
+    }
+
+    /// A [`Pipe`] whose client and server exchange packets over emulated
+    /// links, with time driven by a [`ManualClock`].
+    ///
+    /// Scenarios run as fast as the CPU allows and are fully reproducible.
+    pub struct EmulatedPipe {
+        pub pipe: Pipe,
+
+        pub clock: Arc<ManualClock>,
+
+        /// The client to server direction.
+        pub uplink: Link,
+
+        /// The server to client direction.
+        pub downlink: Link,
+    }
+
+    impl EmulatedPipe {
+        /// Creates a pipe using `config` for both endpoints, with the given
+        /// link properties for each direction.
+        pub fn with_config(
+            config: &mut Config, uplink: LinkConfig, downlink: LinkConfig,
+        ) -> Result<EmulatedPipe> {
+            let clock = Arc::new(ManualClock::new(time::Instant::now()));
+
+            config.set_clock(clock.clone());
+
+            Ok(EmulatedPipe {
+                pipe: Pipe::with_config(config)?,
+                clock,
+                uplink: Link::new(uplink),
+                downlink: Link::new(downlink),
+            })
+        }
+
+        /// Returns the current emulated time.
+        pub fn now(&self) -> time::Instant {
+            self.clock.now()
+        }
+
+        fn flush(
+            conn: &mut Connection, link: &mut Link, now: time::Instant,
+        ) -> Result<()> {
+            match emit_flight(conn) {
+                Ok(flight) =>
+                    for (pkt, info) in flight {
+                        link.send(pkt, info, now);
+                    },
+
+                Err(Error::Done) => (),
+
+                Err(e) => return Err(e),
+            }
+
+            Ok(())
+        }
+
+        fn deliver(
+            conn: &mut Connection, link: &mut Link, now: time::Instant,
+        ) -> Result<()> {
+            while let Some((mut pkt, info)) = link.recv(now) {
+                let info = RecvInfo {
+                    to: info.to,
+                    from: info.from,
+                };
+
+                match conn.recv(&mut pkt, info) {
+                    Ok(_) | Err(Error::Done) => (),
+
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Sends any pending packets, then advances time to the next event
+        /// (packet arrival or timer expiration) and processes it.
+        ///
+        /// Returns `false` if there was nothing left to do.
+        pub fn step(&mut self) -> Result<bool> {
+            let now = self.now();
+
+            Self::flush(&mut self.pipe.client, &mut self.uplink, now)?;
+            Self::flush(&mut self.pipe.server, &mut self.downlink, now)?;
+
+            let next = [
+                self.uplink.next_arrival(),
+                self.downlink.next_arrival(),
+                self.pipe.client.timeout_instant(),
+                self.pipe.server.timeout_instant(),
+            ]
+            .iter()
+            .flatten()
+            .min()
+            .copied();
+
+            let next = match next {
+                Some(v) => v,
+
+                None => return Ok(false),
+            };
+
+            if next > now {
+                self.clock.advance(next - now);
+            }
+
+            let now = self.now();
+
+            Self::deliver(&mut self.pipe.server, &mut self.uplink, now)?;
+            Self::deliver(&mut self.pipe.client, &mut self.downlink, now)?;
+
+            let timeout = self.pipe.client.timeout_instant();
+            if matches!(timeout, Some(t) if t <= now) {
+                self.pipe.client.on_timeout();
+            }
+
+            let timeout = self.pipe.server.timeout_instant();
+            if matches!(timeout, Some(t) if t <= now) {
+                self.pipe.server.on_timeout();
+            }
+
+            Ok(true)
+        }
+
+        /// Steps the emulation until `cond` returns `true`, or until `limit`
+        /// of emulated time has passed.
+        ///
+        /// Returns whether `cond` was met.
+        pub fn run_until<F>(
+            &mut self, limit: time::Duration, mut cond: F,
+        ) -> Result<bool>
+        where
+            F: FnMut(&mut Pipe) -> bool,
+        {
+            let deadline = self.now() + limit;
+
+            while !cond(&mut self.pipe) {
+                if self.now() >= deadline || !self.step()? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+
+        /// Runs the emulation until both endpoints complete the handshake.
+        pub fn handshake(&mut self) -> Result<()> {
+            let done = self.run_until(time::Duration::from_secs(60), |p| {
+                p.client.is_established() && p.server.is_established()
+            })?;
+
+            if !done {
+                return Err(Error::Done);
+            }
+
+            Ok(())
+        }
+    }
+
+    pub fn process_flight(
+        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
+    ) -> Result<()> {
+        for (mut pkt, si) in flight {
+            let info = RecvInfo {
+                to: si.to,
+                from: si.from,
+            };
+
+            conn.recv(&mut pkt, info)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn emit_flight_with_max_buffer(
+        conn: &mut Connection, out_size: usize, from: Option<SocketAddr>,
+        to: Option<SocketAddr>,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        let mut flight = Vec::new();
+
+        loop {
+            let mut out = vec![0u8; out_size];
+
+            let info = match conn.send_on_path(&mut out, from, to) {
+                Ok((written, info)) => {
+                    out.truncate(written);
+                    info
+                },
+
+                Err(Error::Done) => break,
+
+                Err(e) => return Err(e),
+            };
+
+            flight.push((out, info));
+        }
+
+        if flight.is_empty() {
+            return Err(Error::Done);
+        }
+
+        Ok(flight)
+    }
+
+    pub fn emit_flight_on_path(
+        conn: &mut Connection, from: Option<SocketAddr>, to: Option<SocketAddr>,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        emit_flight_with_max_buffer(conn, 65535, from, to)
+    }
+
+    pub fn emit_flight(
+        conn: &mut Connection,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        emit_flight_on_path(conn, None, None)
+    }
+
+    pub fn encode_pkt(
+        conn: &mut Connection, pkt_type: packet::Type, frames: &[frame::Frame],
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut b = octets::OctetsMut::with_slice(buf);
+
+        let epoch = pkt_type.to_epoch()?;
+
+        let space = &mut conn.pkt_num_spaces[epoch];
+
+        let pn = space.next_pkt_num;
+        let pn_len = 4;
+
+        let send_path = conn.paths.get_active()?;
+        let active_dcid_seq = send_path
+            .active_dcid_seq
+            .as_ref()
+            .ok_or(Error::InvalidState)?;
+        let active_scid_seq = send_path
+            .active_scid_seq
+            .as_ref()
+            .ok_or(Error::InvalidState)?;
+
+        let hdr = Header {
+            ty: pkt_type,
+            version: conn.version,
+            dcid: ConnectionId::from_ref(
+                conn.ids.get_dcid(*active_dcid_seq)?.cid.as_ref(),
+            ),
+            scid: ConnectionId::from_ref(
+                conn.ids.get_scid(*active_scid_seq)?.cid.as_ref(),
+            ),
+            pkt_num: pn,
+            pkt_num_len: pn_len,
+            token: conn.token.clone(),
+            versions: None,
+            key_phase: conn.key_phase,
+            spin_bit: false,
+        };
+
+        hdr.to_bytes(&mut b)?;
+
+        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+
+        if pkt_type != packet::Type::Short {
+            let len = pn_len + payload_len + space.crypto_overhead().unwrap();
+            b.put_varint(len as u64)?;
+        }
+
+        // Always encode packet number in 4 bytes, to allow encoding packets
+        // with empty payloads.
+        b.put_u32(pn as u32)?;
+
+        let payload_offset = b.off();
+
+        for frame in frames {
+            frame.to_bytes(&mut b)?;
+        }
+
+        let aead = match space.crypto_seal {
+            Some(ref v) => v,
+            None => return Err(Error::InvalidState),
+        };
+
+        let written = packet::encrypt_pkt(
+            &mut b,
+            pn,
+            pn_len,
+            payload_len,
+            payload_offset,
+            None,
+            aead,
+        )?;
+
+        space.next_pkt_num += 1;
+
+        Ok(written)
+    }
+
+    pub fn decode_pkt(
+        conn: &mut Connection, buf: &mut [u8],
+    ) -> Result<Vec<frame::Frame>> {
+        let mut b = octets::OctetsMut::with_slice(buf);
+
+        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
+
+        let epoch = hdr.ty.to_epoch()?;
+
+        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
+
+        let payload_len = b.cap();
+
+        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
 
         let pn = packet::decode_pkt_num(
             conn.pkt_num_spaces[epoch].largest_rx_pkt_num,
@@ -8912,6 +12890,21 @@ pub mod testing {
         Ok(frames)
     }
 
+    pub fn decode_pkt_num_len(conn: &Connection, buf: &[u8]) -> usize {
+        let mut buf = buf.to_vec();
+        let mut b = octets::OctetsMut::with_slice(&mut buf);
+
+        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
+
+        let epoch = hdr.ty.to_epoch().unwrap();
+
+        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
+
+        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
+
+        hdr.pkt_num_len
+    }
+
     pub fn create_cid_and_reset_token(
         cid_len: usize,
     ) -> (ConnectionId<'static>, u128) {
@@ -8952,12 +12945,15 @@ mod tests {
             initial_source_connection_id: Some(b"woot woot".to_vec().into()),
             retry_source_connection_id: Some(b"retry".to_vec().into()),
             max_datagram_frame_size: Some(32),
+            min_ack_delay: Some(1000),
+            grease_quic_bit: true,
+            custom_params: Vec::new(),
         };
 
         let mut raw_params = [42; 256];
         let raw_params =
             TransportParams::encode(&tp, true, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 94);
+        assert_eq!(raw_params.len(), 108);
 
         let new_tp = TransportParams::decode(raw_params, false).unwrap();
 
@@ -8982,12 +12978,15 @@ mod tests {
             initial_source_connection_id: Some(b"woot woot".to_vec().into()),
             retry_source_connection_id: None,
             max_datagram_frame_size: Some(32),
+            min_ack_delay: Some(1000),
+            grease_quic_bit: true,
+            custom_params: Vec::new(),
         };
 
         let mut raw_params = [42; 256];
         let raw_params =
             TransportParams::encode(&tp, false, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 69);
+        assert_eq!(raw_params.len(), 83);
 
         let new_tp = TransportParams::decode(raw_params, true).unwrap();
 
@@ -8995,24 +12994,122 @@ mod tests {
     }
 
     #[test]
-    fn transport_params_forbid_duplicates() {
-        // Given an encoded param.
-        let initial_source_connection_id = b"id";
-        let initial_source_connection_id_raw = [
-            15,
-            initial_source_connection_id.len() as u8,
-            initial_source_connection_id[0],
-            initial_source_connection_id[1],
-        ];
-
-        // No error when decoding the param.
-        let tp = TransportParams::decode(
-            initial_source_connection_id_raw.as_slice(),
-            true,
-        )
-        .unwrap();
+    fn transport_params_custom() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
 
-        assert_eq!(
+        // Natively supported parameters can't be overridden.
+        for id in [0x0004, 0x0020, 0x2ab2, 0xff04_de1b, 1 << 62] {
+            assert_eq!(
+                config.set_custom_transport_param(id, b"foo"),
+                Err(Error::InvalidTransportParam)
+            );
+        }
+
+        assert_eq!(config.set_custom_transport_param(0x1b, b"foo"), Ok(()));
+        assert_eq!(config.set_custom_transport_param(0xf00d, b""), Ok(()));
+        assert_eq!(config.set_custom_transport_param(0x1b, b"bar"), Ok(()));
+
+        let tp = &config.local_transport_params;
+        assert_eq!(tp.custom_params, vec![
+            (0x1b, b"bar".to_vec()),
+            (0xf00d, b"".to_vec())
+        ]);
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(tp, false, &mut raw_params).unwrap();
+
+        let new_tp = TransportParams::decode(raw_params, true).unwrap();
+        assert_eq!(new_tp, *tp);
+
+        assert_eq!(new_tp.custom_param(0x1b), Some(&b"bar"[..]));
+        assert_eq!(new_tp.custom_param(0xf00d), Some(&b""[..]));
+        assert_eq!(new_tp.custom_param(0x1c), None);
+    }
+
+    #[test]
+    fn transport_params_custom_handshake() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        // A value too long to fit in a fixed size buffer alongside the other
+        // parameters.
+        let value = vec![0xba; 1000];
+
+        assert_eq!(config.set_custom_transport_param(0x1b, &value), Ok(()));
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The client receives the server's parameter, but not the other way
+        // around.
+        let client_tp = pipe.server.peer_transport_params().unwrap();
+        assert_eq!(client_tp.custom_param(0x1b), None);
+
+        let server_tp = pipe.client.peer_transport_params().unwrap();
+        assert_eq!(server_tp.custom_param(0x1b), Some(&value[..]));
+    }
+
+    #[test]
+    fn transport_params_min_ack_delay() {
+        let tp = TransportParams {
+            max_ack_delay: 25,
+            min_ack_delay: Some(25_000),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        let new_tp = TransportParams::decode(raw_params, true).unwrap();
+        assert_eq!(new_tp.min_ack_delay, Some(25_000));
+
+        // The minimum ACK delay can't exceed the maximum one.
+        let tp = TransportParams {
+            max_ack_delay: 25,
+            min_ack_delay: Some(25_001),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        assert_eq!(
+            TransportParams::decode(raw_params, true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn transport_params_forbid_duplicates() {
+        // Given an encoded param.
+        let initial_source_connection_id = b"id";
+        let initial_source_connection_id_raw = [
+            15,
+            initial_source_connection_id.len() as u8,
+            initial_source_connection_id[0],
+            initial_source_connection_id[1],
+        ];
+
+        // No error when decoding the param.
+        let tp = TransportParams::decode(
+            initial_source_connection_id_raw.as_slice(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
             tp.initial_source_connection_id,
             Some(initial_source_connection_id.to_vec().into())
         );
@@ -9029,6 +13126,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn transport_params_out_of_range() {
+        // Values that don't fit in a varint can't be encoded.
+        let tp = TransportParams {
+            initial_max_data: 1 << 62,
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        assert_eq!(
+            TransportParams::encode(&tp, false, &mut raw_params),
+            Err(Error::InvalidTransportParam)
+        );
+
+        // Connection IDs can't be longer than the maximum length.
+        let tp = TransportParams {
+            initial_source_connection_id: Some(vec![0xba; 21].into()),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        assert_eq!(
+            TransportParams::decode(raw_params, true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
     #[test]
     fn unknown_version() {
         let mut config = Config::new(0xbabababa).unwrap();
@@ -9308,6 +13435,75 @@ mod tests {
         assert!(pipe.server.handshake_confirmed);
     }
 
+    #[test]
+    fn is_handshake_confirmed() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        assert!(!pipe.client.is_handshake_confirmed());
+        assert!(!pipe.server.is_handshake_confirmed());
+
+        // Client sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        // Client completes the handshake, but it isn't confirmed yet.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert!(pipe.client.is_established());
+        assert!(!pipe.client.is_handshake_confirmed());
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server confirms the handshake as soon as it completes, and sends
+        // HANDSHAKE_DONE.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        assert!(pipe.server.is_established());
+        assert!(pipe.server.is_handshake_confirmed());
+        assert!(!pipe.client.is_handshake_confirmed());
+
+        // Client confirms the handshake once it receives HANDSHAKE_DONE.
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        assert!(pipe.client.is_handshake_confirmed());
+    }
+
+    #[test]
+    fn handshake_done_sent_by_client() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Only servers can send HANDSHAKE_DONE.
+        let frames = [frame::Frame::HandshakeDone];
+
+        let pkt_type = packet::Type::Short;
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidPacket)
+        );
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::ProtocolViolation as u64,
+                frame_type: 0x1e,
+                reason: Vec::new(),
+            })
+        );
+    }
+
     #[test]
     fn handshake_resumption() {
         #[cfg(not(feature = "openssl"))]
@@ -9402,10 +13598,9 @@ mod tests {
         assert_eq!(pipe.server.sent_count, 1);
     }
 
-    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn handshake_0rtt() {
-        let mut buf = [0; 65535];
+    fn manual_clock() {
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
 
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
@@ -9421,54 +13616,66 @@ mod tests {
         config.set_initial_max_stream_data_bidi_local(15);
         config.set_initial_max_stream_data_bidi_remote(15);
         config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
+        config.set_max_idle_timeout(180_000);
         config.verify_peer(false);
+        config.set_clock(clock.clone());
 
-        // Perform initial handshake.
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+        // Lose the packet carrying the stream data.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(flight.len(), 1);
+        assert_eq!(flight[0].1.at, clock.now());
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        let pto = pipe.client.timeout().unwrap();
+        assert!(pto > time::Duration::ZERO);
 
-        // Client sends 0-RTT packet.
-        let pkt_type = packet::Type::ZeroRTT;
+        // Time doesn't move unless the clock is advanced.
+        assert_eq!(pipe.client.timeout(), Some(pto));
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, true),
-        }];
+        clock.advance(pto - time::Duration::from_millis(1));
+        assert_eq!(pipe.client.timeout(), Some(time::Duration::from_millis(1)));
 
+        pipe.client.on_timeout();
         assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(1200)
+            pipe.client
+                .paths
+                .get_active()
+                .unwrap()
+                .recovery
+                .loss_probes(packet::Epoch::Application),
+            0
         );
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+        clock.advance(time::Duration::from_millis(1));
+        assert_eq!(pipe.client.timeout(), Some(time::Duration::ZERO));
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        pipe.client.on_timeout();
+        assert!(
+            pipe.client
+                .paths
+                .get_active()
+                .unwrap()
+                .recovery
+                .loss_probes(packet::Epoch::Application) >
+                0
+        );
+
+        // The probe retransmits the lost data.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert!(flight.iter().all(|(_, si)| si.at == clock.now()));
+        testing::process_flight(&mut pipe.server, flight).unwrap();
 
         let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-        assert_eq!(&b[..5], b"aaaaa");
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
     }
 
-    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
-    #[test]
-    fn handshake_0rtt_reordered() {
-        let mut buf = [0; 65535];
-
+    fn emulated_config() -> Config {
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -9479,1793 +13686,1981 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_data(10_000_000);
+        config.set_initial_max_stream_data_bidi_local(10_000_000);
+        config.set_initial_max_stream_data_bidi_remote(10_000_000);
         config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
+        config.set_max_idle_timeout(180_000);
         config.verify_peer(false);
+        config
+    }
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    fn emulated_transfer(
+        pipe: &mut testing::EmulatedPipe, len: usize,
+    ) -> Result<bool> {
+        let data = vec![42; len];
+        let mut sent = 0;
+        let mut recvd = 0;
+        let mut buf = vec![0; 65535];
+
+        pipe.run_until(time::Duration::from_secs(60), |p| {
+            if sent < len {
+                if let Ok(n) = p.client.stream_send(0, &data[sent..], true) {
+                    sent += n;
+                }
+            }
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+            while let Ok((n, _)) = p.server.stream_recv(0, &mut buf) {
+                recvd += n;
+            }
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+            recvd == len
+        })
+    }
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        let mut initial = buf[..len].to_vec();
+    #[test]
+    fn emulated_link_is_deterministic() {
+        let config = testing::LinkConfig {
+            delay: time::Duration::from_millis(10),
+            jitter: time::Duration::from_millis(5),
+            bandwidth: Some(125_000),
+            loss: testing::Loss::Random(0.3),
+            seed: 1234,
+        };
 
-        // Client sends 0-RTT packet.
-        let pkt_type = packet::Type::ZeroRTT;
+        let info = SendInfo {
+            from: testing::Pipe::client_addr(),
+            to: testing::Pipe::server_addr(),
+            at: time::Instant::now(),
+        };
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, true),
-        }];
+        let run = |config: testing::LinkConfig| {
+            let mut link = testing::Link::new(config);
 
-        let len =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
-        let mut zrtt = buf[..len].to_vec();
+            for i in 0..100 {
+                link.send(vec![i; 1200], info, info.at);
+            }
 
-        // 0-RTT packet is received before the Initial one.
-        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+            let mut out = Vec::new();
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 1);
-        assert_eq!(pipe.server.undecryptable_pkts[0].0.len(), zrtt.len());
+            while let Some(t) = link.next_arrival() {
+                let (pkt, _) = link.recv(t).unwrap();
+                out.push((t, pkt[0]));
+            }
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+            (link.dropped, out)
+        };
 
-        // Initial packet is also received.
-        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
+        let (dropped, out) = run(config.clone());
+        assert!(dropped > 0 && dropped < 100);
+        assert_eq!(out.len() as u64 + dropped, 100);
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        // Arrivals are ordered, and include the serialization delay of each
+        // packet at 1 Mbps.
+        assert!(out.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert!(out[0].0 >= info.at + time::Duration::from_millis(19));
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-        assert_eq!(&b[..5], b"aaaaa");
+        // The same seed replays the same scenario.
+        assert_eq!(run(config.clone()), (dropped, out.clone()));
+
+        // A different seed doesn't.
+        let other = testing::LinkConfig {
+            seed: 4321,
+            ..config
+        };
+        assert_ne!(run(other), (dropped, out));
     }
 
-    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn handshake_0rtt_truncated() {
-        let mut buf = [0; 65535];
+    fn emulated_link_loss_patterns() {
+        let info = SendInfo {
+            from: testing::Pipe::client_addr(),
+            to: testing::Pipe::server_addr(),
+            at: time::Instant::now(),
+        };
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+        let delivered = |loss| {
+            let mut link = testing::Link::new(testing::LinkConfig {
+                loss,
+                ..Default::default()
+            });
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+            for i in 0..10 {
+                link.send(vec![i], info, info.at);
+            }
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+            let mut out = Vec::new();
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+            while let Some((pkt, _)) = link.recv(info.at) {
+                out.push(pkt[0]);
+            }
 
-        // Client sends initial flight.
-        pipe.client.send(&mut buf).unwrap();
+            out
+        };
 
-        // Client sends 0-RTT packet.
-        let pkt_type = packet::Type::ZeroRTT;
+        assert_eq!(delivered(testing::Loss::None), (0..10).collect::<Vec<_>>());
+        assert_eq!(delivered(testing::Loss::Periodic(3)), [0, 1, 3, 4, 6, 7, 9]);
+        assert_eq!(delivered(testing::Loss::Indices(vec![0, 5, 9])), [
+            1, 2, 3, 4, 6, 7, 8
+        ]);
+        assert_eq!(
+            delivered(testing::Loss::Burst {
+                probability: 1.0,
+                len: 3
+            }),
+            Vec::<u8>::new()
+        );
+        assert_eq!(delivered(testing::Loss::Random(1.0)), Vec::<u8>::new());
+    }
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, true),
-        }];
+    #[test]
+    fn emulated_pipe_delay() {
+        let link = testing::LinkConfig {
+            delay: time::Duration::from_millis(25),
+            ..Default::default()
+        };
 
-        let len =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+        let mut config = emulated_config();
+        let mut pipe =
+            testing::EmulatedPipe::with_config(&mut config, link.clone(), link)
                 .unwrap();
 
-        // Simulate a truncated packet by sending one byte less.
-        let mut zrtt = buf[..len - 1].to_vec();
+        let start = pipe.now();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // 0-RTT packet is received before the Initial one.
-        assert_eq!(pipe.server_recv(&mut zrtt), Err(Error::InvalidPacket));
+        // The handshake takes at least one round-trip for each endpoint.
+        assert!(pipe.now() - start >= time::Duration::from_millis(50));
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+        let rtt = pipe.pipe.client.path_stats().next().unwrap().rtt;
+        assert!(rtt >= time::Duration::from_millis(50));
+        assert!(rtt < time::Duration::from_millis(75));
 
-        assert!(pipe.server.is_closed());
+        assert_eq!(emulated_transfer(&mut pipe, 100_000), Ok(true));
+        assert_eq!(pipe.pipe.client.stats().lost, 0);
     }
 
     #[test]
-    fn crypto_limit() {
-        let mut buf = [0; 65535];
+    fn emulated_pipe_bandwidth() {
+        let uplink = testing::LinkConfig {
+            delay: time::Duration::from_millis(5),
+            bandwidth: Some(1_000_000),
+            ..Default::default()
+        };
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+        let downlink = testing::LinkConfig {
+            delay: time::Duration::from_millis(5),
+            ..Default::default()
+        };
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let mut config = emulated_config();
+        let mut pipe =
+            testing::EmulatedPipe::with_config(&mut config, uplink, downlink)
+                .unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client send a 1-byte frame that starts from the crypto stream offset
-        // limit.
-        let frames = [frame::Frame::Crypto {
-            data: stream::RangeBuf::from(b"a", MAX_CRYPTO_STREAM_OFFSET, false),
-        }];
+        let start = pipe.now();
+        assert_eq!(emulated_transfer(&mut pipe, 500_000), Ok(true));
 
-        let pkt_type = packet::Type::Short;
+        // Transferring 500KB over a 1MB/s bottleneck takes at least half a
+        // second.
+        assert!(pipe.now() - start >= time::Duration::from_millis(500));
+    }
 
-        let written =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
+    #[test]
+    fn emulated_pipe_loss_recovery() {
+        let uplink = testing::LinkConfig {
+            delay: time::Duration::from_millis(20),
+            jitter: time::Duration::from_millis(2),
+            loss: testing::Loss::Periodic(10),
+            seed: 42,
+            ..Default::default()
+        };
 
-        let active_path = pipe.server.paths.get_active().unwrap();
-        let info = RecvInfo {
-            to: active_path.local_addr(),
-            from: active_path.peer_addr(),
+        let downlink = testing::LinkConfig {
+            delay: time::Duration::from_millis(20),
+            loss: testing::Loss::Random(0.05),
+            seed: 43,
+            ..Default::default()
         };
 
-        assert_eq!(
-            pipe.server.recv(&mut buf[..written], info),
-            Err(Error::CryptoBufferExceeded)
-        );
+        let mut config = emulated_config();
+        let mut pipe =
+            testing::EmulatedPipe::with_config(&mut config, uplink, downlink)
+                .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let written = match pipe.server.send(&mut buf) {
-            Ok((write, _)) => write,
+        assert_eq!(emulated_transfer(&mut pipe, 300_000), Ok(true));
 
-            Err(_) => unreachable!(),
+        assert!(pipe.uplink.dropped > 0);
+        assert!(pipe.pipe.client.stats().lost > 0);
+        assert!(pipe.pipe.client.stats().retrans > 0);
+    }
+
+    /// Transfers 1MB from the client over a 1MB/s bottleneck that drops every
+    /// 50th packet, using the given congestion control algorithm.
+    ///
+    /// Returns how long the transfer took, the number of packets the client
+    /// sent and lost, and its final congestion window.
+    fn emulated_cc_transfer(
+        algo: CongestionControlAlgorithm,
+    ) -> (time::Duration, usize, usize, usize) {
+        let uplink = testing::LinkConfig {
+            delay: time::Duration::from_millis(20),
+            bandwidth: Some(1_000_000),
+            loss: testing::Loss::Periodic(50),
+            ..Default::default()
         };
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..written]).unwrap();
-        let mut iter = frames.iter();
+        let downlink = testing::LinkConfig {
+            delay: time::Duration::from_millis(20),
+            ..Default::default()
+        };
+
+        let mut config = emulated_config();
+        config.set_cc_algorithm(algo);
+
+        let mut pipe =
+            testing::EmulatedPipe::with_config(&mut config, uplink, downlink)
+                .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let start = pipe.now();
+        assert_eq!(emulated_transfer(&mut pipe, 1_000_000), Ok(true));
+
+        let stats = pipe.pipe.client.stats();
+        let cwnd = pipe.pipe.client.path_stats().next().unwrap().cwnd;
+
+        (pipe.now() - start, stats.sent, stats.lost, cwnd)
+    }
+
+    #[test]
+    fn emulated_reno_transfer() {
+        let (elapsed, sent, lost, cwnd) =
+            emulated_cc_transfer(CongestionControlAlgorithm::Reno);
+
+        assert!(elapsed >= time::Duration::from_secs(1));
+        assert!(sent > 1_000_000 / MAX_SEND_UDP_PAYLOAD_SIZE);
+        assert!(lost > 0);
+        assert!(
+            cwnd >= MAX_SEND_UDP_PAYLOAD_SIZE * recovery::MINIMUM_WINDOW_PACKETS
+        );
 
+        // Time only moves with the emulated clock, so the same scenario
+        // always plays out the same way.
         assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::ConnectionClose {
-                error_code: 0x0d,
-                frame_type: 0,
-                reason: Vec::new(),
-            })
+            emulated_cc_transfer(CongestionControlAlgorithm::Reno),
+            (elapsed, sent, lost, cwnd)
         );
     }
 
     #[test]
-    fn limit_handshake_data() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert-big.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
+    fn emulated_cubic_transfer() {
+        let (elapsed, sent, lost, cwnd) =
+            emulated_cc_transfer(CongestionControlAlgorithm::CUBIC);
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert!(elapsed >= time::Duration::from_secs(1));
+        assert!(sent > 1_000_000 / MAX_SEND_UDP_PAYLOAD_SIZE);
+        assert!(lost > 0);
+        assert!(
+            cwnd >= MAX_SEND_UDP_PAYLOAD_SIZE * recovery::MINIMUM_WINDOW_PACKETS
+        );
+
+        // Time only moves with the emulated clock, so the same scenario
+        // always plays out the same way.
+        assert_eq!(
+            emulated_cc_transfer(CongestionControlAlgorithm::CUBIC),
+            (elapsed, sent, lost, cwnd)
+        );
+    }
+
+    /// Returns the number of datagrams in the server's first flight, and in
+    /// the client's following flight, which carries its Handshake Finished.
+    fn handshake_flight_lens(config: &mut Config) -> (u64, u64) {
+        let mut pipe = testing::Pipe::with_config(config).unwrap();
 
         let flight = testing::emit_flight(&mut pipe.client).unwrap();
-        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
         testing::process_flight(&mut pipe.server, flight).unwrap();
 
         let flight = testing::emit_flight(&mut pipe.server).unwrap();
-        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+        let server_len = flight.len() as u64;
+        testing::process_flight(&mut pipe.client, flight).unwrap();
 
-        assert_eq!(server_sent, client_sent * MAX_AMPLIFICATION_FACTOR);
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let client_len = flight.len() as u64;
+
+        (server_len, client_len)
     }
 
-    #[test]
-    fn custom_limit_handshake_data() {
-        const CUSTOM_AMPLIFICATION_FACTOR: usize = 2;
+    /// Runs a handshake over links with a 20ms delay in each direction that
+    /// drop the given datagrams, returning the pipe once both endpoints are
+    /// established, and how long it took.
+    fn emulated_lossy_handshake(
+        config: &mut Config, uplink_loss: Vec<u64>, downlink_loss: Vec<u64>,
+    ) -> (testing::EmulatedPipe, time::Duration) {
+        let uplink = testing::LinkConfig {
+            delay: time::Duration::from_millis(20),
+            loss: testing::Loss::Indices(uplink_loss),
+            ..Default::default()
+        };
 
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert-big.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_max_amplification_factor(CUSTOM_AMPLIFICATION_FACTOR);
+        let downlink = testing::LinkConfig {
+            delay: time::Duration::from_millis(20),
+            loss: testing::Loss::Indices(downlink_loss),
+            ..Default::default()
+        };
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        let mut pipe =
+            testing::EmulatedPipe::with_config(config, uplink, downlink).unwrap();
 
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
-        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
-        testing::process_flight(&mut pipe.server, flight).unwrap();
+        let start = pipe.now();
 
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
-        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+        // A single loss is recovered after one PTO, with some margin for the
+        // retransmissions themselves to be delivered.
+        let done = pipe.run_until(time::Duration::from_secs(3), |p| {
+            p.client.is_established() && p.server.is_established()
+        });
+        assert_eq!(done, Ok(true));
 
-        assert_eq!(server_sent, client_sent * CUSTOM_AMPLIFICATION_FACTOR);
+        let elapsed = pipe.now() - start;
+
+        (pipe, elapsed)
     }
 
     #[test]
-    fn stream() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    fn emulated_handshake_client_initial_lost() {
+        let mut config = emulated_config();
 
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        assert!(!pipe.server.stream_finished(4));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        let (pipe, elapsed) =
+            emulated_lossy_handshake(&mut config, vec![0], vec![]);
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
-        assert_eq!(&b[..12], b"hello, world");
+        assert_eq!(pipe.uplink.dropped, 1);
 
-        assert!(pipe.server.stream_finished(4));
+        // Without any RTT sample, the client only retransmits its Initial once
+        // the PTO based on the initial RTT fires.
+        assert!(elapsed >= recovery::INITIAL_RTT * 3);
     }
 
-    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn zero_rtt() {
-        let mut buf = [0; 65535];
+    fn emulated_handshake_server_first_flight_lost() {
+        let mut config = emulated_config();
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+        let (server_flight, _) = handshake_flight_lens(&mut config);
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let (pipe, elapsed) = emulated_lossy_handshake(
+            &mut config,
+            vec![],
+            (0..server_flight).collect(),
+        );
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+        assert_eq!(pipe.downlink.dropped, server_flight);
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+        // Neither endpoint has an RTT sample, so whichever retransmits first
+        // waits for the PTO based on the initial RTT.
+        assert!(elapsed >= recovery::INITIAL_RTT * 3);
+    }
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        let mut initial = buf[..len].to_vec();
+    #[test]
+    fn emulated_handshake_client_finished_lost() {
+        let mut config = emulated_config();
 
-        assert!(pipe.client.is_in_early_data());
+        let (_, client_flight) = handshake_flight_lens(&mut config);
 
-        // Client sends 0-RTT data.
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
-
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        let mut zrtt = buf[..len].to_vec();
-
-        // Server receives packets.
-        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
-        assert!(pipe.server.is_in_early_data());
+        let (pipe, elapsed) = emulated_lossy_handshake(
+            &mut config,
+            (1..1 + client_flight).collect(),
+            vec![],
+        );
 
-        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+        assert_eq!(pipe.uplink.dropped, client_flight);
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        // The Finished message is retransmitted after a PTO based on the RTT
+        // sample taken from the server's first flight, so well before the PTO
+        // based on the initial RTT.
+        assert!(elapsed > time::Duration::from_millis(80));
+        assert!(elapsed < recovery::INITIAL_RTT * 3);
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
-        assert_eq!(&b[..12], b"hello, world");
+        assert!(pipe.uplink.sent > 1 + client_flight);
     }
 
+    #[cfg(feature = "deterministic-rng")]
     #[test]
-    fn stream_send_on_32bit_arch() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(2_u64.pow(32) + 5);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(10);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(0);
-        config.verify_peer(false);
+    fn seeded_rng() {
+        let client_ids = |seed| {
+            let rng = Arc::new(SeededRng::new(seed));
+
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config.set_rng(rng.clone());
+            config.set_connection_id_generator(Arc::new(
+                RandomConnectionIdGenerator::with_rng(MAX_CONN_ID_LEN, rng),
+            ));
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+            let scid = config.generate_scid().unwrap();
 
-        // In 32bit arch, send_capacity() should be min(2^32+5, cwnd),
-        // not min(5, cwnd)
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+            let conn = connect(
+                Some("quic.tech"),
+                &scid,
+                "127.0.0.1:1234".parse().unwrap(),
+                "127.0.0.1:4321".parse().unwrap(),
+                &mut config,
+            )
+            .unwrap();
 
-        assert_eq!(pipe.advance(), Ok(()));
+            (scid, conn.destination_id().into_owned())
+        };
 
-        assert!(!pipe.server.stream_finished(4));
+        // The same seed yields the same connection IDs.
+        let (scid, dcid) = client_ids(42);
+        assert_eq!(client_ids(42), (scid.clone(), dcid.clone()));
+        assert_ne!(scid, dcid);
+
+        // A different seed doesn't.
+        let (other_scid, other_dcid) = client_ids(43);
+        assert_ne!(other_scid, scid);
+        assert_ne!(other_dcid, dcid);
     }
 
+    #[cfg(feature = "instrumentation")]
     #[test]
-    fn empty_stream_frame() {
+    fn tx_and_ack_hooks() {
+        use std::sync::Mutex;
+
         let mut buf = [0; 65535];
 
+        let tx_events = Arc::new(Mutex::new(Vec::new()));
+        let ack_events = Arc::new(Mutex::new(Vec::new()));
+
         let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, false),
-        }];
+        let events = tx_events.clone();
+        pipe.client.set_tx_hook(Box::new(move |ev: &TxEvent| {
+            events.lock().unwrap().push(*ev);
+        }));
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        let events = ack_events.clone();
+        pipe.client.set_ack_hook(Box::new(move |ev: &AckEvent| {
+            events.lock().unwrap().push(*ev);
+        }));
 
-        let mut readable = pipe.server.readable();
-        assert_eq!(readable.next(), Some(4));
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, false)));
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"", 5, true),
-        }];
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((1, true)));
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        let tx_events = tx_events.lock().unwrap();
+        assert_eq!(tx_events.len(), pipe.client.sent_count);
 
-        let mut readable = pipe.server.readable();
-        assert_eq!(readable.next(), Some(4));
+        let first = tx_events.first().unwrap();
+        assert_eq!(first.ty, packet::Type::Initial);
+        assert_eq!(first.pkt_num, 0);
+        assert!(first.ack_eliciting);
+        assert_eq!(first.from, testing::Pipe::client_addr());
+        assert_eq!(first.to, testing::Pipe::server_addr());
 
-        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((0, true)));
+        let last = tx_events.last().unwrap();
+        assert_eq!(last.ty, packet::Type::Short);
+        assert!(last.bytes_in_flight >= last.size);
+        assert_eq!(last.cwnd, pipe.client.path_stats().next().unwrap().cwnd);
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"", 15, true),
-        }];
+        let ack_events = ack_events.lock().unwrap();
+        assert!(!ack_events.is_empty());
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FinalSize)
-        );
+        let last = ack_events.last().unwrap();
+        assert!(last.acked_bytes > 0);
+        assert_eq!(last.lost_packets, 0);
+        assert!(last.latest_rtt > time::Duration::ZERO);
+        assert!(last.min_rtt.is_some());
     }
 
+    #[cfg(feature = "instrumentation")]
     #[test]
-    fn update_key_request() {
-        let mut b = [0; 15];
+    fn congestion_hook() {
+        use std::sync::Mutex;
+
+        let cc_events = Arc::new(Mutex::new(Vec::new()));
 
         let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-        assert_eq!(pipe.advance(), Ok(()));
 
-        // Client sends message with key update request.
-        assert_eq!(pipe.client_update_key(), Ok(()));
-        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        let events = cc_events.clone();
+        pipe.client
+            .set_congestion_hook(Box::new(move |ev: &CongestionEvent| {
+                events.lock().unwrap().push(*ev);
+            }));
 
-        // Ensure server updates key and it correctly decrypts the message.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, false)));
-        assert_eq!(&b[..5], b"hello");
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Ensure ACK for key update.
-        assert!(
-            pipe.server.pkt_num_spaces[packet::Epoch::Application]
-                .key_update
-                .as_ref()
-                .unwrap()
-                .update_acked
-        );
+        // The pacing rate is set once an RTT sample is available.
+        assert!(cc_events.lock().unwrap().iter().any(|ev| matches!(
+            ev.transition,
+            CongestionTransition::PacingRateUpdated(_)
+        )));
 
-        // Server sends message with the new key.
-        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        cc_events.lock().unwrap().clear();
 
-        // Ensure update key is completed and client can decrypt packet.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-        assert_eq!(pipe.client.stream_recv(4, &mut b), Ok((5, true)));
-        assert_eq!(&b[..5], b"world");
-    }
+        // Client sends stream data, and the first packet is lost.
+        assert_eq!(pipe.client.stream_send(0, &[0; 10000], true), Ok(10000));
 
-    #[test]
-    fn update_key_request_twice_error() {
-        let mut buf = [0; 65535];
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert!(flight.len() > 4);
+        flight.remove(0);
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-        assert_eq!(pipe.advance(), Ok(()));
+        testing::process_flight(&mut pipe.server, flight).unwrap();
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"hello", 0, false),
-        }];
+        // Server ACKs the rest, so the client detects the loss.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        testing::process_flight(&mut pipe.client, flight).unwrap();
 
-        // Client sends stream frame with key update request.
-        assert_eq!(pipe.client_update_key(), Ok(()));
-        let written = testing::encode_pkt(
-            &mut pipe.client,
-            packet::Type::Short,
-            &frames,
-            &mut buf,
-        )
-        .unwrap();
+        let cc_events = cc_events.lock().unwrap();
 
-        // Server correctly decode with new key.
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+        let transitions = cc_events
+            .iter()
+            .map(|ev| ev.transition)
+            .filter(|t| !matches!(t, CongestionTransition::PacingRateUpdated(_)))
+            .collect::<Vec<_>>();
 
-        // Client sends stream frame with another key update request before server
-        // ACK.
-        assert_eq!(pipe.client_update_key(), Ok(()));
-        let written = testing::encode_pkt(
-            &mut pipe.client,
-            packet::Type::Short,
-            &frames,
-            &mut buf,
-        )
-        .unwrap();
+        assert_eq!(transitions, vec![
+            CongestionTransition::SlowStartExit(SlowStartExitReason::Loss),
+            CongestionTransition::RecoveryStart,
+        ]);
 
-        // Check server correctly closes the connection with a key update error
-        // for the peer.
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Err(Error::KeyUpdate));
+        let ev = cc_events
+            .iter()
+            .find(|ev| ev.transition == CongestionTransition::RecoveryStart)
+            .unwrap();
+        assert_eq!(ev.cwnd, ev.ssthresh);
+        assert_eq!(ev.from, testing::Pipe::client_addr());
+        assert_eq!(ev.to, testing::Pipe::server_addr());
     }
 
     #[test]
-    /// Tests that receiving a MAX_STREAM_DATA frame for a receive-only
-    /// unidirectional stream is forbidden.
-    fn max_stream_data_receive_uni() {
+    fn handshake_timeout() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_max_idle_timeout(180_000);
+        config.set_handshake_timeout(50);
 
-        // Client opens unidirectional stream.
-        assert_eq!(pipe.client.stream_send(2, b"hello", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
-        // Client sends MAX_STREAM_DATA on local unidirectional stream.
-        let frames = [frame::Frame::MaxStreamData {
-            stream_id: 2,
-            max: 1024,
-        }];
+        // Client sends initial flight, but never completes the handshake.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert!(pipe.server.send(&mut buf).is_ok());
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::InvalidStreamState(2)),
-        );
+        // The handshake timeout fires before the idle and loss detection
+        // timeouts.
+        let timer = pipe.server.timeout().unwrap();
+        assert!(timer <= time::Duration::from_millis(50));
+
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.server.on_timeout();
+
+        assert!(pipe.server.is_closed());
+        assert!(pipe.server.is_timed_out());
+        assert_eq!(pipe.server.timeout(), None);
     }
 
     #[test]
-    fn empty_payload() {
-        let mut buf = [0; 65535];
+    fn handshake_timeout_completed() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_handshake_timeout(50);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert!(pipe.server.handshake_timer.is_some());
 
-        let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Send a packet with no frames.
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &[], &mut buf),
-            Err(Error::InvalidPacket)
-        );
+        // The timeout is disarmed once the handshake completes.
+        assert_eq!(pipe.server.handshake_timer, None);
     }
 
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn min_payload() {
+    fn handshake_0rtt() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-
-        // Send a non-ack-eliciting packet.
-        let frames = [frame::Frame::Padding { len: 4 }];
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
 
-        let pkt_type = packet::Type::Initial;
-        let written =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let initial_path = pipe
-            .server
-            .paths
-            .get_active()
-            .expect("initial path not found");
+        // Extract session,
+        let session = pipe.client.session().unwrap();
 
-        assert_eq!(initial_path.max_send_bytes, 195);
-
-        // Force server to send a single PING frame.
-        pipe.server
-            .paths
-            .get_active_mut()
-            .expect("no active path")
-            .recovery
-            .inc_loss_probes(packet::Epoch::Initial);
-
-        let initial_path = pipe
-            .server
-            .paths
-            .get_active_mut()
-            .expect("initial path not found");
-
-        // Artificially limit the amount of bytes the server can send.
-        initial_path.max_send_bytes = 60;
-
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
-    }
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-    #[test]
-    fn flow_control_limit() {
-        let mut buf = [0; 65535];
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        // Client sends 0-RTT packet.
+        let pkt_type = packet::Type::ZeroRTT;
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-        ];
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
 
-        let pkt_type = packet::Type::Short;
         assert_eq!(
             pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
+            Ok(1200)
         );
-    }
 
-    #[test]
-    fn flow_control_limit_dup() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
 
-        let frames = [
-            // One byte less than stream limit.
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaa", 0, false),
-            },
-            // Same stream, but one byte more.
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-        ];
+        // 0-RTT stream data is readable.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
 
-        let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
     }
 
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn flow_control_update() {
+    fn handshake_0rtt_reordered() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-        ];
+        // Extract session,
+        let session = pipe.client.session().unwrap();
 
-        let pkt_type = packet::Type::Short;
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut initial = buf[..len].to_vec();
 
-        pipe.server.stream_recv(0, &mut buf).unwrap();
-        pipe.server.stream_recv(4, &mut buf).unwrap();
+        // Client sends 0-RTT packet.
+        let pkt_type = packet::Type::ZeroRTT;
 
         let frames = [frame::Frame::Stream {
             stream_id: 4,
-            data: stream::RangeBuf::from(b"a", 1, false),
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
         }];
 
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
-
-        assert!(len > 0);
-
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-        let mut iter = frames.iter();
-
-        // Ignore ACK.
-        iter.next().unwrap();
-
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::MaxStreamData {
-                stream_id: 0,
-                max: 30
-            })
-        );
-        assert_eq!(iter.next(), Some(&frame::Frame::MaxData { max: 61 }));
-    }
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        let mut zrtt = buf[..len].to_vec();
 
-    #[test]
-    /// Tests that flow control is properly updated even when a stream is shut
-    /// down.
-    fn flow_control_drain() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        // 0-RTT packet is received before the Initial one.
+        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
 
-        // Client opens a stream and sends some data.
-        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 1);
+        assert_eq!(pipe.server.undecryptable_pkts[0].0.len(), zrtt.len());
 
-        // Server receives data, without reading it.
         let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
         assert_eq!(r.next(), None);
 
-        // In the meantime, client sends more data.
-        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.stream_send(4, b"aaaaa", true), Ok(5));
-
-        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.stream_send(8, b"aaaaa", true), Ok(5));
-
-        // Server shuts down one stream.
-        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+        // Initial packet is also received.
+        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
 
+        // 0-RTT stream data is readable.
         let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
         assert_eq!(r.next(), None);
 
-        // Flush connection.
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
     }
 
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn stream_flow_control_limit_bidi() {
+    fn handshake_0rtt_reordered_rejected() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+        config.enable_events(true);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaaa", 0, true),
-        }];
+        // Extract session,
+        let session = pipe.client.session().unwrap();
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
-        );
-    }
+        // Configure session on new connection, but reject early data on the
+        // server.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-    #[test]
-    fn stream_flow_control_limit_uni() {
-        let mut buf = [0; 65535];
+        pipe.server.handshake.set_early_data_enabled(false);
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut initial = buf[..len].to_vec();
+
+        // Client sends two 0-RTT packets.
+        let pkt_type = packet::Type::ZeroRTT;
 
         let frames = [frame::Frame::Stream {
-            stream_id: 2,
-            data: stream::RangeBuf::from(b"aaaaaaaaaaa", 0, true),
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
         }];
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
-        );
-    }
-
-    #[test]
-    fn stream_flow_control_update() {
-        let mut buf = [0; 65535];
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        let mut zrtt = buf[..len].to_vec();
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        let mut zrtt2 = buf[..len].to_vec();
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaaaaaa", 0, false),
-        }];
+        // 0-RTT packet is received before the Initial one, and buffered.
+        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 1);
 
-        let pkt_type = packet::Type::Short;
+        // Initial packet is received, and the buffered 0-RTT packet dropped.
+        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
 
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        assert!(pipe.server.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_0rtt_open
+            .is_none());
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
 
-        pipe.server.stream_recv(4, &mut buf).unwrap();
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"a", 9, false),
-        }];
+        // Later 0-RTT packets are dropped without being buffered.
+        assert_eq!(pipe.server_recv(&mut zrtt2), Ok(zrtt2.len()));
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
 
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
 
-        assert!(len > 0);
+        // Both dropped packets are reported.
+        let mut dropped = Vec::new();
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-        let mut iter = frames.iter();
+        while let Some(ev) = pipe.server.poll() {
+            if let Event::UndecryptablePacket { .. } = ev {
+                dropped.push(ev);
+            }
+        }
 
-        // Ignore ACK.
-        iter.next().unwrap();
+        assert_eq!(dropped, [Event::UndecryptablePacket {
+            ty: Type::ZeroRTT,
+            count: 2,
+        }]);
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::MaxStreamData {
-                stream_id: 4,
-                max: 24,
-            })
-        );
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert!(!pipe.server.is_closed());
     }
 
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn stream_left_bidi() {
+    fn handshake_0rtt_truncated() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(3, pipe.client.peer_streams_left_bidi());
-        assert_eq!(3, pipe.server.peer_streams_left_bidi());
+        // Extract session,
+        let session = pipe.client.session().unwrap();
 
-        pipe.server.stream_send(1, b"a", false).ok();
-        assert_eq!(2, pipe.server.peer_streams_left_bidi());
-        pipe.server.stream_send(5, b"a", false).ok();
-        assert_eq!(1, pipe.server.peer_streams_left_bidi());
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-        pipe.server.stream_send(9, b"a", false).ok();
-        assert_eq!(0, pipe.server.peer_streams_left_bidi());
+        // Client sends initial flight.
+        pipe.client.send(&mut buf).unwrap();
 
-        let frames = [frame::Frame::MaxStreamsBidi { max: MAX_STREAM_ID }];
+        // Client sends 0-RTT packet.
+        let pkt_type = packet::Type::ZeroRTT;
 
-        let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
 
-        assert_eq!(MAX_STREAM_ID - 3, pipe.server.peer_streams_left_bidi());
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        // Simulate a truncated packet by sending one byte less.
+        let mut zrtt = buf[..len - 1].to_vec();
+
+        // 0-RTT packet is received before the Initial one.
+        assert_eq!(pipe.server_recv(&mut zrtt), Err(Error::InvalidPacket));
+
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+
+        assert!(pipe.server.is_closed());
     }
 
     #[test]
-    fn stream_left_uni() {
+    fn crypto_limit() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
 
-        assert_eq!(3, pipe.client.peer_streams_left_uni());
-        assert_eq!(3, pipe.server.peer_streams_left_uni());
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        pipe.server.stream_send(3, b"a", false).ok();
-        assert_eq!(2, pipe.server.peer_streams_left_uni());
-        pipe.server.stream_send(7, b"a", false).ok();
-        assert_eq!(1, pipe.server.peer_streams_left_uni());
+        // Client send a 1-byte frame that starts from the crypto stream offset
+        // limit.
+        let frames = [frame::Frame::Crypto {
+            data: stream::RangeBuf::from(
+                b"a",
+                DEFAULT_MAX_CRYPTO_STREAM_OFFSET,
+                false,
+            ),
+        }];
 
-        pipe.server.stream_send(11, b"a", false).ok();
-        assert_eq!(0, pipe.server.peer_streams_left_uni());
+        let pkt_type = packet::Type::Short;
 
-        let frames = [frame::Frame::MaxStreamsUni { max: MAX_STREAM_ID }];
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
 
-        let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        let active_path = pipe.server.paths.get_active().unwrap();
+        let info = RecvInfo {
+            to: active_path.local_addr(),
+            from: active_path.peer_addr(),
+        };
 
-        assert_eq!(MAX_STREAM_ID - 3, pipe.server.peer_streams_left_uni());
-    }
+        assert_eq!(
+            pipe.server.recv(&mut buf[..written], info),
+            Err(Error::CryptoBufferExceeded)
+        );
 
-    #[test]
-    fn stream_limit_bidi() {
-        let mut buf = [0; 65535];
+        let written = match pipe.server.send(&mut buf) {
+            Ok((write, _)) => write,
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+            Err(_) => unreachable!(),
+        };
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 12,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 16,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 20,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 24,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 28,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-        ];
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..written]).unwrap();
+        let mut iter = frames.iter();
 
-        let pkt_type = packet::Type::Short;
         assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::StreamLimit),
+            iter.next(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: 0x0d,
+                frame_type: 0x06,
+                reason: Vec::new(),
+            })
         );
     }
 
     #[test]
-    fn stream_limit_max_bidi() {
+    fn crypto_limit_custom() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        assert_eq!(
+            config.set_max_crypto_buffer_size(4095),
+            Err(Error::InvalidState)
+        );
+        assert_eq!(config.set_max_crypto_buffer_size(4096), Ok(()));
+        assert_eq!(config.set_max_crypto_buffer_size(1 << 14), Ok(()));
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [frame::Frame::MaxStreamsBidi { max: MAX_STREAM_ID }];
+        // Data below the limit is accepted.
+        let frames = [frame::Frame::Crypto {
+            data: stream::RangeBuf::from(b"a", (1 << 14) - 2, false),
+        }];
 
         let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        let frames = [frame::Frame::MaxStreamsBidi {
-            max: MAX_STREAM_ID + 1,
+        let written =
+            testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..written]), Ok(written));
+
+        // Data reaching the limit closes the connection.
+        let frames = [frame::Frame::Crypto {
+            data: stream::RangeBuf::from(b"a", 1 << 14, false),
         }];
 
-        let pkt_type = packet::Type::Short;
+        let written =
+            testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
+                .unwrap();
+
         assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::InvalidFrame),
+            pipe.client_recv(&mut buf[..written]),
+            Err(Error::CryptoBufferExceeded)
         );
     }
 
     #[test]
-    fn stream_limit_uni() {
+    fn post_handshake_crypto_from_client() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 2,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 6,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 10,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 14,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 18,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 22,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 26,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-        ];
+        let frames = [frame::Frame::Crypto {
+            data: stream::RangeBuf::from(b"a", 0, false),
+        }];
 
         let pkt_type = packet::Type::Short;
+
         assert_eq!(
             pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::StreamLimit),
+            Err(Error::InvalidPacket)
+        );
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::ProtocolViolation as u64,
+                frame_type: 0x06,
+                reason: Vec::new(),
+            })
         );
     }
 
     #[test]
-    fn stream_limit_max_uni() {
+    fn max_frames_per_packet() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_max_frames_per_packet(4);
+        config.verify_peer(false);
 
-        let frames = [frame::Frame::MaxStreamsUni { max: MAX_STREAM_ID }];
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
         let pkt_type = packet::Type::Short;
+
+        // Packets up to the limit are accepted.
+        let frames = vec![frame::Frame::Ping { mtu_probe: None }; 4];
+
         assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        let frames = [frame::Frame::MaxStreamsUni {
-            max: MAX_STREAM_ID + 1,
-        }];
+        // One more frame closes the connection.
+        let frames = vec![frame::Frame::Ping { mtu_probe: None }; 5];
 
-        let pkt_type = packet::Type::Short;
         assert_eq!(
             pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::InvalidFrame),
+            Err(Error::InvalidPacket)
+        );
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::ProtocolViolation as u64,
+                frame_type: 0x01,
+                reason: Vec::new(),
+            })
         );
     }
 
     #[test]
-    fn stream_left_reset_bidi() {
+    fn close_on_frame_error() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(3, pipe.client.peer_streams_left_bidi());
-        assert_eq!(3, pipe.server.peer_streams_left_bidi());
+        // Badly formatted frame.
+        let frames = [frame::Frame::NewToken { token: vec![] }];
 
-        pipe.client.stream_send(0, b"a", false).ok();
-        assert_eq!(2, pipe.client.peer_streams_left_bidi());
-        pipe.client.stream_send(4, b"a", false).ok();
-        assert_eq!(1, pipe.client.peer_streams_left_bidi());
-        pipe.client.stream_send(8, b"a", false).ok();
-        assert_eq!(0, pipe.client.peer_streams_left_bidi());
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame)
+        );
 
-        // Client resets the stream.
-        pipe.client
-            .stream_shutdown(0, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
-        assert_eq!(0, pipe.client.peer_streams_left_bidi());
-        let mut r = pipe.server.readable();
-        assert_eq!(Some(0), r.next());
-        assert_eq!(Some(4), r.next());
-        assert_eq!(Some(8), r.next());
-        assert_eq!(None, r.next());
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
         assert_eq!(
-            pipe.server.stream_recv(0, &mut buf),
-            Err(Error::StreamReset(1001))
+            frames.first(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::FrameEncodingError as u64,
+                frame_type: 0x07,
+                reason: Vec::new(),
+            })
         );
 
-        let mut r = pipe.server.readable();
-        assert_eq!(Some(4), r.next());
-        assert_eq!(Some(8), r.next());
-        assert_eq!(None, r.next());
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server resets the stream in reaction.
-        pipe.server
-            .stream_shutdown(0, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        // Frame violating the flow control limits.
+        let frames = [frame::Frame::Stream {
+            stream_id: 0,
+            data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaaa", 0, false),
+        }];
 
-        assert_eq!(1, pipe.client.peer_streams_left_bidi());
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl)
+        );
 
-        // Repeat for the other 2 streams
-        pipe.client
-            .stream_shutdown(4, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.client
-            .stream_shutdown(8, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
-        let mut r = pipe.server.readable();
-        assert_eq!(Some(4), r.next());
-        assert_eq!(Some(8), r.next());
-        assert_eq!(None, r.next());
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
         assert_eq!(
-            pipe.server.stream_recv(4, &mut buf),
-            Err(Error::StreamReset(1001))
+            frames.first(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::FlowControlError as u64,
+                frame_type: 0x0e,
+                reason: Vec::new(),
+            })
         );
 
+        assert!(pipe.server.local_error().is_some());
         assert_eq!(
-            pipe.server.stream_recv(8, &mut buf),
-            Err(Error::StreamReset(1001))
+            pipe.server.local_error().unwrap().wire_error_code(),
+            Some(WireErrorCode::FlowControlError)
         );
+    }
 
-        let mut r = pipe.server.readable();
-        assert_eq!(None, r.next());
+    #[test]
+    fn ack_frequency() {
+        let mut buf = [0; 65535];
 
-        pipe.server
-            .stream_shutdown(4, Shutdown::Write, 1001)
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
-        pipe.server
-            .stream_shutdown(8, Shutdown::Write, 1001)
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
-        pipe.advance().unwrap();
-
-        assert_eq!(3, pipe.client.peer_streams_left_bidi());
-    }
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.enable_ack_frequency(true);
 
-    #[test]
-    fn stream_reset_counts() {
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        pipe.client.stream_send(0, b"a", false).ok();
-        pipe.client.stream_send(2, b"a", false).ok();
-        pipe.client.stream_send(4, b"a", false).ok();
-        pipe.client.stream_send(8, b"a", false).ok();
-        pipe.advance().unwrap();
+        assert_eq!(pipe.client.peer_transport_params.min_ack_delay, Some(1000));
+        assert_eq!(pipe.server.peer_transport_params.min_ack_delay, Some(1000));
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.reset_stream_count_local, 0);
+        // The client asks the server to send ACKs less frequently.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
 
-        // Client resets the stream.
-        pipe.client
-            .stream_shutdown(0, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.reset_stream_count_local, 1);
-        assert_eq!(stats.reset_stream_count_remote, 0);
-        let stats = pipe.server.stats();
-        assert_eq!(stats.reset_stream_count_local, 0);
-        assert_eq!(stats.reset_stream_count_remote, 1);
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-        // Server resets the stream in reaction.
-        pipe.server
-            .stream_shutdown(0, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        assert!(frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::AckFrequency { .. })));
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.reset_stream_count_local, 1);
-        assert_eq!(stats.reset_stream_count_remote, 1);
-        let stats = pipe.server.stats();
-        assert_eq!(stats.reset_stream_count_local, 1);
-        assert_eq!(stats.reset_stream_count_remote, 1);
+        assert_eq!(pipe.advance(), Ok(()));
 
-        // Repeat for the other streams
-        pipe.client
-            .stream_shutdown(2, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.client
-            .stream_shutdown(4, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.client
-            .stream_shutdown(8, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        // Ack-eliciting packets, including the one carrying the new
+        // parameters, only trigger an ACK once the threshold is exceeded.
+        let frames = [frame::Frame::AckFrequency {
+            seq_num: 10,
+            ack_eliciting_threshold: 3,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: 0,
+        }];
 
-        pipe.server
-            .stream_shutdown(4, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.server
-            .stream_shutdown(8, Shutdown::Write, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.reset_stream_count_local, 4);
-        assert_eq!(stats.reset_stream_count_remote, 3);
-        let stats = pipe.server.stats();
-        assert_eq!(stats.reset_stream_count_local, 3);
-        assert_eq!(stats.reset_stream_count_remote, 4);
-    }
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
 
-    #[test]
-    fn stream_stop_counts() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
 
-        pipe.client.stream_send(0, b"a", false).ok();
-        pipe.client.stream_send(2, b"a", false).ok();
-        pipe.client.stream_send(4, b"a", false).ok();
-        pipe.client.stream_send(8, b"a", false).ok();
-        pipe.advance().unwrap();
+        let timeout = pipe.server.timeout().unwrap();
+        assert!(timeout <= time::Duration::from_millis(25));
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.reset_stream_count_local, 0);
+        assert!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
+                .unwrap() >
+                0
+        );
 
-        // Server stops the stream and client automatically resets.
-        pipe.server
-            .stream_shutdown(0, Shutdown::Read, 1001)
-            .unwrap();
-        pipe.advance().unwrap();
+        // IMMEDIATE_ACK triggers an ACK right away.
+        let frames = [frame::Frame::ImmediateAck];
+        assert!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
+                .unwrap() >
+                0
+        );
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.stopped_stream_count_local, 0);
-        assert_eq!(stats.stopped_stream_count_remote, 1);
-        assert_eq!(stats.reset_stream_count_local, 1);
-        assert_eq!(stats.reset_stream_count_remote, 0);
+        // The ACK is sent once the requested delay expires.
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
 
-        let stats = pipe.server.stats();
-        assert_eq!(stats.stopped_stream_count_local, 1);
-        assert_eq!(stats.stopped_stream_count_remote, 0);
-        assert_eq!(stats.reset_stream_count_local, 0);
-        assert_eq!(stats.reset_stream_count_remote, 1);
+        let timeout = pipe.server.timeout().unwrap();
+        std::thread::sleep(timeout + time::Duration::from_millis(1));
+        pipe.server.on_timeout();
 
-        // Repeat for the other streams
-        pipe.server
-            .stream_shutdown(2, Shutdown::Read, 1001)
+        assert!(
+            pipe.server.pkt_num_spaces[packet::Epoch::Application].ack_elicited
+        );
+    }
+
+    #[test]
+    fn ack_frequency_peer_min_ack_delay() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
-        pipe.server
-            .stream_shutdown(4, Shutdown::Read, 1001)
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
-        pipe.server
-            .stream_shutdown(8, Shutdown::Read, 1001)
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        pipe.advance().unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_ack_delay(100);
+        config.verify_peer(false);
+        config.enable_ack_frequency(true);
 
-        let stats = pipe.client.stats();
-        assert_eq!(stats.stopped_stream_count_local, 0);
-        assert_eq!(stats.stopped_stream_count_remote, 4);
-        assert_eq!(stats.reset_stream_count_local, 4);
-        assert_eq!(stats.reset_stream_count_remote, 0);
+        // Only accept requests for an ACK delay of at least 50ms.
+        config.local_transport_params.min_ack_delay = Some(50_000);
 
-        let stats = pipe.server.stats();
-        assert_eq!(stats.stopped_stream_count_local, 4);
-        assert_eq!(stats.stopped_stream_count_remote, 0);
-        assert_eq!(stats.reset_stream_count_local, 0);
-        assert_eq!(stats.reset_stream_count_remote, 4);
-    }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-    #[test]
-    fn streams_blocked_max_bidi() {
-        let mut buf = [0; 65535];
+        // Lower the delay the client would otherwise request below the
+        // server's minimum.
+        pipe.client.recovery_config.max_ack_delay =
+            time::Duration::from_millis(10);
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
 
-        let frames = [frame::Frame::StreamsBlockedBidi {
-            limit: MAX_STREAM_ID,
-        }];
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-        let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        let mut pkt = buf[..len].to_vec();
+        let frames = testing::decode_pkt(&mut pipe.server, &mut pkt).unwrap();
 
-        let frames = [frame::Frame::StreamsBlockedBidi {
-            limit: MAX_STREAM_ID + 1,
-        }];
+        // The request is raised to the server's minimum.
+        assert!(frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::AckFrequency {
+                request_max_ack_delay: 50_000,
+                ..
+            })));
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::InvalidFrame),
-        );
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.server.local_error, None);
     }
 
     #[test]
-    fn streams_blocked_max_uni() {
+    fn ack_frequency_not_enabled() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [frame::Frame::StreamsBlockedUni {
-            limit: MAX_STREAM_ID,
+        let frames = [frame::Frame::AckFrequency {
+            seq_num: 0,
+            ack_eliciting_threshold: 2,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: 1,
         }];
 
         let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidState)
+        );
 
-        let frames = [frame::Frame::StreamsBlockedUni {
-            limit: MAX_STREAM_ID + 1,
-        }];
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let pkt_type = packet::Type::Short;
+        let frames = [frame::Frame::ImmediateAck];
         assert_eq!(
             pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::InvalidFrame),
+            Err(Error::InvalidState)
         );
     }
 
     #[test]
-    fn stream_data_overlap() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    fn client_hello_handler_accept() {
+        // Accepts all connections, recording the ClientHello.
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Option<ClientHello>>);
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"bbbbb", 3, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"ccccc", 6, false),
-            },
-        ];
+        impl ClientHelloHandler for Recorder {
+            fn on_client_hello(&self, hello: &ClientHello) -> ClientHelloAction {
+                *self.0.lock().unwrap() = Some(hello.clone());
 
-        let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+                ClientHelloAction::Accept
+            }
+        }
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((11, false)));
-        assert_eq!(&b[..11], b"aaaaabbbccc");
-    }
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
 
-    #[test]
-    fn stream_data_overlap_with_reordering() {
-        let mut buf = [0; 65535];
+        let handler = Arc::new(Recorder::default());
+        config.set_client_hello_handler(handler.clone());
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"ccccc", 6, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"bbbbb", 3, false),
-            },
-        ];
-
-        let pkt_type = packet::Type::Short;
-        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
-
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((11, false)));
-        assert_eq!(&b[..11], b"aaaaabccccc");
+        let hello = handler.0.lock().unwrap().take().unwrap();
+        assert_eq!(hello.server_name(), Some("quic.tech"));
+        assert_eq!(hello.application_protos(), &[
+            b"proto1".to_vec(),
+            b"proto2".to_vec()
+        ]);
     }
 
     #[test]
-    /// Tests that receiving a valid RESET_STREAM frame when all data has
-    /// already been read, notifies the application.
-    fn reset_stream_data_recvd() {
-        let mut b = [0; 15];
-        let mut buf = [0; 65535];
+    fn client_hello_handler_reject() {
+        // Refuses all connections.
+        struct Refuse;
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        impl ClientHelloHandler for Refuse {
+            fn on_client_hello(&self, _: &ClientHello) -> ClientHelloAction {
+                ClientHelloAction::Reject(WireErrorCode::ConnectionRefused as u64)
+            }
+        }
 
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut buf = [0; 65535];
 
-        // Server gets data and sends data back, closing stream.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_client_hello_handler(Arc::new(Refuse));
 
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, false)));
-        assert!(!pipe.server.stream_finished(0));
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        assert_eq!(pipe.server.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(
+            pipe.server.local_error(),
+            Some(&ConnectionError {
+                is_app: false,
+                error_code: WireErrorCode::ConnectionRefused as u64,
+                reason: vec![],
+            })
+        );
 
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        // Server only sends CONNECTION_CLOSE, without continuing the
+        // handshake.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
-        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((0, true)));
-        assert!(pipe.client.stream_finished(0));
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
-        // Client sends RESET_STREAM, closing stream.
-        let frames = [frame::Frame::ResetStream {
-            stream_id: 0,
-            error_code: 42,
-            final_size: 5,
-        }];
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::ConnectionRefused as u64,
+                frame_type: 0,
+                reason: vec![],
+            })
+        );
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        assert!(!frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::Crypto { .. })));
+    }
 
-        // Server is notified of stream readability, due to reset.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
+    #[test]
+    fn client_hello_handler_reject_early_data() {
+        // Rejects early data when resuming, recording the ClientHello.
+        #[derive(Default)]
+        struct NoEarlyData(std::sync::Mutex<Option<ClientHello>>);
 
-        assert_eq!(
-            pipe.server.stream_recv(0, &mut b),
-            Err(Error::StreamReset(42))
-        );
+        impl ClientHelloHandler for NoEarlyData {
+            fn on_client_hello(&self, hello: &ClientHello) -> ClientHelloAction {
+                *self.0.lock().unwrap() = Some(hello.clone());
 
-        assert!(pipe.server.stream_finished(0));
+                if hello.early_data() {
+                    return ClientHelloAction::RejectEarlyData;
+                }
 
-        // Sending RESET_STREAM again shouldn't make stream readable again.
-        pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
-    }
+                ClientHelloAction::Accept
+            }
+        }
 
-    #[test]
-    /// Tests that receiving a valid RESET_STREAM frame when all data has _not_
-    /// been read, discards all buffered data and notifies the application.
-    fn reset_stream_data_not_recvd() {
-        let mut b = [0; 15];
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        let handler = Arc::new(NoEarlyData::default());
+        config.set_client_hello_handler(handler.clone());
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(0, b"h", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        let hello = handler.0.lock().unwrap().take().unwrap();
+        assert!(!hello.early_data());
+        assert!(hello.psk_identities().is_empty());
 
-        // Server gets data and sends data back, closing stream.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        let session = pipe.client.session().unwrap();
 
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((1, false)));
-        assert!(!pipe.server.stream_finished(0));
+        // Resume the session with 0-RTT data.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        assert_eq!(pipe.server.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
+        let hello = handler.0.lock().unwrap().take().unwrap();
+        assert!(hello.early_data());
+        assert_eq!(hello.psk_identities().len(), 1);
 
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        // The server can't decrypt 0-RTT packets.
+        assert!(!pipe.server.is_in_early_data());
+        assert!(pipe.server.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_0rtt_open
+            .is_none());
 
-        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((0, true)));
-        assert!(pipe.client.stream_finished(0));
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert!(pipe.server.is_resumed());
+    }
 
-        // Client sends RESET_STREAM, closing stream.
-        let frames = [frame::Frame::ResetStream {
-            stream_id: 0,
-            error_code: 42,
-            final_size: 5,
-        }];
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
+    #[test]
+    fn max_early_data() {
+        let mut buf = [0; 65535];
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_early_data(50);
+        config.enable_early_data();
+        config.verify_peer(false);
 
-        // Server is notified of stream readability, due to reset.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(
-            pipe.server.stream_recv(0, &mut b),
-            Err(Error::StreamReset(42))
-        );
+        let session = pipe.client.session().unwrap();
 
-        assert!(pipe.server.stream_finished(0));
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-        // Sending RESET_STREAM again shouldn't make stream readable again.
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
-    }
+        // The first 0-RTT packet fits within the limit.
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
 
-    #[test]
-    /// Tests that RESET_STREAM frames exceeding the connection-level flow
-    /// control limit cause an error.
-    fn reset_stream_flow_control() {
-        let mut buf = [0; 65535];
+        let len = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::ZeroRTT,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        // The second one doesn't, so it's dropped.
+        let frames = [frame::Frame::Stream {
+            stream_id: 8,
+            data: stream::RangeBuf::from(b"bbbbb", 0, true),
+        }];
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::ResetStream {
-                stream_id: 4,
-                error_code: 0,
-                final_size: 15,
-            },
-            frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-        ];
+        let len = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::ZeroRTT,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
-        );
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
+        assert!(pipe.server.early_data_recv <= 50);
     }
 
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    /// Tests that RESET_STREAM frames exceeding the stream-level flow control
-    /// limit cause an error.
-    fn reset_stream_flow_control_stream() {
-        let mut buf = [0; 65535];
+    fn anti_replay() {
+        #[derive(Default)]
+        struct StrikeRegister(std::sync::Mutex<HashSet<Vec<u8>>>);
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        impl AntiReplay for StrikeRegister {
+            fn insert(&self, ticket: &[u8]) -> bool {
+                self.0.lock().unwrap().insert(ticket.to_vec())
+            }
+        }
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::ResetStream {
-                stream_id: 4,
-                error_code: 0,
-                final_size: 16, // Past stream's flow control limit.
-            },
-        ];
+        let mut buf = [0; 65535];
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
-        );
-    }
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_ticket_lifetime(60);
+        assert_eq!(config.set_max_tickets(1), Ok(()));
+        config.enable_early_data();
+        config.verify_peer(false);
 
-    #[test]
-    fn path_challenge() {
-        let mut buf = [0; 65535];
+        let store = Arc::new(StrikeRegister::default());
+        config.set_anti_replay(store.clone());
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
+        assert!(store.0.lock().unwrap().is_empty());
 
-        let frames = [frame::Frame::PathChallenge { data: [0xba; 8] }];
+        let session = pipe.client.session().unwrap();
 
-        let pkt_type = packet::Type::Short;
+        // The first use of the ticket for early data is accepted.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        assert!(len > 0);
+        assert!(pipe.server.is_in_early_data());
+        assert_eq!(store.0.lock().unwrap().len(), 1);
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-        let mut iter = frames.iter();
+        // Replaying the ticket only resumes the session.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
 
-        // Ignore ACK.
-        iter.next().unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::PathResponse { data: [0xba; 8] })
-        );
+        assert!(!pipe.server.is_in_early_data());
+        assert!(pipe.server.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_0rtt_open
+            .is_none());
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert!(pipe.server.is_resumed());
     }
 
-    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    /// Simulates reception of an early 1-RTT packet on the server, by
-    /// delaying the client's Handshake packet that completes the handshake.
-    fn early_1rtt_packet() {
+    fn client_initial_dcid() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-
-        // Client sends initial flight
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
-        testing::process_flight(&mut pipe.server, flight).unwrap();
+        let client_scid = ConnectionId::from_ref(&[0xcd; 16]);
+        let server_scid = ConnectionId::from_ref(&[0xab; 16]);
+        let dcid = ConnectionId::from_ref(&[0xba; 8]);
 
-        // Server sends initial flight.
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
-        testing::process_flight(&mut pipe.client, flight).unwrap();
-
-        // Client sends Handshake packet.
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
 
-        // Emulate handshake packet delay by not making server process client
-        // packet.
-        let delayed = flight;
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
 
-        testing::emit_flight(&mut pipe.server).ok();
+        // The connection ID is too short, or too long.
+        for len in [7, MAX_CONN_ID_LEN + 1] {
+            let dcid = vec![0xba; len];
 
-        assert!(pipe.client.is_established());
+            assert_eq!(
+                connect_with_dcid(
+                    Some("quic.tech"),
+                    &client_scid,
+                    &ConnectionId::from_vec(dcid),
+                    client_addr,
+                    server_addr,
+                    &mut config,
+                )
+                .err(),
+                Some(Error::InvalidState)
+            );
+        }
 
-        // Send 1-RTT packet #0.
-        let frames = [frame::Frame::Stream {
-            stream_id: 0,
-            data: stream::RangeBuf::from(b"hello, world", 0, true),
-        }];
+        let mut client = connect_with_dcid(
+            Some("quic.tech"),
+            &client_scid,
+            &dcid,
+            client_addr,
+            server_addr,
+            &mut config,
+        )
+        .unwrap();
 
-        let pkt_type = packet::Type::Short;
-        let written =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
+        assert_eq!(client.destination_id(), dcid);
 
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+        let (len, _) = client.send(&mut buf).unwrap();
 
-        // Send 1-RTT packet #1.
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"hello, world", 0, true),
-        }];
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        assert_eq!(hdr.ty, Type::Initial);
+        assert_eq!(hdr.dcid, dcid);
 
-        let written =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
+        let mut pipe = testing::Pipe {
+            client,
+            server: accept(
+                &server_scid,
+                None,
+                server_addr,
+                client_addr,
+                &mut config,
+            )
+            .unwrap(),
+        };
 
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+        // The server derives the same Initial secrets from the connection ID.
+        let info = RecvInfo {
+            to: server_addr,
+            from: client_addr,
+        };
 
-        assert!(!pipe.server.is_established());
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
 
-        // Client sent 1-RTT packets 0 and 1, but server hasn't received them.
-        //
-        // Note that `largest_rx_pkt_num` is initialized to 0, so we need to
-        // send another 1-RTT packet to make this check meaningful.
-        assert_eq!(
-            pipe.server.pkt_num_spaces[packet::Epoch::Application]
-                .largest_rx_pkt_num,
-            0
-        );
+    #[test]
+    fn stateless_reset() {
+        let mut buf = [0; 65535];
 
-        // Process delayed packet.
-        testing::process_flight(&mut pipe.server, delayed).unwrap();
+        let mut resets = StatelessReset::new(&[0xba; 32]);
 
-        assert!(pipe.server.is_established());
+        let client_scid = ConnectionId::from_ref(&[0xcd; 16]);
+        let server_scid = ConnectionId::from_ref(&[0xab; 16]);
 
-        assert_eq!(
-            pipe.server.pkt_num_spaces[packet::Epoch::Application]
-                .largest_rx_pkt_num,
-            0
-        );
-    }
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_stateless_reset_token(Some(resets.token(&server_scid)));
+        config.verify_peer(false);
 
-    #[test]
-    fn stop_sending() {
-        let mut b = [0; 15];
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
 
-        let mut buf = [0; 65535];
+        let mut pipe = testing::Pipe {
+            client: connect(
+                Some("quic.tech"),
+                &client_scid,
+                client_addr,
+                server_addr,
+                &mut config,
+            )
+            .unwrap(),
+            server: accept(
+                &server_scid,
+                None,
+                server_addr,
+                client_addr,
+                &mut config,
+            )
+            .unwrap(),
+        };
 
-        let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data, and closes stream.
+        // The server lost the connection state, so it answers the client's
+        // next packet with a stateless reset.
         assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Server gets data.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
 
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
-        assert!(pipe.server.stream_finished(0));
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        let mut reset = [0; 1500];
+        let now = time::Instant::now();
 
-        // Server sends data, until blocked.
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        let reset_len = resets
+            .write(&buf[..len], server_scid.len(), now, &mut reset)
+            .unwrap();
+        assert!(reset_len < len);
 
-        while pipe.server.stream_send(0, b"world", false) != Err(Error::Done) {
-            assert_eq!(pipe.advance(), Ok(()));
-        }
+        let info = RecvInfo {
+            to: client_addr,
+            from: server_addr,
+        };
 
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), None);
+        assert_eq!(
+            pipe.client.recv(&mut reset[..reset_len], info),
+            Ok(reset_len)
+        );
+        assert!(pipe.client.is_closed());
+    }
 
-        // Client sends STOP_SENDING.
-        let frames = [frame::Frame::StopSending {
-            stream_id: 0,
-            error_code: 42,
-        }];
+    #[test]
+    fn stateless_reset_key() {
+        let key = [0xba; 32];
+        let resets = StatelessReset::new(&key);
 
-        let pkt_type = packet::Type::Short;
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_active_connection_id_limit(2);
+        config.set_stateless_reset_token(Some(0xba));
+        config.set_stateless_reset_key(&key);
+        config.verify_peer(false);
 
-        // Server sent a RESET_STREAM frame in response.
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let mut iter = frames.iter();
+        // The initial token is derived from the key.
+        let server_scid = pipe.server.source_id().into_owned();
+        assert_eq!(
+            pipe.client.peer_transport_params.stateless_reset_token,
+            Some(resets.token(&server_scid))
+        );
 
-        // Skip ACK frame.
-        iter.next();
+        // So are those of new Connection IDs, whatever the token provided.
+        let (scid, seq) = pipe.server.generate_scid(0xba, false).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
 
         assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::ResetStream {
-                stream_id: 0,
-                error_code: 42,
-                final_size: 15,
-            })
+            pipe.client.ids.get_dcid(seq).unwrap().reset_token,
+            Some(resets.token(&scid))
         );
 
-        // Stream is writable, but writing returns an error.
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        let (scid, _) = testing::create_cid_and_reset_token(16);
+        let seq = pipe.client.new_scid(&scid, 0xba, true).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
 
         assert_eq!(
-            pipe.server.stream_send(0, b"world", true),
-            Err(Error::StreamStopped(42)),
+            pipe.server.ids.get_dcid(seq).unwrap().reset_token,
+            Some(resets.token(&scid))
         );
+    }
 
-        assert_eq!(pipe.server.streams.len(), 1);
-
-        // Client acks RESET_STREAM frame.
-        let mut ranges = ranges::RangeSet::default();
-        ranges.insert(0..6);
+    #[test]
+    fn extension_frame() {
+        // Extension frames with the body prefixed by its length.
+        struct LengthPrefixed;
 
-        let frames = [frame::Frame::ACK {
-            ack_delay: 15,
-            ranges,
-            ecn_counts: None,
-        }];
+        impl ExtensionFrameHandler for LengthPrefixed {
+            fn frame_len(&self, _frame_type: u64, buf: &[u8]) -> Result<usize> {
+                let mut b = octets::Octets::with_slice(buf);
+                let len = b.get_varint().map_err(|_| Error::InvalidFrame)?;
 
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
+                Ok(b.off() + len as usize)
+            }
+        }
 
-        // Stream is collected on the server after RESET_STREAM is acked.
-        assert_eq!(pipe.server.streams.len(), 0);
+        const FRAME_TYPE: u64 = 0x3f00_0000_0000_00af;
 
-        // Sending STOP_SENDING again shouldn't trigger RESET_STREAM again.
-        let frames = [frame::Frame::StopSending {
-            stream_id: 0,
-            error_code: 42,
-        }];
+        let mut buf = [0; 65535];
 
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        // Natively supported frames can't be overridden.
+        assert_eq!(
+            config.register_extension_frame(0x30, Arc::new(LengthPrefixed)),
+            Err(Error::InvalidFrame)
+        );
+        assert_eq!(
+            config.register_extension_frame(FRAME_TYPE, Arc::new(LengthPrefixed)),
+            Ok(())
+        );
 
-        assert_eq!(frames.len(), 1);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        match frames.first() {
-            Some(frame::Frame::ACK { .. }) => (),
+        let frames = [
+            frame::Frame::Extension {
+                frame_type: FRAME_TYPE,
+                data: vec![0x03, 0x01, 0x02, 0x03],
+            },
+            frame::Frame::Extension {
+                frame_type: FRAME_TYPE,
+                data: vec![0x00],
+            },
+        ];
 
-            f => panic!("expected ACK frame, got {:?}", f),
-        };
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), None);
-    }
+        assert_eq!(
+            pipe.server.extension_frame_recv(),
+            Ok((FRAME_TYPE, vec![0x03, 0x01, 0x02, 0x03]))
+        );
+        assert_eq!(
+            pipe.server.extension_frame_recv(),
+            Ok((FRAME_TYPE, vec![0x00]))
+        );
+        assert_eq!(pipe.server.extension_frame_recv(), Err(Error::Done));
 
-    #[test]
-    fn stop_sending_fin() {
-        let mut b = [0; 15];
+        // A truncated frame is rejected.
+        let frames = [frame::Frame::Extension {
+            frame_type: FRAME_TYPE,
+            data: vec![0x05, 0x01],
+        }];
 
-        let mut buf = [0; 65535];
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame)
+        );
 
+        // Unregistered frame types are still rejected.
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data, and closes stream.
-        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Server gets data.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-        assert!(pipe.server.stream_finished(4));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
-
-        // Server sends data...
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.server.stream_send(4, b"world", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // ...and buffers more, and closes stream.
-        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
-
-        // Client sends STOP_SENDING before server flushes stream.
-        let frames = [frame::Frame::StopSending {
-            stream_id: 4,
-            error_code: 42,
+        let frames = [frame::Frame::Extension {
+            frame_type: FRAME_TYPE,
+            data: vec![0x00],
         }];
 
-        let pkt_type = packet::Type::Short;
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
-
-        // Server sent a RESET_STREAM frame in response.
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-
-        let mut iter = frames.iter();
-
-        // Skip ACK frame.
-        iter.next();
-
         assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::ResetStream {
-                stream_id: 4,
-                error_code: 42,
-                final_size: 5,
-            })
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame)
         );
-
-        // No more frames are sent by the server.
-        assert_eq!(iter.next(), None);
     }
 
     #[test]
-    /// Tests that resetting a stream restores flow control for unsent data.
-    fn stop_sending_unsent_tx_cap() {
+    fn extension_frame_recv_queue_full() {
+        struct Empty;
+
+        impl ExtensionFrameHandler for Empty {
+            fn frame_len(&self, _frame_type: u64, _buf: &[u8]) -> Result<usize> {
+                Ok(0)
+            }
+        }
+
+        const FRAME_TYPE: u64 = 0x3f00_0000_0000_00af;
+
         let mut buf = [0; 65535];
 
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -11278,193 +15673,165 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        config.set_initial_max_data(15);
-        config.set_initial_max_stream_data_bidi_local(30);
-        config.set_initial_max_stream_data_bidi_remote(30);
-        config.set_initial_max_stream_data_uni(30);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(0);
         config.verify_peer(false);
+        config
+            .register_extension_frame(FRAME_TYPE, Arc::new(Empty))
+            .unwrap();
+        config.set_extension_frame_recv_max_queue_len(2);
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-
-        // Server sends some data.
-        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Server buffers some data, until send capacity limit reached.
-        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(
-            pipe.server.stream_send(4, b"hello", false),
-            Err(Error::Done)
-        );
-
-        // Client sends STOP_SENDING.
-        let frames = [frame::Frame::StopSending {
-            stream_id: 4,
-            error_code: 42,
-        }];
+        let frames = [
+            frame::Frame::Extension {
+                frame_type: FRAME_TYPE,
+                data: vec![],
+            },
+            frame::Frame::Extension {
+                frame_type: FRAME_TYPE,
+                data: vec![],
+            },
+            frame::Frame::Extension {
+                frame_type: FRAME_TYPE,
+                data: vec![],
+            },
+        ];
 
         let pkt_type = packet::Type::Short;
-        pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        // Server can now send more data (on a different stream).
-        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Only the first two frames were queued.
+        assert_eq!(pipe.server.extension_frame_recv(), Ok((FRAME_TYPE, vec![])));
+        assert_eq!(pipe.server.extension_frame_recv(), Ok((FRAME_TYPE, vec![])));
+        assert_eq!(pipe.server.extension_frame_recv(), Err(Error::Done));
 
-        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
-        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
-        assert_eq!(
-            pipe.server.stream_send(8, b"hello", false),
-            Err(Error::Done)
-        );
-        assert_eq!(pipe.advance(), Ok(()));
+        // Once the queue is drained, new frames are queued again.
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        assert_eq!(pipe.server.extension_frame_recv(), Ok((FRAME_TYPE, vec![])));
+        assert_eq!(pipe.server.extension_frame_recv(), Ok((FRAME_TYPE, vec![])));
+        assert_eq!(pipe.server.extension_frame_recv(), Err(Error::Done));
+        assert!(!pipe.server.is_closed());
     }
 
     #[test]
-    fn stream_shutdown_read() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
+    fn extension_frame_send() {
+        // Extension frames with a fixed-length body, retransmitted only if
+        // their type is odd.
+        struct Fixed;
+
+        impl ExtensionFrameHandler for Fixed {
+            fn frame_len(&self, _frame_type: u64, buf: &[u8]) -> Result<usize> {
+                if buf.len() < 4 {
+                    return Err(Error::InvalidFrame);
+                }
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+                Ok(4)
+            }
 
-        assert_eq!(pipe.client.streams.len(), 1);
-        assert_eq!(pipe.server.streams.len(), 1);
+            fn retransmit(&self, frame_type: u64, _data: &[u8]) -> bool {
+                frame_type % 2 == 1
+            }
+        }
 
-        // Server shuts down stream.
-        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+        const RELIABLE: u64 = 0x3f00_0000_0000_00af;
+        const UNRELIABLE: u64 = 0x3f00_0000_0000_00b0;
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config
+            .register_extension_frame(RELIABLE, Arc::new(Fixed))
+            .unwrap();
+        config
+            .register_extension_frame(UNRELIABLE, Arc::new(Fixed))
+            .unwrap();
 
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-        let mut dummy = buf[..len].to_vec();
+        // Frames can't be sent before 1-RTT keys are available.
+        assert_eq!(
+            pipe.client.extension_frame_send(RELIABLE, &[1, 2, 3, 4]),
+            Err(Error::InvalidState)
+        );
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut dummy[..len]).unwrap();
-        let mut iter = frames.iter();
+        assert_eq!(pipe.handshake(), Ok(()));
 
+        // Unregistered frame types and badly formatted bodies are rejected.
         assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::StopSending {
-                stream_id: 4,
-                error_code: 42,
-            })
+            pipe.client.extension_frame_send(0x3f00, &[1, 2, 3, 4]),
+            Err(Error::InvalidFrame)
+        );
+        assert_eq!(
+            pipe.client.extension_frame_send(RELIABLE, &[1, 2, 3]),
+            Err(Error::InvalidFrame)
+        );
+        assert_eq!(
+            pipe.client.extension_frame_send(RELIABLE, &[1, 2, 3, 4, 5]),
+            Err(Error::InvalidFrame)
         );
 
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
-
+        assert_eq!(
+            pipe.client.extension_frame_send(RELIABLE, &[1, 2, 3, 4]),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.client.extension_frame_send(UNRELIABLE, &[5, 6, 7, 8]),
+            Ok(())
+        );
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Sending more data is forbidden.
-        let mut r = pipe.client.writable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        assert_eq!(
+            pipe.server.extension_frame_recv(),
+            Ok((RELIABLE, vec![1, 2, 3, 4]))
+        );
+        assert_eq!(
+            pipe.server.extension_frame_recv(),
+            Ok((UNRELIABLE, vec![5, 6, 7, 8]))
+        );
+        assert_eq!(pipe.server.extension_frame_recv(), Err(Error::Done));
 
         assert_eq!(
-            pipe.client.stream_send(4, b"bye", false),
-            Err(Error::StreamStopped(42))
+            pipe.client.extension_frame_send(RELIABLE, &[1, 2, 3, 4]),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.client.extension_frame_send(UNRELIABLE, &[5, 6, 7, 8]),
+            Ok(())
         );
 
-        // Server sends some data, without reading the incoming data, and closes
-        // the stream.
-        assert_eq!(pipe.server.stream_send(4, b"hello, world", true), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Packets are sent, but never received.
+        testing::emit_flight(&mut pipe.client).unwrap();
 
-        // Client reads the data.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        // Wait until timer expires. Since the RTT is very low, wait a bit more.
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
 
-        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((12, true)));
+        pipe.client.on_timeout();
 
-        // Stream is collected on both sides.
-        assert_eq!(pipe.client.streams.len(), 0);
-        assert_eq!(pipe.server.streams.len(), 0);
+        assert_eq!(pipe.advance(), Ok(()));
 
+        // Only the frame whose handler asked for it is retransmitted.
         assert_eq!(
-            pipe.server.stream_shutdown(4, Shutdown::Read, 0),
-            Err(Error::Done)
+            pipe.server.extension_frame_recv(),
+            Ok((RELIABLE, vec![1, 2, 3, 4]))
         );
+        assert_eq!(pipe.server.extension_frame_recv(), Err(Error::Done));
     }
 
     #[test]
-    fn stream_shutdown_read_after_fin() {
+    fn peer_close_draining() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.client.streams.len(), 1);
-        assert_eq!(pipe.server.streams.len(), 1);
-
-        // Server shuts down stream.
-        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
-
-        // Server has nothing to send.
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
-
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Server sends some data, without reading the incoming data, and closes
-        // the stream.
-        assert_eq!(pipe.server.stream_send(4, b"hello, world", true), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Client reads the data.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((12, true)));
-
-        // Stream is collected on both sides.
-        assert_eq!(pipe.client.streams.len(), 0);
-        assert_eq!(pipe.server.streams.len(), 0);
-
-        assert_eq!(
-            pipe.server.stream_shutdown(4, Shutdown::Read, 0),
-            Err(Error::Done)
-        );
-    }
-
-    #[test]
-    fn stream_shutdown_read_update_max_data() {
-        let mut buf = [0; 65535];
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
 
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
@@ -11477,157 +15844,153 @@ mod tests {
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
         config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(10000);
-        config.set_initial_max_stream_data_bidi_remote(10000);
-        config.set_initial_max_streams_bidi(10);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
         config.verify_peer(false);
+        config.set_clock(clock.clone());
 
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Leave some unacknowledged data in flight on the server.
+        assert_eq!(pipe.server.stream_send(1, b"hello", false), Ok(5));
+        testing::emit_flight(&mut pipe.server).unwrap();
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .loss_detection_timer()
+            .is_some());
 
-        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((1, false)));
-        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Read, 123), Ok(()));
+        let pto = pipe.server.paths.get_active().unwrap().recovery.pto();
 
-        assert_eq!(pipe.server.rx_data, 1);
-        assert_eq!(pipe.client.tx_data, 1);
-        assert_eq!(pipe.client.max_tx_data, 30);
+        // Frames following CONNECTION_CLOSE are not processed.
+        let frames = [
+            frame::Frame::ConnectionClose {
+                error_code: 0x1234,
+                frame_type: 0,
+                reason: b"bye".to_vec(),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            },
+        ];
 
-        assert_eq!(
-            pipe.client
-                .stream_send(0, &buf[..pipe.client.tx_cap], false),
-            Ok(29)
-        );
-        assert_eq!(pipe.advance(), Ok(()));
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        assert!(!pipe.server.stream_readable(0)); // nothing can be consumed
+        assert!(pipe.server.is_draining());
+        assert_eq!(pipe.server.readable().len(), 0);
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .loss_detection_timer()
+            .is_none());
 
-        // The client has increased its tx_data, and server has received it, so
-        // it increases flow control accordingly.
-        assert_eq!(pipe.client.tx_data, 30);
-        assert_eq!(pipe.server.rx_data, 30);
-        assert_eq!(pipe.client.tx_cap, 45);
-    }
+        // Nothing is sent and further packets are ignored.
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
 
-    #[test]
-    fn stream_shutdown_uni() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let recv_count = pipe.server.stats().recv;
 
-        // Exchange some data on uni streams.
-        assert_eq!(pipe.client.stream_send(2, b"hello, world", false), Ok(10));
-        assert_eq!(pipe.server.stream_send(3, b"hello, world", false), Ok(10));
-        assert_eq!(pipe.advance(), Ok(()));
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
+        assert_eq!(pipe.server.stats().recv, recv_count);
 
-        // Test local and remote shutdown.
-        assert_eq!(pipe.client.stream_shutdown(2, Shutdown::Write, 42), Ok(()));
-        assert_eq!(
-            pipe.client.stream_shutdown(2, Shutdown::Read, 42),
-            Err(Error::InvalidStreamState(2))
-        );
+        // The draining state lasts for three times the PTO.
+        assert_eq!(pipe.server.timeout(), Some(pto * 3));
 
-        assert_eq!(
-            pipe.client.stream_shutdown(3, Shutdown::Write, 42),
-            Err(Error::InvalidStreamState(3))
-        );
-        assert_eq!(pipe.client.stream_shutdown(3, Shutdown::Read, 42), Ok(()));
+        clock.advance(pto * 3);
+        pipe.server.on_timeout();
+
+        assert!(pipe.server.is_closed());
     }
 
     #[test]
-    fn stream_shutdown_write() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
+    fn limit_handshake_data() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert-big.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+        testing::process_flight(&mut pipe.server, flight).unwrap();
 
-        assert_eq!(pipe.client.streams.len(), 1);
-        assert_eq!(pipe.server.streams.len(), 1);
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
 
-        // Server sends some data.
-        assert_eq!(pipe.server.stream_send(4, b"goodbye, world", false), Ok(14));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(server_sent, client_sent * MAX_AMPLIFICATION_FACTOR);
+    }
 
-        // Server shuts down stream.
-        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Write, 42), Ok(()));
+    #[test]
+    fn custom_limit_handshake_data() {
+        const CUSTOM_AMPLIFICATION_FACTOR: usize = 2;
 
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), None);
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert-big.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_max_amplification_factor(CUSTOM_AMPLIFICATION_FACTOR);
 
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
-        let mut dummy = buf[..len].to_vec();
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+        testing::process_flight(&mut pipe.server, flight).unwrap();
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut dummy[..len]).unwrap();
-        let mut iter = frames.iter();
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::ResetStream {
-                stream_id: 4,
-                error_code: 42,
-                final_size: 14,
-            })
-        );
+        assert_eq!(server_sent, client_sent * CUSTOM_AMPLIFICATION_FACTOR);
+    }
 
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+    #[test]
+    fn stream() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Sending more data is forbidden.
-        assert_eq!(
-            pipe.server.stream_send(4, b"bye", false),
-            Err(Error::FinalSize)
-        );
-
-        // Client sends some data and closes the stream.
-        assert_eq!(pipe.client.stream_send(4, b"bye", true), Ok(3));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert!(!pipe.server.stream_finished(4));
 
-        // Server reads the data.
         let mut r = pipe.server.readable();
         assert_eq!(r.next(), Some(4));
         assert_eq!(r.next(), None);
 
-        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((15, true)));
-
-        // Client processes readable streams.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(
-            pipe.client.stream_recv(4, &mut buf),
-            Err(Error::StreamReset(42))
-        );
-
-        // Stream is collected on both sides.
-        assert_eq!(pipe.client.streams.len(), 0);
-        assert_eq!(pipe.server.streams.len(), 0);
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
+        assert_eq!(&b[..12], b"hello, world");
 
-        assert_eq!(
-            pipe.server.stream_shutdown(4, Shutdown::Write, 0),
-            Err(Error::Done)
-        );
+        assert!(pipe.server.stream_finished(4));
     }
 
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    /// Tests that shutting down a stream restores flow control for unsent data.
-    fn stream_shutdown_write_unsent_tx_cap() {
+    fn zero_rtt() {
+        let mut buf = [0; 65535];
+
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -11638,622 +16001,738 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        config.set_initial_max_data(15);
-        config.set_initial_max_stream_data_bidi_local(30);
-        config.set_initial_max_stream_data_bidi_remote(30);
-        config.set_initial_max_stream_data_uni(30);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
         config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(0);
+        config.enable_early_data();
         config.verify_peer(false);
 
+        // Perform initial handshake.
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data.
-        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Extract session,
+        let session = pipe.client.session().unwrap();
 
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut initial = buf[..len].to_vec();
+
+        assert!(pipe.client.is_in_early_data());
+
+        // Client sends 0-RTT data.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut zrtt = buf[..len].to_vec();
+
+        // Server receives packets.
+        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
+        assert!(pipe.server.is_in_early_data());
+
+        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+
+        // 0-RTT stream data is readable.
         let mut r = pipe.server.readable();
         assert_eq!(r.next(), Some(4));
         assert_eq!(r.next(), None);
 
         let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-
-        // Server sends some data.
-        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
+        assert_eq!(&b[..12], b"hello, world");
+    }
 
-        // Server buffers some data, until send capacity limit reached.
-        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
-        assert_eq!(
-            pipe.server.stream_send(4, b"hello", false),
-            Err(Error::Done)
-        );
+    #[test]
+    fn stream_send_on_32bit_arch() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(2_u64.pow(32) + 5);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(0);
+        config.verify_peer(false);
 
-        // Client shouldn't update flow control.
-        assert!(!pipe.client.should_update_max_data());
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server shuts down stream.
-        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Write, 42), Ok(()));
-        assert_eq!(pipe.advance(), Ok(()));
+        // In 32bit arch, send_capacity() should be min(2^32+5, cwnd),
+        // not min(5, cwnd)
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
 
-        // Server can now send more data (on a different stream).
-        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
-        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
-        assert_eq!(
-            pipe.server.stream_send(8, b"hello", false),
-            Err(Error::Done)
-        );
-        assert_eq!(pipe.advance(), Ok(()));
+        assert!(!pipe.server.stream_finished(4));
     }
 
     #[test]
-    /// Tests that the order of flushable streams scheduled on the wire is the
-    /// same as the order of `stream_send()` calls done by the application.
-    fn stream_round_robin() {
+    fn empty_stream_frame() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, false),
+        }];
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
 
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+        let mut readable = pipe.server.readable();
+        assert_eq!(readable.next(), Some(4));
 
-        let mut iter = frames.iter();
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, false)));
 
-        // Skip ACK frame.
-        iter.next();
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"", 5, true),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+
+        let mut readable = pipe.server.readable();
+        assert_eq!(readable.next(), Some(4));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((0, true)));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"", 15, true),
+        }];
 
+        let pkt_type = packet::Type::Short;
         assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"aaaaa", 0, false),
-            })
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FinalSize)
         );
+    }
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+    #[test]
+    fn stream_data_beyond_final_size() {
+        let mut buf = [0; 65535];
 
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"b", 5, false),
+        }];
 
         assert_eq!(
-            frames.first(),
-            Some(&frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaa", 0, false),
-            })
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FinalSize)
         );
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
         let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
         assert_eq!(
             frames.first(),
-            Some(&frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            Some(&frame::Frame::ConnectionClose {
+                error_code: WireErrorCode::FinalSizeError as u64,
+                frame_type: 0x0e,
+                reason: Vec::new(),
             })
         );
+
+        assert_eq!(
+            pipe.server.local_error().unwrap().wire_error_code(),
+            Some(WireErrorCode::FinalSizeError)
+        );
     }
 
     #[test]
-    /// Tests the readable iterator.
-    fn stream_readable() {
+    fn stream_data_beyond_final_size_and_flow_control() {
+        let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // No readable streams.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
-
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), None);
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
 
-        assert_eq!(pipe.advance(), Ok(()));
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        // Server received stream.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        // The data also exceeds the stream's flow control limit, but the final
+        // size violation takes precedence.
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"bbbbbbbbbb", 10, false),
+        }];
 
         assert_eq!(
-            pipe.server.stream_send(0, b"aaaaaaaaaaaaaaa", false),
-            Ok(15)
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FinalSize)
         );
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
-
-        // Client drains stream.
-        let mut b = [0; 15];
-        pipe.client.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), None);
-
-        // Server shuts down stream.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Read, 0), Ok(()));
+    }
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+    #[test]
+    fn stream_final_size_below_received_data() {
+        let mut buf = [0; 65535];
 
-        // Client creates multiple streams.
-        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaaaaaaa", 0, false),
+        }];
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.len(), 2);
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        assert!(r.next().is_some());
-        assert!(r.next().is_some());
-        assert!(r.next().is_none());
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"", 5, true),
+        }];
 
-        assert_eq!(r.len(), 0);
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FinalSize)
+        );
     }
 
     #[test]
-    /// Tests the writable iterator.
-    fn stream_writable() {
+    fn stream_data_beyond_reset_final_size() {
+        let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // No writable streams.
-        let mut w = pipe.client.writable();
-        assert_eq!(w.next(), None);
-
-        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+        let frames = [frame::Frame::ResetStream {
+            stream_id: 4,
+            error_code: 42,
+            final_size: 5,
+        }];
 
-        // Client created stream.
-        let mut w = pipe.client.writable();
-        assert_eq!(w.next(), Some(0));
-        assert_eq!(w.next(), None);
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        assert_eq!(pipe.advance(), Ok(()));
+        // Data within the final size is still accepted.
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaa", 0, false),
+        }];
 
-        // Server created stream.
-        let mut w = pipe.server.writable();
-        assert_eq!(w.next(), Some(0));
-        assert_eq!(w.next(), None);
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 3, false),
+        }];
 
         assert_eq!(
-            pipe.server.stream_send(0, b"aaaaaaaaaaaaaaa", false),
-            Ok(15)
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FinalSize)
         );
+    }
 
-        // Server stream is full.
-        let mut w = pipe.server.writable();
-        assert_eq!(w.next(), None);
+    #[test]
+    fn update_key_request() {
+        let mut b = [0; 15];
 
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Client drains stream.
-        let mut b = [0; 15];
-        pipe.client.stream_recv(0, &mut b).unwrap();
+        // Client sends message with key update request.
+        assert_eq!(pipe.client_update_key(), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server stream is writable again.
-        let mut w = pipe.server.writable();
-        assert_eq!(w.next(), Some(0));
-        assert_eq!(w.next(), None);
-
-        // Server shuts down stream.
-        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Write, 0), Ok(()));
+        // Ensure server updates key and it correctly decrypts the message.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, false)));
+        assert_eq!(&b[..5], b"hello");
 
-        let mut w = pipe.server.writable();
-        assert_eq!(w.next(), None);
+        // Ensure ACK for key update.
+        assert!(
+            pipe.server.pkt_num_spaces[packet::Epoch::Application]
+                .key_update
+                .as_ref()
+                .unwrap()
+                .update_acked
+        );
 
-        // Client creates multiple streams.
-        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        // Server sends message with the new key.
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
+        // Ensure update key is completed and client can decrypt packet.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+        assert_eq!(pipe.client.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"world");
+    }
+
+    #[test]
+    fn update_key_request_twice_error() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
         assert_eq!(pipe.advance(), Ok(()));
 
-        let mut w = pipe.server.writable();
-        assert_eq!(w.len(), 2);
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"hello", 0, false),
+        }];
 
-        assert!(w.next().is_some());
-        assert!(w.next().is_some());
-        assert!(w.next().is_none());
+        // Client sends stream frame with key update request.
+        assert_eq!(pipe.client_update_key(), Ok(()));
+        let written = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
 
-        assert_eq!(w.len(), 0);
+        // Server correctly decode with new key.
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
 
-        // Server finishes stream.
-        assert_eq!(pipe.server.stream_send(8, b"aaaaa", true), Ok(5));
+        // Client sends stream frame with another key update request before server
+        // ACK.
+        assert_eq!(pipe.client_update_key(), Ok(()));
+        let written = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
 
-        let mut w = pipe.server.writable();
-        assert_eq!(w.next(), Some(4));
-        assert_eq!(w.next(), None);
+        // Check server correctly closes the connection with a key update error
+        // for the peer.
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Err(Error::KeyUpdate));
     }
 
     #[test]
-    fn stream_writable_blocked() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(70);
-        config.set_initial_max_stream_data_bidi_local(150000);
-        config.set_initial_max_stream_data_bidi_remote(150000);
-        config.set_initial_max_stream_data_uni(150000);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    fn initiate_key_update() {
+        let mut b = [0; 15];
 
-        // Client creates stream and sends some data.
-        let send_buf = [0; 35];
-        assert_eq!(pipe.client.stream_send(0, &send_buf, false), Ok(35));
+        let mut pipe = testing::Pipe::new().unwrap();
 
-        // Stream is still writable as it still has capacity.
-        assert_eq!(pipe.client.stream_writable_next(), Some(0));
-        assert_eq!(pipe.client.stream_writable_next(), None);
+        // Keys can't be updated before the handshake is confirmed.
+        assert_eq!(pipe.client.initiate_key_update(), Err(Error::InvalidState));
 
-        // Client fills stream, which becomes unwritable due to connection
-        // capacity.
-        let send_buf = [0; 36];
-        assert_eq!(pipe.client.stream_send(0, &send_buf, false), Ok(35));
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_writable_next(), None);
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
 
-        assert_eq!(pipe.client.tx_cap, 0);
+        // The previous update needs to complete first.
+        assert_eq!(pipe.client.initiate_key_update(), Err(Error::InvalidState));
 
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        let mut b = [0; 70];
-        pipe.server.stream_recv(0, &mut b).unwrap();
+        // Server follows the update and decrypts the message.
+        assert_eq!(pipe.server.key_phase, pipe.client.key_phase);
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, false)));
+        assert_eq!(&b[..5], b"hello");
 
+        // Server replies with the new keys.
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // The connection capacity has increased and the stream is now writable
-        // again.
-        assert_ne!(pipe.client.tx_cap, 0);
+        assert_eq!(pipe.client.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"world");
 
-        assert_eq!(pipe.client.stream_writable_next(), Some(0));
-        assert_eq!(pipe.client.stream_writable_next(), None);
+        // Client learned when the server started using the new keys.
+        assert_ne!(
+            pipe.client.pkt_num_spaces[packet::Epoch::Application]
+                .key_update
+                .as_ref()
+                .unwrap()
+                .pn_on_update,
+            u64::MAX
+        );
     }
 
     #[test]
-    /// Tests that we don't exceed the per-connection flow control limit set by
-    /// the peer.
-    fn flow_control_limit_send() {
+    /// Tests that receiving a MAX_STREAM_DATA frame for a receive-only
+    /// unidirectional stream is forbidden.
+    fn max_stream_data_receive_uni() {
+        let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(
-            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
-            Ok(15)
-        );
+        // Client opens unidirectional stream.
+        assert_eq!(pipe.client.stream_send(2, b"hello", false), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
+
+        // Client sends MAX_STREAM_DATA on local unidirectional stream.
+        let frames = [frame::Frame::MaxStreamData {
+            stream_id: 2,
+            max: 1024,
+        }];
+
+        let pkt_type = packet::Type::Short;
         assert_eq!(
-            pipe.client.stream_send(4, b"aaaaaaaaaaaaaaa", false),
-            Ok(15)
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidStreamState(2)),
         );
-        assert_eq!(pipe.advance(), Ok(()));
-        assert_eq!(pipe.client.stream_send(8, b"a", false), Err(Error::Done));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.server.readable();
-        assert!(r.next().is_some());
-        assert!(r.next().is_some());
-        assert!(r.next().is_none());
     }
 
     #[test]
-    /// Tests that invalid packets received before any other valid ones cause
-    /// the server to close the connection immediately.
-    fn invalid_initial_server() {
+    fn empty_payload() {
         let mut buf = [0; 65535];
-        let mut pipe = testing::Pipe::new().unwrap();
-
-        let frames = [frame::Frame::Padding { len: 10 }];
-
-        let written = testing::encode_pkt(
-            &mut pipe.client,
-            packet::Type::Initial,
-            &frames,
-            &mut buf,
-        )
-        .unwrap();
-
-        // Corrupt the packets's last byte to make decryption fail (the last
-        // byte is part of the AEAD tag, so changing it means that the packet
-        // cannot be authenticated during decryption).
-        buf[written - 1] = !buf[written - 1];
 
-        assert_eq!(pipe.server.timeout(), None);
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
+        // Send a packet with no frames.
+        let pkt_type = packet::Type::Short;
         assert_eq!(
-            pipe.server_recv(&mut buf[..written]),
-            Err(Error::CryptoFail)
+            pipe.send_pkt_to_server(pkt_type, &[], &mut buf),
+            Err(Error::InvalidPacket)
         );
-
-        assert!(pipe.server.is_closed());
     }
 
     #[test]
-    /// Tests that invalid Initial packets received to cause
-    /// the client to close the connection immediately.
-    fn invalid_initial_client() {
+    fn min_payload() {
         let mut buf = [0; 65535];
-        let mut pipe = testing::Pipe::new().unwrap();
-
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-        // Server sends initial flight.
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(1200));
+        let mut pipe = testing::Pipe::new().unwrap();
 
-        let frames = [frame::Frame::Padding { len: 10 }];
+        // Send a non-ack-eliciting packet.
+        let frames = [frame::Frame::Padding { len: 4 }];
 
-        let written = testing::encode_pkt(
-            &mut pipe.server,
-            packet::Type::Initial,
-            &frames,
-            &mut buf,
-        )
-        .unwrap();
+        let pkt_type = packet::Type::Initial;
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
 
-        // Corrupt the packets's last byte to make decryption fail (the last
-        // byte is part of the AEAD tag, so changing it means that the packet
-        // cannot be authenticated during decryption).
-        buf[written - 1] = !buf[written - 1];
+        let initial_path = pipe
+            .server
+            .paths
+            .get_active()
+            .expect("initial path not found");
 
-        // Client will ignore invalid packet.
-        assert_eq!(pipe.client_recv(&mut buf[..written]), Ok(71));
+        assert_eq!(initial_path.max_send_bytes, 195);
 
-        // The connection should be alive...
-        assert!(!pipe.client.is_closed());
+        // Force server to send a single PING frame.
+        pipe.server
+            .paths
+            .get_active_mut()
+            .expect("no active path")
+            .recovery
+            .inc_loss_probes(packet::Epoch::Initial);
 
-        // ...and the idle timeout should be armed.
-        assert!(pipe.client.idle_timer.is_some());
+        let initial_path = pipe
+            .server
+            .paths
+            .get_active_mut()
+            .expect("initial path not found");
+
+        // Artificially limit the amount of bytes the server can send.
+        initial_path.max_send_bytes = 60;
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that packets with invalid payload length received before any other
-    /// valid packet cause the server to close the connection immediately.
-    fn invalid_initial_payload() {
+    fn flow_control_limit() {
         let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let mut b = octets::OctetsMut::with_slice(&mut buf);
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+        ];
 
-        let epoch = packet::Type::Initial.to_epoch().unwrap();
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
 
-        let pn = 0;
-        let pn_len = packet::pkt_num_len(pn, 0);
+    #[test]
+    fn flow_control_limit_dup() {
+        let mut buf = [0; 65535];
 
-        let dcid = pipe.client.destination_id();
-        let scid = pipe.client.source_id();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let hdr = Header {
-            ty: packet::Type::Initial,
-            version: pipe.client.version,
-            dcid: ConnectionId::from_ref(&dcid),
-            scid: ConnectionId::from_ref(&scid),
-            pkt_num: 0,
-            pkt_num_len: pn_len,
-            token: pipe.client.token.clone(),
-            versions: None,
-            key_phase: false,
-        };
+        let frames = [
+            // One byte less than stream limit.
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaa", 0, false),
+            },
+            // Same stream, but one byte more.
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+        ];
 
-        hdr.to_bytes(&mut b).unwrap();
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+    }
 
-        // Payload length is invalid!!!
-        let payload_len = 4096;
+    #[test]
+    fn flow_control_update() {
+        let mut buf = [0; 65535];
 
-        let len = pn_len + payload_len;
-        b.put_varint(len as u64).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        packet::encode_pkt_num(pn, pn_len, &mut b).unwrap();
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+        ];
 
-        let payload_offset = b.off();
+        let pkt_type = packet::Type::Short;
 
-        let frames = [frame::Frame::Padding { len: 10 }];
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        for frame in &frames {
-            frame.to_bytes(&mut b).unwrap();
-        }
+        pipe.server.stream_recv(0, &mut buf).unwrap();
+        pipe.server.stream_recv(4, &mut buf).unwrap();
 
-        let space = &mut pipe.client.pkt_num_spaces[epoch];
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"a", 1, false),
+        }];
 
-        // Use correct payload length when encrypting the packet.
-        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
 
-        let aead = space.crypto_seal.as_ref().unwrap();
+        assert!(len > 0);
 
-        let written = packet::encrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            payload_offset,
-            None,
-            aead,
-        )
-        .unwrap();
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        let mut iter = frames.iter();
 
-        assert_eq!(pipe.server.timeout(), None);
+        // Ignore ACK.
+        iter.next().unwrap();
 
         assert_eq!(
-            pipe.server_recv(&mut buf[..written]),
-            Err(Error::InvalidPacket)
+            iter.next(),
+            Some(&frame::Frame::MaxStreamData {
+                stream_id: 0,
+                max: 30
+            })
         );
-
-        assert!(pipe.server.is_closed());
+        assert_eq!(iter.next(), Some(&frame::Frame::MaxData { max: 61 }));
     }
 
     #[test]
-    /// Tests that invalid packets don't cause the connection to be closed.
-    fn invalid_packet() {
-        let mut buf = [0; 65535];
-
+    /// Tests that flow control is properly updated even when a stream is shut
+    /// down.
+    fn flow_control_drain() {
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [frame::Frame::Padding { len: 10 }];
+        // Client opens a stream and sends some data.
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let written = testing::encode_pkt(
-            &mut pipe.client,
-            packet::Type::Short,
-            &frames,
-            &mut buf,
-        )
-        .unwrap();
+        // Server receives data, without reading it.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
 
-        // Corrupt the packets's last byte to make decryption fail (the last
-        // byte is part of the AEAD tag, so changing it means that the packet
-        // cannot be authenticated during decryption).
-        buf[written - 1] = !buf[written - 1];
+        // In the meantime, client sends more data.
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", true), Ok(5));
 
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(8, b"aaaaa", true), Ok(5));
 
-        // Corrupt the packets's first byte to make the header fail decoding.
-        buf[0] = 255;
+        // Server shuts down one stream.
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
 
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Flush connection.
+        assert_eq!(pipe.advance(), Ok(()));
     }
 
     #[test]
-    fn recv_empty_buffer() {
+    /// Tests that data discarded when a stream is shut down for reading is
+    /// credited back to the connection-level flow control window.
+    fn flow_control_shutdown_read_credit() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.server_recv(&mut buf[..0]), Err(Error::BufferTooShort));
+        // Client uses up the whole connection window.
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        // Server reads one stream, and shuts down the other without reading.
+        assert_eq!(pipe.server.stream_recv(8, &mut buf), Ok((15, false)));
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        assert!(frames.contains(&frame::Frame::MaxData { max: 60 }));
     }
 
     #[test]
-    fn stop_sending_before_flushed_packets() {
-        let mut b = [0; 15];
-
+    fn stream_flow_control_limit_bidi() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data, and closes stream.
-        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaaa", 0, true),
+        }];
 
-        // Server gets data.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
 
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
-        assert!(pipe.server.stream_finished(0));
+    #[test]
+    fn stream_flow_control_limit_uni() {
+        let mut buf = [0; 65535];
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server sends data, until blocked.
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        let frames = [frame::Frame::Stream {
+            stream_id: 2,
+            data: stream::RangeBuf::from(b"aaaaaaaaaaa", 0, true),
+        }];
 
-        while pipe.server.stream_send(0, b"world", false) != Err(Error::Done) {}
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
 
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), None);
+    #[test]
+    fn stream_flow_control_update() {
+        let mut buf = [0; 65535];
 
-        // Client sends STOP_SENDING.
-        let frames = [frame::Frame::StopSending {
-            stream_id: 0,
-            error_code: 42,
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaaaaaa", 0, false),
         }];
 
         let pkt_type = packet::Type::Short;
+
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        pipe.server.stream_recv(4, &mut buf).unwrap();
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"a", 9, false),
+        }];
+
         let len = pipe
             .send_pkt_to_server(pkt_type, &frames, &mut buf)
             .unwrap();
 
-        // Server sent a RESET_STREAM frame in response.
+        assert!(len > 0);
+
         let frames =
             testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-
         let mut iter = frames.iter();
 
-        // Skip ACK frame.
-        iter.next();
+        // Ignore ACK.
+        iter.next().unwrap();
 
         assert_eq!(
             iter.next(),
-            Some(&frame::Frame::ResetStream {
-                stream_id: 0,
-                error_code: 42,
-                final_size: 0,
+            Some(&frame::Frame::MaxStreamData {
+                stream_id: 4,
+                max: 24,
             })
         );
-
-        // Stream is writable, but writing returns an error.
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(
-            pipe.server.stream_send(0, b"world", true),
-            Err(Error::StreamStopped(42)),
-        );
-
-        assert_eq!(pipe.server.streams.len(), 1);
-
-        // Client acks RESET_STREAM frame.
-        let mut ranges = ranges::RangeSet::default();
-        ranges.insert(0..6);
-
-        let frames = [frame::Frame::ACK {
-            ack_delay: 15,
-            ranges,
-            ecn_counts: None,
-        }];
-
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
-
-        // Client has ACK'd the RESET_STREAM so the stream is collected.
-        assert_eq!(pipe.server.streams.len(), 0);
     }
 
     #[test]
-    fn reset_before_flushed_packets() {
-        let mut b = [0; 15];
+    fn stream_flow_control_update_threshold() {
+        let mut buf = [0; 65535];
 
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
@@ -12266,398 +16745,3616 @@ mod tests {
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
         config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(5);
+        config.set_initial_max_stream_data_bidi_local(15);
         config.set_initial_max_stream_data_bidi_remote(15);
         config.set_initial_max_streams_bidi(3);
-        config.verify_peer(false);
+        config.set_stream_window_update_threshold(0.9).unwrap();
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends some data, and closes stream.
-        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaa", false), Ok(9));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server gets data.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
-
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
-        assert!(pipe.server.stream_finished(0));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
-
-        // Server sends data and is blocked by small stream flow control.
-        let mut r = pipe.server.writable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
+        // Data that was received but not read doesn't extend the credit.
+        assert!(!pipe.server.streams.get(0).unwrap().recv.almost_full());
 
-        assert_eq!(pipe.server.stream_send(0, b"helloworld", false), Ok(5));
+        // Reading less than the threshold doesn't extend the credit either.
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((9, false)));
+        assert!(!pipe.server.streams.has_almost_full());
         assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.streams.get(0).unwrap().recv.max_data(), 15);
 
-        // Client reads to give flow control back.
-        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((5, false)));
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaa", false), Ok(6));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server writes stream data and resets the stream before sending a
-        // packet.
-        assert_eq!(pipe.server.stream_send(0, b"world", false), Ok(5));
-        pipe.server.stream_shutdown(0, Shutdown::Write, 42).unwrap();
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((6, false)));
+        assert!(pipe.server.streams.has_almost_full());
         assert_eq!(pipe.advance(), Ok(()));
-
-        // Client has ACK'd the RESET_STREAM so the stream is collected.
-        assert_eq!(pipe.server.streams.len(), 0);
+        assert_eq!(pipe.server.streams.get(0).unwrap().recv.max_data(), 30);
     }
 
     #[test]
-    /// Tests that the MAX_STREAMS frame is sent for bidirectional streams.
-    fn stream_limit_update_bidi() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(10);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(0);
-        config.verify_peer(false);
+    fn stream_flow_control_update_threshold_invalid() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        for v in [0.0, 1.0, -0.1, 1.1, f64::NAN] {
+            assert_eq!(
+                config.set_stream_window_update_threshold(v),
+                Err(Error::InvalidState)
+            );
+        }
+
+        assert_eq!(config.set_stream_window_update_threshold(0.01), Ok(()));
+        assert_eq!(config.set_stream_window_update_threshold(0.99), Ok(()));
+    }
+
+    #[test]
+    fn stream_open() {
+        let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_open_bidi(), Ok(0));
+        assert_eq!(pipe.client.stream_open_uni(), Ok(2));
+        assert_eq!(pipe.server.stream_open_bidi(), Ok(1));
+        assert_eq!(pipe.server.stream_open_uni(), Ok(3));
 
+        // IDs already used directly by the application are skipped.
         assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_open_bidi(), Ok(8));
+        assert_eq!(pipe.client.stream_open_bidi(), Err(Error::StreamLimit));
 
-        assert_eq!(pipe.client.stream_send(4, b"b", true), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_open_uni(), Ok(6));
+        assert_eq!(pipe.client.stream_open_uni(), Ok(10));
+        assert_eq!(pipe.client.stream_open_uni(), Err(Error::StreamLimit));
 
-        assert_eq!(pipe.client.stream_send(0, b"b", true), Ok(1));
+        // Opened streams can be written to.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.client.stream_send(2, b"hello", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        pipe.server.stream_recv(4, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut r = pipe.server.readable().collect::<Vec<u64>>();
+        r.sort();
+        assert_eq!(r, [0, 2, 4]);
+    }
 
-        // Server sends stream data, with fin.
-        assert_eq!(pipe.server.stream_send(0, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+    #[test]
+    fn stream_send_peer_initiated() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.server.stream_send(4, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Streams that the peer hasn't opened can't be created locally.
+        assert_eq!(
+            pipe.server.stream_send(0, b"a", false),
+            Err(Error::InvalidStreamState(0))
+        );
+        assert_eq!(
+            pipe.client.stream_send(5, b"a", false),
+            Err(Error::InvalidStreamState(5))
+        );
+        assert_eq!(
+            pipe.client.stream_send(3, b"a", false),
+            Err(Error::InvalidStreamState(3))
+        );
 
-        assert_eq!(pipe.server.stream_send(4, b"b", true), Ok(1));
+        // Once opened by the peer, bidirectional streams can be written to.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.stream_send(0, b"a", false), Ok(1));
+    }
 
-        assert_eq!(pipe.server.stream_send(0, b"b", true), Ok(1));
+    #[test]
+    fn stream_left_bidi() {
+        let mut buf = [0; 65535];
 
-        // Server sends MAX_STREAMS.
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client tries to create new streams.
-        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(3, pipe.client.peer_streams_left_bidi());
+        assert_eq!(3, pipe.server.peer_streams_left_bidi());
 
-        assert_eq!(pipe.client.stream_send(12, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        pipe.server.stream_send(1, b"a", false).ok();
+        assert_eq!(2, pipe.server.peer_streams_left_bidi());
+        pipe.server.stream_send(5, b"a", false).ok();
+        assert_eq!(1, pipe.server.peer_streams_left_bidi());
 
-        assert_eq!(pipe.client.stream_send(16, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        pipe.server.stream_send(9, b"a", false).ok();
+        assert_eq!(0, pipe.server.peer_streams_left_bidi());
 
-        assert_eq!(
-            pipe.client.stream_send(20, b"a", false),
-            Err(Error::StreamLimit)
-        );
+        let frames = [frame::Frame::MaxStreamsBidi { max: MAX_STREAM_ID }];
 
-        assert_eq!(pipe.server.readable().len(), 3);
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        assert_eq!(MAX_STREAM_ID - 3, pipe.server.peer_streams_left_bidi());
     }
 
     #[test]
-    /// Tests that the MAX_STREAMS frame is sent for unidirectional streams.
-    fn stream_limit_update_uni() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(10);
-        config.set_initial_max_streams_bidi(0);
-        config.set_initial_max_streams_uni(3);
-        config.verify_peer(false);
+    fn stream_left_uni() {
+        let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(2, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(3, pipe.client.peer_streams_left_uni());
+        assert_eq!(3, pipe.server.peer_streams_left_uni());
 
-        assert_eq!(pipe.client.stream_send(6, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        pipe.server.stream_send(3, b"a", false).ok();
+        assert_eq!(2, pipe.server.peer_streams_left_uni());
+        pipe.server.stream_send(7, b"a", false).ok();
+        assert_eq!(1, pipe.server.peer_streams_left_uni());
 
-        assert_eq!(pipe.client.stream_send(6, b"b", true), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        pipe.server.stream_send(11, b"a", false).ok();
+        assert_eq!(0, pipe.server.peer_streams_left_uni());
 
-        assert_eq!(pipe.client.stream_send(2, b"b", true), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        let frames = [frame::Frame::MaxStreamsUni { max: MAX_STREAM_ID }];
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(2, &mut b).unwrap();
-        pipe.server.stream_recv(6, &mut b).unwrap();
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        // Server sends MAX_STREAMS.
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(MAX_STREAM_ID - 3, pipe.server.peer_streams_left_uni());
+    }
 
-        // Client tries to create new streams.
-        assert_eq!(pipe.client.stream_send(10, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+    #[test]
+    fn stream_limit_bidi() {
+        let mut buf = [0; 65535];
 
-        assert_eq!(pipe.client.stream_send(14, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(18, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 12,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 16,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 20,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 24,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 28,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+        ];
 
+        let pkt_type = packet::Type::Short;
         assert_eq!(
-            pipe.client.stream_send(22, b"a", false),
-            Err(Error::StreamLimit)
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::StreamLimit),
         );
-
-        assert_eq!(pipe.server.readable().len(), 3);
     }
 
     #[test]
-    /// Tests that the stream's fin flag is properly flushed even if there's no
-    /// data in the buffer, and that the buffer becomes readable on the other
-    /// side.
-    fn stream_zero_length_fin() {
+    fn stream_limit_max_bidi() {
+        let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(
-            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
-            Ok(15)
-        );
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert!(r.next().is_none());
-
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Client sends zero-length frame.
-        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Stream should be readable on the server after receiving empty fin.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert!(r.next().is_none());
+        let frames = [frame::Frame::MaxStreamsBidi { max: MAX_STREAM_ID }];
 
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        // Client sends zero-length frame (again).
-        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
+        let frames = [frame::Frame::MaxStreamsBidi {
+            max: MAX_STREAM_ID + 1,
+        }];
 
-        // Stream should _not_ be readable on the server after receiving empty
-        // fin, because it was already finished.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame),
+        );
     }
 
     #[test]
-    /// Tests that the stream's fin flag is properly flushed even if there's no
-    /// data in the buffer, that the buffer becomes readable on the other
-    /// side and stays readable even if the stream is fin'd locally.
-    fn stream_zero_length_fin_deferred_collection() {
+    fn stream_limit_uni() {
+        let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 2,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 6,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 10,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 14,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 18,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 22,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 26,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
         assert_eq!(
-            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
-            Ok(15)
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::StreamLimit),
         );
-        assert_eq!(pipe.advance(), Ok(()));
-
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert!(r.next().is_none());
-
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Client sends zero-length frame.
-        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Server sends zero-length frame.
-        assert_eq!(pipe.server.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Stream should be readable on the server after receiving empty fin.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert!(r.next().is_none());
-
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Client sends zero-length frame (again).
-        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Stream should _not_ be readable on the server after receiving empty
-        // fin, because it was already finished.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
-
-        // Stream _is_readable on the client side.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(0));
-
-        pipe.client.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
-
-        // Stream is completed and _is not_ readable.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), None);
     }
 
     #[test]
-    /// Tests that the stream gets created with stream_send() even if there's
-    /// no data in the buffer and the fin flag is not set.
-    fn stream_zero_length_non_fin() {
+    fn stream_limit_max_uni() {
+        let mut buf = [0; 65535];
+
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(0, b"", false), Ok(0));
+        let frames = [frame::Frame::MaxStreamsUni { max: MAX_STREAM_ID }];
 
-        // The stream now should have been created.
-        assert_eq!(pipe.client.streams.len(), 1);
-        assert_eq!(pipe.advance(), Ok(()));
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
 
-        // Sending an empty non-fin should not change any stream state on the
-        // other side.
-        let mut r = pipe.server.readable();
-        assert!(r.next().is_none());
+        let frames = [frame::Frame::MaxStreamsUni {
+            max: MAX_STREAM_ID + 1,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame),
+        );
     }
 
     #[test]
-    /// Tests that completed streams are garbage collected.
-    fn collect_streams() {
+    fn stream_left_reset_bidi() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.streams.len(), 0);
-        assert_eq!(pipe.server.streams.len(), 0);
+        assert_eq!(3, pipe.client.peer_streams_left_bidi());
+        assert_eq!(3, pipe.server.peer_streams_left_bidi());
 
-        assert_eq!(pipe.client.stream_send(0, b"aaaaa", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        pipe.client.stream_send(0, b"a", false).ok();
+        assert_eq!(2, pipe.client.peer_streams_left_bidi());
+        pipe.client.stream_send(4, b"a", false).ok();
+        assert_eq!(1, pipe.client.peer_streams_left_bidi());
+        pipe.client.stream_send(8, b"a", false).ok();
+        assert_eq!(0, pipe.client.peer_streams_left_bidi());
 
-        assert!(!pipe.client.stream_finished(0));
+        // Client resets the stream.
+        pipe.client
+            .stream_shutdown(0, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        assert_eq!(0, pipe.client.peer_streams_left_bidi());
+        let mut r = pipe.server.readable();
+        assert_eq!(Some(0), r.next());
+        assert_eq!(Some(4), r.next());
+        assert_eq!(Some(8), r.next());
+        assert_eq!(None, r.next());
+
+        assert_eq!(
+            pipe.server.stream_recv(0, &mut buf),
+            Err(Error::StreamReset(1001))
+        );
+
+        let mut r = pipe.server.readable();
+        assert_eq!(Some(4), r.next());
+        assert_eq!(Some(8), r.next());
+        assert_eq!(None, r.next());
+
+        // Server resets the stream in reaction.
+        pipe.server
+            .stream_shutdown(0, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        assert_eq!(1, pipe.client.peer_streams_left_bidi());
+
+        // Repeat for the other 2 streams
+        pipe.client
+            .stream_shutdown(4, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.client
+            .stream_shutdown(8, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        let mut r = pipe.server.readable();
+        assert_eq!(Some(4), r.next());
+        assert_eq!(Some(8), r.next());
+        assert_eq!(None, r.next());
+
+        assert_eq!(
+            pipe.server.stream_recv(4, &mut buf),
+            Err(Error::StreamReset(1001))
+        );
+
+        assert_eq!(
+            pipe.server.stream_recv(8, &mut buf),
+            Err(Error::StreamReset(1001))
+        );
+
+        let mut r = pipe.server.readable();
+        assert_eq!(None, r.next());
+
+        pipe.server
+            .stream_shutdown(4, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.server
+            .stream_shutdown(8, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        assert_eq!(3, pipe.client.peer_streams_left_bidi());
+    }
+
+    #[test]
+    fn stream_reset_counts() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        pipe.client.stream_send(0, b"a", false).ok();
+        pipe.client.stream_send(2, b"a", false).ok();
+        pipe.client.stream_send(4, b"a", false).ok();
+        pipe.client.stream_send(8, b"a", false).ok();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.reset_stream_count_local, 0);
+
+        // Client resets the stream.
+        pipe.client
+            .stream_shutdown(0, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.reset_stream_count_local, 1);
+        assert_eq!(stats.reset_stream_count_remote, 0);
+        let stats = pipe.server.stats();
+        assert_eq!(stats.reset_stream_count_local, 0);
+        assert_eq!(stats.reset_stream_count_remote, 1);
+
+        // Server resets the stream in reaction.
+        pipe.server
+            .stream_shutdown(0, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.reset_stream_count_local, 1);
+        assert_eq!(stats.reset_stream_count_remote, 1);
+        let stats = pipe.server.stats();
+        assert_eq!(stats.reset_stream_count_local, 1);
+        assert_eq!(stats.reset_stream_count_remote, 1);
+
+        // Repeat for the other streams
+        pipe.client
+            .stream_shutdown(2, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.client
+            .stream_shutdown(4, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.client
+            .stream_shutdown(8, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        pipe.server
+            .stream_shutdown(4, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.server
+            .stream_shutdown(8, Shutdown::Write, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.reset_stream_count_local, 4);
+        assert_eq!(stats.reset_stream_count_remote, 3);
+        let stats = pipe.server.stats();
+        assert_eq!(stats.reset_stream_count_local, 3);
+        assert_eq!(stats.reset_stream_count_remote, 4);
+    }
+
+    #[test]
+    fn stream_stop_counts() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        pipe.client.stream_send(0, b"a", false).ok();
+        pipe.client.stream_send(2, b"a", false).ok();
+        pipe.client.stream_send(4, b"a", false).ok();
+        pipe.client.stream_send(8, b"a", false).ok();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.reset_stream_count_local, 0);
+
+        // Server stops the stream and client automatically resets.
+        pipe.server
+            .stream_shutdown(0, Shutdown::Read, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.stopped_stream_count_local, 0);
+        assert_eq!(stats.stopped_stream_count_remote, 1);
+        assert_eq!(stats.reset_stream_count_local, 1);
+        assert_eq!(stats.reset_stream_count_remote, 0);
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.stopped_stream_count_local, 1);
+        assert_eq!(stats.stopped_stream_count_remote, 0);
+        assert_eq!(stats.reset_stream_count_local, 0);
+        assert_eq!(stats.reset_stream_count_remote, 1);
+
+        // Repeat for the other streams
+        pipe.server
+            .stream_shutdown(2, Shutdown::Read, 1001)
+            .unwrap();
+        pipe.server
+            .stream_shutdown(4, Shutdown::Read, 1001)
+            .unwrap();
+        pipe.server
+            .stream_shutdown(8, Shutdown::Read, 1001)
+            .unwrap();
+        pipe.advance().unwrap();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.stopped_stream_count_local, 0);
+        assert_eq!(stats.stopped_stream_count_remote, 4);
+        assert_eq!(stats.reset_stream_count_local, 4);
+        assert_eq!(stats.reset_stream_count_remote, 0);
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.stopped_stream_count_local, 4);
+        assert_eq!(stats.stopped_stream_count_remote, 0);
+        assert_eq!(stats.reset_stream_count_local, 0);
+        assert_eq!(stats.reset_stream_count_remote, 4);
+    }
+
+    #[test]
+    fn streams_blocked_max_bidi() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::StreamsBlockedBidi {
+            limit: MAX_STREAM_ID,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        let frames = [frame::Frame::StreamsBlockedBidi {
+            limit: MAX_STREAM_ID + 1,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame),
+        );
+    }
+
+    #[test]
+    fn streams_blocked_max_uni() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::StreamsBlockedUni {
+            limit: MAX_STREAM_ID,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        let frames = [frame::Frame::StreamsBlockedUni {
+            limit: MAX_STREAM_ID + 1,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidFrame),
+        );
+    }
+
+    #[test]
+    fn stream_data_overlap() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"bbbbb", 3, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"ccccc", 6, false),
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((11, false)));
+        assert_eq!(&b[..11], b"aaaaabbbccc");
+    }
+
+    #[test]
+    fn stream_data_overlap_with_reordering() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"ccccc", 6, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"bbbbb", 3, false),
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((11, false)));
+        assert_eq!(&b[..11], b"aaaaabccccc");
+    }
+
+    #[test]
+    /// Tests that receiving a valid RESET_STREAM frame when all data has
+    /// already been read, notifies the application.
+    fn reset_stream_data_recvd() {
+        let mut b = [0; 15];
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server gets data and sends data back, closing stream.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, false)));
+        assert!(!pipe.server.stream_finished(0));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((0, true)));
+        assert!(pipe.client.stream_finished(0));
+
+        // Client sends RESET_STREAM, closing stream.
+        let frames = [frame::Frame::ResetStream {
+            stream_id: 0,
+            error_code: 42,
+            final_size: 5,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+
+        // Server is notified of stream readability, due to reset.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_recv(0, &mut b),
+            Err(Error::StreamReset(42))
+        );
+
+        assert!(pipe.server.stream_finished(0));
+
+        // Sending RESET_STREAM again shouldn't make stream readable again.
+        pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    /// Tests that receiving a valid RESET_STREAM frame when all data has _not_
+    /// been read, discards all buffered data and notifies the application.
+    fn reset_stream_data_not_recvd() {
+        let mut b = [0; 15];
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(0, b"h", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server gets data and sends data back, closing stream.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((1, false)));
+        assert!(!pipe.server.stream_finished(0));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((0, true)));
+        assert!(pipe.client.stream_finished(0));
+
+        // Client sends RESET_STREAM, closing stream.
+        let frames = [frame::Frame::ResetStream {
+            stream_id: 0,
+            error_code: 42,
+            final_size: 5,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+
+        // Server is notified of stream readability, due to reset.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_recv(0, &mut b),
+            Err(Error::StreamReset(42))
+        );
+
+        assert!(pipe.server.stream_finished(0));
+
+        // Sending RESET_STREAM again shouldn't make stream readable again.
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    /// Tests that RESET_STREAM frames exceeding the connection-level flow
+    /// control limit cause an error.
+    fn reset_stream_flow_control() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 0,
+                final_size: 15,
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
+
+    #[test]
+    /// Tests that RESET_STREAM frames exceeding the stream-level flow control
+    /// limit cause an error.
+    fn reset_stream_flow_control_stream() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 0,
+                final_size: 16, // Past stream's flow control limit.
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
+
+    #[test]
+    /// Tests that data discarded by RESET_STREAM frames is credited back to
+    /// the connection-level flow control window.
+    fn reset_stream_flow_control_credit() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data on two streams, and resets them before the
+        // server reads any of it, using up the whole connection window.
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            },
+            frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 0,
+                final_size: 15,
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"aaaaaaaaaa", 0, false),
+            },
+            frame::Frame::ResetStream {
+                stream_id: 8,
+                error_code: 0,
+                final_size: 15,
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        // Server raises the connection limit, as the data will never be read.
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        assert!(frames.contains(&frame::Frame::MaxData { max: 60 }));
+
+        // Client can send more data without exceeding the limit.
+        let frames = [frame::Frame::Stream {
+            stream_id: 0,
+            data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+        }];
+
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        // Duplicate RESET_STREAM frames don't credit the window again.
+        let frames = [frame::Frame::ResetStream {
+            stream_id: 4,
+            error_code: 0,
+            final_size: 15,
+        }];
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        assert!(!frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::MaxData { .. })));
+    }
+
+    #[test]
+    fn path_challenge() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::PathChallenge { data: [0xba; 8] }];
+
+        let pkt_type = packet::Type::Short;
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        assert!(len > 0);
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        let mut iter = frames.iter();
+
+        // Ignore ACK.
+        iter.next().unwrap();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::PathResponse { data: [0xba; 8] })
+        );
+    }
+
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
+    #[test]
+    /// Simulates reception of an early 1-RTT packet on the server, by
+    /// delaying the client's Handshake packet that completes the handshake.
+    fn early_1rtt_packet() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends initial flight
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        // Client sends Handshake packet.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        // Emulate handshake packet delay by not making server process client
+        // packet.
+        let delayed = flight;
+
+        testing::emit_flight(&mut pipe.server).ok();
+
+        assert!(pipe.client.is_established());
+
+        // Send 1-RTT packet #0.
+        let frames = [frame::Frame::Stream {
+            stream_id: 0,
+            data: stream::RangeBuf::from(b"hello, world", 0, true),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+
+        // Send 1-RTT packet #1.
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"hello, world", 0, true),
+        }];
+
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+
+        assert!(!pipe.server.is_established());
+
+        // Client sent 1-RTT packets 0 and 1, but server hasn't received them.
+        //
+        // Note that `largest_rx_pkt_num` is initialized to 0, so we need to
+        // send another 1-RTT packet to make this check meaningful.
+        assert_eq!(
+            pipe.server.pkt_num_spaces[packet::Epoch::Application]
+                .largest_rx_pkt_num,
+            0
+        );
+
+        // Process delayed packet.
+        testing::process_flight(&mut pipe.server, delayed).unwrap();
+
+        assert!(pipe.server.is_established());
+
+        assert_eq!(
+            pipe.server.pkt_num_spaces[packet::Epoch::Application]
+                .largest_rx_pkt_num,
+            0
+        );
+    }
+
+    #[test]
+    fn stop_sending() {
+        let mut b = [0; 15];
+
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data, and closes stream.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server gets data.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert!(pipe.server.stream_finished(0));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Server sends data, until blocked.
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        while pipe.server.stream_send(0, b"world", false) != Err(Error::Done) {
+            assert_eq!(pipe.advance(), Ok(()));
+        }
+
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), None);
+
+        // Client sends STOP_SENDING.
+        let frames = [frame::Frame::StopSending {
+            stream_id: 0,
+            error_code: 42,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        // Server sent a RESET_STREAM frame in response.
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        let mut iter = frames.iter();
+
+        // Skip ACK frame.
+        iter.next();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::ResetStream {
+                stream_id: 0,
+                error_code: 42,
+                final_size: 15,
+            })
+        );
+
+        // Stream is writable, but writing returns an error.
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_send(0, b"world", true),
+            Err(Error::StreamStopped(42)),
+        );
+
+        assert_eq!(pipe.server.streams.len(), 1);
+
+        // Client acks RESET_STREAM frame.
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(0..6);
+
+        let frames = [frame::Frame::ACK {
+            ack_delay: 15,
+            ranges,
+            ecn_counts: None,
+        }];
+
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
+
+        // Stream is collected on the server after RESET_STREAM is acked.
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        // Sending STOP_SENDING again shouldn't trigger RESET_STREAM again.
+        let frames = [frame::Frame::StopSending {
+            stream_id: 0,
+            error_code: 42,
+        }];
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        assert_eq!(frames.len(), 1);
+
+        match frames.first() {
+            Some(frame::Frame::ACK { .. }) => (),
+
+            f => panic!("expected ACK frame, got {:?}", f),
+        };
+
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn stop_sending_fin() {
+        let mut b = [0; 15];
+
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data, and closes stream.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server gets data.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert!(pipe.server.stream_finished(4));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Server sends data...
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_send(4, b"world", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // ...and buffers more, and closes stream.
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+
+        // Client sends STOP_SENDING before server flushes stream.
+        let frames = [frame::Frame::StopSending {
+            stream_id: 4,
+            error_code: 42,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        // Server sent a RESET_STREAM frame in response.
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        let mut iter = frames.iter();
+
+        // Skip ACK frame.
+        iter.next();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 42,
+                final_size: 5,
+            })
+        );
+
+        // No more frames are sent by the server.
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    /// Tests that resetting a stream restores flow control for unsent data.
+    fn stop_sending_unsent_tx_cap() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(15);
+        config.set_initial_max_stream_data_bidi_local(30);
+        config.set_initial_max_stream_data_bidi_remote(30);
+        config.set_initial_max_stream_data_uni(30);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(0);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+
+        // Server sends some data.
+        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server buffers some data, until send capacity limit reached.
+        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(
+            pipe.server.stream_send(4, b"hello", false),
+            Err(Error::Done)
+        );
+
+        // Client sends STOP_SENDING.
+        let frames = [frame::Frame::StopSending {
+            stream_id: 4,
+            error_code: 42,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        // Server can now send more data (on a different stream).
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
+        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
+        assert_eq!(
+            pipe.server.stream_send(8, b"hello", false),
+            Err(Error::Done)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+    }
+
+    #[test]
+    fn stream_shutdown_read() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert_eq!(pipe.server.streams.len(), 1);
+
+        // Server shuts down stream.
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let mut dummy = buf[..len].to_vec();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut dummy[..len]).unwrap();
+        let mut iter = frames.iter();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::StopSending {
+                stream_id: 4,
+                error_code: 42,
+            })
+        );
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Sending more data is forbidden.
+        let mut r = pipe.client.writable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.client.stream_send(4, b"bye", false),
+            Err(Error::StreamStopped(42))
+        );
+
+        // Server sends some data, without reading the incoming data, and closes
+        // the stream.
+        assert_eq!(pipe.server.stream_send(4, b"hello, world", true), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client reads the data.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((12, true)));
+
+        // Stream is collected on both sides.
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        assert_eq!(
+            pipe.server.stream_shutdown(4, Shutdown::Read, 0),
+            Err(Error::Done)
+        );
+    }
+
+    #[test]
+    fn stream_shutdown_read_after_fin() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert_eq!(pipe.server.streams.len(), 1);
+
+        // Server shuts down stream.
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Server has nothing to send.
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server sends some data, without reading the incoming data, and closes
+        // the stream.
+        assert_eq!(pipe.server.stream_send(4, b"hello, world", true), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client reads the data.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((12, true)));
+
+        // Stream is collected on both sides.
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        assert_eq!(
+            pipe.server.stream_shutdown(4, Shutdown::Read, 0),
+            Err(Error::Done)
+        );
+    }
+
+    #[test]
+    fn stream_shutdown_read_update_max_data() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((1, false)));
+        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Read, 123), Ok(()));
+
+        assert_eq!(pipe.server.rx_data, 1);
+        assert_eq!(pipe.client.tx_data, 1);
+        assert_eq!(pipe.client.max_tx_data, 30);
+
+        assert_eq!(
+            pipe.client
+                .stream_send(0, &buf[..pipe.client.tx_cap], false),
+            Ok(29)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.server.stream_readable(0)); // nothing can be consumed
+
+        // The client has increased its tx_data, and server has received it, so
+        // it increases flow control accordingly.
+        assert_eq!(pipe.client.tx_data, 30);
+        assert_eq!(pipe.server.rx_data, 30);
+        assert_eq!(pipe.client.tx_cap, 45);
+    }
+
+    #[test]
+    fn send_capacity_api() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(10);
+        config.set_initial_max_stream_data_bidi_remote(10);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.send_capacity(), 30);
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.send_capacity(), 25);
+        assert_eq!(pipe.client.stream_capacity(0), Ok(5));
+
+        assert_eq!(pipe.client.stream_send(4, b"aaaaaaaaaa", false), Ok(10));
+        assert_eq!(pipe.client.send_capacity(), 15);
+        assert_eq!(pipe.client.stream_capacity(4), Ok(0));
+
+        assert_eq!(pipe.client.close(true, 0x00, b""), Ok(()));
+        assert_eq!(pipe.client.send_capacity(), 0);
+    }
+
+    #[test]
+    fn stream_shutdown_uni() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Exchange some data on uni streams.
+        assert_eq!(pipe.client.stream_send(2, b"hello, world", false), Ok(10));
+        assert_eq!(pipe.server.stream_send(3, b"hello, world", false), Ok(10));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Test local and remote shutdown.
+        assert_eq!(pipe.client.stream_shutdown(2, Shutdown::Write, 42), Ok(()));
+        assert_eq!(
+            pipe.client.stream_shutdown(2, Shutdown::Read, 42),
+            Err(Error::InvalidStreamState(2))
+        );
+
+        assert_eq!(
+            pipe.client.stream_shutdown(3, Shutdown::Write, 42),
+            Err(Error::InvalidStreamState(3))
+        );
+        assert_eq!(pipe.client.stream_shutdown(3, Shutdown::Read, 42), Ok(()));
+    }
+
+    #[test]
+    fn stream_shutdown_write() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert_eq!(pipe.server.streams.len(), 1);
+
+        // Server sends some data.
+        assert_eq!(pipe.server.stream_send(4, b"goodbye, world", false), Ok(14));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server shuts down stream.
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Write, 42), Ok(()));
+
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), None);
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let mut dummy = buf[..len].to_vec();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut dummy[..len]).unwrap();
+        let mut iter = frames.iter();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 42,
+                final_size: 14,
+            })
+        );
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Sending more data is forbidden.
+        assert_eq!(
+            pipe.server.stream_send(4, b"bye", false),
+            Err(Error::FinalSize)
+        );
+
+        // Client sends some data and closes the stream.
+        assert_eq!(pipe.client.stream_send(4, b"bye", true), Ok(3));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server reads the data.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((15, true)));
+
+        // Client processes readable streams.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.client.stream_recv(4, &mut buf),
+            Err(Error::StreamReset(42))
+        );
+
+        // Stream is collected on both sides.
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        assert_eq!(
+            pipe.server.stream_shutdown(4, Shutdown::Write, 0),
+            Err(Error::Done)
+        );
+    }
+
+    #[test]
+    /// Tests that shutting down a stream restores flow control for unsent data.
+    fn stream_shutdown_write_unsent_tx_cap() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(15);
+        config.set_initial_max_stream_data_bidi_local(30);
+        config.set_initial_max_stream_data_bidi_remote(30);
+        config.set_initial_max_stream_data_uni(30);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(0);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+
+        // Server sends some data.
+        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server buffers some data, until send capacity limit reached.
+        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.server.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(
+            pipe.server.stream_send(4, b"hello", false),
+            Err(Error::Done)
+        );
+
+        // Client shouldn't update flow control.
+        assert!(!pipe.client.should_update_max_data());
+
+        // Server shuts down stream.
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Write, 42), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server can now send more data (on a different stream).
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
+        assert_eq!(pipe.server.stream_send(8, b"hello", false), Ok(5));
+        assert_eq!(
+            pipe.server.stream_send(8, b"hello", false),
+            Err(Error::Done)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+    }
+
+    #[test]
+    /// Tests that the order of flushable streams scheduled on the wire is the
+    /// same as the order of `stream_send()` calls done by the application.
+    fn stream_round_robin() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        let mut iter = frames.iter();
+
+        // Skip ACK frame.
+        iter.next();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            })
+        );
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            })
+        );
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            })
+        );
+    }
+
+    #[test]
+    /// Tests the readable iterator.
+    fn stream_readable() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // No readable streams.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), None);
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server received stream.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_send(0, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        // Client drains stream.
+        let mut b = [0; 15];
+        pipe.client.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), None);
+
+        // Server shuts down stream.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Read, 0), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Client creates multiple streams.
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.len(), 2);
+
+        assert!(r.next().is_some());
+        assert!(r.next().is_some());
+        assert!(r.next().is_none());
+
+        assert_eq!(r.len(), 0);
+    }
+
+    #[test]
+    /// Tests the writable iterator.
+    fn stream_writable() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // No writable streams.
+        let mut w = pipe.client.writable();
+        assert_eq!(w.next(), None);
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+
+        // Client created stream.
+        let mut w = pipe.client.writable();
+        assert_eq!(w.next(), Some(0));
+        assert_eq!(w.next(), None);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server created stream.
+        let mut w = pipe.server.writable();
+        assert_eq!(w.next(), Some(0));
+        assert_eq!(w.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_send(0, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+
+        // Server stream is full.
+        let mut w = pipe.server.writable();
+        assert_eq!(w.next(), None);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client drains stream.
+        let mut b = [0; 15];
+        pipe.client.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server stream is writable again.
+        let mut w = pipe.server.writable();
+        assert_eq!(w.next(), Some(0));
+        assert_eq!(w.next(), None);
+
+        // Server shuts down stream.
+        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Write, 0), Ok(()));
+
+        let mut w = pipe.server.writable();
+        assert_eq!(w.next(), None);
+
+        // Client creates multiple streams.
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(8, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut w = pipe.server.writable();
+        assert_eq!(w.len(), 2);
+
+        assert!(w.next().is_some());
+        assert!(w.next().is_some());
+        assert!(w.next().is_none());
+
+        assert_eq!(w.len(), 0);
+
+        // Server finishes stream.
+        assert_eq!(pipe.server.stream_send(8, b"aaaaa", true), Ok(5));
+
+        let mut w = pipe.server.writable();
+        assert_eq!(w.next(), Some(4));
+        assert_eq!(w.next(), None);
+    }
+
+    #[test]
+    fn stream_writable_blocked() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150000);
+        config.set_initial_max_stream_data_bidi_remote(150000);
+        config.set_initial_max_stream_data_uni(150000);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client creates stream and sends some data.
+        let send_buf = [0; 35];
+        assert_eq!(pipe.client.stream_send(0, &send_buf, false), Ok(35));
+
+        // Stream is still writable as it still has capacity.
+        assert_eq!(pipe.client.stream_writable_next(), Some(0));
+        assert_eq!(pipe.client.stream_writable_next(), None);
+
+        // Client fills stream, which becomes unwritable due to connection
+        // capacity.
+        let send_buf = [0; 36];
+        assert_eq!(pipe.client.stream_send(0, &send_buf, false), Ok(35));
+
+        assert_eq!(pipe.client.stream_writable_next(), None);
+
+        assert_eq!(pipe.client.tx_cap, 0);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 70];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The connection capacity has increased and the stream is now writable
+        // again.
+        assert_ne!(pipe.client.tx_cap, 0);
+
+        assert_eq!(pipe.client.stream_writable_next(), Some(0));
+        assert_eq!(pipe.client.stream_writable_next(), None);
+    }
+
+    #[test]
+    fn stream_cancel_pending() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_cancel_pending(0, 0),
+            Err(Error::InvalidStreamState(0))
+        );
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaaa", true), Ok(10));
+        assert_eq!(pipe.client.tx_data, 15);
+
+        // Data that was already sent can't be cancelled.
+        assert_eq!(
+            pipe.client.stream_cancel_pending(0, 3),
+            Err(Error::InvalidStreamState(0))
+        );
+
+        assert_eq!(pipe.client.stream_cancel_pending(0, 15), Err(Error::Done));
+
+        assert_eq!(pipe.client.stream_cancel_pending(0, 5), Ok(10));
+        assert_eq!(pipe.client.tx_data, 5);
+
+        // Nothing is left to send.
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+
+        // The stream can be finished with different data.
+        assert_eq!(pipe.client.stream_send(0, b"!", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((6, true)));
+        assert_eq!(&buf[..6], b"hello!");
+    }
+
+    #[test]
+    fn stream_acked_offset() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_acked_offset(0),
+            Err(Error::InvalidStreamState(0))
+        );
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.stream_acked_offset(0), Ok(0));
+
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_acked_offset(0), Ok(5));
+
+        // Lose the packet carrying the next chunk of data.
+        assert_eq!(pipe.client.stream_send(0, b"world", false), Ok(5));
+        testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert_eq!(pipe.client.stream_send(0, b"!", false), Ok(1));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        // The last chunk was acknowledged, but it doesn't move the offset past
+        // the lost one.
+        assert_eq!(pipe.client.stream_acked_offset(0), Ok(5));
+    }
+
+    #[test]
+    /// Tests that we don't exceed the per-connection flow control limit set by
+    /// the peer.
+    fn flow_control_limit_send() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(
+            pipe.client.stream_send(4, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_send(8, b"a", false), Err(Error::Done));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert!(r.next().is_some());
+        assert!(r.next().is_some());
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    /// Tests that invalid packets received before any other valid ones cause
+    /// the server to close the connection immediately.
+    fn invalid_initial_server() {
+        let mut buf = [0; 65535];
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let frames = [frame::Frame::Padding { len: 10 }];
+
+        let written = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Initial,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+
+        // Corrupt the packets's last byte to make decryption fail (the last
+        // byte is part of the AEAD tag, so changing it means that the packet
+        // cannot be authenticated during decryption).
+        buf[written - 1] = !buf[written - 1];
+
+        assert_eq!(pipe.server.timeout(), None);
+
+        assert_eq!(
+            pipe.server_recv(&mut buf[..written]),
+            Err(Error::CryptoFail)
+        );
+
+        assert!(pipe.server.is_closed());
+    }
+
+    #[test]
+    /// Tests that invalid Initial packets received to cause
+    /// the client to close the connection immediately.
+    fn invalid_initial_client() {
+        let mut buf = [0; 65535];
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends initial flight.
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(1200));
+
+        let frames = [frame::Frame::Padding { len: 10 }];
+
+        let written = testing::encode_pkt(
+            &mut pipe.server,
+            packet::Type::Initial,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+
+        // Corrupt the packets's last byte to make decryption fail (the last
+        // byte is part of the AEAD tag, so changing it means that the packet
+        // cannot be authenticated during decryption).
+        buf[written - 1] = !buf[written - 1];
+
+        // Client will ignore invalid packet.
+        assert_eq!(pipe.client_recv(&mut buf[..written]), Ok(71));
+
+        // The connection should be alive...
+        assert!(!pipe.client.is_closed());
+
+        // ...and the idle timeout should be armed.
+        assert!(pipe.client.idle_timer.is_some());
+    }
+
+    #[test]
+    /// Tests that packets with invalid payload length received before any other
+    /// valid packet cause the server to close the connection immediately.
+    fn invalid_initial_payload() {
+        let mut buf = [0; 65535];
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let mut b = octets::OctetsMut::with_slice(&mut buf);
+
+        let epoch = packet::Type::Initial.to_epoch().unwrap();
+
+        let pn = 0;
+        let pn_len = packet::pkt_num_len(pn, None);
+
+        let dcid = pipe.client.destination_id();
+        let scid = pipe.client.source_id();
+
+        let hdr = Header {
+            ty: packet::Type::Initial,
+            version: pipe.client.version,
+            dcid: ConnectionId::from_ref(&dcid),
+            scid: ConnectionId::from_ref(&scid),
+            pkt_num: 0,
+            pkt_num_len: pn_len,
+            token: pipe.client.token.clone(),
+            versions: None,
+            key_phase: false,
+            spin_bit: false,
+        };
+
+        hdr.to_bytes(&mut b).unwrap();
+
+        // Payload length is invalid!!!
+        let payload_len = 4096;
+
+        let len = pn_len + payload_len;
+        b.put_varint(len as u64).unwrap();
+
+        packet::encode_pkt_num(pn, pn_len, &mut b).unwrap();
+
+        let payload_offset = b.off();
+
+        let frames = [frame::Frame::Padding { len: 10 }];
+
+        for frame in &frames {
+            frame.to_bytes(&mut b).unwrap();
+        }
+
+        let space = &mut pipe.client.pkt_num_spaces[epoch];
+
+        // Use correct payload length when encrypting the packet.
+        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+
+        let aead = space.crypto_seal.as_ref().unwrap();
+
+        let written = packet::encrypt_pkt(
+            &mut b,
+            pn,
+            pn_len,
+            payload_len,
+            payload_offset,
+            None,
+            aead,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.server.timeout(), None);
+
+        assert_eq!(
+            pipe.server_recv(&mut buf[..written]),
+            Err(Error::InvalidPacket)
+        );
+
+        assert!(pipe.server.is_closed());
+    }
+
+    #[test]
+    /// Tests that invalid packets don't cause the connection to be closed.
+    fn invalid_packet() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::Padding { len: 10 }];
+
+        let written = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+
+        // Corrupt the packets's last byte to make decryption fail (the last
+        // byte is part of the AEAD tag, so changing it means that the packet
+        // cannot be authenticated during decryption).
+        buf[written - 1] = !buf[written - 1];
+
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+
+        // Corrupt the packets's first byte to make the header fail decoding.
+        buf[0] = 255;
+
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+    }
+
+    #[test]
+    fn recv_empty_buffer() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.server_recv(&mut buf[..0]), Err(Error::BufferTooShort));
+    }
+
+    #[test]
+    /// Tests that garbage following a valid packet in a datagram is dropped
+    /// without affecting the packet, or the connection.
+    fn recv_trailing_garbage() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends initial flight, server receives it followed by junk.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        buf[len..len + 32].fill(0xff);
+
+        assert_eq!(pipe.server_recv(&mut buf[..len + 32]), Ok(len + 32));
+        assert_eq!(pipe.server.recv_count, 1);
+        assert!(!pipe.server.is_closed());
+
+        // The handshake completes normally.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert_eq!(testing::process_flight(&mut pipe.client, flight), Ok(()));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[test]
+    /// Tests that coalesced packets using a different version than the
+    /// connection are skipped.
+    fn recv_coalesced_different_version() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends initial flight, and coalesces a copy of it using an
+        // unknown version.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        buf.copy_within(..len, len);
+        buf[len + 1..len + 5].copy_from_slice(&0x1a2a_3a4a_u32.to_be_bytes());
+
+        assert_eq!(pipe.server_recv(&mut buf[..len * 2]), Ok(len * 2));
+        assert_eq!(pipe.server.recv_count, 1);
+        assert!(!pipe.server.is_closed());
+
+        // The handshake completes normally.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert_eq!(testing::process_flight(&mut pipe.client, flight), Ok(()));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[test]
+    fn stop_sending_before_flushed_packets() {
+        let mut b = [0; 15];
+
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data, and closes stream.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server gets data.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert!(pipe.server.stream_finished(0));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Server sends data, until blocked.
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        while pipe.server.stream_send(0, b"world", false) != Err(Error::Done) {}
+
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), None);
+
+        // Client sends STOP_SENDING.
+        let frames = [frame::Frame::StopSending {
+            stream_id: 0,
+            error_code: 42,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        // Server sent a RESET_STREAM frame in response.
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+        let mut iter = frames.iter();
+
+        // Skip ACK frame.
+        iter.next();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::ResetStream {
+                stream_id: 0,
+                error_code: 42,
+                final_size: 0,
+            })
+        );
+
+        // Stream is writable, but writing returns an error.
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_send(0, b"world", true),
+            Err(Error::StreamStopped(42)),
+        );
+
+        assert_eq!(pipe.server.streams.len(), 1);
+
+        // Client acks RESET_STREAM frame.
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(0..6);
+
+        let frames = [frame::Frame::ACK {
+            ack_delay: 15,
+            ranges,
+            ecn_counts: None,
+        }];
+
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
+
+        // Client has ACK'd the RESET_STREAM so the stream is collected.
+        assert_eq!(pipe.server.streams.len(), 0);
+    }
+
+    #[test]
+    fn reset_before_flushed_packets() {
+        let mut b = [0; 15];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(5);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data, and closes stream.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server gets data.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert!(pipe.server.stream_finished(0));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Server sends data and is blocked by small stream flow control.
+        let mut r = pipe.server.writable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_send(0, b"helloworld", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client reads to give flow control back.
+        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((5, false)));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server writes stream data and resets the stream before sending a
+        // packet.
+        assert_eq!(pipe.server.stream_send(0, b"world", false), Ok(5));
+        pipe.server.stream_shutdown(0, Shutdown::Write, 42).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client has ACK'd the RESET_STREAM so the stream is collected.
+        assert_eq!(pipe.server.streams.len(), 0);
+    }
+
+    #[test]
+    /// Tests that the MAX_STREAMS frame is sent for bidirectional streams.
+    fn stream_limit_update_bidi() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(0);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"b", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"b", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        pipe.server.stream_recv(4, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server sends stream data, with fin.
+        assert_eq!(pipe.server.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(4, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(4, b"b", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(0, b"b", true), Ok(1));
+
+        // Server sends MAX_STREAMS.
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client tries to create new streams.
+        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(12, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(16, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(20, b"a", false),
+            Err(Error::StreamLimit)
+        );
+
+        assert_eq!(pipe.server.readable().len(), 3);
+    }
+
+    #[test]
+    /// Tests that the MAX_STREAMS frame is sent for unidirectional streams.
+    fn stream_limit_update_uni() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(0);
+        config.set_initial_max_streams_uni(3);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(2, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(6, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(6, b"b", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(2, b"b", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(2, &mut b).unwrap();
+        pipe.server.stream_recv(6, &mut b).unwrap();
+
+        // Server sends MAX_STREAMS.
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client tries to create new streams.
+        assert_eq!(pipe.client.stream_send(10, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(14, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(18, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(22, b"a", false),
+            Err(Error::StreamLimit)
+        );
+
+        assert_eq!(pipe.server.readable().len(), 3);
+    }
+
+    #[test]
+    /// Tests that the stream's fin flag is properly flushed even if there's no
+    /// data in the buffer, and that the buffer becomes readable on the other
+    /// side.
+    fn stream_zero_length_fin() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert!(r.next().is_none());
+
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client sends zero-length frame.
+        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Stream should be readable on the server after receiving empty fin.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert!(r.next().is_none());
+
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client sends zero-length frame (again).
+        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Stream should _not_ be readable on the server after receiving empty
+        // fin, because it was already finished.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    /// Tests that the stream's fin flag is properly flushed even if there's no
+    /// data in the buffer, that the buffer becomes readable on the other
+    /// side and stays readable even if the stream is fin'd locally.
+    fn stream_zero_length_fin_deferred_collection() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert!(r.next().is_none());
+
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client sends zero-length frame.
+        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server sends zero-length frame.
+        assert_eq!(pipe.server.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Stream should be readable on the server after receiving empty fin.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert!(r.next().is_none());
+
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client sends zero-length frame (again).
+        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Stream should _not_ be readable on the server after receiving empty
+        // fin, because it was already finished.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Stream _is_readable on the client side.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(0));
+
+        pipe.client.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Stream is completed and _is not_ readable.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    /// Tests that the stream gets created with stream_send() even if there's
+    /// no data in the buffer and the fin flag is not set.
+    fn stream_zero_length_non_fin() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"", false), Ok(0));
+
+        // The stream now should have been created.
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Sending an empty non-fin should not change any stream state on the
+        // other side.
+        let mut r = pipe.server.readable();
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    /// Tests that a zero-length fin can be sent after the stream and connection
+    /// flow control limits have been reached.
+    fn stream_zero_length_fin_flow_control_blocked() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(15);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaa", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_capacity(0), Ok(0));
+        assert_eq!(pipe.client.send_capacity(), 0);
+
+        // Client sends zero-length fin, without reading on the server side
+        // first so no more credit is granted.
+        assert_eq!(pipe.client.stream_send(0, b"", true), Ok(0));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((15, true)));
+        assert!(pipe.server.stream_finished(0));
+    }
+
+    #[test]
+    /// Tests that completed streams are garbage collected.
+    fn collect_streams() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.client.stream_finished(0));
         assert!(!pipe.server.stream_finished(0));
 
-        assert_eq!(pipe.client.streams.len(), 1);
-        assert_eq!(pipe.server.streams.len(), 1);
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert_eq!(pipe.server.streams.len(), 1);
+
+        let mut b = [0; 5];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(0, b"aaaaa", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.client.stream_finished(0));
+        assert!(pipe.server.stream_finished(0));
+
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        let mut b = [0; 5];
+        pipe.client.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        assert!(pipe.client.stream_finished(0));
+        assert!(pipe.server.stream_finished(0));
+
+        assert_eq!(pipe.client.stream_send(0, b"", true), Err(Error::Done));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 0,
+            data: stream::RangeBuf::from(b"aa", 0, false),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+    }
+
+    #[test]
+    fn config_set_cc_algorithm_name() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(config.set_cc_algorithm_name("reno"), Ok(()));
+
+        // Unknown name.
+        assert_eq!(
+            config.set_cc_algorithm_name("???"),
+            Err(Error::CongestionControl)
+        );
+    }
+
+    #[test]
+    fn peer_cert() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        match pipe.client.peer_cert() {
+            Some(c) => assert_eq!(c.len(), 753),
+
+            None => panic!("missing server certificate"),
+        }
+    }
+
+    #[test]
+    fn peer_cert_chain() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert-big.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        match pipe.client.peer_cert_chain() {
+            Some(c) => assert_eq!(c.len(), 5),
+
+            None => panic!("missing server certificate chain"),
+        }
+    }
+
+    #[test]
+    fn load_cert_and_key_from_pem() {
+        let cert = std::fs::read("examples/cert-big.crt").unwrap();
+        let key = std::fs::read("examples/cert.key").unwrap();
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config.load_cert_chain_from_pem(&cert).unwrap();
+        config.load_priv_key_from_pem(&key).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_cert_chain().map(|c| c.len()), Some(5));
+    }
+
+    #[test]
+    fn load_priv_key_from_der() {
+        let cert = std::fs::read("examples/cert.crt").unwrap();
+        let key = std::fs::read("examples/cert.key.der").unwrap();
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config.load_cert_chain_from_pem(&cert).unwrap();
+        config.load_priv_key_from_der(&key).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_cert_chain().map(|c| c.len()), Some(1));
+    }
+
+    #[test]
+    fn load_invalid_cert_and_key() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(config.load_cert_chain_from_pem(b""), Err(Error::TlsFail));
+        assert_eq!(
+            config.load_cert_chain_from_pem(b"not a certificate"),
+            Err(Error::TlsFail)
+        );
+
+        assert_eq!(config.load_priv_key_from_pem(b""), Err(Error::TlsFail));
+        assert_eq!(config.load_priv_key_from_der(b""), Err(Error::TlsFail));
+        assert_eq!(
+            config.load_priv_key_from_der(b"not a key"),
+            Err(Error::TlsFail)
+        );
+
+        // A PEM-encoded key is not valid DER.
+        let key = std::fs::read("examples/cert.key").unwrap();
+        assert_eq!(config.load_priv_key_from_der(&key), Err(Error::TlsFail));
+    }
+
+    #[test]
+    fn ocsp_stapling() {
+        let mut server_config = Config::new(PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.set_ocsp_response(b"ocsp response").unwrap();
+        server_config
+            .set_signed_cert_timestamp_list(b"\x00\x06\x00\x04scts")
+            .unwrap();
+
+        let mut client_config = Config::new(PROTOCOL_VERSION).unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.verify_peer(false);
+        client_config.enable_ocsp_stapling();
+        client_config.enable_signed_cert_timestamps();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.peer_ocsp_response(),
+            Some(b"ocsp response".as_ref())
+        );
+        assert_eq!(
+            pipe.client.peer_signed_cert_timestamp_list(),
+            Some(b"\x00\x06\x00\x04scts".as_ref())
+        );
+
+        assert_eq!(pipe.server.peer_ocsp_response(), None);
+        assert_eq!(pipe.server.peer_signed_cert_timestamp_list(), None);
+    }
+
+    #[test]
+    fn ocsp_stapling_not_requested() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_ocsp_response(b"ocsp response").unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_ocsp_response(), None);
+
+        // Malformed SCT lists are rejected.
+        assert_eq!(
+            config.set_signed_cert_timestamp_list(b"scts"),
+            Err(Error::TlsFail)
+        );
+    }
+
+    #[test]
+    fn server_name_cert_selection() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config
+            .add_cert_chain_for_server_name(
+                "Quic.Tech",
+                "examples/cert-big.crt",
+                "examples/cert.key",
+            )
+            .unwrap();
+
+        // The client requests "quic.tech", so the matching chain is used.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_cert_chain().map(|c| c.len()), Some(5));
+        assert_eq!(pipe.server.server_name(), Some("quic.tech"));
+    }
+
+    #[test]
+    fn server_name_cert_selection_wildcard() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config
+            .add_cert_chain_for_server_name(
+                "*.tech",
+                "examples/cert-big.crt",
+                "examples/cert.key",
+            )
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_cert_chain().map(|c| c.len()), Some(5));
+    }
+
+    #[test]
+    fn server_name_cert_selection_fallback() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config
+            .add_cert_chain_for_server_name(
+                "example.com",
+                "examples/cert-big.crt",
+                "examples/cert.key",
+            )
+            .unwrap();
+
+        // No match, the default certificate is used.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_cert_chain().map(|c| c.len()), Some(1));
+
+        // Invalid files are rejected.
+        assert_eq!(
+            config.add_cert_chain_for_server_name(
+                "example.com",
+                "examples/cert.key",
+                "examples/cert.key",
+            ),
+            Err(Error::TlsFail)
+        );
+    }
+
+    #[test]
+    fn retry() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends Retry packet.
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+
+        let odcid = hdr.dcid.clone();
+
+        let mut scid = [0; MAX_CONN_ID_LEN];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let token = b"quiche test retry token";
+
+        len = packet::retry(
+            &hdr.scid,
+            &hdr.dcid,
+            &scid,
+            token,
+            hdr.version,
+            &mut buf,
+        )
+        .unwrap();
+
+        // Client receives Retry and sends new Initial.
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        assert_eq!(&hdr.token.unwrap(), token);
+
+        // Server accepts connection.
+        let from = "127.0.0.1:1234".parse().unwrap();
+        pipe.server = accept(
+            &scid,
+            Some(&odcid),
+            testing::Pipe::server_addr(),
+            from,
+            &mut config,
+        )
+        .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+    }
+
+    #[test]
+    fn retry_with_invalid_integrity_tag() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends Retry packet, which gets tampered with.
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+
+        let mut scid = [0; MAX_CONN_ID_LEN];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let token = b"quiche test retry token";
+
+        len = packet::retry(
+            &hdr.scid,
+            &hdr.dcid,
+            &scid,
+            token,
+            hdr.version,
+            &mut buf,
+        )
+        .unwrap();
+
+        buf[len - 1] ^= 0xff;
+
+        // Client ignores the Retry and keeps using the original destination
+        // connection ID.
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        assert!(!pipe.client.did_retry);
+        assert_eq!(pipe.client.destination_id(), hdr.dcid);
+    }
+
+    #[test]
+    fn missing_retry_source_connection_id() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends Retry packet.
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+
+        let mut scid = [0; MAX_CONN_ID_LEN];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let token = b"quiche test retry token";
+
+        len = packet::retry(
+            &hdr.scid,
+            &hdr.dcid,
+            &scid,
+            token,
+            hdr.version,
+            &mut buf,
+        )
+        .unwrap();
+
+        // Client receives Retry and sends new Initial.
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server accepts connection and send first flight. But original
+        // destination connection ID is ignored.
+        let from = "127.0.0.1:1234".parse().unwrap();
+        pipe.server =
+            accept(&scid, None, testing::Pipe::server_addr(), from, &mut config)
+                .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        assert_eq!(
+            testing::process_flight(&mut pipe.client, flight),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn invalid_retry_source_connection_id() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends Retry packet.
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+
+        let mut scid = [0; MAX_CONN_ID_LEN];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let token = b"quiche test retry token";
+
+        len = packet::retry(
+            &hdr.scid,
+            &hdr.dcid,
+            &scid,
+            token,
+            hdr.version,
+            &mut buf,
+        )
+        .unwrap();
+
+        // Client receives Retry and sends new Initial.
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server accepts connection and send first flight. But original
+        // destination connection ID is invalid.
+        let from = "127.0.0.1:1234".parse().unwrap();
+        let odcid = ConnectionId::from_ref(b"bogus value");
+        pipe.server = accept(
+            &scid,
+            Some(&odcid),
+            testing::Pipe::server_addr(),
+            from,
+            &mut config,
+        )
+        .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        assert_eq!(
+            testing::process_flight(&mut pipe.client, flight),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn unexpected_retry_source_connection_id() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Server includes retry_source_connection_id without sending Retry.
+        pipe.server
+            .local_transport_params
+            .retry_source_connection_id = Some(b"bogus value".to_vec().into());
+        assert_eq!(pipe.server.encode_transport_params(), Ok(()));
+
+        // Client sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        // Client rejects transport parameters.
+        assert_eq!(
+            testing::process_flight(&mut pipe.client, flight),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    /// Tests that a zero-length NEW_TOKEN frame is detected as an error.
+    fn zero_length_new_token() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = vec![frame::Frame::NewToken { token: vec![] }];
+
+        let pkt_type = packet::Type::Short;
+
+        let written =
+            testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        assert_eq!(
+            pipe.client_recv(&mut buf[..written]),
+            Err(Error::InvalidFrame)
+        );
+    }
+
+    #[test]
+    fn poll_events() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.enable_events(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let info = pipe.client.handshake_info().unwrap();
+        assert_eq!(
+            pipe.client.poll(),
+            Some(Event::HandshakeCompleted(Box::new(info)))
+        );
+        assert_eq!(pipe.client.poll(), None);
+
+        let info = pipe.server.handshake_info().unwrap();
+        assert_eq!(
+            pipe.server.poll(),
+            Some(Event::HandshakeCompleted(Box::new(info)))
+        );
+        assert_eq!(pipe.server.poll(), None);
+
+        // Client fills the stream's flow control window.
+        assert_eq!(pipe.client.stream_send(0, &buf[..15], false), Ok(15));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.poll(), Some(Event::StreamReadable(0)));
+        assert_eq!(pipe.server.poll(), None);
+
+        // Events are edge-triggered.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Err(Error::Done));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.poll(), None);
+
+        // Reading the data gives more flow control credit to the client.
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((15, false)));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.poll(), Some(Event::StreamWritable(0)));
+        assert_eq!(pipe.client.poll(), None);
+
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.poll(), Some(Event::StreamReadable(0)));
+        assert_eq!(pipe.client.poll(), Some(Event::StreamFinished(0)));
+
+        // Server sends a token.
+        let frames = vec![frame::Frame::NewToken {
+            token: vec![1, 2, 3],
+        }];
+
+        let written = testing::encode_pkt(
+            &mut pipe.server,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..written]), Ok(written));
+        assert_eq!(pipe.client.poll(), Some(Event::NewToken(vec![1, 2, 3])));
+
+        // Server closes the connection.
+        assert_eq!(pipe.server.close(true, 42, b"bye"), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.poll(),
+            Some(Event::PeerClosed {
+                is_app: true,
+                error_code: 42,
+                reason: b"bye".to_vec(),
+            })
+        );
+        assert_eq!(pipe.client.poll(), None);
+    }
+
+    fn spin_bit_config(mode: SpinBitMode) -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(1000);
+        config.set_initial_max_stream_data_bidi_local(1000);
+        config.set_initial_max_stream_data_bidi_remote(1000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.enable_events(true);
+        config.set_spin_bit_mode(mode);
+        config
+    }
+
+    /// Exchanges a flight in each direction, returning the spin bit values of
+    /// the short header packets sent by the client.
+    fn spin_bit_round_trip(pipe: &mut testing::Pipe) -> Vec<bool> {
+        assert_eq!(pipe.client.stream_send(0, b"ping", false), Ok(4));
 
-        let mut b = [0; 5];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let spins = flight
+            .iter()
+            .filter(|(pkt, _)| pkt[0] & 0x80 == 0)
+            .map(|(pkt, _)| pkt[0] & 0x20 != 0)
+            .collect();
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
 
-        assert_eq!(pipe.server.stream_send(0, b"aaaaa", true), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.stream_send(0, b"pong", false), Ok(4));
 
-        assert!(!pipe.client.stream_finished(0));
-        assert!(pipe.server.stream_finished(0));
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert_eq!(testing::process_flight(&mut pipe.client, flight), Ok(()));
 
-        assert_eq!(pipe.client.streams.len(), 1);
-        assert_eq!(pipe.server.streams.len(), 0);
+        spins
+    }
 
-        let mut b = [0; 5];
-        pipe.client.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
+    #[test]
+    fn spin_bit() {
+        let mut config = spin_bit_config(SpinBitMode::Enabled);
 
-        assert_eq!(pipe.client.streams.len(), 0);
-        assert_eq!(pipe.server.streams.len(), 0);
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert!(pipe.client.stream_finished(0));
-        assert!(pipe.server.stream_finished(0));
+        // Let the spin values settle after the handshake.
+        spin_bit_round_trip(&mut pipe);
+
+        while pipe.client.poll().is_some() {}
+        while pipe.server.poll().is_some() {}
+
+        let client_transitions =
+            pipe.client.path_stats().next().unwrap().spin_transitions;
+        let server_transitions =
+            pipe.server.path_stats().next().unwrap().spin_transitions;
+
+        // The client's value flips once per round trip, and so does the
+        // value reflected by the server.
+        let mut spins = Vec::new();
+        for _ in 0..4 {
+            let v = spin_bit_round_trip(&mut pipe);
+            assert!(!v.is_empty());
+            assert!(v.iter().all(|s| *s == v[0]));
+            spins.push(v[0]);
+        }
 
-        assert_eq!(pipe.client.stream_send(0, b"", true), Err(Error::Done));
+        assert_ne!(spins[0], spins[1]);
+        assert_ne!(spins[1], spins[2]);
+        assert_ne!(spins[2], spins[3]);
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 0,
-            data: stream::RangeBuf::from(b"aa", 0, false),
-        }];
+        assert_eq!(
+            pipe.client.path_stats().next().unwrap().spin_transitions,
+            client_transitions + 4
+        );
+        assert_eq!(
+            pipe.server.path_stats().next().unwrap().spin_transitions,
+            server_transitions + 4
+        );
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        let flips = std::iter::from_fn(|| pipe.client.poll())
+            .filter(|e| matches!(e, Event::SpinBitFlipped { .. }))
+            .count();
+        assert_eq!(flips, 4);
+
+        assert_eq!(
+            std::iter::from_fn(|| pipe.server.poll())
+                .filter_map(|e| match e {
+                    Event::SpinBitFlipped { value, .. } => Some(value),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            spins
+        );
     }
 
     #[test]
-    fn config_set_cc_algorithm_name() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    fn spin_bit_disabled() {
+        let mut config = spin_bit_config(SpinBitMode::Disabled);
 
-        assert_eq!(config.set_cc_algorithm_name("reno"), Ok(()));
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Unknown name.
-        assert_eq!(
-            config.set_cc_algorithm_name("???"),
-            Err(Error::CongestionControl)
-        );
+        let mut spins = Vec::new();
+        for _ in 0..4 {
+            spins.extend(spin_bit_round_trip(&mut pipe));
+        }
+
+        // The value sent never changes, regardless of what the peer sends.
+        assert!(spins.iter().all(|s| *s == spins[0]));
+
+        assert_eq!(pipe.client.path_stats().next().unwrap().spin_transitions, 0);
+        assert_eq!(pipe.server.path_stats().next().unwrap().spin_transitions, 0);
+
+        assert!(std::iter::from_fn(|| pipe.client.poll())
+            .all(|e| !matches!(e, Event::SpinBitFlipped { .. })));
     }
 
     #[test]
-    fn peer_cert() {
-        let mut pipe = testing::Pipe::new().unwrap();
+    fn anomaly_events() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.enable_events(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        match pipe.client.peer_cert() {
-            Some(c) => assert_eq!(c.len(), 753),
+        while pipe.client.poll().is_some() {}
+        while pipe.server.poll().is_some() {}
 
-            None => panic!("missing server certificate"),
+        let pkt_type = packet::Type::Short;
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
+
+        // A packet received twice.
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        let mut dup = buf[..len].to_vec();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.server.poll(), None);
+
+        assert_eq!(pipe.server_recv(&mut dup), Ok(len));
+        assert_eq!(
+            pipe.server.poll(),
+            Some(Event::DuplicatePacket {
+                ty: Type::Short,
+                count: 1
+            })
+        );
+
+        // Packets with a corrupted authentication tag.
+        for _ in 0..3 {
+            let len = testing::encode_pkt(
+                &mut pipe.client,
+                pkt_type,
+                &frames,
+                &mut buf,
+            )
+            .unwrap();
+            buf[len - 1] ^= 0xff;
+
+            assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
         }
+
+        // Drops are coalesced until the event is polled.
+        assert_eq!(
+            pipe.server.poll(),
+            Some(Event::UndecryptablePacket {
+                ty: Type::Short,
+                count: 3
+            })
+        );
+        assert_eq!(pipe.server.poll(), None);
+
+        // Packets are sent, but never received.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        testing::emit_flight(&mut pipe.client).unwrap();
+
+        // Wait until timer expires. Since the RTT is very low, wait a bit more.
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.client.on_timeout();
+
+        assert_eq!(
+            pipe.client.poll(),
+            Some(Event::ProbeTimeout {
+                local_addr: testing::Pipe::client_addr(),
+                peer_addr: testing::Pipe::server_addr(),
+                count: 1,
+            })
+        );
+        assert_eq!(pipe.client.poll(), None);
+
+        // More data than allowed by the stream's flow control limit.
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaaa", 0, false),
+        }];
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl)
+        );
+        assert!(matches!(
+            pipe.server.poll(),
+            Some(Event::FlowControlViolation(0x08..=0x0f))
+        ));
     }
 
     #[test]
-    fn peer_cert_chain() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    fn dropped_packet_events_interleaved() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
-            .load_cert_chain_from_pem_file("examples/cert-big.crt")
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
         config
             .load_priv_key_from_pem_file("examples/cert.key")
@@ -12665,22 +20362,59 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
+        config.verify_peer(false);
+        config.enable_events(true);
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        match pipe.client.peer_cert_chain() {
-            Some(c) => assert_eq!(c.len(), 5),
+        while pipe.server.poll().is_some() {}
 
-            None => panic!("missing server certificate chain"),
+        let pkt_type = packet::Type::Short;
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
+
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        let dup = buf[..len].to_vec();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        // Alternate forged and replayed packets.
+        for _ in 0..100 {
+            let len = testing::encode_pkt(
+                &mut pipe.client,
+                pkt_type,
+                &frames,
+                &mut buf,
+            )
+            .unwrap();
+            buf[len - 1] ^= 0xff;
+
+            assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+            assert_eq!(pipe.server_recv(&mut dup.clone()), Ok(dup.len()));
         }
+
+        assert_eq!(
+            pipe.server.poll(),
+            Some(Event::UndecryptablePacket {
+                ty: Type::Short,
+                count: 100
+            })
+        );
+        assert_eq!(
+            pipe.server.poll(),
+            Some(Event::DuplicatePacket {
+                ty: Type::Short,
+                count: 100
+            })
+        );
+        assert_eq!(pipe.server.poll(), None);
     }
 
     #[test]
-    fn retry() {
-        let mut buf = [0; 65535];
-
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    fn probe_rtt() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
@@ -12690,64 +20424,122 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
+        config.verify_peer(false);
+        config.enable_events(true);
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
+        config.set_clock(clock.clone());
 
-        // Client sends initial flight.
-        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server sends Retry packet.
-        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        while pipe.client.poll().is_some() {}
 
-        let odcid = hdr.dcid.clone();
+        let sent_before = pipe.client.stats().sent;
 
-        let mut scid = [0; MAX_CONN_ID_LEN];
-        rand::rand_bytes(&mut scid[..]);
-        let scid = ConnectionId::from_ref(&scid);
+        assert_eq!(pipe.client.probe_rtt(), Ok(()));
 
-        let token = b"quiche test retry token";
+        // The probe was sent in its own packet.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert!(pipe.client.stats().sent > sent_before);
 
-        len = packet::retry(
-            &hdr.scid,
-            &hdr.dcid,
-            &scid,
-            token,
-            hdr.version,
-            &mut buf,
-        )
-        .unwrap();
+        let path = pipe.client.paths.get_active().unwrap();
+        let time_sent = path
+            .recovery
+            .unacked_time_sent(
+                packet::Epoch::Application,
+                path.rtt_probe_pkt_num.unwrap(),
+            )
+            .unwrap();
 
-        // Client receives Retry and sends new Initial.
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        clock.advance(time::Duration::from_millis(20));
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
-        assert_eq!(&hdr.token.unwrap(), token);
+        // The sample is measured from the time the probe was sent.
+        assert_eq!(
+            pipe.client.poll(),
+            Some(Event::RttSample(clock.now() - time_sent))
+        );
+        assert_eq!(pipe.client.poll(), None);
 
-        // Server accepts connection.
-        let from = "127.0.0.1:1234".parse().unwrap();
-        pipe.server = accept(
-            &scid,
-            Some(&odcid),
-            testing::Pipe::server_addr(),
-            from,
-            &mut config,
-        )
-        .unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        // The probe is only reported once.
+        assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.poll(), None);
+    }
+
+    #[test]
+    fn probe_rtt_lost() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_events(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        while pipe.client.poll().is_some() {}
+
+        // Drop the packet carrying the probe.
+        assert_eq!(pipe.client.probe_rtt(), Ok(()));
+        assert!(testing::emit_flight(&mut pipe.client).is_ok());
+        assert!(pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .rtt_probe_pkt_num
+            .is_some());
+
+        // Packets sent later get the probe declared lost.
+        for _ in 0..3 {
+            assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+            assert_eq!(pipe.advance(), Ok(()));
+        }
+
+        assert!(pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .rtt_probe_pkt_num
+            .is_none());
+        assert!(pipe.client.stats().lost > 0);
+
+        while let Some(e) = pipe.client.poll() {
+            assert!(!matches!(e, Event::RttSample(_)));
+        }
+    }
+
+    #[test]
+    fn poll_events_disabled() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert!(pipe.client.is_established());
-        assert!(pipe.server.is_established());
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+
+        assert_eq!(pipe.client.poll(), None);
+        assert_eq!(pipe.server.poll(), None);
     }
 
     #[test]
-    fn missing_retry_source_connection_id() {
-        let mut buf = [0; 65535];
-
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    fn handshake_info() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
@@ -12757,376 +20549,547 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.handshake_info(), None);
 
-        // Client sends initial flight.
-        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server sends Retry packet.
-        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        let info = pipe.server.handshake_info().unwrap();
+        assert_eq!(info.version, crate::PROTOCOL_VERSION);
+        assert_eq!(info.application_proto, b"proto1");
+        assert_eq!(info.server_name.as_deref(), Some("quic.tech"));
+        assert!(info.cipher.is_some());
+        assert_eq!(info.peer_transport_params.initial_max_data, 30);
+        assert!(!info.resumed);
+        assert!(!info.early_data_accepted);
+
+        let info = pipe.client.handshake_info().unwrap();
+        assert_eq!(info.application_proto, b"proto1");
+        assert_eq!(info.cipher, pipe.server.handshake_info().unwrap().cipher);
+        assert!(!info.resumed);
+
+        // Resume the session with early data.
+        let session = pipe.client.session().unwrap();
 
-        let mut scid = [0; MAX_CONN_ID_LEN];
-        rand::rand_bytes(&mut scid[..]);
-        let scid = ConnectionId::from_ref(&scid);
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let token = b"quiche test retry token";
+        let info = pipe.server.handshake_info().unwrap();
+        assert!(info.resumed);
+        assert_eq!(info.early_data_accepted, cfg!(not(feature = "openssl")));
 
-        len = packet::retry(
-            &hdr.scid,
-            &hdr.dcid,
-            &scid,
-            token,
-            hdr.version,
-            &mut buf,
-        )
-        .unwrap();
+        let info = pipe.client.handshake_info().unwrap();
+        assert!(info.resumed);
+        assert_eq!(info.early_data_accepted, cfg!(not(feature = "openssl")));
+    }
 
-        // Client receives Retry and sends new Initial.
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+    #[test]
+    /// Tests that a NEW_TOKEN frame sent by client is detected as an error.
+    fn client_sent_new_token() {
+        let mut buf = [0; 65535];
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server accepts connection and send first flight. But original
-        // destination connection ID is ignored.
-        let from = "127.0.0.1:1234".parse().unwrap();
-        pipe.server =
-            accept(&scid, None, testing::Pipe::server_addr(), from, &mut config)
+        let frames = vec![frame::Frame::NewToken {
+            token: vec![1, 2, 3],
+        }];
+
+        let pkt_type = packet::Type::Short;
+
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
                 .unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert_eq!(
+            pipe.server_recv(&mut buf[..written]),
+            Err(Error::InvalidPacket)
+        );
+    }
+
+    fn check_send(_: &mut impl Send) {}
+
+    #[test]
+    fn config_must_be_send() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        check_send(&mut config);
+    }
+
+    #[test]
+    fn connection_must_be_send() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        check_send(&mut pipe.client);
+    }
+
+    fn check_sync(_: &mut impl Sync) {}
+
+    #[test]
+    fn config_must_be_sync() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        check_sync(&mut config);
+    }
+
+    #[test]
+    fn connection_must_be_sync() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        check_sync(&mut pipe.client);
+    }
+
+    #[test]
+    fn data_blocked() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaaa", false), Ok(10));
+        assert_eq!(pipe.client.blocked_limit, None);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"aaaaaaaaaa", false), Ok(10));
+        assert_eq!(pipe.client.blocked_limit, None);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(8, b"aaaaaaaaaaa", false), Ok(10));
+        assert_eq!(pipe.client.blocked_limit, Some(30));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.blocked_limit, None);
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        let mut iter = frames.iter();
+
+        assert_eq!(iter.next(), Some(&frame::Frame::DataBlocked { limit: 30 }));
 
         assert_eq!(
-            testing::process_flight(&mut pipe.client, flight),
-            Err(Error::InvalidTransportParam)
+            iter.next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"aaaaaaaaaa", 0, false),
+            })
         );
+
+        assert_eq!(iter.next(), None);
     }
 
     #[test]
-    fn invalid_retry_source_connection_id() {
+    fn stream_data_blocked() {
         let mut buf = [0; 65535];
 
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        // Client sends initial flight.
-        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        // Server sends Retry packet.
-        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaa", false), Ok(5));
+        assert_eq!(pipe.client.streams.blocked().len(), 1);
 
-        let mut scid = [0; MAX_CONN_ID_LEN];
-        rand::rand_bytes(&mut scid[..]);
-        let scid = ConnectionId::from_ref(&scid);
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        let token = b"quiche test retry token";
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-        len = packet::retry(
-            &hdr.scid,
-            &hdr.dcid,
-            &scid,
-            token,
-            hdr.version,
-            &mut buf,
-        )
-        .unwrap();
+        let mut iter = frames.iter();
 
-        // Client receives Retry and sends new Initial.
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        // Skip ACK frame.
+        iter.next();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::StreamDataBlocked {
+                stream_id: 0,
+                limit: 15,
+            })
+        );
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            })
+        );
+
+        assert_eq!(iter.next(), None);
+
+        // Send from another stream, make sure we don't send STREAM_DATA_BLOCKED
+        // again.
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
 
         let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        // Server accepts connection and send first flight. But original
-        // destination connection ID is invalid.
-        let from = "127.0.0.1:1234".parse().unwrap();
-        let odcid = ConnectionId::from_ref(b"bogus value");
-        pipe.server = accept(
-            &scid,
-            Some(&odcid),
-            testing::Pipe::server_addr(),
-            from,
-            &mut config,
-        )
-        .unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        let mut iter = frames.iter();
 
         assert_eq!(
-            testing::process_flight(&mut pipe.client, flight),
-            Err(Error::InvalidTransportParam)
+            iter.next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            })
+        );
+
+        assert_eq!(iter.next(), None);
+
+        // Send again from blocked stream and make sure it is not marked as
+        // blocked again.
+        assert_eq!(
+            pipe.client.stream_send(0, b"aaaaaa", false),
+            Err(Error::Done)
         );
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that a zero-length NEW_TOKEN frame is detected as an error.
-    fn zero_length_new_token() {
+    fn stream_data_blocked_unblocked_flow_control() {
         let mut buf = [0; 65535];
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaah", false),
+            Ok(15)
+        );
+        assert_eq!(pipe.client.streams.blocked().len(), 1);
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        // Send again on blocked stream. It's blocked at the same offset as
+        // previously, so it should not be marked as blocked again.
+        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        let frames = vec![frame::Frame::NewToken { token: vec![] }];
+        // No matter how many times we try to write stream data tried, no
+        // packets containing STREAM_BLOCKED should be emitted.
+        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
 
-        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
 
-        let written =
-            testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
-                .unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
 
-        assert_eq!(
-            pipe.client_recv(&mut buf[..written]),
-            Err(Error::InvalidFrame)
-        );
-    }
+        // Now read some data at the server to release flow control.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
 
-    #[test]
-    /// Tests that a NEW_TOKEN frame sent by client is detected as an error.
-    fn client_sent_new_token() {
-        let mut buf = [0; 65535];
+        let mut b = [0; 10];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((10, false)));
+        assert_eq!(&b[..10], b"aaaaaaaaaa");
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, b"hhhhhhhhhh!", false), Ok(10));
+        assert_eq!(pipe.client.streams.blocked().len(), 1);
 
-        let frames = vec![frame::Frame::NewToken {
-            token: vec![1, 2, 3],
-        }];
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
 
-        let pkt_type = packet::Type::Short;
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-        let written =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
+        let mut iter = frames.iter();
 
         assert_eq!(
-            pipe.server_recv(&mut buf[..written]),
-            Err(Error::InvalidPacket)
+            iter.next(),
+            Some(&frame::Frame::StreamDataBlocked {
+                stream_id: 0,
+                limit: 25,
+            })
         );
-    }
 
-    fn check_send(_: &mut impl Send) {}
+        // don't care about remaining received frames
 
-    #[test]
-    fn config_must_be_send() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        check_send(&mut config);
+        assert_eq!(pipe.client.stream_send(0, b"!", false), Err(Error::Done));
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    fn connection_must_be_send() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        check_send(&mut pipe.client);
-    }
+    fn app_limited_true() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_max_recv_udp_payload_size(1200);
+        config.verify_peer(false);
 
-    fn check_sync(_: &mut impl Sync) {}
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-    #[test]
-    fn config_must_be_sync() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        check_sync(&mut config);
-    }
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-    #[test]
-    fn connection_must_be_sync() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        check_sync(&mut pipe.client);
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server sends stream data smaller than cwnd.
+        let send_buf = [0; 10000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf, false), Ok(10000));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // app_limited should be true because we send less than cwnd.
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .expect("no active")
+            .recovery
+            .app_limited());
     }
 
     #[test]
-    fn data_blocked() {
-        let mut buf = [0; 65535];
+    fn app_limited_false() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_max_recv_udp_payload_size(1200);
+        config.verify_peer(false);
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaaa", false), Ok(10));
-        assert_eq!(pipe.client.blocked_limit, None);
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(4, b"aaaaaaaaaa", false), Ok(10));
-        assert_eq!(pipe.client.blocked_limit, None);
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(8, b"aaaaaaaaaaa", false), Ok(10));
-        assert_eq!(pipe.client.blocked_limit, Some(30));
+        // Server sends stream data bigger than cwnd.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(12000));
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.client.blocked_limit, None);
+        testing::emit_flight(&mut pipe.server).ok();
 
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+        // We can't create a new packet header because there is no room by cwnd.
+        // app_limited should be false because we can't send more by cwnd.
+        assert!(!pipe
+            .server
+            .paths
+            .get_active()
+            .expect("no active")
+            .recovery
+            .app_limited());
+    }
 
-        let mut iter = frames.iter();
+    #[test]
+    fn sends_ack_only_pkt_when_full_cwnd_and_ack_elicited() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(3);
+        config.set_max_recv_udp_payload_size(1200);
+        config.verify_peer(false);
 
-        assert_eq!(iter.next(), Some(&frame::Frame::DataBlocked { limit: 30 }));
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends stream data bigger than cwnd (it will never arrive to the
+        // server).
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.client.stream_send(0, &send_buf1, false), Ok(12000));
 
+        testing::emit_flight(&mut pipe.client).ok();
+
+        // Server sends some stream data that will need ACKs.
         assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"aaaaaaaaaa", 0, false),
-            })
+            pipe.server.stream_send(1, &send_buf1[..500], false),
+            Ok(500)
         );
 
-        assert_eq!(iter.next(), None);
-    }
+        testing::process_flight(
+            &mut pipe.client,
+            testing::emit_flight(&mut pipe.server).unwrap(),
+        )
+        .unwrap();
 
-    #[test]
-    fn stream_data_blocked() {
-        let mut buf = [0; 65535];
+        let mut buf = [0; 2000];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let ret = pipe.client.send(&mut buf);
 
-        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
+        assert_eq!(pipe.client.tx_cap, 0);
 
-        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
+        assert!(matches!(ret, Ok((_, _))), "the client should at least send one packet to acknowledge the newly received data");
 
-        assert_eq!(pipe.client.stream_send(0, b"aaaaaa", false), Ok(5));
-        assert_eq!(pipe.client.streams.blocked().len(), 1);
+        let (sent, _) = ret.unwrap();
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
+        assert_ne!(sent, 0, "the client should at least send a pure ACK packet");
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..sent]).unwrap();
+        assert_eq!(1, frames.len());
+        assert!(
+            matches!(frames[0], frame::Frame::ACK { .. }),
+            "the packet sent by the client must be an ACK only packet"
+        );
+    }
+
+    /// Like sends_ack_only_pkt_when_full_cwnd_and_ack_elicited, but when
+    /// ack_eliciting is explicitly requested.
+    #[test]
+    fn sends_ack_only_pkt_when_full_cwnd_and_ack_elicited_despite_max_unacknowledging(
+    ) {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(3);
+        config.set_max_recv_udp_payload_size(1200);
+        config.verify_peer(false);
 
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let mut iter = frames.iter();
+        // Client sends stream data bigger than cwnd (it will never arrive to the
+        // server). This exhausts the congestion window.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.client.stream_send(0, &send_buf1, false), Ok(12000));
 
-        // Skip ACK frame.
-        iter.next();
+        testing::emit_flight(&mut pipe.client).ok();
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::StreamDataBlocked {
-                stream_id: 0,
-                limit: 15,
-            })
-        );
+        // Client gets PING frames from server, which elicit ACK
+        let mut buf = [0; 2000];
+        for _ in 0..recovery::MAX_OUTSTANDING_NON_ACK_ELICITING {
+            let written = testing::encode_pkt(
+                &mut pipe.server,
+                packet::Type::Short,
+                &[frame::Frame::Ping { mtu_probe: None }],
+                &mut buf,
+            )
+            .unwrap();
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            })
-        );
+            pipe.client_recv(&mut buf[..written])
+                .expect("client recv ping");
 
-        assert_eq!(iter.next(), None);
+            // Client acknowledges despite a full congestion window
+            let ret = pipe.client.send(&mut buf);
 
-        // Send from another stream, make sure we don't send STREAM_DATA_BLOCKED
-        // again.
-        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+            assert!(matches!(ret, Ok((_, _))), "the client should at least send one packet to acknowledge the newly received data");
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
+            let (sent, _) = ret.unwrap();
 
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+            assert_ne!(
+                sent, 0,
+                "the client should at least send a pure ACK packet"
+            );
 
-        let mut iter = frames.iter();
+            let frames =
+                testing::decode_pkt(&mut pipe.server, &mut buf[..sent]).unwrap();
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            })
-        );
+            assert_eq!(1, frames.len());
 
-        assert_eq!(iter.next(), None);
+            assert!(
+                matches!(frames[0], frame::Frame::ACK { .. }),
+                "the packet sent by the client must be an ACK only packet"
+            );
+        }
 
-        // Send again from blocked stream and make sure it is not marked as
-        // blocked again.
+        // The client shouldn't need to send any more packets after the ACK only
+        // packet it just sent.
         assert_eq!(
-            pipe.client.stream_send(0, b"aaaaaa", false),
-            Err(Error::Done)
+            pipe.client.send(&mut buf),
+            Err(Error::Done),
+            "nothing for client to send after ACK-only packet"
         );
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
-        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    fn stream_data_blocked_unblocked_flow_control() {
-        let mut buf = [0; 65535];
-        let mut pipe = testing::Pipe::new().unwrap();
+    fn app_limited_false_no_frame() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_max_recv_udp_payload_size(1405);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(
-            pipe.client.stream_send(0, b"aaaaaaaaaaaaaaah", false),
-            Ok(15)
-        );
-        assert_eq!(pipe.client.streams.blocked().len(), 1);
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
-
-        // Send again on blocked stream. It's blocked at the same offset as
-        // previously, so it should not be marked as blocked again.
-        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
-
-        // No matter how many times we try to write stream data tried, no
-        // packets containing STREAM_BLOCKED should be emitted.
-        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
-        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
-
-        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
-        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
-
-        assert_eq!(pipe.client.stream_send(0, b"h", false), Err(Error::Done));
-        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
-
-        // Now read some data at the server to release flow control.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(0));
-        assert_eq!(r.next(), None);
 
-        let mut b = [0; 10];
-        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((10, false)));
-        assert_eq!(&b[..10], b"aaaaaaaaaa");
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(0, b"hhhhhhhhhh!", false), Ok(10));
-        assert_eq!(pipe.client.streams.blocked().len(), 1);
-
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
-
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
-
-        let mut iter = frames.iter();
-
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::StreamDataBlocked {
-                stream_id: 0,
-                limit: 25,
-            })
-        );
+        // Server sends stream data bigger than cwnd.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(12000));
 
-        // don't care about remaining received frames
+        testing::emit_flight(&mut pipe.server).ok();
 
-        assert_eq!(pipe.client.stream_send(0, b"!", false), Err(Error::Done));
-        assert_eq!(pipe.client.streams.blocked().len(), 0);
-        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+        // We can't create a new packet header because there is no room by cwnd.
+        // app_limited should be false because we can't send more by cwnd.
+        assert!(!pipe
+            .server
+            .paths
+            .get_active()
+            .expect("no active")
+            .recovery
+            .app_limited());
     }
 
     #[test]
-    fn app_limited_true() {
+    fn app_limited_false_no_header() {
         let mut config = Config::new(PROTOCOL_VERSION).unwrap();
         config
             .set_application_protos(&[b"proto1", b"proto2"])
@@ -13134,7 +21097,7 @@ mod tests {
         config.set_initial_max_data(50000);
         config.set_initial_max_stream_data_bidi_local(50000);
         config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_recv_udp_payload_size(1200);
+        config.set_max_recv_udp_payload_size(1406);
         config.verify_peer(false);
 
         let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
@@ -13149,13 +21112,15 @@ mod tests {
         pipe.server.stream_recv(0, &mut b).unwrap();
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server sends stream data smaller than cwnd.
-        let send_buf = [0; 10000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf, false), Ok(10000));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Server sends stream data bigger than cwnd.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(12000));
 
-        // app_limited should be true because we send less than cwnd.
-        assert!(pipe
+        testing::emit_flight(&mut pipe.server).ok();
+
+        // We can't create a new frame because there is no room by cwnd.
+        // app_limited should be false because we can't send more by cwnd.
+        assert!(!pipe
             .server
             .paths
             .get_active()
@@ -13165,7 +21130,7 @@ mod tests {
     }
 
     #[test]
-    fn app_limited_false() {
+    fn app_limited_not_changed_on_no_new_frames() {
         let mut config = Config::new(PROTOCOL_VERSION).unwrap();
         config
             .set_application_protos(&[b"proto1", b"proto2"])
@@ -13188,26 +21153,118 @@ mod tests {
         pipe.server.stream_recv(0, &mut b).unwrap();
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server sends stream data bigger than cwnd.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(12000));
+        // Client's app_limited is true because its bytes-in-flight
+        // is much smaller than the current cwnd.
+        assert!(pipe
+            .client
+            .paths
+            .get_active()
+            .expect("no active")
+            .recovery
+            .app_limited());
+
+        // Client has no new frames to send - returns Done.
+        assert_eq!(testing::emit_flight(&mut pipe.client), Err(Error::Done));
+
+        // Client's app_limited should remain the same.
+        assert!(pipe
+            .client
+            .paths
+            .get_active()
+            .expect("no active")
+            .recovery
+            .app_limited());
+    }
+
+    #[test]
+    fn stats_bytes_in_flight() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let in_flight = pipe.client.stats().bytes_in_flight;
+
+        // Client sends stream data, without the packets being delivered.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let sent: usize = flight.iter().map(|(pkt, _)| pkt.len()).sum();
+
+        let stats = pipe.client.stats();
+        assert_eq!(stats.bytes_in_flight, in_flight + sent);
+        assert!(stats.app_limited_periods > 0);
+
+        // The data is much smaller than the congestion window.
+        let path_stats = pipe.client.path_stats().next().unwrap();
+        assert_eq!(path_stats.bytes_in_flight, in_flight + sent);
+        assert!(path_stats.app_limited);
+        assert!(path_stats.bytes_in_flight < path_stats.cwnd);
+
+        // Once acknowledged, nothing is in flight anymore.
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stats().bytes_in_flight, 0);
+    }
+
+    #[test]
+    fn limit_ack_ranges() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let epoch = packet::Epoch::Application;
+
+        assert_eq!(pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.len(), 0);
+
+        let frames = [
+            frame::Frame::Ping { mtu_probe: None },
+            frame::Frame::Padding { len: 3 },
+        ];
+
+        let pkt_type = packet::Type::Short;
+
+        let mut last_packet_sent = 0;
+
+        for _ in 0..512 {
+            let recv_count = pipe.server.recv_count;
+
+            last_packet_sent = pipe.client.pkt_num_spaces[epoch].next_pkt_num;
+
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
+                .unwrap();
+
+            assert_eq!(pipe.server.recv_count, recv_count + 1);
+
+            // Skip packet number.
+            pipe.client.pkt_num_spaces[epoch].next_pkt_num += 1;
+        }
 
-        testing::emit_flight(&mut pipe.server).ok();
+        assert_eq!(
+            pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.len(),
+            MAX_ACK_RANGES
+        );
 
-        // We can't create a new packet header because there is no room by cwnd.
-        // app_limited should be false because we can't send more by cwnd.
-        assert!(!pipe
-            .server
-            .paths
-            .get_active()
-            .expect("no active")
-            .recovery
-            .app_limited());
+        assert_eq!(
+            pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.first(),
+            Some(last_packet_sent - ((MAX_ACK_RANGES as u64) - 1) * 2)
+        );
+
+        assert_eq!(
+            pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.last(),
+            Some(last_packet_sent)
+        );
     }
 
     #[test]
-    fn sends_ack_only_pkt_when_full_cwnd_and_ack_elicited() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    /// Tests that streams are correctly scheduled based on their priority.
+    fn stream_priority() {
+        // Limit 1-RTT packet size to avoid congestion control interference.
+        const MAX_TEST_PACKET_SIZE: usize = 540;
+
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
@@ -13217,318 +21274,334 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(3);
-        config.set_max_recv_udp_payload_size(1200);
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_local(1_000_000);
+        config.set_initial_max_stream_data_bidi_remote(1_000_000);
+        config.set_initial_max_stream_data_uni(0);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(0);
         config.verify_peer(false);
 
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends stream data bigger than cwnd (it will never arrive to the
-        // server).
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.client.stream_send(0, &send_buf1, false), Ok(12000));
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        testing::emit_flight(&mut pipe.client).ok();
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        // Server sends some stream data that will need ACKs.
-        assert_eq!(
-            pipe.server.stream_send(1, &send_buf1[..500], false),
-            Ok(500)
-        );
+        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        testing::process_flight(
-            &mut pipe.client,
-            testing::emit_flight(&mut pipe.server).unwrap(),
-        )
-        .unwrap();
+        assert_eq!(pipe.client.stream_send(12, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let mut buf = [0; 2000];
+        assert_eq!(pipe.client.stream_send(16, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let ret = pipe.client.send(&mut buf);
+        assert_eq!(pipe.client.stream_send(20, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.tx_cap, 0);
+        let mut b = [0; 1];
 
-        assert!(matches!(ret, Ok((_, _))), "the client should at least send one packet to acknowledge the newly received data");
+        let out = [b'b'; 500];
 
-        let (sent, _) = ret.unwrap();
+        // Server prioritizes streams as follows:
+        //  * Stream 8 and 16 have the same priority but are non-incremental.
+        //  * Stream 4, 12 and 20 have the same priority but 20 is non-incremental
+        //    and 4 and 12 are incremental.
+        //  * Stream 0 is on its own.
 
-        assert_ne!(sent, 0, "the client should at least send a pure ACK packet");
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(0, 255, true), Ok(()));
+        pipe.server.stream_send(0, &out, false).unwrap();
+        pipe.server.stream_send(0, &out, false).unwrap();
+        pipe.server.stream_send(0, &out, false).unwrap();
 
-        let frames =
-            testing::decode_pkt(&mut pipe.server, &mut buf[..sent]).unwrap();
-        assert_eq!(1, frames.len());
-        assert!(
-            matches!(frames[0], frame::Frame::ACK { .. }),
-            "the packet sent by the client must be an ACK only packet"
-        );
-    }
+        pipe.server.stream_recv(12, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(12, 42, true), Ok(()));
+        pipe.server.stream_send(12, &out, false).unwrap();
+        pipe.server.stream_send(12, &out, false).unwrap();
+        pipe.server.stream_send(12, &out, false).unwrap();
 
-    /// Like sends_ack_only_pkt_when_full_cwnd_and_ack_elicited, but when
-    /// ack_eliciting is explicitly requested.
-    #[test]
-    fn sends_ack_only_pkt_when_full_cwnd_and_ack_elicited_despite_max_unacknowledging(
-    ) {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(3);
-        config.set_max_recv_udp_payload_size(1200);
-        config.verify_peer(false);
+        pipe.server.stream_recv(16, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(16, 10, false), Ok(()));
+        pipe.server.stream_send(16, &out, false).unwrap();
+        pipe.server.stream_send(16, &out, false).unwrap();
+        pipe.server.stream_send(16, &out, false).unwrap();
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        pipe.server.stream_recv(4, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(4, 42, true), Ok(()));
+        pipe.server.stream_send(4, &out, false).unwrap();
+        pipe.server.stream_send(4, &out, false).unwrap();
+        pipe.server.stream_send(4, &out, false).unwrap();
 
-        // Client sends stream data bigger than cwnd (it will never arrive to the
-        // server). This exhausts the congestion window.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.client.stream_send(0, &send_buf1, false), Ok(12000));
+        pipe.server.stream_recv(8, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(8, 10, false), Ok(()));
+        pipe.server.stream_send(8, &out, false).unwrap();
+        pipe.server.stream_send(8, &out, false).unwrap();
+        pipe.server.stream_send(8, &out, false).unwrap();
 
-        testing::emit_flight(&mut pipe.client).ok();
+        pipe.server.stream_recv(20, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(20, 42, false), Ok(()));
+        pipe.server.stream_send(20, &out, false).unwrap();
+        pipe.server.stream_send(20, &out, false).unwrap();
+        pipe.server.stream_send(20, &out, false).unwrap();
 
-        // Client gets PING frames from server, which elicit ACK
-        let mut buf = [0; 2000];
-        for _ in 0..recovery::MAX_OUTSTANDING_NON_ACK_ELICITING {
-            let written = testing::encode_pkt(
-                &mut pipe.server,
-                packet::Type::Short,
-                &[frame::Frame::Ping { mtu_probe: None }],
-                &mut buf,
-            )
-            .unwrap();
+        // First is stream 8.
+        let mut off = 0;
 
-            pipe.client_recv(&mut buf[..written])
-                .expect("client recv ping");
+        for _ in 1..=3 {
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
-            // Client acknowledges despite a full congestion window
-            let ret = pipe.client.send(&mut buf);
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+            let stream = frames.first().unwrap();
 
-            assert!(matches!(ret, Ok((_, _))), "the client should at least send one packet to acknowledge the newly received data");
+            assert_eq!(stream, &frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(&out, off, false),
+            });
 
-            let (sent, _) = ret.unwrap();
+            off = match stream {
+                frame::Frame::Stream { data, .. } => data.max_off(),
 
-            assert_ne!(
-                sent, 0,
-                "the client should at least send a pure ACK packet"
+                _ => unreachable!(),
+            };
+        }
+
+        // Then is stream 16.
+        let mut off = 0;
+
+        for _ in 1..=3 {
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+            let stream = frames.first().unwrap();
+
+            assert_eq!(stream, &frame::Frame::Stream {
+                stream_id: 16,
+                data: stream::RangeBuf::from(&out, off, false),
+            });
+
+            off = match stream {
+                frame::Frame::Stream { data, .. } => data.max_off(),
+
+                _ => unreachable!(),
+            };
+        }
+
+        // Then is stream 20.
+        let mut off = 0;
+
+        for _ in 1..=3 {
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+            let stream = frames.first().unwrap();
+
+            assert_eq!(stream, &frame::Frame::Stream {
+                stream_id: 20,
+                data: stream::RangeBuf::from(&out, off, false),
+            });
+
+            off = match stream {
+                frame::Frame::Stream { data, .. } => data.max_off(),
+
+                _ => unreachable!(),
+            };
+        }
+
+        // Then are stream 12 and 4, with the same priority, incrementally.
+        let mut off = 0;
+
+        for _ in 1..=3 {
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
+            assert_eq!(
+                frames.first(),
+                Some(&frame::Frame::Stream {
+                    stream_id: 12,
+                    data: stream::RangeBuf::from(&out, off, false),
+                })
             );
 
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+
             let frames =
-                testing::decode_pkt(&mut pipe.server, &mut buf[..sent]).unwrap();
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
-            assert_eq!(1, frames.len());
+            let stream = frames.first().unwrap();
 
-            assert!(
-                matches!(frames[0], frame::Frame::ACK { .. }),
-                "the packet sent by the client must be an ACK only packet"
-            );
-        }
+            assert_eq!(stream, &frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(&out, off, false),
+            });
 
-        // The client shouldn't need to send any more packets after the ACK only
-        // packet it just sent.
-        assert_eq!(
-            pipe.client.send(&mut buf),
-            Err(Error::Done),
-            "nothing for client to send after ACK-only packet"
-        );
-    }
+            off = match stream {
+                frame::Frame::Stream { data, .. } => data.max_off(),
 
-    #[test]
-    fn app_limited_false_no_frame() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_recv_udp_payload_size(1405);
-        config.verify_peer(false);
+                _ => unreachable!(),
+            };
+        }
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        // Final is stream 0.
+        let mut off = 0;
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        for _ in 1..=3 {
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(), Ok(()));
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+            let stream = frames.first().unwrap();
 
-        // Server sends stream data bigger than cwnd.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(12000));
+            assert_eq!(stream, &frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(&out, off, false),
+            });
 
-        testing::emit_flight(&mut pipe.server).ok();
+            off = match stream {
+                frame::Frame::Stream { data, .. } => data.max_off(),
 
-        // We can't create a new packet header because there is no room by cwnd.
-        // app_limited should be false because we can't send more by cwnd.
-        assert!(!pipe
-            .server
-            .paths
-            .get_active()
-            .expect("no active")
-            .recovery
-            .app_limited());
+                _ => unreachable!(),
+            };
+        }
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    fn app_limited_false_no_header() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    /// Tests that changing a stream's priority is correctly propagated.
+    fn stream_reprioritize() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_recv_udp_payload_size(1406);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(0);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(0);
         config.verify_peer(false);
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server sends stream data bigger than cwnd.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(12000));
-
-        testing::emit_flight(&mut pipe.server).ok();
-
-        // We can't create a new frame because there is no room by cwnd.
-        // app_limited should be false because we can't send more by cwnd.
-        assert!(!pipe
-            .server
-            .paths
-            .get_active()
-            .expect("no active")
-            .recovery
-            .app_limited());
-    }
-
-    #[test]
-    fn app_limited_not_changed_on_no_new_frames() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_recv_udp_payload_size(1200);
-        config.verify_peer(false);
-
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.client.stream_send(12, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Client's app_limited is true because its bytes-in-flight
-        // is much smaller than the current cwnd.
-        assert!(pipe
-            .client
-            .paths
-            .get_active()
-            .expect("no active")
-            .recovery
-            .app_limited());
-
-        // Client has no new frames to send - returns Done.
-        assert_eq!(testing::emit_flight(&mut pipe.client), Err(Error::Done));
+        let mut b = [0; 1];
 
-        // Client's app_limited should remain the same.
-        assert!(pipe
-            .client
-            .paths
-            .get_active()
-            .expect("no active")
-            .recovery
-            .app_limited());
-    }
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(0, 255, true), Ok(()));
+        pipe.server.stream_send(0, b"b", false).unwrap();
 
-    #[test]
-    fn limit_ack_ranges() {
-        let mut buf = [0; 65535];
+        pipe.server.stream_recv(12, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(12, 42, true), Ok(()));
+        pipe.server.stream_send(12, b"b", false).unwrap();
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        pipe.server.stream_recv(8, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(8, 10, true), Ok(()));
+        pipe.server.stream_send(8, b"b", false).unwrap();
 
-        let epoch = packet::Epoch::Application;
+        pipe.server.stream_recv(4, &mut b).unwrap();
+        assert_eq!(pipe.server.stream_priority(4, 42, true), Ok(()));
+        pipe.server.stream_send(4, b"b", false).unwrap();
 
-        assert_eq!(pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.len(), 0);
+        // Stream 0 is re-prioritized!!!
+        assert_eq!(pipe.server.stream_priority(0, 20, true), Ok(()));
 
-        let frames = [
-            frame::Frame::Ping { mtu_probe: None },
-            frame::Frame::Padding { len: 3 },
-        ];
+        // First is stream 8.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
-        let pkt_type = packet::Type::Short;
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
-        let mut last_packet_sent = 0;
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"b", 0, false),
+            })
+        );
 
-        for _ in 0..512 {
-            let recv_count = pipe.server.recv_count;
+        // Then is stream 0.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
-            last_packet_sent = pipe.client.pkt_num_spaces[epoch].next_pkt_num;
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf)
-                .unwrap();
+        assert_eq!(
+            frames.first(),
+            Some(&frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"b", 0, false),
+            })
+        );
 
-            assert_eq!(pipe.server.recv_count, recv_count + 1);
+        // Then are stream 12 and 4, with the same priority.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
-            // Skip packet number.
-            pipe.client.pkt_num_spaces[epoch].next_pkt_num += 1;
-        }
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
 
         assert_eq!(
-            pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.len(),
-            MAX_ACK_RANGES
+            frames.first(),
+            Some(&frame::Frame::Stream {
+                stream_id: 12,
+                data: stream::RangeBuf::from(b"b", 0, false),
+            })
         );
 
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+
         assert_eq!(
-            pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.first(),
-            Some(last_packet_sent - ((MAX_ACK_RANGES as u64) - 1) * 2)
+            frames.first(),
+            Some(&frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"b", 0, false),
+            })
         );
 
-        assert_eq!(
-            pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.last(),
-            Some(last_packet_sent)
-        );
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that streams are correctly scheduled based on their priority.
-    fn stream_priority() {
+    /// Tests that streams and datagrams are correctly scheduled.
+    fn stream_datagram_priority() {
         // Limit 1-RTT packet size to avoid congestion control interference.
         const MAX_TEST_PACKET_SIZE: usize = 540;
 
@@ -13550,6 +21623,7 @@ mod tests {
         config.set_initial_max_stream_data_uni(0);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(0);
+        config.enable_dgram(true, 10, 10);
         config.verify_peer(false);
 
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
@@ -13561,27 +21635,15 @@ mod tests {
         assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        assert_eq!(pipe.client.stream_send(12, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        assert_eq!(pipe.client.stream_send(16, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
-
-        assert_eq!(pipe.client.stream_send(20, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
-
         let mut b = [0; 1];
 
         let out = [b'b'; 500];
 
-        // Server prioritizes streams as follows:
-        //  * Stream 8 and 16 have the same priority but are non-incremental.
-        //  * Stream 4, 12 and 20 have the same priority but 20 is non-incremental
-        //    and 4 and 12 are incremental.
-        //  * Stream 0 is on its own.
+        // Server prioritizes Stream 0 and 4 with the same urgency with
+        // incremental, meaning the frames should be sent in round-robin
+        // fashion. It also sends DATAGRAMS which are always interleaved with
+        // STREAM frames. So we'll expect a mix of frame types regardless
+        // of the order that the application writes things in.
 
         pipe.server.stream_recv(0, &mut b).unwrap();
         assert_eq!(pipe.server.stream_priority(0, 255, true), Ok(()));
@@ -13589,172 +21651,279 @@ mod tests {
         pipe.server.stream_send(0, &out, false).unwrap();
         pipe.server.stream_send(0, &out, false).unwrap();
 
-        pipe.server.stream_recv(12, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(12, 42, true), Ok(()));
-        pipe.server.stream_send(12, &out, false).unwrap();
-        pipe.server.stream_send(12, &out, false).unwrap();
-        pipe.server.stream_send(12, &out, false).unwrap();
-
-        pipe.server.stream_recv(16, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(16, 10, false), Ok(()));
-        pipe.server.stream_send(16, &out, false).unwrap();
-        pipe.server.stream_send(16, &out, false).unwrap();
-        pipe.server.stream_send(16, &out, false).unwrap();
-
-        pipe.server.stream_recv(4, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(4, 42, true), Ok(()));
+        assert_eq!(pipe.server.stream_priority(4, 255, true), Ok(()));
         pipe.server.stream_send(4, &out, false).unwrap();
         pipe.server.stream_send(4, &out, false).unwrap();
         pipe.server.stream_send(4, &out, false).unwrap();
 
-        pipe.server.stream_recv(8, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(8, 10, false), Ok(()));
-        pipe.server.stream_send(8, &out, false).unwrap();
-        pipe.server.stream_send(8, &out, false).unwrap();
-        pipe.server.stream_send(8, &out, false).unwrap();
-
-        pipe.server.stream_recv(20, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(20, 42, false), Ok(()));
-        pipe.server.stream_send(20, &out, false).unwrap();
-        pipe.server.stream_send(20, &out, false).unwrap();
-        pipe.server.stream_send(20, &out, false).unwrap();
+        for _ in 1..=6 {
+            assert_eq!(pipe.server.dgram_send(&out), Ok(()));
+        }
 
-        // First is stream 8.
-        let mut off = 0;
+        let mut off_0 = 0;
+        let mut off_4 = 0;
 
         for _ in 1..=3 {
+            // DATAGRAM
             let (len, _) =
                 pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let stream = frames.first().unwrap();
+            let mut frame_iter = frames.iter();
 
-            assert_eq!(stream, &frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(&out, off, false),
+            assert_eq!(frame_iter.next().unwrap(), &frame::Frame::Datagram {
+                data: out.into()
             });
+            assert_eq!(frame_iter.next(), None);
 
-            off = match stream {
-                frame::Frame::Stream { data, .. } => data.max_off(),
-
-                _ => unreachable!(),
-            };
-        }
-
-        // Then is stream 16.
-        let mut off = 0;
-
-        for _ in 1..=3 {
+            // STREAM 0
             let (len, _) =
                 pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let stream = frames.first().unwrap();
+            let mut frame_iter = frames.iter();
+            let stream = frame_iter.next().unwrap();
 
             assert_eq!(stream, &frame::Frame::Stream {
-                stream_id: 16,
-                data: stream::RangeBuf::from(&out, off, false),
+                stream_id: 0,
+                data: stream::RangeBuf::from(&out, off_0, false),
             });
 
-            off = match stream {
+            off_0 = match stream {
                 frame::Frame::Stream { data, .. } => data.max_off(),
 
                 _ => unreachable!(),
             };
-        }
+            assert_eq!(frame_iter.next(), None);
 
-        // Then is stream 20.
-        let mut off = 0;
+            // DATAGRAM
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
-        for _ in 1..=3 {
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+            let mut frame_iter = frames.iter();
+
+            assert_eq!(frame_iter.next().unwrap(), &frame::Frame::Datagram {
+                data: out.into()
+            });
+            assert_eq!(frame_iter.next(), None);
+
+            // STREAM 4
             let (len, _) =
                 pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let stream = frames.first().unwrap();
+            let mut frame_iter = frames.iter();
+            let stream = frame_iter.next().unwrap();
 
             assert_eq!(stream, &frame::Frame::Stream {
-                stream_id: 20,
-                data: stream::RangeBuf::from(&out, off, false),
+                stream_id: 4,
+                data: stream::RangeBuf::from(&out, off_4, false),
             });
 
-            off = match stream {
+            off_4 = match stream {
                 frame::Frame::Stream { data, .. } => data.max_off(),
 
                 _ => unreachable!(),
             };
+            assert_eq!(frame_iter.next(), None);
         }
+    }
 
-        // Then are stream 12 and 4, with the same priority, incrementally.
-        let mut off = 0;
+    #[test]
+    /// Tests that old data is retransmitted on PTO.
+    fn early_retransmit() {
+        let mut buf = [0; 65535];
 
-        for _ in 1..=3 {
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            assert_eq!(
-                frames.first(),
-                Some(&frame::Frame::Stream {
-                    stream_id: 12,
-                    data: stream::RangeBuf::from(&out, off, false),
-                })
-            );
+        // Client sends more stream data, but packet is lost
+        assert_eq!(pipe.client.stream_send(4, b"b", false), Ok(1));
+        assert!(pipe.client.send(&mut buf).is_ok());
 
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        // Wait until PTO expires. Since the RTT is very low, wait a bit more.
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        pipe.client.on_timeout();
 
-            let stream = frames.first().unwrap();
+        let epoch = packet::Epoch::Application;
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .recovery
+                .loss_probes(epoch),
+            1,
+        );
 
-            assert_eq!(stream, &frame::Frame::Stream {
+        // Client retransmits stream data in PTO probe.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .recovery
+                .loss_probes(epoch),
+            0,
+        );
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        let mut iter = frames.iter();
+
+        // Skip ACK frame.
+        iter.next();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::Stream {
                 stream_id: 4,
-                data: stream::RangeBuf::from(&out, off, false),
-            });
+                data: stream::RangeBuf::from(b"b", 0, false),
+            })
+        );
+        assert_eq!(pipe.client.stats().retrans, 1);
+    }
 
-            off = match stream {
-                frame::Frame::Stream { data, .. } => data.max_off(),
+    #[test]
+    fn autoflush_disabled() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_autoflush(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Data is held back until flushed.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+
+        assert_eq!(pipe.client.stream_send(0, b"b", false), Ok(1));
+        assert_eq!(pipe.client.stream_send(0, b"c", true), Ok(1));
+        assert_eq!(pipe.client.stream_send(4, b"", true), Ok(0));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+
+        pipe.client.flush();
+
+        // All writes are coalesced into a single frame.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert!(frames.iter().any(|f| *f ==
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"abc", 0, true),
+            }));
+
+        // The zero-length fin is sent once flushed as well.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert!(frames.iter().any(|f| *f ==
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"", 0, true),
+            }));
+
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+
+        // Flushing a single stream only sends that stream's data.
+        assert_eq!(pipe.client.stream_send(8, b"d", false), Ok(1));
+        assert_eq!(pipe.server.stream_send(1, b"e", false), Ok(1));
+        pipe.client.stream_flush(8);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(8));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn send_coalescing() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(10000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_send_coalescing_delay(50);
 
-                _ => unreachable!(),
-            };
-        }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        // Final is stream 0.
-        let mut off = 0;
+        // A small write is delayed.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+        assert!(pipe.client.send_coalescing_timer.is_some());
+        assert!(
+            pipe.client.timeout().unwrap() <= time::Duration::from_millis(50)
+        );
 
-        for _ in 1..=3 {
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        // Once enough data to fill a packet is queued, it's sent right away.
+        assert_eq!(pipe.client.stream_send(0, &[0; 1500], false), Ok(1500));
+        assert!(pipe.client.send(&mut buf).is_ok());
+        assert_eq!(pipe.client.send_coalescing_timer, None);
+        assert_eq!(pipe.advance(), Ok(()));
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let stream = frames.first().unwrap();
+        // The delay is bounded by the timer.
+        assert_eq!(pipe.client.stream_send(0, b"b", false), Ok(1));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
 
-            assert_eq!(stream, &frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(&out, off, false),
-            });
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+        pipe.client.on_timeout();
 
-            off = match stream {
-                frame::Frame::Stream { data, .. } => data.max_off(),
+        assert!(pipe.client.send(&mut buf).is_ok());
+        assert_eq!(pipe.advance(), Ok(()));
 
-                _ => unreachable!(),
-            };
-        }
+        // In immediate mode packets are never delayed.
+        pipe.client.set_send_immediate(true);
 
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+        assert_eq!(pipe.client.stream_send(0, b"c", false), Ok(1));
+        assert!(pipe.client.send(&mut buf).is_ok());
+        assert_eq!(pipe.client.send_coalescing_timer, None);
     }
 
     #[test]
-    /// Tests that changing a stream's priority is correctly propagated.
-    fn stream_reprioritize() {
+    fn send_coalescing_control_frames() {
         let mut buf = [0; 65535];
 
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -13767,304 +21936,351 @@ mod tests {
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(0);
-        config.set_initial_max_streams_bidi(5);
-        config.set_initial_max_streams_uni(0);
+        config.set_initial_max_data(10000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_active_connection_id_limit(2);
         config.verify_peer(false);
+        config.set_send_coalescing_delay(50);
 
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
-
-        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        // A small write is delayed.
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
 
-        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        // A pending NEW_CONNECTION_ID frame is sent right away, together with
+        // the stream data.
+        let (scid, reset_token) = testing::create_cid_and_reset_token(16);
+        assert_eq!(pipe.client.new_scid(&scid, reset_token, false), Ok(1));
 
-        assert_eq!(pipe.client.stream_send(12, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.send_coalescing_timer, None);
 
-        let mut b = [0; 1];
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(0, 255, true), Ok(()));
-        pipe.server.stream_send(0, b"b", false).unwrap();
+        assert!(frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::NewConnectionId { .. })));
+        assert!(frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::Stream { stream_id: 0, .. })));
 
-        pipe.server.stream_recv(12, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(12, 42, true), Ok(()));
-        pipe.server.stream_send(12, b"b", false).unwrap();
+        // A pending MAX_STREAM_DATA frame isn't delayed either.
+        assert_eq!(pipe.server.stream_send(0, &[0; 6000], false), Ok(6000));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        pipe.server.stream_recv(8, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(8, 10, true), Ok(()));
-        pipe.server.stream_send(8, b"b", false).unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"b", false), Ok(1));
+        assert_eq!(pipe.client.stream_recv(0, &mut buf), Ok((6000, false)));
+        assert!(pipe.client.streams.has_almost_full());
 
-        pipe.server.stream_recv(4, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(4, 42, true), Ok(()));
-        pipe.server.stream_send(4, b"b", false).unwrap();
+        assert!(pipe.client.send(&mut buf).is_ok());
+        assert_eq!(pipe.client.send_coalescing_timer, None);
+    }
 
-        // Stream 0 is re-prioritized!!!
-        assert_eq!(pipe.server.stream_priority(0, 20, true), Ok(()));
+    #[test]
+    fn stream_groups() {
+        let mut buf = [0; 65535];
 
-        // First is stream 8.
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        assert_eq!(pipe.client.stream_group(0, 1), Ok(()));
+        assert_eq!(pipe.client.stream_group(4, 1), Ok(()));
+        assert_eq!(pipe.client.stream_group(8, 2), Ok(()));
 
+        // A stream can only belong to a single group.
+        assert_eq!(pipe.client.stream_group(0, 1), Ok(()));
         assert_eq!(
-            frames.first(),
-            Some(&frame::Frame::Stream {
-                stream_id: 8,
-                data: stream::RangeBuf::from(b"b", 0, false),
-            })
+            pipe.client.stream_group(0, 2),
+            Err(Error::InvalidStreamState(0))
         );
 
-        // Then is stream 0.
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
-
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(4, b"world", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(8, b"bye", true), Ok(3));
+        assert_eq!(pipe.advance(), Ok(()));
 
         assert_eq!(
-            frames.first(),
-            Some(&frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"b", 0, false),
+            pipe.client.group_stats(1),
+            Some(StreamGroupStats {
+                streams: 2,
+                tx_data: 10,
+                rx_data: 0,
             })
         );
 
-        // Then are stream 12 and 4, with the same priority.
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
-
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-
         assert_eq!(
-            frames.first(),
-            Some(&frame::Frame::Stream {
-                stream_id: 12,
-                data: stream::RangeBuf::from(b"b", 0, false),
+            pipe.client.group_stats(2),
+            Some(StreamGroupStats {
+                streams: 1,
+                tx_data: 3,
+                rx_data: 0,
             })
         );
 
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.group_stats(3), None);
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
+        // Server replies on one of the group's streams.
+        assert_eq!(pipe.server.stream_send(4, b"hi", false), Ok(2));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((2, false)));
+        assert_eq!(pipe.client.group_stats(1).unwrap().rx_data, 2);
+
+        let mut streams: Vec<u64> = pipe.client.group_streams(1).collect();
+        streams.sort();
+        assert_eq!(streams, vec![0, 4]);
+
+        // Client closes the group.
+        assert_eq!(pipe.client.group_close(1, 42), Ok(()));
+        assert_eq!(pipe.client.group_close(1, 42), Err(Error::Done));
+        assert_eq!(pipe.client.group_stats(1), None);
+        assert_eq!(pipe.client.group_streams(1).len(), 0);
 
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The group's streams are reset, while other streams are unaffected.
         assert_eq!(
-            frames.first(),
-            Some(&frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"b", 0, false),
-            })
+            pipe.server.stream_recv(0, &mut buf),
+            Err(Error::StreamReset(42))
         );
-
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+        assert_eq!(
+            pipe.server.stream_send(4, b"hi", false),
+            Err(Error::StreamStopped(42))
+        );
+        assert_eq!(pipe.server.stream_recv(8, &mut buf), Ok((3, true)));
     }
 
     #[test]
-    /// Tests that streams and datagrams are correctly scheduled.
-    fn stream_datagram_priority() {
-        // Limit 1-RTT packet size to avoid congestion control interference.
-        const MAX_TEST_PACKET_SIZE: usize = 540;
+    fn stream_group_limit() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let mut buf = [0; 65535];
+        assert_eq!(pipe.client.group_set_max_data(1, 10), Err(Error::Done));
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(1_000_000);
-        config.set_initial_max_stream_data_bidi_local(1_000_000);
-        config.set_initial_max_stream_data_bidi_remote(1_000_000);
-        config.set_initial_max_stream_data_uni(0);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(0);
-        config.enable_dgram(true, 10, 10);
-        config.verify_peer(false);
+        assert_eq!(pipe.client.stream_group(0, 1), Ok(()));
+        assert_eq!(pipe.client.stream_group(4, 1), Ok(()));
+        assert_eq!(pipe.client.group_set_max_data(1, 10), Ok(()));
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.stream_capacity(0), Ok(10));
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+
+        assert_eq!(pipe.client.stream_capacity(4), Ok(5));
+        assert_eq!(pipe.client.stream_send(4, b"world!", false), Ok(5));
+
+        // The group's limit is reached.
+        assert_eq!(pipe.client.stream_capacity(0), Ok(0));
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Err(Error::Done));
+
+        // Streams outside of the group are unaffected.
+        assert_eq!(pipe.client.stream_send(8, b"a", false), Ok(1));
+
+        // Raising the limit allows writing again.
+        assert_eq!(pipe.client.group_set_max_data(1, 12), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, b"abc", false), Ok(2));
+
+        assert_eq!(pipe.client.group_stats(1).unwrap().tx_data, 12);
+
+        assert_eq!(pipe.advance(), Ok(()));
+    }
+
+    #[test]
+    fn stream_group_collected() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.client.stream_group(0, 1), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.server.stream_send(0, b"world", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
-        let mut b = [0; 1];
+        assert_eq!(pipe.client.group_stats(1).map(|s| s.streams), Some(1));
 
-        let out = [b'b'; 500];
+        // Once its last stream is collected, the group is removed.
+        assert_eq!(pipe.client.stream_recv(0, &mut buf), Ok((5, true)));
+        assert!(pipe.client.streams.is_collected(0));
 
-        // Server prioritizes Stream 0 and 4 with the same urgency with
-        // incremental, meaning the frames should be sent in round-robin
-        // fashion. It also sends DATAGRAMS which are always interleaved with
-        // STREAM frames. So we'll expect a mix of frame types regardless
-        // of the order that the application writes things in.
+        assert_eq!(pipe.client.group_stats(1), None);
+        assert_eq!(pipe.client.group_set_max_data(1, 10), Err(Error::Done));
+    }
 
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.server.stream_priority(0, 255, true), Ok(()));
-        pipe.server.stream_send(0, &out, false).unwrap();
-        pipe.server.stream_send(0, &out, false).unwrap();
-        pipe.server.stream_send(0, &out, false).unwrap();
+    #[test]
+    fn stream_reliability_max_retransmissions() {
+        let mut buf = [0; 65535];
 
-        assert_eq!(pipe.server.stream_priority(4, 255, true), Ok(()));
-        pipe.server.stream_send(4, &out, false).unwrap();
-        pipe.server.stream_send(4, &out, false).unwrap();
-        pipe.server.stream_send(4, &out, false).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        for _ in 1..=6 {
-            assert_eq!(pipe.server.dgram_send(&out), Ok(()));
-        }
+        assert_eq!(
+            pipe.client.stream_reliability(
+                4,
+                StreamReliability::MaxRetransmissions(1),
+                42
+            ),
+            Ok(())
+        );
+
+        // Client sends stream data, but packet is lost.
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert!(pipe.client.send(&mut buf).is_ok());
+
+        // Wait until PTO expires. Since the RTT is very low, wait a bit more.
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.client.on_timeout();
+
+        // Client retransmits stream data once, but packet is lost again.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert!(frames.iter().any(|f| *f ==
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            }));
+
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.client.on_timeout();
+
+        // Client gives up on the data and resets the stream instead.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert!(!frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::Stream { .. })));
+
+        assert!(frames.iter().any(|f| *f ==
+            frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 42,
+                final_size: 1,
+            }));
 
-        let mut off_0 = 0;
-        let mut off_4 = 0;
+        assert_eq!(pipe.client.stats().retrans, 1);
+        assert_eq!(pipe.client.stats().reset_stream_count_local, 1);
 
-        for _ in 1..=3 {
-            // DATAGRAM
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        // Sending more data is forbidden.
+        assert_eq!(
+            pipe.client.stream_send(4, b"b", false),
+            Err(Error::FinalSize)
+        );
+    }
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let mut frame_iter = frames.iter();
+    #[test]
+    fn stream_reliability_max_retransmissions_per_range() {
+        let mut buf = [0; 65535];
 
-            assert_eq!(frame_iter.next().unwrap(), &frame::Frame::Datagram {
-                data: out.into()
-            });
-            assert_eq!(frame_iter.next(), None);
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            // STREAM 0
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        assert_eq!(
+            pipe.client.stream_reliability(
+                4,
+                StreamReliability::MaxRetransmissions(1),
+                42
+            ),
+            Ok(())
+        );
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let mut frame_iter = frames.iter();
-            let stream = frame_iter.next().unwrap();
+        // Client sends stream data, but packet is lost.
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert!(pipe.client.send(&mut buf).is_ok());
 
-            assert_eq!(stream, &frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(&out, off_0, false),
-            });
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
 
-            off_0 = match stream {
-                frame::Frame::Stream { data, .. } => data.max_off(),
+        pipe.client.on_timeout();
 
-                _ => unreachable!(),
-            };
-            assert_eq!(frame_iter.next(), None);
+        // Client retransmits stream data, which is received this time.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stats().retrans, 1);
 
-            // DATAGRAM
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        // Client sends more stream data, but packet is lost.
+        assert_eq!(pipe.client.stream_send(4, b"b", false), Ok(1));
+        assert!(pipe.client.send(&mut buf).is_ok());
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let mut frame_iter = frames.iter();
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
 
-            assert_eq!(frame_iter.next().unwrap(), &frame::Frame::Datagram {
-                data: out.into()
-            });
-            assert_eq!(frame_iter.next(), None);
+        pipe.client.on_timeout();
 
-            // STREAM 4
-            let (len, _) =
-                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+        // The new data was never retransmitted before, so it still can be.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf[..len]).unwrap();
-            let mut frame_iter = frames.iter();
-            let stream = frame_iter.next().unwrap();
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-            assert_eq!(stream, &frame::Frame::Stream {
+        assert!(frames.iter().any(|f| *f ==
+            frame::Frame::Stream {
                 stream_id: 4,
-                data: stream::RangeBuf::from(&out, off_4, false),
-            });
+                data: stream::RangeBuf::from(b"b", 1, false),
+            }));
 
-            off_4 = match stream {
-                frame::Frame::Stream { data, .. } => data.max_off(),
-
-                _ => unreachable!(),
-            };
-            assert_eq!(frame_iter.next(), None);
-        }
+        assert_eq!(pipe.client.stats().retrans, 2);
+        assert_eq!(pipe.client.stats().reset_stream_count_local, 0);
     }
 
     #[test]
-    /// Tests that old data is retransmitted on PTO.
-    fn early_retransmit() {
+    fn stream_reliability_deadline() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
-        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(
+            pipe.client.stream_reliability(
+                4,
+                StreamReliability::Deadline(time::Instant::now()),
+                42
+            ),
+            Ok(())
+        );
 
-        // Client sends more stream data, but packet is lost
+        // Client sends stream data, but packet is lost.
         assert_eq!(pipe.client.stream_send(4, b"b", false), Ok(1));
         assert!(pipe.client.send(&mut buf).is_ok());
 
-        // Wait until PTO expires. Since the RTT is very low, wait a bit more.
         let timer = pipe.client.timeout().unwrap();
         std::thread::sleep(timer + time::Duration::from_millis(1));
 
         pipe.client.on_timeout();
 
-        let epoch = packet::Epoch::Application;
-        assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .recovery
-                .loss_probes(epoch),
-            1,
-        );
-
-        // Client retransmits stream data in PTO probe.
+        // The deadline has passed, so the data isn't retransmitted and the
+        // stream is reset instead.
         let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .recovery
-                .loss_probes(epoch),
-            0,
-        );
 
         let frames =
             testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
 
-        let mut iter = frames.iter();
-
-        // Skip ACK frame.
-        iter.next();
+        assert!(!frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::Stream { stream_id: 4, .. })));
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::Stream {
+        assert!(frames.iter().any(|f| *f ==
+            frame::Frame::ResetStream {
                 stream_id: 4,
-                data: stream::RangeBuf::from(b"b", 0, false),
-            })
-        );
-        assert_eq!(pipe.client.stats().retrans, 1);
+                error_code: 42,
+                final_size: 1,
+            }));
     }
 
     #[test]
@@ -14184,6 +22400,34 @@ mod tests {
         assert_eq!(pipe.server.sent_count, pipe.client.recv_count);
     }
 
+    #[test]
+    /// Tests that the handshake completion and the first 1-RTT data are sent
+    /// in the same UDP datagram.
+    fn coalesce_handshake_and_short() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends first flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        // Server sends first flight.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        // Client sends stream data along with its last handshake message.
+        assert!(pipe.client.is_established());
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        // A single datagram completes the handshake and delivers the data.
+        assert!(pipe.server.is_established());
+        assert!(pipe.server.stream_readable(4));
+    }
+
     #[test]
     /// Tests that client avoids handshake deadlock by arming PTO.
     fn handshake_anti_deadlock() {
@@ -14941,40 +23185,198 @@ mod tests {
     }
 
     #[test]
-    fn app_peer_error() {
+    fn app_peer_error() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.server.close(true, 0x1234, b"hello!"), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.peer_error(),
+            Some(&ConnectionError {
+                is_app: true,
+                error_code: 0x1234u64,
+                reason: b"hello!".to_vec()
+            })
+        );
+    }
+
+    #[test]
+    fn local_error() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.server.local_error(), None);
+
+        assert_eq!(pipe.server.close(true, 0x1234, b"hello!"), Ok(()));
+
+        assert_eq!(
+            pipe.server.local_error(),
+            Some(&ConnectionError {
+                is_app: true,
+                error_code: 0x1234u64,
+                reason: b"hello!".to_vec()
+            })
+        );
+
+        // Application errors don't carry transport error codes.
+        assert_eq!(pipe.server.local_error().unwrap().wire_error_code(), None);
+    }
+
+    #[test]
+    fn wire_error_code() {
+        for code in 0x0..=0x10 {
+            let wire = WireErrorCode::try_from(code).unwrap();
+            assert_eq!(wire as u64, code);
+        }
+
+        assert_eq!(WireErrorCode::try_from(0x11), Err(Error::Done));
+
+        // TLS alerts.
+        assert_eq!(WireErrorCode::try_from(0x12a), Err(Error::Done));
+
+        assert_eq!(
+            Error::InvalidStreamState(4).to_wire(),
+            WireErrorCode::StreamStateError as u64
+        );
+        assert_eq!(
+            Error::FinalSize.to_wire(),
+            WireErrorCode::FinalSizeError as u64
+        );
+        assert_eq!(
+            Error::InvalidPacket.to_wire(),
+            WireErrorCode::ProtocolViolation as u64
+        );
+        assert_eq!(
+            Error::CongestionControl.to_wire(),
+            WireErrorCode::InternalError as u64
+        );
+    }
+
+    #[test]
+    fn trace_id() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_trace_label("worker-1");
+
+        let pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let hex = |cid: &ConnectionId| {
+            cid.iter().map(|b| format!("{b:02x}")).collect::<String>()
+        };
+
+        assert_eq!(pipe.client.trace_id(), hex(&pipe.client.source_id()));
+        assert_eq!(
+            pipe.server.trace_id(),
+            format!("worker-1-{}", hex(&pipe.server.source_id()))
+        );
+    }
+
+    #[test]
+    fn pre_handshake_data() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_max_pre_handshake_data(25);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        assert!(!pipe.client.is_app_data_ready());
+
+        // Data is queued up to the configured limit.
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaaa", false), Ok(10));
+        assert_eq!(pipe.client.stream_send(4, b"bbbbb", true), Ok(5));
+        assert_eq!(pipe.client.stream_send(0, b"cccccccccccc", true), Ok(10));
+        assert_eq!(pipe.client.stream_send(0, b"c", true), Err(Error::Done));
+        assert_eq!(pipe.client.pre_handshake_data_len(), 25);
+
+        // Peer-initiated streams can't be written to.
+        assert_eq!(
+            pipe.client.stream_send(1, b"a", false),
+            Err(Error::InvalidStreamState(1))
+        );
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert!(pipe.client.is_app_data_ready());
+
+        // Data is written to the streams in order, as far as flow control
+        // allows.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.pre_handshake_data_len(), 5);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"bbbbb");
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((15, false)));
+        assert_eq!(&b[..15], b"aaaaaaaaaaccccc");
+
+        // More data can be written once the peer raises the limits.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.pre_handshake_data_len(), 0);
+
+        // The fin flag was dropped when the data was truncated.
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, false)));
+        assert_eq!(&b[..5], b"ccccc");
+    }
+
+    #[test]
+    fn stream_ops_after_close() {
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.server.close(true, 0x1234, b"hello!"), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(0, b"world", false), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
 
+        assert_eq!(pipe.server.close(true, 0x1234, b"bye"), Ok(()));
+
+        // Stream operations fail on the closing side.
         assert_eq!(
-            pipe.client.peer_error(),
-            Some(&ConnectionError {
-                is_app: true,
-                error_code: 0x1234u64,
-                reason: b"hello!".to_vec()
-            })
+            pipe.server.stream_send(0, b"more", false),
+            Err(Error::ConnectionClosed(0x1234))
         );
-    }
-
-    #[test]
-    fn local_error() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.server.local_error(), None);
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, false)));
+        assert_eq!(
+            pipe.server.stream_recv(0, &mut b),
+            Err(Error::ConnectionClosed(0x1234))
+        );
 
-        assert_eq!(pipe.server.close(true, 0x1234, b"hello!"), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert!(pipe.client.is_draining());
 
+        // Buffered data can still be read by the peer, after which the
+        // closing error is reported.
+        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((5, false)));
         assert_eq!(
-            pipe.server.local_error(),
-            Some(&ConnectionError {
-                is_app: true,
-                error_code: 0x1234u64,
-                reason: b"hello!".to_vec()
-            })
+            pipe.client.stream_recv(0, &mut b),
+            Err(Error::ConnectionClosed(0x1234))
+        );
+        assert_eq!(
+            pipe.client.stream_send(4, b"more", false),
+            Err(Error::ConnectionClosed(0x1234))
         );
+
+        assert_eq!(pipe.client.peer_error().unwrap().reason, b"bye".to_vec());
     }
 
     #[test]
@@ -15066,6 +23468,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn max_recv_udp_payload_size() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        // Values below the minimum QUIC datagram size are clamped.
+        config.set_max_recv_udp_payload_size(500);
+
+        let pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.server.max_recv_udp_payload_size(), 1200);
+
+        config.set_max_recv_udp_payload_size(1350);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.server.max_recv_udp_payload_size(), 1350);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_transport_params.max_udp_payload_size, 1350);
+
+        // Datagrams exceeding the limit are dropped.
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            },
+            frame::Frame::Padding { len: 1400 },
+        ];
+
+        let len = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+        assert!(len > 1350);
+
+        let recv = pipe.server.stats().recv;
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.server.stats().recv, recv);
+        assert_eq!(pipe.server.readable().next(), None);
+
+        // Those within the limit are processed.
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaa", 0, false),
+            },
+            frame::Frame::Padding { len: 1200 },
+        ];
+
+        let len = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+        assert!(len <= 1350);
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.server.stats().recv, recv + 1);
+        assert_eq!(pipe.server.readable().next(), Some(0));
+    }
+
     #[test]
     /// Tests that connection-level send capacity decreases as more stream data
     /// is buffered.
@@ -15385,7 +23869,7 @@ mod tests {
             iter.next(),
             Some(&frame::Frame::ConnectionClose {
                 error_code: 0x7,
-                frame_type: 0,
+                frame_type: 0x18,
                 reason: Vec::new(),
             })
         );
@@ -15456,7 +23940,7 @@ mod tests {
             iter.next(),
             Some(&frame::Frame::ConnectionClose {
                 error_code: 0x7,
-                frame_type: 0,
+                frame_type: 0x18,
                 reason: Vec::new(),
             })
         );
@@ -15650,6 +24134,104 @@ mod tests {
         assert_eq!(pipe.client.new_scid(&scid_1, reset_token_1, false), Ok(2));
     }
 
+    #[test]
+    fn generated_scids() {
+        struct RoutableGenerator;
+
+        impl ConnectionIdGenerator for RoutableGenerator {
+            fn generate(&self, seq: u64) -> ConnectionId<'static> {
+                // Encode a fixed server ID followed by the sequence number.
+                let mut cid = vec![0xab; 12];
+                cid[4..].copy_from_slice(&seq.to_be_bytes());
+
+                ConnectionId::from_vec(cid)
+            }
+        }
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(3);
+        config.set_connection_id_generator(Arc::new(RoutableGenerator));
+
+        let scid = config.generate_scid().unwrap();
+        assert_eq!(scid.as_ref(), &[
+            0xab, 0xab, 0xab, 0xab, 0, 0, 0, 0, 0, 0, 0, 0
+        ]);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let (scid_1, seq_1) = pipe.server.generate_scid(0x1234, false).unwrap();
+        assert_eq!(seq_1, 1);
+        assert_eq!(&scid_1[..4], &[0xab; 4]);
+        assert_eq!(&scid_1[4..], &1u64.to_be_bytes());
+
+        let (scid_2, seq_2) = pipe.server.generate_scid(0x5678, false).unwrap();
+        assert_eq!(seq_2, 2);
+        assert_eq!(&scid_2[4..], &2u64.to_be_bytes());
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The client received both connection IDs.
+        assert_eq!(pipe.client.available_dcids(), 2);
+
+        assert_eq!(pipe.client.retire_dcid(0), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.destination_id(), scid_1);
+    }
+
+    #[test]
+    fn generated_scids_invalid_len() {
+        struct FixedLenGenerator(usize);
+
+        impl ConnectionIdGenerator for FixedLenGenerator {
+            fn generate(&self, _seq: u64) -> ConnectionId<'static> {
+                ConnectionId::from_vec(vec![0xab; self.0])
+            }
+        }
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        config.set_connection_id_generator(Arc::new(FixedLenGenerator(0)));
+        assert_eq!(config.generate_scid(), Err(Error::InvalidState));
+
+        config.set_connection_id_generator(Arc::new(FixedLenGenerator(
+            MAX_CONN_ID_LEN + 1,
+        )));
+        assert_eq!(config.generate_scid(), Err(Error::InvalidState));
+
+        config.set_connection_id_generator(Arc::new(FixedLenGenerator(
+            MAX_CONN_ID_LEN,
+        )));
+        assert_eq!(config.generate_scid().map(|c| c.len()), Ok(MAX_CONN_ID_LEN));
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        pipe.server.cid_generator = Arc::new(FixedLenGenerator(0));
+        assert_eq!(
+            pipe.server.generate_scid(0x1234, false),
+            Err(Error::InvalidState)
+        );
+
+        pipe.server.cid_generator =
+            Arc::new(FixedLenGenerator(MAX_CONN_ID_LEN + 1));
+        assert_eq!(
+            pipe.server.generate_scid(0x1234, false),
+            Err(Error::InvalidState)
+        );
+    }
+
     #[test]
     /// Tests the limit to retired DCID sequence numbers.
     fn connection_id_retire_limit() {
@@ -15733,7 +24315,7 @@ mod tests {
             iter.next(),
             Some(&frame::Frame::ConnectionClose {
                 error_code: 0x9,
-                frame_type: 0,
+                frame_type: 0x18,
                 reason: Vec::new(),
             })
         );
@@ -16336,32 +24918,174 @@ mod tests {
 
         assert_eq!(pipe.advance(), Ok(()));
 
-        let mut v1 = pipe.client.paths_iter(client_addr).collect::<Vec<_>>();
-        let mut v2 = vec![server_addr, server_addr_2];
-
-        v1.sort();
-        v2.sort();
+        let mut v1 = pipe.client.paths_iter(client_addr).collect::<Vec<_>>();
+        let mut v2 = vec![server_addr, server_addr_2];
+
+        v1.sort();
+        v2.sort();
+
+        assert_eq!(v1, v2);
+
+        let mut v1 = pipe.client.paths_iter(client_addr_2).collect::<Vec<_>>();
+        let mut v2 = vec![server_addr];
+
+        v1.sort();
+        v2.sort();
+
+        assert_eq!(v1, v2);
+
+        let mut v1 = pipe.client.paths_iter(client_addr_3).collect::<Vec<_>>();
+        let mut v2 = vec![server_addr];
+
+        v1.sort();
+        v2.sort();
+
+        assert_eq!(v1, v2);
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.path_challenge_rx_count, 3);
+    }
+
+    #[test]
+    fn multipath() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(2);
+        config.set_active_connection_id_limit(2);
+        config.set_multipath(true);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr = testing::Pipe::client_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(pipe.client.paths().collect::<Vec<_>>(), vec![(
+            client_addr,
+            server_addr
+        )]);
+
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr_2, server_addr)),
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2)),
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2)),
+        );
+
+        // Both paths can now be used concurrently.
+        assert_eq!(pipe.client.paths().collect::<Vec<_>>(), vec![
+            (client_addr, server_addr),
+            (client_addr_2, server_addr)
+        ]);
+        assert_eq!(pipe.server.paths().count(), 2);
+
+        // Stream data can be sent on the non-active path.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+
+        let (len, si) = pipe
+            .client
+            .send_on_path(&mut buf, Some(client_addr_2), Some(server_addr))
+            .unwrap();
+        assert_eq!(si.from, client_addr_2);
+
+        let ri = RecvInfo {
+            from: si.from,
+            to: si.to,
+        };
+        assert_eq!(pipe.server.recv(&mut buf[..len], ri), Ok(len));
+
+        // The server doesn't migrate to the new path.
+        assert_eq!(
+            pipe.server.paths.get_active().unwrap().peer_addr(),
+            client_addr
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+
+        // The packet sent on the second path is acked and accounted for on
+        // that path only.
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let stats = pipe.client.path_stats().collect::<Vec<_>>();
+        assert_eq!(stats[0].lost, 0);
+        assert_eq!(stats[1].lost, 0);
+        assert!(stats[1].min_rtt.is_some());
+    }
 
-        assert_eq!(v1, v2);
+    #[test]
+    fn multipath_disabled() {
+        let mut buf = [0; 65535];
 
-        let mut v1 = pipe.client.paths_iter(client_addr_2).collect::<Vec<_>>();
-        let mut v2 = vec![server_addr];
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(2);
+        config.set_active_connection_id_limit(2);
 
-        v1.sort();
-        v2.sort();
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
 
-        assert_eq!(v1, v2);
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr = testing::Pipe::client_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
 
-        let mut v1 = pipe.client.paths_iter(client_addr_3).collect::<Vec<_>>();
-        let mut v2 = vec![server_addr];
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        v1.sort();
-        v2.sort();
+        // Only the active path can be used.
+        assert_eq!(pipe.client.paths().collect::<Vec<_>>(), vec![(
+            client_addr,
+            server_addr
+        )]);
 
-        assert_eq!(v1, v2);
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
 
-        let stats = pipe.server.stats();
-        assert_eq!(stats.path_challenge_rx_count, 3);
+        assert_eq!(
+            pipe.client.send_on_path(
+                &mut buf,
+                Some(client_addr_2),
+                Some(server_addr)
+            ),
+            Err(Error::Done)
+        );
     }
 
     #[test]
@@ -16721,6 +25445,280 @@ mod tests {
         );
     }
 
+    #[test]
+    fn grease_quic_bit() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_grease_quic_bit(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.client.peer_transport_params.grease_quic_bit);
+        assert!(pipe.server.peer_transport_params.grease_quic_bit);
+
+        let recv_before = pipe.server.stats().recv;
+
+        let mut greased = 0;
+
+        for _ in 0..32 {
+            assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+
+            let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+            greased += flight.iter().filter(|(p, _)| p[0] & 0x40 == 0).count();
+
+            // Greased packets are accepted.
+            assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+            assert_eq!(pipe.advance(), Ok(()));
+        }
+
+        assert!(greased > 0);
+        assert!(pipe.server.stats().recv >= recv_before + 32);
+
+        // The fixed bit is never greased if the peer doesn't support it.
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(!pipe.client.peer_transport_params.grease_quic_bit);
+
+        for _ in 0..32 {
+            assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+
+            let flight = testing::emit_flight(&mut pipe.client).unwrap();
+            assert!(flight.iter().all(|(p, _)| p[0] & 0x40 != 0));
+
+            assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+            assert_eq!(pipe.advance(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn disable_active_migration() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(3);
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(2);
+        config.set_disable_active_migration(true);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+        let client_addr_3 = "127.0.0.1:9012".parse().unwrap();
+
+        // The client honors the server's transport parameter.
+        assert_eq!(
+            pipe.client.probe_path(client_addr_2, server_addr),
+            Err(Error::InvalidState)
+        );
+        assert_eq!(
+            pipe.client.migrate(client_addr_2, server_addr),
+            Err(Error::InvalidState)
+        );
+
+        // A client ignoring the parameter can't migrate to a new CID.
+        pipe.client.peer_transport_params.disable_active_migration = false;
+
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.path_event_next(), None);
+        assert_eq!(pipe.server.paths().collect::<Vec<_>>(), vec![(
+            server_addr,
+            client_addr
+        )]);
+
+        // NAT rebinding, where the client keeps using the same CID, is still
+        // validated.
+        assert_eq!(pipe.client.stream_send(0, b"data", true), Ok(4));
+
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+        flight
+            .iter_mut()
+            .for_each(|(_, si)| si.from = client_addr_3);
+
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::ReusedSourceConnectionId(
+                0,
+                (server_addr, client_addr),
+                (server_addr, client_addr_3)
+            ))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_3))
+        );
+    }
+
+    #[test]
+    fn nat_rebinding_keeps_rtt_estimate() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(3);
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(2);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let server_addr = testing::Pipe::server_addr();
+        let rebound_client_addr = "127.0.0.1:5678".parse().unwrap();
+        let migrated_client_addr = "127.0.0.2:5678".parse().unwrap();
+
+        let old_rtt = pipe.server.paths.get_active().unwrap().recovery.rtt();
+        assert_ne!(old_rtt, recovery::INITIAL_RTT);
+
+        // Only the client's port changes, so the RTT estimate is retained.
+        assert_eq!(pipe.client.stream_send(0, b"data", false), Ok(4));
+
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+        flight
+            .iter_mut()
+            .for_each(|(_, si)| si.from = rebound_client_addr);
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+
+        let pid = pipe
+            .server
+            .paths
+            .path_id_from_addrs(&(server_addr, rebound_client_addr))
+            .unwrap();
+        let path = pipe.server.paths.get(pid).unwrap();
+        assert!(!path.validated());
+        assert_eq!(path.recovery.rtt(), old_rtt);
+
+        // A change of IP address resets the RTT estimator.
+        assert_eq!(pipe.client.stream_send(0, b"data", false), Ok(4));
+
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+        flight
+            .iter_mut()
+            .for_each(|(_, si)| si.from = migrated_client_addr);
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+
+        let pid = pipe
+            .server
+            .paths
+            .path_id_from_addrs(&(server_addr, migrated_client_addr))
+            .unwrap();
+        let path = pipe.server.paths.get(pid).unwrap();
+        assert_eq!(path.recovery.rtt(), recovery::INITIAL_RTT);
+    }
+
+    #[test]
+    fn pkt_num_len_after_rebinding() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(3);
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(2);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let rebound_client_addr = "127.0.0.1:5678".parse().unwrap();
+
+        // Pretend the server has been sending for a long time.
+        let high_pn = 1 << 30;
+        pipe.server.pkt_num_spaces[packet::Epoch::Application].next_pkt_num =
+            high_pn;
+
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // None of the new packet numbers have been acknowledged, so the
+        // encoding needs to cover the whole gap.
+        assert_eq!(pipe.server.stream_send(0, b"b", false), Ok(1));
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        for (pkt, _) in &flight {
+            assert_eq!(testing::decode_pkt_num_len(&pipe.client, pkt), 4);
+        }
+        assert_eq!(testing::process_flight(&mut pipe.client, flight), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(
+            pipe.server.pkt_num_spaces[packet::Epoch::Application]
+                .largest_acked_pkt_num >=
+                Some(high_pn)
+        );
+
+        // After an idle period the client's NAT binding changes, and the
+        // server starts using a new path with no recovery state. Packet
+        // numbers are still short since they are based on the largest
+        // acknowledged packet.
+        assert_eq!(pipe.client.stream_send(0, b"c", false), Ok(1));
+
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+        flight
+            .iter_mut()
+            .for_each(|(_, si)| si.from = rebound_client_addr);
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(0, b"d", false), Ok(1));
+
+        let mut flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert!(flight.iter().any(|(_, si)| si.to == rebound_client_addr));
+        for (pkt, _) in &flight {
+            assert_eq!(testing::decode_pkt_num_len(&pipe.client, pkt), 1);
+        }
+
+        // The NAT translates the address back for the client.
+        flight
+            .iter_mut()
+            .for_each(|(_, si)| si.to = testing::Pipe::client_addr());
+        assert_eq!(testing::process_flight(&mut pipe.client, flight), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.client.stream_recv(0, &mut b), Ok((2, false)));
+        assert_eq!(&b[..2], b"bd");
+    }
+
     #[test]
     fn resilience_against_migration_attack() {
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -17191,6 +26189,7 @@ mod tests {
             token: pipe.client.token.clone(),
             versions: None,
             key_phase: pipe.client.key_phase,
+            spin_bit: false,
         };
         hdr.to_bytes(&mut b).expect("encode header");
         let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
@@ -17330,32 +26329,133 @@ mod tests {
     }
 }
 
+/// The buffer types used to parse and serialize the QUIC wire format.
+///
+/// This is re-exported so that applications implementing extensions (e.g.
+/// through [`ExtensionFrameHandler`]) can use the same version of the crate.
+///
+/// [`ExtensionFrameHandler`]: trait.ExtensionFrameHandler.html
+pub use octets;
+
+pub use crate::cid::ConnectionIdGenerator;
+pub use crate::cid::RandomConnectionIdGenerator;
+
+pub use crate::client_hello::AntiReplay;
+pub use crate::client_hello::ClientHello;
+pub use crate::client_hello::ClientHelloAction;
+pub use crate::client_hello::ClientHelloHandler;
+
+pub use crate::clock::Clock;
+pub use crate::clock::SystemClock;
+
+pub use crate::endpoint::QuicEndpoint;
+
+pub use crate::extension::ExtensionFrameHandler;
+
+pub use crate::crypto::constant_time_eq;
+pub use crate::crypto::derive_hdr_key;
+pub use crate::crypto::derive_pkt_iv;
+pub use crate::crypto::derive_pkt_key;
+pub use crate::crypto::expand_label;
+pub use crate::crypto::Algorithm;
+pub use crate::crypto::TokenKey;
+
+pub use crate::decrypt::DecryptedPacket;
+pub use crate::decrypt::Decryptor;
+
 pub use crate::packet::ConnectionId;
 pub use crate::packet::Header;
 pub use crate::packet::Type;
 
+#[cfg(feature = "instrumentation")]
+pub use crate::hooks::AckEvent;
+#[cfg(feature = "instrumentation")]
+pub use crate::hooks::AckHook;
+#[cfg(feature = "instrumentation")]
+pub use crate::hooks::CongestionEvent;
+#[cfg(feature = "instrumentation")]
+pub use crate::hooks::CongestionHook;
+#[cfg(feature = "instrumentation")]
+pub use crate::hooks::TxEvent;
+#[cfg(feature = "instrumentation")]
+pub use crate::hooks::TxHook;
+
+#[cfg(feature = "deterministic-rng")]
+pub use crate::rand::Rng;
+#[cfg(feature = "deterministic-rng")]
+pub use crate::rand::SeededRng;
+#[cfg(feature = "deterministic-rng")]
+pub use crate::rand::SystemRng;
+
 pub use crate::path::PathEvent;
 pub use crate::path::PathStats;
 pub use crate::path::SocketAddrIter;
+pub use crate::path::SpinBitMode;
+
+pub use crate::race::ConnectionRace;
+pub use crate::race::DEFAULT_RACE_ATTEMPT_DELAY;
+
+pub use crate::stateless_reset::StatelessReset;
+
+pub use crate::redact::set_log_payloads;
 
 pub use crate::recovery::congestion::CongestionControlAlgorithm;
+#[cfg(feature = "instrumentation")]
+pub use crate::recovery::CongestionTransition;
+#[cfg(feature = "instrumentation")]
+pub use crate::recovery::SlowStartExitReason;
 
+pub use crate::stream::StreamGroupStats;
 pub use crate::stream::StreamIter;
+pub use crate::stream::StreamReliability;
 
+mod ackfreq;
+#[cfg(feature = "btrace")]
+pub mod btrace;
+#[cfg(feature = "checkpoint")]
+mod checkpoint;
 mod cid;
+mod client_hello;
+mod clock;
+#[cfg(feature = "internal")]
+#[doc(hidden)]
+pub mod crypto;
+#[cfg(not(feature = "internal"))]
 mod crypto;
+mod decrypt;
 mod dgram;
+#[cfg(feature = "differential")]
+pub mod differential;
+mod endpoint;
+mod extension;
 #[cfg(feature = "ffi")]
 mod ffi;
 mod flowcontrol;
+#[cfg(feature = "internal")]
+#[doc(hidden)]
+pub mod frame;
+#[cfg(not(feature = "internal"))]
 mod frame;
 pub mod h3;
+#[cfg(feature = "instrumentation")]
+mod hooks;
 mod minmax;
+#[cfg(all(feature = "net", target_os = "linux"))]
+pub mod net;
+#[cfg(feature = "internal")]
+#[doc(hidden)]
+pub mod packet;
+#[cfg(not(feature = "internal"))]
 mod packet;
 mod path;
 mod pmtud;
+mod race;
 mod rand;
-mod ranges;
+pub mod ranges;
 mod recovery;
+mod redact;
+mod stateless_reset;
 mod stream;
+#[cfg(test)]
+mod test_vectors;
 mod tls;
@@ -63,6 +63,7 @@ struct MinmaxSample<T> {
     value: T,
 }
 
+#[derive(Clone)]
 pub struct Minmax<T> {
     estimate: [MinmaxSample<T>; 3],
 }
@@ -76,12 +77,9 @@ impl<T> Deref for Minmax<T> {
 }
 
 impl<T: PartialOrd + Copy> Minmax<T> {
-    pub fn new(val: T) -> Self {
+    pub fn new(time: Instant, val: T) -> Self {
         Minmax {
-            estimate: [MinmaxSample {
-                time: Instant::now(),
-                value: val,
-            }; 3],
+            estimate: [MinmaxSample { time, value: val }; 3],
         }
     }
 
@@ -183,7 +181,7 @@ mod tests {
 
     #[test]
     fn reset_filter_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Instant::now(), Duration::ZERO);
         let now = Instant::now();
         let rtt = Duration::from_millis(50);
 
@@ -202,7 +200,7 @@ mod tests {
 
     #[test]
     fn reset_filter_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(Instant::now(), 0);
         let now = Instant::now();
         let bw = 2000;
 
@@ -221,7 +219,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Instant::now(), Duration::ZERO);
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let win = Duration::from_millis(500);
@@ -245,7 +243,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(Instant::now(), 0);
         let bw_200 = 200;
         let bw_500 = 500;
         let win = Duration::from_millis(500);
@@ -269,7 +267,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Instant::now(), Duration::ZERO);
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let win = Duration::from_millis(500);
@@ -293,7 +291,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(Instant::now(), 0);
         let bw_200 = 200;
         let bw_500 = 500;
         let win = Duration::from_millis(500);
@@ -317,7 +315,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_estimates_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Instant::now(), Duration::ZERO);
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let rtt_23 = Duration::from_millis(23);
@@ -349,7 +347,7 @@ mod tests {
 
     #[test]
     fn get_windowed_min_estimates_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(Instant::now(), 0);
         let bw_500 = 500;
         let bw_400 = 400;
         let bw_300 = 300;
@@ -381,7 +379,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_estimates_rtt() {
-        let mut f = Minmax::new(Duration::ZERO);
+        let mut f = Minmax::new(Instant::now(), Duration::ZERO);
         let rtt_25 = Duration::from_millis(25);
         let rtt_24 = Duration::from_millis(24);
         let rtt_23 = Duration::from_millis(23);
@@ -413,7 +411,7 @@ mod tests {
 
     #[test]
     fn get_windowed_max_estimates_bandwidth() {
-        let mut f = Minmax::new(0);
+        let mut f = Minmax::new(Instant::now(), 0);
         let bw_500 = 500;
         let bw_400 = 400;
         let bw_300 = 300;
@@ -0,0 +1,1116 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Helpers for configuring UDP sockets for QUIC.
+//!
+//! Getting the most out of a QUIC connection requires a few socket options
+//! that are cumbersome to set and use through the standard library:
+//!
+//! * The local address each datagram was received on, which is needed to fill
+//!   [`RecvInfo`] correctly on sockets bound to a wildcard address.
+//! * The ECN codepoint of received datagrams.
+//! * The Don't Fragment bit, without which Path MTU Discovery probes might be
+//!   fragmented instead of dropped.
+//! * UDP Generic Receive Offload (GRO), which lets the kernel coalesce
+//!   datagrams of the same flow to reduce the per-datagram overhead.
+//!
+//! [`configure()`] enables those options on a socket, and [`recv_from()`]
+//! and [`send_to()`] use them to exchange datagrams along with their
+//! metadata. The helpers work with any type that exposes a raw file
+//! descriptor, such as `std::net::UdpSocket` or `mio::net::UdpSocket`.
+//!
+//! This module is only available on Linux.
+//!
+//! [`RecvInfo`]: ../struct.RecvInfo.html
+//! [`configure()`]: fn.configure.html
+//! [`recv_from()`]: fn.recv_from.html
+//! [`send_to()`]: fn.send_to.html
+
+use std::io;
+use std::mem;
+use std::ptr;
+use std::time;
+
+use std::collections::HashMap;
+
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::SocketAddrV4;
+use std::net::SocketAddrV6;
+
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+
+use libc::c_int;
+use libc::c_void;
+
+use slab::Slab;
+
+use crate::Config;
+use crate::Connection;
+use crate::ConnectionId;
+use crate::Header;
+use crate::RecvInfo;
+use crate::SendInfo;
+use crate::Type;
+
+/// The Not-ECT codepoint, for packets that don't support ECN.
+pub const ECN_NOT_ECT: u8 = 0b00;
+
+/// The ECT(1) codepoint.
+pub const ECN_ECT1: u8 = 0b01;
+
+/// The ECT(0) codepoint.
+pub const ECN_ECT0: u8 = 0b10;
+
+/// The CE codepoint, set by routers experiencing congestion.
+pub const ECN_CE: u8 = 0b11;
+
+const ECN_MASK: u8 = 0b11;
+
+// Large enough for a packet info, a traffic class and a GRO control message,
+// aligned like `cmsghdr`.
+type ControlBuffer = [u64; 16];
+
+/// The socket options enabled by [`configure()`].
+///
+/// Options that are not supported by the running kernel are left disabled.
+///
+/// [`configure()`]: fn.configure.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SocketCapabilities {
+    /// Whether the local address of received datagrams is reported.
+    pub pktinfo: bool,
+
+    /// Whether the ECN codepoint of received datagrams is reported.
+    pub ecn: bool,
+
+    /// Whether sent datagrams have the Don't Fragment bit set.
+    pub dont_fragment: bool,
+
+    /// Whether received datagrams can be coalesced by GRO.
+    pub gro: bool,
+}
+
+/// Metadata about datagrams received with [`recv_from()`].
+///
+/// [`recv_from()`]: fn.recv_from.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// The number of bytes received.
+    pub len: usize,
+
+    /// The size of each datagram, when several datagrams were coalesced by
+    /// GRO. The last one might be shorter.
+    pub segment_size: Option<usize>,
+
+    /// The ECN codepoint of the datagrams.
+    pub ecn: u8,
+
+    /// The addresses to pass to [`recv()`] along with the datagrams.
+    ///
+    /// [`recv()`]: ../struct.Connection.html#method.recv
+    pub info: RecvInfo,
+}
+
+impl RecvMeta {
+    /// Returns the lengths of the individual datagrams received.
+    pub fn datagram_lens(&self) -> impl Iterator<Item = usize> {
+        let len = self.len;
+        let segment_size = self.segment_size.unwrap_or(len).max(1);
+
+        (0..len)
+            .step_by(segment_size)
+            .map(move |off| segment_size.min(len - off))
+    }
+}
+
+/// A buffer for datagrams received with [`recv_batch()`].
+///
+/// [`recv_batch()`]: fn.recv_batch.html
+#[derive(Clone, Debug)]
+pub struct Datagram {
+    /// The buffer the datagrams are received into.
+    pub buf: Vec<u8>,
+
+    /// Metadata about the datagrams currently held in the buffer.
+    pub meta: RecvMeta,
+}
+
+impl Datagram {
+    /// Creates an empty buffer that can receive up to `capacity` bytes.
+    pub fn new(capacity: usize) -> Datagram {
+        let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+
+        Datagram {
+            buf: vec![0; capacity],
+
+            meta: RecvMeta {
+                len: 0,
+                segment_size: None,
+                ecn: ECN_NOT_ECT,
+                info: RecvInfo {
+                    from: unspecified,
+                    to: unspecified,
+                },
+            },
+        }
+    }
+
+    /// Returns the bytes received.
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.meta.len]
+    }
+}
+
+/// Enables the socket options useful for QUIC on the given UDP socket.
+///
+/// This requests the local address and ECN codepoint of received datagrams
+/// to be reported, sets the Don't Fragment bit on sent datagrams, and
+/// enables GRO. Options for IPv4 are also set on IPv6 sockets, so that they
+/// apply to IPv4-mapped traffic on dual-stack sockets.
+///
+/// Options the kernel doesn't support are skipped, and the ones that were
+/// enabled are returned. An error is only returned if the address family
+/// of the socket can't be determined.
+pub fn configure<S: AsRawFd>(socket: &S) -> io::Result<SocketCapabilities> {
+    let fd = socket.as_raw_fd();
+
+    let family = socket_family(fd)?;
+
+    let mut caps = SocketCapabilities::default();
+
+    let v4_pktinfo = set_int(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, 1);
+    let v4_ecn = set_int(fd, libc::IPPROTO_IP, libc::IP_RECVTOS, 1);
+    let v4_df = set_int(
+        fd,
+        libc::IPPROTO_IP,
+        libc::IP_MTU_DISCOVER,
+        libc::IP_PMTUDISC_PROBE,
+    );
+
+    if family == libc::AF_INET6 {
+        caps.pktinfo = set_int(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1);
+        caps.ecn = set_int(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1);
+        caps.dont_fragment = set_int(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        );
+    } else {
+        caps.pktinfo = v4_pktinfo;
+        caps.ecn = v4_ecn;
+        caps.dont_fragment = v4_df;
+    }
+
+    caps.gro = set_int(fd, libc::SOL_UDP, libc::UDP_GRO, 1);
+
+    Ok(caps)
+}
+
+/// Receives datagrams from the given socket, along with their metadata.
+///
+/// The `local` parameter is the address the socket is bound to. Its port is
+/// used for the local address reported in [`RecvMeta::info`], and the whole
+/// address is used when the socket doesn't report the local address of
+/// datagrams.
+///
+/// If GRO is enabled, several datagrams might be returned at once, so `buf`
+/// should be large enough to hold them (e.g. 64KB). They need to be passed
+/// to [`recv()`] separately, see [`RecvMeta::datagram_lens()`].
+///
+/// [`RecvMeta::info`]: struct.RecvMeta.html#structfield.info
+/// [`RecvMeta::datagram_lens()`]: struct.RecvMeta.html#method.datagram_lens
+/// [`recv()`]: ../struct.Connection.html#method.recv
+pub fn recv_from<S: AsRawFd>(
+    socket: &S, buf: &mut [u8], local: SocketAddr,
+) -> io::Result<RecvMeta> {
+    let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut control: ControlBuffer = [0; 16];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut name as *mut _ as *mut c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let from = std_addr_from_c(&name)?;
+
+    Ok(recv_meta(&msg, len as usize, from, local))
+}
+
+/// Receives a batch of datagrams from the given socket with a single
+/// system call, along with their metadata.
+///
+/// This is like [`recv_from()`], but fills as many of `datagrams` as there
+/// are datagrams ready to be read, waiting only for the first one if the
+/// socket is blocking. Each buffer might contain several datagrams
+/// coalesced by GRO. The number of buffers filled is returned.
+///
+/// [`recv_from()`]: fn.recv_from.html
+pub fn recv_batch<S: AsRawFd>(
+    socket: &S, datagrams: &mut [Datagram], local: SocketAddr,
+) -> io::Result<usize> {
+    let n = datagrams.len();
+
+    let mut names: Vec<libc::sockaddr_storage> =
+        vec![unsafe { mem::zeroed() }; n];
+    let mut controls: Vec<ControlBuffer> = vec![[0; 16]; n];
+
+    let mut iovs: Vec<libc::iovec> = datagrams
+        .iter_mut()
+        .map(|d| libc::iovec {
+            iov_base: d.buf.as_mut_ptr() as *mut c_void,
+            iov_len: d.buf.len(),
+        })
+        .collect();
+
+    let mut msgs: Vec<libc::mmsghdr> = (0..n)
+        .map(|i| {
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+
+            let hdr = &mut msg.msg_hdr;
+            hdr.msg_name = &mut names[i] as *mut _ as *mut c_void;
+            hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            hdr.msg_iov = &mut iovs[i];
+            hdr.msg_iovlen = 1;
+            hdr.msg_control = controls[i].as_mut_ptr() as *mut c_void;
+            hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+
+            msg
+        })
+        .collect();
+
+    let received = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            n as _,
+            libc::MSG_WAITFORONE as _,
+            ptr::null_mut(),
+        )
+    };
+
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let received = received as usize;
+
+    for (i, msg) in msgs.iter().take(received).enumerate() {
+        let from = std_addr_from_c(&names[i])?;
+
+        datagrams[i].meta =
+            recv_meta(&msg.msg_hdr, msg.msg_len as usize, from, local);
+    }
+
+    Ok(received)
+}
+
+/// Builds the metadata of a received datagram from its control messages.
+fn recv_meta(
+    msg: &libc::msghdr, len: usize, from: SocketAddr, local: SocketAddr,
+) -> RecvMeta {
+    let mut meta = RecvMeta {
+        len,
+        segment_size: None,
+        ecn: ECN_NOT_ECT,
+        info: RecvInfo { from, to: local },
+    };
+
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+
+    while !cmsg.is_null() {
+        let (level, ty) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+        let data = unsafe { libc::CMSG_DATA(cmsg) };
+
+        match (level, ty) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let pktinfo: libc::in_pktinfo =
+                    unsafe { ptr::read_unaligned(data as *const _) };
+
+                let ip = Ipv4Addr::from(u32::from_be(pktinfo.ipi_addr.s_addr));
+                meta.info.to = SocketAddr::new(ip.into(), local.port());
+            },
+
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let pktinfo: libc::in6_pktinfo =
+                    unsafe { ptr::read_unaligned(data as *const _) };
+
+                let ip = Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr);
+                meta.info.to = SocketAddr::new(ip.into(), local.port());
+            },
+
+            // The TOS is reported as a single byte for IPv4.
+            (libc::IPPROTO_IP, libc::IP_TOS) => {
+                meta.ecn = unsafe { *data } & ECN_MASK;
+            },
+
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                let tclass: c_int =
+                    unsafe { ptr::read_unaligned(data as *const _) };
+
+                meta.ecn = tclass as u8 & ECN_MASK;
+            },
+
+            (libc::SOL_UDP, libc::UDP_GRO) => {
+                let segment_size: c_int =
+                    unsafe { ptr::read_unaligned(data as *const _) };
+
+                meta.segment_size = Some(segment_size as usize);
+            },
+
+            _ => (),
+        }
+
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+
+    // On dual-stack sockets IPv4 datagrams come from IPv4-mapped IPv6
+    // addresses, while IP_PKTINFO reports a plain IPv4 local address. Map it
+    // as well, so that both addresses match those of the connection's path.
+    if let (SocketAddr::V6(_), SocketAddr::V4(to)) = (from, meta.info.to) {
+        meta.info.to =
+            SocketAddr::new(to.ip().to_ipv6_mapped().into(), to.port());
+    }
+
+    meta
+}
+
+/// Sends a datagram on the given socket as described by `send_info`.
+///
+/// Unless it is unspecified, `send_info.from` is used as the source address
+/// of the datagram, which is needed on sockets bound to a wildcard address
+/// to reply from the address the peer sent its datagrams to. The `ecn`
+/// parameter is the ECN codepoint to mark the datagram with, such as
+/// [`ECN_ECT0`].
+///
+/// The send time in `send_info` is ignored.
+///
+/// [`ECN_ECT0`]: constant.ECN_ECT0.html
+pub fn send_to<S: AsRawFd>(
+    socket: &S, buf: &[u8], send_info: &SendInfo, ecn: u8,
+) -> io::Result<usize> {
+    let (mut name, name_len) = std_addr_to_c(&send_info.to);
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut control: ControlBuffer = [0; 16];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut name as *mut _ as *mut c_void;
+    msg.msg_namelen = name_len;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+
+    let mut control_len = 0;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+
+    if !send_info.from.ip().is_unspecified() {
+        control_len += match send_info.from {
+            SocketAddr::V4(from) => {
+                let pktinfo = libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: libc::in_addr {
+                        s_addr: u32::from(*from.ip()).to_be(),
+                    },
+                    ipi_addr: libc::in_addr { s_addr: 0 },
+                };
+
+                unsafe {
+                    put_cmsg(cmsg, libc::IPPROTO_IP, libc::IP_PKTINFO, pktinfo)
+                }
+            },
+
+            SocketAddr::V6(from) => {
+                let pktinfo = libc::in6_pktinfo {
+                    ipi6_addr: libc::in6_addr {
+                        s6_addr: from.ip().octets(),
+                    },
+                    ipi6_ifindex: 0,
+                };
+
+                unsafe {
+                    put_cmsg(
+                        cmsg,
+                        libc::IPPROTO_IPV6,
+                        libc::IPV6_PKTINFO,
+                        pktinfo,
+                    )
+                }
+            },
+        };
+
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    if ecn & ECN_MASK != ECN_NOT_ECT {
+        let tos = c_int::from(ecn & ECN_MASK);
+
+        control_len += match send_info.to {
+            SocketAddr::V4(_) => unsafe {
+                put_cmsg(cmsg, libc::IPPROTO_IP, libc::IP_TOS, tos)
+            },
+
+            SocketAddr::V6(_) => unsafe {
+                put_cmsg(cmsg, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)
+            },
+        };
+    }
+
+    if control_len == 0 {
+        msg.msg_control = ptr::null_mut();
+    }
+
+    msg.msg_controllen = control_len as _;
+
+    let written = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+
+    if written < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(written as usize)
+}
+
+/// Dispatches received datagrams to server-side connections.
+///
+/// This is a minimal connection manager for servers: connections are
+/// created for Initial packets of supported versions that are carried in
+/// datagrams of at least 1200 bytes, version negotiation is done for other
+/// versions, and packets of existing connections are routed based on their
+/// destination connection ID. Address validation with Retry packets and
+/// issuing additional connection IDs are left out, so servers that need them
+/// should manage connections themselves.
+///
+/// A typical event loop reads batches of datagrams with [`recv_batch()`]
+/// and passes them to [`process_datagrams()`], handles application data on
+/// the [`connections()`], and then calls [`send()`].
+///
+/// [`recv_batch()`]: fn.recv_batch.html
+/// [`process_datagrams()`]: struct.Listener.html#method.process_datagrams
+/// [`connections()`]: struct.Listener.html#method.connections
+/// [`send()`]: struct.Listener.html#method.send
+pub struct Listener {
+    config: Config,
+
+    conns: Slab<Connection>,
+
+    ids: HashMap<ConnectionId<'static>, usize>,
+
+    stateless: Vec<(Vec<u8>, SendInfo)>,
+}
+
+impl Listener {
+    /// Creates a listener accepting connections with the given
+    /// configuration.
+    pub fn new(config: Config) -> Listener {
+        Listener {
+            config,
+            conns: Slab::new(),
+            ids: HashMap::new(),
+            stateless: Vec::new(),
+        }
+    }
+
+    /// Processes received datagrams, creating connections as needed.
+    ///
+    /// Datagrams coalesced by GRO are split before being passed to the
+    /// matching connection. Datagrams that can't be attributed to a
+    /// connection are dropped. Returns the number of datagrams that were
+    /// passed to a connection.
+    pub fn process_datagrams(&mut self, datagrams: &mut [Datagram]) -> usize {
+        let mut processed = 0;
+
+        for d in datagrams.iter_mut() {
+            let meta = d.meta;
+
+            let mut off = 0;
+
+            for len in meta.datagram_lens() {
+                let buf = &mut d.buf[off..off + len];
+                off += len;
+
+                if self.process_datagram(buf, meta.info) {
+                    processed += 1;
+                }
+            }
+        }
+
+        processed
+    }
+
+    fn process_datagram(&mut self, buf: &mut [u8], info: RecvInfo) -> bool {
+        let hdr = match Header::from_slice(buf, crate::MAX_CONN_ID_LEN) {
+            Ok(v) => v,
+
+            Err(_) => return false,
+        };
+
+        let (idx, is_new) = match self.ids.get(&hdr.dcid) {
+            Some(idx) => (*idx, false),
+
+            None => {
+                if hdr.ty != Type::Initial {
+                    return false;
+                }
+
+                // Clients must pad datagrams carrying Initial packets, so
+                // anything smaller can't start a connection.
+                if buf.len() < crate::MIN_CLIENT_INITIAL_LEN {
+                    return false;
+                }
+
+                if !crate::version_is_supported(hdr.version) {
+                    let mut out = vec![0; crate::MIN_CLIENT_INITIAL_LEN];
+
+                    if let Ok(len) =
+                        crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)
+                    {
+                        out.truncate(len);
+
+                        self.stateless.push((out, SendInfo {
+                            from: info.to,
+                            to: info.from,
+                            at: time::Instant::now(),
+                        }));
+                    }
+
+                    return false;
+                }
+
+                let mut scid = [0; crate::MAX_CONN_ID_LEN];
+                self.config.rng.fill_bytes(&mut scid);
+                let scid: ConnectionId<'static> = scid.to_vec().into();
+
+                let conn = match crate::accept(
+                    &scid,
+                    None,
+                    info.to,
+                    info.from,
+                    &mut self.config,
+                ) {
+                    Ok(v) => v,
+
+                    Err(_) => return false,
+                };
+
+                let idx = self.conns.insert(conn);
+
+                self.ids.insert(scid, idx);
+                self.ids.insert(hdr.dcid.into_owned(), idx);
+
+                (idx, true)
+            },
+        };
+
+        if self.conns[idx].recv(buf, info).is_err() {
+            // Don't keep connections that never processed a packet.
+            if is_new {
+                self.remove(idx);
+            }
+
+            return false;
+        }
+
+        true
+    }
+
+    fn remove(&mut self, idx: usize) {
+        self.conns.remove(idx);
+        self.ids.retain(|_, v| *v != idx);
+    }
+
+    /// Returns an iterator over the connections of the listener.
+    pub fn connections(&mut self) -> impl Iterator<Item = &mut Connection> {
+        self.conns.iter_mut().map(|(_, c)| c)
+    }
+
+    /// Returns the amount of time until the next connection timeout event.
+    ///
+    /// See [`Connection::timeout()`].
+    ///
+    /// [`Connection::timeout()`]: ../struct.Connection.html#method.timeout
+    pub fn timeout(&self) -> Option<time::Duration> {
+        self.conns.iter().filter_map(|(_, c)| c.timeout()).min()
+    }
+
+    /// Processes the timeout events of the connections whose timer expired.
+    pub fn on_timeout(&mut self) {
+        for (_, c) in self.conns.iter_mut() {
+            if c.timeout() == Some(time::Duration::ZERO) {
+                c.on_timeout();
+            }
+        }
+    }
+
+    /// Sends pending packets of all connections on the given socket.
+    ///
+    /// The `out` buffer is used to write the packets, and should be large
+    /// enough for the maximum UDP payload size configured. Closed
+    /// connections are removed from the listener afterwards. Sending stops
+    /// early if the socket would block, in which case the packets that
+    /// weren't sent are treated as lost by their connections. Returns the
+    /// number of datagrams sent.
+    pub fn send<S: AsRawFd>(
+        &mut self, socket: &S, out: &mut [u8],
+    ) -> io::Result<usize> {
+        let mut sent = 0;
+
+        let res = self.send_pending(socket, out, &mut sent);
+
+        let closed: Vec<usize> = self
+            .conns
+            .iter()
+            .filter(|(_, c)| c.is_closed())
+            .map(|(idx, _)| idx)
+            .collect();
+
+        for idx in closed {
+            self.remove(idx);
+        }
+
+        match res {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+
+            _ => Ok(sent),
+        }
+    }
+
+    fn send_pending<S: AsRawFd>(
+        &mut self, socket: &S, out: &mut [u8], sent: &mut usize,
+    ) -> io::Result<()> {
+        for (pkt, send_info) in self.stateless.drain(..) {
+            send_to(socket, &pkt, &send_info, ECN_NOT_ECT)?;
+
+            *sent += 1;
+        }
+
+        for (_, c) in self.conns.iter_mut() {
+            while let Ok((len, send_info)) = c.send(out) {
+                send_to(socket, &out[..len], &send_info, ECN_NOT_ECT)?;
+
+                *sent += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a control message at `cmsg` and returns the space it uses.
+///
+/// # Safety
+///
+/// `cmsg` must point to enough space in the control buffer for `data`.
+unsafe fn put_cmsg<T>(
+    cmsg: *mut libc::cmsghdr, level: c_int, ty: c_int, data: T,
+) -> usize {
+    let len = mem::size_of::<T>() as u32;
+
+    (*cmsg).cmsg_level = level;
+    (*cmsg).cmsg_type = ty;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+
+    ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut T, data);
+
+    libc::CMSG_SPACE(len) as usize
+}
+
+fn set_int(fd: RawFd, level: c_int, name: c_int, value: c_int) -> bool {
+    let rc = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const _ as *const c_void,
+            mem::size_of::<c_int>() as libc::socklen_t,
+        )
+    };
+
+    rc == 0
+}
+
+fn socket_family(fd: RawFd) -> io::Result<c_int> {
+    let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+    let rc =
+        unsafe { libc::getsockname(fd, &mut name as *mut _ as *mut _, &mut len) };
+
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(c_int::from(name.ss_family))
+}
+
+fn std_addr_from_c(addr: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match c_int::from(addr.ss_family) {
+        libc::AF_INET => {
+            let sin: libc::sockaddr_in =
+                unsafe { ptr::read(addr as *const _ as *const _) };
+
+            let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            let port = u16::from_be(sin.sin_port);
+
+            Ok(SocketAddrV4::new(ip, port).into())
+        },
+
+        libc::AF_INET6 => {
+            let sin6: libc::sockaddr_in6 =
+                unsafe { ptr::read(addr as *const _ as *const _) };
+
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            let port = u16::from_be(sin6.sin6_port);
+
+            Ok(SocketAddrV6::new(
+                ip,
+                port,
+                sin6.sin6_flowinfo,
+                sin6.sin6_scope_id,
+            )
+            .into())
+        },
+
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported address family",
+        )),
+    }
+}
+
+fn std_addr_to_c(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(*addr.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+
+            unsafe {
+                ptr::write(&mut storage as *mut _ as *mut libc::sockaddr_in, sin)
+            };
+
+            mem::size_of::<libc::sockaddr_in>()
+        },
+
+        SocketAddr::V6(addr) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+
+            unsafe {
+                ptr::write(
+                    &mut storage as *mut _ as *mut libc::sockaddr_in6,
+                    sin6,
+                )
+            };
+
+            mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::UdpSocket;
+
+    #[test]
+    fn configure_and_exchange() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("0.0.0.0:0").unwrap();
+
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+        let b_loopback =
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), b_addr.port());
+
+        let caps = configure(&b).unwrap();
+        assert!(caps.pktinfo);
+        assert!(caps.ecn);
+
+        let send_info = SendInfo {
+            from: a_addr,
+            to: b_loopback,
+            at: std::time::Instant::now(),
+        };
+
+        assert_eq!(send_to(&a, b"hello", &send_info, ECN_ECT0).unwrap(), 5);
+
+        let mut buf = [0; 65535];
+        let meta = recv_from(&b, &mut buf, b_addr).unwrap();
+
+        assert_eq!(meta.len, 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(meta.ecn, ECN_ECT0);
+        assert_eq!(meta.info.from, a_addr);
+
+        // The wildcard address is replaced by the actual local address.
+        assert_eq!(meta.info.to, b_loopback);
+        assert_eq!(meta.datagram_lens().collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn dual_stack_addresses() {
+        // Skip if IPv6 isn't available.
+        let b = match UdpSocket::bind("[::]:0") {
+            Ok(v) => v,
+
+            Err(_) => return,
+        };
+
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        configure(&b).unwrap();
+        b.set_read_timeout(Some(time::Duration::from_secs(1)))
+            .unwrap();
+
+        a.send_to(b"hello", (Ipv4Addr::LOCALHOST, b_addr.port()))
+            .unwrap();
+
+        let mut buf = [0; 65535];
+
+        // Skip if the socket only accepts IPv6 traffic.
+        let meta = match recv_from(&b, &mut buf, b_addr) {
+            Ok(v) => v,
+
+            Err(_) => return,
+        };
+
+        // Both addresses are IPv4-mapped IPv6 addresses.
+        let mapped = Ipv4Addr::LOCALHOST.to_ipv6_mapped();
+
+        assert_eq!(
+            meta.info.from,
+            SocketAddr::new(mapped.into(), a_addr.port())
+        );
+        assert_eq!(meta.info.to, SocketAddr::new(mapped.into(), b_addr.port()));
+    }
+
+    #[test]
+    fn datagram_lens() {
+        let meta = RecvMeta {
+            len: 2500,
+            segment_size: Some(1200),
+            ecn: ECN_NOT_ECT,
+            info: RecvInfo {
+                from: "127.0.0.1:1234".parse().unwrap(),
+                to: "127.0.0.1:4321".parse().unwrap(),
+            },
+        };
+
+        assert_eq!(meta.datagram_lens().collect::<Vec<_>>(), vec![
+            1200, 1200, 100
+        ]);
+    }
+
+    fn config() -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config
+    }
+
+    #[test]
+    fn listener_batch() {
+        let timeout = Some(time::Duration::from_millis(50));
+
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server_socket.set_read_timeout(timeout).unwrap();
+        let server_addr = server_socket.local_addr().unwrap();
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(timeout).unwrap();
+        let client_addr = client_socket.local_addr().unwrap();
+
+        configure(&server_socket).unwrap();
+
+        let mut listener = Listener::new(config());
+
+        let mut scid = [0; crate::MAX_CONN_ID_LEN];
+        crate::rand::rand_bytes(&mut scid);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let mut client = crate::connect(
+            Some("quic.tech"),
+            &scid,
+            client_addr,
+            server_addr,
+            &mut config(),
+        )
+        .unwrap();
+
+        let mut out = [0; 65535];
+        let mut datagrams = vec![Datagram::new(65535); 8];
+
+        for _ in 0..10 {
+            while let Ok((len, send_info)) = client.send(&mut out) {
+                send_to(&client_socket, &out[..len], &send_info, ECN_NOT_ECT)
+                    .unwrap();
+            }
+
+            let n = recv_batch(&server_socket, &mut datagrams, server_addr)
+                .unwrap_or(0);
+
+            // Several packets of the same connection are processed at once.
+            listener.process_datagrams(&mut datagrams[..n]);
+
+            listener.send(&server_socket, &mut out).unwrap();
+
+            while let Ok(meta) = recv_from(&client_socket, &mut out, client_addr)
+            {
+                client.recv(&mut out[..meta.len], meta.info).unwrap();
+            }
+
+            if client.is_established() {
+                break;
+            }
+        }
+
+        assert!(client.is_established());
+        assert_eq!(listener.connections().count(), 1);
+        assert!(listener.connections().all(|c| c.is_established()));
+
+        // The server connection starts draining when the client closes.
+        assert_eq!(client.close(false, 0x0, b""), Ok(()));
+
+        while let Ok((len, send_info)) = client.send(&mut out) {
+            send_to(&client_socket, &out[..len], &send_info, ECN_NOT_ECT)
+                .unwrap();
+        }
+
+        let n = recv_batch(&server_socket, &mut datagrams, server_addr).unwrap();
+        assert_eq!(listener.process_datagrams(&mut datagrams[..n]), n);
+
+        assert!(listener.connections().all(|c| c.is_draining()));
+    }
+
+    #[test]
+    fn listener_drops_invalid_initials() {
+        let client_addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let server_addr: SocketAddr = "127.0.0.1:4321".parse().unwrap();
+
+        let info = RecvInfo {
+            from: client_addr,
+            to: server_addr,
+        };
+
+        let mut listener = Listener::new(config());
+
+        let mut scid = [0; crate::MAX_CONN_ID_LEN];
+        crate::rand::rand_bytes(&mut scid);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let mut client = crate::connect(
+            Some("quic.tech"),
+            &scid,
+            client_addr,
+            server_addr,
+            &mut config(),
+        )
+        .unwrap();
+
+        let mut out = [0; 65535];
+        let (len, _) = client.send(&mut out).unwrap();
+        assert!(len >= crate::MIN_CLIENT_INITIAL_LEN);
+
+        // Initial packets in datagrams that are too small are dropped.
+        let mut pkt = out[..crate::MIN_CLIENT_INITIAL_LEN - 1].to_vec();
+        assert!(!listener.process_datagram(&mut pkt, info));
+        assert_eq!(listener.connections().count(), 0);
+
+        // Connections that fail to process their first packet are removed.
+        let mut pkt = out[..len].to_vec();
+
+        // Make the packet's length exceed the datagram's.
+        let len_off =
+            1 + 4 + 1 + client.destination_id().len() + 1 + scid.len() + 1;
+        pkt[len_off] = 0x7f;
+        pkt[len_off + 1] = 0xff;
+
+        assert!(!listener.process_datagram(&mut pkt, info));
+        assert_eq!(listener.connections().count(), 0);
+        assert!(listener.ids.is_empty());
+
+        // A valid Initial creates a connection.
+        let mut pkt = out[..len].to_vec();
+        assert!(listener.process_datagram(&mut pkt, info));
+        assert_eq!(listener.connections().count(), 1);
+    }
+}
@@ -24,6 +24,7 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::convert::TryFrom;
 use std::fmt::Display;
 use std::ops::Index;
 use std::ops::IndexMut;
@@ -38,10 +39,12 @@ use crate::Result;
 use crate::crypto;
 use crate::rand;
 use crate::ranges;
+use crate::redact::Redacted;
 use crate::stream;
 
 const FORM_BIT: u8 = 0x80;
 const FIXED_BIT: u8 = 0x40;
+const SPIN_BIT: u8 = 0x20;
 const KEY_PHASE_BIT: u8 = 0x04;
 
 const TYPE_MASK: u8 = 0x30;
@@ -51,7 +54,7 @@ pub const MAX_CID_LEN: u8 = 20;
 
 pub const MAX_PKT_NUM_LEN: usize = 4;
 
-const SAMPLE_LEN: usize = 16;
+pub const SAMPLE_LEN: usize = 16;
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Epoch {
@@ -315,6 +318,9 @@ pub struct Header<'a> {
     /// The key phase bit of the packet. It's only meaningful after the header
     /// protection is removed.
     pub(crate) key_phase: bool,
+
+    /// The latency spin bit of the packet. Only present in `Short` packets.
+    pub(crate) spin_bit: bool,
 }
 
 impl<'a> Header<'a> {
@@ -362,6 +368,7 @@ impl<'a> Header<'a> {
                 token: None,
                 versions: None,
                 key_phase: false,
+                spin_bit: first & SPIN_BIT != 0,
             });
         }
 
@@ -380,17 +387,8 @@ impl<'a> Header<'a> {
             }
         };
 
-        let dcid_len = b.get_u8()?;
-        if crate::version_is_supported(version) && dcid_len > MAX_CID_LEN {
-            return Err(Error::InvalidPacket);
-        }
-        let dcid = b.get_bytes(dcid_len as usize)?.to_vec();
-
-        let scid_len = b.get_u8()?;
-        if crate::version_is_supported(version) && scid_len > MAX_CID_LEN {
-            return Err(Error::InvalidPacket);
-        }
-        let scid = b.get_bytes(scid_len as usize)?.to_vec();
+        let dcid = get_cid_with_len(b, version)?;
+        let scid = get_cid_with_len(b, version)?;
 
         // End of invariants.
 
@@ -429,13 +427,14 @@ impl<'a> Header<'a> {
         Ok(Header {
             ty,
             version,
-            dcid: dcid.into(),
-            scid: scid.into(),
+            dcid,
+            scid,
             pkt_num: 0,
             pkt_num_len: 0,
             token,
             versions,
             key_phase: false,
+            spin_bit: false,
         })
     }
 
@@ -453,6 +452,11 @@ impl<'a> Header<'a> {
             // Set fixed bit.
             first |= FIXED_BIT;
 
+            // Set spin bit.
+            if self.spin_bit {
+                first |= SPIN_BIT;
+            }
+
             // Set key phase bit.
             if self.key_phase {
                 first |= KEY_PHASE_BIT;
@@ -481,11 +485,8 @@ impl<'a> Header<'a> {
 
         out.put_u32(self.version)?;
 
-        out.put_u8(self.dcid.len() as u8)?;
-        out.put_bytes(&self.dcid)?;
-
-        out.put_u8(self.scid.len() as u8)?;
-        out.put_bytes(&self.scid)?;
+        put_cid_with_len(out, &self.dcid)?;
+        put_cid_with_len(out, &self.scid)?;
 
         // Only Initial and Retry packets have a token.
         match self.ty {
@@ -517,7 +518,7 @@ impl<'a> Header<'a> {
     /// Returns true if the packet has a long header.
     ///
     /// The `b` parameter represents the first byte of the QUIC header.
-    fn is_long(b: u8) -> bool {
+    pub(crate) fn is_long(b: u8) -> bool {
         b & FORM_BIT != 0
     }
 }
@@ -537,10 +538,7 @@ impl<'a> std::fmt::Debug for Header<'a> {
         }
 
         if let Some(ref token) = self.token {
-            write!(f, " token=")?;
-            for b in token {
-                write!(f, "{b:02x}")?;
-            }
+            write!(f, " token={}", Redacted(token))?;
         }
 
         if let Some(ref versions) = self.versions {
@@ -549,18 +547,33 @@ impl<'a> std::fmt::Debug for Header<'a> {
 
         if self.ty == Type::Short {
             write!(f, " key_phase={}", self.key_phase)?;
+            write!(f, " spin_bit={}", self.spin_bit)?;
         }
 
         Ok(())
     }
 }
 
-pub fn pkt_num_len(pn: u64, largest_acked: u64) -> usize {
-    let num_unacked: u64 = pn.saturating_sub(largest_acked) + 1;
-    // computes ceil of num_unacked.log2()
-    let min_bits = u64::BITS - num_unacked.leading_zeros();
-    // get the num len in bytes
-    ((min_bits + 7) / 8) as usize
+/// Returns the number of bytes needed to encode the given packet number.
+///
+/// This follows the sample algorithm in RFC 9000, Appendix A.2: the encoding
+/// needs to represent at least twice the range between the largest
+/// acknowledged packet number (if any) and the packet number being sent, so
+/// that the peer can decode it unambiguously.
+pub fn pkt_num_len(pn: u64, largest_acked: Option<u64>) -> usize {
+    let num_unacked = match largest_acked {
+        Some(largest_acked) => pn.saturating_sub(largest_acked),
+
+        None => pn + 1,
+    };
+
+    // Computes ceil(log2(num_unacked)) + 1.
+    let min_bits = u64::BITS - num_unacked.saturating_sub(1).leading_zeros() + 1;
+
+    // Round up to bytes, but never exceed the maximum packet number length.
+    let len = ((min_bits + 7) / 8) as usize;
+
+    len.clamp(1, MAX_PKT_NUM_LEN)
 }
 
 pub fn decrypt_hdr(
@@ -587,24 +600,23 @@ pub fn decrypt_hdr(
 
     let pn_len = usize::from((first & PKT_NUM_MASK) + 1);
 
-    let ciphertext = &mut ciphertext[..pn_len];
-
-    for i in 0..pn_len {
-        ciphertext[i] ^= mask[i + 1];
-    }
-
-    // Extract packet number corresponding to the decoded length.
-    let pn = match pn_len {
-        1 => u64::from(b.get_u8()?),
+    // The packet number length is protected, so it must not affect timing
+    // (see RFC 9001, Section 9.5). All of the possible packet number bytes are
+    // unmasked, but only those that are part of the packet number are written
+    // back, and the packet number is then extracted without branching.
+    let mut pn_buf = [0; MAX_PKT_NUM_LEN];
 
-        2 => u64::from(b.get_u16()?),
+    for i in 0..MAX_PKT_NUM_LEN {
+        let keep = u8::from(i < pn_len).wrapping_neg();
 
-        3 => u64::from(b.get_u24()?),
+        pn_buf[i] = ciphertext[i] ^ mask[i + 1];
+        ciphertext[i] ^= mask[i + 1] & keep;
+    }
 
-        4 => u64::from(b.get_u32()?),
+    let pn = u32::from_be_bytes(pn_buf) >> (8 * (MAX_PKT_NUM_LEN - pn_len));
+    let pn = u64::from(pn);
 
-        _ => return Err(Error::InvalidPacket),
-    };
+    b.skip(pn_len)?;
 
     // Write decrypted first byte back into the input buffer.
     let (mut first_buf, _) = b.split_at(1)?;
@@ -620,6 +632,14 @@ pub fn decrypt_hdr(
     Ok(())
 }
 
+/// Recovers a full packet number from its truncated encoding.
+///
+/// This follows the sample algorithm in RFC 9000, Appendix A.3, picking the
+/// packet number closest to the next expected one, without ever going below
+/// zero or above 2^62 - 1.
+///
+/// Both inputs come from the protected part of the header, so the candidate
+/// is adjusted using masks instead of branches.
 pub fn decode_pkt_num(largest_pn: u64, truncated_pn: u64, pn_len: usize) -> u64 {
     let pn_nbits = pn_len * 8;
     let expected_pn = largest_pn + 1;
@@ -628,16 +648,15 @@ pub fn decode_pkt_num(largest_pn: u64, truncated_pn: u64, pn_len: usize) -> u64
     let pn_mask = pn_win - 1;
     let candidate_pn = (expected_pn & !pn_mask) | truncated_pn;
 
-    if candidate_pn + pn_hwin <= expected_pn && candidate_pn < (1 << 62) - pn_win
-    {
-        return candidate_pn + pn_win;
-    }
+    let forward = (candidate_pn + pn_hwin <= expected_pn) &
+        (candidate_pn < (1 << 62) - pn_win);
 
-    if candidate_pn > expected_pn + pn_hwin && candidate_pn >= pn_win {
-        return candidate_pn - pn_win;
-    }
+    let backward =
+        (candidate_pn > expected_pn + pn_hwin) & (candidate_pn >= pn_win);
 
     candidate_pn
+        .wrapping_add(pn_win & u64::from(forward).wrapping_neg())
+        .wrapping_sub(pn_win & u64::from(backward).wrapping_neg())
 }
 
 pub fn decrypt_pkt<'a>(
@@ -705,6 +724,18 @@ pub fn encrypt_pkt(
     Ok(payload_offset + ciphertext_len)
 }
 
+/// Clears the fixed bit of the packet header at the start of the buffer.
+///
+/// This must only be done when the peer advertised the `grease_quic_bit`
+/// transport parameter.
+pub fn grease_fixed_bit(b: &mut octets::OctetsMut) -> Result<()> {
+    let (mut first, _) = b.split_at(1)?;
+
+    first.as_mut()[0] &= !FIXED_BIT;
+
+    Ok(())
+}
+
 pub fn encode_pkt_num(
     pn: u64, pn_len: usize, b: &mut octets::OctetsMut,
 ) -> Result<()> {
@@ -723,6 +754,40 @@ pub fn encode_pkt_num(
     Ok(())
 }
 
+/// Reads a connection ID preceded by its one-byte length, as found in long
+/// header packets of the given version.
+///
+/// Connection IDs longer than [`MAX_CID_LEN`] bytes are rejected for the
+/// versions supported by this library. Other versions may use up to 255
+/// bytes, which needs to be accepted to be able to negotiate the version.
+pub fn get_cid_with_len(
+    b: &mut octets::OctetsMut, version: u32,
+) -> Result<ConnectionId<'static>> {
+    let len = b.get_u8()?;
+
+    if crate::version_is_supported(version) && len > MAX_CID_LEN {
+        return Err(Error::InvalidPacket);
+    }
+
+    Ok(b.get_bytes(len as usize)?.to_vec().into())
+}
+
+/// Writes a connection ID preceded by its one-byte length, as done in long
+/// header packets.
+///
+/// Returns [`InvalidPacket`] if the connection ID is longer than 255 bytes,
+/// rather than silently truncating its length.
+///
+/// [`InvalidPacket`]: ../enum.Error.html#variant.InvalidPacket
+pub fn put_cid_with_len(b: &mut octets::OctetsMut, cid: &[u8]) -> Result<()> {
+    let len = u8::try_from(cid.len()).map_err(|_| Error::InvalidPacket)?;
+
+    b.put_u8(len)?;
+    b.put_bytes(cid)?;
+
+    Ok(())
+}
+
 pub fn negotiate_version(
     scid: &[u8], dcid: &[u8], out: &mut [u8],
 ) -> Result<usize> {
@@ -733,10 +798,8 @@ pub fn negotiate_version(
     b.put_u8(first)?;
     b.put_u32(0)?;
 
-    b.put_u8(scid.len() as u8)?;
-    b.put_bytes(scid)?;
-    b.put_u8(dcid.len() as u8)?;
-    b.put_bytes(dcid)?;
+    put_cid_with_len(&mut b, scid)?;
+    put_cid_with_len(&mut b, dcid)?;
     b.put_u32(crate::PROTOCOL_VERSION_V1)?;
 
     Ok(b.off())
@@ -762,6 +825,7 @@ pub fn retry(
         token: Some(token.to_vec()),
         versions: None,
         key_phase: false,
+        spin_bit: false,
     };
 
     hdr.to_bytes(&mut b)?;
@@ -778,11 +842,12 @@ pub fn verify_retry_integrity(
 ) -> Result<()> {
     let tag = compute_retry_integrity_tag(b, odcid, version)?;
 
-    ring::constant_time::verify_slices_are_equal(
+    if !crypto::constant_time_eq(
         &b.as_ref()[..aead::AES_128_GCM.tag_len()],
         tag.as_ref(),
-    )
-    .map_err(|_| Error::CryptoFail)?;
+    ) {
+        return Err(Error::CryptoFail);
+    }
 
     Ok(())
 }
@@ -812,8 +877,7 @@ fn compute_retry_integrity_tag(
 
     let mut pb = octets::OctetsMut::with_slice(&mut pseudo);
 
-    pb.put_u8(odcid.len() as u8)?;
-    pb.put_bytes(odcid)?;
+    put_cid_with_len(&mut pb, odcid)?;
     pb.put_bytes(&b.buf()[..hdr_len])?;
 
     let key = aead::LessSafeKey::new(
@@ -854,6 +918,8 @@ pub struct PktNumSpace {
 
     pub next_pkt_num: u64,
 
+    pub largest_acked_pkt_num: Option<u64>,
+
     pub recv_pkt_need_ack: ranges::RangeSet,
 
     pub recv_pkt_num: PktNumWindow,
@@ -871,16 +937,18 @@ pub struct PktNumSpace {
 }
 
 impl PktNumSpace {
-    pub fn new() -> PktNumSpace {
+    pub(crate) fn new(now: time::Instant) -> PktNumSpace {
         PktNumSpace {
             largest_rx_pkt_num: 0,
 
-            largest_rx_pkt_time: time::Instant::now(),
+            largest_rx_pkt_time: now,
 
             largest_rx_non_probing_pkt_num: 0,
 
             next_pkt_num: 0,
 
+            largest_acked_pkt_num: None,
+
             recv_pkt_need_ack: ranges::RangeSet::new(crate::MAX_ACK_RANGES),
 
             recv_pkt_num: PktNumWindow::default(),
@@ -956,6 +1024,16 @@ impl PktNumWindow {
         self.window |= mask;
     }
 
+    /// Marks all packet numbers up to and including `seq` as received.
+    ///
+    /// This is meant to be used on a new window only, as the state of packet
+    /// numbers after `seq` is discarded.
+    #[cfg(feature = "checkpoint")]
+    pub fn insert_up_to(&mut self, seq: u64) {
+        self.lower = seq.saturating_add(1);
+        self.window = 0;
+    }
+
     pub fn contains(&mut self, seq: u64) -> bool {
         // Packet is on the right end of the window.
         if seq > self.upper() {
@@ -995,6 +1073,7 @@ mod tests {
             token: Some(vec![0xba; 24]),
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 63];
@@ -1009,6 +1088,46 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn retry_integrity_v1() {
+        // Retry packet from RFC 9001 Appendix A.4.
+        let mut pkt = [
+            0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e, 0x04, 0xa2,
+            0x65, 0xba, 0x2e, 0xff, 0x4d, 0x82, 0x90, 0x58, 0xfb, 0x3f, 0x0f,
+            0x24, 0x96, 0xba,
+        ];
+
+        let odcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+        let hdr = Header::from_bytes(&mut b, 0).unwrap();
+        assert_eq!(hdr.ty, Type::Retry);
+        assert_eq!(hdr.token, Some(b"token".to_vec()));
+
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V1),
+            Ok(())
+        );
+
+        // The tag doesn't match a different original destination CID.
+        assert_eq!(
+            verify_retry_integrity(&b, &[0xba; 8], crate::PROTOCOL_VERSION_V1),
+            Err(Error::CryptoFail)
+        );
+
+        // The tag doesn't match a modified packet.
+        pkt[15] ^= 0x01;
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+        Header::from_bytes(&mut b, 0).unwrap();
+
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V1),
+            Err(Error::CryptoFail)
+        );
+    }
+
     #[test]
     fn initial() {
         let hdr = Header {
@@ -1022,6 +1141,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 50];
@@ -1049,6 +1169,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 50];
@@ -1077,6 +1198,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 50];
@@ -1105,6 +1227,7 @@ mod tests {
             token: Some(vec![0x05, 0x06, 0x07, 0x08]),
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 50];
@@ -1116,6 +1239,78 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn cid_with_len() {
+        let mut d = [0; 300];
+
+        for len in [0, 8, 20, 255] {
+            let cid = vec![0xba; len];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert_eq!(put_cid_with_len(&mut b, &cid), Ok(()));
+            assert_eq!(b.off(), len + 1);
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert_eq!(
+                get_cid_with_len(&mut b, 0xafafafaf),
+                Ok(ConnectionId::from_ref(&cid))
+            );
+            assert_eq!(b.off(), len + 1);
+        }
+
+        // The length doesn't fit in a single byte.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(
+            put_cid_with_len(&mut b, &[0xba; 256]),
+            Err(Error::InvalidPacket)
+        );
+        assert_eq!(b.off(), 0);
+
+        // Long connection IDs are only allowed for unsupported versions.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        put_cid_with_len(&mut b, &[0xba; 21]).unwrap();
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(
+            get_cid_with_len(&mut b, crate::PROTOCOL_VERSION_V1),
+            Err(Error::InvalidPacket)
+        );
+
+        // Truncated connection ID.
+        let mut b = octets::OctetsMut::with_slice(&mut d[..10]);
+        assert_eq!(
+            get_cid_with_len(&mut b, 0xafafafaf),
+            Err(Error::BufferTooShort)
+        );
+    }
+
+    #[test]
+    fn negotiate_version_long_cids() {
+        let scid = [0xbb; 255];
+        let dcid = [];
+
+        let mut d = [0; 300];
+
+        let len = negotiate_version(&scid, &dcid, &mut d).unwrap();
+        assert_eq!(len, 1 + 4 + 1 + 255 + 1 + 4);
+
+        // Fixed fields, the first byte is random.
+        assert_eq!(d[0] & FORM_BIT, FORM_BIT);
+        assert_eq!(&d[1..6], &[0x00, 0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(&d[261..len], &[0x00, 0x00, 0x00, 0x00, 0x01]);
+
+        let hdr = Header::from_slice(&mut d[..len], 0).unwrap();
+        assert_eq!(hdr.ty, Type::VersionNegotiation);
+        assert_eq!(hdr.dcid, ConnectionId::from_ref(&scid));
+        assert_eq!(hdr.scid, ConnectionId::from_ref(&dcid));
+        assert_eq!(hdr.versions, Some(vec![crate::PROTOCOL_VERSION_V1]));
+
+        assert_eq!(
+            negotiate_version(&[0xbb; 256], &dcid, &mut d),
+            Err(Error::InvalidPacket)
+        );
+    }
+
     #[test]
     fn handshake() {
         let hdr = Header {
@@ -1129,6 +1324,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 50];
@@ -1153,6 +1349,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         let mut d = [0; 50];
@@ -1164,15 +1361,40 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn application_spin_bit() {
+        let hdr = Header {
+            ty: Type::Short,
+            version: 0,
+            dcid: vec![0xba, 0xba, 0xba, 0xba].into(),
+            scid: ConnectionId::default(),
+            pkt_num: 0,
+            pkt_num_len: 0,
+            token: None,
+            versions: None,
+            key_phase: false,
+            spin_bit: true,
+        };
+
+        let mut d = [0; 50];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(hdr.to_bytes(&mut b).is_ok());
+        assert_eq!(d[0] & SPIN_BIT, SPIN_BIT);
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Header::from_bytes(&mut b, 4).unwrap(), hdr);
+    }
+
     #[test]
     fn pkt_num_encode_decode() {
-        let num_len = pkt_num_len(0, 0);
+        let num_len = pkt_num_len(0, None);
         assert_eq!(num_len, 1);
         let pn = decode_pkt_num(0xa82f30ea, 0x9b32, 2);
         assert_eq!(pn, 0xa82f9b32);
         let mut d = [0; 10];
         let mut b = octets::OctetsMut::with_slice(&mut d);
-        let num_len = pkt_num_len(0xac5c02, 0xabe8b3);
+        let num_len = pkt_num_len(0xac5c02, Some(0xabe8b3));
         assert_eq!(num_len, 2);
         encode_pkt_num(0xac5c02, num_len, &mut b).unwrap();
         // reading
@@ -1181,7 +1403,7 @@ mod tests {
         let pn = decode_pkt_num(0xac5c01, hdr_num, num_len);
         assert_eq!(pn, 0xac5c02);
         // sending 0xace8fe while having 0xabe8b3 acked
-        let num_len = pkt_num_len(0xace9fe, 0xabe8b3);
+        let num_len = pkt_num_len(0xace9fe, Some(0xabe8b3));
         assert_eq!(num_len, 3);
         let mut b = octets::OctetsMut::with_slice(&mut d);
         encode_pkt_num(0xace9fe, num_len, &mut b).unwrap();
@@ -1192,6 +1414,166 @@ mod tests {
         assert_eq!(pn, 0xace9fe);
     }
 
+    #[test]
+    fn pkt_num_len_vectors() {
+        // Nothing acked yet.
+        assert_eq!(pkt_num_len(0, None), 1);
+        assert_eq!(pkt_num_len(127, None), 1);
+        assert_eq!(pkt_num_len(128, None), 2);
+
+        // RFC 9000, Appendix A.2.
+        assert_eq!(pkt_num_len(0xac5c02, Some(0xabe8b3)), 2);
+        assert_eq!(pkt_num_len(0xace8fe, Some(0xabe8b3)), 3);
+
+        // The length depends on the distance from the largest acked packet,
+        // not on the packet number itself.
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - 1)), 1);
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - 128)), 1);
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - 129)), 2);
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - (1 << 15))), 2);
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - (1 << 15) - 1)), 3);
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - (1 << 23))), 3);
+        assert_eq!(pkt_num_len(1 << 40, Some((1 << 40) - (1 << 23) - 1)), 4);
+
+        // Larger gaps can't be represented, so use the maximum length.
+        assert_eq!(pkt_num_len(1 << 40, Some(0)), MAX_PKT_NUM_LEN);
+        assert_eq!(pkt_num_len((1 << 62) - 1, None), MAX_PKT_NUM_LEN);
+    }
+
+    #[test]
+    fn pkt_num_decode_vectors() {
+        // RFC 9000, Appendix A.3.
+        assert_eq!(decode_pkt_num(0xa82f30ea, 0x9b32, 2), 0xa82f9b32);
+
+        // Nothing received yet.
+        assert_eq!(decode_pkt_num(0, 0, 1), 0);
+        assert_eq!(decode_pkt_num(0, 0xff, 1), 0xff);
+
+        // Wrapping forward across the truncation window.
+        assert_eq!(decode_pkt_num(0xff, 0x01, 1), 0x101);
+        assert_eq!(decode_pkt_num(0xfffe, 0x0000, 2), 0x10000);
+        assert_eq!(decode_pkt_num(0xffff_fffe, 0x0000_0001, 4), 0x1_0000_0001);
+
+        // Wrapping backward across the truncation window.
+        assert_eq!(decode_pkt_num(0x100, 0xff, 1), 0xff);
+        assert_eq!(decode_pkt_num(0x1_0000_0000, 0xffff_ffff, 4), 0xffff_ffff);
+
+        // Edges of the half window.
+        assert_eq!(decode_pkt_num(0x100, 0x81, 1), 0x181);
+        assert_eq!(decode_pkt_num(0x100, 0x82, 1), 0x82);
+        assert_eq!(decode_pkt_num(0x17f, 0x00, 1), 0x200);
+        assert_eq!(decode_pkt_num(0x17f, 0x01, 1), 0x101);
+
+        // Never decode beyond the largest possible packet number.
+        let max_pn = (1 << 62) - 1;
+        assert_eq!(decode_pkt_num(max_pn - 1, 0xff, 1), max_pn);
+        assert_eq!(decode_pkt_num(max_pn - 1, 0x00, 1), max_pn - 0xff);
+        assert_eq!(
+            decode_pkt_num(max_pn - 1, 0x0000_0000, 4),
+            max_pn - 0xffff_ffff
+        );
+    }
+
+    #[test]
+    fn pkt_num_round_trip() {
+        // Simple xorshift generator, so failures are reproducible.
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = move |max: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % max
+        };
+
+        for _ in 0..10_000 {
+            let largest_acked = next(1 << 61);
+
+            // Keep the gap within what a 4 byte packet number can represent.
+            let gap_bits = 4 * next(8) + 1;
+            let pn = largest_acked + 1 + next(1 << gap_bits);
+            let pn_len = pkt_num_len(pn, Some(largest_acked));
+
+            // The peer received at least the acknowledged packet, and maybe
+            // some later packets as well.
+            let largest_rx = largest_acked + next(pn - largest_acked);
+
+            let mut d = [0; 4];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            encode_pkt_num(pn, pn_len, &mut b).unwrap();
+
+            let mut b = octets::Octets::with_slice(&d);
+            let truncated = match pn_len {
+                1 => u64::from(b.get_u8().unwrap()),
+                2 => u64::from(b.get_u16().unwrap()),
+                3 => u64::from(b.get_u24().unwrap()),
+                _ => u64::from(b.get_u32().unwrap()),
+            };
+
+            assert_eq!(
+                decode_pkt_num(largest_rx, truncated, pn_len),
+                pn,
+                "largest_acked={} largest_rx={} len={}",
+                largest_acked,
+                largest_rx,
+                pn_len
+            );
+        }
+    }
+
+    #[test]
+    fn decrypt_hdr_pkt_num_len() {
+        let dcid = [0xba; 8];
+
+        let (_, seal) = crypto::derive_initial_key_material(
+            &dcid,
+            crate::PROTOCOL_VERSION,
+            false,
+        )
+        .unwrap();
+
+        let (open, _) = crypto::derive_initial_key_material(
+            &dcid,
+            crate::PROTOCOL_VERSION,
+            true,
+        )
+        .unwrap();
+
+        for pn_len in 1..=MAX_PKT_NUM_LEN {
+            let pn = 0x0102_0304 >> (8 * (MAX_PKT_NUM_LEN - pn_len));
+
+            let mut out = [0; 128];
+            let mut b = octets::OctetsMut::with_slice(&mut out);
+
+            b.put_u8(FIXED_BIT | (pn_len - 1) as u8).unwrap();
+            b.put_bytes(&dcid).unwrap();
+            encode_pkt_num(pn, pn_len, &mut b).unwrap();
+
+            let payload_offset = b.off();
+
+            b.put_bytes(&[0xaa; 32]).unwrap();
+
+            let written =
+                encrypt_pkt(&mut b, pn, pn_len, 32, payload_offset, None, &seal)
+                    .unwrap();
+
+            let protected = out[..written].to_vec();
+
+            let mut b = octets::OctetsMut::with_slice(&mut out[..written]);
+
+            let mut hdr = Header::from_bytes(&mut b, dcid.len()).unwrap();
+            decrypt_hdr(&mut b, &mut hdr, &open).unwrap();
+
+            assert_eq!(hdr.pkt_num, pn);
+            assert_eq!(hdr.pkt_num_len, pn_len);
+
+            let pn_end = 1 + dcid.len() + pn_len;
+            assert_eq!(b.off(), pn_end);
+
+            // The bytes following the packet number are left untouched.
+            assert_eq!(&out[pn_end..written], &protected[pn_end..]);
+        }
+    }
+
     #[test]
     fn pkt_num_window() {
         let mut win = PktNumWindow::default();
@@ -1935,6 +2317,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         hdr.to_bytes(&mut b).unwrap();
@@ -1967,6 +2350,7 @@ mod tests {
             token: None,
             versions: None,
             key_phase: false,
+            spin_bit: false,
         };
 
         hdr.to_bytes(&mut b).unwrap();
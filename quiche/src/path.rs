@@ -37,7 +37,9 @@ use slab::Slab;
 use crate::Error;
 use crate::Result;
 
+use crate::packet;
 use crate::pmtud;
+use crate::rand;
 use crate::recovery;
 use crate::recovery::HandshakeStatus;
 
@@ -73,6 +75,28 @@ impl PathState {
     }
 }
 
+/// Whether network paths take part in the latency spin bit.
+///
+/// The spin bit lets on-path observers measure the round-trip time of a
+/// connection, see [RFC 9000, Section 17.4].
+///
+/// [RFC 9000, Section 17.4]: https://www.rfc-editor.org/rfc/rfc9000#section-17.4
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpinBitMode {
+    /// The spin bit is set to a random value, fixed for each path, and the
+    /// value received from the peer is ignored.
+    Disabled   = 0,
+
+    /// The spin bit is used on all paths.
+    Enabled    = 1,
+
+    /// The spin bit is used on a randomly selected 15 out of 16 paths, and is
+    /// disabled on the others, so that endpoints that never use it can't be
+    /// told apart.
+    Randomized = 2,
+}
+
 /// A path-specific event.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PathEvent {
@@ -199,6 +223,26 @@ pub struct Path {
 
     /// Whether or not we should force eliciting of an ACK (e.g. via PING frame)
     pub needs_ack_eliciting: bool,
+
+    /// Whether the application requested an RTT probe that wasn't sent yet.
+    pub rtt_probe_pending: bool,
+
+    /// The packet number of the packet carrying the last RTT probe, until it
+    /// is acknowledged or declared lost.
+    pub rtt_probe_pkt_num: Option<u64>,
+
+    /// Whether the path takes part in the latency spin bit.
+    spin_enabled: bool,
+
+    /// The spin bit value sent on this path.
+    spin_value: bool,
+
+    /// The largest packet number received on this path, used to ignore the
+    /// spin bit of reordered packets.
+    spin_largest_rx_pkt_num: Option<u64>,
+
+    /// The number of times the spin bit value changed.
+    spin_transitions: u64,
 }
 
 impl Path {
@@ -208,7 +252,7 @@ impl Path {
         local_addr: SocketAddr, peer_addr: SocketAddr,
         recovery_config: &recovery::RecoveryConfig,
         path_challenge_recv_max_queue_len: usize, pmtud_init: usize,
-        is_initial: bool,
+        is_initial: bool, now: time::Instant,
     ) -> Self {
         let (state, active_scid_seq, active_dcid_seq) = if is_initial {
             (PathState::Validated, Some(0), Some(0))
@@ -223,7 +267,7 @@ impl Path {
             active_dcid_seq,
             state,
             active: false,
-            recovery: recovery::Recovery::new_with_config(recovery_config),
+            recovery: recovery::Recovery::new_with_config(recovery_config, now),
             pmtud: pmtud::Pmtud::new(pmtud_init),
             in_flight_challenges: VecDeque::new(),
             max_challenge_size: 0,
@@ -246,6 +290,12 @@ impl Path {
             failure_notified: false,
             migrating: false,
             needs_ack_eliciting: false,
+            rtt_probe_pending: false,
+            rtt_probe_pkt_num: None,
+            spin_enabled: false,
+            spin_value: false,
+            spin_largest_rx_pkt_num: None,
+            spin_transitions: 0,
         }
     }
 
@@ -428,6 +478,17 @@ impl Path {
             trace_id,
         );
 
+        // Forget the RTT probe if it was declared lost.
+        if let Some(pn) = self.rtt_probe_pkt_num {
+            if self
+                .recovery
+                .unacked_time_sent(packet::Epoch::Application, pn)
+                .is_none()
+            {
+                self.rtt_probe_pkt_num = None;
+            }
+        }
+
         let mut lost_probe_time = None;
         self.in_flight_challenges.retain(|(_, _, sent_time)| {
             if *sent_time <= now {
@@ -473,6 +534,63 @@ impl Path {
         (lost_packets, lost_bytes)
     }
 
+    /// Decides whether the path takes part in the latency spin bit, and picks
+    /// the value to send if it doesn't.
+    pub fn init_spin_bit(&mut self, mode: SpinBitMode, rng: &dyn rand::Rng) {
+        let mut r = [0; 1];
+        rng.fill_bytes(&mut r);
+
+        self.spin_enabled = match mode {
+            SpinBitMode::Disabled => false,
+
+            SpinBitMode::Enabled => true,
+
+            SpinBitMode::Randomized => r[0] & 0x0f != 0,
+        };
+
+        self.spin_value = !self.spin_enabled && r[0] & 0x80 != 0;
+    }
+
+    /// Returns the spin bit value to send on this path.
+    #[inline]
+    pub fn spin_bit(&self) -> bool {
+        self.spin_value
+    }
+
+    /// Updates the spin value from the spin bit of a received short header
+    /// packet.
+    ///
+    /// Returns whether the value changed.
+    pub fn on_spin_bit_received(
+        &mut self, pkt_num: u64, spin_bit: bool, is_server: bool,
+    ) -> bool {
+        if !self.spin_enabled {
+            return false;
+        }
+
+        // Only packets that increase the largest packet number received on
+        // the path are considered, so that reordering doesn't cause spurious
+        // transitions.
+        if self.spin_largest_rx_pkt_num.map_or(false, |v| pkt_num <= v) {
+            return false;
+        }
+
+        self.spin_largest_rx_pkt_num = Some(pkt_num);
+
+        // The server reflects the value it receives, while the client inverts
+        // it.
+        let value = if is_server { spin_bit } else { !spin_bit };
+
+        if value == self.spin_value {
+            return false;
+        }
+
+        self.spin_value = value;
+        self.spin_transitions += 1;
+
+        true
+    }
+
     pub fn stats(&self) -> PathStats {
         PathStats {
             local_addr: self.local_addr,
@@ -487,12 +605,16 @@ impl Path {
             min_rtt: self.recovery.min_rtt(),
             rttvar: self.recovery.rttvar(),
             cwnd: self.recovery.cwnd(),
+            bytes_in_flight: self.recovery.bytes_in_flight(),
+            app_limited: self.recovery.app_limited(),
+            app_limited_periods: self.recovery.app_limited_periods(),
             sent_bytes: self.sent_bytes,
             recv_bytes: self.recv_bytes,
             lost_bytes: self.recovery.bytes_lost,
             stream_retrans_bytes: self.stream_retrans_bytes,
             pmtu: self.recovery.max_datagram_size(),
             delivery_rate: self.recovery.delivery_rate(),
+            spin_transitions: self.spin_transitions,
         }
     }
 }
@@ -869,6 +991,18 @@ pub struct PathStats {
     /// The size of the connection's congestion window in bytes.
     pub cwnd: usize,
 
+    /// The number of bytes sent in ack-eliciting or padded packets that are
+    /// neither acknowledged nor declared lost yet.
+    pub bytes_in_flight: usize,
+
+    /// Whether the sender is application-limited, i.e. it didn't have enough
+    /// data to fill the congestion window, in which case the window isn't
+    /// grown.
+    pub app_limited: bool,
+
+    /// The number of times the sender became application-limited.
+    pub app_limited_periods: u64,
+
     /// The number of sent bytes.
     pub sent_bytes: u64,
 
@@ -893,6 +1027,10 @@ pub struct PathStats {
     /// [`SendInfo.at`]: struct.SendInfo.html#structfield.at
     /// [Pacing]: index.html#pacing
     pub delivery_rate: u64,
+
+    /// The number of times the latency spin bit value changed. This is zero
+    /// if the path doesn't take part in the spin bit.
+    pub spin_transitions: u64,
 }
 
 impl std::fmt::Debug for PathStats {
@@ -914,6 +1052,12 @@ impl std::fmt::Debug for PathStats {
             self.recv, self.sent, self.lost, self.retrans, self.rtt, self.min_rtt, self.rttvar, self.cwnd,
         )?;
 
+        write!(
+            f,
+            " bytes_in_flight={} app_limited={} app_limited_periods={}",
+            self.bytes_in_flight, self.app_limited, self.app_limited_periods,
+        )?;
+
         write!(
             f,
             " sent_bytes={} recv_bytes={} lost_bytes={}",
@@ -924,7 +1068,9 @@ impl std::fmt::Debug for PathStats {
             f,
             " stream_retrans_bytes={} pmtu={} delivery_rate={}",
             self.stream_retrans_bytes, self.pmtu, self.delivery_rate,
-        )
+        )?;
+
+        write!(f, " spin_transitions={}", self.spin_transitions)
     }
 }
 
@@ -954,6 +1100,7 @@ mod tests {
             config.path_challenge_recv_max_queue_len,
             1200,
             true,
+            time::Instant::now(),
         );
         let mut path_mgr = PathMap::new(path, 2, false, true, 1200);
 
@@ -964,6 +1111,7 @@ mod tests {
             config.path_challenge_recv_max_queue_len,
             1200,
             false,
+            time::Instant::now(),
         );
         path_mgr.insert_path(probed_path, false).unwrap();
 
@@ -1041,6 +1189,7 @@ mod tests {
             config.path_challenge_recv_max_queue_len,
             1200,
             true,
+            time::Instant::now(),
         );
         let mut client_path_mgr = PathMap::new(path, 2, false, false, 1200);
         let mut server_path = Path::new(
@@ -1050,6 +1199,7 @@ mod tests {
             config.path_challenge_recv_max_queue_len,
             1200,
             false,
+            time::Instant::now(),
         );
 
         let client_pid = client_path_mgr
@@ -1133,6 +1283,7 @@ mod tests {
             config.path_challenge_recv_max_queue_len,
             1200,
             true,
+            time::Instant::now(),
         );
         let mut client_path_mgr = PathMap::new(path, 2, false, false, 1200);
         let mut server_path = Path::new(
@@ -1142,6 +1293,7 @@ mod tests {
             config.path_challenge_recv_max_queue_len,
             1200,
             false,
+            time::Instant::now(),
         );
 
         let client_pid = client_path_mgr
@@ -0,0 +1,531 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::time;
+
+use crate::Connection;
+use crate::Error;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+
+/// The default delay between two connection attempts, as recommended by
+/// [RFC 8305].
+///
+/// [RFC 8305]: https://www.rfc-editor.org/rfc/rfc8305.html#section-5
+pub const DEFAULT_RACE_ATTEMPT_DELAY: time::Duration =
+    time::Duration::from_millis(250);
+
+struct Attempt {
+    conn: Connection,
+
+    started: bool,
+}
+
+/// Races client connection attempts to the same server, in the style of
+/// [Happy Eyeballs].
+///
+/// Each attempt is a separate client [`Connection`], typically one per
+/// address family of the server. Attempts are started in the order they
+/// were added, each one after the previous one has been given the attempt
+/// delay to complete its handshake, or as soon as all previous ones failed.
+///
+/// The first attempt to complete its handshake wins the race. All other
+/// attempts that were started are then closed with a `NO_ERROR` transport
+/// error, and those not started yet are never started. The application
+/// should keep driving the race until [`is_done()`] returns `true`, so the
+/// losers get to send their `CONNECTION_CLOSE` frames, and then take the
+/// winning connection out with [`into_winner()`].
+///
+/// Like [`Connection`], the race doesn't perform any I/O itself. Packets are
+/// routed to an attempt based on the addresses of their [`RecvInfo`], and
+/// the [`SendInfo`] returned by [`send()`] tells which socket to send each
+/// packet on.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut out = [0; 1350];
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let local_v6 = "[::1]:1234".parse().unwrap();
+/// # let peer_v6 = "[::1]:4433".parse().unwrap();
+/// # let local_v4 = "127.0.0.1:1234".parse().unwrap();
+/// # let peer_v4 = "127.0.0.1:4433".parse().unwrap();
+/// let mut race =
+///     quiche::ConnectionRace::new(quiche::DEFAULT_RACE_ATTEMPT_DELAY);
+///
+/// race.add(quiche::connect(
+///     None,
+///     &scid,
+///     local_v6,
+///     peer_v6,
+///     &mut config,
+/// )?)?;
+/// race.add(quiche::connect(
+///     None,
+///     &scid,
+///     local_v4,
+///     peer_v4,
+///     &mut config,
+/// )?)?;
+///
+/// while !race.is_done() {
+///     loop {
+///         let (write, send_info) = match race.send(&mut out) {
+///             Ok(v) => v,
+///
+///             Err(quiche::Error::Done) => break,
+///
+///             Err(e) => return Err(e),
+///         };
+///
+///         // Send `out[..write]` to `send_info.to` from `send_info.from`.
+///     }
+///
+///     // Wait for incoming packets or `race.timeout()`, pass them to
+///     // `race.recv()` or call `race.on_timeout()`.
+/// }
+///
+/// let conn = race.into_winner();
+/// # Ok::<(), quiche::Error>(())
+/// ```
+///
+/// [Happy Eyeballs]: https://www.rfc-editor.org/rfc/rfc8305.html
+/// [`is_done()`]: struct.ConnectionRace.html#method.is_done
+/// [`into_winner()`]: struct.ConnectionRace.html#method.into_winner
+/// [`send()`]: struct.ConnectionRace.html#method.send
+pub struct ConnectionRace {
+    attempts: Vec<Attempt>,
+
+    attempt_delay: time::Duration,
+
+    next_attempt_time: Option<time::Instant>,
+
+    winner: Option<usize>,
+}
+
+impl ConnectionRace {
+    /// Creates an empty race, starting attempts `attempt_delay` apart.
+    pub fn new(attempt_delay: time::Duration) -> ConnectionRace {
+        ConnectionRace {
+            attempts: Vec::new(),
+            attempt_delay,
+            next_attempt_time: None,
+            winner: None,
+        }
+    }
+
+    /// Adds a connection attempt to the race.
+    ///
+    /// Attempts are started in the order they are added, so the preferred
+    /// address should be added first.
+    ///
+    /// Returns [`InvalidState`] if the connection is not a client connection
+    /// that has yet to send anything, or if the race was already won.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn add(&mut self, conn: Connection) -> Result<()> {
+        if conn.is_server || conn.sent_count > 0 || self.winner.is_some() {
+            return Err(Error::InvalidState);
+        }
+
+        self.attempts.push(Attempt {
+            conn,
+            started: false,
+        });
+
+        Ok(())
+    }
+
+    /// Processes a packet received for one of the attempts.
+    ///
+    /// The packet is passed to the attempt whose addresses match `info`, see
+    /// [`Connection::recv()`]. If the attempt completes its handshake, it
+    /// wins the race.
+    ///
+    /// Returns [`Done`] if no attempt matches `info`.
+    ///
+    /// [`Connection::recv()`]: struct.Connection.html#method.recv
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
+        let attempt = self
+            .attempts
+            .iter_mut()
+            .filter(|a| a.started)
+            .find(|a| {
+                a.conn.paths.get_active().map_or(false, |p| {
+                    p.local_addr() == info.to && p.peer_addr() == info.from
+                })
+            })
+            .ok_or(Error::Done)?;
+
+        let read = attempt.conn.recv(buf, info)?;
+
+        self.check_winner();
+
+        Ok(read)
+    }
+
+    /// Writes a single packet to be sent by one of the attempts.
+    ///
+    /// Attempts that are due are started first. See [`Connection::send()`]
+    /// for details, the returned [`SendInfo`] tells which addresses the
+    /// packet must be sent from and to.
+    ///
+    /// Returns [`Done`] if no attempt has anything to send.
+    ///
+    /// [`Connection::send()`]: struct.Connection.html#method.send
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        self.start_attempts();
+
+        for attempt in self.attempts.iter_mut().filter(|a| a.started) {
+            match attempt.conn.send(out) {
+                Err(Error::Done) => continue,
+
+                v => return v,
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the amount of time until the next timeout event.
+    ///
+    /// This is the earliest of the attempts' timeouts, see
+    /// [`Connection::timeout()`], and of the time the next attempt is due.
+    ///
+    /// [`Connection::timeout()`]: struct.Connection.html#method.timeout
+    pub fn timeout(&self) -> Option<time::Duration> {
+        let next_attempt = match (self.now(), self.next_attempt_time) {
+            (Some(now), Some(t)) if self.has_pending_attempts() =>
+                Some(t.saturating_duration_since(now)),
+
+            _ => None,
+        };
+
+        self.attempts
+            .iter()
+            .filter(|a| a.started)
+            .filter_map(|a| a.conn.timeout())
+            .chain(next_attempt)
+            .min()
+    }
+
+    /// Processes a timeout event.
+    ///
+    /// This calls [`Connection::on_timeout()`] on all started attempts, and
+    /// starts the next attempt if it is due.
+    ///
+    /// [`Connection::on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn on_timeout(&mut self) {
+        for attempt in self.attempts.iter_mut().filter(|a| a.started) {
+            attempt.conn.on_timeout();
+        }
+
+        self.start_attempts();
+    }
+
+    /// Returns the connection that won the race, if any.
+    pub fn winner(&self) -> Option<&Connection> {
+        self.winner.map(|i| &self.attempts[i].conn)
+    }
+
+    /// Returns true if the race is over.
+    ///
+    /// This is the case once an attempt won and all other started attempts
+    /// are closed, or once all attempts failed.
+    pub fn is_done(&self) -> bool {
+        match self.winner {
+            Some(winner) => self
+                .attempts
+                .iter()
+                .enumerate()
+                .filter(|(i, a)| *i != winner && a.started)
+                .all(|(_, a)| a.conn.is_closed()),
+
+            None =>
+                !self.attempts.is_empty() &&
+                    self.attempts
+                        .iter()
+                        .all(|a| a.started && a.conn.is_closed()),
+        }
+    }
+
+    /// Consumes the race, returning the connection that won it.
+    ///
+    /// Attempts that lost the race are dropped, even if they haven't finished
+    /// closing yet.
+    pub fn into_winner(mut self) -> Option<Connection> {
+        let winner = self.winner?;
+
+        Some(self.attempts.swap_remove(winner).conn)
+    }
+
+    fn now(&self) -> Option<time::Instant> {
+        self.attempts.first().map(|a| a.conn.clock.now())
+    }
+
+    fn has_pending_attempts(&self) -> bool {
+        self.winner.is_none() && self.attempts.iter().any(|a| !a.started)
+    }
+
+    /// Starts the next attempts, if they are due or all previous attempts
+    /// failed.
+    fn start_attempts(&mut self) {
+        let now = match self.now() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        while self.has_pending_attempts() {
+            let due = self.next_attempt_time.map_or(true, |t| now >= t);
+
+            let previous_failed = self
+                .attempts
+                .iter()
+                .filter(|a| a.started)
+                .all(|a| a.conn.is_closed());
+
+            if !due && !previous_failed {
+                break;
+            }
+
+            if let Some(attempt) = self.attempts.iter_mut().find(|a| !a.started) {
+                trace!("{} starting connection attempt", attempt.conn.trace_id);
+
+                attempt.started = true;
+            }
+
+            self.next_attempt_time = Some(now + self.attempt_delay);
+        }
+    }
+
+    /// Promotes the first established attempt, and closes all others.
+    fn check_winner(&mut self) {
+        if self.winner.is_some() {
+            return;
+        }
+
+        self.winner = self
+            .attempts
+            .iter()
+            .position(|a| a.started && a.conn.is_established());
+
+        let winner = match self.winner {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        for (i, attempt) in self.attempts.iter_mut().enumerate() {
+            if i == winner || !attempt.started {
+                continue;
+            }
+
+            trace!("{} abandoning connection attempt", attempt.conn.trace_id);
+
+            // The attempt might already be closing.
+            attempt.conn.close(false, 0x0, b"").ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use crate::testing;
+    use crate::Config;
+    use crate::ConnectionId;
+
+    fn config(clock: Arc<testing::ManualClock>) -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_clock(clock);
+        config
+    }
+
+    fn connect(
+        config: &mut Config, local: &str, peer: &str,
+    ) -> (Connection, SocketAddr, SocketAddr) {
+        let local = local.parse().unwrap();
+        let peer = peer.parse().unwrap();
+
+        let mut scid = [0; 16];
+        crate::rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let conn = crate::connect(Some("quic.tech"), &scid, local, peer, config)
+            .unwrap();
+
+        (conn, local, peer)
+    }
+
+    fn emit_flight(race: &mut ConnectionRace) -> Vec<(Vec<u8>, SendInfo)> {
+        let mut flight = Vec::new();
+
+        loop {
+            let mut out = vec![0; 65535];
+
+            match race.send(&mut out) {
+                Ok((written, info)) => {
+                    out.truncate(written);
+                    flight.push((out, info));
+                },
+
+                Err(Error::Done) => break,
+
+                Err(e) => panic!("send failed: {:?}", e),
+            }
+        }
+
+        flight
+    }
+
+    #[test]
+    fn first_established_attempt_wins() {
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
+        let mut config = config(clock.clone());
+
+        let (conn_v6, _, peer_v6) =
+            connect(&mut config, "[::1]:1234", "[::1]:4321");
+        let (conn_v4, local_v4, peer_v4) =
+            connect(&mut config, "127.0.0.1:1234", "127.0.0.1:4321");
+
+        let mut race = ConnectionRace::new(time::Duration::ZERO);
+        assert_eq!(race.add(conn_v6), Ok(()));
+        assert_eq!(race.add(conn_v4), Ok(()));
+
+        // Only the IPv4 server is reachable.
+        let server_scid = ConnectionId::from_ref(&[0xba; 16]);
+        let mut server =
+            crate::accept(&server_scid, None, peer_v4, local_v4, &mut config)
+                .unwrap();
+
+        while race.winner().is_none() {
+            let flight = emit_flight(&mut race)
+                .into_iter()
+                .filter(|(_, si)| si.to == peer_v4)
+                .collect();
+            assert_eq!(testing::process_flight(&mut server, flight), Ok(()));
+
+            for (mut pkt, si) in testing::emit_flight(&mut server).unwrap() {
+                let info = RecvInfo {
+                    to: si.to,
+                    from: si.from,
+                };
+
+                assert_eq!(race.recv(&mut pkt, info), Ok(pkt.len()));
+            }
+        }
+
+        assert!(race.winner().unwrap().is_established());
+        assert_eq!(
+            race.winner()
+                .unwrap()
+                .paths_iter(local_v4)
+                .collect::<Vec<_>>(),
+            vec![peer_v4]
+        );
+
+        // The IPv6 attempt is abandoned.
+        let flight = emit_flight(&mut race);
+        assert!(flight.iter().any(|(_, si)| si.to == peer_v6));
+        assert!(!race.is_done());
+
+        // Once it finished closing, the race is over.
+        clock.advance(race.timeout().unwrap());
+        race.on_timeout();
+
+        assert!(race.is_done());
+
+        let conn = race.into_winner().unwrap();
+        assert!(conn.is_established());
+    }
+
+    #[test]
+    fn attempts_start_after_delay() {
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
+        let mut config = config(clock.clone());
+
+        let (conn_v6, local_v6, _) =
+            connect(&mut config, "[::1]:1234", "[::1]:4321");
+        let (conn_v4, local_v4, _) =
+            connect(&mut config, "127.0.0.1:1234", "127.0.0.1:4321");
+
+        let mut race = ConnectionRace::new(DEFAULT_RACE_ATTEMPT_DELAY);
+        assert_eq!(race.add(conn_v6), Ok(()));
+        assert_eq!(race.add(conn_v4), Ok(()));
+
+        // Only the preferred attempt starts right away.
+        let flight = emit_flight(&mut race);
+        assert!(!flight.is_empty());
+        assert!(flight.iter().all(|(_, si)| si.from == local_v6));
+
+        assert_eq!(race.timeout(), Some(DEFAULT_RACE_ATTEMPT_DELAY));
+
+        clock.advance(DEFAULT_RACE_ATTEMPT_DELAY);
+        race.on_timeout();
+
+        let flight = emit_flight(&mut race);
+        assert!(!flight.is_empty());
+        assert!(flight.iter().all(|(_, si)| si.from == local_v4));
+
+        assert!(!race.is_done());
+        assert!(race.winner().is_none());
+    }
+
+    #[test]
+    fn add_after_send() {
+        let clock = Arc::new(testing::ManualClock::new(time::Instant::now()));
+        let mut config = config(clock);
+
+        let (mut conn, ..) = connect(&mut config, "[::1]:1234", "[::1]:4321");
+
+        let mut out = [0; 1350];
+        assert!(conn.send(&mut out).is_ok());
+
+        let mut race = ConnectionRace::new(DEFAULT_RACE_ATTEMPT_DELAY);
+        assert_eq!(race.add(conn), Err(Error::InvalidState));
+        assert!(race.into_winner().is_none());
+    }
+}
@@ -24,6 +24,77 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+/// A source of randomness for a connection.
+///
+/// Connections draw from it to generate values that only need to be
+/// unpredictable to an off-path observer, such as the client's initial
+/// destination connection ID and PATH_CHALLENGE data. Key material is always
+/// generated by the TLS library.
+///
+/// Replacing the default makes those values reproducible across runs, which is
+/// useful for fuzzing and differential testing. See [`set_rng()`].
+///
+/// [`set_rng()`]: struct.Config.html#method.set_rng
+pub trait Rng: Send + Sync {
+    /// Fills `buf` with random bytes.
+    fn fill_bytes(&self, buf: &mut [u8]);
+}
+
+/// The default [`Rng`], which reads from the system's cryptographically secure
+/// random number generator.
+///
+/// [`Rng`]: trait.Rng.html
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRng;
+
+impl Rng for SystemRng {
+    fn fill_bytes(&self, buf: &mut [u8]) {
+        rand_bytes(buf);
+    }
+}
+
+/// A deterministic [`Rng`] that produces the same sequence of bytes for the
+/// same seed.
+///
+/// It is not cryptographically secure and must not be used outside of
+/// testing.
+///
+/// [`Rng`]: trait.Rng.html
+#[cfg(feature = "deterministic-rng")]
+#[derive(Debug)]
+pub struct SeededRng {
+    state: std::sync::Mutex<u64>,
+}
+
+#[cfg(feature = "deterministic-rng")]
+impl SeededRng {
+    /// Creates a generator from the given seed.
+    pub fn new(seed: u64) -> Self {
+        SeededRng {
+            state: std::sync::Mutex::new(seed),
+        }
+    }
+}
+
+#[cfg(feature = "deterministic-rng")]
+impl Rng for SeededRng {
+    fn fill_bytes(&self, buf: &mut [u8]) {
+        let mut state = self.state.lock().unwrap();
+
+        for chunk in buf.chunks_mut(8) {
+            // SplitMix64.
+            *state = state.wrapping_add(0x9e3779b97f4a7c15);
+
+            let mut z = *state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^= z >> 31;
+
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
 pub fn rand_bytes(buf: &mut [u8]) {
     unsafe {
         RAND_bytes(buf.as_mut_ptr(), buf.len());
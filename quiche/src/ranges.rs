@@ -24,6 +24,33 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+//! Sets of non-overlapping ranges.
+//!
+//! This module provides the [`RangeSet`] collection that is used internally to
+//! track packet numbers that need to be acknowledged, and stream data that was
+//! received or acknowledged. It can also be useful to applications and tools
+//! that need to track the same kind of information.
+//!
+//! ## Examples:
+//!
+//! ```
+//! use quiche::ranges::RangeSet;
+//!
+//! let mut set = RangeSet::default();
+//!
+//! set.insert(0..5);
+//! set.insert(10..15);
+//! set.insert(4..8);
+//!
+//! assert_eq!(set.iter().collect::<Vec<_>>(), [0..8, 10..15]);
+//! assert_eq!(set.iter().rev().next(), Some(10..15));
+//! assert_eq!(set.first(), Some(0));
+//! assert_eq!(set.last(), Some(14));
+//!
+//! set.remove_until(11);
+//! assert_eq!(set, 12..15);
+//! ```
+
 use std::iter::FromIterator;
 use std::ops::Range;
 
@@ -37,9 +64,15 @@ const MAX_INLINE_CAPACITY: usize = 4;
 const MIN_TO_INLINE: usize = 2;
 
 /// A sorted collection of non overlapping [`u64`] ranges
+///
+/// Overlapping and adjacent ranges are merged on insertion, so the stored
+/// ranges are always separated by at least one value that isn't covered.
 #[derive(Clone, PartialEq, Eq, PartialOrd)]
 pub enum RangeSet {
+    /// Ranges stored inline, used while only a few ranges are stored.
     Inline(InlineRangeSet),
+
+    /// Ranges stored in a [`BTreeMap`], used for larger numbers of ranges.
     BTree(BTreeRangeSet),
 }
 
@@ -79,6 +112,11 @@ impl RangeSet {
         }
     }
 
+    /// Returns true if no ranges are stored in this [`RangeSet`].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Converts the inner representation from a BTree to Inline and vice versa
     /// when the proper conditions are met. Keeps the stored data intact.
     #[inline(always)]
@@ -120,6 +158,8 @@ impl RangeSet {
     }
 
     /// Iterate over the stored ranges in incremental order.
+    ///
+    /// The returned iterator can be reversed to iterate in decremental order.
     pub fn iter(
         &self,
     ) -> impl DoubleEndedIterator<Item = Range<u64>> + ExactSizeIterator + '_
@@ -164,6 +204,10 @@ impl RangeSet {
         }
     }
 
+    /// Remove all values lower than or equal to `largest` from the collection.
+    ///
+    /// Ranges that cover `largest` are truncated so that they start right
+    /// after it.
     #[inline]
     pub fn remove_until(&mut self, largest: u64) {
         match self {
@@ -174,6 +218,7 @@ impl RangeSet {
         self.fixup();
     }
 
+    /// Insert a single value into the collection.
     pub fn push_item(&mut self, item: u64) {
         self.insert(item..item + 1)
     }
@@ -749,4 +794,66 @@ mod tests {
         assert_eq!(r.first(), Some(4));
         assert_eq!(r.last(), Some(19));
     }
+
+    /// An operation applied to both a `RangeSet` and a model of it.
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(Range<u64>),
+        RemoveUntil(u64),
+    }
+
+    fn op() -> impl proptest::strategy::Strategy<Value = Op> {
+        use proptest::prelude::*;
+
+        prop_oneof![
+            4 => (0..500_u64, 1..20_u64).prop_map(|(s, l)| Op::Insert(s..s + l)),
+            1 => (0..500_u64).prop_map(Op::RemoveUntil),
+        ]
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn model(ops in proptest::collection::vec(op(), 0..100)) {
+            let mut set = RangeSet::default();
+            let mut model = std::collections::BTreeSet::new();
+
+            for op in ops {
+                match op {
+                    Op::Insert(r) => {
+                        model.extend(r.clone());
+                        set.insert(r);
+                    },
+
+                    Op::RemoveUntil(largest) => {
+                        model.retain(|v| *v > largest);
+                        set.remove_until(largest);
+                    },
+                }
+
+                // The set covers exactly the same values as the model.
+                proptest::prop_assert!(set.flatten().eq(model.iter().copied()));
+                proptest::prop_assert!(
+                    set.flatten().rev().eq(model.iter().rev().copied())
+                );
+
+                proptest::prop_assert_eq!(set.first(), model.first().copied());
+                proptest::prop_assert_eq!(set.last(), model.last().copied());
+                proptest::prop_assert_eq!(set.is_empty(), model.is_empty());
+
+                // Ranges are sorted, non-empty and separated by a gap.
+                let ranges = set.iter().collect::<Vec<_>>();
+
+                for r in &ranges {
+                    proptest::prop_assert!(r.start < r.end);
+                }
+
+                for w in ranges.windows(2) {
+                    proptest::prop_assert!(w[0].end < w[1].start);
+                }
+
+                let rev = set.iter().rev().collect::<Vec<_>>();
+                proptest::prop_assert!(rev.iter().eq(ranges.iter().rev()));
+            }
+        }
+    }
 }
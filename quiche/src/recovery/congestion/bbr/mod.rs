@@ -43,6 +43,7 @@ pub(crate) static BBR: CongestionControlOps = CongestionControlOps {
     congestion_event,
     checkpoint,
     rollback,
+    on_persistent_congestion,
     has_custom_pacing,
     debug_fmt,
 };
@@ -186,9 +187,7 @@ pub struct State {
 }
 
 impl State {
-    pub fn new() -> Self {
-        let now = Instant::now();
-
+    pub fn new(now: Instant) -> Self {
         State {
             state: BBRStateMachine::Startup,
 
@@ -196,7 +195,7 @@ impl State {
 
             btlbw: 0,
 
-            btlbwfilter: Minmax::new(0),
+            btlbwfilter: Minmax::new(now, 0),
 
             rtprop: Duration::ZERO,
 
@@ -277,8 +276,8 @@ fn bbr_exit_recovery(r: &mut Congestion) {
 
 // Congestion Control Hooks.
 //
-fn on_init(r: &mut Congestion) {
-    init::bbr_init(r);
+fn on_init(r: &mut Congestion, now: Instant) {
+    init::bbr_init(r, now);
 }
 
 fn on_packet_sent(
@@ -333,6 +332,14 @@ fn rollback(_r: &mut Congestion) -> bool {
     false
 }
 
+fn on_persistent_congestion(r: &mut Congestion) {
+    // Upon retransmission timeout.
+    r.bbr_state.prior_cwnd = per_ack::bbr_save_cwnd(r);
+
+    r.congestion_window =
+        r.max_datagram_size * r.minimum_congestion_window_packets;
+}
+
 fn has_custom_pacing() -> bool {
     true
 }
@@ -472,6 +479,37 @@ mod tests {
         assert_eq!(r.bytes_in_flight, mss * 2);
     }
 
+    #[test]
+    fn bbr_persistent_congestion() {
+        let mut sender = test_sender();
+        let mss = sender.max_datagram_size;
+
+        let rtt = Duration::from_millis(50);
+        sender.update_rtt(rtt);
+
+        for _ in 0..10 {
+            sender.send_packet(mss);
+        }
+
+        sender.advance_time(rtt);
+        sender.ack_n_packets(5, mss);
+
+        let cwnd_prev = sender.congestion_window;
+
+        sender.lose_n_packets(2, mss, None);
+        assert!(sender.bbr_state.in_recovery);
+
+        sender.on_persistent_congestion();
+
+        // The cwnd collapses to the minimum window, and the one from before
+        // the loss is kept to be restored once recovery is over.
+        assert_eq!(
+            sender.congestion_window,
+            mss * sender.minimum_congestion_window_packets
+        );
+        assert_eq!(sender.bbr_state.prior_cwnd, cwnd_prev);
+    }
+
     #[test]
     fn bbr_drain() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -656,6 +694,14 @@ mod tests {
         assert!(r.congestion.bbr_state.filled_pipe);
         assert_eq!(r.congestion.bbr_state.state, BBRStateMachine::ProbeBW);
 
+        // Leaving Startup is reported as a slow start exit.
+        #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+        assert!(r.congestion_transitions().contains(
+            &CongestionTransition::SlowStartExit(
+                SlowStartExitReason::FullBandwidth
+            )
+        ));
+
         // In the first ProbeBW cycle, pacing_gain should be >= 1.0.
         assert!(r.congestion.bbr_state.pacing_gain >= 1.0);
     }
@@ -26,7 +26,6 @@
 
 use super::*;
 use crate::rand;
-use crate::recovery;
 
 /// 1.2Mbps in bytes/sec
 const PACING_RATE_1_2MBPS: u64 = 1200 * 1000 / 8;
@@ -167,7 +166,7 @@ fn bbr_modulate_cwnd_for_recovery(r: &mut Congestion, bytes_in_flight: usize) {
         r.congestion_window = r
             .congestion_window
             .saturating_sub(lost_bytes)
-            .max(r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS);
+            .max(r.max_datagram_size * r.minimum_congestion_window_packets);
     }
 
     if r.bbr_state.packet_conservation {
@@ -234,6 +233,8 @@ fn bbr_check_full_pipe(r: &mut Congestion) {
 
     if r.bbr_state.full_bw_count >= 3 {
         r.bbr_state.filled_pipe = true;
+
+        r.on_slow_start_exit(SlowStartExitReason::FullBandwidth);
     }
 }
 
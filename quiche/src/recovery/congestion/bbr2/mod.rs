@@ -44,6 +44,7 @@ pub(crate) static BBR2: CongestionControlOps = CongestionControlOps {
     congestion_event,
     checkpoint,
     rollback,
+    on_persistent_congestion,
     has_custom_pacing,
     debug_fmt,
 };
@@ -396,9 +397,7 @@ pub struct State {
 }
 
 impl State {
-    pub fn new() -> Self {
-        let now = Instant::now();
-
+    pub fn new(now: Instant) -> Self {
         State {
             tx_in_flight: 0,
 
@@ -454,7 +453,7 @@ impl State {
 
             inflight_latest: 0,
 
-            max_bw_filter: Minmax::new(0),
+            max_bw_filter: Minmax::new(now, 0),
 
             cycle_count: 0,
 
@@ -462,7 +461,7 @@ impl State {
 
             extra_acked_delivered: 0,
 
-            extra_acked_filter: Minmax::new(0),
+            extra_acked_filter: Minmax::new(now, 0),
 
             filled_pipe: false,
 
@@ -544,8 +543,8 @@ fn bbr2_exit_recovery(r: &mut Congestion) {
 
 // Congestion Control Hooks.
 //
-fn on_init(r: &mut Congestion) {
-    init::bbr2_init(r);
+fn on_init(r: &mut Congestion, now: Instant) {
+    init::bbr2_init(r, now);
 }
 
 fn on_packet_sent(
@@ -607,6 +606,14 @@ fn rollback(_r: &mut Congestion) -> bool {
     false
 }
 
+fn on_persistent_congestion(r: &mut Congestion) {
+    // Upon retransmission timeout.
+    r.bbr2_state.prior_cwnd = per_ack::bbr2_save_cwnd(r);
+
+    r.congestion_window =
+        r.max_datagram_size * r.minimum_congestion_window_packets;
+}
+
 fn has_custom_pacing() -> bool {
     true
 }
@@ -819,6 +826,78 @@ mod tests {
         assert_eq!(r.cwnd(), mss * 3);
     }
 
+    #[test]
+    fn bbr2_persistent_congestion() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR2);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+        let mss = r.max_datagram_size;
+
+        // Send 5 packets.
+        for pn in 0..5 {
+            let pkt = Sent {
+                pkt_num: pn,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: mss,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+            };
+
+            r.on_packet_sent(
+                pkt,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        let rtt = Duration::from_millis(50);
+        let now = now + rtt;
+
+        // Make a packet loss to trigger a congestion event.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(4..5);
+
+        assert!(r
+            .on_ack_received(
+                &acked,
+                25,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            )
+            .is_ok());
+
+        assert!(r.congestion.bbr2_state.in_recovery);
+
+        let prior_cwnd = r.congestion.bbr2_state.prior_cwnd;
+
+        (r.congestion.cc_ops.on_persistent_congestion)(&mut r.congestion);
+
+        // The cwnd collapses to the minimum window, and the one from before
+        // the loss is kept to be restored once recovery is over.
+        assert_eq!(
+            r.cwnd(),
+            mss * r.congestion.minimum_congestion_window_packets
+        );
+        assert_eq!(r.congestion.bbr2_state.prior_cwnd, prior_cwnd);
+    }
+
     #[test]
     fn bbr2_probe_bw() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -1005,6 +1084,14 @@ mod tests {
             BBR2StateMachine::ProbeBWCRUISE
         );
 
+        // Leaving Startup is reported as a slow start exit.
+        #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+        assert!(r.congestion_transitions().contains(
+            &CongestionTransition::SlowStartExit(
+                SlowStartExitReason::FullBandwidth
+            )
+        ));
+
         // After RTPROP_FILTER_LEN (10s), switch to ProbeRTT.
         let now = now + PROBE_RTT_INTERVAL;
 
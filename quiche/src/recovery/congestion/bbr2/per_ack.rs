@@ -26,7 +26,6 @@
 
 use super::*;
 use crate::rand;
-use crate::recovery;
 
 /// 1.2Mbps in bytes/sec
 const PACING_RATE_1_2MBPS: u64 = 1200 * 1000 / 8;
@@ -106,6 +105,8 @@ fn bbr2_check_startup_full_bandwidth(r: &mut Congestion) {
 
     if r.bbr2_state.full_bw_count >= MAX_BW_COUNT {
         r.bbr2_state.filled_pipe = true;
+
+        r.on_slow_start_exit(SlowStartExitReason::FullBandwidth);
     }
 }
 
@@ -125,6 +126,10 @@ fn bbr2_check_startup_high_loss(r: &mut Congestion) {
 }
 
 fn bbr2_handle_queue_too_high_in_startup(r: &mut Congestion) {
+    if !r.bbr2_state.filled_pipe {
+        r.on_slow_start_exit(SlowStartExitReason::Loss);
+    }
+
     r.bbr2_state.filled_pipe = true;
     r.bbr2_state.inflight_hi = bbr2_inflight(r, r.bbr2_state.max_bw, 1.0);
 }
@@ -712,7 +717,7 @@ fn bbr2_modulate_cwnd_for_recovery(r: &mut Congestion, in_flight: usize) {
         r.congestion_window = r
             .congestion_window
             .saturating_sub(lost_bytes)
-            .max(r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS);
+            .max(r.max_datagram_size * r.minimum_congestion_window_packets);
     }
 
     if r.bbr2_state.packet_conservation {
@@ -36,10 +36,10 @@ use std::cmp;
 use std::time::Duration;
 use std::time::Instant;
 
-use crate::recovery;
 use crate::recovery::rtt::RttStats;
 use crate::recovery::Acked;
 use crate::recovery::Sent;
+use crate::recovery::SlowStartExitReason;
 
 use super::reno;
 use super::Congestion;
@@ -52,6 +52,7 @@ pub(crate) static CUBIC: CongestionControlOps = CongestionControlOps {
     congestion_event,
     checkpoint,
     rollback,
+    on_persistent_congestion,
     has_custom_pacing,
     debug_fmt,
 };
@@ -145,7 +146,7 @@ impl State {
     }
 }
 
-fn on_init(_r: &mut Congestion) {}
+fn on_init(_r: &mut Congestion, _now: Instant) {}
 
 fn on_packet_sent(
     r: &mut Congestion, sent_bytes: usize, bytes_in_flight: usize, now: Instant,
@@ -246,6 +247,10 @@ fn on_packet_acked(
         if r.hystart.on_packet_acked(packet, rtt_stats.latest_rtt, now) {
             // Exit to congestion avoidance if CSS ends.
             r.ssthresh = r.congestion_window;
+
+            r.on_slow_start_exit(SlowStartExitReason::Hystart);
+        } else if r.congestion_window >= r.ssthresh {
+            r.on_slow_start_exit(SlowStartExitReason::Threshold);
         }
     } else {
         // Congestion avoidance.
@@ -337,6 +342,10 @@ fn congestion_event(
     // Start a new congestion event if packet was sent after the
     // start of the previous congestion recovery period.
     if !in_congestion_recovery {
+        if r.congestion_window < r.ssthresh {
+            r.on_slow_start_exit(SlowStartExitReason::Loss);
+        }
+
         r.congestion_recovery_start_time = Some(now);
 
         // Fast convergence
@@ -350,7 +359,7 @@ fn congestion_event(
         r.ssthresh = (r.congestion_window as f64 * BETA_CUBIC) as usize;
         r.ssthresh = cmp::max(
             r.ssthresh,
-            r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS,
+            r.max_datagram_size * r.minimum_congestion_window_packets,
         );
         r.congestion_window = r.ssthresh;
 
@@ -403,6 +412,16 @@ fn rollback(r: &mut Congestion) -> bool {
     true
 }
 
+fn on_persistent_congestion(r: &mut Congestion) {
+    reno::on_persistent_congestion(r);
+
+    // The state saved before the last congestion event no longer applies, so
+    // make sure it can't be rolled back to. K and w_max are reset when the
+    // next congestion avoidance stage starts (RFC 9438, Section 4.8).
+    r.cubic_state.cwnd_inc = 0;
+    r.cubic_state.prior = PriorState::default();
+}
+
 fn has_custom_pacing() -> bool {
     false
 }
@@ -421,6 +440,7 @@ mod tests {
 
     use crate::CongestionControlAlgorithm;
 
+    use crate::recovery;
     use crate::recovery::congestion::hystart;
     use crate::recovery::congestion::test_sender::TestSender;
     use crate::recovery::Recovery;
@@ -524,6 +544,15 @@ mod tests {
         // Exit from the recovery.
         sender.advance_time(rtt);
 
+        // Fill the window with packets sent after the congestion event.
+        for _ in 0..cur_cwnd / size + 1 {
+            sender.send_packet(size);
+        }
+
+        // Packets sent before the congestion event don't grow the window.
+        sender.ack_n_packets(sender.initial_congestion_window_packets - 1, size);
+        assert_eq!(sender.congestion_window, cur_cwnd);
+
         // During Congestion Avoidance, it will take
         // 5 ACKs to increase cwnd by 1 MSS.
         for _ in 0..5 {
@@ -761,6 +790,55 @@ mod tests {
         assert_eq!(sender.congestion_window(), prev_cwnd);
     }
 
+    #[test]
+    fn cubic_persistent_congestion() {
+        let mut sender = test_sender();
+        let size = sender.max_datagram_size;
+
+        // Send initcwnd full MSS packets to become no longer app limited
+        for _ in 0..sender.initial_congestion_window_packets {
+            sender.send_packet(size);
+        }
+
+        // Trigger congestion event to update ssthresh
+        sender.lose_n_packets(1, size, None);
+
+        let rtt = Duration::from_millis(100);
+        sender.update_rtt(rtt);
+        sender.advance_time(rtt);
+
+        // Checkpoint the state in congestion avoidance, so that it could be
+        // rolled back to.
+        sender.lose_n_packets(1, size, Some(sender.time));
+
+        sender.on_persistent_congestion();
+
+        let min_cwnd = size * sender.minimum_congestion_window_packets;
+        assert_eq!(sender.congestion_window(), min_cwnd);
+
+        // The state from before the congestion event can't be restored.
+        assert!(!rollback(&mut sender));
+        assert_eq!(sender.congestion_window(), min_cwnd);
+
+        sender.advance_time(rtt);
+
+        let acked = Acked {
+            pkt_num: 0,
+            time_sent: sender.time,
+            size,
+            delivered: 0,
+            delivered_time: sender.time,
+            first_sent_time: sender.time,
+            is_app_limited: false,
+            rtt: Duration::ZERO,
+        };
+
+        sender.inject_ack(acked, sender.time);
+
+        // Recovery is over and cwnd grows in slow start again.
+        assert_eq!(sender.congestion_window(), min_cwnd + size);
+    }
+
     #[test]
     fn cubic_fast_convergence() {
         let mut sender = test_sender();
@@ -786,6 +864,15 @@ mod tests {
         // Exit from the recovery.
         sender.advance_time(rtt);
 
+        // Fill the window with packets sent after the congestion event.
+        for _ in 0..cur_cwnd / size + 1 {
+            sender.send_packet(size);
+        }
+
+        // Packets sent before the congestion event don't grow the window.
+        sender.ack_n_packets(sender.initial_congestion_window_packets - 1, size);
+        assert_eq!(sender.congestion_window, cur_cwnd);
+
         // During Congestion Avoidance, it will take
         // 5 ACKs to increase cwnd by 1 MSS.
         for _ in 0..5 {
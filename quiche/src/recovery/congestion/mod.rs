@@ -31,8 +31,18 @@ use super::rtt::RttStats;
 use super::Acked;
 use super::RecoveryConfig;
 use super::Sent;
+use super::SlowStartExitReason;
 
 pub const PACING_MULTIPLIER: f64 = 1.25;
+
+// How many ACKs per congestion window to request from the peer, when the ACK
+// frequency extension is used.
+const ACKS_PER_CWND: usize = 4;
+
+// The largest number of ack-eliciting packets the peer can be asked to receive
+// before sending an ACK.
+const MAX_ACK_ELICITING_THRESHOLD: usize = 10;
+
 pub struct Congestion {
     // Congestion control.
     pub(crate) cc_ops: &'static CongestionControlOps,
@@ -70,18 +80,31 @@ pub struct Congestion {
 
     pub(crate) app_limited: bool,
 
+    /// The number of times the sender became application-limited.
+    pub(crate) app_limited_periods: u64,
+
     pub(crate) delivery_rate: delivery_rate::Rate,
 
     /// Initial congestion window size in terms of packet count.
     pub(crate) initial_congestion_window_packets: usize,
 
+    /// Minimum congestion window size in terms of packet count.
+    pub(crate) minimum_congestion_window_packets: usize,
+
     max_datagram_size: usize,
 
     pub(crate) lost_count: usize,
+
+    /// Why the congestion controller last left slow start, if it did so since
+    /// the last time this was read.
+    #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+    pub(crate) slow_start_exit: Option<SlowStartExitReason>,
 }
 
 impl Congestion {
-    pub(crate) fn from_config(recovery_config: &RecoveryConfig) -> Self {
+    pub(crate) fn from_config(
+        recovery_config: &RecoveryConfig, now: Instant,
+    ) -> Self {
         let initial_congestion_window = recovery_config.max_send_udp_payload_size *
             recovery_config.initial_congestion_window_packets;
 
@@ -102,16 +125,21 @@ impl Congestion {
 
             app_limited: false,
 
+            app_limited_periods: 0,
+
             lost_count: 0,
 
             initial_congestion_window_packets: recovery_config
                 .initial_congestion_window_packets,
 
+            minimum_congestion_window_packets: recovery_config
+                .minimum_congestion_window_packets,
+
             max_datagram_size: recovery_config.max_send_udp_payload_size,
 
             send_quantum: initial_congestion_window,
 
-            delivery_rate: delivery_rate::Rate::default(),
+            delivery_rate: delivery_rate::Rate::new(now),
 
             hystart: hystart::Hystart::new(recovery_config.hystart),
 
@@ -121,20 +149,33 @@ impl Congestion {
                 0,
                 recovery_config.max_send_udp_payload_size,
                 recovery_config.max_pacing_rate,
+                now,
             ),
 
             prr: prr::PRR::default(),
 
-            bbr_state: bbr::State::new(),
+            bbr_state: bbr::State::new(now),
+
+            bbr2_state: bbr2::State::new(now),
 
-            bbr2_state: bbr2::State::new(),
+            #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+            slow_start_exit: None,
         };
 
-        (cc.cc_ops.on_init)(&mut cc);
+        (cc.cc_ops.on_init)(&mut cc, now);
 
         cc
     }
 
+    /// Records that the congestion controller left slow start (or BBR's
+    /// Startup state) for the given reason.
+    pub(crate) fn on_slow_start_exit(&mut self, _reason: SlowStartExitReason) {
+        #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+        {
+            self.slow_start_exit = Some(_reason);
+        }
+    }
+
     pub(crate) fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
         match self.congestion_recovery_start_time {
             Some(congestion_recovery_start_time) =>
@@ -160,7 +201,23 @@ impl Congestion {
         self.congestion_window
     }
 
-    fn update_app_limited(&mut self, v: bool) {
+    /// Returns the number of ack-eliciting packets the peer can receive
+    /// before it needs to send an ACK.
+    ///
+    /// The peer is asked to acknowledge a few times per congestion window, so
+    /// ACK traffic is reduced without slowing down the window growth.
+    pub(crate) fn ack_eliciting_threshold(&self) -> u64 {
+        let cwnd_packets = self.congestion_window / self.max_datagram_size;
+
+        (cwnd_packets / ACKS_PER_CWND).clamp(1, MAX_ACK_ELICITING_THRESHOLD)
+            as u64
+    }
+
+    pub(crate) fn update_app_limited(&mut self, v: bool) {
+        if v && !self.app_limited {
+            self.app_limited_periods += 1;
+        }
+
         self.app_limited = v;
     }
 
@@ -195,7 +252,7 @@ impl Congestion {
 
         self.schedule_next_packet(now, sent_bytes);
 
-        pkt.time_sent = self.get_packet_send_time();
+        pkt.time_sent = self.get_packet_send_time(now);
 
         // bytes_in_flight is already updated. Use previous value.
         self.delivery_rate
@@ -241,8 +298,12 @@ impl Congestion {
         self.pacer.send(sent_bytes, now);
     }
 
-    pub(crate) fn get_packet_send_time(&self) -> Instant {
-        self.pacer.next_time()
+    pub(crate) fn get_packet_send_time(&self, now: Instant) -> Instant {
+        if !self.pacer.enabled() {
+            return now;
+        }
+
+        self.pacer.next_time().max(now)
     }
 }
 
@@ -282,7 +343,7 @@ impl FromStr for CongestionControlAlgorithm {
 }
 
 pub(crate) struct CongestionControlOps {
-    pub on_init: fn(r: &mut Congestion),
+    pub on_init: fn(r: &mut Congestion, now: Instant),
 
     pub on_packet_sent: fn(
         r: &mut Congestion,
@@ -311,6 +372,8 @@ pub(crate) struct CongestionControlOps {
 
     pub rollback: fn(r: &mut Congestion) -> bool,
 
+    pub on_persistent_congestion: fn(r: &mut Congestion),
+
     pub has_custom_pacing: fn() -> bool,
 
     pub debug_fmt: fn(
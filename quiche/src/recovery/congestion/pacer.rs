@@ -77,7 +77,7 @@ pub struct Pacer {
 impl Pacer {
     pub fn new(
         enabled: bool, capacity: usize, rate: u64, max_datagram_size: usize,
-        max_pacing_rate: Option<u64>,
+        max_pacing_rate: Option<u64>, now: Instant,
     ) -> Self {
         // Round capacity to MSS.
         let capacity = capacity / max_datagram_size * max_datagram_size;
@@ -96,9 +96,9 @@ impl Pacer {
 
             rate: pacing_rate,
 
-            last_update: Instant::now(),
+            last_update: now,
 
-            next_time: Instant::now(),
+            next_time: now,
 
             max_datagram_size,
 
@@ -125,6 +125,17 @@ impl Pacer {
         self.max_pacing_rate
     }
 
+    /// Sets the max pacing rate, lowering the current pacing rate if needed.
+    ///
+    /// A higher limit only takes effect on the next rate update.
+    pub fn set_max_pacing_rate(&mut self, max_pacing_rate: Option<u64>) {
+        self.max_pacing_rate = max_pacing_rate;
+
+        if let Some(max_rate) = max_pacing_rate {
+            self.rate = self.rate.min(max_rate);
+        }
+    }
+
     /// Updates the bucket capacity or pacing_rate.
     pub fn update(&mut self, capacity: usize, rate: u64, now: Instant) {
         let capacity = capacity / self.max_datagram_size * self.max_datagram_size;
@@ -217,10 +228,11 @@ mod tests {
         let max_burst = datagram_size * 10;
         let pacing_rate = 100_000;
 
-        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size, None);
-
         let now = Instant::now();
 
+        let mut p =
+            Pacer::new(true, max_burst, pacing_rate, datagram_size, None, now);
+
         // Send 6000 (half of max_burst) -> no timestamp change yet.
         p.send(6000, now);
 
@@ -250,10 +262,11 @@ mod tests {
         let max_burst = datagram_size * 10;
         let pacing_rate = 100_000;
 
-        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size, None);
-
         let now = Instant::now();
 
+        let mut p =
+            Pacer::new(true, max_burst, pacing_rate, datagram_size, None, now);
+
         // Send 6000 (half of max_burst) -> no timestamp change yet.
         p.send(6000, now);
 
@@ -275,6 +288,8 @@ mod tests {
         let pacing_rate = 100_000;
         let max_pacing_rate = 50_000;
 
+        let now = Instant::now();
+
         // Use the max_pacing_rate.
         let mut p = Pacer::new(
             true,
@@ -282,10 +297,9 @@ mod tests {
             pacing_rate,
             datagram_size,
             Some(max_pacing_rate),
+            now,
         );
 
-        let now = Instant::now();
-
         // Send 6000 (half of max_burst) -> no timestamp change yet.
         p.send(6000, now);
 
@@ -324,4 +338,51 @@ mod tests {
             Duration::from_secs_f64(interval)
         );
     }
+
+    #[test]
+    fn pacer_lower_max_pacing_rate() {
+        let datagram_size = 1200;
+        let max_burst = datagram_size * 10;
+        let pacing_rate = 100_000;
+
+        let mut p = Pacer::new(
+            true,
+            max_burst,
+            pacing_rate,
+            datagram_size,
+            None,
+            Instant::now(),
+        );
+        assert_eq!(p.rate(), pacing_rate);
+
+        // Lowering the max pacing rate applies immediately.
+        p.set_max_pacing_rate(Some(50_000));
+        assert_eq!(p.rate(), 50_000);
+        assert_eq!(p.max_pacing_rate(), Some(50_000));
+
+        let now = Instant::now();
+
+        // Fill the first burst.
+        p.send(max_burst, now);
+
+        // Start of a second burst.
+        let now = now + Duration::from_millis(5);
+        p.send(max_burst, now);
+
+        let interval = max_burst as f64 / 50_000_f64;
+
+        assert_eq!(p.next_time() - now, Duration::from_secs_f64(interval));
+
+        // Raising the max pacing rate applies on the next update.
+        p.set_max_pacing_rate(Some(200_000));
+        assert_eq!(p.rate(), 50_000);
+
+        p.update(max_burst, pacing_rate, now);
+        assert_eq!(p.rate(), pacing_rate);
+
+        // Removing the limit.
+        p.set_max_pacing_rate(None);
+        p.update(max_burst, 300_000, now);
+        assert_eq!(p.rate(), 300_000);
+    }
 }
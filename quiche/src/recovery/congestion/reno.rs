@@ -36,6 +36,7 @@ use crate::recovery;
 use crate::recovery::rtt::RttStats;
 use crate::recovery::Acked;
 use crate::recovery::Sent;
+use crate::recovery::SlowStartExitReason;
 
 use super::Congestion;
 use super::CongestionControlOps;
@@ -47,11 +48,12 @@ pub(crate) static RENO: CongestionControlOps = CongestionControlOps {
     congestion_event,
     checkpoint,
     rollback,
+    on_persistent_congestion,
     has_custom_pacing,
     debug_fmt,
 };
 
-pub fn on_init(_r: &mut Congestion) {}
+pub fn on_init(_r: &mut Congestion, _now: Instant) {}
 
 pub fn on_packet_sent(
     _r: &mut Congestion, _sent_bytes: usize, _bytes_in_flight: usize,
@@ -93,6 +95,10 @@ fn on_packet_acked(
         if r.hystart.on_packet_acked(packet, rtt_stats.latest_rtt, now) {
             // Exit to congestion avoidance if CSS ends.
             r.ssthresh = r.congestion_window;
+
+            r.on_slow_start_exit(SlowStartExitReason::Hystart);
+        } else if r.congestion_window >= r.ssthresh {
+            r.on_slow_start_exit(SlowStartExitReason::Threshold);
         }
     } else {
         // Congestion avoidance.
@@ -114,6 +120,10 @@ fn congestion_event(
     let time_sent = largest_lost_pkt.time_sent;
 
     if !r.in_congestion_recovery(time_sent) {
+        if r.congestion_window < r.ssthresh {
+            r.on_slow_start_exit(SlowStartExitReason::Loss);
+        }
+
         r.congestion_recovery_start_time = Some(now);
 
         r.congestion_window = (r.congestion_window as f64 *
@@ -122,7 +132,7 @@ fn congestion_event(
 
         r.congestion_window = cmp::max(
             r.congestion_window,
-            r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS,
+            r.max_datagram_size * r.minimum_congestion_window_packets,
         );
 
         r.bytes_acked_ca = (r.congestion_window as f64 *
@@ -142,6 +152,18 @@ fn rollback(_r: &mut Congestion) -> bool {
     true
 }
 
+pub fn on_persistent_congestion(r: &mut Congestion) {
+    // Collapse the congestion window to its minimum and start over in slow
+    // start, as described in RFC 9002, Section 7.6.2.
+    r.congestion_window =
+        r.max_datagram_size * r.minimum_congestion_window_packets;
+
+    r.bytes_acked_sl = 0;
+    r.bytes_acked_ca = 0;
+
+    r.congestion_recovery_start_time = None;
+}
+
 fn has_custom_pacing() -> bool {
     false
 }
@@ -224,6 +246,53 @@ mod tests {
         assert_eq!(prev_cwnd / 2, sender.congestion_window);
     }
 
+    #[test]
+    fn reno_congestion_event_minimum_window() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::Reno);
+        cfg.set_minimum_congestion_window_packets(8).unwrap();
+
+        let mut sender = TestSender::with_config(&cfg);
+        let size = sender.max_datagram_size;
+
+        sender.send_packet(size);
+        sender.lose_n_packets(1, size, None);
+
+        // The cwnd is not reduced below the configured minimum window.
+        assert_eq!(sender.congestion_window, size * 8);
+    }
+
+    #[test]
+    fn reno_persistent_congestion() {
+        let mut sender = test_sender();
+        let size = sender.max_datagram_size;
+
+        for _ in 0..14 {
+            sender.send_packet(size);
+        }
+
+        sender.lose_n_packets(1, size, None);
+
+        let ssthresh = sender.ssthresh;
+
+        sender.on_persistent_congestion();
+
+        // The cwnd collapses to the minimum window and recovery ends.
+        assert_eq!(
+            sender.congestion_window,
+            size * sender.minimum_congestion_window_packets
+        );
+        assert_eq!(sender.ssthresh, ssthresh);
+        assert_eq!(sender.congestion_recovery_start_time, None);
+
+        let cwnd_prev = sender.congestion_window;
+
+        sender.ack_n_packets(1, size);
+
+        // Acks grow the cwnd in slow start again.
+        assert_eq!(sender.congestion_window, cwnd_prev + size);
+    }
+
     #[test]
     fn reno_congestion_avoidance() {
         let mut sender = test_sender();
@@ -247,6 +316,15 @@ mod tests {
         sender.update_rtt(rtt);
         sender.advance_time(2 * rtt);
 
+        // Fill the window with packets sent after the congestion event.
+        for _ in 0..8 {
+            sender.send_packet(size);
+        }
+
+        // Packets sent before the congestion event don't grow the window.
+        sender.ack_n_packets(13, size);
+        assert_eq!(sender.congestion_window, cur_cwnd);
+
         sender.ack_n_packets(8, size);
         // After acking more than cwnd, expect cwnd increased by MSS
         assert_eq!(sender.congestion_window, cur_cwnd + size);
@@ -53,13 +53,19 @@ impl TestSender {
         cfg.set_cc_algorithm(algo);
         cfg.enable_hystart(hystart);
 
+        Self::with_config(&cfg)
+    }
+
+    pub(crate) fn with_config(cfg: &crate::Config) -> Self {
+        let time = Instant::now();
+
         TestSender {
             next_pkt: 0,
             next_ack: 0,
             bytes_in_flight: 0,
-            time: Instant::now(),
-            rtt_stats: RttStats::new(Duration::from_micros(0)),
-            cc: Congestion::from_config(&RecoveryConfig::from_config(&cfg)),
+            time,
+            rtt_stats: RttStats::new(Duration::from_micros(0), time),
+            cc: Congestion::from_config(&RecoveryConfig::from_config(cfg), time),
             sent_packets: VecDeque::new(),
         }
     }
@@ -173,6 +179,10 @@ impl TestSender {
         self.bytes_in_flight -= n * bytes;
     }
 
+    pub(crate) fn on_persistent_congestion(&mut self) {
+        (self.cc.cc_ops.on_persistent_congestion)(&mut self.cc);
+    }
+
     pub(crate) fn update_rtt(&mut self, rtt: Duration) {
         self.rtt_stats
             .update_rtt(rtt, Duration::ZERO, self.time, true)
@@ -49,19 +49,23 @@ use smallvec::SmallVec;
 use self::congestion::pacer;
 use self::congestion::Congestion;
 use self::rtt::RttStats;
+#[cfg(test)]
+pub(crate) use self::rtt::INITIAL_RTT;
 
 // Loss Recovery
-const INITIAL_PACKET_THRESHOLD: u64 = 3;
+pub(crate) const INITIAL_PACKET_THRESHOLD: u64 = 3;
 
 const MAX_PACKET_THRESHOLD: u64 = 20;
 
-const INITIAL_TIME_THRESHOLD: f64 = 9.0 / 8.0;
+pub(crate) const INITIAL_TIME_THRESHOLD: f64 = 9.0 / 8.0;
 
 const GRANULARITY: Duration = Duration::from_millis(1);
 
 const MAX_PTO_PROBES_COUNT: usize = 2;
 
-const MINIMUM_WINDOW_PACKETS: usize = 2;
+const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
+
+pub(crate) const MINIMUM_WINDOW_PACKETS: usize = 2;
 
 const LOSS_REDUCTION_FACTOR: f64 = 0.5;
 
@@ -106,9 +110,32 @@ struct LossDetectionResult {
     lost_packets: usize,
     lost_bytes: usize,
     pmtud_lost_bytes: usize,
+
+    /// The send times of the first and last ack-eliciting packets declared
+    /// lost, if any.
+    lost_ack_eliciting_span: Option<(Instant, Instant)>,
 }
 
 impl RecoveryEpoch {
+    /// Returns the largest packet number covered by `ranges` among the
+    /// packets sent in this epoch, if any.
+    fn largest_sent_in(&self, ranges: &RangeSet) -> Option<u64> {
+        for range in ranges.iter().rev() {
+            let idx =
+                self.sent_packets.partition_point(|p| p.pkt_num < range.end);
+
+            // No packet was sent before the end of this range, so none could
+            // have been sent in lower ranges either.
+            let pkt_num = self.sent_packets.get(idx.checked_sub(1)?)?.pkt_num;
+
+            if pkt_num >= range.start {
+                return Some(pkt_num);
+            }
+        }
+
+        None
+    }
+
     fn detect_and_remove_acked_packets(
         &mut self, now: Instant, acked: &RangeSet, newly_acked: &mut Vec<Acked>,
         rtt_stats: &RttStats, trace_id: &str,
@@ -215,6 +242,7 @@ impl RecoveryEpoch {
         let mut pmtud_lost_bytes = 0;
 
         let mut largest_lost_pkt = None;
+        let mut lost_ack_eliciting_span: Option<(Instant, Instant)> = None;
 
         let unacked_iter = self.sent_packets
         .iter_mut()
@@ -247,6 +275,14 @@ impl RecoveryEpoch {
                     // cloning the whole packet should be relatively cheap.
                     largest_lost_pkt = Some(unacked.clone());
 
+                    if unacked.ack_eliciting {
+                        let first = lost_ack_eliciting_span
+                            .map_or(unacked.time_sent, |(first, _)| first);
+
+                        lost_ack_eliciting_span =
+                            Some((first, unacked.time_sent));
+                    }
+
                     self.in_flight_count -= 1;
 
                     trace!(
@@ -276,9 +312,18 @@ impl RecoveryEpoch {
             lost_packets,
             lost_bytes,
             pmtud_lost_bytes,
+            lost_ack_eliciting_span,
         }
     }
 
+    /// Returns whether any packet sent strictly between `start` and `end` was
+    /// acknowledged.
+    fn acked_between(&self, start: Instant, end: Instant) -> bool {
+        self.sent_packets.iter().any(|p| {
+            p.time_acked.is_some() && p.time_sent > start && p.time_sent < end
+        })
+    }
+
     fn drain_acked_and_lost_packets(&mut self, loss_thresh: Instant) {
         // In order to avoid removing elements from the middle of the list
         // (which would require copying other elements to compact the list),
@@ -325,6 +370,9 @@ pub struct Recovery {
 
     pto_count: u32,
 
+    /// How many times persistent congestion was declared.
+    persistent_congestion_count: usize,
+
     rtt_stats: RttStats,
 
     pub lost_spurious_count: usize,
@@ -333,6 +381,9 @@ pub struct Recovery {
 
     time_thresh: f64,
 
+    /// Whether packet numbers are shared with other paths.
+    multipath: bool,
+
     bytes_in_flight: usize,
 
     bytes_sent: usize,
@@ -344,6 +395,11 @@ pub struct Recovery {
     #[cfg(feature = "qlog")]
     qlog_metrics: QlogMetrics,
 
+    /// The congestion controller state last reported by
+    /// `congestion_transitions()`.
+    #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+    congestion_state: CongestionState,
+
     /// How many non-ack-eliciting packets have been sent.
     outstanding_non_ack_eliciting: usize,
 
@@ -359,8 +415,12 @@ pub struct RecoveryConfig {
     cc_algorithm: CongestionControlAlgorithm,
     hystart: bool,
     pacing: bool,
-    max_pacing_rate: Option<u64>,
+    pub max_pacing_rate: Option<u64>,
     initial_congestion_window_packets: usize,
+    minimum_congestion_window_packets: usize,
+    packet_reordering_threshold: u64,
+    time_reordering_threshold: f64,
+    multipath: bool,
 }
 
 impl RecoveryConfig {
@@ -374,12 +434,19 @@ impl RecoveryConfig {
             max_pacing_rate: config.max_pacing_rate,
             initial_congestion_window_packets: config
                 .initial_congestion_window_packets,
+            minimum_congestion_window_packets: config
+                .minimum_congestion_window_packets,
+            packet_reordering_threshold: config.packet_reordering_threshold,
+            time_reordering_threshold: config.time_reordering_threshold,
+            multipath: config.multipath,
         }
     }
 }
 
 impl Recovery {
-    pub fn new_with_config(recovery_config: &RecoveryConfig) -> Self {
+    pub fn new_with_config(
+        recovery_config: &RecoveryConfig, now: Instant,
+    ) -> Self {
         Recovery {
             epochs: Default::default(),
 
@@ -387,13 +454,17 @@ impl Recovery {
 
             pto_count: 0,
 
-            rtt_stats: RttStats::new(recovery_config.max_ack_delay),
+            persistent_congestion_count: 0,
+
+            rtt_stats: RttStats::new(recovery_config.max_ack_delay, now),
 
             lost_spurious_count: 0,
 
-            pkt_thresh: INITIAL_PACKET_THRESHOLD,
+            pkt_thresh: recovery_config.packet_reordering_threshold,
 
-            time_thresh: INITIAL_TIME_THRESHOLD,
+            time_thresh: recovery_config.time_reordering_threshold,
+
+            multipath: recovery_config.multipath,
 
             bytes_in_flight: 0,
 
@@ -406,9 +477,12 @@ impl Recovery {
             #[cfg(feature = "qlog")]
             qlog_metrics: QlogMetrics::default(),
 
+            #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+            congestion_state: CongestionState::default(),
+
             outstanding_non_ack_eliciting: 0,
 
-            congestion: Congestion::from_config(recovery_config),
+            congestion: Congestion::from_config(recovery_config, now),
 
             newly_acked: Vec::new(),
         }
@@ -416,7 +490,10 @@ impl Recovery {
 
     #[cfg(test)]
     pub fn new(config: &Config) -> Self {
-        Self::new_with_config(&RecoveryConfig::from_config(config))
+        Self::new_with_config(
+            &RecoveryConfig::from_config(config),
+            Instant::now(),
+        )
     }
 
     /// Returns whether or not we should elicit an ACK even if we wouldn't
@@ -505,8 +582,10 @@ impl Recovery {
         trace!("{} {:?}", trace_id, self);
     }
 
-    pub fn get_packet_send_time(&self) -> Instant {
-        self.congestion.get_packet_send_time()
+    /// Returns the time at which the next packet should be sent, which is
+    /// never earlier than `now`, and is `now` if pacing is disabled.
+    pub fn get_packet_send_time(&self, now: Instant) -> Instant {
+        self.congestion.get_packet_send_time(now)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -515,7 +594,17 @@ impl Recovery {
         epoch: packet::Epoch, handshake_status: HandshakeStatus, now: Instant,
         trace_id: &str,
     ) -> Result<(usize, usize, usize)> {
-        let largest_acked = ranges.last().unwrap();
+        let largest_acked = if self.multipath {
+            // Packet numbers are shared by all paths, so only the packets that
+            // were sent on this path are relevant.
+            match self.epochs[epoch].largest_sent_in(ranges) {
+                Some(v) => v,
+
+                None => return Ok((0, 0, 0)),
+            }
+        } else {
+            ranges.last().unwrap()
+        };
 
         // Update the largest acked packet.
         let largest_acked = self.epochs[epoch]
@@ -700,6 +789,10 @@ impl Recovery {
         self.congestion.congestion_window()
     }
 
+    pub fn ack_eliciting_threshold(&self) -> u64 {
+        self.congestion.ack_eliciting_threshold()
+    }
+
     pub fn cwnd_available(&self) -> usize {
         // Ignore cwnd when sending probe packets.
         if self.epochs.iter().any(|e| e.loss_probes > 0) {
@@ -715,6 +808,33 @@ impl Recovery {
         self.rtt_stats.rtt()
     }
 
+    #[cfg(feature = "instrumentation")]
+    pub fn latest_rtt(&self) -> Duration {
+        self.rtt_stats.latest_rtt
+    }
+
+    /// Returns the time the given packet was sent at, if it was neither
+    /// acknowledged nor declared lost yet.
+    pub fn unacked_time_sent(
+        &self, epoch: packet::Epoch, pkt_num: u64,
+    ) -> Option<Instant> {
+        let sent_packets = &self.epochs[epoch].sent_packets;
+        let idx = sent_packets.partition_point(|p| p.pkt_num < pkt_num);
+
+        sent_packets
+            .get(idx)
+            .filter(|p| {
+                p.pkt_num == pkt_num &&
+                    p.time_acked.is_none() &&
+                    p.time_lost.is_none()
+            })
+            .map(|p| p.time_sent)
+    }
+
+    pub fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
     pub fn min_rtt(&self) -> Option<Duration> {
         self.rtt_stats.min_rtt()
     }
@@ -723,10 +843,55 @@ impl Recovery {
         self.rtt_stats.rttvar
     }
 
+    /// Takes over the round-trip time estimate of another path.
+    ///
+    /// This is only appropriate when the peer's address changed in its port
+    /// number alone (RFC 9000, Section 9.4), e.g. after a NAT rebinding.
+    pub fn inherit_rtt_stats(&mut self, other: &Recovery) {
+        self.rtt_stats = other.rtt_stats.clone();
+    }
+
     pub fn pto(&self) -> Duration {
         self.rtt() + cmp::max(self.rtt_stats.rttvar * 4, GRANULARITY)
     }
 
+    /// Returns how many consecutive times the probe timeout expired without
+    /// receiving an acknowledgement.
+    pub fn pto_count(&self) -> u32 {
+        self.pto_count
+    }
+
+    /// Returns how many times persistent congestion was declared.
+    pub fn persistent_congestion_count(&self) -> usize {
+        self.persistent_congestion_count
+    }
+
+    /// Returns whether the loss of ack-eliciting packets sent from `first` to
+    /// `last` in the given packet number space establishes persistent
+    /// congestion, as described in RFC 9002, Section 7.6.
+    fn in_persistent_congestion(
+        &self, epoch: packet::Epoch, first: Instant, last: Instant,
+    ) -> bool {
+        // Packets sent before the first RTT sample are not considered.
+        match self.rtt_stats.first_rtt_sample {
+            Some(sample) if first > sample => (),
+
+            _ => return false,
+        }
+
+        let max_ack_delay = if epoch == packet::Epoch::Application {
+            self.rtt_stats.max_ack_delay
+        } else {
+            Duration::ZERO
+        };
+
+        let duration =
+            (self.pto() + max_ack_delay) * PERSISTENT_CONGESTION_THRESHOLD;
+
+        last.duration_since(first) > duration &&
+            !self.epochs[epoch].acked_between(first, last)
+    }
+
     pub fn delivery_rate(&self) -> u64 {
         self.congestion.delivery_rate()
     }
@@ -736,7 +901,7 @@ impl Recovery {
     }
 
     pub fn pmtud_update_max_datagram_size(
-        &mut self, new_max_datagram_size: usize,
+        &mut self, new_max_datagram_size: usize, now: Instant,
     ) {
         // Congestion Window is updated only when it's not updated already.
         // Update cwnd if it hasn't been updated yet.
@@ -754,14 +919,22 @@ impl Recovery {
             0,
             new_max_datagram_size,
             self.congestion.pacer.max_pacing_rate(),
+            now,
         );
 
         self.max_datagram_size = new_max_datagram_size;
     }
 
-    pub fn update_max_datagram_size(&mut self, new_max_datagram_size: usize) {
+    pub fn set_max_pacing_rate(&mut self, v: Option<u64>) {
+        self.congestion.pacer.set_max_pacing_rate(v);
+    }
+
+    pub fn update_max_datagram_size(
+        &mut self, new_max_datagram_size: usize, now: Instant,
+    ) {
         self.pmtud_update_max_datagram_size(
             self.max_datagram_size.min(new_max_datagram_size),
+            now,
         )
     }
 
@@ -886,6 +1059,18 @@ impl Recovery {
             self.bytes_in_flight -= loss.lost_bytes;
         };
 
+        if let Some((first, last)) = loss.lost_ack_eliciting_span {
+            if self.in_persistent_congestion(epoch, first, last) {
+                trace!("{} persistent congestion on epoch {}", trace_id, epoch);
+
+                (self.congestion.cc_ops.on_persistent_congestion)(
+                    &mut self.congestion,
+                );
+
+                self.persistent_congestion_count += 1;
+            }
+        }
+
         self.bytes_in_flight -= loss.pmtud_lost_bytes;
 
         self.epochs[epoch]
@@ -897,14 +1082,17 @@ impl Recovery {
     }
 
     pub fn update_app_limited(&mut self, v: bool) {
-        self.congestion.app_limited = v;
+        self.congestion.update_app_limited(v);
     }
 
-    #[cfg(test)]
     pub fn app_limited(&self) -> bool {
         self.congestion.app_limited
     }
 
+    pub fn app_limited_periods(&self) -> u64 {
+        self.congestion.app_limited_periods
+    }
+
     pub fn delivery_rate_update_app_limited(&mut self, v: bool) {
         self.congestion.delivery_rate.update_app_limited(v);
     }
@@ -929,6 +1117,57 @@ impl Recovery {
         self.qlog_metrics.maybe_update(qlog_metrics)
     }
 
+    /// Returns the state transitions of the congestion controller since the
+    /// last call.
+    #[cfg(any(feature = "qlog", feature = "instrumentation"))]
+    pub fn congestion_transitions(
+        &mut self,
+    ) -> SmallVec<[CongestionTransition; 3]> {
+        let mut transitions = SmallVec::new();
+
+        let latest = CongestionState {
+            recovery_start_time: self.congestion.congestion_recovery_start_time,
+
+            pacing_rate: self.congestion.pacer.rate(),
+        };
+
+        // A rollback after a spurious loss restores an older recovery start
+        // time, which isn't a new recovery period.
+        let recovery_entered = match (
+            self.congestion_state.recovery_start_time,
+            latest.recovery_start_time,
+        ) {
+            (None, Some(_)) => true,
+
+            (Some(prev), Some(latest)) => latest > prev,
+
+            _ => false,
+        };
+
+        if let Some(reason) = self.congestion.slow_start_exit.take() {
+            transitions.push(CongestionTransition::SlowStartExit(reason));
+        }
+
+        if recovery_entered {
+            transitions.push(CongestionTransition::RecoveryStart);
+        }
+
+        if self.congestion_state.pacing_rate != latest.pacing_rate {
+            transitions.push(CongestionTransition::PacingRateUpdated(
+                latest.pacing_rate,
+            ));
+        }
+
+        self.congestion_state = latest;
+
+        transitions
+    }
+
+    #[cfg(feature = "instrumentation")]
+    pub fn ssthresh(&self) -> usize {
+        self.congestion.ssthresh
+    }
+
     pub fn send_quantum(&self) -> usize {
         self.congestion.send_quantum()
     }
@@ -1078,6 +1317,82 @@ impl Default for HandshakeStatus {
     }
 }
 
+/// A state transition of the congestion controller.
+#[cfg(any(feature = "qlog", feature = "instrumentation"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionTransition {
+    /// The congestion controller left slow start.
+    SlowStartExit(SlowStartExitReason),
+
+    /// A new congestion recovery period started, following packet loss.
+    RecoveryStart,
+
+    /// The pacing rate changed to the given value, in bytes per second.
+    PacingRateUpdated(u64),
+}
+
+/// Why the congestion controller left slow start.
+///
+/// For BBR and BBRv2 this is the reason for leaving the Startup state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowStartExitReason {
+    /// HyStart++ detected an increase in round-trip time.
+    Hystart,
+
+    /// Packets were lost.
+    Loss,
+
+    /// The congestion window reached the slow start threshold.
+    Threshold,
+
+    /// BBR estimated that the bottleneck bandwidth was reached, because the
+    /// delivery rate stopped growing.
+    FullBandwidth,
+}
+
+/// Returns the qlog `congestion_state_updated` event for the transitions
+/// returned by a single `congestion_transitions()` call, if any.
+#[cfg(feature = "qlog")]
+pub fn qlog_congestion_state(
+    transitions: &[CongestionTransition],
+) -> Option<EventData> {
+    let slow_start_exit = transitions.iter().find_map(|t| match t {
+        CongestionTransition::SlowStartExit(reason) => Some(*reason),
+
+        _ => None,
+    });
+
+    let recovery_start =
+        transitions.contains(&CongestionTransition::RecoveryStart);
+
+    let (old, new) = match (slow_start_exit, recovery_start) {
+        (Some(_), true) => (Some("slow_start"), "recovery"),
+
+        (Some(_), false) => (Some("slow_start"), "congestion_avoidance"),
+
+        (None, true) => (None, "recovery"),
+
+        (None, false) => return None,
+    };
+
+    Some(EventData::CongestionStateUpdated(
+        qlog::events::quic::CongestionStateUpdated {
+            old: old.map(String::from),
+            new: new.to_string(),
+            trigger: None,
+        },
+    ))
+}
+
+/// The congestion controller state tracked to detect transitions.
+#[cfg(any(feature = "qlog", feature = "instrumentation"))]
+#[derive(Default)]
+struct CongestionState {
+    recovery_start_time: Option<Instant>,
+
+    pacing_rate: u64,
+}
+
 // We don't need to log all qlog metrics every time there is a recovery event.
 // Instead, we can log only the MetricsUpdated event data fields that we care
 // about, only when they change. To support this, the QLogMetrics structure
@@ -1821,6 +2136,271 @@ mod tests {
         assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
     }
 
+    #[test]
+    fn invalid_thresholds() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(
+            cfg.set_minimum_congestion_window_packets(0),
+            Err(crate::Error::InvalidState)
+        );
+        assert_eq!(
+            cfg.set_packet_reordering_threshold(0),
+            Err(crate::Error::InvalidState)
+        );
+
+        for v in [0.0, 0.99, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                cfg.set_time_reordering_threshold(v),
+                Err(crate::Error::InvalidState)
+            );
+        }
+
+        assert_eq!(cfg.set_minimum_congestion_window_packets(1), Ok(()));
+        assert_eq!(cfg.set_packet_reordering_threshold(1), Ok(()));
+        assert_eq!(cfg.set_time_reordering_threshold(1.0), Ok(()));
+
+        let r = Recovery::new(&cfg);
+        assert_eq!(r.pkt_thresh, 1);
+        assert_eq!(r.time_thresh, 1.0);
+    }
+
+    #[test]
+    fn loss_on_reordering_custom_threshold() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_packet_reordering_threshold(4).unwrap();
+
+        let mut r = Recovery::new(&cfg);
+        assert_eq!(r.pkt_thresh, 4);
+
+        let mut now = Instant::now();
+
+        for pkt_num in 0..4 {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        assert_eq!(r.bytes_in_flight, 4000);
+
+        // Wait for 10ms.
+        now += Duration::from_millis(10);
+
+        // Only the last packet is acked, which would be enough to declare the
+        // first packet lost with the default threshold.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(3..4);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000))
+        );
+
+        assert_eq!(r.congestion.lost_count, 0);
+        assert_eq!(r.bytes_in_flight, 3000);
+    }
+
+    #[test]
+    fn persistent_congestion() {
+        let sent = |pkt_num: u64, time_sent: Instant| Sent {
+            pkt_num,
+            frames: smallvec![],
+            time_sent,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: time_sent,
+            first_sent_time: time_sent,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data: false,
+            pmtud: false,
+        };
+
+        // Returns how many times persistent congestion was declared, when
+        // two packets sent `gap` apart are lost.
+        let run = |gap: Duration| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+            let mut r = Recovery::new(&cfg);
+
+            let mut now = Instant::now();
+
+            let epoch = packet::Epoch::Application;
+            let status = HandshakeStatus::default();
+
+            // Get a first RTT sample of 10ms.
+            r.on_packet_sent(sent(0, now), epoch, status, now, "");
+
+            now += Duration::from_millis(10);
+
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(0..1);
+
+            assert_eq!(
+                r.on_ack_received(&acked, 0, epoch, status, now, ""),
+                Ok((0, 0, 1000))
+            );
+
+            now += Duration::from_millis(10);
+
+            r.on_packet_sent(sent(1, now), epoch, status, now, "");
+
+            now += gap;
+
+            r.on_packet_sent(sent(2, now), epoch, status, now, "");
+
+            now += Duration::from_millis(100);
+
+            r.on_packet_sent(sent(3, now), epoch, status, now, "");
+
+            now += Duration::from_millis(10);
+
+            // Only the last packet is acked, so the others are lost.
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(3..4);
+
+            assert_eq!(
+                r.on_ack_received(&acked, 0, epoch, status, now, ""),
+                Ok((2, 2000, 1000))
+            );
+
+            (r.persistent_congestion_count(), r.cwnd())
+        };
+
+        // The losses span more than 3 PTOs.
+        assert_eq!(
+            run(Duration::from_millis(100)),
+            (1, crate::MAX_SEND_UDP_PAYLOAD_SIZE * MINIMUM_WINDOW_PACKETS)
+        );
+
+        // The losses are too close to each other.
+        assert_eq!(run(Duration::from_millis(50)).0, 0);
+    }
+
+    #[test]
+    fn multipath_largest_acked() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.set_multipath(true);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        // Packets 1 and 3 are sent on another path.
+        for pkt_num in [0, 2] {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        now += Duration::from_millis(10);
+
+        // Packets sent on the other path only.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(3..4);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 0))
+        );
+        assert_eq!(
+            r.get_largest_acked_on_epoch(packet::Epoch::Application),
+            None
+        );
+        assert_eq!(r.rtt_stats.first_rtt_sample, None);
+
+        acked.insert(0..3);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 2000))
+        );
+        assert_eq!(
+            r.get_largest_acked_on_epoch(packet::Epoch::Application),
+            Some(2)
+        );
+
+        // The largest acked packet was sent on this path, so an RTT sample was
+        // taken.
+        assert_eq!(r.rtt_stats.latest_rtt, Duration::from_millis(10));
+        assert_eq!(r.bytes_in_flight, 0);
+    }
+
     #[test]
     fn pacing() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -1865,7 +2445,7 @@ mod tests {
 
         // First packet will be sent out immediately.
         assert_eq!(r.congestion.pacer.rate(), 0);
-        assert_eq!(r.get_packet_send_time(), now);
+        assert_eq!(r.get_packet_send_time(now), now);
 
         // Wait 50ms for ACK.
         now += Duration::from_millis(50);
@@ -1924,7 +2504,7 @@ mod tests {
         assert_eq!(r.bytes_in_flight, 6000);
 
         // Pacing is not done during initial phase of connection.
-        assert_eq!(r.get_packet_send_time(), now);
+        assert_eq!(r.get_packet_send_time(now), now);
 
         // Send the third packet out.
         let p = Sent {
@@ -1995,11 +2575,78 @@ mod tests {
         assert_eq!(r.congestion.pacer.rate(), pacing_rate);
 
         assert_eq!(
-            r.get_packet_send_time(),
+            r.get_packet_send_time(now),
             now + Duration::from_secs_f64(12000.0 / pacing_rate as f64)
         );
     }
 
+    #[test]
+    fn pacing_disabled() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+        cfg.enable_pacing(false);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        // Send out a full initcwnd and get it acked, so that following
+        // packets would be paced.
+        for (pkt_num, size) in [(0, 12000), (1, 6000), (2, 6000), (3, 1000)] {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+
+            if pkt_num == 0 {
+                now += Duration::from_millis(50);
+
+                let mut acked = ranges::RangeSet::default();
+                acked.insert(0..1);
+
+                assert_eq!(
+                    r.on_ack_received(
+                        &acked,
+                        10,
+                        packet::Epoch::Application,
+                        HandshakeStatus::default(),
+                        now,
+                        "",
+                    ),
+                    Ok((0, 0, 12000))
+                );
+            }
+        }
+
+        assert_eq!(r.bytes_in_flight, 13000);
+        assert!(r.congestion.pacer.rate() > 0);
+
+        // Packets are never scheduled in the future.
+        assert_eq!(r.get_packet_send_time(now), now);
+    }
+
     #[test]
     fn pmtud_loss_on_timer() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
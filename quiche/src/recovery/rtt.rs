@@ -33,6 +33,7 @@ pub(crate) const INITIAL_RTT: Duration = Duration::from_millis(333);
 
 pub(crate) const RTT_WINDOW: Duration = Duration::from_secs(300);
 
+#[derive(Clone)]
 pub struct RttStats {
     pub(super) latest_rtt: Duration,
 
@@ -59,10 +60,10 @@ impl std::fmt::Debug for RttStats {
 }
 
 impl RttStats {
-    pub(crate) fn new(max_ack_delay: Duration) -> Self {
+    pub(crate) fn new(max_ack_delay: Duration, now: Instant) -> Self {
         RttStats {
             latest_rtt: Duration::ZERO,
-            min_rtt: Minmax::new(Duration::ZERO),
+            min_rtt: Minmax::new(now, Duration::ZERO),
             smoothed_rtt: INITIAL_RTT,
             rttvar: INITIAL_RTT / 2,
             first_rtt_sample: None,
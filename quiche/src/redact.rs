@@ -0,0 +1,157 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+static LOG_PAYLOADS: AtomicBool = AtomicBool::new(false);
+
+/// Configures whether trace logs include the contents of packets and frames.
+///
+/// By default, data that can carry application payloads or secrets, such as
+/// stream data, datagrams, tokens, connection close reasons and HTTP/3 field
+/// values, is only logged by its length. Enabling this logs that data in full,
+/// which can help when debugging interoperability issues, at the cost of
+/// exposing it to anyone with access to the logs.
+///
+/// Full payload logging is only available in debug builds, and this has no
+/// effect in release builds.
+pub fn set_log_payloads(v: bool) {
+    LOG_PAYLOADS.store(v, Ordering::Relaxed);
+}
+
+/// Returns whether the contents of packets and frames can be logged.
+pub(crate) fn log_payloads() -> bool {
+    cfg!(debug_assertions) && LOG_PAYLOADS.load(Ordering::Relaxed)
+}
+
+/// Formats data as hex when payload logging is enabled, or as its length
+/// otherwise.
+pub(crate) struct Redacted<'a>(pub &'a [u8]);
+
+impl Redacted<'_> {
+    fn fmt_with(
+        &self, f: &mut std::fmt::Formatter, log_payloads: bool,
+    ) -> std::fmt::Result {
+        if !log_payloads {
+            return write!(f, "[{} bytes]", self.0.len());
+        }
+
+        for b in self.0 {
+            write!(f, "{b:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.fmt_with(f, log_payloads())
+    }
+}
+
+/// Formats data as a ` data=` field when payload logging is enabled, or as
+/// nothing otherwise. This is meant for data whose length is already logged.
+pub(crate) struct Payload<'a>(pub &'a [u8]);
+
+impl Payload<'_> {
+    fn fmt_with(
+        &self, f: &mut std::fmt::Formatter, log_payloads: bool,
+    ) -> std::fmt::Result {
+        if !log_payloads {
+            return Ok(());
+        }
+
+        write!(f, " data=")?;
+
+        Redacted(self.0).fmt_with(f, log_payloads)
+    }
+}
+
+impl std::fmt::Display for Payload<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.fmt_with(f, log_payloads())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::frame;
+    use crate::stream;
+
+    /// Formats `Redacted` and `Payload` with an explicit payload logging
+    /// setting, as the global one is shared with tests running in parallel.
+    struct WithFlag<T>(T, bool);
+
+    impl std::fmt::Display for WithFlag<Redacted<'_>> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.0.fmt_with(f, self.1)
+        }
+    }
+
+    impl std::fmt::Display for WithFlag<Payload<'_>> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.0.fmt_with(f, self.1)
+        }
+    }
+
+    #[test]
+    fn redaction() {
+        let stream = frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"secret", 0, true),
+        };
+
+        let close = frame::Frame::ApplicationClose {
+            error_code: 42,
+            reason: b"bye".to_vec(),
+        };
+
+        assert_eq!(format!("{stream:?}"), "STREAM id=4 off=0 len=6 fin=true");
+        assert_eq!(
+            format!("{close:?}"),
+            "APPLICATION_CLOSE err=2a reason=[3 bytes]"
+        );
+
+        assert_eq!(
+            format!("{}", WithFlag(Redacted(b"secret"), false)),
+            "[6 bytes]"
+        );
+        assert_eq!(format!("{}", WithFlag(Payload(b"secret"), false)), "");
+
+        assert_eq!(
+            format!("{}", WithFlag(Redacted(b"secret"), true)),
+            "736563726574"
+        );
+        assert_eq!(
+            format!("{}", WithFlag(Payload(b"secret"), true)),
+            " data=736563726574"
+        );
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::time;
+
+use ring::hmac;
+
+use crate::rand;
+
+use crate::Error;
+use crate::Result;
+
+/// The length of a stateless reset token.
+const TOKEN_LEN: usize = 16;
+
+/// The minimum length of a stateless reset, as it needs to look like a short
+/// header packet with at least 5 bytes before the token (RFC 9000, section
+/// 10.3).
+const MIN_RESET_LEN: usize = 1 + 4 + TOKEN_LEN;
+
+/// The maximum length of a stateless reset, matching that of a short header
+/// packet carrying a 20 bytes connection ID and a minimal payload.
+const MAX_RESET_LEN: usize = 43;
+
+/// The default maximum number of stateless resets written per second.
+const DEFAULT_MAX_RATE: u64 = 100;
+
+/// Writes stateless resets in response to packets that match no known
+/// connection.
+///
+/// After losing the state of a connection (e.g. because of a restart), a
+/// server can't decrypt the packets the client keeps sending on it, so it
+/// can't close the connection either. Instead it can answer with a stateless
+/// reset, which the client recognizes by the stateless reset token that was
+/// associated to the connection ID it used.
+///
+/// For this to work the server needs to be able to recompute the tokens
+/// without any per-connection state, so the tokens are derived from the
+/// connection ID and a secret key, which must persist across restarts and be
+/// shared among all the servers a client could be routed to. Configuring the
+/// same key with [`set_stateless_reset_key()`] makes connections advertise
+/// the matching tokens, which can also be computed with [`token()`].
+///
+/// Stateless resets are always shorter than the packet triggering them, so
+/// that two endpoints can't end up resetting each other in a loop, and their
+/// rate is limited, so they can't be used to amplify attacks.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut buf = [0; 512];
+/// # let mut out = [0; 512];
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// # let key = [0xba; 32];
+/// let mut resets = quiche::StatelessReset::new(&key);
+///
+/// let (len, src) = socket.recv_from(&mut buf).unwrap();
+///
+/// // The packet matches no known connection.
+/// if let Ok(len) = resets.write(
+///     &buf[..len],
+///     quiche::MAX_CONN_ID_LEN,
+///     std::time::Instant::now(),
+///     &mut out,
+/// ) {
+///     socket.send_to(&out[..len], &src).unwrap();
+/// }
+/// ```
+///
+/// [`token()`]: struct.StatelessReset.html#method.token
+/// [`set_stateless_reset_key()`]:
+/// struct.Config.html#method.set_stateless_reset_key
+pub struct StatelessReset {
+    key: hmac::Key,
+
+    max_rate: u64,
+
+    budget: f64,
+
+    last_write: Option<time::Instant>,
+}
+
+impl StatelessReset {
+    /// Creates a stateless reset writer deriving tokens from the given key.
+    pub fn new(key: &[u8; 32]) -> Self {
+        StatelessReset {
+            key: new_key(key),
+            max_rate: DEFAULT_MAX_RATE,
+            budget: DEFAULT_MAX_RATE as f64,
+            last_write: None,
+        }
+    }
+
+    /// Sets the maximum number of stateless resets written per second.
+    ///
+    /// Short bursts of up to this many resets are allowed.
+    ///
+    /// The default value is `100`.
+    pub fn set_max_rate(&mut self, v: u64) {
+        self.max_rate = v;
+        self.budget = self.budget.min(v as f64);
+    }
+
+    /// Returns the stateless reset token associated to the given connection
+    /// ID.
+    pub fn token(&self, cid: &[u8]) -> u128 {
+        derive_token(&self.key, cid)
+    }
+
+    /// Writes a stateless reset in response to the packet in `buf`.
+    ///
+    /// The `dcid_len` parameter is the length of the connection IDs issued
+    /// by the server, which is needed to extract the destination connection
+    /// ID from the short header of the received packet.
+    ///
+    /// On success the number of bytes written to `out` is returned.
+    ///
+    /// [`InvalidPacket`] is returned if the received packet doesn't have a
+    /// short header, while [`Done`] is returned if it's too short to be
+    /// answered, or if the rate limit was reached.
+    ///
+    /// [`InvalidPacket`]: enum.Error.html#variant.InvalidPacket
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn write(
+        &mut self, buf: &[u8], dcid_len: usize, now: time::Instant,
+        out: &mut [u8],
+    ) -> Result<usize> {
+        let first = *buf.first().ok_or(Error::InvalidPacket)?;
+
+        if crate::packet::Header::is_long(first) || buf.len() < 1 + dcid_len {
+            return Err(Error::InvalidPacket);
+        }
+
+        // The reset must be shorter than the received packet.
+        let len = std::cmp::min(buf.len() - 1, MAX_RESET_LEN);
+
+        if len < MIN_RESET_LEN {
+            return Err(Error::Done);
+        }
+
+        if out.len() < len {
+            return Err(Error::BufferTooShort);
+        }
+
+        if !self.consume_budget(now) {
+            return Err(Error::Done);
+        }
+
+        let token = self.token(&buf[1..1 + dcid_len]);
+
+        let (unpredictable, token_out) = out[..len].split_at_mut(len - TOKEN_LEN);
+
+        rand::rand_bytes(unpredictable);
+
+        // Clear the header form bit and set the fixed bit, so the reset looks
+        // like a short header packet.
+        unpredictable[0] = (unpredictable[0] & 0x3f) | 0x40;
+
+        token_out.copy_from_slice(&token.to_be_bytes());
+
+        Ok(len)
+    }
+
+    fn consume_budget(&mut self, now: time::Instant) -> bool {
+        if let Some(last_write) = self.last_write {
+            let elapsed = now.saturating_duration_since(last_write);
+
+            self.budget = (self.budget +
+                elapsed.as_secs_f64() * self.max_rate as f64)
+                .min(self.max_rate as f64);
+        }
+
+        self.last_write = Some(now);
+
+        if self.budget < 1.0 {
+            return false;
+        }
+
+        self.budget -= 1.0;
+
+        true
+    }
+}
+
+/// Creates the key stateless reset tokens are derived from.
+pub(crate) fn new_key(key: &[u8; 32]) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, key)
+}
+
+/// Derives the stateless reset token associated to a connection ID.
+pub(crate) fn derive_token(key: &hmac::Key, cid: &[u8]) -> u128 {
+    let tag = hmac::sign(key, cid);
+
+    let mut token = [0; TOKEN_LEN];
+    token.copy_from_slice(&tag.as_ref()[..TOKEN_LEN]);
+
+    u128::from_be_bytes(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn short_pkt(dcid: &[u8], len: usize) -> Vec<u8> {
+        let mut pkt = vec![0x41];
+        pkt.extend_from_slice(dcid);
+        pkt.resize(len, 0xba);
+
+        pkt
+    }
+
+    #[test]
+    fn token() {
+        let resets = StatelessReset::new(&[0xba; 32]);
+
+        assert_eq!(resets.token(b"cid1"), resets.token(b"cid1"));
+        assert_ne!(resets.token(b"cid1"), resets.token(b"cid2"));
+
+        // Tokens only depend on the key.
+        let other = StatelessReset::new(&[0xba; 32]);
+        assert_eq!(resets.token(b"cid1"), other.token(b"cid1"));
+
+        let other = StatelessReset::new(&[0xbb; 32]);
+        assert_ne!(resets.token(b"cid1"), other.token(b"cid1"));
+    }
+
+    #[test]
+    fn write() {
+        let mut resets = StatelessReset::new(&[0xba; 32]);
+        let now = time::Instant::now();
+
+        let mut out = [0; 1500];
+
+        let dcid = [0xab; 8];
+        let token = resets.token(&dcid).to_be_bytes();
+
+        let pkt = short_pkt(&dcid, 1200);
+        assert_eq!(resets.write(&pkt, dcid.len(), now, &mut out), Ok(43));
+        assert_eq!(out[0] & 0xc0, 0x40);
+        assert_eq!(&out[43 - TOKEN_LEN..43], &token);
+
+        // Small packets get a reset one byte shorter.
+        let pkt = short_pkt(&dcid, 30);
+        assert_eq!(resets.write(&pkt, dcid.len(), now, &mut out), Ok(29));
+        assert_eq!(&out[29 - TOKEN_LEN..29], &token);
+
+        let pkt = short_pkt(&dcid, 22);
+        assert_eq!(resets.write(&pkt, dcid.len(), now, &mut out), Ok(21));
+
+        let pkt = short_pkt(&dcid, 21);
+        assert_eq!(
+            resets.write(&pkt, dcid.len(), now, &mut out),
+            Err(Error::Done)
+        );
+
+        // Long header packets are not answered.
+        let mut pkt = short_pkt(&dcid, 1200);
+        pkt[0] = 0xc0;
+        assert_eq!(
+            resets.write(&pkt, dcid.len(), now, &mut out),
+            Err(Error::InvalidPacket)
+        );
+
+        assert_eq!(
+            resets.write(&[], dcid.len(), now, &mut out),
+            Err(Error::InvalidPacket)
+        );
+
+        let pkt = short_pkt(&dcid, 1200);
+        assert_eq!(
+            resets.write(&pkt, dcid.len(), now, &mut out[..42]),
+            Err(Error::BufferTooShort)
+        );
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut resets = StatelessReset::new(&[0xba; 32]);
+        resets.set_max_rate(2);
+
+        let mut now = time::Instant::now();
+
+        let mut out = [0; 1500];
+        let pkt = short_pkt(&[0xab; 8], 1200);
+
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Ok(43));
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Ok(43));
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Err(Error::Done));
+
+        // The budget is replenished over time.
+        now += time::Duration::from_millis(500);
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Ok(43));
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Err(Error::Done));
+
+        // But never above the maximum rate.
+        now += time::Duration::from_secs(10);
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Ok(43));
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Ok(43));
+        assert_eq!(resets.write(&pkt, 8, now, &mut out), Err(Error::Done));
+    }
+}
@@ -28,6 +28,8 @@ use std::cmp;
 
 use std::sync::Arc;
 
+use std::time;
+
 use std::collections::hash_map;
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -96,6 +98,12 @@ pub struct StreamMap {
     /// created streams, to prevent peers from re-creating them.
     collected: StreamIdHashSet,
 
+    /// Number of streams of each type, indexed by the two least significant
+    /// bits of the stream ID, that are considered collected without being
+    /// tracked individually (e.g. because they were opened before the
+    /// connection state was imported).
+    collected_below: [u64; 4],
+
     /// Peer's maximum bidirectional stream count limit.
     peer_max_streams_bidi: u64,
 
@@ -159,13 +167,26 @@ pub struct StreamMap {
     /// map elements is the error code to include in the STOP_SENDING frame.
     stopped: StreamIdHashMap<u64>,
 
+    /// Set of stream IDs corresponding to streams that have data held back
+    /// until flushed. The value of the map elements indicates whether a
+    /// zero-length frame with the fin flag set also needs to be sent.
+    held: StreamIdHashMap<bool>,
+
+    /// Map of stream groups indexed by group ID.
+    groups: HashMap<u64, StreamGroup>,
+
     /// The maximum size of a stream window.
     max_stream_window: u64,
+
+    /// The fraction of a stream window that needs to be consumed before the
+    /// stream's flow control limit is updated.
+    window_update_threshold: f64,
 }
 
 impl StreamMap {
     pub fn new(
         max_streams_bidi: u64, max_streams_uni: u64, max_stream_window: u64,
+        window_update_threshold: f64,
     ) -> StreamMap {
         StreamMap {
             local_max_streams_bidi: max_streams_bidi,
@@ -176,6 +197,8 @@ impl StreamMap {
 
             max_stream_window,
 
+            window_update_threshold,
+
             ..StreamMap::default()
         }
     }
@@ -185,6 +208,11 @@ impl StreamMap {
         self.streams.get(&id)
     }
 
+    /// Returns an iterator over all streams that weren't collected yet.
+    pub fn iter(&self) -> impl Iterator<Item = (&u64, &Stream)> {
+        self.streams.iter()
+    }
+
     /// Returns the mutable stream with the given ID if it exists.
     pub fn get_mut(&mut self, id: u64) -> Option<&mut Stream> {
         self.streams.get_mut(&id)
@@ -209,7 +237,9 @@ impl StreamMap {
         let (stream, is_new_and_writable) = match self.streams.entry(id) {
             hash_map::Entry::Vacant(v) => {
                 // Stream has already been closed and garbage collected.
-                if self.collected.contains(&id) {
+                if self.collected.contains(&id) ||
+                    (id >> 2) < self.collected_below[(id & 0x3) as usize]
+                {
                     return Err(Error::Done);
                 }
 
@@ -298,7 +328,7 @@ impl StreamMap {
                     },
                 };
 
-                let s = Stream::new(
+                let mut s = Stream::new(
                     id,
                     max_rx_data,
                     max_tx_data,
@@ -307,6 +337,9 @@ impl StreamMap {
                     self.max_stream_window,
                 );
 
+                s.recv
+                    .set_window_update_threshold(self.window_update_threshold)?;
+
                 let is_writable = s.is_writable();
 
                 (v.insert(s), is_writable)
@@ -511,6 +544,67 @@ impl StreamMap {
         self.local_max_streams_uni_next
     }
 
+    /// Returns the stream count limits and the number of opened streams.
+    #[cfg(feature = "checkpoint")]
+    pub fn limits(&self) -> StreamLimits {
+        StreamLimits {
+            peer_max_streams_bidi: self.peer_max_streams_bidi,
+            peer_max_streams_uni: self.peer_max_streams_uni,
+            peer_opened_streams_bidi: self.peer_opened_streams_bidi,
+            peer_opened_streams_uni: self.peer_opened_streams_uni,
+            local_max_streams_bidi: self.local_max_streams_bidi,
+            local_max_streams_bidi_next: self.local_max_streams_bidi_next,
+            local_max_streams_uni: self.local_max_streams_uni,
+            local_max_streams_uni_next: self.local_max_streams_uni_next,
+            local_opened_streams_bidi: self.local_opened_streams_bidi,
+            local_opened_streams_uni: self.local_opened_streams_uni,
+        }
+    }
+
+    /// Restores the stream count limits and the number of opened streams.
+    ///
+    /// All the streams counted as opened are considered collected, so that
+    /// they can't be created again.
+    #[cfg(feature = "checkpoint")]
+    pub fn set_limits(&mut self, limits: &StreamLimits, is_server: bool) {
+        self.peer_max_streams_bidi = limits.peer_max_streams_bidi;
+        self.peer_max_streams_uni = limits.peer_max_streams_uni;
+        self.peer_opened_streams_bidi = limits.peer_opened_streams_bidi;
+        self.peer_opened_streams_uni = limits.peer_opened_streams_uni;
+        self.local_max_streams_bidi = limits.local_max_streams_bidi;
+        self.local_max_streams_bidi_next = limits.local_max_streams_bidi_next;
+        self.local_max_streams_uni = limits.local_max_streams_uni;
+        self.local_max_streams_uni_next = limits.local_max_streams_uni_next;
+        self.local_opened_streams_bidi = limits.local_opened_streams_bidi;
+        self.local_opened_streams_uni = limits.local_opened_streams_uni;
+
+        let local = is_server as u64;
+        let peer = !is_server as u64;
+
+        let opened = [
+            (limits.local_opened_streams_bidi, local),
+            (limits.local_opened_streams_uni, 0x2 | local),
+            (limits.peer_opened_streams_bidi, peer),
+            (limits.peer_opened_streams_uni, 0x2 | peer),
+        ];
+
+        for (count, ty) in opened {
+            self.collected_below[ty as usize] = count;
+        }
+    }
+
+    /// Returns the ID of the next stream of the given type that can be
+    /// initiated locally.
+    pub fn next_local_stream_id(&self, bidi: bool, is_server: bool) -> u64 {
+        let (opened, dir) = if bidi {
+            (self.local_opened_streams_bidi, 0)
+        } else {
+            (self.local_opened_streams_uni, 0x2)
+        };
+
+        (opened << 2) | dir | (is_server as u64)
+    }
+
     /// Returns the number of bidirectional streams that can be created
     /// before the peer's stream count limit is reached.
     pub fn peer_streams_left_bidi(&self) -> u64 {
@@ -542,6 +636,25 @@ impl StreamMap {
 
         let s = self.streams.remove(&stream_id).unwrap();
 
+        self.held.remove(&stream_id);
+
+        if let Some(group_id) = s.group {
+            let last = match self.groups.get_mut(&group_id) {
+                Some(group) => {
+                    group.stats.streams = group.stats.streams.saturating_sub(1);
+
+                    group.stats.streams == 0
+                },
+
+                None => false,
+            };
+
+            // Remove the group together with its last stream.
+            if last {
+                self.groups.remove(&group_id);
+            }
+        }
+
         self.remove_readable(&s.priority_key);
 
         self.remove_writable(&s.priority_key);
@@ -587,9 +700,103 @@ impl StreamMap {
         self.stopped.iter()
     }
 
+    /// Adds the stream ID to the held streams set.
+    ///
+    /// If `empty_fin` is true, a zero-length frame with the fin flag set will
+    /// need to be sent once the stream is flushed.
+    pub fn insert_held(&mut self, stream_id: u64, empty_fin: bool) {
+        *self.held.entry(stream_id).or_default() |= empty_fin;
+    }
+
+    /// Removes the stream ID from the held streams set, returning whether a
+    /// zero-length frame with the fin flag set needs to be sent.
+    pub fn remove_held(&mut self, stream_id: u64) -> Option<bool> {
+        self.held.remove(&stream_id)
+    }
+
+    /// Creates an iterator over streams that have data held back until
+    /// flushed.
+    pub fn held(&self) -> StreamIter {
+        StreamIter {
+            streams: self.held.keys().copied().collect(),
+            index: 0,
+        }
+    }
+
+    /// Adds the stream to the given group, creating the group if needed.
+    ///
+    /// A stream can only belong to a single group.
+    pub fn insert_group(&mut self, stream_id: u64, group_id: u64) -> Result<()> {
+        let stream = self
+            .streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamState(stream_id))?;
+
+        match stream.group {
+            Some(g) if g == group_id => return Ok(()),
+
+            Some(_) => return Err(Error::InvalidStreamState(stream_id)),
+
+            None => (),
+        }
+
+        stream.group = Some(group_id);
+
+        self.groups.entry(group_id).or_default().stats.streams += 1;
+
+        Ok(())
+    }
+
+    /// Removes the given group, and detaches its streams from it.
+    pub fn remove_group(&mut self, group_id: u64) -> Option<StreamGroupStats> {
+        let stats = self.groups.remove(&group_id)?.stats;
+
+        for stream in self.streams.values_mut() {
+            if stream.group == Some(group_id) {
+                stream.group = None;
+            }
+        }
+
+        Some(stats)
+    }
+
+    /// Returns the given group if it exists.
+    pub fn group(&self, group_id: u64) -> Option<&StreamGroup> {
+        self.groups.get(&group_id)
+    }
+
+    /// Returns the given group as mutable if it exists.
+    pub fn group_mut(&mut self, group_id: u64) -> Option<&mut StreamGroup> {
+        self.groups.get_mut(&group_id)
+    }
+
+    /// Returns the number of bytes that can still be written on the given
+    /// stream according to its group's limit, if any.
+    pub fn group_cap(&self, stream_id: u64) -> Option<u64> {
+        self.streams
+            .get(&stream_id)
+            .and_then(|s| s.group)
+            .and_then(|g| self.groups.get(&g))
+            .and_then(|g| g.tx_cap())
+    }
+
+    /// Creates an iterator over the streams in the given group.
+    pub fn group_streams(&self, group_id: u64) -> StreamIter {
+        StreamIter {
+            streams: self
+                .streams
+                .iter()
+                .filter(|(_, s)| s.group == Some(group_id))
+                .map(|(id, _)| *id)
+                .collect(),
+            index: 0,
+        }
+    }
+
     /// Returns true if the stream has been collected.
     pub fn is_collected(&self, stream_id: u64) -> bool {
-        self.collected.contains(&stream_id)
+        self.collected.contains(&stream_id) ||
+            (stream_id >> 2) < self.collected_below[(stream_id & 0x3) as usize]
     }
 
     /// Returns true if there are any streams that have data to write.
@@ -597,6 +804,19 @@ impl StreamMap {
         !self.flushable.is_empty()
     }
 
+    /// Returns the number of bytes that flushable streams are allowed to send.
+    pub fn flushable_len(&self) -> usize {
+        self.flushable
+            .iter()
+            .filter_map(|k| self.streams.get(&k.id))
+            .map(|s| {
+                let max_off = cmp::min(s.send.off_flush(), s.send.max_off());
+
+                max_off.saturating_sub(s.send.off_front()) as usize
+            })
+            .sum()
+    }
+
     /// Returns true if there are any streams that have data to read.
     pub fn has_readable(&self) -> bool {
         !self.readable.is_empty()
@@ -669,6 +889,88 @@ pub struct Stream {
     pub incremental: bool,
 
     pub priority_key: Arc<StreamPriorityKey>,
+
+    /// The stream's reliability mode. Default is [`Reliable`].
+    ///
+    /// [`Reliable`]: enum.StreamReliability.html#variant.Reliable
+    pub reliability: StreamReliability,
+
+    /// The error code used to reset the stream once its data expires.
+    pub reliability_error_code: u64,
+
+    /// The group the stream belongs to, if any.
+    pub group: Option<u64>,
+}
+
+/// Stream count limits of a connection, and the number of streams opened so
+/// far by each endpoint.
+#[cfg(feature = "checkpoint")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamLimits {
+    pub peer_max_streams_bidi: u64,
+    pub peer_max_streams_uni: u64,
+    pub peer_opened_streams_bidi: u64,
+    pub peer_opened_streams_uni: u64,
+    pub local_max_streams_bidi: u64,
+    pub local_max_streams_bidi_next: u64,
+    pub local_max_streams_uni: u64,
+    pub local_max_streams_uni_next: u64,
+    pub local_opened_streams_bidi: u64,
+    pub local_opened_streams_uni: u64,
+}
+
+/// Accounting state of a group of streams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamGroupStats {
+    /// The number of streams in the group that are not yet complete.
+    pub streams: usize,
+
+    /// The number of bytes written by the application on the group's streams.
+    pub tx_data: u64,
+
+    /// The number of bytes read by the application from the group's streams.
+    pub rx_data: u64,
+}
+
+/// A group of streams.
+#[derive(Default)]
+pub struct StreamGroup {
+    /// The group's accounting state.
+    pub stats: StreamGroupStats,
+
+    /// The maximum number of bytes that can be written by the application on
+    /// the group's streams, if limited.
+    pub max_tx_data: Option<u64>,
+}
+
+impl StreamGroup {
+    /// Returns the number of bytes that can still be written on the group's
+    /// streams, if limited.
+    pub fn tx_cap(&self) -> Option<u64> {
+        self.max_tx_data
+            .map(|max| max.saturating_sub(self.stats.tx_data))
+    }
+}
+
+/// The reliability mode of a stream's send side.
+///
+/// In modes other than [`Reliable`], lost data that can no longer be
+/// retransmitted is dropped, and the stream is reset with a `RESET_STREAM`
+/// frame, so that the peer can move past it.
+///
+/// [`Reliable`]: enum.StreamReliability.html#variant.Reliable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StreamReliability {
+    /// Lost data is always retransmitted.
+    #[default]
+    Reliable,
+
+    /// Lost data is retransmitted only until the given deadline.
+    Deadline(time::Instant),
+
+    /// Each range of lost data is retransmitted at most the given number of
+    /// times.
+    MaxRetransmissions(usize),
 }
 
 impl Stream {
@@ -691,6 +993,25 @@ impl Stream {
             urgency: priority_key.urgency,
             incremental: priority_key.incremental,
             priority_key,
+            reliability: StreamReliability::Reliable,
+            reliability_error_code: 0,
+            group: None,
+        }
+    }
+
+    /// Returns true if the lost data in the given range can still be
+    /// retransmitted at the given time, according to the stream's reliability
+    /// mode.
+    pub fn can_retransmit(
+        &self, now: time::Instant, off: u64, len: usize,
+    ) -> bool {
+        match self.reliability {
+            StreamReliability::Reliable => true,
+
+            StreamReliability::Deadline(deadline) => now < deadline,
+
+            StreamReliability::MaxRetransmissions(max) =>
+                self.send.retransmissions(off, len) < max,
         }
     }
 
@@ -714,7 +1035,7 @@ impl Stream {
         let off_front = self.send.off_front();
 
         !self.send.is_empty() &&
-            off_front < self.send.off_back() &&
+            off_front < self.send.off_flush() &&
             off_front < self.send.max_off()
     }
 
@@ -1032,6 +1353,8 @@ impl PartialEq for RangeBuf {
 mod tests {
     use super::*;
 
+    use crate::flowcontrol::DEFAULT_UPDATE_THRESHOLD;
+
     #[test]
     fn recv_flow_control() {
         let mut stream = Stream::new(0, 15, 0, true, true, DEFAULT_STREAM_WINDOW);
@@ -1160,6 +1483,43 @@ mod tests {
         assert_eq!(stream.recv.reset(0, 5), Ok(0));
     }
 
+    #[test]
+    fn recv_reset_consumed_off() {
+        let mut stream = Stream::new(0, 15, 0, true, true, DEFAULT_STREAM_WINDOW);
+        let mut buf = [0; 32];
+
+        let first = RangeBuf::from(b"hello", 0, false);
+
+        assert_eq!(stream.recv.write(first), Ok(()));
+        assert_eq!(stream.recv.emit(&mut buf[..2]), Ok((2, false)));
+        assert_eq!(stream.recv.consumed_off(), 2);
+
+        assert_eq!(stream.recv.reset(0, 10), Ok(5));
+        assert_eq!(stream.recv.consumed_off(), 10);
+    }
+
+    #[test]
+    fn recv_shutdown_consumed_off() {
+        let mut stream = Stream::new(0, 15, 0, true, true, DEFAULT_STREAM_WINDOW);
+
+        let first = RangeBuf::from(b"hello", 0, false);
+
+        assert_eq!(stream.recv.write(first), Ok(()));
+        assert_eq!(stream.recv.consumed_off(), 0);
+
+        assert_eq!(stream.recv.shutdown(), Ok(()));
+        assert_eq!(stream.recv.consumed_off(), 5);
+
+        // Data received after shutdown is discarded right away.
+        let second = RangeBuf::from(b"world", 5, false);
+
+        assert_eq!(stream.recv.write(second), Ok(()));
+        assert_eq!(stream.recv.consumed_off(), 10);
+
+        assert_eq!(stream.recv.reset(0, 12), Ok(2));
+        assert_eq!(stream.recv.consumed_off(), 12);
+    }
+
     #[test]
     fn recv_reset_change() {
         let mut stream = Stream::new(0, 15, 0, true, true, DEFAULT_STREAM_WINDOW);
@@ -1816,7 +2176,7 @@ mod tests {
         let local_tp = crate::TransportParams::default();
         let peer_tp = crate::TransportParams::default();
 
-        let mut streams = StreamMap::new(5, 5, 5);
+        let mut streams = StreamMap::new(5, 5, 5, DEFAULT_UPDATE_THRESHOLD);
 
         let stream_id = 500;
         assert!(!is_local(stream_id, true), "stream id is peer initiated");
@@ -1837,7 +2197,7 @@ mod tests {
         let local_tp = crate::TransportParams::default();
         let peer_tp = crate::TransportParams::default();
 
-        let mut streams = StreamMap::new(5, 5, 5);
+        let mut streams = StreamMap::new(5, 5, 5, DEFAULT_UPDATE_THRESHOLD);
 
         for stream_id in [8, 12, 4] {
             assert!(is_local(stream_id, false), "stream id is client initiated");
@@ -1854,7 +2214,7 @@ mod tests {
         let local_tp = crate::TransportParams::default();
         let peer_tp = crate::TransportParams::default();
 
-        let mut streams = StreamMap::new(3, 3, 3);
+        let mut streams = StreamMap::new(3, 3, 3, DEFAULT_UPDATE_THRESHOLD);
 
         // Highest permitted
         let stream_id = 8;
@@ -1886,7 +2246,7 @@ mod tests {
             ..Default::default()
         };
 
-        let mut streams = StreamMap::new(100, 100, 100);
+        let mut streams = StreamMap::new(100, 100, 100, DEFAULT_UPDATE_THRESHOLD);
 
         for id in [0, 4, 8, 12] {
             assert!(streams
@@ -1922,7 +2282,7 @@ mod tests {
             ..Default::default()
         };
 
-        let mut streams = StreamMap::new(100, 100, 100);
+        let mut streams = StreamMap::new(100, 100, 100, DEFAULT_UPDATE_THRESHOLD);
 
         // Inserting same-urgency incremental streams in a "random" order yields
         // same order to start with.
@@ -1957,7 +2317,7 @@ mod tests {
             ..Default::default()
         };
 
-        let mut streams = StreamMap::new(100, 100, 100);
+        let mut streams = StreamMap::new(100, 100, 100, DEFAULT_UPDATE_THRESHOLD);
 
         // Streams where the urgency descends (becomes more important). No stream
         // shares an urgency.
@@ -2060,7 +2420,7 @@ mod tests {
             ..Default::default()
         };
 
-        let mut streams = StreamMap::new(100, 100, 100);
+        let mut streams = StreamMap::new(100, 100, 100, DEFAULT_UPDATE_THRESHOLD);
 
         // Streams that share some urgency level
         let input = vec![
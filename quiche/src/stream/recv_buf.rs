@@ -87,27 +87,14 @@ impl RecvBuf {
     /// as handling incoming data that overlaps data that is already in the
     /// buffer.
     pub fn write(&mut self, buf: RangeBuf) -> Result<()> {
+        // Data beyond a known final size is a final size violation, even if it
+        // also exceeds the flow control limit.
+        self.check_final_size(buf.max_off(), buf.fin())?;
+
         if buf.max_off() > self.max_data() {
             return Err(Error::FlowControl);
         }
 
-        if let Some(fin_off) = self.fin_off {
-            // Stream's size is known, forbid data beyond that point.
-            if buf.max_off() > fin_off {
-                return Err(Error::FinalSize);
-            }
-
-            // Stream's size is already known, forbid changing it.
-            if buf.fin() && fin_off != buf.max_off() {
-                return Err(Error::FinalSize);
-            }
-        }
-
-        // Stream's known size is lower than data already received.
-        if buf.fin() && buf.max_off() < self.len {
-            return Err(Error::FinalSize);
-        }
-
         // We already saved the final offset, so there's nothing else we
         // need to keep from the RangeBuf if it's empty.
         if self.fin_off.is_some() && buf.is_empty() {
@@ -249,20 +236,38 @@ impl RecvBuf {
         Ok((len, self.is_fin()))
     }
 
-    /// Resets the stream at the given offset.
-    pub fn reset(&mut self, error_code: u64, final_size: u64) -> Result<usize> {
-        // Stream's size is already known, forbid changing it.
+    /// Checks that data ending at `max_off` is consistent with the stream's
+    /// final size, when `fin` indicates that `max_off` is the final size
+    /// itself.
+    ///
+    /// Once known, the final size can't change, and no data can be received
+    /// beyond it. The final size also can't be lower than the largest offset
+    /// of the data received so far.
+    pub fn check_final_size(&self, max_off: u64, fin: bool) -> Result<()> {
         if let Some(fin_off) = self.fin_off {
-            if fin_off != final_size {
+            // Stream's size is known, forbid data beyond that point.
+            if max_off > fin_off {
+                return Err(Error::FinalSize);
+            }
+
+            // Stream's size is already known, forbid changing it.
+            if fin && fin_off != max_off {
                 return Err(Error::FinalSize);
             }
         }
 
         // Stream's known size is lower than data already received.
-        if final_size < self.len {
+        if fin && max_off < self.len {
             return Err(Error::FinalSize);
         }
 
+        Ok(())
+    }
+
+    /// Resets the stream at the given offset.
+    pub fn reset(&mut self, error_code: u64, final_size: u64) -> Result<usize> {
+        self.check_final_size(final_size, true)?;
+
         // Calculate how many bytes need to be removed from the connection flow
         // control.
         let max_data_delta = final_size - self.len;
@@ -306,6 +311,12 @@ impl RecvBuf {
         self.flow_control.window()
     }
 
+    /// Sets the fraction of the window that needs to be consumed before the
+    /// flow control limit is updated.
+    pub fn set_window_update_threshold(&mut self, v: f64) -> Result<()> {
+        self.flow_control.set_update_threshold(v)
+    }
+
     /// Autotune the window size.
     pub fn autotune_window(&mut self, now: time::Instant, rtt: time::Duration) {
         self.flow_control.autotune_window(now, rtt);
@@ -331,6 +342,19 @@ impl RecvBuf {
         self.off
     }
 
+    /// Returns the offset up to which data has been consumed, either because
+    /// the application read it or because it was discarded.
+    ///
+    /// Data below this offset no longer counts against the connection-level
+    /// flow control window.
+    pub fn consumed_off(&self) -> u64 {
+        if self.drain {
+            return self.len;
+        }
+
+        self.off
+    }
+
     /// Returns true if we need to update the local flow control limit.
     pub fn almost_full(&self) -> bool {
         self.fin_off.is_none() && self.flow_control.should_update_max_data()
@@ -439,6 +463,23 @@ mod tests {
         assert_eq!(recv.emit(&mut buf), Ok((0, true)));
     }
 
+    #[test]
+    fn final_size_before_flow_control() {
+        let mut recv = RecvBuf::new(15, DEFAULT_STREAM_WINDOW);
+
+        let buf = RangeBuf::from(b"hello", 0, true);
+        assert!(recv.write(buf).is_ok());
+
+        // Beyond both the final size and the flow control limit.
+        let buf = RangeBuf::from(b"helloworld", 10, false);
+        assert_eq!(recv.write(buf), Err(Error::FinalSize));
+
+        assert_eq!(recv.check_final_size(5, true), Ok(()));
+        assert_eq!(recv.check_final_size(3, false), Ok(()));
+        assert_eq!(recv.check_final_size(6, false), Err(Error::FinalSize));
+        assert_eq!(recv.check_final_size(4, true), Err(Error::FinalSize));
+    }
+
     #[test]
     fn ordered_read() {
         let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW);
@@ -976,4 +1017,61 @@ mod tests {
 
         assert_eq!(recv.emit(&mut buf), Err(Error::Done));
     }
+
+    #[test]
+    fn heavily_overlapping_retransmissions() {
+        let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW);
+
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+
+        // Ranges retransmitted with different boundaries, as if the sender
+        // repacketized lost data several times.
+        let ranges = [
+            (100, 150),
+            (120, 130),
+            (90, 160),
+            (50, 110),
+            (140, 200),
+            (0, 10),
+            (5, 60),
+            (0, 200),
+            (30, 170),
+        ];
+
+        let mut received = vec![false; data.len()];
+
+        for &(start, end) in ranges.iter() {
+            let fin = end == data.len();
+            let buf = RangeBuf::from(&data[start..end], start as u64, fin);
+
+            assert!(recv.write(buf).is_ok());
+
+            received[start..end].iter_mut().for_each(|b| *b = true);
+
+            // Only one copy of each received byte is buffered.
+            let buffered: usize = recv.data.values().map(|b| b.len()).sum();
+            assert_eq!(buffered, received.iter().filter(|b| **b).count());
+
+            // Buffers never overlap each other.
+            let mut prev_max_off = 0;
+
+            for b in recv.data.values() {
+                assert!(b.off() >= prev_max_off);
+                prev_max_off = b.max_off();
+            }
+        }
+
+        let mut buf = [0; 256];
+
+        let (len, fin) = recv.emit(&mut buf).unwrap();
+        assert_eq!(len, data.len());
+        assert!(fin);
+        assert_eq!(&buf[..len], &data[..]);
+        assert_eq!(recv.data.len(), 0);
+
+        // Retransmissions of data that was already read are ignored.
+        assert!(recv.write(RangeBuf::from(&data[..100], 0, false)).is_ok());
+        assert_eq!(recv.data.len(), 0);
+        assert_eq!(recv.emit(&mut buf), Err(Error::Done));
+    }
 }
@@ -26,6 +26,7 @@
 
 use std::cmp;
 
+use std::collections::BTreeMap;
 use std::collections::VecDeque;
 
 use crate::Error;
@@ -77,12 +78,20 @@ pub struct SendBuf {
     /// The final stream offset written to the stream, if any.
     fin_off: Option<u64>,
 
+    /// The maximum offset of data that can be sent, if data written after it
+    /// is held back until the buffer is flushed.
+    flush_off: Option<u64>,
+
     /// Whether the stream's send-side has been shut down.
     shutdown: bool,
 
     /// Ranges of data offsets that have been acked.
     acked: ranges::RangeSet,
 
+    /// The number of times ranges of data offsets have been retransmitted,
+    /// keyed by the start of each range and mapping to its end and count.
+    retransmitted: BTreeMap<u64, (u64, usize)>,
+
     /// The error code received via STOP_SENDING.
     error: Option<u64>,
 }
@@ -176,7 +185,7 @@ impl SendBuf {
             let off_front = self.off_front();
 
             if self.is_empty() ||
-                off_front >= self.off ||
+                off_front >= self.off_flush() ||
                 off_front != next_off ||
                 off_front >= self.max_data
             {
@@ -237,6 +246,20 @@ impl SendBuf {
         self.max_data = cmp::max(self.max_data, max_data);
     }
 
+    /// Holds back data written from now on until `flush()` is called.
+    ///
+    /// Calling this again before the buffer is flushed has no effect.
+    pub fn hold(&mut self) {
+        if self.flush_off.is_none() {
+            self.flush_off = Some(self.off);
+        }
+    }
+
+    /// Allows all buffered data to be sent.
+    pub fn flush(&mut self) {
+        self.flush_off = None;
+    }
+
     /// Updates the last offset the stream was blocked at, if any.
     pub fn update_blocked_at(&mut self, blocked_at: Option<u64>) {
         self.blocked_at = blocked_at;
@@ -293,6 +316,15 @@ impl SendBuf {
             // position too much, so make sure that doesn't happen.
             self.pos = self.pos.saturating_sub(drop + 1);
         }
+
+        // Forget about retransmissions of data that was fully acked.
+        while let Some(e) = self.retransmitted.first_entry() {
+            if e.get().0 > ack_off {
+                break;
+            }
+
+            e.remove();
+        }
     }
 
     pub fn retransmit(&mut self, off: u64, len: usize) {
@@ -307,17 +339,17 @@ impl SendBuf {
             return;
         }
 
-        for i in 0..self.data.len() {
+        // Buffers are ordered by offset, so skip the ones that end before the
+        // retransmitted range without walking the whole queue.
+        let first = self.data.partition_point(|b| b.max_off() < off);
+
+        for i in first..self.data.len() {
             let buf = &mut self.data[i];
 
             if buf.off >= max_off {
                 break;
             }
 
-            if off > buf.max_off() {
-                continue;
-            }
-
             // Split the buffer into 2 if the retransmit range ends before the
             // buffer's final offset.
             let new_buf = if buf.off < max_off && max_off < buf.max_off() {
@@ -346,12 +378,136 @@ impl SendBuf {
         }
     }
 
+    /// Returns the highest number of times data in the given range has been
+    /// retransmitted.
+    pub fn retransmissions(&self, off: u64, len: usize) -> usize {
+        let max_off = off + len as u64;
+
+        // The range starting before `off` might overlap with it.
+        let before = self
+            .retransmitted
+            .range(..off)
+            .next_back()
+            .filter(|(_, (end, _))| *end > off);
+
+        before
+            .into_iter()
+            .chain(self.retransmitted.range(off..max_off))
+            .map(|(_, (_, count))| *count)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Records that the data in the given range is being retransmitted.
+    pub fn count_retransmission(&mut self, off: u64, len: usize) {
+        let max_off = off + len as u64;
+
+        if len == 0 {
+            return;
+        }
+
+        // Split the ranges overlapping the boundaries of the new one, so that
+        // only the data in the new range is counted.
+        self.split_retransmitted(off);
+        self.split_retransmitted(max_off);
+
+        let overlapping = self
+            .retransmitted
+            .range(off..max_off)
+            .map(|(start, (end, _))| (*start, *end))
+            .collect::<Vec<_>>();
+
+        let mut next = off;
+
+        for (start, end) in overlapping {
+            // Fill the gap before the existing range.
+            if next < start {
+                self.retransmitted.insert(next, (start, 1));
+            }
+
+            if let Some((_, count)) = self.retransmitted.get_mut(&start) {
+                *count += 1;
+            }
+
+            next = end;
+        }
+
+        if next < max_off {
+            self.retransmitted.insert(next, (max_off, 1));
+        }
+    }
+
+    /// Splits the retransmitted range containing `off` in two at `off`.
+    fn split_retransmitted(&mut self, off: u64) {
+        let (start, end, count) =
+            match self.retransmitted.range(..off).next_back() {
+                Some((start, (end, count))) if *end > off =>
+                    (*start, *end, *count),
+
+                _ => return,
+            };
+
+        self.retransmitted.insert(start, (off, count));
+        self.retransmitted.insert(off, (end, count));
+    }
+
+    /// Drops the data buffered at or after the given offset, as if it had
+    /// never been written.
+    ///
+    /// Only data that was never sent can be dropped, [`InvalidState`] is
+    /// returned otherwise. If there is no data to drop, [`Done`] is returned.
+    ///
+    /// On success the number of bytes dropped is returned.
+    ///
+    /// [`InvalidState`]: ../enum.Error.html#variant.InvalidState
+    /// [`Done`]: ../enum.Error.html#variant.Done
+    pub fn truncate(&mut self, off: u64) -> Result<u64> {
+        if off >= self.off {
+            return Err(Error::Done);
+        }
+
+        if off < self.emit_off {
+            return Err(Error::InvalidState);
+        }
+
+        // Unsent data is at the end of the queue, so drop buffers from the back
+        // until reaching the one that contains the new end of the stream.
+        while let Some(buf) = self.data.back_mut() {
+            if buf.off >= off {
+                self.data.pop_back();
+                continue;
+            }
+
+            if buf.max_off() > off {
+                buf.split_off((off - buf.off) as usize);
+            }
+
+            break;
+        }
+
+        let dropped = self.off - off;
+
+        self.pos = cmp::min(self.pos, self.data.len());
+        self.off = off;
+        self.len -= dropped;
+
+        // The final size wasn't sent yet, so it can be written again.
+        if self.fin_off.map_or(false, |fin_off| fin_off > off) {
+            self.fin_off = None;
+        }
+
+        self.flush_off = self.flush_off.map(|flush_off| cmp::min(flush_off, off));
+
+        Ok(dropped)
+    }
+
     /// Resets the stream at the current offset and clears all buffered data.
     pub fn reset(&mut self) -> (u64, u64) {
         let unsent_off = cmp::max(self.off_front(), self.emit_off);
         let unsent_len = self.off_back().saturating_sub(unsent_off);
 
         self.fin_off = Some(unsent_off);
+        self.flush_off = None;
 
         // Drop all buffered data.
         self.data.clear();
@@ -397,6 +553,12 @@ impl SendBuf {
         self.off
     }
 
+    /// Returns the largest offset of data that can be sent, excluding data
+    /// held back until the buffer is flushed.
+    pub fn off_flush(&self) -> u64 {
+        self.flush_off.unwrap_or(self.off)
+    }
+
     /// Returns the lowest offset of data buffered.
     pub fn off_front(&self) -> u64 {
         let mut pos = self.pos;
@@ -491,6 +653,140 @@ impl SendBuf {
 mod tests {
     use super::*;
 
+    #[test]
+    fn retransmissions() {
+        let mut buf = SendBuf::new(u64::MAX);
+
+        assert_eq!(buf.write(b"something", false), Ok(9));
+        assert_eq!(buf.retransmissions(0, 9), 0);
+
+        buf.count_retransmission(2, 3);
+        assert_eq!(buf.retransmissions(0, 2), 0);
+        assert_eq!(buf.retransmissions(0, 3), 1);
+        assert_eq!(buf.retransmissions(4, 5), 1);
+        assert_eq!(buf.retransmissions(5, 4), 0);
+
+        // Ranges are counted separately.
+        buf.count_retransmission(0, 4);
+        assert_eq!(buf.retransmissions(0, 2), 1);
+        assert_eq!(buf.retransmissions(3, 1), 2);
+        assert_eq!(buf.retransmissions(4, 1), 1);
+        assert_eq!(buf.retransmissions(5, 4), 0);
+
+        buf.count_retransmission(3, 6);
+        assert_eq!(buf.retransmissions(0, 3), 2);
+        assert_eq!(buf.retransmissions(3, 1), 3);
+        assert_eq!(buf.retransmissions(4, 5), 2);
+        assert_eq!(buf.retransmissions(6, 3), 1);
+
+        // Counts are dropped once the data is acked.
+        buf.ack_and_drop(0, 4);
+        assert_eq!(buf.retransmitted.len(), 3);
+        assert_eq!(buf.retransmissions(4, 5), 2);
+
+        buf.ack_and_drop(4, 5);
+        assert!(buf.retransmitted.is_empty());
+    }
+
+    #[test]
+    fn hold_and_flush() {
+        let mut buf = [0; 15];
+
+        let mut send = SendBuf::new(u64::MAX);
+        assert_eq!(send.len, 0);
+
+        assert_eq!(send.write(b"hello", false), Ok(5));
+
+        send.hold();
+
+        assert_eq!(send.write(b"world", false), Ok(5));
+        assert_eq!(send.off_flush(), 5);
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 5);
+        assert!(!fin);
+        assert_eq!(&buf[..written], b"hello");
+
+        // Holding again doesn't move the flush offset.
+        send.hold();
+
+        assert_eq!(send.write(b"!", true), Ok(1));
+        assert_eq!(send.off_flush(), 5);
+
+        assert_eq!(send.emit(&mut buf), Ok((0, false)));
+
+        send.flush();
+        assert_eq!(send.off_flush(), 11);
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 6);
+        assert!(fin);
+        assert_eq!(&buf[..written], b"world!");
+    }
+
+    #[test]
+    fn truncate() {
+        let mut buf = [0; 15];
+
+        let mut send = SendBuf::new(u64::MAX);
+
+        assert_eq!(send.write(b"hello", false), Ok(5));
+        assert_eq!(send.write(b"world!", true), Ok(6));
+        assert_eq!(send.len, 11);
+
+        let (written, fin) = send.emit(&mut buf[..3]).unwrap();
+        assert_eq!(written, 3);
+        assert!(!fin);
+
+        // Data that was already sent can't be dropped.
+        assert_eq!(send.truncate(2), Err(Error::InvalidState));
+
+        // Nothing to drop past the end of the stream.
+        assert_eq!(send.truncate(11), Err(Error::Done));
+
+        // Drop data from the middle of a buffer, along with the final size.
+        assert_eq!(send.truncate(4), Ok(7));
+        assert_eq!(send.len, 1);
+        assert_eq!(send.off_back(), 4);
+        assert!(!send.is_fin());
+
+        assert_eq!(send.write(b"p", true), Ok(1));
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 2);
+        assert!(fin);
+        assert_eq!(&buf[..written], b"lp");
+
+        assert_eq!(send.truncate(4), Err(Error::InvalidState));
+    }
+
+    #[test]
+    fn truncate_held() {
+        let mut buf = [0; 15];
+
+        let mut send = SendBuf::new(u64::MAX);
+
+        assert_eq!(send.write(b"hello", false), Ok(5));
+
+        send.hold();
+
+        assert_eq!(send.write(b"world", false), Ok(5));
+
+        // Drop all data, including the part that isn't held back.
+        assert_eq!(send.truncate(0), Ok(10));
+        assert_eq!(send.off_flush(), 0);
+        assert_eq!(send.emit(&mut buf), Ok((0, false)));
+
+        // New data is still held back until the buffer is flushed.
+        assert_eq!(send.write(b"hi", false), Ok(2));
+        assert_eq!(send.emit(&mut buf), Ok((0, false)));
+
+        send.flush();
+
+        assert_eq!(send.emit(&mut buf), Ok((2, false)));
+        assert_eq!(&buf[..2], b"hi");
+    }
+
     #[test]
     fn empty_write() {
         let mut buf = [0; 5];
@@ -778,4 +1074,37 @@ mod tests {
         assert_eq!(fin_off, 50);
         assert_eq!(unsent, 0);
     }
+
+    #[test]
+    fn retransmit_within_many_buffers() {
+        let mut buf = [0; 100];
+
+        let mut send = SendBuf::new(u64::MAX);
+
+        let data: Vec<u8> = (0..100).collect();
+        assert_eq!(send.write(&data, false), Ok(100));
+        assert_eq!(send.bufs_count(), 20);
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 100);
+        assert!(!fin);
+        assert_eq!(send.off_front(), 100);
+
+        // Retransmit a range spanning parts of three buffers.
+        send.retransmit(42, 10);
+        assert_eq!(send.len, 10);
+        assert_eq!(send.off_front(), 42);
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 10);
+        assert!(!fin);
+        assert_eq!(&buf[..written], &data[42..52]);
+
+        // Retransmit the tail of the stream.
+        send.retransmit(97, 3);
+        assert_eq!(send.off_front(), 97);
+
+        let (written, _) = send.emit(&mut buf).unwrap();
+        assert_eq!(&buf[..written], &data[97..]);
+    }
 }
@@ -0,0 +1,344 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Loader for the specification test vectors in the `test-vectors`
+//! directory.
+//!
+//! See the README file in that directory for a description of the format.
+
+use std::path::Path;
+
+use crate::Error;
+use crate::Result;
+
+use crate::crypto;
+use crate::decrypt::hex_decode;
+use crate::packet;
+
+/// A test vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Vector {
+    /// The name of the file and line the vector starts at, used when
+    /// reporting failures.
+    location: String,
+
+    /// The kind of the vector, which determines its fields.
+    kind: String,
+
+    /// The fields of the vector, in order.
+    fields: Vec<(String, String)>,
+}
+
+impl Vector {
+    fn get(&self, key: &str) -> Result<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .ok_or(Error::InvalidState)
+    }
+
+    fn bytes(&self, key: &str) -> Result<Vec<u8>> {
+        let v: String = self.get(key)?.split_whitespace().collect();
+
+        hex_decode(&v).ok_or(Error::InvalidState)
+    }
+
+    fn version(&self) -> Result<u32> {
+        let v = self.get("version")?;
+        let v = v.strip_prefix("0x").ok_or(Error::InvalidState)?;
+
+        u32::from_str_radix(v, 16).map_err(|_| Error::InvalidState)
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.kind.as_str() {
+            "initial_keys" => self.check_initial_keys(),
+
+            "header_protection" => self.check_header_protection(),
+
+            "retry" => self.check_retry(),
+
+            "version" => self.check_version(),
+
+            "version_negotiation" => self.check_version_negotiation(),
+
+            _ => Err(Error::InvalidState),
+        }
+    }
+
+    fn check_initial_keys(&self) -> Result<()> {
+        let (open, seal) = crypto::derive_initial_key_material(
+            &self.bytes("dcid")?,
+            self.version()?,
+            true,
+        )?;
+
+        // The server opens packets with the client's keys, and seals them
+        // with its own.
+        self.check_keys("client", open.alg(), open.secret())?;
+        self.check_keys("server", seal.alg(), seal.secret())
+    }
+
+    fn check_keys(
+        &self, side: &str, aead: crypto::Algorithm, secret: &[u8],
+    ) -> Result<()> {
+        let mut key = vec![0; aead.key_len()];
+        let mut iv = vec![0; aead.nonce_len()];
+        let mut hp = vec![0; aead.key_len()];
+
+        crypto::derive_pkt_key(aead, secret, &mut key)?;
+        crypto::derive_pkt_iv(aead, secret, &mut iv)?;
+        crypto::derive_hdr_key(aead, secret, &mut hp)?;
+
+        assert_eq!(secret, self.bytes(&format!("{}_secret", side))?);
+        assert_eq!(key, self.bytes(&format!("{}_key", side))?);
+        assert_eq!(iv, self.bytes(&format!("{}_iv", side))?);
+        assert_eq!(hp, self.bytes(&format!("{}_hp", side))?);
+
+        Ok(())
+    }
+
+    fn check_header_protection(&self) -> Result<()> {
+        let open = if self.get("secret").is_ok() {
+            let aead = match self.get("cipher")? {
+                "aes128gcm" => crypto::Algorithm::AES128_GCM,
+
+                "aes256gcm" => crypto::Algorithm::AES256_GCM,
+
+                "chacha20" => crypto::Algorithm::ChaCha20_Poly1305,
+
+                _ => return Err(Error::InvalidState),
+            };
+
+            crypto::Open::from_secret(aead, self.bytes("secret")?)?
+        } else {
+            // Packets sent by the client are opened by the server.
+            let is_server = match self.get("side")? {
+                "client" => true,
+
+                "server" => false,
+
+                _ => return Err(Error::InvalidState),
+            };
+
+            crypto::derive_initial_key_material(
+                &self.bytes("dcid")?,
+                self.version()?,
+                is_server,
+            )?
+            .0
+        };
+
+        let mask = open.new_mask(&self.bytes("sample")?)?;
+
+        assert_eq!(mask.to_vec(), self.bytes("mask")?);
+
+        Ok(())
+    }
+
+    fn check_retry(&self) -> Result<()> {
+        let version = self.version()?;
+        let odcid = self.bytes("odcid")?;
+
+        let mut pkt = self.bytes("packet")?;
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+        let hdr = packet::Header::from_bytes(&mut b, 0)?;
+
+        assert_eq!(hdr.ty, packet::Type::Retry);
+        assert_eq!(hdr.version, version);
+
+        assert_eq!(packet::verify_retry_integrity(&b, &odcid, version), Ok(()));
+
+        // The tag doesn't match a different original destination CID.
+        let mut other_odcid = odcid.clone();
+        other_odcid.push(0xba);
+
+        assert_eq!(
+            packet::verify_retry_integrity(&b, &other_odcid, version),
+            Err(Error::CryptoFail)
+        );
+
+        Ok(())
+    }
+
+    fn check_version(&self) -> Result<()> {
+        let supported = match self.get("supported")? {
+            "true" => true,
+
+            "false" => false,
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        assert_eq!(crate::version_is_supported(self.version()?), supported);
+
+        Ok(())
+    }
+
+    fn check_version_negotiation(&self) -> Result<()> {
+        let expected = self.bytes("packet")?;
+
+        let mut out = vec![0; expected.len() + 1];
+
+        let len = packet::negotiate_version(
+            &self.bytes("scid")?,
+            &self.bytes("dcid")?,
+            &mut out,
+        )?;
+
+        assert_eq!(len, expected.len());
+
+        // Only the header form bit of the first byte is fixed.
+        assert_eq!(out[0] & 0x80, expected[0] & 0x80);
+        assert_eq!(&out[1..len], &expected[1..]);
+
+        // Greased versions must never be advertised.
+        let hdr = packet::Header::from_slice(&mut out[..len], 0)?;
+
+        for v in hdr.versions.unwrap_or_default() {
+            assert!(crate::version_is_supported(v));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the test vectors in `text`, which was read from the file `name`.
+fn parse_vectors(name: &str, text: &str) -> Result<Vec<Vector>> {
+    let mut vectors: Vec<Vector> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(kind) =
+            line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+        {
+            vectors.push(Vector {
+                location: format!("{}:{}", name, i + 1),
+                kind: kind.to_string(),
+                fields: Vec::new(),
+            });
+
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or(Error::InvalidState)?;
+
+        // Fields must belong to a vector.
+        let vector = vectors.last_mut().ok_or(Error::InvalidState)?;
+
+        vector
+            .fields
+            .push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(vectors)
+}
+
+/// Loads the test vectors from all `.txt` files in the given directory.
+fn load_vectors(dir: &Path) -> Result<Vec<Vector>> {
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(|_| Error::InvalidState)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map_or(false, |e| e == "txt"))
+        .collect::<Vec<_>>();
+
+    paths.sort();
+
+    let mut vectors = Vec::new();
+
+    for path in paths {
+        let text =
+            std::fs::read_to_string(&path).map_err(|_| Error::InvalidState)?;
+
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        vectors.extend(parse_vectors(&name, &text)?);
+    }
+
+    Ok(vectors)
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let text = "
+            # Comment.
+            [version]
+            version = 0x00000001
+            supported = true
+
+            [retry]
+            packet = ff00 0000
+        ";
+
+        let vectors = parse_vectors("test.txt", text).unwrap();
+        assert_eq!(vectors.len(), 2);
+
+        assert_eq!(vectors[0].location, "test.txt:3");
+        assert_eq!(vectors[0].kind, "version");
+        assert_eq!(vectors[0].version(), Ok(1));
+        assert_eq!(vectors[0].get("supported"), Ok("true"));
+        assert_eq!(vectors[0].get("packet"), Err(Error::InvalidState));
+
+        assert_eq!(vectors[1].location, "test.txt:7");
+        assert_eq!(vectors[1].bytes("packet"), Ok(vec![0xff, 0, 0, 0]));
+
+        // Fields outside of a vector.
+        assert_eq!(
+            parse_vectors("test.txt", "version = 0x00000001"),
+            Err(Error::InvalidState)
+        );
+
+        // Lines that are neither a kind nor a field.
+        assert_eq!(
+            parse_vectors("test.txt", "[version]\nversion"),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    fn test_vectors() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-vectors");
+
+        let vectors = load_vectors(&dir).unwrap();
+        assert!(!vectors.is_empty());
+
+        for v in &vectors {
+            if let Err(e) = v.check() {
+                panic!("{} ({}): {:?}", v.location, v.kind, e);
+            }
+        }
+    }
+}
@@ -101,9 +101,54 @@ impl Context {
             );
         }
     }
+
+    pub fn set_ticket_lifetime(&mut self, secs: u32) {
+        unsafe {
+            SSL_CTX_set_timeout(self.as_mut_ptr(), secs);
+            SSL_CTX_set_session_psk_dhe_timeout(self.as_mut_ptr(), secs);
+        }
+    }
+
+    pub fn set_max_tickets(&mut self, v: usize) -> Result<()> {
+        map_result(unsafe { SSL_CTX_set_num_tickets(self.as_mut_ptr(), v) })
+    }
+
+    pub fn set_ocsp_response(&mut self, response: &[u8]) -> Result<()> {
+        map_result(unsafe {
+            SSL_CTX_set_ocsp_response(
+                self.as_mut_ptr(),
+                response.as_ptr(),
+                response.len(),
+            )
+        })
+    }
+
+    pub fn set_signed_cert_timestamp_list(&mut self, list: &[u8]) -> Result<()> {
+        map_result(unsafe {
+            SSL_CTX_set_signed_cert_timestamp_list(
+                self.as_mut_ptr(),
+                list.as_ptr(),
+                list.len(),
+            )
+        })
+    }
+
+    pub fn enable_ocsp_stapling(&mut self) {
+        unsafe { SSL_CTX_enable_ocsp_stapling(self.as_mut_ptr()) };
+    }
+
+    pub fn enable_signed_cert_timestamps(&mut self) {
+        unsafe { SSL_CTX_enable_signed_cert_timestamps(self.as_mut_ptr()) };
+    }
 }
 
 impl Handshake {
+    pub fn set_early_data_enabled(&mut self, enabled: bool) {
+        unsafe {
+            SSL_set_early_data_enabled(self.as_mut_ptr(), i32::from(enabled));
+        }
+    }
+
     pub fn set_quic_early_data_context(&mut self, context: &[u8]) -> Result<()> {
         map_result(unsafe {
             SSL_set_quic_early_data_context(
@@ -274,6 +319,44 @@ impl Handshake {
     pub fn is_in_early_data(&self) -> bool {
         unsafe { SSL_in_early_data(self.as_ptr()) == 1 }
     }
+
+    pub fn early_data_accepted(&self) -> bool {
+        unsafe { SSL_early_data_accepted(self.as_ptr()) == 1 }
+    }
+
+    pub fn ocsp_response(&self) -> Option<&[u8]> {
+        let mut ptr: *const u8 = ptr::null();
+        let mut len: usize = 0;
+
+        unsafe {
+            SSL_get0_ocsp_response(self.as_ptr(), &mut ptr, &mut len);
+        }
+
+        if len == 0 {
+            return None;
+        }
+
+        Some(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+
+    pub fn signed_cert_timestamp_list(&self) -> Option<&[u8]> {
+        let mut ptr: *const u8 = ptr::null();
+        let mut len: usize = 0;
+
+        unsafe {
+            SSL_get0_signed_cert_timestamp_list(
+                self.as_ptr(),
+                &mut ptr,
+                &mut len,
+            );
+        }
+
+        if len == 0 {
+            return None;
+        }
+
+        Some(unsafe { slice::from_raw_parts(ptr, len) })
+    }
 }
 
 pub(super) fn get_session_bytes(session: *mut SSL_SESSION) -> Result<Vec<u8>> {
@@ -300,6 +383,24 @@ extern {
     ) -> c_int;
     fn SSL_CTX_set_early_data_enabled(ctx: *mut SSL_CTX, enabled: i32);
 
+    fn SSL_CTX_set_timeout(ctx: *mut SSL_CTX, timeout: u32) -> u32;
+
+    fn SSL_CTX_set_session_psk_dhe_timeout(ctx: *mut SSL_CTX, timeout: u32);
+
+    fn SSL_CTX_set_num_tickets(ctx: *mut SSL_CTX, num_tickets: usize) -> c_int;
+
+    fn SSL_CTX_set_ocsp_response(
+        ctx: *mut SSL_CTX, response: *const u8, response_len: usize,
+    ) -> c_int;
+
+    fn SSL_CTX_set_signed_cert_timestamp_list(
+        ctx: *mut SSL_CTX, list: *const u8, list_len: usize,
+    ) -> c_int;
+
+    fn SSL_CTX_enable_ocsp_stapling(ctx: *mut SSL_CTX);
+
+    fn SSL_CTX_enable_signed_cert_timestamps(ctx: *mut SSL_CTX);
+
     pub(super) fn SSL_CTX_set_session_cache_mode(
         ctx: *mut SSL_CTX, mode: c_int,
     ) -> c_int;
@@ -328,6 +429,8 @@ extern {
         ssl: *mut SSL, name: *const c_char,
     ) -> c_int;
 
+    fn SSL_set_early_data_enabled(ssl: *mut SSL, enabled: i32);
+
     fn SSL_set_quic_early_data_context(
         ssl: *mut SSL, context: *const u8, context_len: usize,
     ) -> c_int;
@@ -339,8 +442,32 @@ extern {
 
     fn SSL_reset_early_data_reject(ssl: *mut SSL);
 
+    pub(super) fn SSL_CTX_clear_chain_certs(ctx: *mut SSL_CTX) -> c_int;
+
+    pub(super) fn SSL_CTX_add1_chain_cert(
+        ctx: *mut SSL_CTX, x509: *mut X509,
+    ) -> c_int;
+
+    pub(super) fn SSL_clear_chain_certs(ssl: *mut SSL) -> c_int;
+
+    pub(super) fn SSL_add1_chain_cert(ssl: *mut SSL, x509: *mut X509) -> c_int;
+
+    // BIO
+
+    pub(super) fn BIO_new_mem_buf(buf: *const c_void, len: isize) -> *mut BIO;
+
     fn SSL_in_early_data(ssl: *const SSL) -> c_int;
 
+    fn SSL_early_data_accepted(ssl: *const SSL) -> c_int;
+
+    fn SSL_get0_ocsp_response(
+        ssl: *const SSL, out: *mut *const u8, out_len: *mut usize,
+    );
+
+    fn SSL_get0_signed_cert_timestamp_list(
+        ssl: *const SSL, out: *mut *const u8, out_len: *mut usize,
+    );
+
     fn SSL_SESSION_to_bytes(
         session: *const SSL_SESSION, out: *mut *mut u8, out_len: *mut usize,
     ) -> c_int;
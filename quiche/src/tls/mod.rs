@@ -24,16 +24,20 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::collections::HashMap;
 use std::ffi;
 use std::ptr;
 use std::slice;
 
+use std::sync::Arc;
+
 use std::io::Write;
 
 use once_cell::sync::Lazy;
 
 use libc::c_char;
 use libc::c_int;
+use libc::c_long;
 use libc::c_uint;
 use libc::c_void;
 
@@ -101,11 +105,22 @@ struct X509_STORE_CTX {
 
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
-#[cfg(windows)]
 struct X509 {
     _unused: c_void,
 }
 
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct EVP_PKEY {
+    _unused: c_void,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct BIO {
+    _unused: c_void,
+}
+
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 struct STACK_OF {
@@ -129,7 +144,75 @@ pub static QUICHE_EX_DATA_INDEX: Lazy<c_int> = Lazy::new(|| unsafe {
     SSL_get_ex_new_index(0, ptr::null(), ptr::null(), ptr::null(), ptr::null())
 });
 
-pub struct Context(*mut SSL_CTX);
+/// A certificate chain and its private key.
+struct CertifiedKey {
+    /// The leaf certificate, followed by optional intermediate certificates.
+    certs: Vec<*mut X509>,
+
+    key: *mut EVP_PKEY,
+}
+
+impl CertifiedKey {
+    fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<CertifiedKey> {
+        let certs = read_pem_certs(cert_chain)?;
+
+        let key = match read_pem_priv_key(key) {
+            Ok(v) => v,
+
+            Err(e) => {
+                for cert in certs {
+                    unsafe { X509_free(cert) };
+                }
+
+                return Err(e);
+            },
+        };
+
+        Ok(CertifiedKey { certs, key })
+    }
+
+    /// Installs the certificate chain and private key on the given connection.
+    fn apply(&self, ssl: *mut SSL) -> Result<()> {
+        unsafe {
+            map_result(SSL_use_certificate(ssl, self.certs[0]))?;
+            map_result(SSL_use_PrivateKey(ssl, self.key))?;
+
+            map_result(SSL_clear_chain_certs(ssl))?;
+
+            for cert in &self.certs[1..] {
+                map_result(SSL_add1_chain_cert(ssl, *cert))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// NOTE: The certificates and key are never modified after being parsed, and
+// are only used to take new references to them.
+unsafe impl std::marker::Send for CertifiedKey {}
+unsafe impl std::marker::Sync for CertifiedKey {}
+
+impl Drop for CertifiedKey {
+    fn drop(&mut self) {
+        unsafe {
+            for cert in &self.certs {
+                X509_free(*cert);
+            }
+
+            EVP_PKEY_free(self.key);
+        }
+    }
+}
+
+/// Certificates to present to clients, indexed by server name.
+type ServerCerts = HashMap<String, Arc<CertifiedKey>>;
+
+pub struct Context {
+    ptr: *mut SSL_CTX,
+
+    server_certs: Arc<ServerCerts>,
+}
 
 impl Context {
     // Note: some vendor-specific methods are implemented by each vendor's
@@ -138,7 +221,7 @@ impl Context {
         unsafe {
             let ctx_raw = SSL_CTX_new(TLS_method());
 
-            let mut ctx = Context(ctx_raw);
+            let mut ctx = Context::from_ptr(ctx_raw);
 
             ctx.set_session_callback();
 
@@ -154,17 +237,39 @@ impl Context {
     ) -> Context {
         use foreign_types_shared::ForeignType;
 
-        let mut ctx = Context(ssl_ctx_builder.build().into_ptr() as _);
+        let mut ctx = Context::from_ptr(ssl_ctx_builder.build().into_ptr() as _);
         ctx.set_session_callback();
 
         ctx
     }
 
+    fn from_ptr(ptr: *mut SSL_CTX) -> Context {
+        Context {
+            ptr,
+            server_certs: Arc::new(HashMap::new()),
+        }
+    }
+
     pub fn new_handshake(&mut self) -> Result<Handshake> {
-        unsafe {
-            let ssl = SSL_new(self.as_mut_ptr());
-            Ok(Handshake::new(ssl))
+        let ssl = unsafe { SSL_new(self.as_mut_ptr()) };
+
+        let mut handshake = Handshake::new(ssl);
+
+        if !self.server_certs.is_empty() {
+            unsafe {
+                SSL_set_cert_cb(
+                    ssl,
+                    Some(select_certificate),
+                    Arc::as_ptr(&self.server_certs) as *mut c_void,
+                );
+            }
+
+            // Keep the certificates alive for as long as the handshake might
+            // need them.
+            handshake.server_certs = Some(self.server_certs.clone());
         }
+
+        Ok(handshake)
     }
 
     pub fn load_verify_locations_from_file(&mut self, file: &str) -> Result<()> {
@@ -205,6 +310,79 @@ impl Context {
         })
     }
 
+    pub fn use_certificate_chain(&mut self, pem: &[u8]) -> Result<()> {
+        let certs = read_pem_certs(pem)?;
+
+        let rc = unsafe { self.use_certificate_chain_x509(&certs) };
+
+        for cert in certs {
+            unsafe { X509_free(cert) };
+        }
+
+        rc
+    }
+
+    unsafe fn use_certificate_chain_x509(
+        &mut self, certs: &[*mut X509],
+    ) -> Result<()> {
+        map_result(SSL_CTX_use_certificate(self.as_mut_ptr(), certs[0]))?;
+
+        map_result(SSL_CTX_clear_chain_certs(self.as_mut_ptr()))?;
+
+        for cert in &certs[1..] {
+            map_result(SSL_CTX_add1_chain_cert(self.as_mut_ptr(), *cert))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn use_privkey(&mut self, pem: &[u8]) -> Result<()> {
+        let key = read_pem_priv_key(pem)?;
+
+        self.use_privkey_evp(key)
+    }
+
+    pub fn use_privkey_der(&mut self, der: &[u8]) -> Result<()> {
+        let mut der_ptr = der.as_ptr();
+
+        let key = unsafe {
+            d2i_AutoPrivateKey(ptr::null_mut(), &mut der_ptr, der.len() as _)
+        };
+
+        if key.is_null() {
+            log_ssl_error();
+
+            return Err(Error::TlsFail);
+        }
+
+        self.use_privkey_evp(key)
+    }
+
+    /// Installs the given private key, taking ownership of it.
+    fn use_privkey_evp(&mut self, key: *mut EVP_PKEY) -> Result<()> {
+        unsafe {
+            let rc = SSL_CTX_use_PrivateKey(self.as_mut_ptr(), key);
+
+            EVP_PKEY_free(key);
+
+            map_result(rc)
+        }
+    }
+
+    pub fn add_server_cert_files(
+        &mut self, server_name: &str, cert_file: &str, key_file: &str,
+    ) -> Result<()> {
+        let cert_chain = std::fs::read(cert_file).map_err(|_| Error::TlsFail)?;
+        let key = std::fs::read(key_file).map_err(|_| Error::TlsFail)?;
+
+        let cert = CertifiedKey::from_pem(&cert_chain, &key)?;
+
+        Arc::make_mut(&mut self.server_certs)
+            .insert(server_name.to_ascii_lowercase(), Arc::new(cert));
+
+        Ok(())
+    }
+
     #[cfg(not(windows))]
     fn load_ca_certs(&mut self) -> Result<()> {
         unsafe { map_result(SSL_CTX_set_default_verify_paths(self.as_mut_ptr())) }
@@ -328,7 +506,7 @@ impl Context {
     }
 
     fn as_mut_ptr(&mut self) -> *mut SSL_CTX {
-        self.0
+        self.ptr
     }
 }
 
@@ -352,6 +530,9 @@ pub struct Handshake {
     /// SSL_process_quic_post_handshake should be called when whenever
     /// SSL_provide_quic_data is called to process the provided data.
     provided_data_outstanding: bool,
+    /// Certificates selectable by server name, referenced by the certificate
+    /// callback.
+    server_certs: Option<Arc<ServerCerts>>,
 }
 
 impl Handshake {
@@ -366,6 +547,7 @@ impl Handshake {
         Handshake {
             ptr,
             provided_data_outstanding: false,
+            server_certs: None,
         }
     }
 
@@ -971,9 +1153,10 @@ extern fn new_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
     let handshake = Handshake::new(ssl);
     let peer_params = handshake.quic_transport_params();
 
-    // Serialize session object into buffer.
+    // Serialize session object into buffer. The intermediate copy contains
+    // the resumption secret, so make sure it's wiped once no longer needed.
     let session_bytes = match get_session_bytes(session) {
-        Ok(v) => v,
+        Ok(v) => crypto::Secret::from(v),
         Err(_) => return 0,
     };
 
@@ -1012,6 +1195,124 @@ extern fn new_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
     0
 }
 
+extern fn select_certificate(ssl: *mut SSL, arg: *mut c_void) -> c_int {
+    // Only servers select the certificate based on the server name.
+    if unsafe { SSL_is_server(ssl) } == 0 {
+        return 1;
+    }
+
+    let server_certs = match unsafe { (arg as *const ServerCerts).as_ref() } {
+        Some(v) => v,
+
+        None => return 0,
+    };
+
+    let server_name = unsafe {
+        let ptr = SSL_get_servername(
+            ssl, 0, // TLSEXT_NAMETYPE_host_name
+        );
+
+        if ptr.is_null() {
+            return 1;
+        }
+
+        match ffi::CStr::from_ptr(ptr).to_str() {
+            Ok(v) => v.to_ascii_lowercase(),
+
+            Err(_) => return 1,
+        }
+    };
+
+    // Look for an exact match first, and fallback to a wildcard certificate
+    // covering the first label.
+    let cert = server_certs.get(&server_name).or_else(|| {
+        let (_, parent) = server_name.split_once('.')?;
+        server_certs.get(&format!("*.{parent}"))
+    });
+
+    match cert {
+        Some(cert) => match cert.apply(ssl) {
+            Ok(_) => 1,
+
+            Err(_) => 0,
+        },
+
+        // Use the default certificate.
+        None => 1,
+    }
+}
+
+/// Parses a series of PEM-encoded certificates.
+///
+/// On success the caller owns the returned certificates.
+fn read_pem_certs(pem: &[u8]) -> Result<Vec<*mut X509>> {
+    let mut certs = Vec::new();
+
+    unsafe {
+        let bio = BIO_new_mem_buf(pem.as_ptr() as *const c_void, pem.len() as _);
+
+        if bio.is_null() {
+            return Err(Error::TlsFail);
+        }
+
+        loop {
+            let cert = PEM_read_bio_X509(
+                bio,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+
+            if cert.is_null() {
+                break;
+            }
+
+            certs.push(cert);
+        }
+
+        BIO_free(bio);
+
+        // Reading past the last certificate leaves an error in the queue.
+        ERR_clear_error();
+    }
+
+    if certs.is_empty() {
+        return Err(Error::TlsFail);
+    }
+
+    Ok(certs)
+}
+
+/// Parses a PEM-encoded private key.
+///
+/// On success the caller owns the returned key.
+fn read_pem_priv_key(pem: &[u8]) -> Result<*mut EVP_PKEY> {
+    unsafe {
+        let bio = BIO_new_mem_buf(pem.as_ptr() as *const c_void, pem.len() as _);
+
+        if bio.is_null() {
+            return Err(Error::TlsFail);
+        }
+
+        let key = PEM_read_bio_PrivateKey(
+            bio,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+
+        BIO_free(bio);
+
+        if key.is_null() {
+            log_ssl_error();
+
+            return Err(Error::TlsFail);
+        }
+
+        Ok(key)
+    }
+}
+
 pub fn map_result(bssl_result: c_int) -> Result<()> {
     match bssl_result {
         1 => Ok(()),
@@ -1063,6 +1364,10 @@ extern {
         ctx: *mut SSL_CTX, file: *const c_char, ty: c_int,
     ) -> c_int;
 
+    fn SSL_CTX_use_certificate(ctx: *mut SSL_CTX, x509: *mut X509) -> c_int;
+
+    fn SSL_CTX_use_PrivateKey(ctx: *mut SSL_CTX, pkey: *mut EVP_PKEY) -> c_int;
+
     fn SSL_CTX_load_verify_locations(
         ctx: *mut SSL_CTX, file: *const c_char, path: *const c_char,
     ) -> c_int;
@@ -1113,6 +1418,18 @@ extern {
 
     fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
 
+    fn SSL_is_server(ssl: *const SSL) -> c_int;
+
+    fn SSL_set_cert_cb(
+        ssl: *mut SSL,
+        cb: Option<extern fn(ssl: *mut SSL, arg: *mut c_void) -> c_int>,
+        arg: *mut c_void,
+    );
+
+    fn SSL_use_certificate(ssl: *mut SSL, x509: *mut X509) -> c_int;
+
+    fn SSL_use_PrivateKey(ssl: *mut SSL, pkey: *mut EVP_PKEY) -> c_int;
+
     fn SSL_get_error(ssl: *const SSL, ret_code: c_int) -> c_int;
 
     fn SSL_set_accept_state(ssl: *mut SSL);
@@ -1189,14 +1506,34 @@ extern {
     fn X509_STORE_add_cert(ctx: *mut X509_STORE, x: *mut X509) -> c_int;
 
     // X509
-    #[cfg(windows)]
     fn X509_free(x: *mut X509);
     #[cfg(windows)]
     fn d2i_X509(px: *mut X509, input: *const *const u8, len: c_int) -> *mut X509;
 
+    // EVP_PKEY
+    fn EVP_PKEY_free(pkey: *mut EVP_PKEY);
+
+    fn d2i_AutoPrivateKey(
+        out: *mut *mut EVP_PKEY, input: *mut *const u8, len: c_long,
+    ) -> *mut EVP_PKEY;
+
+    // BIO
+    fn BIO_free(bio: *mut BIO) -> c_int;
+
+    // PEM
+    fn PEM_read_bio_X509(
+        bio: *mut BIO, x509: *mut *mut X509, cb: *mut c_void, u: *mut c_void,
+    ) -> *mut X509;
+
+    fn PEM_read_bio_PrivateKey(
+        bio: *mut BIO, pkey: *mut *mut EVP_PKEY, cb: *mut c_void, u: *mut c_void,
+    ) -> *mut EVP_PKEY;
+
     // ERR
     fn ERR_peek_error() -> c_uint;
 
+    fn ERR_clear_error();
+
     fn ERR_error_string_n(err: c_uint, buf: *mut c_char, len: usize);
 
     // OPENSSL
@@ -9,12 +9,6 @@ struct OPENSSL_STACK {
     _unused: c_void,
 }
 
-#[allow(non_camel_case_types)]
-#[repr(transparent)]
-struct X509 {
-    _unused: c_void,
-}
-
 #[repr(C)]
 #[allow(non_camel_case_types)]
 pub(super) struct SSL_QUIC_METHOD {
@@ -55,9 +49,39 @@ impl Context {
     pub fn set_early_data_enabled(&mut self, _enabled: bool) {
         // not yet supported
     }
+
+    pub fn set_ticket_lifetime(&mut self, secs: u32) {
+        unsafe { SSL_CTX_set_timeout(self.as_mut_ptr(), c_long::from(secs)) };
+    }
+
+    pub fn set_max_tickets(&mut self, v: usize) -> Result<()> {
+        map_result(unsafe { SSL_CTX_set_num_tickets(self.as_mut_ptr(), v) })
+    }
+
+    pub fn set_ocsp_response(&mut self, _response: &[u8]) -> Result<()> {
+        // not yet supported
+        Err(Error::TlsFail)
+    }
+
+    pub fn set_signed_cert_timestamp_list(&mut self, _list: &[u8]) -> Result<()> {
+        // not yet supported
+        Err(Error::TlsFail)
+    }
+
+    pub fn enable_ocsp_stapling(&mut self) {
+        // not yet supported
+    }
+
+    pub fn enable_signed_cert_timestamps(&mut self) {
+        // not yet supported
+    }
 }
 
 impl Handshake {
+    pub fn set_early_data_enabled(&mut self, _enabled: bool) {
+        // not yet supported
+    }
+
     pub fn set_quic_early_data_context(&mut self, _context: &[u8]) -> Result<()> {
         // not supported for now.
         map_result(1)
@@ -141,6 +165,10 @@ impl Handshake {
         false
     }
 
+    pub fn early_data_accepted(&self) -> bool {
+        false
+    }
+
     pub fn set_session(&mut self, session: &[u8]) -> Result<()> {
         unsafe {
             let ctx = SSL_get_SSL_CTX(self.as_ptr());
@@ -170,6 +198,16 @@ impl Handshake {
         // not yet supported
     }
 
+    pub fn ocsp_response(&self) -> Option<&[u8]> {
+        // not yet supported
+        None
+    }
+
+    pub fn signed_cert_timestamp_list(&self) -> Option<&[u8]> {
+        // not yet supported
+        None
+    }
+
     pub fn sigalg(&self) -> Option<String> {
         let sigalg = "";
 
@@ -288,6 +326,43 @@ pub(super) unsafe fn SSL_get_ex_new_index(
     CRYPTO_get_ex_new_index(CRYPTO_EX_INDEX_SSL, argl, argp, newf, dupf, freef)
 }
 
+#[allow(non_snake_case)]
+pub(super) unsafe fn SSL_CTX_clear_chain_certs(ctx: *mut SSL_CTX) -> c_int {
+    const SSL_CTRL_CHAIN: c_int = 88;
+
+    SSL_CTX_ctrl(ctx, SSL_CTRL_CHAIN, 0, ptr::null_mut()) as c_int
+}
+
+#[allow(non_snake_case)]
+pub(super) unsafe fn SSL_CTX_add1_chain_cert(
+    ctx: *mut SSL_CTX, x509: *mut X509,
+) -> c_int {
+    const SSL_CTRL_CHAIN_CERT: c_int = 89;
+
+    SSL_CTX_ctrl(ctx, SSL_CTRL_CHAIN_CERT, 1, x509 as *mut c_void) as c_int
+}
+
+#[allow(non_snake_case)]
+pub(super) unsafe fn SSL_clear_chain_certs(ssl: *mut SSL) -> c_int {
+    const SSL_CTRL_CHAIN: c_int = 88;
+
+    SSL_ctrl(ssl, SSL_CTRL_CHAIN, 0, ptr::null_mut()) as c_int
+}
+
+#[allow(non_snake_case)]
+pub(super) unsafe fn SSL_add1_chain_cert(
+    ssl: *mut SSL, x509: *mut X509,
+) -> c_int {
+    const SSL_CTRL_CHAIN_CERT: c_int = 89;
+
+    SSL_ctrl(ssl, SSL_CTRL_CHAIN_CERT, 1, x509 as *mut c_void) as c_int
+}
+
+#[allow(non_snake_case)]
+pub(super) unsafe fn BIO_new_mem_buf(buf: *const c_void, len: isize) -> *mut BIO {
+    BIO_new_mem_buf_int(buf, len as c_int)
+}
+
 #[allow(non_snake_case)]
 unsafe fn SSL_get_negotiated_group(ssl: *const SSL) -> c_int {
     const SSL_CTRL_GET_NEGOTIATED_GROUP: c_int = 134;
@@ -306,7 +381,8 @@ pub(super) fn get_session_bytes(session: *mut SSL_SESSION) -> Result<Vec<u8>> {
         if out_len == 0 {
             return Err(Error::TlsFail);
         }
-        let mut out: Vec<c_uchar> = Vec::with_capacity(out_len as usize);
+        let mut out =
+            crypto::Secret::from(Vec::<c_uchar>::with_capacity(out_len as usize));
 
         let out_len = i2d_SSL_SESSION(session, &mut out.as_mut_ptr());
         let session_bytes =
@@ -325,6 +401,10 @@ extern {
         ctx: *mut SSL_CTX, cmd: c_int, larg: c_long, parg: *mut c_void,
     ) -> c_long;
 
+    fn SSL_CTX_set_timeout(ctx: *mut SSL_CTX, timeout: c_long) -> c_long;
+
+    fn SSL_CTX_set_num_tickets(ctx: *mut SSL_CTX, num_tickets: usize) -> c_int;
+
     fn SSL_get_peer_cert_chain(ssl: *const SSL) -> *mut STACK_OF;
 
     fn SSL_get0_peer_certificate(ssl: *const SSL) -> *mut X509;
@@ -356,4 +436,7 @@ extern {
     ) -> c_int;
 
     fn SSL_group_to_name(ssl: *const SSL, id: c_int) -> *const c_char;
+
+    #[link_name = "BIO_new_mem_buf"]
+    fn BIO_new_mem_buf_int(buf: *const c_void, len: c_int) -> *mut BIO;
 }